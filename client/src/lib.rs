@@ -0,0 +1,465 @@
+//! Client for the cedar-agent authorization API.
+//!
+//! ```no_run
+//! use cedar_agent_client::{AuthorizeRequest, Client};
+//!
+//! # async fn run() -> Result<(), cedar_agent_client::Error> {
+//! let client = Client::builder("http://localhost:8181").api_key("k1f8...").build()?;
+//! let request = AuthorizeRequest::new(r#"User::"alice""#, r#"Action::"view""#, r#"Doc::"42""#);
+//! if client.is_allowed(&request).await? {
+//!     // ...
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+mod types;
+
+pub use types::*;
+
+use futures::stream::{self, StreamExt};
+use hyper::client::HttpConnector;
+use hyper::header::{HeaderValue, CONTENT_TYPE, RETRY_AFTER};
+use hyper::{Body, Method, Request, StatusCode, Uri};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::fmt;
+use std::time::Duration;
+
+const API_KEY_HEADER: &str = "x-api-key";
+/// Longest `Retry-After` the client waits out; beyond it (a used-up quota) the error is returned.
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(5);
+
+#[derive(Debug)]
+pub enum Error {
+    /// The base URL is not a plain `http://` URL, or the API key is not a valid header value.
+    Config(String),
+    /// The request could not be sent, or the connection failed before a response.
+    Transport(hyper::Error),
+    /// No response within the configured timeout.
+    Timeout,
+    /// The agent answered with an error status. `code` names what was wrong with a rejected
+    /// request, such as `invalid_principal` or `schema_violation`.
+    Status {
+        status: StatusCode,
+        message: String,
+        code: Option<String>,
+        retry_after: Option<Duration>,
+    },
+    /// The response body is not what the endpoint returns.
+    Decode(serde_json::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Config(e) => write!(f, "Invalid client configuration: {}", e),
+            Error::Transport(e) => write!(f, "Failed to reach the agent: {}", e),
+            Error::Timeout => write!(f, "The agent did not answer in time"),
+            Error::Status { status, message, .. } => write!(f, "The agent answered {}: {}", status, message),
+            Error::Decode(e) => write!(f, "Failed to decode the agent's response: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl Error {
+    /// Whether the same request may succeed if sent again: the agent was unreachable, shed the
+    /// request, or had not reached the requested data version yet.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Error::Transport(e) => e.is_connect() || e.is_incomplete_message() || e.is_closed(),
+            Error::Timeout => true,
+            Error::Status { status, retry_after, .. } => match *status {
+                StatusCode::BAD_GATEWAY | StatusCode::SERVICE_UNAVAILABLE | StatusCode::GATEWAY_TIMEOUT => true,
+                StatusCode::TOO_MANY_REQUESTS => retry_after.is_some_and(|after| after <= MAX_RETRY_AFTER),
+                _ => false,
+            },
+            Error::Config(_) | Error::Decode(_) => false,
+        }
+    }
+}
+
+/// Configures a [`Client`].
+#[derive(Debug, Clone)]
+pub struct ClientBuilder {
+    base_url: String,
+    api_key: Option<String>,
+    timeout: Option<Duration>,
+    retries: u32,
+    backoff: Duration,
+    concurrency: usize,
+    pool_max_idle_per_host: usize,
+    pool_idle_timeout: Duration,
+    http2: bool,
+}
+
+impl ClientBuilder {
+    /// Sent as `X-Api-Key`, for agents with `CEDAR_API_KEYS` set.
+    pub fn api_key(mut self, key: impl Into<String>) -> Self {
+        self.api_key = Some(key.into());
+        self
+    }
+
+    /// Time allowed for each attempt, including reading the response. 5 seconds by default.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Attempts after the first for a request that failed with a retryable error. 2 by default.
+    pub fn retries(mut self, retries: u32) -> Self {
+        self.retries = retries;
+        self
+    }
+
+    /// Wait before the first retry, doubled for each one after. 50 milliseconds by default; a
+    /// `Retry-After` from the agent takes precedence.
+    pub fn backoff(mut self, backoff: Duration) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    /// Requests [`Client::authorize_all`] keeps in flight at once. 16 by default.
+    pub fn concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    /// Idle connections kept open for reuse. 32 by default.
+    pub fn pool_max_idle_per_host(mut self, max: usize) -> Self {
+        self.pool_max_idle_per_host = max;
+        self
+    }
+
+    /// How long an idle connection is kept. 90 seconds by default.
+    pub fn pool_idle_timeout(mut self, timeout: Duration) -> Self {
+        self.pool_idle_timeout = timeout;
+        self
+    }
+
+    /// Speaks HTTP/2 without upgrade (h2c), so all requests share one connection.
+    pub fn http2(mut self, http2: bool) -> Self {
+        self.http2 = http2;
+        self
+    }
+
+    pub fn build(self) -> Result<Client, Error> {
+        let base: Uri = self
+            .base_url
+            .trim_end_matches('/')
+            .parse()
+            .map_err(|e| Error::Config(format!("Invalid URL {}: {}", self.base_url, e)))?;
+        if base.scheme_str() != Some("http") || base.authority().is_none() {
+            return Err(Error::Config(format!("Invalid URL {}: expected http://host:port", self.base_url)));
+        }
+        let api_key = match self.api_key {
+            Some(key) => Some(HeaderValue::from_str(&key).map_err(|e| Error::Config(format!("Invalid API key: {}", e)))?),
+            None => None,
+        };
+
+        let mut connector = HttpConnector::new();
+        connector.set_nodelay(true);
+        let http = hyper::Client::builder()
+            .pool_max_idle_per_host(self.pool_max_idle_per_host)
+            .pool_idle_timeout(self.pool_idle_timeout)
+            .http2_only(self.http2)
+            .build(connector);
+        Ok(Client {
+            http,
+            base: base.to_string().trim_end_matches('/').to_string(),
+            api_key,
+            timeout: self.timeout,
+            retries: self.retries,
+            backoff: self.backoff,
+            concurrency: self.concurrency,
+        })
+    }
+}
+
+/// A pooled connection to one agent. Cloning is cheap and shares the pool.
+#[derive(Debug, Clone)]
+pub struct Client {
+    http: hyper::Client<HttpConnector>,
+    base: String,
+    api_key: Option<HeaderValue>,
+    timeout: Option<Duration>,
+    retries: u32,
+    backoff: Duration,
+    concurrency: usize,
+}
+
+impl Client {
+    /// A client for the agent at `base_url` (e.g. `http://localhost:8181`) with the defaults.
+    pub fn new(base_url: &str) -> Result<Self, Error> {
+        Self::builder(base_url).build()
+    }
+
+    pub fn builder(base_url: impl Into<String>) -> ClientBuilder {
+        ClientBuilder {
+            base_url: base_url.into(),
+            api_key: None,
+            timeout: Some(Duration::from_secs(5)),
+            retries: 2,
+            backoff: Duration::from_millis(50),
+            concurrency: 16,
+            pool_max_idle_per_host: 32,
+            pool_idle_timeout: Duration::from_secs(90),
+            http2: false,
+        }
+    }
+
+    /// `POST /authorize`.
+    pub async fn authorize(&self, request: &AuthorizeRequest) -> Result<AuthorizeResponse, Error> {
+        self.call(Method::POST, "/authorize", Some(request)).await
+    }
+
+    /// Whether `request` is allowed.
+    pub async fn is_allowed(&self, request: &AuthorizeRequest) -> Result<bool, Error> {
+        Ok(self.authorize(request).await?.is_allowed())
+    }
+
+    /// Authorizes each request, keeping up to the configured concurrency in flight over the
+    /// pooled connections. Results are in the order of `requests`.
+    pub async fn authorize_all(&self, requests: &[AuthorizeRequest]) -> Vec<Result<AuthorizeResponse, Error>> {
+        stream::iter(requests)
+            .map(|request| self.authorize(request))
+            .buffered(self.concurrency)
+            .collect()
+            .await
+    }
+
+    /// `POST /authorize/batch`: one principal, or token, for many action/resource pairs in a
+    /// single call.
+    pub async fn authorize_batch(&self, request: &BatchRequest) -> Result<BatchResponse, Error> {
+        self.call(Method::POST, "/authorize/batch", Some(request)).await
+    }
+
+    /// `POST /authorize/explain`: the decision plus how each policy evaluated.
+    pub async fn explain(&self, request: &AuthorizeRequest) -> Result<ExplainResponse, Error> {
+        self.call(Method::POST, "/authorize/explain", Some(request)).await
+    }
+
+    /// `POST /authorize/why-not`: for a denial, the permit policies that nearly applied and
+    /// what failed in each.
+    pub async fn why_not(&self, request: &AuthorizeRequest) -> Result<WhyNotResponse, Error> {
+        self.call(Method::POST, "/authorize/why-not", Some(request)).await
+    }
+
+    /// `POST /authorize/suggest`: a permit policy, to edit, that would allow a denied request.
+    pub async fn suggest(&self, request: &AuthorizeRequest) -> Result<SuggestResponse, Error> {
+        self.call(Method::POST, "/authorize/suggest", Some(request)).await
+    }
+
+    /// `POST /v1/evaluate`.
+    pub async fn evaluate(&self, request: &EvaluateRequest) -> Result<EvaluateResponse, Error> {
+        self.call(Method::POST, "/v1/evaluate", Some(request)).await
+    }
+
+    /// `GET /health`.
+    pub async fn health(&self) -> Result<Health, Error> {
+        self.call::<(), _>(Method::GET, "/health", None).await
+    }
+
+    /// The agent's current data version.
+    pub async fn data_version(&self) -> Result<u64, Error> {
+        Ok(self.call::<(), DataVersion>(Method::GET, "/v1/data/version", None).await?.version)
+    }
+
+    /// Waits until the data version differs from `since`, for up to `wait` (2 minutes at most).
+    /// The timeout for this call is extended by `wait`.
+    pub async fn wait_for_change(&self, since: u64, wait: Duration) -> Result<DataVersion, Error> {
+        let path = format!("/v1/data/version?since={}&wait={}ms", since, wait.as_millis());
+        let client = Client {
+            timeout: self.timeout.map(|timeout| timeout + wait),
+            ..self.clone()
+        };
+        client.call::<(), _>(Method::GET, &path, None).await
+    }
+
+    async fn call<B: Serialize, T: DeserializeOwned>(&self, method: Method, path: &str, body: Option<&B>) -> Result<T, Error> {
+        let body = body.map(|body| serde_json::to_vec(body).expect("request types serialize"));
+        let mut attempt = 0;
+        loop {
+            match self.send(&method, path, body.as_deref()).await {
+                Err(e) if attempt < self.retries && e.is_retryable() => {
+                    tokio::time::sleep(retry_delay(&e, self.backoff, attempt)).await;
+                    attempt += 1;
+                }
+                Err(e) => return Err(e),
+                Ok(bytes) => return serde_json::from_slice(&bytes).map_err(Error::Decode),
+            }
+        }
+    }
+
+    async fn send(&self, method: &Method, path: &str, body: Option<&[u8]>) -> Result<hyper::body::Bytes, Error> {
+        let mut request = Request::builder().method(method).uri(format!("{}{}", self.base, path));
+        if let Some(key) = &self.api_key {
+            request = request.header(API_KEY_HEADER, key.clone());
+        }
+        let request = match body {
+            Some(body) => request
+                .header(CONTENT_TYPE, "application/json")
+                .body(Body::from(body.to_vec())),
+            None => request.body(Body::empty()),
+        }
+        .expect("base URL and path were validated");
+
+        let exchange = async {
+            let response = self.http.request(request).await.map_err(Error::Transport)?;
+            let status = response.status();
+            let retry_after = response
+                .headers()
+                .get(RETRY_AFTER)
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.parse().ok())
+                .map(Duration::from_secs);
+            let bytes = hyper::body::to_bytes(response.into_body()).await.map_err(Error::Transport)?;
+            if status.is_success() {
+                Ok(bytes)
+            } else {
+                let (message, code) = error_body(&bytes);
+                Err(Error::Status {
+                    status,
+                    message,
+                    code,
+                    retry_after,
+                })
+            }
+        };
+        match self.timeout {
+            Some(timeout) => tokio::time::timeout(timeout, exchange).await.map_err(|_| Error::Timeout)?,
+            None => exchange.await,
+        }
+    }
+}
+
+/// The `error` and `code` of the agent's `{"error": "...", "code": "..."}` bodies, or the body
+/// itself.
+fn error_body(body: &[u8]) -> (String, Option<String>) {
+    #[derive(serde::Deserialize)]
+    struct ErrorBody {
+        error: String,
+        #[serde(default)]
+        code: Option<String>,
+    }
+    match serde_json::from_slice::<ErrorBody>(body) {
+        Ok(body) => (body.error, body.code),
+        Err(_) => (String::from_utf8_lossy(body).into_owned(), None),
+    }
+}
+
+/// Wait before retry number `attempt` (from 0): the agent's `Retry-After`, else exponential
+/// backoff.
+fn retry_delay(error: &Error, backoff: Duration, attempt: u32) -> Duration {
+    match error {
+        Error::Status { retry_after: Some(after), .. } => (*after).min(MAX_RETRY_AFTER),
+        _ => backoff.saturating_mul(1 << attempt.min(16)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::service::{make_service_fn, service_fn};
+    use hyper::{Response, Server};
+    use std::convert::Infallible;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    fn status(status: StatusCode, retry_after: Option<u64>) -> Error {
+        Error::Status {
+            status,
+            message: String::new(),
+            code: None,
+            retry_after: retry_after.map(Duration::from_secs),
+        }
+    }
+
+    #[test]
+    fn retries_only_what_may_succeed_again() {
+        assert!(status(StatusCode::SERVICE_UNAVAILABLE, Some(1)).is_retryable());
+        assert!(status(StatusCode::TOO_MANY_REQUESTS, Some(1)).is_retryable());
+        // A used-up quota resets at the end of the hour or day
+        assert!(!status(StatusCode::TOO_MANY_REQUESTS, Some(3600)).is_retryable());
+        assert!(!status(StatusCode::BAD_REQUEST, None).is_retryable());
+        assert!(!status(StatusCode::UNAUTHORIZED, None).is_retryable());
+
+        let backoff = Duration::from_millis(50);
+        assert_eq!(retry_delay(&Error::Timeout, backoff, 0), backoff);
+        assert_eq!(retry_delay(&Error::Timeout, backoff, 2), backoff * 4);
+        assert_eq!(retry_delay(&status(StatusCode::SERVICE_UNAVAILABLE, Some(1)), backoff, 2), Duration::from_secs(1));
+        assert_eq!(error_body(br#"{"error":"No schema loaded"}"#), ("No schema loaded".to_string(), None));
+        assert_eq!(
+            error_body(br#"{"error":"Failed to parse principal: ...","code":"invalid_principal"}"#),
+            ("Failed to parse principal: ...".to_string(), Some("invalid_principal".to_string()))
+        );
+        assert_eq!(error_body(b"Forbidden"), ("Forbidden".to_string(), None));
+    }
+
+    #[test]
+    fn accepts_only_plain_http_urls() {
+        assert!(Client::new("http://localhost:8181/").is_ok());
+        assert!(Client::new("https://localhost:8181").is_err());
+        assert!(Client::new("localhost:8181").is_err());
+    }
+
+    #[tokio::test]
+    async fn retries_shed_requests_and_decodes_decisions() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&calls);
+        let make = make_service_fn(move |_| {
+            let calls = Arc::clone(&counter);
+            async move {
+                Ok::<_, Infallible>(service_fn(move |req: Request<Body>| {
+                    let calls = Arc::clone(&calls);
+                    async move {
+                        assert_eq!(req.headers()[API_KEY_HEADER], "secret");
+                        let body: serde_json::Value =
+                            serde_json::from_slice(&hyper::body::to_bytes(req.into_body()).await.unwrap()).unwrap();
+                        // The first request is shed, as by a full evaluation queue
+                        if calls.fetch_add(1, Ordering::SeqCst) == 0 {
+                            return Ok::<_, Infallible>(
+                                Response::builder().status(503).body(Body::from(r#"{"error":"Overloaded"}"#)).unwrap(),
+                            );
+                        }
+                        let decision = if body["principal"] == r#"User::"alice""# { "Allow" } else { "Deny" };
+                        let json = serde_json::json!({
+                            "decision": decision,
+                            "diagnostics": {"reason": ["p1"], "errors": []},
+                        });
+                        Ok(Response::new(Body::from(json.to_string())))
+                    }
+                }))
+            }
+        });
+        let server = Server::bind(&([127, 0, 0, 1], 0).into()).serve(make);
+        let addr = server.local_addr();
+        tokio::spawn(server);
+
+        let client = Client::builder(format!("http://{}", addr))
+            .api_key("secret")
+            .backoff(Duration::from_millis(1))
+            .build()
+            .unwrap();
+        let requests = [
+            AuthorizeRequest::new(r#"User::"alice""#, r#"Action::"view""#, r#"Doc::"1""#),
+            AuthorizeRequest::new(r#"User::"bob""#, r#"Action::"view""#, r#"Doc::"1""#),
+        ];
+        let results = client.authorize_all(&requests).await;
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+        let decisions: Vec<_> = results.into_iter().map(|r| r.unwrap().decision).collect();
+        assert_eq!(decisions, [Decision::Allow, Decision::Deny]);
+
+        let client = Client::builder(format!("http://{}", addr)).api_key("secret").retries(0).build().unwrap();
+        calls.store(0, Ordering::SeqCst);
+        match client.authorize(&requests[0]).await {
+            Err(Error::Status { status, message, .. }) => {
+                assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+                assert_eq!(message, "Overloaded");
+            }
+            other => panic!("expected 503, got {:?}", other),
+        }
+    }
+}
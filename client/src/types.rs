@@ -0,0 +1,360 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+/// Body of `POST /authorize` and of the `/authorize/explain`, `/authorize/why-not` and
+/// `/authorize/suggest` calls.
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct AuthorizeRequest {
+    /// Entity UID such as `User::"alice"`. Left empty on mTLS connections with a SPIFFE ID,
+    /// which then becomes the principal.
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub principal: String,
+    pub action: String,
+    pub resource: String,
+    /// Entities in Cedar's JSON format, added to the agent's stored entities for this request.
+    pub entities: Value,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub context: Option<Value>,
+    /// Data version the agent must have reached, e.g. the one returned by a write.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_version: Option<u64>,
+    /// Bearer token (JWT) standing for the principal, which is then left empty.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+}
+
+impl AuthorizeRequest {
+    pub fn new(principal: impl Into<String>, action: impl Into<String>, resource: impl Into<String>) -> Self {
+        AuthorizeRequest {
+            principal: principal.into(),
+            action: action.into(),
+            resource: resource.into(),
+            entities: Value::Array(Vec::new()),
+            context: None,
+            min_version: None,
+            token: None,
+        }
+    }
+
+    pub fn entities(mut self, entities: Value) -> Self {
+        self.entities = entities;
+        self
+    }
+
+    pub fn context(mut self, context: Value) -> Self {
+        self.context = Some(context);
+        self
+    }
+
+    pub fn min_version(mut self, version: u64) -> Self {
+        self.min_version = Some(version);
+        self
+    }
+
+    /// Authorizes the principal `token` stands for; the agent verifies it against its JWKS.
+    pub fn token(mut self, token: impl Into<String>) -> Self {
+        self.principal.clear();
+        self.token = Some(token.into());
+        self
+    }
+}
+
+/// Body of `POST /authorize/batch`: one principal, or a token standing for it, authorized for
+/// many action/resource pairs.
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct BatchRequest {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub principal: String,
+    /// Shared by every item; an item's own context is merged over it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub context: Option<Value>,
+    pub entities: Value,
+    pub requests: Vec<BatchItem>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_version: Option<u64>,
+}
+
+impl BatchRequest {
+    pub fn for_principal(principal: impl Into<String>) -> Self {
+        BatchRequest {
+            token: None,
+            principal: principal.into(),
+            context: None,
+            entities: Value::Array(Vec::new()),
+            requests: Vec::new(),
+            min_version: None,
+        }
+    }
+
+    pub fn for_token(token: impl Into<String>) -> Self {
+        BatchRequest {
+            token: Some(token.into()),
+            ..Self::for_principal("")
+        }
+    }
+
+    pub fn item(mut self, action: impl Into<String>, resource: impl Into<String>) -> Self {
+        self.requests.push(BatchItem {
+            action: action.into(),
+            resource: resource.into(),
+            context: None,
+        });
+        self
+    }
+
+    pub fn entities(mut self, entities: Value) -> Self {
+        self.entities = entities;
+        self
+    }
+
+    pub fn context(mut self, context: Value) -> Self {
+        self.context = Some(context);
+        self
+    }
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct BatchItem {
+    pub action: String,
+    pub resource: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub context: Option<Value>,
+}
+
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+pub struct BatchResponse {
+    /// One per item, in the order of the request's.
+    pub results: Vec<BatchResult>,
+}
+
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+#[serde(untagged)]
+pub enum BatchResult {
+    Decision(Box<AuthorizeResponse>),
+    /// The item could not be evaluated; `code` is one of the `/authorize` error codes.
+    Error { error: String, code: String },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Decision {
+    Allow,
+    Deny,
+}
+
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+pub struct AuthorizeResponse {
+    pub decision: Decision,
+    /// Per-action decisions when the requested action was an action group.
+    #[serde(default)]
+    pub actions: Vec<ActionDecision>,
+    /// What the caller must do to enforce the decision, from `@obligation` annotations.
+    #[serde(default)]
+    pub obligations: Vec<Directive>,
+    /// What the caller may act on, from `@advice` annotations.
+    #[serde(default)]
+    pub advice: Vec<Directive>,
+    pub diagnostics: Diagnostics,
+}
+
+/// An obligation or advice entry: `require_mfa`, or `mask` with the value `ssn`.
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+pub struct Directive {
+    pub id: String,
+    #[serde(default)]
+    pub value: Option<String>,
+    /// The policy that carries it.
+    pub policy: String,
+}
+
+impl AuthorizeResponse {
+    pub fn is_allowed(&self) -> bool {
+        self.decision == Decision::Allow
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+pub struct ActionDecision {
+    pub action: String,
+    pub decision: Decision,
+    pub reason: Vec<String>,
+}
+
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+pub struct Diagnostics {
+    /// IDs of the policies that determined the decision.
+    pub reason: Vec<String>,
+    pub errors: Vec<String>,
+    /// Permit policies whose conditions held, including ones a forbid overrode.
+    #[serde(default)]
+    pub permits: Vec<String>,
+    /// Forbid policies whose conditions held.
+    #[serde(default)]
+    pub forbids: Vec<String>,
+    /// Policies that failed to evaluate.
+    #[serde(default)]
+    pub errored: Vec<String>,
+    /// For a denial, `forbid` when a forbid policy held, else `default`.
+    #[serde(default)]
+    pub denied_by: Option<String>,
+    /// `allow` or `deny` when no policy applied and the agent's default decision was used.
+    #[serde(default)]
+    pub default_decision: Option<String>,
+    #[serde(default)]
+    pub warnings: Vec<String>,
+    /// Each of `errors` with where the failing expression is in its policy file.
+    #[serde(default)]
+    pub error_details: Vec<ErrorDetail>,
+    /// Source file of each policy in `reason`/`errors`, when overlays are configured.
+    #[serde(default)]
+    pub sources: BTreeMap<String, String>,
+}
+
+/// A policy that failed to evaluate. Positions are 1-based; they and the snippet are absent
+/// for policies not loaded from text.
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+pub struct ErrorDetail {
+    pub policy: String,
+    pub message: String,
+    #[serde(default)]
+    pub help: Option<String>,
+    #[serde(default)]
+    pub source: Option<String>,
+    #[serde(default)]
+    pub line: Option<usize>,
+    #[serde(default)]
+    pub column: Option<usize>,
+    #[serde(default)]
+    pub snippet: Option<String>,
+}
+
+/// Body of `POST /v1/evaluate`: a request evaluated against inline policies and schema instead
+/// of the agent's loaded state.
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct EvaluateRequest {
+    /// Policies in Cedar's text format.
+    pub policies: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub schema: Option<Value>,
+    #[serde(flatten)]
+    pub request: AuthorizeRequest,
+}
+
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+pub struct EvaluateResponse {
+    #[serde(flatten)]
+    pub response: AuthorizeResponse,
+    /// Strict-mode validation of the inline policies against the inline schema.
+    #[serde(default)]
+    pub validation_errors: Vec<String>,
+}
+
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+pub struct ExplainResponse {
+    #[serde(flatten)]
+    pub response: AuthorizeResponse,
+    pub policies: Vec<PolicyExplanation>,
+}
+
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+pub struct PolicyExplanation {
+    pub id: String,
+    /// `permit` or `forbid`.
+    pub effect: String,
+    pub scope_matched: bool,
+    pub satisfied: bool,
+    #[serde(default)]
+    pub error: Option<String>,
+    pub conditions: Vec<ConditionResult>,
+}
+
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+pub struct ConditionResult {
+    /// `when` or `unless`.
+    pub kind: String,
+    #[serde(flatten)]
+    pub body: TermResult,
+    pub passed: bool,
+    #[serde(default)]
+    pub terms: Vec<TermResult>,
+}
+
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+pub struct TermResult {
+    pub condition: String,
+    pub result: String,
+    #[serde(default)]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+pub struct WhyNotResponse {
+    pub decision: Decision,
+    /// For a denial, `forbid` when a forbid policy held, else `default`.
+    #[serde(default)]
+    pub denied_by: Option<String>,
+    /// Forbid policies that held.
+    #[serde(default)]
+    pub forbidden_by: Vec<String>,
+    /// Permit policies that did not apply, those failing the fewest checks first.
+    pub near_misses: Vec<NearMiss>,
+}
+
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+pub struct NearMiss {
+    pub id: String,
+    pub failures: Vec<Failure>,
+}
+
+/// A scope element or condition clause that kept a policy from applying.
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+pub struct Failure {
+    /// `principal`, `action`, `resource`, `when` or `unless`.
+    pub element: String,
+    pub constraint: String,
+    #[serde(default)]
+    pub failed_terms: Vec<TermResult>,
+    #[serde(default)]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+pub struct SuggestResponse {
+    pub decision: Decision,
+    /// A permit policy that would allow the request, in Cedar's text format.
+    pub policy: String,
+    /// Set when a forbid policy denies the request, which no permit overrides.
+    #[serde(default)]
+    pub warning: Option<String>,
+}
+
+/// Body of `GET /health`.
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+pub struct Health {
+    /// `healthy`, or `stale` while a source fails to refresh and its last good state is served.
+    pub status: String,
+    #[serde(default)]
+    pub sources: BTreeMap<String, SourceStatus>,
+}
+
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+pub struct SourceStatus {
+    pub status: String,
+    #[serde(default)]
+    pub last_success: Option<u64>,
+    #[serde(default)]
+    pub last_error: Option<String>,
+    #[serde(default)]
+    pub failures: u64,
+}
+
+/// Body of `GET /v1/data/version`.
+#[derive(Debug, Clone, Copy, Deserialize, PartialEq, Eq)]
+pub struct DataVersion {
+    pub version: u64,
+    /// Whether the version differs from `since`; `false` when the wait ran out.
+    #[serde(default)]
+    pub changed: bool,
+}
@@ -0,0 +1,168 @@
+use crate::config::Config;
+use crate::upgrade;
+use log::{info, warn};
+use std::future::Future;
+use std::net::SocketAddr;
+use tokio::net::{TcpListener, TcpSocket};
+
+const BACKLOG: u32 = 1024;
+
+/// The listening sockets of the agent: one normally, or `CEDAR_ACCEPTORS` sockets bound to the
+/// same address with `SO_REUSEPORT`, so the kernel spreads connections across them instead of
+/// funnelling every accept through a single loop.
+pub struct Acceptors {
+    addr: SocketAddr,
+    listeners: Vec<std::net::TcpListener>,
+    pin: bool,
+}
+
+fn socket(addr: SocketAddr) -> std::io::Result<TcpSocket> {
+    if addr.is_ipv4() {
+        TcpSocket::new_v4()
+    } else {
+        TcpSocket::new_v6()
+    }
+}
+
+/// Binds one listening socket, with `SO_REUSEPORT` when it will share the port.
+fn bind_one(addr: SocketAddr, reuse_port: bool) -> std::io::Result<std::net::TcpListener> {
+    let socket = socket(addr)?;
+    socket.set_reuseaddr(true)?;
+    #[cfg(all(unix, not(any(target_os = "solaris", target_os = "illumos"))))]
+    socket.set_reuseport(reuse_port)?;
+    #[cfg(not(all(unix, not(any(target_os = "solaris", target_os = "illumos")))))]
+    if reuse_port {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            "SO_REUSEPORT is not available on this platform",
+        ));
+    }
+    socket.bind(addr)?;
+    socket.listen(BACKLOG)?.into_std()
+}
+
+impl Acceptors {
+    /// Binds every socket up front, so a port that is taken fails startup. A process started
+    /// by an upgrade takes over the sockets of the one it replaces instead.
+    pub fn bind(addr: SocketAddr, config: &Config) -> Result<Self, Box<dyn std::error::Error>> {
+        let count = config.acceptors;
+        let listeners = match upgrade::inherited()? {
+            Some(listeners) => {
+                if listeners.len() != count {
+                    return Err(format!("Inherited {} listening sockets, but CEDAR_ACCEPTORS is {}", listeners.len(), count).into());
+                }
+                for listener in &listeners {
+                    let local = listener.local_addr()?;
+                    if addr.port() != 0 && local != addr {
+                        return Err(format!("Inherited a socket listening on {}, not BIND_ADDR {}", local, addr).into());
+                    }
+                }
+                info!("Took over {} listening sockets on {}", count, addr);
+                listeners
+            }
+            None => (0..count)
+                .map(|_| bind_one(addr, count > 1))
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|e| format!("Failed to bind {}: {}", addr, e))?,
+        };
+        if count > 1 {
+            info!("Accepting on {} with {} SO_REUSEPORT sockets", addr, count);
+        }
+        upgrade::register(&listeners);
+        Ok(Self {
+            addr,
+            listeners,
+            pin: config.pin_acceptors,
+        })
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Runs `serve` on every socket until one of them fails, or all of them stop once the agent
+    /// stops accepting. A single socket is served on the current runtime. Several each get a
+    /// thread with its own single-threaded runtime, which also runs the connections accepted
+    /// there; with `CEDAR_PIN_ACCEPTORS` the threads are pinned to cores in turn.
+    pub async fn run<F, Fut>(mut self, serve: F) -> Result<(), Box<dyn std::error::Error>>
+    where
+        F: Fn(TcpListener) -> Fut + Clone + Send + 'static,
+        Fut: Future<Output = Result<(), Box<dyn std::error::Error>>> + 'static,
+    {
+        // Every socket is listening, so a process this one replaces can stop accepting
+        upgrade::signal_ready();
+        if self.listeners.len() == 1 {
+            let listener = self.listeners.remove(0);
+            listener.set_nonblocking(true)?;
+            return serve(TcpListener::from_std(listener)?).await;
+        }
+
+        let cores = if self.pin {
+            core_affinity::get_core_ids().unwrap_or_default()
+        } else {
+            Vec::new()
+        };
+        if self.pin && cores.is_empty() {
+            warn!("CEDAR_PIN_ACCEPTORS is set but the CPU cores could not be listed; not pinning");
+        }
+
+        let count = self.listeners.len();
+        let (done_tx, mut done_rx) = tokio::sync::mpsc::unbounded_channel::<Result<(), String>>();
+        for (i, listener) in self.listeners.into_iter().enumerate() {
+            let runtime = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .map_err(|e| format!("Failed to start acceptor runtime: {}", e))?;
+            let core = (!cores.is_empty()).then(|| cores[i % cores.len()]);
+            let serve = serve.clone();
+            let done_tx = done_tx.clone();
+            std::thread::Builder::new()
+                .name(format!("acceptor-{}", i))
+                .spawn(move || {
+                    if let Some(core) = core {
+                        if !core_affinity::set_for_current(core) {
+                            warn!("Failed to pin acceptor {} to core {}", i, core.id);
+                        }
+                    }
+                    let result = runtime.block_on(async move {
+                        listener.set_nonblocking(true)?;
+                        serve(TcpListener::from_std(listener)?).await
+                    });
+                    let _ = done_tx.send(result.map_err(|e| format!("Acceptor {} failed: {}", i, e)));
+                })
+                .map_err(|e| format!("Failed to start acceptor thread: {}", e))?;
+        }
+        drop(done_tx);
+
+        // The first acceptor to fail takes the agent down; they stop without error only once
+        // the agent stops accepting
+        let mut stopped = 0;
+        while let Some(result) = done_rx.recv().await {
+            result?;
+            stopped += 1;
+            if stopped == count {
+                break;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn binds_several_sockets_to_one_port() {
+        let first = bind_one("127.0.0.1:0".parse().unwrap(), true).unwrap();
+        let addr = first.local_addr().unwrap();
+        let second = bind_one(addr, true).unwrap();
+        assert_eq!(second.local_addr().unwrap(), addr);
+    }
+
+    #[tokio::test]
+    async fn refuses_a_taken_port_without_reuse_port() {
+        let first = bind_one("127.0.0.1:0".parse().unwrap(), false).unwrap();
+        assert!(bind_one(first.local_addr().unwrap(), false).is_err());
+    }
+}
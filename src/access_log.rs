@@ -0,0 +1,124 @@
+use crate::config::AccessLogFormat;
+use chrono::Utc;
+use hyper::body::HttpBody;
+use serde::Serialize;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::net::SocketAddr;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Decision of an `/authorize` call, attached to the response as an extension so the access log
+/// can report it without re-reading the body.
+#[derive(Debug, Clone)]
+pub struct LoggedDecision(pub String);
+
+/// One served request.
+#[derive(Debug, Serialize)]
+pub struct AccessEntry {
+    pub time: String,
+    pub client: String,
+    pub method: String,
+    pub path: String,
+    pub protocol: String,
+    pub status: u16,
+    pub latency_ms: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bytes: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub decision: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub referer: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user_agent: Option<String>,
+}
+
+impl AccessEntry {
+    pub fn new<B>(client: SocketAddr, req: &hyper::Request<B>) -> Self {
+        let headers = req.headers();
+        let header = |name: hyper::header::HeaderName| {
+            headers
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .map(str::to_string)
+        };
+        Self {
+            time: String::new(),
+            client: client.ip().to_string(),
+            method: req.method().to_string(),
+            path: req.uri().path_and_query().map(|p| p.to_string()).unwrap_or_default(),
+            protocol: format!("{:?}", req.version()),
+            status: 0,
+            latency_ms: 0.0,
+            bytes: None,
+            decision: None,
+            referer: header(hyper::header::REFERER),
+            user_agent: header(hyper::header::USER_AGENT),
+        }
+    }
+}
+
+/// HTTP access log, written separately from the application log on stdout.
+pub struct AccessLog {
+    format: AccessLogFormat,
+    sink: Mutex<Box<dyn Write + Send>>,
+}
+
+impl AccessLog {
+    /// Opens the access log; an empty `path` writes to stdout.
+    pub fn open(format: AccessLogFormat, path: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let sink: Box<dyn Write + Send> = if path.is_empty() {
+            Box::new(std::io::stdout())
+        } else {
+            let file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .map_err(|e| format!("Failed to open access log {}: {}", path, e))?;
+            log::info!("Writing access log to {}", path);
+            Box::new(file)
+        };
+        Ok(Self {
+            format,
+            sink: Mutex::new(sink),
+        })
+    }
+
+    /// Completes `entry` from the response and writes it.
+    pub fn record(&self, mut entry: AccessEntry, response: &hyper::Response<hyper::Body>, latency: Duration) {
+        let now = Utc::now();
+        entry.status = response.status().as_u16();
+        entry.latency_ms = (latency.as_secs_f64() * 1_000_000.0).round() / 1000.0;
+        entry.bytes = response.body().size_hint().exact();
+        entry.decision = response.extensions().get::<LoggedDecision>().map(|d| d.0.clone());
+
+        let line = match self.format {
+            AccessLogFormat::Combined => {
+                let quoted = |v: &Option<String>| v.as_deref().unwrap_or("-").replace('"', "\\\"");
+                format!(
+                    "{} - - [{}] \"{} {} {}\" {} {} \"{}\" \"{}\" {:.3} {}",
+                    entry.client,
+                    now.format("%d/%b/%Y:%H:%M:%S %z"),
+                    entry.method,
+                    entry.path,
+                    entry.protocol,
+                    entry.status,
+                    entry.bytes.map(|b| b.to_string()).unwrap_or_else(|| "-".to_string()),
+                    quoted(&entry.referer),
+                    quoted(&entry.user_agent),
+                    entry.latency_ms,
+                    entry.decision.as_deref().unwrap_or("-"),
+                )
+            }
+            AccessLogFormat::Json => {
+                entry.time = now.to_rfc3339_opts(chrono::SecondsFormat::Millis, true);
+                serde_json::to_string(&entry).unwrap()
+            }
+        };
+
+        let mut sink = self.sink.lock().unwrap();
+        if let Err(e) = writeln!(sink, "{}", line) {
+            log::error!("Failed to write access log: {}", e);
+        }
+    }
+}
@@ -0,0 +1,134 @@
+use crate::acceptors::Acceptors;
+use crate::config::{AcmeChallenge, Config};
+use crate::{tls, upgrade, CedarService};
+use futures::StreamExt;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Response, Server, StatusCode};
+use log::{error, info};
+use rustls_acme::caches::DirCache;
+use rustls_acme::{AcmeConfig, ResolvesServerCertAcme, UseChallenge};
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio_rustls::rustls::crypto::ring;
+
+const HTTP01_PREFIX: &str = "/.well-known/acme-challenge/";
+
+/// Binds the plain-HTTP listener that answers HTTP-01 validation requests; everything else gets
+/// `404`. Binding happens up front so a port that is unavailable fails startup rather than
+/// leaving the agent unable to ever pass validation. A process started by an upgrade takes over
+/// the listener of the one it replaces, which stops answering once it is to exit.
+fn serve_http01(
+    addr: SocketAddr,
+    resolver: Arc<ResolvesServerCertAcme>,
+    service: Arc<CedarService>,
+) -> Result<impl std::future::Future<Output = ()>, Box<dyn std::error::Error>> {
+    let make_svc = make_service_fn(move |_| {
+        let resolver = Arc::clone(&resolver);
+        async move {
+            Ok::<_, Infallible>(service_fn(move |req: hyper::Request<Body>| {
+                let key_auth = req
+                    .uri()
+                    .path()
+                    .strip_prefix(HTTP01_PREFIX)
+                    .and_then(|token| resolver.get_http_01_key_auth(token));
+                let response = match key_auth {
+                    Some(key_auth) => Response::builder()
+                        .header("content-type", "application/octet-stream")
+                        .body(Body::from(key_auth))
+                        .unwrap(),
+                    None => Response::builder()
+                        .status(StatusCode::NOT_FOUND)
+                        .body(Body::from("Not found"))
+                        .unwrap(),
+                };
+                async move { Ok::<_, Infallible>(response) }
+            }))
+        }
+    });
+
+    let listener = match upgrade::inherited_http01()? {
+        Some(listener) => listener,
+        None => std::net::TcpListener::bind(addr)
+            .map_err(|e| format!("Failed to bind ACME HTTP-01 listener on {}: {}", addr, e))?,
+    };
+    upgrade::register_http01(&listener);
+    listener.set_nonblocking(true)?;
+    let server = Server::from_tcp(listener)?
+        .serve(make_svc)
+        .with_graceful_shutdown(async move { service.exiting().await });
+    info!("Serving ACME HTTP-01 challenges on {}", addr);
+    Ok(async move {
+        if let Err(e) = server.await {
+            error!("ACME HTTP-01 listener failed: {}", e);
+        }
+    })
+}
+
+/// Registers `contact` as a `mailto:` URI unless it already carries a scheme.
+fn contact_uri(contact: &str) -> String {
+    if contact.contains(':') {
+        contact.to_string()
+    } else {
+        format!("mailto:{}", contact)
+    }
+}
+
+/// Serves HTTPS with certificates obtained and renewed from the configured ACME directory.
+/// Certificates and the account key are cached on disk, so restarts do not re-issue.
+pub async fn serve(
+    addr: SocketAddr,
+    service: Arc<CedarService>,
+    config: &Config,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let acceptors = Acceptors::bind(addr, config)?;
+    let provider = Arc::new(ring::default_provider());
+    let acme = AcmeConfig::new_with_provider(&config.acme_domains, Arc::clone(&provider))
+        .contact(config.acme_contact.iter().map(|contact| contact_uri(contact)))
+        .cache(DirCache::new(config.acme_cache_dir.clone()));
+    let acme = match config.acme_directory.as_str() {
+        "production" => acme.directory_lets_encrypt(true),
+        "staging" => acme.directory_lets_encrypt(false),
+        url => acme.directory(url),
+    };
+    let mut state = acme
+        .challenge_type(match config.acme_challenge {
+            AcmeChallenge::TlsAlpn01 => UseChallenge::TlsAlpn01,
+            AcmeChallenge::Http01 => UseChallenge::Http01,
+        })
+        .state();
+
+    let resolver = state.resolver();
+    let challenge = match config.acme_challenge {
+        AcmeChallenge::TlsAlpn01 => Some(state.challenge_rustls_config_with_provider(provider)),
+        AcmeChallenge::Http01 => {
+            let http_addr: SocketAddr = config
+                .acme_http_addr
+                .parse()
+                .map_err(|e| format!("Invalid ACME HTTP address: {}", e))?;
+            tokio::spawn(serve_http01(http_addr, Arc::clone(&resolver), Arc::clone(&service))?);
+            None
+        }
+    };
+
+    // Drives ordering and renewal; each step is reported as an event
+    let events = Arc::clone(&service);
+    tokio::spawn(async move {
+        while let Some(event) = state.next().await {
+            match event {
+                Ok(ok) => {
+                    info!("ACME: {:?}", ok);
+                    events.metrics.incr("acme_events", &[("result", "success")]);
+                }
+                Err(e) => {
+                    error!("ACME: {}", e);
+                    events.metrics.incr("acme_events", &[("result", "error")]);
+                }
+            }
+        }
+    });
+
+    info!("Requesting certificates for {} via ACME", config.acme_domains.join(", "));
+    let server_config = tls::server_config(resolver, config.acme_challenge == AcmeChallenge::TlsAlpn01)?;
+    tls::serve_tls(acceptors, service, server_config, challenge).await
+}
@@ -0,0 +1,406 @@
+use crate::config::Config;
+use crate::{digest, policies};
+use crate::{CedarService, PolicyState};
+use cedar_policy::{PolicyId, PolicySet};
+use chrono::{SecondsFormat, Utc};
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Read;
+
+/// `format` of every archive's manifest.
+const FORMAT: &str = "cedar-agent-archive";
+/// Bumped when a change to the layout would make older agents misread an archive.
+const FORMAT_VERSION: u32 = 1;
+const MANIFEST: &str = "manifest.json";
+/// Static policies in Cedar's JSON policy format, which keeps every policy ID.
+const POLICIES: &str = "policies.json";
+/// Templates and template-linked policies in Cedar's JSON policy format.
+const TEMPLATES: &str = "templates.json";
+const SCHEMA: &str = "schema.json";
+const ENTITIES: &str = "entities.json";
+/// Largest archive `POST /admin/import` accepts.
+pub const MAX_ARCHIVE: usize = 64 << 20;
+/// Largest archive accepted once decompressed.
+const MAX_UNPACKED: u64 = 1 << 30;
+
+/// What an archive holds and where it came from, stored as `manifest.json`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Manifest {
+    format: String,
+    format_version: u32,
+    /// Version of the agent that wrote the archive.
+    agent_version: String,
+    created: String,
+    /// Data version of the exported state on the agent that wrote it.
+    data_version: u64,
+    policies: usize,
+    templates: usize,
+    template_links: usize,
+    entities: usize,
+    schema: bool,
+    /// IDs of the disabled policies.
+    #[serde(default)]
+    disabled: Vec<String>,
+    /// SHA-256 digest of each other file in the archive.
+    files: BTreeMap<String, String>,
+}
+
+/// The state an archive holds.
+pub struct Contents {
+    pub manifest: Manifest,
+    policy_set: PolicySet,
+    schema: Option<Value>,
+    entities: Vec<Value>,
+}
+
+/// What `POST /admin/import` and `cedar-agent import` report.
+#[derive(Debug, Serialize)]
+pub struct Summary {
+    pub policies: usize,
+    pub templates: usize,
+    pub template_links: usize,
+    pub entities: usize,
+    pub schema: bool,
+    pub disabled: usize,
+    /// Data version the archive was exported at.
+    pub exported_version: u64,
+    pub exported_at: String,
+}
+
+impl Contents {
+    pub fn summary(&self) -> Summary {
+        Summary {
+            policies: self.manifest.policies,
+            templates: self.manifest.templates,
+            template_links: self.manifest.template_links,
+            entities: self.manifest.entities,
+            schema: self.manifest.schema,
+            disabled: self.manifest.disabled.len(),
+            exported_version: self.manifest.data_version,
+            exported_at: self.manifest.created.clone(),
+        }
+    }
+
+    /// Parses the archived state, with entities ingested as this agent is configured to and
+    /// checked against the archived schema, as are the policies.
+    pub fn into_state(self, service: &CedarService) -> Result<PolicyState, String> {
+        let schema = match self.schema {
+            Some(ref json) => Some(
+                cedar_policy::Schema::from_json_value(json.clone()).map_err(|e| format!("Invalid schema: {}", e))?,
+            ),
+            None => None,
+        };
+        if let Some(ref schema) = schema {
+            crate::schema::check_policies(schema, &self.policy_set)
+                .map_err(|e| format!("{}: {}", e.error, e.validation_errors.join("; ")))?;
+        }
+        let entities = crate::entities::EntityStore::from_json(
+            Value::Array(self.entities),
+            schema.as_ref(),
+            service.state().entities.ingest(),
+        )
+        .map_err(|e| format!("Invalid entities: {}", e))?;
+        entities.check_budget(service.entity_memory_limit)?;
+        let disabled = self
+            .manifest
+            .disabled
+            .iter()
+            .map(PolicyId::new)
+            .filter(|id| self.policy_set.policy(id).is_some())
+            .collect();
+        let policies = policies::LoadedPolicies::from_set(self.policy_set, MANIFEST);
+        Ok(PolicyState {
+            disabled,
+            ..PolicyState::new(policies, schema, self.schema, std::sync::Arc::new(entities))
+        })
+    }
+}
+
+/// Packs `state` into a gzip-compressed tar archive.
+pub fn export(state: &PolicyState, data_version: u64) -> Result<Vec<u8>, String> {
+    let json = state
+        .policy_set
+        .clone()
+        .to_json()
+        .map_err(|e| format!("Failed to serialize policies: {}", e))?;
+    let part = |key: &str| json.get(key).cloned().unwrap_or_else(|| serde_json::json!({}));
+    let templates = serde_json::json!({
+        "templates": part("templates"),
+        "templateLinks": json.get("templateLinks").cloned().unwrap_or_else(|| serde_json::json!([])),
+    });
+    let entities: Vec<Value> = state
+        .entities
+        .direct()
+        .iter()
+        .map(|e| e.to_json_value().map_err(|e| format!("Failed to serialize entities: {}", e)))
+        .collect::<Result<_, _>>()?;
+
+    let mut files = vec![
+        (POLICIES, to_vec(&serde_json::json!({ "staticPolicies": part("staticPolicies") }))?),
+        (TEMPLATES, to_vec(&templates)?),
+        (ENTITIES, to_vec(&entities)?),
+    ];
+    if let Some(ref schema) = state.schema_json {
+        files.push((SCHEMA, to_vec(schema)?));
+    }
+    let manifest = Manifest {
+        format: FORMAT.to_string(),
+        format_version: FORMAT_VERSION,
+        agent_version: env!("CARGO_PKG_VERSION").to_string(),
+        created: Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true),
+        data_version,
+        policies: state.policy_set.policies().filter(|p| p.is_static()).count(),
+        templates: state.policy_set.templates().count(),
+        template_links: state.policy_set.policies().filter(|p| !p.is_static()).count(),
+        entities: entities.len(),
+        schema: state.schema_json.is_some(),
+        disabled: state.disabled.iter().map(|id| id.to_string()).collect(),
+        files: files.iter().map(|(name, data)| (name.to_string(), digest::sha256_hex(data))).collect(),
+    };
+    files.insert(0, (MANIFEST, to_vec(&manifest)?));
+
+    pack(&files, Utc::now().timestamp().max(0) as u64)
+}
+
+/// Unpacks an archive, checking its format and the digest of every file.
+pub fn import(archive: &[u8]) -> Result<Contents, String> {
+    let mut files = unpack(archive, MAX_UNPACKED)?;
+    let manifest: Manifest = parse(&files, MANIFEST)?.ok_or("Archive has no manifest.json")?;
+    if manifest.format != FORMAT {
+        return Err(format!("Not a {} (format is '{}')", FORMAT, manifest.format));
+    }
+    if manifest.format_version > FORMAT_VERSION {
+        return Err(format!(
+            "Archive format version {} is newer than this agent reads ({}); upgrade the agent",
+            manifest.format_version, FORMAT_VERSION
+        ));
+    }
+    for (name, digest) in &manifest.files {
+        let data = files.get(name.as_str()).ok_or_else(|| format!("Archive is missing {}", name))?;
+        if digest::sha256_hex(data) != *digest {
+            return Err(format!("{} does not match the digest in the manifest", name));
+        }
+    }
+    files.retain(|name, _| manifest.files.contains_key(name) || name == MANIFEST);
+
+    let static_policies: Value = parse(&files, POLICIES)?.ok_or("Archive has no policies.json")?;
+    let mut policies = parse::<Value>(&files, TEMPLATES)?.unwrap_or_else(|| serde_json::json!({}));
+    let object = policies.as_object_mut().ok_or("templates.json is not an object")?;
+    object.insert(
+        "staticPolicies".to_string(),
+        static_policies.get("staticPolicies").cloned().unwrap_or_else(|| serde_json::json!({})),
+    );
+    object.entry("templates").or_insert_with(|| serde_json::json!({}));
+    object.entry("templateLinks").or_insert_with(|| serde_json::json!([]));
+    let policy_set = PolicySet::from_json_value(policies).map_err(|e| format!("Invalid policies: {}", e))?;
+    crate::schedule::check(&policy_set)?;
+
+    Ok(Contents {
+        schema: parse(&files, SCHEMA)?,
+        entities: parse(&files, ENTITIES)?.unwrap_or_default(),
+        policy_set,
+        manifest,
+    })
+}
+
+fn parse<T: serde::de::DeserializeOwned>(files: &BTreeMap<String, Vec<u8>>, name: &str) -> Result<Option<T>, String> {
+    files
+        .get(name)
+        .map(|data| serde_json::from_slice(data).map_err(|e| format!("Invalid {}: {}", name, e)))
+        .transpose()
+}
+
+fn to_vec<T: Serialize>(value: &T) -> Result<Vec<u8>, String> {
+    serde_json::to_vec_pretty(value).map_err(|e| e.to_string())
+}
+
+/// Packs `files` into a gzip-compressed tar archive.
+fn pack(files: &[(&str, Vec<u8>)], mtime: u64) -> Result<Vec<u8>, String> {
+    let mut builder = tar::Builder::new(GzEncoder::new(Vec::new(), Compression::default()));
+    for (name, data) in files {
+        let mut header = tar::Header::new_ustar();
+        header.set_entry_type(tar::EntryType::Regular);
+        header.set_size(data.len() as u64);
+        header.set_mode(0o644);
+        header.set_mtime(mtime);
+        builder
+            .append_data(&mut header, name, data.as_slice())
+            .map_err(|e| format!("Failed to write {} to the archive: {}", name, e))?;
+    }
+    builder
+        .into_inner()
+        .and_then(GzEncoder::finish)
+        .map_err(|e| format!("Failed to write the archive: {}", e))
+}
+
+/// The regular files in a gzip-compressed tar archive by name, with any leading `./` removed.
+/// Refuses more than `limit` bytes once decompressed, and reads to the end of the gzip stream
+/// so its checksum and length are checked.
+fn unpack(archive: &[u8], limit: u64) -> Result<BTreeMap<String, Vec<u8>>, String> {
+    let invalid = |e: std::io::Error| format!("Failed to read the archive: {}", e);
+    let mut tar = tar::Archive::new(Limited {
+        inner: GzDecoder::new(archive),
+        left: limit,
+    });
+    let mut files = BTreeMap::new();
+    for entry in tar.entries().map_err(invalid)? {
+        let mut entry = entry.map_err(invalid)?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        let name = entry.path().map_err(invalid)?.to_string_lossy().trim_start_matches("./").to_string();
+        let mut data = Vec::new();
+        entry.read_to_end(&mut data).map_err(invalid)?;
+        files.insert(name, data);
+    }
+    std::io::copy(&mut tar.into_inner(), &mut std::io::sink()).map_err(invalid)?;
+    Ok(files)
+}
+
+/// Reads at most `left` more bytes from `inner`, failing rather than stopping short.
+struct Limited<R> {
+    inner: R,
+    left: u64,
+}
+
+impl<R: Read> Read for Limited<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let max = buf.len().min(usize::try_from(self.left.saturating_add(1)).unwrap_or(usize::MAX));
+        let read = self.inner.read(&mut buf[..max])?;
+        self.left = self
+            .left
+            .checked_sub(read as u64)
+            .ok_or_else(|| std::io::Error::other("it is too large once decompressed"))?;
+        Ok(read)
+    }
+}
+
+/// `cedar-agent export --out <archive>`: writes the state the agent would start with (its files,
+/// or Verified Permissions, and the persisted entities) to an archive.
+pub fn run_export(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let out = match args {
+        [flag, path] if flag == "--out" => path,
+        _ => return Err("Usage: cedar-agent export --out <state.tar.gz>".into()),
+    };
+    let config = Config::from_env()?;
+    let service = CedarService::new(&config)?;
+    let archive = export(&service.state(), service.data_version())?;
+    fs::write(out, archive).map_err(|e| format!("Failed to write {}: {}", out, e))?;
+    println!("Exported to {}", out);
+    Ok(())
+}
+
+/// `cedar-agent import <archive>`: checks an archive and writes its policies, schema and entities
+/// to the files the agent loads (`CEDAR_POLICY_PATH`, `CEDAR_SCHEMA_PATH` and
+/// `CEDAR_ENTITIES_PATH`), so the next start serves the archived state.
+pub fn run_import(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let path = match args {
+        [path] if !path.starts_with('-') => path,
+        _ => return Err("Usage: cedar-agent import <state.tar.gz>".into()),
+    };
+    let config = Config::from_env()?;
+    let archive = fs::read(path).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+    let contents = import(&archive)?;
+    if contents.policy_set.policies().any(|p| !p.is_static()) {
+        return Err("The archive holds template-linked policies, which Cedar policy files cannot express; \
+                    import it with POST /admin/import instead"
+            .into());
+    }
+    let entities_path = config
+        .entities_path
+        .clone()
+        .ok_or("CEDAR_ENTITIES_PATH is needed to import entities")?;
+    let summary = contents.summary();
+
+    let mut text = Vec::new();
+    for template in contents.policy_set.templates() {
+        text.push(with_id(template.annotation(crate::policies::ID_ANNOTATION), template.id(), &template.to_string()));
+    }
+    for policy in contents.policy_set.policies() {
+        text.push(with_id(policy.annotation(crate::policies::ID_ANNOTATION), policy.id(), &policy.to_string()));
+    }
+    fs::write(&config.policy_path, text.join("\n\n") + "\n")
+        .map_err(|e| format!("Failed to write {}: {}", config.policy_path, e))?;
+    if let Some(ref schema) = contents.schema {
+        fs::write(&config.schema_path, to_vec(schema)?).map_err(|e| format!("Failed to write {}: {}", config.schema_path, e))?;
+    }
+    fs::write(&entities_path, to_vec(&contents.entities)?).map_err(|e| format!("Failed to write {}: {}", entities_path, e))?;
+    if summary.disabled > 0 {
+        eprintln!("Note: {} disabled policies are written enabled; files cannot disable policies", summary.disabled);
+    }
+    println!("{}", serde_json::to_string_pretty(&summary)?);
+    Ok(())
+}
+
+/// Policy text that keeps `id` when loaded from a file, adding an `@id` unless it has one.
+fn with_id(annotated: Option<&str>, id: &PolicyId, text: &str) -> String {
+    match annotated {
+        Some(_) => text.to_string(),
+        None => format!("@id({})\n{}", serde_json::to_string(&id.to_string()).unwrap_or_default(), text),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tar_and_gzip_round_trip() {
+        let long = format!("{}.json", "n".repeat(120));
+        let archive = pack(&[("a.json", b"{}".to_vec()), (&long, vec![b'x'; 1000])], 0).unwrap();
+        let files = unpack(&archive, MAX_UNPACKED).unwrap();
+        assert_eq!(files["a.json"], b"{}");
+        assert_eq!(files[&long].len(), 1000);
+
+        assert!(unpack(&archive, 100).err().unwrap().contains("too large"));
+        for trailer in [8, 4] {
+            // The CRC, then the length
+            let mut corrupt = archive.clone();
+            let at = corrupt.len() - trailer;
+            corrupt[at] ^= 1;
+            assert!(unpack(&corrupt, MAX_UNPACKED).is_err());
+        }
+        assert!(unpack(b"not an archive at all", MAX_UNPACKED).is_err());
+    }
+
+    #[test]
+    fn rejects_tampered_archives() {
+        let manifest = |files: BTreeMap<String, String>| Manifest {
+            format: FORMAT.to_string(),
+            format_version: FORMAT_VERSION,
+            agent_version: String::new(),
+            created: String::new(),
+            data_version: 0,
+            policies: 0,
+            templates: 0,
+            template_links: 0,
+            entities: 0,
+            schema: false,
+            disabled: Vec::new(),
+            files,
+        };
+        let policies = br#"{"staticPolicies": {}}"#;
+        let archive = |manifest: &Manifest, policies: &[u8]| {
+            pack(&[(MANIFEST, to_vec(manifest).unwrap()), (POLICIES, policies.to_vec())], 0).unwrap()
+        };
+        let files = BTreeMap::from([(POLICIES.to_string(), digest::sha256_hex(policies))]);
+        assert!(import(&archive(&manifest(files.clone()), policies)).is_ok());
+        assert!(import(&archive(&manifest(files.clone()), br#"{"staticPolicies": {} }"#)).is_err());
+
+        let mut newer = manifest(files);
+        newer.format_version += 1;
+        assert!(import(&archive(&newer, policies)).err().unwrap().contains("newer"));
+    }
+
+    #[test]
+    fn keeps_positional_ids_in_policy_text() {
+        let id = PolicyId::new("policy0");
+        assert_eq!(with_id(None, &id, "permit(principal, action, resource);"), "@id(\"policy0\")\npermit(principal, action, resource);");
+        assert_eq!(with_id(Some("a"), &id, "@id(\"a\") permit(principal, action, resource);"), "@id(\"a\") permit(principal, action, resource);");
+    }
+}
@@ -0,0 +1,310 @@
+use crate::{digest, quota, rbac, reload, PolicyState};
+use cedar_policy::{Entity, PolicySet};
+use chrono::{SecondsFormat, Utc};
+use hyper::Body;
+use log::info;
+use serde::Serialize;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::net::IpAddr;
+use std::sync::Mutex;
+
+/// Actor of changes the agent makes on its own (LDAP, Kubernetes and AVP syncs).
+const AGENT: &str = "agent";
+/// Actor of admin requests that carry no credential the agent knows.
+const ANONYMOUS: &str = "anonymous";
+
+tokio::task_local! {
+    /// Who made the request being served.
+    static ACTOR: Actor;
+}
+
+/// How an actor was identified. Administrator and API key names are separate namespaces, so a
+/// name alone does not say who acted.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Kind {
+    Admin,
+    ApiKey,
+    Scim,
+    Anonymous,
+    Agent,
+}
+
+/// Who made a change: an administrator, API key or SCIM client, and the address they called
+/// from.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Actor {
+    #[serde(rename = "actor")]
+    name: String,
+    #[serde(skip)]
+    kind: Kind,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    address: Option<IpAddr>,
+}
+
+impl Actor {
+    /// The caller of a checked request: the administrator it authenticated as, else the name
+    /// of its API key.
+    pub fn of(req: &hyper::Request<Body>, address: IpAddr) -> Self {
+        let (name, kind) = match (req.extensions().get::<rbac::Admin>(), req.extensions().get::<quota::Caller>()) {
+            (Some(admin), _) => (admin.0.clone(), Kind::Admin),
+            (None, Some(caller)) => (caller.0.clone(), Kind::ApiKey),
+            (None, None) if req.uri().path().starts_with("/scim/") => ("scim".to_string(), Kind::Scim),
+            (None, None) => (ANONYMOUS.to_string(), Kind::Anonymous),
+        };
+        Self {
+            name,
+            kind,
+            address: Some(address),
+        }
+    }
+
+    /// The actor of the request being served, or the agent itself outside of one.
+    fn current() -> Self {
+        ACTOR.try_with(Actor::clone).unwrap_or_else(|_| Self {
+            name: AGENT.to_string(),
+            kind: Kind::Agent,
+            address: None,
+        })
+    }
+}
+
+/// Name of the caller of the request being served, if it authenticated as an administrator or
+/// with an API key.
+pub fn identified() -> Option<String> {
+    ACTOR
+        .try_with(|actor| matches!(actor.kind, Kind::Admin | Kind::ApiKey).then(|| actor.name.clone()))
+        .ok()
+        .flatten()
+}
+
+/// Name of the administrator the request being served authenticated as (see `CEDAR_ADMIN_KEYS`
+/// and `CEDAR_ADMIN_USERS`); `None` for API keys and other callers.
+pub fn administrator() -> Option<String> {
+    ACTOR
+        .try_with(|actor| (actor.kind == Kind::Admin).then(|| actor.name.clone()))
+        .ok()
+        .flatten()
+}
+
+/// Runs `future` with `actor` as the author of the changes it makes.
+pub async fn scope<F: std::future::Future>(actor: Actor, future: F) -> F::Output {
+    ACTOR.scope(actor, future).await
+}
+
+/// SHA-256 digests of the policies, schema and entities of a state, and the policies it has
+/// disabled, to show what a change replaced and with what.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Hashes {
+    policies: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    schema: Option<String>,
+    entities: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    disabled: Vec<String>,
+}
+
+impl Hashes {
+    pub fn of(state: &PolicyState) -> Self {
+        Self {
+            policies: policies_hash(&state.policy_set),
+            schema: state.schema_json.as_ref().map(|schema| sha256(&[reload::canonical(schema.clone()).to_string()])),
+            entities: entities_hash(state.entities.direct()),
+            disabled: state.disabled.iter().map(|id| id.to_string()).collect(),
+        }
+    }
+}
+
+/// Digest of policies and templates by ID and text, independent of the order they were loaded
+/// in.
+pub fn policies_hash(policy_set: &PolicySet) -> String {
+    let policies: Vec<String> = reload::statements(policy_set)
+        .into_iter()
+        .map(|(id, statement)| format!("{}\0{}", id, statement))
+        .collect();
+    sha256(&policies)
+}
+
+/// Digest of entities in their canonical JSON form, independent of the order they (and their
+/// parents) are stored in.
+fn entities_hash(entities: &[Entity]) -> String {
+    let mut entities: Vec<String> = entities
+        .iter()
+        .map(|entity| match entity.to_json_value() {
+            Ok(json) => reload::canonical(json).to_string(),
+            Err(_) => entity.uid().to_string(),
+        })
+        .collect();
+    entities.sort();
+    sha256(&entities)
+}
+
+fn sha256(parts: &[String]) -> String {
+    digest::hex(&digest::of_parts(parts))
+}
+
+/// How a change ended.
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum Outcome {
+    Success,
+    Failure,
+}
+
+#[derive(Debug, Serialize)]
+struct Entry<'a> {
+    time: String,
+    #[serde(flatten)]
+    actor: Actor,
+    action: &'a str,
+    outcome: Outcome,
+    /// The data version the change produced.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    data_version: Option<u64>,
+    before: Hashes,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    after: Option<Hashes>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+/// Log of changes to policies, schema and entities, one JSON object per line, kept apart from
+/// the decision log.
+pub struct AuditLog {
+    file: Mutex<File>,
+}
+
+impl AuditLog {
+    pub fn open(path: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|e| format!("Failed to open audit log {}: {}", path, e))?;
+        info!("Writing admin audit log to {}", path);
+        Ok(Self { file: Mutex::new(file) })
+    }
+
+    /// Records a change that took the state from `before` to `after` as data version `version`.
+    pub fn success(&self, action: &str, before: Hashes, after: Hashes, version: u64) -> Result<(), String> {
+        self.write(Entry {
+            data_version: Some(version),
+            after: Some(after),
+            ..entry(action, Outcome::Success, before)
+        })
+    }
+
+    /// Records a change that was rejected or failed, leaving the state at `before`.
+    pub fn failure(&self, action: &str, before: Hashes, error: String) -> Result<(), String> {
+        self.write(Entry {
+            error: Some(error),
+            ..entry(action, Outcome::Failure, before)
+        })
+    }
+
+    fn write(&self, entry: Entry) -> Result<(), String> {
+        let mut line = serde_json::to_string(&entry).map_err(|e| e.to_string())?;
+        line.push('\n');
+        let mut file = self.file.lock().unwrap();
+        file.write_all(line.as_bytes()).map_err(|e| e.to_string())?;
+        file.flush().map_err(|e| e.to_string())
+    }
+}
+
+fn entry(action: &str, outcome: Outcome, before: Hashes) -> Entry<'_> {
+    Entry {
+        time: Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+        actor: Actor::current(),
+        action,
+        outcome,
+        data_version: None,
+        before,
+        after: None,
+        error: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn hashes_do_not_depend_on_order() {
+        let policies = |src: &str| crate::policies::parse(src).unwrap();
+        let permit = r#"@id("a") permit(principal, action, resource);"#;
+        let forbid = r#"@id("b") forbid(principal, action, resource);"#;
+        assert_eq!(
+            policies_hash(&policies(&format!("{}{}", permit, forbid))),
+            policies_hash(&policies(&format!("{}{}", forbid, permit)))
+        );
+        assert_ne!(
+            policies_hash(&policies(permit)),
+            policies_hash(&policies(r#"@id("a") permit(principal, action, resource) when { false };"#))
+        );
+
+        let entity = |uid: &str| Entity::with_uid(uid.parse().unwrap());
+        let (alice, bob) = (entity(r#"User::"alice""#), entity(r#"User::"bob""#));
+        assert_eq!(entities_hash(&[alice.clone(), bob.clone()]), entities_hash(&[bob.clone(), alice.clone()]));
+        assert_ne!(entities_hash(&[alice]), entities_hash(&[bob]));
+        assert_eq!(entities_hash(&[]), sha256(&[]));
+
+        let groups = |parents: &[&str]| {
+            let parents = parents.iter().map(|p| format!(r#"Group::"{}""#, p).parse().unwrap()).collect();
+            Entity::new_no_attrs(r#"User::"alice""#.parse().unwrap(), parents)
+        };
+        assert_eq!(entities_hash(&[groups(&["a", "b", "c"])]), entities_hash(&[groups(&["c", "a", "b"])]));
+    }
+
+    #[tokio::test]
+    async fn records_the_actor_of_the_request() {
+        let req = |path: &str| hyper::Request::builder().uri(path).body(Body::empty()).unwrap();
+        let address: IpAddr = "10.0.0.5".parse().unwrap();
+        let mut admin = req("/v1/schema");
+        admin.extensions_mut().insert(rbac::Admin("ops".to_string()));
+        admin.extensions_mut().insert(quota::Caller("ci".to_string()));
+        assert_eq!(Actor::of(&admin, address).name, "ops");
+        assert_eq!(Actor::of(&req("/scim/v2/Users"), address).name, "scim");
+        assert_eq!(Actor::of(&req("/admin/reload"), address).name, ANONYMOUS);
+
+        let actor = Actor::of(&admin, address);
+        assert_eq!(scope(actor.clone(), async { Actor::current() }).await, actor);
+        assert_eq!(Actor::current().name, AGENT);
+        assert_eq!(scope(actor, async { identified() }).await.as_deref(), Some("ops"));
+        assert_eq!(scope(Actor::of(&req("/v1/schema"), address), async { identified() }).await, None);
+        assert_eq!(identified(), None);
+
+        // An API key named like an administrator is not one
+        let mut key = req("/v1/schema");
+        key.extensions_mut().insert(quota::Caller("ops".to_string()));
+        assert_eq!(scope(Actor::of(&key, address), async { identified() }).await.as_deref(), Some("ops"));
+        assert_eq!(scope(Actor::of(&key, address), async { administrator() }).await, None);
+        assert_eq!(scope(Actor::of(&admin, address), async { administrator() }).await.as_deref(), Some("ops"));
+    }
+
+    #[test]
+    fn serializes_entries() {
+        let hashes = Hashes {
+            policies: "p".to_string(),
+            schema: None,
+            entities: "e".to_string(),
+            disabled: Vec::new(),
+        };
+        let entry = Entry {
+            error: Some("Schema rejected".to_string()),
+            ..entry("schema.replace", Outcome::Failure, hashes)
+        };
+        let mut json = serde_json::to_value(&entry).unwrap();
+        json.as_object_mut().unwrap().remove("time");
+        assert_eq!(
+            json,
+            json!({
+                "actor": "agent",
+                "action": "schema.replace",
+                "outcome": "failure",
+                "before": {"policies": "p", "entities": "e"},
+                "error": "Schema rejected"
+            })
+        );
+    }
+}
@@ -0,0 +1,340 @@
+use crate::config::Config;
+use crate::{policies, CedarService};
+use aws_sdk_verifiedpermissions::error::DisplayErrorContext;
+use aws_sdk_verifiedpermissions::primitives::DateTime;
+use aws_sdk_verifiedpermissions::types::{EntityIdentifier, PolicyDefinitionDetail, PolicyDefinitionItem};
+use aws_sdk_verifiedpermissions::Client;
+use cedar_policy::{EntityId, EntityUid, Policy, PolicyId, PolicySet, Schema, SlotId, Template};
+use log::{error, info};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// A policy as stored in Verified Permissions.
+#[derive(Debug, Clone, PartialEq)]
+pub enum StoredPolicy {
+    Static {
+        id: String,
+        statement: String,
+    },
+    Linked {
+        id: String,
+        template_id: String,
+        /// Entities bound to the template's `?principal` and `?resource` slots.
+        values: HashMap<SlotId, EntityUid>,
+    },
+}
+
+/// Builds the policy set from templates (`(id, statement)`) and policies. Everything keeps its
+/// Verified Permissions ID, so decisions name the same policies as the AVP console.
+pub fn policy_set(templates: &[(String, String)], policies: &[StoredPolicy]) -> Result<PolicySet, String> {
+    let mut policy_set = PolicySet::new();
+    for (id, statement) in templates {
+        let template = Template::parse(Some(PolicyId::new(id)), statement)
+            .map_err(|e| format!("Failed to parse policy template {}: {}", id, e))?;
+        policy_set
+            .add_template(template)
+            .map_err(|e| format!("Failed to add policy template {}: {}", id, e))?;
+    }
+    for policy in policies {
+        match policy {
+            StoredPolicy::Static { id, statement } => {
+                let parsed = Policy::parse(Some(PolicyId::new(id)), statement)
+                    .map_err(|e| format!("Failed to parse policy {}: {}", id, e))?;
+                policy_set
+                    .add(parsed)
+                    .map_err(|e| format!("Failed to add policy {}: {}", id, e))?;
+            }
+            StoredPolicy::Linked { id, template_id, values } => {
+                policy_set
+                    .link(PolicyId::new(template_id), PolicyId::new(id), values.clone())
+                    .map_err(|e| format!("Failed to link policy {} to template {}: {}", id, template_id, e))?;
+            }
+        }
+    }
+    crate::schedule::check(&policy_set)?;
+    Ok(policy_set)
+}
+
+fn entity_uid(entity: Option<&EntityIdentifier>) -> Result<Option<EntityUid>, String> {
+    entity
+        .map(|entity| {
+            let type_name = entity
+                .entity_type()
+                .parse()
+                .map_err(|e| format!("Invalid entity type {}: {}", entity.entity_type(), e))?;
+            Ok(EntityUid::from_type_name_and_id(type_name, EntityId::new(entity.entity_id())))
+        })
+        .transpose()
+}
+
+/// Reads a policy store, fetching statements only for policies and templates that changed since
+/// the last sync.
+struct Syncer {
+    client: Client,
+    store_id: String,
+    /// Statement of every policy and template, with the update time it was fetched at.
+    statements: HashMap<String, (DateTime, String)>,
+    /// Update times of everything in the store at the last sync, to skip unchanged stores.
+    fingerprint: Vec<(String, DateTime)>,
+}
+
+type Fetched = (PolicySet, Option<(Schema, serde_json::Value)>);
+
+impl Syncer {
+    async fn policy_statement(&mut self, id: &str, updated: &DateTime) -> Result<String, String> {
+        if let Some((at, statement)) = self.statements.get(id) {
+            if at == updated {
+                return Ok(statement.clone());
+            }
+        }
+        let policy = self
+            .client
+            .get_policy()
+            .policy_store_id(&self.store_id)
+            .policy_id(id)
+            .send()
+            .await
+            .map_err(|e| format!("Failed to get policy {}: {}", id, DisplayErrorContext(e)))?;
+        let Some(PolicyDefinitionDetail::Static(definition)) = policy.definition() else {
+            return Err(format!("Policy {} is not a static policy", id));
+        };
+        let statement = definition.statement().to_string();
+        self.statements.insert(id.to_string(), (*updated, statement.clone()));
+        Ok(statement)
+    }
+
+    async fn template_statement(&mut self, id: &str, updated: &DateTime) -> Result<String, String> {
+        if let Some((at, statement)) = self.statements.get(id) {
+            if at == updated {
+                return Ok(statement.clone());
+            }
+        }
+        let template = self
+            .client
+            .get_policy_template()
+            .policy_store_id(&self.store_id)
+            .policy_template_id(id)
+            .send()
+            .await
+            .map_err(|e| format!("Failed to get policy template {}: {}", id, DisplayErrorContext(e)))?;
+        let statement = template.statement().to_string();
+        self.statements.insert(id.to_string(), (*updated, statement.clone()));
+        Ok(statement)
+    }
+
+    /// The store's policies and schema, or `None` if nothing changed since the last sync.
+    async fn fetch(&mut self) -> Result<Option<Fetched>, String> {
+        let schema = match self.client.get_schema().policy_store_id(&self.store_id).send().await {
+            Ok(schema) => Some((schema.schema().to_string(), *schema.last_updated_date())),
+            Err(e) if e.as_service_error().is_some_and(|e| e.is_resource_not_found_exception()) => None,
+            Err(e) => return Err(format!("Failed to get schema: {}", DisplayErrorContext(e))),
+        };
+
+        let mut template_items = Vec::new();
+        let mut pages = self
+            .client
+            .list_policy_templates()
+            .policy_store_id(&self.store_id)
+            .into_paginator()
+            .items()
+            .send();
+        while let Some(item) = pages.next().await {
+            template_items.push(item.map_err(|e| format!("Failed to list policy templates: {}", DisplayErrorContext(e)))?);
+        }
+        let mut policy_items = Vec::new();
+        let mut pages = self
+            .client
+            .list_policies()
+            .policy_store_id(&self.store_id)
+            .into_paginator()
+            .items()
+            .send();
+        while let Some(item) = pages.next().await {
+            policy_items.push(item.map_err(|e| format!("Failed to list policies: {}", DisplayErrorContext(e)))?);
+        }
+
+        let mut fingerprint: Vec<(String, DateTime)> = template_items
+            .iter()
+            .map(|t| (t.policy_template_id().to_string(), *t.last_updated_date()))
+            .chain(policy_items.iter().map(|p| (p.policy_id().to_string(), *p.last_updated_date())))
+            .chain(schema.iter().map(|(_, updated)| ("(schema)".to_string(), *updated)))
+            .collect();
+        fingerprint.sort_by(|a, b| a.0.cmp(&b.0));
+        if fingerprint == self.fingerprint {
+            return Ok(None);
+        }
+
+        let mut templates = Vec::with_capacity(template_items.len());
+        for item in &template_items {
+            let statement = self.template_statement(item.policy_template_id(), item.last_updated_date()).await?;
+            templates.push((item.policy_template_id().to_string(), statement));
+        }
+        let mut policies = Vec::with_capacity(policy_items.len());
+        for item in &policy_items {
+            let id = item.policy_id().to_string();
+            policies.push(match item.definition() {
+                Some(PolicyDefinitionItem::TemplateLinked(linked)) => {
+                    let mut values = HashMap::new();
+                    if let Some(principal) = entity_uid(linked.principal())? {
+                        values.insert(SlotId::principal(), principal);
+                    }
+                    if let Some(resource) = entity_uid(linked.resource())? {
+                        values.insert(SlotId::resource(), resource);
+                    }
+                    StoredPolicy::Linked {
+                        id,
+                        template_id: linked.policy_template_id().to_string(),
+                        values,
+                    }
+                }
+                _ => StoredPolicy::Static {
+                    statement: self.policy_statement(&id, item.last_updated_date()).await?,
+                    id,
+                },
+            });
+        }
+        let policy_set = policy_set(&templates, &policies)?;
+
+        let schema = match schema {
+            Some((schema_src, _)) => {
+                let schema_json: serde_json::Value = serde_json::from_str(&schema_src)
+                    .map_err(|e| format!("Failed to parse schema: {}", e))?;
+                let schema = Schema::from_json_value(schema_json.clone())
+                    .map_err(|e| format!("Failed to parse schema: {}", e))?;
+                Some((schema, schema_json))
+            }
+            None => None,
+        };
+
+        // Forget statements of deleted policies
+        self.statements.retain(|id, _| fingerprint.iter().any(|(known, _)| known == id));
+        self.fingerprint = fingerprint;
+        Ok(Some((policy_set, schema)))
+    }
+}
+
+/// One sync; returns the number of policies activated, or `None` if the store was unchanged.
+async fn sync(service: &CedarService, syncer: &mut Syncer) -> Result<Option<usize>, String> {
+    let Some((policy_set, schema)) = syncer.fetch().await? else {
+        return Ok(None);
+    };
+    let source = format!("avp:{}", syncer.store_id);
+    let sources = policy_set
+        .policies()
+        .map(|p| p.id().clone())
+        .chain(policy_set.templates().map(|t| t.id().clone()))
+        .map(|id| (id, source.clone()))
+        .collect();
+    let count = policy_set.policies().count();
+    let loaded = policies::LoadedPolicies {
+        policy_set,
+        sources,
+        layered: false,
+    };
+    if let Err(e) = service.replace_policies("avp.sync", loaded, schema, None, None) {
+        // Fetch everything again next time rather than treating the rejected state as synced
+        syncer.fingerprint.clear();
+        return Err(format!("{}: {}", e.error, e.validation_errors.join("; ")));
+    }
+    Ok(Some(count))
+}
+
+/// Syncs from the policy store in `CEDAR_AVP_POLICY_STORE_ID` (one-way), failing startup if
+/// the first sync does not succeed, then polls every `CEDAR_AVP_SYNC_INTERVAL_SECS`. A store
+/// that fails to sync or validate leaves the last good policies active.
+pub async fn start(service: Arc<CedarService>, config: &Config) -> Result<(), Box<dyn std::error::Error>> {
+    let sdk_config = aws_config::load_from_env().await;
+    let mut syncer = Syncer {
+        client: Client::new(&sdk_config),
+        store_id: config.avp_policy_store_id.clone().unwrap_or_default(),
+        statements: HashMap::new(),
+        fingerprint: Vec::new(),
+    };
+
+    let count = sync(&service, &mut syncer)
+        .await
+        .map_err(|e| format!("Failed to sync from Verified Permissions policy store {}: {}", syncer.store_id, e))?;
+    info!(
+        "Synced {} policies from Verified Permissions policy store {}",
+        count.unwrap_or_default(),
+        syncer.store_id
+    );
+    service.metrics.incr("policy_syncs", &[("source", "avp"), ("result", "success")]);
+    service.source_refreshed("avp");
+
+    let interval = config.avp_sync_interval;
+    tokio::spawn(async move {
+        let mut ticks = tokio::time::interval(interval);
+        ticks.tick().await;
+        loop {
+            ticks.tick().await;
+            match sync(&service, &mut syncer).await {
+                Ok(Some(count)) => {
+                    info!("Synced {} policies from Verified Permissions", count);
+                    service.metrics.incr("policy_syncs", &[("source", "avp"), ("result", "success")]);
+                    service.source_refreshed("avp");
+                }
+                Ok(None) => service.source_refreshed("avp"),
+                Err(e) => {
+                    error!("Failed to sync from Verified Permissions: {}", e);
+                    service.metrics.incr("policy_syncs", &[("source", "avp"), ("result", "error")]);
+                    service.source_failed("avp", &e);
+                }
+            }
+        }
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builds_static_and_linked_policies_with_store_ids() {
+        let templates = [(
+            "tmpl-1".to_string(),
+            "permit (principal == ?principal, action, resource in ?resource);".to_string(),
+        )];
+        let policies = [
+            StoredPolicy::Static {
+                id: "pol-static".to_string(),
+                statement: r#"forbid (principal, action == Action::"delete", resource);"#.to_string(),
+            },
+            StoredPolicy::Linked {
+                id: "pol-linked".to_string(),
+                template_id: "tmpl-1".to_string(),
+                values: HashMap::from([
+                    (SlotId::principal(), r#"User::"alice""#.parse().unwrap()),
+                    (SlotId::resource(), r#"Folder::"reports""#.parse().unwrap()),
+                ]),
+            },
+        ];
+        let set = policy_set(&templates, &policies).unwrap();
+        let mut ids: Vec<String> = set.policies().map(|p| p.id().to_string()).collect();
+        ids.sort();
+        assert_eq!(ids, ["pol-linked", "pol-static"]);
+        assert!(set.template(&PolicyId::new("tmpl-1")).is_some());
+    }
+
+    #[test]
+    fn rejects_links_to_unknown_templates() {
+        let policies = [StoredPolicy::Linked {
+            id: "pol-linked".to_string(),
+            template_id: "missing".to_string(),
+            values: HashMap::new(),
+        }];
+        let err = policy_set(&[], &policies).unwrap_err();
+        assert!(err.contains("pol-linked"), "{}", err);
+    }
+
+    #[test]
+    fn rejects_malformed_windows() {
+        let policies = [StoredPolicy::Static {
+            id: "pol-break-glass".to_string(),
+            statement: r#"@expires_at("tomorrow") permit (principal, action, resource);"#.to_string(),
+        }];
+        let err = policy_set(&[], &policies).unwrap_err();
+        assert!(err.contains("pol-break-glass"), "{}", err);
+    }
+}
@@ -0,0 +1,111 @@
+use hyper::Body;
+use serde_json::{Map, Value};
+
+/// The W3C Baggage header (https://www.w3.org/TR/baggage/).
+pub const HEADER: &str = "baggage";
+
+/// Limits the specification sets on a request's baggage; entries beyond them are dropped.
+const MAX_ENTRIES: usize = 180;
+const MAX_BYTES: usize = 8192;
+
+/// Copies the configured entries of incoming baggage into the request context.
+pub struct Baggage {
+    keys: Vec<String>,
+    attribute: String,
+}
+
+impl Baggage {
+    pub fn new(keys: &[String], attribute: &str) -> Self {
+        Self {
+            keys: keys.to_vec(),
+            attribute: attribute.to_string(),
+        }
+    }
+
+    /// Sets the reserved attribute of a request context to a record of the configured baggage
+    /// entries the request carries, replacing any value the caller sent so that policies only
+    /// see what the mesh propagated.
+    pub fn stamp(&self, context: Option<Value>, header: Option<&str>) -> Result<Value, String> {
+        let mut context = match context {
+            Some(Value::Object(fields)) => fields,
+            Some(Value::Null) | None => Default::default(),
+            Some(_) => return Err("Context must be an object".to_string()),
+        };
+        let entries = header.map(|header| parse(header, &self.keys)).unwrap_or_default();
+        context.insert(self.attribute.clone(), Value::Object(entries));
+        Ok(Value::Object(context))
+    }
+}
+
+/// A request's baggage, its `baggage` headers joined as one list.
+pub fn header(req: &hyper::Request<Body>) -> Option<String> {
+    let values: Vec<&str> = req
+        .headers()
+        .get_all(HEADER)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .collect();
+    (!values.is_empty()).then(|| values.join(","))
+}
+
+/// The entries of a baggage header named in `keys`, as strings. Properties after `;` are
+/// ignored, values are percent-decoded and the first entry for a key wins.
+pub fn parse(header: &str, keys: &[String]) -> Map<String, Value> {
+    let mut entries = Map::new();
+    let mut bytes = 0;
+    for member in header.split(',').take(MAX_ENTRIES) {
+        bytes += member.len() + 1;
+        if bytes > MAX_BYTES + 1 {
+            break;
+        }
+        let entry = member.split(';').next().unwrap_or_default();
+        let Some((key, value)) = entry.split_once('=') else {
+            continue;
+        };
+        let key = key.trim();
+        if keys.iter().any(|k| k == key) && !entries.contains_key(key) {
+            entries.insert(key.to_string(), Value::String(crate::percent_decode(value.trim())));
+        }
+    }
+    entries
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn keys() -> Vec<String> {
+        vec!["tenant_id".to_string(), "session_risk".to_string()]
+    }
+
+    #[test]
+    fn parses_only_configured_entries() {
+        let header = "tenant_id = acme%20corp ;ttl=30, userId=alice,session_risk=high,tenant_id=other, broken";
+        assert_eq!(
+            Value::Object(parse(header, &keys())),
+            json!({"tenant_id": "acme corp", "session_risk": "high"})
+        );
+        assert!(parse("", &keys()).is_empty());
+    }
+
+    #[test]
+    fn ignores_baggage_beyond_the_limits() {
+        let padding = format!("pad={}", "x".repeat(MAX_BYTES));
+        assert!(parse(&format!("{},tenant_id=acme", padding), &keys()).is_empty());
+        let many = vec!["other=1"; MAX_ENTRIES].join(",");
+        assert!(parse(&format!("{},tenant_id=acme", many), &keys()).is_empty());
+    }
+
+    #[test]
+    fn stamps_the_reserved_attribute_over_any_sent_value() {
+        let baggage = Baggage::new(&keys(), "baggage");
+        let context = json!({"baggage": {"tenant_id": "forged", "admin": true}, "mfa": true});
+        assert_eq!(
+            baggage.stamp(Some(context), Some("tenant_id=acme")).unwrap(),
+            json!({"baggage": {"tenant_id": "acme"}, "mfa": true})
+        );
+        assert_eq!(baggage.stamp(None, None).unwrap(), json!({"baggage": {}}));
+        assert!(baggage.stamp(Some(json!([1])), None).is_err());
+    }
+}
@@ -0,0 +1,208 @@
+use crate::config::Config;
+use crate::{handle_request, AuthzRequest, CedarService};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Client, Method, Server};
+use std::convert::Infallible;
+use std::fs;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+struct BenchOptions {
+    config: Config,
+    requests_path: String,
+    iterations: usize,
+    warmup: usize,
+    skip_http: bool,
+}
+
+impl BenchOptions {
+    fn parse(args: &[String]) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut opts = BenchOptions {
+            config: Config::from_env()?,
+            requests_path: String::new(),
+            iterations: 1000,
+            warmup: 100,
+            skip_http: false,
+        };
+
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let mut value = |name: &str| {
+                args.next()
+                    .cloned()
+                    .ok_or_else(|| format!("Missing value for {}", name))
+            };
+            match arg.as_str() {
+                "--policies" => opts.config.policy_path = value(arg)?,
+                "--schema" => opts.config.schema_path = value(arg)?,
+                "--entities" => opts.config.entities_path = Some(value(arg)?),
+                "--requests" => opts.requests_path = value(arg)?,
+                "--iterations" => {
+                    opts.iterations = value(arg)?
+                        .parse()
+                        .map_err(|e| format!("Invalid --iterations: {}", e))?
+                }
+                "--warmup" => {
+                    opts.warmup = value(arg)?
+                        .parse()
+                        .map_err(|e| format!("Invalid --warmup: {}", e))?
+                }
+                "--no-http" => opts.skip_http = true,
+                other => return Err(format!("Unknown bench argument: {}", other).into()),
+            }
+        }
+
+        if opts.requests_path.is_empty() {
+            return Err("Usage: cedar-agent bench --requests <corpus.jsonl> [--policies <path>] \
+                        [--schema <path>] [--entities <path>] [--iterations N] [--warmup N] [--no-http]"
+                .into());
+        }
+        // Decisions are not logged, so a running agent's log is left to it
+        opts.config.decision_log_path = None;
+        if opts.iterations == 0 {
+            return Err("--iterations must be greater than zero".into());
+        }
+
+        Ok(opts)
+    }
+}
+
+/// Loads the request corpus: one `/authorize` request body per line, blank lines ignored.
+/// The raw line is kept so the HTTP run sends exactly what is in the file.
+pub fn load_corpus(path: &str) -> Result<Vec<(String, AuthzRequest)>, Box<dyn std::error::Error>> {
+    let src = fs::read_to_string(path).map_err(|e| format!("Failed to read corpus file: {}", e))?;
+
+    let mut corpus = Vec::new();
+    for (line_no, line) in src.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let req = serde_json::from_str::<AuthzRequest>(line)
+            .map_err(|e| format!("Invalid request on line {}: {}", line_no + 1, e))?;
+        corpus.push((line.to_string(), req));
+    }
+
+    if corpus.is_empty() {
+        return Err(format!("Corpus file {} contains no requests", path).into());
+    }
+    Ok(corpus)
+}
+
+pub fn percentile(sorted: &[Duration], p: f64) -> Duration {
+    let idx = ((sorted.len() as f64 - 1.0) * p).round() as usize;
+    sorted[idx]
+}
+
+fn report(label: &str, mut samples: Vec<Duration>, errors: usize, elapsed: Duration) {
+    samples.sort();
+    let throughput = samples.len() as f64 / elapsed.as_secs_f64();
+    println!("{}", label);
+    println!("  requests:   {} ({} errors)", samples.len(), errors);
+    println!("  throughput: {:.0} req/s", throughput);
+    println!(
+        "  latency:    p50 {:?}  p90 {:?}  p99 {:?}  max {:?}",
+        percentile(&samples, 0.50),
+        percentile(&samples, 0.90),
+        percentile(&samples, 0.99),
+        samples[samples.len() - 1]
+    );
+}
+
+fn bench_in_process(service: &CedarService, corpus: &[(String, AuthzRequest)], opts: &BenchOptions) {
+    for (_, req) in corpus.iter().cycle().take(opts.warmup) {
+        let _ = service.authorize(req.clone());
+    }
+
+    let mut samples = Vec::with_capacity(opts.iterations);
+    let mut errors = 0;
+    let started = Instant::now();
+    for (_, req) in corpus.iter().cycle().take(opts.iterations) {
+        let req = req.clone();
+        let t = Instant::now();
+        if service.authorize(req).is_err() {
+            errors += 1;
+        }
+        samples.push(t.elapsed());
+    }
+    report("In-process evaluation", samples, errors, started.elapsed());
+}
+
+async fn bench_http(
+    service: Arc<CedarService>,
+    corpus: &[(String, AuthzRequest)],
+    opts: &BenchOptions,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let make_svc = make_service_fn(move |_| {
+        let service = Arc::clone(&service);
+        async move {
+            Ok::<_, Infallible>(service_fn(move |req| {
+                handle_request(req, Arc::clone(&service))
+            }))
+        }
+    });
+
+    let bind: SocketAddr = ([127, 0, 0, 1], 0).into();
+    let server = Server::try_bind(&bind)?.serve(make_svc);
+    let uri = format!("http://{}/authorize", server.local_addr());
+    tokio::spawn(server);
+
+    let client = Client::new();
+    let send = |body: &str| {
+        let req = hyper::Request::builder()
+            .method(Method::POST)
+            .uri(&uri)
+            .header("content-type", "application/json")
+            .body(Body::from(body.to_string()))
+            .expect("valid benchmark request");
+        client.request(req)
+    };
+
+    for (body, _) in corpus.iter().cycle().take(opts.warmup) {
+        let _ = send(body).await;
+    }
+
+    let mut samples = Vec::with_capacity(opts.iterations);
+    let mut errors = 0;
+    let started = Instant::now();
+    for (body, _) in corpus.iter().cycle().take(opts.iterations) {
+        let t = Instant::now();
+        match send(body).await {
+            Ok(resp) => {
+                let ok = resp.status().is_success();
+                hyper::body::to_bytes(resp.into_body()).await?;
+                if !ok {
+                    errors += 1;
+                }
+            }
+            Err(_) => errors += 1,
+        }
+        samples.push(t.elapsed());
+    }
+    report("HTTP round trip", samples, errors, started.elapsed());
+
+    Ok(())
+}
+
+pub async fn run(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let opts = BenchOptions::parse(args)?;
+    let service = Arc::new(
+        CedarService::new(&opts.config)?.without_decision_logging(),
+    );
+    let corpus = load_corpus(&opts.requests_path)?;
+
+    println!(
+        "Benchmarking {} requests ({} distinct, {} warmup)",
+        opts.iterations,
+        corpus.len(),
+        opts.warmup
+    );
+
+    bench_in_process(&service, &corpus, &opts);
+    if !opts.skip_http {
+        bench_http(service, &corpus, &opts).await?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,153 @@
+use crate::CedarService;
+use cedar_policy::{eval_expression, Context, Entities, EntityUid, EvalResult, Expression, Request};
+use serde::Serialize;
+
+pub const PATH: &str = "/v1/capabilities";
+
+/// Endpoints every agent serves to clients, as `(method, path)`. The admin API (`/admin/*`,
+/// `/debug/*`, `/scim/*`) is left out.
+const ENDPOINTS: &[(&str, &str)] = &[
+    ("GET", "/health"),
+    ("GET", "/metrics"),
+    ("GET", PATH),
+    ("POST", "/authorize"),
+    ("POST", "/authorize/batch"),
+    ("POST", "/authorize/explain"),
+    ("POST", "/authorize/why-not"),
+    ("POST", "/authorize/suggest"),
+    ("POST", "/v1/evaluate"),
+    ("GET", "/v1/policies"),
+    ("GET", "/v1/policies/metadata"),
+    ("GET", "/v1/policies/lint"),
+    ("POST", "/v1/policies/lint"),
+    ("GET", "/v1/schema"),
+    ("PUT", "/v1/schema"),
+    ("GET", "/v1/schema/introspect"),
+    ("GET", "/v1/schema/action-groups"),
+    ("GET", "/v1/schema/lint"),
+    ("POST", "/v1/schema/impact"),
+    ("POST", "/v1/schema/context-usage"),
+    ("POST", "/v1/schema/infer"),
+    ("GET", "/v1/data/version"),
+    ("PUT", "/v1/data/entities"),
+    ("PATCH", "/v1/data/entities"),
+    ("POST", "/v1/data/entities/validate"),
+    ("GET", "/v1/data/entities/{uid}/ancestors"),
+    ("GET", "/v1/data/entities/{uid}/descendants"),
+    ("GET", "/v1/usage"),
+];
+
+/// Cedar extensions, each with an expression that only evaluates to `true` when the extension
+/// is built in.
+const EXTENSIONS: &[(&str, &str)] = &[
+    ("datetime", r#"datetime("2024-01-01").offset(duration("1h")) > datetime("2024-01-01")"#),
+    ("decimal", r#"decimal("1.5").lessThan(decimal("2.0"))"#),
+    ("ipaddr", r#"ip("10.0.0.1").isInRange(ip("10.0.0.0/8"))"#),
+];
+
+#[derive(Debug, Serialize)]
+pub struct Cedar {
+    pub language_version: String,
+    pub sdk_version: String,
+}
+
+#[derive(Debug, Serialize, PartialEq)]
+pub struct Endpoint {
+    pub method: &'static str,
+    pub path: &'static str,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Features {
+    /// Requests with unknown values are evaluated to residual policies; the agent always needs
+    /// every value.
+    pub partial_evaluation: bool,
+    /// Policy templates and template-linked policies.
+    pub templates: bool,
+    /// `POST /authorize/batch`.
+    pub batch: bool,
+    /// Requests and policies are validated against a schema.
+    pub schema: bool,
+    /// The agent holds entities requests do not have to carry.
+    pub entity_store: bool,
+    /// Principals and resources missing from a request are fetched from upstream services.
+    pub entity_fetching: bool,
+    pub decision_log: bool,
+    pub graphql: bool,
+}
+
+/// Response of `GET /v1/capabilities`: what the deployed agent supports, so clients can adapt.
+#[derive(Debug, Serialize)]
+pub struct Capabilities {
+    pub agent_version: &'static str,
+    pub cedar: Cedar,
+    pub extensions: Vec<&'static str>,
+    pub endpoints: Vec<Endpoint>,
+    pub features: Features,
+}
+
+/// The extensions built into the Cedar evaluator, found by evaluating an expression with each.
+pub fn extensions() -> Vec<&'static str> {
+    let uid: EntityUid = r#"Probe::"probe""#.parse().expect("valid probe UID");
+    let Ok(request) = Request::new(uid.clone(), uid.clone(), uid, Context::empty(), None) else {
+        return Vec::new();
+    };
+    let entities = Entities::empty();
+    EXTENSIONS
+        .iter()
+        .filter(|(_, probe)| {
+            probe.parse::<Expression>().is_ok_and(|expr| {
+                matches!(eval_expression(&request, &entities, &expr), Ok(EvalResult::Bool(true)))
+            })
+        })
+        .map(|(name, _)| *name)
+        .collect()
+}
+
+/// The endpoints `service` serves to clients.
+fn endpoints(service: &CedarService) -> Vec<Endpoint> {
+    let optional = [
+        (service.decision_index.is_some(), ("GET", crate::decision_index::PATH)),
+        (cfg!(feature = "graphql"), ("POST", "/graphql")),
+        (cfg!(feature = "playground"), ("GET", "/playground")),
+    ];
+    ENDPOINTS
+        .iter()
+        .copied()
+        .chain(optional.into_iter().filter(|(served, _)| *served).map(|(_, endpoint)| endpoint))
+        .map(|(method, path)| Endpoint { method, path })
+        .collect()
+}
+
+pub fn capabilities(service: &CedarService) -> Capabilities {
+    let state = service.state();
+    Capabilities {
+        agent_version: env!("CARGO_PKG_VERSION"),
+        cedar: Cedar {
+            language_version: cedar_policy::get_lang_version().to_string(),
+            sdk_version: cedar_policy::get_sdk_version().to_string(),
+        },
+        extensions: extensions(),
+        endpoints: endpoints(service),
+        features: Features {
+            partial_evaluation: false,
+            templates: true,
+            batch: true,
+            schema: state.schema.is_some(),
+            entity_store: state.entities.len() > 0,
+            entity_fetching: service.fetchers.is_some(),
+            decision_log: service.decision_log.is_some(),
+            graphql: cfg!(feature = "graphql"),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_the_built_in_extensions() {
+        assert_eq!(extensions(), vec!["datetime", "decimal", "ipaddr"]);
+    }
+}
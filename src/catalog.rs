@@ -0,0 +1,128 @@
+use crate::complexity::Complexity;
+use crate::policies::PolicySummary;
+use crate::PolicyState;
+use cedar_policy::{Policy, PolicyId, PolicySet};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::RwLock;
+
+struct Entry {
+    /// The policy as it was when it was introduced; a change makes it a new policy.
+    policy: Policy,
+    version: u64,
+    time: u64,
+    /// Unix time of the last decision it determined; 0 if none has.
+    last_hit: AtomicU64,
+}
+
+/// A policy as reported by `GET /v1/policies/metadata`.
+#[derive(Debug, Serialize)]
+pub struct PolicyMetadata {
+    #[serde(flatten)]
+    pub summary: PolicySummary,
+    /// Data version at which the policy, as it is now, was activated.
+    pub introduced_version: u64,
+    /// Unix time at which the policy, as it is now, was activated.
+    pub introduced_at: u64,
+    /// Unix time of the last decision the policy determined, if any since it was introduced.
+    pub last_hit: Option<u64>,
+    pub complexity: Complexity,
+}
+
+/// When each active policy was introduced and last determined a decision, for access reviews.
+/// A policy whose text changes counts as new. Kept in memory, so a restart starts over.
+#[derive(Default)]
+pub struct Catalog {
+    entries: RwLock<HashMap<String, Entry>>,
+}
+
+impl Catalog {
+    /// Brings the catalog up to date with a newly activated policy set.
+    pub fn update(&self, policy_set: &PolicySet, version: u64, now: u64) {
+        let mut entries = self.entries.write().unwrap();
+        entries.retain(|id, entry| {
+            policy_set
+                .policy(&PolicyId::new(id))
+                .is_some_and(|policy| *policy == entry.policy)
+        });
+        for policy in policy_set.policies() {
+            entries.entry(policy.id().to_string()).or_insert_with(|| Entry {
+                policy: policy.clone(),
+                version,
+                time: now,
+                last_hit: AtomicU64::new(0),
+            });
+        }
+    }
+
+    /// Records that the policies in `reason` determined a decision.
+    pub fn hit(&self, reason: &[String], now: u64) {
+        let entries = self.entries.read().unwrap();
+        for id in reason {
+            if let Some(entry) = entries.get(id) {
+                entry.last_hit.fetch_max(now, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Every active policy with its catalog entry, sorted by ID.
+    pub fn metadata(&self, state: &PolicyState) -> Vec<PolicyMetadata> {
+        let entries = self.entries.read().unwrap();
+        let mut metadata: Vec<PolicyMetadata> = state
+            .policy_set
+            .policies()
+            .map(|policy| {
+                let entry = entries.get(&policy.id().to_string());
+                PolicyMetadata {
+                    summary: state.summary(policy),
+                    introduced_version: entry.map_or(0, |e| e.version),
+                    introduced_at: entry.map_or(0, |e| e.time),
+                    last_hit: entry.map(|e| e.last_hit.load(Ordering::Relaxed)).filter(|t| *t > 0),
+                    complexity: Complexity::of(policy),
+                }
+            })
+            .collect();
+        metadata.sort_by(|a, b| a.summary.id.cmp(&b.summary.id));
+        metadata
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(catalog: &Catalog, id: &str) -> (u64, u64, u64) {
+        let entries = catalog.entries.read().unwrap();
+        let entry = &entries[id];
+        (entry.version, entry.time, entry.last_hit.load(Ordering::Relaxed))
+    }
+
+    #[test]
+    fn tracks_introduction_and_hits_per_policy_text() {
+        let catalog = Catalog::default();
+        let v1 = crate::policies::parse(
+            r#"@id("a") permit (principal, action, resource); @id("b") forbid (principal, action, resource);"#,
+        )
+        .unwrap();
+        catalog.update(&v1, 0, 100);
+        catalog.hit(&["a".to_string(), "unknown".to_string()], 150);
+        catalog.hit(&["a".to_string()], 140);
+        assert_eq!(entry(&catalog, "a"), (0, 100, 150));
+        assert_eq!(entry(&catalog, "b"), (0, 100, 0));
+
+        // Unchanged policies keep their entry; a changed one starts over, a removed one is dropped
+        let v2 = crate::policies::parse(
+            r#"@id("a") permit (principal, action, resource); @id("c") permit (principal, action, resource) when { false };"#,
+        )
+        .unwrap();
+        catalog.update(&v2, 3, 200);
+        assert_eq!(entry(&catalog, "a"), (0, 100, 150));
+        assert_eq!(entry(&catalog, "c"), (3, 200, 0));
+        assert!(!catalog.entries.read().unwrap().contains_key("b"));
+
+        let v3 = crate::policies::parse(r#"@id("a") permit (principal == User::"x", action, resource);"#).unwrap();
+        catalog.update(&v3, 4, 300);
+        assert_eq!(entry(&catalog, "a"), (4, 300, 0));
+    }
+}
@@ -0,0 +1,193 @@
+use crate::metrics::Metrics;
+use crate::{error_response, json_response, read_json, CedarService};
+use hyper::{Body, Method, Request, Response, StatusCode};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::hash::{BuildHasher, RandomState};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::RwLock;
+use std::time::Duration;
+
+pub const PATH: &str = "/admin/chaos";
+/// Longest latency that may be injected, so a typo cannot hang every caller.
+const MAX_LATENCY_MS: u64 = 60_000;
+
+/// A decision forced onto evaluated requests.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Forced {
+    Allow,
+    Deny,
+}
+
+impl Forced {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Forced::Allow => "Allow",
+            Forced::Deny => "Deny",
+        }
+    }
+}
+
+/// Faults injected into evaluation requests, each on a percentage of them. Percentages are
+/// from 0 to 100; a fault with 0 is off.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Faults {
+    /// Delay added before the request is evaluated.
+    pub latency_ms: u64,
+    pub latency_percent: f64,
+    /// Status of the error answered instead of evaluating; 503 unless set.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error_status: Option<u16>,
+    pub error_percent: f64,
+    /// Decision returned whatever the policies decide.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub decision: Option<Forced>,
+    pub decision_percent: f64,
+}
+
+impl Faults {
+    /// Parses and checks faults given as JSON, as in `CEDAR_CHAOS_FAULTS`.
+    pub fn parse(json: &str) -> Result<Self, String> {
+        let faults: Faults = serde_json::from_str(json).map_err(|e| format!("Invalid faults: {}", e))?;
+        faults.check()?;
+        Ok(faults)
+    }
+
+    fn check(&self) -> Result<(), String> {
+        for (name, percent) in [
+            ("latency_percent", self.latency_percent),
+            ("error_percent", self.error_percent),
+            ("decision_percent", self.decision_percent),
+        ] {
+            if !(0.0..=100.0).contains(&percent) {
+                return Err(format!("Invalid faults: {} must be between 0 and 100", name));
+            }
+        }
+        if self.latency_ms > MAX_LATENCY_MS {
+            return Err(format!("Invalid faults: latency_ms must be at most {}", MAX_LATENCY_MS));
+        }
+        if self.error_status.is_some_and(|status| !(400..=599).contains(&status)) {
+            return Err("Invalid faults: error_status must be an HTTP error status (400-599)".to_string());
+        }
+        if self.decision_percent > 0.0 && self.decision.is_none() {
+            return Err("Invalid faults: decision_percent needs a decision (Allow or Deny)".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// Fault injection, on when `CEDAR_CHAOS` is set, for testing how applications behave when the
+/// agent is slow, failing or wrong. The faults can be changed at runtime at `/admin/chaos`.
+pub struct Chaos {
+    faults: RwLock<Faults>,
+    hasher: RandomState,
+    rolls: AtomicU64,
+}
+
+impl Chaos {
+    pub fn new(faults: Faults) -> Self {
+        Self {
+            faults: RwLock::new(faults),
+            hasher: RandomState::new(),
+            rolls: AtomicU64::new(0),
+        }
+    }
+
+    pub fn faults(&self) -> Faults {
+        self.faults.read().unwrap().clone()
+    }
+
+    pub fn set(&self, faults: Faults) {
+        *self.faults.write().unwrap() = faults;
+    }
+
+    /// Whether a fault on `percent` of requests hits this one. Each roll hashes a new count with
+    /// a key random to the process, which spreads hits evenly enough without a random number
+    /// generator.
+    fn roll(&self, percent: f64) -> bool {
+        if percent <= 0.0 {
+            return false;
+        }
+        let n = self.hasher.hash_one(self.rolls.fetch_add(1, Ordering::Relaxed));
+        ((n % 10_000) as f64) < percent * 100.0
+    }
+
+    /// The decision to force onto the request being evaluated, if this one is hit.
+    pub fn forced_decision(&self) -> Option<Forced> {
+        let faults = self.faults.read().unwrap();
+        let decision = faults.decision?;
+        self.roll(faults.decision_percent).then_some(decision)
+    }
+}
+
+/// Delays an evaluation request, or answers it with an error, when the faults hit it.
+pub async fn inject(req: Request<Body>, chaos: &Chaos, metrics: &Metrics) -> Result<Request<Body>, Response<Body>> {
+    let faults = chaos.faults();
+    if chaos.roll(faults.latency_percent) {
+        metrics.incr("chaos_faults", &[("fault", "latency")]);
+        tokio::time::sleep(Duration::from_millis(faults.latency_ms)).await;
+    }
+    if chaos.roll(faults.error_percent) {
+        metrics.incr("chaos_faults", &[("fault", "error")]);
+        let status = faults
+            .error_status
+            .and_then(|status| StatusCode::from_u16(status).ok())
+            .unwrap_or(StatusCode::SERVICE_UNAVAILABLE);
+        return Err(error_response(status, "Injected fault (CEDAR_CHAOS)"));
+    }
+    Ok(req)
+}
+
+/// Serves `/admin/chaos`: `GET` shows the faults injected, `PUT` replaces them and `DELETE`
+/// stops injecting any.
+pub async fn handle(req: Request<Body>, service: &CedarService) -> Response<Body> {
+    let Some(ref chaos) = service.chaos else {
+        return error_response(StatusCode::NOT_FOUND, "Fault injection is not enabled (CEDAR_CHAOS)");
+    };
+    match *req.method() {
+        Method::PUT => {
+            let faults = match read_json::<Faults>(req).await {
+                Ok(faults) => faults,
+                Err(resp) => return resp,
+            };
+            if let Err(e) = faults.check() {
+                return error_response(StatusCode::BAD_REQUEST, e);
+            }
+            warn!("Injecting faults: {}", serde_json::to_string(&faults).unwrap_or_default());
+            chaos.set(faults);
+        }
+        Method::DELETE => {
+            info!("Stopped injecting faults");
+            chaos.set(Faults::default());
+        }
+        _ => {}
+    }
+    json_response(StatusCode::OK, &chaos.faults())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checks_faults() {
+        let faults = Faults::parse(r#"{"latency_ms": 200, "latency_percent": 10, "decision": "Deny", "decision_percent": 1.5}"#).unwrap();
+        assert_eq!((faults.latency_ms, faults.decision, faults.error_percent), (200, Some(Forced::Deny), 0.0));
+        assert_eq!(Faults::parse("{}").unwrap(), Faults::default());
+        assert!(Faults::parse(r#"{"error_percent": 101}"#).is_err());
+        assert!(Faults::parse(r#"{"error_status": 200, "error_percent": 5}"#).is_err());
+        assert!(Faults::parse(r#"{"decision_percent": 5}"#).is_err());
+        assert!(Faults::parse(r#"{"latency_ms": 3600000}"#).is_err());
+        assert!(Faults::parse(r#"{"latency": 10}"#).is_err());
+    }
+
+    #[test]
+    fn hits_about_the_share_asked_for() {
+        let chaos = Chaos::new(Faults::default());
+        let hits = (0..10_000).filter(|_| chaos.roll(10.0)).count();
+        assert!((800..1200).contains(&hits), "{} hits", hits);
+        assert!((0..1000).all(|_| chaos.roll(100.0)));
+        assert!((0..1000).all(|_| !chaos.roll(0.0)));
+    }
+}
@@ -0,0 +1,68 @@
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde_json::{json, Value};
+
+/// Header a request can fix its evaluation time with, honoured only in test mode.
+pub const EVALUATION_TIME_HEADER: &str = "x-cedar-evaluation-time";
+
+/// Where the evaluation time comes from.
+#[derive(Debug, Clone, Copy)]
+pub enum Clock {
+    System,
+    /// Every request is evaluated at this time, so expiry windows give the same decision on
+    /// every run.
+    Fixed(DateTime<Utc>),
+}
+
+impl Clock {
+    pub fn now(&self) -> DateTime<Utc> {
+        match self {
+            Clock::System => Utc::now(),
+            Clock::Fixed(time) => *time,
+        }
+    }
+}
+
+/// Parses an RFC 3339 timestamp, e.g. `2024-06-01T12:00:00Z`.
+pub fn parse_time(s: &str) -> Result<DateTime<Utc>, String> {
+    DateTime::parse_from_rfc3339(s.trim())
+        .map(|time| time.with_timezone(&Utc))
+        .map_err(|e| format!("expected an RFC 3339 timestamp such as 2024-06-01T12:00:00Z ({})", e))
+}
+
+/// Sets `attribute` of a request context to `time` as a Cedar `datetime`, replacing any value
+/// the caller sent so the time cannot be forged.
+pub fn stamp(context: Option<Value>, attribute: &str, time: DateTime<Utc>) -> Result<Value, String> {
+    let mut context = match context {
+        Some(Value::Object(fields)) => fields,
+        Some(Value::Null) | None => Default::default(),
+        Some(_) => return Err("Context must be an object".to_string()),
+    };
+    let arg = time.to_rfc3339_opts(SecondsFormat::Millis, true);
+    context.insert(attribute.to_string(), json!({"__extn": {"fn": "datetime", "arg": arg}}));
+    Ok(Value::Object(context))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stamps_the_time_over_any_sent_value() {
+        let time = parse_time("2024-06-01T12:00:00+02:00").unwrap();
+        let context = stamp(Some(json!({"now": "2099-01-01T00:00:00Z", "mfa": true})), "now", time).unwrap();
+        assert_eq!(
+            context,
+            json!({"now": {"__extn": {"fn": "datetime", "arg": "2024-06-01T10:00:00.000Z"}}, "mfa": true})
+        );
+        assert_eq!(stamp(None, "now", time).unwrap()["now"]["__extn"]["arg"], "2024-06-01T10:00:00.000Z");
+        assert!(stamp(Some(json!([1])), "now", time).is_err());
+    }
+
+    #[test]
+    fn fixed_clocks_do_not_move() {
+        let time = parse_time("2024-06-01T12:00:00Z").unwrap();
+        assert_eq!(Clock::Fixed(time).now(), time);
+        assert!(parse_time("yesterday").is_err());
+        assert!(parse_time("2024-06-01").is_err());
+    }
+}
@@ -0,0 +1,156 @@
+use cedar_policy::{Policy, PolicySet};
+use serde::Serialize;
+use serde_json::Value;
+
+/// Operators whose cost grows with their operands: pattern matching, set comparisons and
+/// entity hierarchy membership.
+const EXPENSIVE_OPERATORS: &[&str] = &["like", "contains", "containsAll", "containsAny", "in"];
+
+/// How costly a policy's conditions may be to evaluate.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct Complexity {
+    /// Deepest nesting of expressions in any condition.
+    pub depth: usize,
+    /// Most elements of any set literal.
+    pub largest_set: usize,
+    /// Uses of `like`, `contains`, `containsAll`, `containsAny` and `in`.
+    pub expensive_operators: usize,
+}
+
+impl Complexity {
+    /// Measures the conditions of `policy`; its scope is not counted.
+    pub fn of(policy: &Policy) -> Self {
+        let mut complexity = Complexity::default();
+        // Only template-linked policies fail to convert, and their template is measured alike
+        let Ok(json) = policy.to_json() else {
+            return complexity;
+        };
+        for condition in json["conditions"].as_array().into_iter().flatten() {
+            let depth = complexity.walk(&condition["body"]);
+            complexity.depth = complexity.depth.max(depth);
+        }
+        complexity
+    }
+
+    /// Depth of the expressions in `json`, an expression of the JSON policy format or the
+    /// operands of one, counting set sizes and expensive operators on the way.
+    fn walk(&mut self, json: &Value) -> usize {
+        match json {
+            // An expression: an operator and its operands
+            Value::Object(map) if map.len() == 1 => {
+                let (operator, operands) = map.iter().next().expect("one entry");
+                match operator.as_str() {
+                    // Literal data, not expressions
+                    "Value" => {
+                        if let Value::Array(set) = operands {
+                            self.largest_set = self.largest_set.max(set.len());
+                        }
+                        1
+                    }
+                    "Set" => {
+                        self.largest_set = self.largest_set.max(operands.as_array().map_or(0, Vec::len));
+                        1 + self.walk(operands)
+                    }
+                    operator => {
+                        if EXPENSIVE_OPERATORS.contains(&operator) {
+                            self.expensive_operators += 1;
+                        }
+                        1 + self.walk(operands)
+                    }
+                }
+            }
+            // Named operands, such as `left` and `right`
+            Value::Object(map) => map.values().map(|operand| self.walk(operand)).max().unwrap_or(0),
+            Value::Array(operands) => operands.iter().map(|operand| self.walk(operand)).max().unwrap_or(0),
+            _ => 0,
+        }
+    }
+}
+
+/// Limits on the complexity of policies; 0 leaves a measure unlimited.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    pub max_depth: usize,
+    pub max_set_size: usize,
+    pub max_expensive_operators: usize,
+    /// Refuse policy sets with a policy over the limits, rather than only warn about them.
+    pub reject: bool,
+}
+
+impl Limits {
+    /// How `complexity` exceeds the limits, if it does.
+    fn violations(&self, complexity: &Complexity) -> Vec<String> {
+        let mut violations = Vec::new();
+        let mut check = |name: &str, value: usize, max: usize| {
+            if max > 0 && value > max {
+                violations.push(format!("{} {} exceeds {}", name, value, max));
+            }
+        };
+        check("condition depth", complexity.depth, self.max_depth);
+        check("set literal of", complexity.largest_set, self.max_set_size);
+        check("expensive operators", complexity.expensive_operators, self.max_expensive_operators);
+        violations
+    }
+
+    /// The policies of `policy_set` over the limits, each with how it exceeds them. An error
+    /// naming them when the limits reject such policies.
+    pub fn check(&self, policy_set: &PolicySet) -> Result<Vec<String>, String> {
+        let mut over = Vec::new();
+        for policy in policy_set.policies() {
+            let violations = self.violations(&Complexity::of(policy));
+            if !violations.is_empty() {
+                over.push(format!("policy {}: {}", policy.id(), violations.join(", ")));
+            }
+        }
+        if self.reject && !over.is_empty() {
+            return Err(format!("Policies too complex to evaluate: {}", over.join("; ")));
+        }
+        Ok(over)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn complexity(text: &str) -> Complexity {
+        let policy_set = crate::policies::parse(text).unwrap();
+        let policy = policy_set.policies().next().unwrap().clone();
+        Complexity::of(&policy)
+    }
+
+    #[test]
+    fn measures_conditions() {
+        assert_eq!(complexity("permit (principal, action, resource);"), Complexity::default());
+        let measured = complexity(
+            r#"permit (principal in Group::"a", action, resource)
+            when { resource.owner == principal && (principal.name like "a*" || context.tags.containsAny(["x", "y", "z"])) }
+            unless { principal in resource.blocked };"#,
+        );
+        assert_eq!(measured.largest_set, 3);
+        // `in` of the scope is not counted
+        assert_eq!(measured.expensive_operators, 3);
+        // && > || > containsAny > . > Var
+        assert_eq!(measured.depth, 5);
+    }
+
+    #[test]
+    fn warns_or_rejects_over_the_limits() {
+        let policy_set = crate::policies::parse(
+            r#"@id("small") permit (principal, action, resource) when { principal.level > 1 };
+            @id("wide") permit (principal, action, resource) when { ["a", "b", "c", "d"].contains(principal.name) };"#,
+        )
+        .unwrap();
+        let mut limits = Limits {
+            max_depth: 0,
+            max_set_size: 3,
+            max_expensive_operators: 0,
+            reject: false,
+        };
+        assert_eq!(limits.check(&policy_set), Ok(vec!["policy wide: set literal of 4 exceeds 3".to_string()]));
+        limits.reject = true;
+        assert!(limits.check(&policy_set).unwrap_err().contains("policy wide"));
+        limits.max_set_size = 4;
+        assert_eq!(limits.check(&policy_set), Ok(Vec::new()));
+    }
+}
@@ -0,0 +1,798 @@
+use crate::ip_filter::{self, IpFilter, IpRules};
+use chrono::{DateTime, Utc};
+use crate::secret_file::{self, SecretFile};
+use log::LevelFilter;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// What to answer when no policy is satisfied and none errored, i.e. the policy set is empty or
+/// nothing applies to the request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DefaultDecision {
+    /// Cedar's own behaviour: implicit deny.
+    Deny,
+    /// Allow the request; intended for migration or monitor-only rollouts.
+    Allow,
+    /// Deny, but log and report a warning so gaps in policy coverage are visible.
+    DenyWithWarning,
+}
+
+impl DefaultDecision {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DefaultDecision::Deny => "deny",
+            DefaultDecision::Allow => "allow",
+            DefaultDecision::DenyWithWarning => "deny-with-warning",
+        }
+    }
+}
+
+impl FromStr for DefaultDecision {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "deny" => Ok(DefaultDecision::Deny),
+            "allow" => Ok(DefaultDecision::Allow),
+            "deny-with-warning" => Ok(DefaultDecision::DenyWithWarning),
+            other => Err(format!(
+                "Invalid default decision '{}' (expected deny, allow or deny-with-warning)",
+                other
+            )),
+        }
+    }
+}
+
+/// Format of the HTTP access log.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessLogFormat {
+    /// Apache/nginx combined log format, with the latency and decision appended.
+    Combined,
+    /// One JSON object per line.
+    Json,
+}
+
+impl FromStr for AccessLogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "combined" => Ok(AccessLogFormat::Combined),
+            "json" => Ok(AccessLogFormat::Json),
+            other => Err(format!(
+                "Invalid access log format '{}' (expected off, combined or json)",
+                other
+            )),
+        }
+    }
+}
+
+/// Shape of `/authorize` responses, for callers written against another authorization service.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ResponseFormat {
+    /// The agent's own shape.
+    #[default]
+    Cedar,
+    /// The agent's shape plus a boolean `allowed`.
+    Allowed,
+    /// The agent's shape with camelCase keys.
+    Camel,
+    /// Amazon Verified Permissions' `IsAuthorized` shape: `"decision": "ALLOW"`.
+    Avp,
+}
+
+impl FromStr for ResponseFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "cedar" => Ok(ResponseFormat::Cedar),
+            "allowed" => Ok(ResponseFormat::Allowed),
+            "camel" => Ok(ResponseFormat::Camel),
+            "avp" => Ok(ResponseFormat::Avp),
+            other => Err(format!(
+                "Invalid response format '{}' (expected cedar, allowed, camel or avp)",
+                other
+            )),
+        }
+    }
+}
+
+/// How the transitive closure of an entity hierarchy is established.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EntityHierarchy {
+    /// Cedar computes it from the parents given, and rejects cycles.
+    #[default]
+    Compute,
+    /// The parents given already list every ancestor and are used as they are; cycles are
+    /// accepted.
+    Provided,
+}
+
+impl FromStr for EntityHierarchy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "compute" => Ok(EntityHierarchy::Compute),
+            "provided" => Ok(EntityHierarchy::Provided),
+            other => Err(format!(
+                "Invalid entity hierarchy '{}' (expected compute or provided)",
+                other
+            )),
+        }
+    }
+}
+
+/// What to do with differing entities that share a UID. Identical copies are always accepted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EntityDuplicates {
+    #[default]
+    Reject,
+    /// Keep the last entity with each UID and drop the others.
+    KeepLast,
+}
+
+impl FromStr for EntityDuplicates {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "reject" => Ok(EntityDuplicates::Reject),
+            "keep-last" => Ok(EntityDuplicates::KeepLast),
+            other => Err(format!(
+                "Invalid entity duplicate handling '{}' (expected reject or keep-last)",
+                other
+            )),
+        }
+    }
+}
+
+/// What to do with stored entities whose hierarchy has dangling parents or cycles.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EntityIntegrity {
+    /// Log the problems and ingest the entities anyway.
+    #[default]
+    Warn,
+    Reject,
+    Off,
+}
+
+impl FromStr for EntityIntegrity {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "warn" => Ok(EntityIntegrity::Warn),
+            "reject" => Ok(EntityIntegrity::Reject),
+            "off" => Ok(EntityIntegrity::Off),
+            other => Err(format!(
+                "Invalid entity integrity handling '{}' (expected warn, reject or off)",
+                other
+            )),
+        }
+    }
+}
+
+/// How the ACME CA validates control of the configured domains.
+#[cfg(feature = "acme")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AcmeChallenge {
+    /// Answered on the HTTPS listener itself (RFC 8737); needs no extra port.
+    TlsAlpn01,
+    /// Answered over plain HTTP on `CEDAR_ACME_HTTP_ADDR`, normally port 80.
+    Http01,
+}
+
+#[cfg(feature = "acme")]
+impl FromStr for AcmeChallenge {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "tls-alpn-01" => Ok(AcmeChallenge::TlsAlpn01),
+            "http-01" => Ok(AcmeChallenge::Http01),
+            other => Err(format!(
+                "Invalid ACME challenge '{}' (expected tls-alpn-01 or http-01)",
+                other
+            )),
+        }
+    }
+}
+
+/// Agent settings, read from the environment (see the Configuration section of the readme).
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub policy_path: String,
+    /// Extra policy files or directories layered on top of `policy_path`, in order.
+    pub policy_overlays: Vec<String>,
+    /// Limits on the complexity of policies, checked whenever policies are loaded.
+    pub complexity_limits: crate::complexity::Limits,
+    /// Severities of the policy lint rules.
+    pub policy_lint: crate::policy_lint::Rules,
+    pub schema_path: String,
+    /// Schema files, or directories of them, merged with the one at `schema_path`.
+    pub schema_fragments: Vec<String>,
+    /// Entities held by the agent; unset means requests carry all of their entities.
+    pub entities_path: Option<String>,
+    /// JSON file defining the upstream services entities are fetched from on demand.
+    pub entity_fetchers: Option<String>,
+    /// How long fetched entities are cached, unless their fetcher says otherwise.
+    pub entity_cache_ttl: Duration,
+    pub entity_cache_max_entries: usize,
+    /// How entities are ingested, for stored and request entities alike.
+    pub entity_hierarchy: EntityHierarchy,
+    pub entity_duplicates: EntityDuplicates,
+    /// Checks of the stored entities' hierarchy for dangling parents and cycles.
+    pub entity_integrity: EntityIntegrity,
+    pub bind_addr: String,
+    /// Listening sockets sharing `bind_addr` with `SO_REUSEPORT`, each with its own thread.
+    pub acceptors: usize,
+    /// Pin acceptor threads to CPU cores.
+    pub pin_acceptors: bool,
+    /// How long a process started on SIGUSR2 has to take over the listening sockets.
+    pub upgrade_timeout: Duration,
+    /// How long connections are waited for once the agent stops accepting.
+    pub drain_timeout: Duration,
+    /// How long `POST /admin/drain` keeps accepting connections after reporting not ready.
+    pub drain_grace: Duration,
+    pub default_decision: DefaultDecision,
+    /// Shape of `/authorize` responses when the request does not ask for one.
+    pub response_format: ResponseFormat,
+    /// Context attribute the agent sets to the evaluation time; unset adds none.
+    pub context_time_attribute: Option<String>,
+    /// Evaluation time used instead of the system clock.
+    pub fixed_time: Option<DateTime<Utc>>,
+    /// Honour `X-Cedar-Evaluation-Time` on requests; for policy test suites, never production.
+    pub test_mode: bool,
+    /// Policy changes go through proposals approved by a second administrator; promoting the
+    /// staging set, enabling or disabling policies and imports are refused.
+    pub require_approval: bool,
+    /// Faults injected into evaluations, with `CEDAR_CHAOS`; never in production.
+    pub chaos: Option<crate::chaos::Faults>,
+    /// StatsD/DogStatsD collector (`host:port`) to push metrics to, if any.
+    pub statsd_addr: Option<String>,
+    pub statsd_prefix: String,
+    /// Tags added to every StatsD metric, e.g. `env:prod`.
+    pub statsd_tags: Vec<String>,
+    /// Serve the `/debug/pprof/*` profiling endpoints and `/debug/decisions`.
+    pub debug_endpoints: bool,
+    /// Recent decisions kept in full for `/debug/decisions`.
+    pub debug_decisions: usize,
+    /// HTTP access log format; `None` disables it.
+    pub access_log: Option<AccessLogFormat>,
+    /// Access log file; empty for stdout.
+    pub access_log_path: String,
+    /// Hash-chained decision log file; unset disables it.
+    pub decision_log_path: Option<String>,
+    /// JSON Lines file recording every change to policies, schema and entities.
+    pub audit_log_path: Option<String>,
+    /// Secret checkpoints of the decision log are signed with (HMAC-SHA256).
+    pub decision_log_signing_key: Option<String>,
+    /// Records between decision log checkpoints.
+    pub decision_log_checkpoint_interval: u64,
+    /// Recent decisions kept in memory for `GET /v1/decisions`; 0 disables it.
+    pub decision_index_size: usize,
+    pub log_level: LevelFilter,
+    /// Syslog target (`udp://`, `tcp://` or `unix://`) to copy logs to, if any.
+    pub syslog: Option<String>,
+    pub syslog_facility: u8,
+    pub ip_filter: IpFilter,
+    /// Shared secrets for HMAC request signing; empty disables verification.
+    pub hmac_secrets: Vec<String>,
+    /// Files the HMAC secrets, API keys and admin keys were read from instead, which are
+    /// watched for rotated values.
+    pub hmac_secrets_file: Option<Arc<SecretFile>>,
+    pub api_keys_file: Option<Arc<SecretFile>>,
+    pub admin_keys_file: Option<Arc<SecretFile>>,
+    /// How often those files are checked for rotation.
+    pub secrets_reload_interval: Duration,
+    /// Allowed clock skew for signed requests, in seconds.
+    pub hmac_max_skew: u64,
+    /// `name=key` API keys; when set, every data-plane request must send one.
+    pub api_keys: Vec<String>,
+    /// Keys of administrators as `name=key`, and their roles as `name=role+role`; keys (or
+    /// users) turn on role checks for the admin API.
+    pub admin_keys: Vec<String>,
+    /// Administrators signing in with HTTP Basic authentication, as `name:bcrypt-hash`.
+    pub admin_users: Vec<String>,
+    pub admin_roles: Vec<String>,
+    /// Per-key `name=hourly/daily` quotas overriding the defaults below.
+    pub api_key_quotas: Vec<String>,
+    /// Default requests per hour and per UTC day for each key; `None` is unlimited.
+    pub api_key_hourly_quota: Option<u64>,
+    pub api_key_daily_quota: Option<u64>,
+    /// Evaluations allowed in flight at once; `None` is unlimited.
+    pub max_concurrent_evaluations: Option<usize>,
+    /// Evaluations that may wait for a slot before further ones are shed.
+    pub max_queued_evaluations: usize,
+    /// Longest an evaluation waits for a slot before it is shed.
+    pub queue_timeout: Duration,
+    /// Longest a request with `min_version` waits for the data version to reach it.
+    pub min_version_wait: Duration,
+    /// Least time a request's deadline (`X-Request-Deadline`, `grpc-timeout`) must leave for it
+    /// to be evaluated rather than refused straight away.
+    pub deadline_min_budget: Duration,
+    /// Evaluations taking at least this long are logged with a breakdown; `None` logs none.
+    pub slow_decision: Option<Duration>,
+    /// Budget for stored entities in bytes (see `EntityStore::size`); `None` is unlimited.
+    pub entity_memory_limit: Option<usize>,
+    /// Largest request body accepted, in bytes; `None` is unlimited.
+    pub max_request_bytes: Option<usize>,
+    /// PEM certificate chain and private key; with both set the agent serves HTTPS.
+    pub tls_cert_path: Option<String>,
+    pub tls_key_path: Option<String>,
+    /// How often the certificate files are checked for rotation.
+    pub tls_reload_interval: Duration,
+    /// Serve mTLS with an SVID from the SPIFFE Workload API.
+    pub spiffe: bool,
+    /// Workload API socket; unset uses `SPIFFE_ENDPOINT_SOCKET`.
+    #[cfg(feature = "spiffe")]
+    pub spiffe_endpoint: Option<String>,
+    /// Trust domains clients may come from; empty accepts any in the trust bundle.
+    #[cfg(feature = "spiffe")]
+    pub spiffe_trust_domains: Vec<String>,
+    /// Entity type a client's SPIFFE ID maps to when a request leaves out its principal.
+    pub spiffe_principal_type: String,
+    /// JWKS file or URL that bearer tokens sent with authorization requests are verified
+    /// against; unset disables tokens.
+    pub token_jwks: Option<String>,
+    /// Required `iss` and `aud` of tokens.
+    pub token_issuer: Option<String>,
+    pub token_audience: Option<String>,
+    /// Entity type of token principals, and the claim holding their ID.
+    pub token_principal_type: String,
+    pub token_principal_claim: String,
+    /// Claim listing the groups that become the principal's parents.
+    pub token_groups_claim: Option<String>,
+    pub token_group_type: String,
+    /// Claims copied onto the principal as attributes.
+    pub token_claims: Vec<String>,
+    /// Context attribute the token's claims are set as a record under; unset adds none.
+    pub token_context_attribute: Option<String>,
+    /// Most items one `POST /authorize/batch` may carry.
+    pub batch_max_items: usize,
+    /// W3C baggage entries copied into the request context; empty copies none.
+    pub baggage_keys: Vec<String>,
+    /// Reserved context attribute the baggage entries are set under as a record.
+    pub baggage_context_attribute: String,
+    /// Where a request's tenant comes from for metric labels: `api-key` or `baggage:<entry>`;
+    /// unset labels no metric by tenant.
+    pub tenant_from: Option<String>,
+    /// Most tenants given their own metric label; later ones share `other`.
+    pub tenant_max_labels: usize,
+    /// Evaluation requests per second each tenant may make; `None` is unlimited.
+    pub tenant_rate_limit: Option<u32>,
+    /// Most entities one evaluation request of a tenant may send; `None` is unlimited.
+    pub tenant_max_entities: Option<usize>,
+    /// Per-tenant overrides of the limits as `tenant=rate/bytes/entities`.
+    pub tenant_limits: Vec<String>,
+    /// Bearer token for the SCIM provisioning endpoints; unset disables them.
+    pub scim_token: Option<String>,
+    pub scim_user_type: String,
+    pub scim_group_type: String,
+    /// Shared secret for state replication: enables `GET /admin/replication/stream` and is the
+    /// token a replica presents to its leader.
+    pub replication_token: Option<String>,
+    /// Leader (`http://host:port`) this agent follows as a read-only replica.
+    pub replica_of: Option<String>,
+    /// Serve gRPC server reflection, describing the gRPC services to tools like grpcurl.
+    pub grpc_reflection: bool,
+    /// Directory the schema and stored entities are persisted in; unset keeps them in memory.
+    pub data_dir: Option<String>,
+    /// Log records after which the store is compacted into a snapshot.
+    pub data_compact_after: usize,
+    /// Amazon Verified Permissions policy store to sync policies and schema from.
+    pub avp_policy_store_id: Option<String>,
+    #[cfg(feature = "avp")]
+    pub avp_sync_interval: Duration,
+    /// Import Kubernetes service accounts and RBAC roles as entities.
+    pub k8s_import: bool,
+    /// Only watch this namespace; unset watches the whole cluster.
+    #[cfg(feature = "kubernetes")]
+    pub k8s_namespace: Option<String>,
+    /// Cedar namespace of the imported entity types.
+    #[cfg(feature = "kubernetes")]
+    pub k8s_entity_namespace: String,
+    /// LDAP/Active Directory server (`ldap://` or `ldaps://`) to sync users and groups from.
+    pub ldap_url: Option<String>,
+    #[cfg(feature = "ldap")]
+    pub ldap_bind_dn: Option<String>,
+    #[cfg(feature = "ldap")]
+    pub ldap_bind_password: Option<String>,
+    #[cfg(feature = "ldap")]
+    pub ldap_base_dn: String,
+    #[cfg(feature = "ldap")]
+    pub ldap_user_filter: String,
+    #[cfg(feature = "ldap")]
+    pub ldap_group_filter: String,
+    /// Attribute holding a user's entity ID, e.g. `uid` or `sAMAccountName`.
+    #[cfg(feature = "ldap")]
+    pub ldap_user_id_attr: String,
+    #[cfg(feature = "ldap")]
+    pub ldap_group_id_attr: String,
+    /// Group attribute listing member DNs.
+    #[cfg(feature = "ldap")]
+    pub ldap_member_attr: String,
+    #[cfg(feature = "ldap")]
+    pub ldap_user_type: String,
+    #[cfg(feature = "ldap")]
+    pub ldap_group_type: String,
+    /// User attributes copied onto entities, as `ldapAttr` or `ldapAttr=entityAttr`.
+    #[cfg(feature = "ldap")]
+    pub ldap_user_attrs: Vec<String>,
+    #[cfg(feature = "ldap")]
+    pub ldap_sync_interval: Duration,
+    /// Domains to obtain a certificate for over ACME; non-empty enables it.
+    pub acme_domains: Vec<String>,
+    /// Contact emails registered with the ACME account.
+    #[cfg(feature = "acme")]
+    pub acme_contact: Vec<String>,
+    /// Where the ACME account key and issued certificates are cached.
+    #[cfg(feature = "acme")]
+    pub acme_cache_dir: String,
+    /// `production`, `staging` (Let's Encrypt) or the directory URL of another ACME CA.
+    #[cfg(feature = "acme")]
+    pub acme_directory: String,
+    #[cfg(feature = "acme")]
+    pub acme_challenge: AcmeChallenge,
+    /// Listener for HTTP-01 challenges.
+    #[cfg(feature = "acme")]
+    pub acme_http_addr: String,
+}
+
+fn env_or(key: &str, default: &str) -> String {
+    std::env::var(key).unwrap_or_else(|_| default.to_string())
+}
+
+/// Reads an optional setting; unset and empty are the same.
+fn env_opt(key: &str) -> Option<String> {
+    std::env::var(key).ok().filter(|s| !s.is_empty())
+}
+
+/// Reads a comma-separated list, ignoring empty entries.
+fn env_list(key: &str) -> Vec<String> {
+    env_or(key, "")
+        .split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// Opens the file `<key>_FILE` names, which holds the list setting `key` instead of the
+/// variable, as with a mounted Kubernetes Secret.
+fn env_secret_file(key: &str) -> Result<Option<Arc<SecretFile>>, String> {
+    let file_key = format!("{}_FILE", key);
+    match (env_opt(key), env_opt(&file_key)) {
+        (Some(_), Some(_)) => Err(format!("{} and {} cannot both be set", key, file_key)),
+        (None, Some(path)) => {
+            let file = SecretFile::open(&path)?;
+            if secret_file::entries(&file.contents()).is_empty() {
+                return Err(format!("{} names {}, which is empty", file_key, path));
+            }
+            Ok(Some(Arc::new(file)))
+        }
+        _ => Ok(None),
+    }
+}
+
+/// A list setting, read from its secret file when it has one.
+fn secret_list(key: &str, file: &Option<Arc<SecretFile>>) -> Vec<String> {
+    match file {
+        Some(file) => secret_file::entries(&file.contents()),
+        None => env_list(key),
+    }
+}
+
+/// Reads the agent's level out of an `env_logger`-style `RUST_LOG` such as `info,hyper=warn` or
+/// `cedar_agent=debug`: a `cedar_agent` directive wins over a bare level, and other crates'
+/// directives are ignored. Anything unusable falls back to `info` rather than failing startup.
+fn rust_log_level(value: &str) -> LevelFilter {
+    let mut level = None;
+    let mut own = None;
+    for directive in value.split(',').map(str::trim) {
+        match directive.split_once('=') {
+            Some(("cedar_agent", l)) => own = l.parse().ok().or(own),
+            Some(_) => {}
+            None => level = directive.parse().ok().or(level),
+        }
+    }
+    own.or(level).unwrap_or_else(|| {
+        // Logging is not set up yet; this matches how the logger prints warnings
+        eprintln!("Warning: Unrecognised RUST_LOG '{}', logging at info", value);
+        LevelFilter::Info
+    })
+}
+
+impl Config {
+    pub fn from_env() -> Result<Self, Box<dyn std::error::Error>> {
+        let hmac_secrets_file = env_secret_file("CEDAR_HMAC_SECRETS")?;
+        let api_keys_file = env_secret_file("CEDAR_API_KEYS")?;
+        let admin_keys_file = env_secret_file("CEDAR_ADMIN_KEYS")?;
+        Ok(Self {
+            policy_path: env_or("CEDAR_POLICY_PATH", "/app/policies/policy.cedar"),
+            policy_overlays: env_list("CEDAR_POLICY_OVERLAYS"),
+            complexity_limits: crate::complexity::Limits {
+                max_depth: env_or("CEDAR_POLICY_MAX_DEPTH", "32")
+                    .parse()
+                    .map_err(|e| format!("Invalid CEDAR_POLICY_MAX_DEPTH: {}", e))?,
+                max_set_size: env_or("CEDAR_POLICY_MAX_SET_SIZE", "10000")
+                    .parse()
+                    .map_err(|e| format!("Invalid CEDAR_POLICY_MAX_SET_SIZE: {}", e))?,
+                max_expensive_operators: env_or("CEDAR_POLICY_MAX_EXPENSIVE_OPERATORS", "50")
+                    .parse()
+                    .map_err(|e| format!("Invalid CEDAR_POLICY_MAX_EXPENSIVE_OPERATORS: {}", e))?,
+                reject: match env_or("CEDAR_POLICY_COMPLEXITY", "warn").to_lowercase().as_str() {
+                    "warn" => false,
+                    "reject" => true,
+                    other => return Err(format!("Invalid CEDAR_POLICY_COMPLEXITY '{}' (expected warn or reject)", other).into()),
+                },
+            },
+            policy_lint: crate::policy_lint::Rules::parse(&env_list("CEDAR_POLICY_LINT"))?,
+            schema_path: env_or("CEDAR_SCHEMA_PATH", "/app/policies/schema.cedarschema.json"),
+            schema_fragments: env_list("CEDAR_SCHEMA_FRAGMENTS"),
+            entities_path: env_opt("CEDAR_ENTITIES_PATH"),
+            entity_fetchers: env_opt("CEDAR_ENTITY_FETCHERS"),
+            entity_cache_ttl: match env_or("CEDAR_ENTITY_CACHE_TTL_SECS", "0").parse::<u64>() {
+                Ok(secs) => Duration::from_secs(secs),
+                Err(e) => return Err(format!("Invalid CEDAR_ENTITY_CACHE_TTL_SECS: {}", e).into()),
+            },
+            entity_cache_max_entries: match env_or("CEDAR_ENTITY_CACHE_MAX_ENTRIES", "10000").parse::<usize>() {
+                Ok(entries) if entries > 0 => entries,
+                Ok(_) => return Err("CEDAR_ENTITY_CACHE_MAX_ENTRIES must be greater than zero".into()),
+                Err(e) => return Err(format!("Invalid CEDAR_ENTITY_CACHE_MAX_ENTRIES: {}", e).into()),
+            },
+            entity_hierarchy: env_or("CEDAR_ENTITY_HIERARCHY", "compute").parse()?,
+            entity_duplicates: env_or("CEDAR_ENTITY_DUPLICATES", "reject").parse()?,
+            entity_integrity: env_or("CEDAR_ENTITY_INTEGRITY", "warn").parse()?,
+            bind_addr: env_or("BIND_ADDR", "0.0.0.0:8181"),
+            acceptors: match env_or("CEDAR_ACCEPTORS", "1").parse::<usize>() {
+                Ok(count) if count > 0 => count,
+                Ok(_) => return Err("CEDAR_ACCEPTORS must be greater than zero".into()),
+                Err(e) => return Err(format!("Invalid CEDAR_ACCEPTORS: {}", e).into()),
+            },
+            pin_acceptors: env_or("CEDAR_PIN_ACCEPTORS", "false") == "true",
+            upgrade_timeout: match env_or("CEDAR_UPGRADE_TIMEOUT_SECS", "30").parse::<u64>() {
+                Ok(secs) if secs > 0 => Duration::from_secs(secs),
+                Ok(_) => return Err("CEDAR_UPGRADE_TIMEOUT_SECS must be greater than zero".into()),
+                Err(e) => return Err(format!("Invalid CEDAR_UPGRADE_TIMEOUT_SECS: {}", e).into()),
+            },
+            drain_timeout: match env_or("CEDAR_DRAIN_TIMEOUT_SECS", "30").parse::<u64>() {
+                Ok(secs) => Duration::from_secs(secs),
+                Err(e) => return Err(format!("Invalid CEDAR_DRAIN_TIMEOUT_SECS: {}", e).into()),
+            },
+            drain_grace: match env_or("CEDAR_DRAIN_GRACE_SECS", "10").parse::<u64>() {
+                Ok(secs) => Duration::from_secs(secs),
+                Err(e) => return Err(format!("Invalid CEDAR_DRAIN_GRACE_SECS: {}", e).into()),
+            },
+            default_decision: env_or("CEDAR_DEFAULT_DECISION", "deny").parse()?,
+            response_format: env_or("CEDAR_RESPONSE_FORMAT", "cedar").parse()?,
+            context_time_attribute: env_opt("CEDAR_CONTEXT_TIME_ATTRIBUTE"),
+            fixed_time: env_opt("CEDAR_FIXED_TIME")
+                .map(|time| crate::clock::parse_time(&time).map_err(|e| format!("Invalid CEDAR_FIXED_TIME: {}", e)))
+                .transpose()?,
+            test_mode: env_or("CEDAR_TEST_MODE", "false") == "true",
+            require_approval: env_or("CEDAR_REQUIRE_APPROVAL", "false") == "true",
+            chaos: match env_or("CEDAR_CHAOS", "false").as_str() {
+                "true" => Some(
+                    env_opt("CEDAR_CHAOS_FAULTS")
+                        .map(|faults| crate::chaos::Faults::parse(&faults).map_err(|e| format!("Invalid CEDAR_CHAOS_FAULTS: {}", e)))
+                        .transpose()?
+                        .unwrap_or_default(),
+                ),
+                _ => None,
+            },
+            statsd_addr: env_opt("CEDAR_STATSD_ADDR"),
+            statsd_prefix: env_or("CEDAR_STATSD_PREFIX", "cedar_agent"),
+            statsd_tags: env_list("CEDAR_STATSD_TAGS"),
+            debug_endpoints: env_or("CEDAR_DEBUG_ENDPOINTS", "false") == "true",
+            debug_decisions: match env_or("CEDAR_DEBUG_DECISIONS", "100").parse() {
+                Ok(n) if n > 0 => n,
+                Ok(_) => return Err("CEDAR_DEBUG_DECISIONS must be greater than zero".into()),
+                Err(e) => return Err(format!("Invalid CEDAR_DEBUG_DECISIONS: {}", e).into()),
+            },
+            access_log: match env_or("CEDAR_ACCESS_LOG", "off").as_str() {
+                "" | "off" => None,
+                format => Some(format.parse()?),
+            },
+            access_log_path: env_or("CEDAR_ACCESS_LOG_PATH", ""),
+            decision_log_path: env_opt("CEDAR_DECISION_LOG_PATH"),
+            audit_log_path: env_opt("CEDAR_AUDIT_LOG_PATH"),
+            decision_log_signing_key: env_opt("CEDAR_DECISION_LOG_SIGNING_KEY"),
+            decision_log_checkpoint_interval: match env_or("CEDAR_DECISION_LOG_CHECKPOINT_INTERVAL", "1000").parse::<u64>() {
+                Ok(records) if records > 0 => records,
+                Ok(_) => return Err("CEDAR_DECISION_LOG_CHECKPOINT_INTERVAL must be greater than zero".into()),
+                Err(e) => return Err(format!("Invalid CEDAR_DECISION_LOG_CHECKPOINT_INTERVAL: {}", e).into()),
+            },
+            decision_index_size: env_or("CEDAR_DECISION_INDEX_SIZE", "10000")
+                .parse()
+                .map_err(|e| format!("Invalid CEDAR_DECISION_INDEX_SIZE: {}", e))?,
+            log_level: match env_opt("CEDAR_LOG_LEVEL") {
+                Some(level) => level
+                    .parse()
+                    .map_err(|_| "Invalid CEDAR_LOG_LEVEL (expected off, error, warn, info, debug or trace)")?,
+                None => rust_log_level(&env_or("RUST_LOG", "info")),
+            },
+            syslog: env_opt("CEDAR_SYSLOG"),
+            syslog_facility: crate::logging::parse_facility(&env_or("CEDAR_SYSLOG_FACILITY", "daemon"))?,
+            tls_cert_path: env_opt("CEDAR_TLS_CERT"),
+            tls_key_path: env_opt("CEDAR_TLS_KEY"),
+            tls_reload_interval: match env_or("CEDAR_TLS_RELOAD_INTERVAL_SECS", "30").parse::<u64>() {
+                Ok(secs) if secs > 0 => Duration::from_secs(secs),
+                Ok(_) => return Err("CEDAR_TLS_RELOAD_INTERVAL_SECS must be greater than zero".into()),
+                Err(e) => return Err(format!("Invalid CEDAR_TLS_RELOAD_INTERVAL_SECS: {}", e).into()),
+            },
+            spiffe: env_or("CEDAR_SPIFFE", "false") == "true",
+            #[cfg(feature = "spiffe")]
+            spiffe_endpoint: env_opt("CEDAR_SPIFFE_ENDPOINT_SOCKET"),
+            #[cfg(feature = "spiffe")]
+            spiffe_trust_domains: env_list("CEDAR_SPIFFE_TRUST_DOMAINS"),
+            spiffe_principal_type: env_or("CEDAR_SPIFFE_PRINCIPAL_TYPE", "Workload"),
+            token_jwks: env_opt("CEDAR_TOKEN_JWKS"),
+            token_issuer: env_opt("CEDAR_TOKEN_ISSUER"),
+            token_audience: env_opt("CEDAR_TOKEN_AUDIENCE"),
+            token_principal_type: env_or("CEDAR_TOKEN_PRINCIPAL_TYPE", "User"),
+            token_principal_claim: env_or("CEDAR_TOKEN_PRINCIPAL_CLAIM", "sub"),
+            token_groups_claim: env_opt("CEDAR_TOKEN_GROUPS_CLAIM"),
+            token_group_type: env_or("CEDAR_TOKEN_GROUP_TYPE", "Group"),
+            token_claims: env_list("CEDAR_TOKEN_CLAIMS"),
+            token_context_attribute: env_opt("CEDAR_TOKEN_CONTEXT_ATTRIBUTE"),
+            batch_max_items: match env_or("CEDAR_BATCH_MAX_ITEMS", "100").parse::<usize>() {
+                Ok(items) if items > 0 => items,
+                Ok(_) => return Err("CEDAR_BATCH_MAX_ITEMS must be greater than zero".into()),
+                Err(e) => return Err(format!("Invalid CEDAR_BATCH_MAX_ITEMS: {}", e).into()),
+            },
+            baggage_keys: env_list("CEDAR_BAGGAGE_KEYS"),
+            baggage_context_attribute: env_or("CEDAR_BAGGAGE_CONTEXT_ATTRIBUTE", "baggage"),
+            tenant_from: env_opt("CEDAR_TENANT_FROM"),
+            tenant_max_labels: match env_or("CEDAR_TENANT_MAX_LABELS", "100").parse::<usize>() {
+                Ok(labels) => labels,
+                Err(e) => return Err(format!("Invalid CEDAR_TENANT_MAX_LABELS: {}", e).into()),
+            },
+            tenant_rate_limit: match env_or("CEDAR_TENANT_RATE_LIMIT", "0").parse::<u32>() {
+                Ok(rate) => (rate > 0).then_some(rate),
+                Err(e) => return Err(format!("Invalid CEDAR_TENANT_RATE_LIMIT: {}", e).into()),
+            },
+            tenant_max_entities: match env_or("CEDAR_TENANT_MAX_ENTITIES", "0").parse::<usize>() {
+                Ok(limit) => (limit > 0).then_some(limit),
+                Err(e) => return Err(format!("Invalid CEDAR_TENANT_MAX_ENTITIES: {}", e).into()),
+            },
+            tenant_limits: env_list("CEDAR_TENANT_LIMITS"),
+            scim_token: env_opt("CEDAR_SCIM_TOKEN"),
+            scim_user_type: env_or("CEDAR_SCIM_USER_TYPE", "User"),
+            scim_group_type: env_or("CEDAR_SCIM_GROUP_TYPE", "Group"),
+            replication_token: env_opt("CEDAR_REPLICATION_TOKEN"),
+            replica_of: env_opt("CEDAR_REPLICA_OF"),
+            grpc_reflection: env_or("CEDAR_GRPC_REFLECTION", "false") == "true",
+            data_dir: env_opt("CEDAR_DATA_DIR"),
+            data_compact_after: match env_or("CEDAR_DATA_COMPACT_AFTER", "100").parse::<usize>() {
+                Ok(records) if records > 0 => records,
+                Ok(_) => return Err("CEDAR_DATA_COMPACT_AFTER must be greater than zero".into()),
+                Err(e) => return Err(format!("Invalid CEDAR_DATA_COMPACT_AFTER: {}", e).into()),
+            },
+            avp_policy_store_id: env_opt("CEDAR_AVP_POLICY_STORE_ID"),
+            #[cfg(feature = "avp")]
+            avp_sync_interval: match env_or("CEDAR_AVP_SYNC_INTERVAL_SECS", "60").parse::<u64>() {
+                Ok(secs) if secs > 0 => Duration::from_secs(secs),
+                Ok(_) => return Err("CEDAR_AVP_SYNC_INTERVAL_SECS must be greater than zero".into()),
+                Err(e) => return Err(format!("Invalid CEDAR_AVP_SYNC_INTERVAL_SECS: {}", e).into()),
+            },
+            k8s_import: env_or("CEDAR_K8S_IMPORT", "false") == "true",
+            #[cfg(feature = "kubernetes")]
+            k8s_namespace: env_opt("CEDAR_K8S_NAMESPACE"),
+            #[cfg(feature = "kubernetes")]
+            k8s_entity_namespace: env_or("CEDAR_K8S_ENTITY_NAMESPACE", "k8s"),
+            ldap_url: env_opt("CEDAR_LDAP_URL"),
+            #[cfg(feature = "ldap")]
+            ldap_bind_dn: env_opt("CEDAR_LDAP_BIND_DN"),
+            #[cfg(feature = "ldap")]
+            ldap_bind_password: env_opt("CEDAR_LDAP_BIND_PASSWORD"),
+            #[cfg(feature = "ldap")]
+            ldap_base_dn: env_or("CEDAR_LDAP_BASE_DN", ""),
+            #[cfg(feature = "ldap")]
+            ldap_user_filter: env_or("CEDAR_LDAP_USER_FILTER", "(objectClass=person)"),
+            #[cfg(feature = "ldap")]
+            ldap_group_filter: env_or("CEDAR_LDAP_GROUP_FILTER", "(|(objectClass=groupOfNames)(objectClass=group))"),
+            #[cfg(feature = "ldap")]
+            ldap_user_id_attr: env_or("CEDAR_LDAP_USER_ID_ATTR", "uid"),
+            #[cfg(feature = "ldap")]
+            ldap_group_id_attr: env_or("CEDAR_LDAP_GROUP_ID_ATTR", "cn"),
+            #[cfg(feature = "ldap")]
+            ldap_member_attr: env_or("CEDAR_LDAP_MEMBER_ATTR", "member"),
+            #[cfg(feature = "ldap")]
+            ldap_user_type: env_or("CEDAR_LDAP_USER_TYPE", "User"),
+            #[cfg(feature = "ldap")]
+            ldap_group_type: env_or("CEDAR_LDAP_GROUP_TYPE", "Group"),
+            #[cfg(feature = "ldap")]
+            ldap_user_attrs: env_list("CEDAR_LDAP_USER_ATTRS"),
+            #[cfg(feature = "ldap")]
+            ldap_sync_interval: match env_or("CEDAR_LDAP_SYNC_INTERVAL_SECS", "300").parse::<u64>() {
+                Ok(secs) if secs > 0 => Duration::from_secs(secs),
+                Ok(_) => return Err("CEDAR_LDAP_SYNC_INTERVAL_SECS must be greater than zero".into()),
+                Err(e) => return Err(format!("Invalid CEDAR_LDAP_SYNC_INTERVAL_SECS: {}", e).into()),
+            },
+            acme_domains: env_list("CEDAR_ACME_DOMAINS"),
+            #[cfg(feature = "acme")]
+            acme_contact: env_list("CEDAR_ACME_CONTACT"),
+            #[cfg(feature = "acme")]
+            acme_cache_dir: env_or("CEDAR_ACME_CACHE_DIR", "/app/acme"),
+            #[cfg(feature = "acme")]
+            acme_directory: env_or("CEDAR_ACME_DIRECTORY", "production"),
+            #[cfg(feature = "acme")]
+            acme_challenge: env_or("CEDAR_ACME_CHALLENGE", "tls-alpn-01").parse()?,
+            #[cfg(feature = "acme")]
+            acme_http_addr: env_or("CEDAR_ACME_HTTP_ADDR", "0.0.0.0:80"),
+            hmac_secrets: secret_list("CEDAR_HMAC_SECRETS", &hmac_secrets_file),
+            hmac_max_skew: env_or("CEDAR_HMAC_MAX_SKEW_SECS", "300")
+                .parse()
+                .map_err(|e| format!("Invalid CEDAR_HMAC_MAX_SKEW_SECS: {}", e))?,
+            api_keys: secret_list("CEDAR_API_KEYS", &api_keys_file),
+            admin_keys: secret_list("CEDAR_ADMIN_KEYS", &admin_keys_file),
+            hmac_secrets_file,
+            api_keys_file,
+            admin_keys_file,
+            secrets_reload_interval: match env_or("CEDAR_SECRETS_RELOAD_INTERVAL_SECS", "30").parse::<u64>() {
+                Ok(secs) if secs > 0 => Duration::from_secs(secs),
+                Ok(_) => return Err("CEDAR_SECRETS_RELOAD_INTERVAL_SECS must be greater than zero".into()),
+                Err(e) => return Err(format!("Invalid CEDAR_SECRETS_RELOAD_INTERVAL_SECS: {}", e).into()),
+            },
+            admin_users: env_list("CEDAR_ADMIN_USERS"),
+            admin_roles: env_list("CEDAR_ADMIN_ROLES"),
+            api_key_quotas: env_list("CEDAR_API_KEY_QUOTAS"),
+            api_key_hourly_quota: match env_or("CEDAR_API_KEY_HOURLY_QUOTA", "0").parse::<u64>() {
+                Ok(limit) => (limit > 0).then_some(limit),
+                Err(e) => return Err(format!("Invalid CEDAR_API_KEY_HOURLY_QUOTA: {}", e).into()),
+            },
+            api_key_daily_quota: match env_or("CEDAR_API_KEY_DAILY_QUOTA", "0").parse::<u64>() {
+                Ok(limit) => (limit > 0).then_some(limit),
+                Err(e) => return Err(format!("Invalid CEDAR_API_KEY_DAILY_QUOTA: {}", e).into()),
+            },
+            max_concurrent_evaluations: match env_or("CEDAR_MAX_CONCURRENT_EVALUATIONS", "0").parse::<usize>() {
+                Ok(limit) => (limit > 0).then_some(limit),
+                Err(e) => return Err(format!("Invalid CEDAR_MAX_CONCURRENT_EVALUATIONS: {}", e).into()),
+            },
+            max_queued_evaluations: env_or("CEDAR_MAX_QUEUED_EVALUATIONS", "100")
+                .parse()
+                .map_err(|e| format!("Invalid CEDAR_MAX_QUEUED_EVALUATIONS: {}", e))?,
+            queue_timeout: Duration::from_millis(
+                env_or("CEDAR_QUEUE_TIMEOUT_MS", "100")
+                    .parse()
+                    .map_err(|e| format!("Invalid CEDAR_QUEUE_TIMEOUT_MS: {}", e))?,
+            ),
+            min_version_wait: Duration::from_millis(
+                env_or("CEDAR_MIN_VERSION_WAIT_MS", "1000")
+                    .parse()
+                    .map_err(|e| format!("Invalid CEDAR_MIN_VERSION_WAIT_MS: {}", e))?,
+            ),
+            deadline_min_budget: Duration::from_millis(
+                env_or("CEDAR_DEADLINE_MIN_BUDGET_MS", "5")
+                    .parse()
+                    .map_err(|e| format!("Invalid CEDAR_DEADLINE_MIN_BUDGET_MS: {}", e))?,
+            ),
+            slow_decision: match env_or("CEDAR_SLOW_DECISION_MS", "0").parse::<u64>() {
+                Ok(ms) => (ms > 0).then(|| Duration::from_millis(ms)),
+                Err(e) => return Err(format!("Invalid CEDAR_SLOW_DECISION_MS: {}", e).into()),
+            },
+            entity_memory_limit: match env_or("CEDAR_ENTITY_MEMORY_LIMIT_BYTES", "0").parse::<usize>() {
+                Ok(limit) => (limit > 0).then_some(limit),
+                Err(e) => return Err(format!("Invalid CEDAR_ENTITY_MEMORY_LIMIT_BYTES: {}", e).into()),
+            },
+            max_request_bytes: match env_or("CEDAR_MAX_REQUEST_BYTES", "0").parse::<usize>() {
+                Ok(limit) => (limit > 0).then_some(limit),
+                Err(e) => return Err(format!("Invalid CEDAR_MAX_REQUEST_BYTES: {}", e).into()),
+            },
+            ip_filter: IpFilter {
+                data_plane: IpRules {
+                    allow: ip_filter::parse_list(&env_list("CEDAR_ALLOW_CIDRS"))?,
+                    deny: ip_filter::parse_list(&env_list("CEDAR_DENY_CIDRS"))?,
+                },
+                admin: IpRules {
+                    allow: ip_filter::parse_list(&env_list("CEDAR_ADMIN_ALLOW_CIDRS"))?,
+                    deny: ip_filter::parse_list(&env_list("CEDAR_ADMIN_DENY_CIDRS"))?,
+                },
+            },
+        })
+    }
+}
@@ -0,0 +1,556 @@
+use crate::validate::Finding;
+use cedar_policy::EntityTypeName;
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+use std::collections::BTreeMap;
+use std::fs;
+use std::net::SocketAddr;
+
+/// What a setting's value must look like.
+#[derive(Clone, Copy)]
+enum Kind {
+    Text,
+    Bool,
+    /// A non-negative integer.
+    Count,
+    /// An integer greater than zero.
+    Positive,
+    /// Comma-separated values.
+    List,
+    /// Comma-separated CIDRs or addresses.
+    Cidrs,
+    /// One of the listed values, case-insensitively.
+    Choice(&'static [&'static str]),
+    /// `ip:port`.
+    Address,
+    Facility,
+    EntityType,
+    LogLevel,
+    /// An RFC 3339 timestamp.
+    Time,
+}
+
+/// One environment variable the agent reads.
+struct Setting {
+    name: &'static str,
+    kind: Kind,
+    default: Option<&'static str>,
+    /// Cargo feature the setting needs.
+    feature: Option<&'static str>,
+    /// Setting without which this one has no effect.
+    requires: Option<&'static str>,
+    description: &'static str,
+}
+
+const fn setting(name: &'static str, kind: Kind, default: Option<&'static str>, description: &'static str) -> Setting {
+    Setting {
+        name,
+        kind,
+        default,
+        feature: None,
+        requires: None,
+        description,
+    }
+}
+
+impl Setting {
+    const fn feature(self, feature: &'static str) -> Self {
+        Setting {
+            feature: Some(feature),
+            ..self
+        }
+    }
+
+    const fn requires(self, requires: &'static str) -> Self {
+        Setting {
+            requires: Some(requires),
+            ..self
+        }
+    }
+}
+
+const LOG_LEVELS: &[&str] = &["off", "error", "warn", "info", "debug", "trace"];
+
+/// Every setting, in the order of the readme's table. This is the published schema that
+/// `cedar-agent config schema` prints.
+const SETTINGS: &[Setting] = &[
+    setting("CEDAR_POLICY_PATH", Kind::Text, Some("/app/policies/policy.cedar"), "Path to Cedar policy file"),
+    setting("CEDAR_POLICY_OVERLAYS", Kind::List, None, "Policy files or directories layered on top of CEDAR_POLICY_PATH"),
+    setting("CEDAR_POLICY_MAX_DEPTH", Kind::Count, Some("32"), "Deepest expression nesting of a policy condition; 0 is unlimited"),
+    setting("CEDAR_POLICY_MAX_SET_SIZE", Kind::Count, Some("10000"), "Most elements of a set literal in a policy; 0 is unlimited"),
+    setting("CEDAR_POLICY_MAX_EXPENSIVE_OPERATORS", Kind::Count, Some("50"), "Most like, contains, containsAll, containsAny and in operators in a policy; 0 is unlimited"),
+    setting("CEDAR_POLICY_COMPLEXITY", Kind::Choice(&["warn", "reject"]), Some("warn"), "Warn about policies over the complexity limits, or refuse them"),
+    setting("CEDAR_POLICY_LINT", Kind::List, None, "Policy lint rule severities as rule=error|warning|off"),
+    setting("CEDAR_SCHEMA_PATH", Kind::Text, Some("/app/policies/schema.cedarschema.json"), "Path to Cedar schema file"),
+    setting("CEDAR_SCHEMA_FRAGMENTS", Kind::List, None, "Schema files or directories merged with CEDAR_SCHEMA_PATH"),
+    setting("CEDAR_ENTITIES_PATH", Kind::Text, None, "Entities held by the agent (Cedar JSON array)"),
+    setting("CEDAR_ENTITY_HIERARCHY", Kind::Choice(&["compute", "provided"]), Some("compute"), "Compute entity ancestors, or take parents as the full ancestor set"),
+    setting("CEDAR_ENTITY_FETCHERS", Kind::Text, None, "JSON file of upstream services principals and resources are fetched from"),
+    setting("CEDAR_ENTITY_CACHE_TTL_SECS", Kind::Count, Some("0"), "How long fetched entities are cached; 0 disables the cache").requires("CEDAR_ENTITY_FETCHERS"),
+    setting("CEDAR_ENTITY_CACHE_MAX_ENTRIES", Kind::Positive, Some("10000"), "Most fetched entities cached at once").requires("CEDAR_ENTITY_FETCHERS"),
+    setting("CEDAR_ENTITY_DUPLICATES", Kind::Choice(&["reject", "keep-last"]), Some("reject"), "Reject differing entities with the same UID, or keep the last"),
+    setting("CEDAR_ENTITY_INTEGRITY", Kind::Choice(&["warn", "reject", "off"]), Some("warn"), "Warn about or reject stored entities with dangling parents or membership cycles"),
+    setting("BIND_ADDR", Kind::Address, Some("0.0.0.0:8181"), "Server bind address"),
+    setting("CEDAR_ACCEPTORS", Kind::Positive, Some("1"), "Listening sockets sharing BIND_ADDR via SO_REUSEPORT"),
+    setting("CEDAR_PIN_ACCEPTORS", Kind::Bool, Some("false"), "Pin acceptor threads to CPU cores").requires("CEDAR_ACCEPTORS"),
+    setting("CEDAR_UPGRADE_TIMEOUT_SECS", Kind::Positive, Some("30"), "How long a process started on SIGUSR2 has to take over the listening sockets"),
+    setting("CEDAR_DRAIN_TIMEOUT_SECS", Kind::Count, Some("30"), "How long open connections are waited for once the agent stops accepting"),
+    setting("CEDAR_DRAIN_GRACE_SECS", Kind::Count, Some("10"), "How long POST /admin/drain keeps accepting connections after reporting not ready"),
+    setting("CEDAR_UPGRADE_FDS", Kind::List, None, "Set by the agent for the process it upgrades to: the listening sockets it inherits"),
+    setting("CEDAR_UPGRADE_READY_FD", Kind::Count, None, "Set by the agent for the process it upgrades to: the pipe it reports ready on"),
+    setting("CEDAR_DEFAULT_DECISION", Kind::Choice(&["deny", "allow", "deny-with-warning"]), Some("deny"), "Decision when no policy applies"),
+    setting("CEDAR_RESPONSE_FORMAT", Kind::Choice(&["cedar", "allowed", "camel", "avp"]), Some("cedar"), "Shape of /authorize responses"),
+    setting("CEDAR_CONTEXT_TIME_ATTRIBUTE", Kind::Text, None, "Context attribute set to the evaluation time"),
+    setting("CEDAR_FIXED_TIME", Kind::Time, None, "Evaluation time used instead of the system clock").requires("CEDAR_CONTEXT_TIME_ATTRIBUTE"),
+    setting("CEDAR_TEST_MODE", Kind::Bool, Some("false"), "Honour X-Cedar-Evaluation-Time on requests").requires("CEDAR_CONTEXT_TIME_ATTRIBUTE"),
+    setting("CEDAR_CHAOS", Kind::Bool, Some("false"), "Inject the faults of /admin/chaos into evaluations; never in production"),
+    setting("CEDAR_CHAOS_FAULTS", Kind::Text, None, "Faults injected from startup, as JSON").requires("CEDAR_CHAOS"),
+    setting("CEDAR_STATSD_ADDR", Kind::Text, None, "StatsD/DogStatsD collector (host:port) to push metrics to"),
+    setting("CEDAR_STATSD_PREFIX", Kind::Text, Some("cedar_agent"), "Prefix for StatsD metric names").requires("CEDAR_STATSD_ADDR"),
+    setting("CEDAR_STATSD_TAGS", Kind::List, None, "Tags added to every StatsD metric").requires("CEDAR_STATSD_ADDR"),
+    setting("CEDAR_DEBUG_ENDPOINTS", Kind::Bool, Some("false"), "Serve the /debug/pprof/* profiling endpoints and /debug/decisions"),
+    setting("CEDAR_DEBUG_DECISIONS", Kind::Count, Some("100"), "Recent decisions kept in full for /debug/decisions").requires("CEDAR_DEBUG_ENDPOINTS"),
+    setting("CEDAR_ACCESS_LOG", Kind::Choice(&["off", "combined", "json"]), Some("off"), "HTTP access log format"),
+    setting("CEDAR_ACCESS_LOG_PATH", Kind::Text, None, "File to append the access log to").requires("CEDAR_ACCESS_LOG"),
+    setting("CEDAR_DECISION_LOG_PATH", Kind::Text, None, "Hash-chained decision log file"),
+    setting("CEDAR_DECISION_LOG_SIGNING_KEY", Kind::Text, None, "Secret decision log checkpoints are signed with").requires("CEDAR_DECISION_LOG_PATH"),
+    setting("CEDAR_DECISION_LOG_CHECKPOINT_INTERVAL", Kind::Positive, Some("1000"), "Records between decision log checkpoints").requires("CEDAR_DECISION_LOG_PATH"),
+    setting("CEDAR_DECISION_INDEX_SIZE", Kind::Count, Some("10000"), "Recent decisions kept in memory for GET /v1/decisions; 0 disables it"),
+    setting("CEDAR_AUDIT_LOG_PATH", Kind::Text, None, "Audit log of policy, schema and entity changes"),
+    setting("CEDAR_SYSLOG", Kind::Text, None, "Syslog target: udp://, tcp:// or unix://"),
+    setting("CEDAR_SYSLOG_FACILITY", Kind::Facility, Some("daemon"), "Syslog facility").requires("CEDAR_SYSLOG"),
+    setting("CEDAR_ALLOW_CIDRS", Kind::Cidrs, None, "CIDRs allowed to call data-plane endpoints"),
+    setting("CEDAR_DENY_CIDRS", Kind::Cidrs, None, "CIDRs refused on data-plane endpoints"),
+    setting("CEDAR_ADMIN_ALLOW_CIDRS", Kind::Cidrs, None, "CIDRs allowed to call admin endpoints"),
+    setting("CEDAR_ADMIN_DENY_CIDRS", Kind::Cidrs, None, "CIDRs refused on admin endpoints"),
+    setting("CEDAR_TLS_CERT", Kind::Text, None, "PEM certificate chain; with CEDAR_TLS_KEY, serve HTTPS"),
+    setting("CEDAR_TLS_KEY", Kind::Text, None, "PEM private key"),
+    setting("CEDAR_TLS_RELOAD_INTERVAL_SECS", Kind::Positive, Some("30"), "How often the certificate files are checked for rotation").requires("CEDAR_TLS_CERT"),
+    setting("CEDAR_ACME_DOMAINS", Kind::List, None, "Domains to obtain certificates for over ACME").feature("acme"),
+    setting("CEDAR_ACME_CONTACT", Kind::List, None, "Contact emails for the ACME account").feature("acme").requires("CEDAR_ACME_DOMAINS"),
+    setting("CEDAR_ACME_CACHE_DIR", Kind::Text, Some("/app/acme"), "Where the account key and certificates are cached").feature("acme").requires("CEDAR_ACME_DOMAINS"),
+    setting("CEDAR_ACME_DIRECTORY", Kind::Text, Some("production"), "production, staging or another CA's directory URL").feature("acme").requires("CEDAR_ACME_DOMAINS"),
+    setting("CEDAR_ACME_CHALLENGE", Kind::Choice(&["tls-alpn-01", "http-01"]), Some("tls-alpn-01"), "ACME challenge type").feature("acme").requires("CEDAR_ACME_DOMAINS"),
+    setting("CEDAR_ACME_HTTP_ADDR", Kind::Address, Some("0.0.0.0:80"), "Listener for HTTP-01 challenges").feature("acme").requires("CEDAR_ACME_DOMAINS"),
+    setting("CEDAR_SPIFFE", Kind::Bool, Some("false"), "Serve mTLS with an SVID from the SPIFFE Workload API").feature("spiffe"),
+    setting("CEDAR_SPIFFE_ENDPOINT_SOCKET", Kind::Text, None, "Workload API socket").feature("spiffe").requires("CEDAR_SPIFFE"),
+    setting("CEDAR_SPIFFE_TRUST_DOMAINS", Kind::List, None, "Trust domains clients may come from").feature("spiffe").requires("CEDAR_SPIFFE"),
+    setting("CEDAR_SPIFFE_PRINCIPAL_TYPE", Kind::EntityType, Some("Workload"), "Entity type a client's SPIFFE ID maps to").requires("CEDAR_SPIFFE"),
+    setting("CEDAR_VAULT_ADDR", Kind::Text, None, "Vault server that vault: references in other settings are read from"),
+    setting("CEDAR_VAULT_NAMESPACE", Kind::Text, None, "Vault Enterprise namespace").requires("CEDAR_VAULT_ADDR"),
+    setting("CEDAR_VAULT_TOKEN", Kind::Text, None, "Vault token").requires("CEDAR_VAULT_ADDR"),
+    setting("CEDAR_VAULT_TOKEN_FILE", Kind::Text, None, "File holding the Vault token, such as a Vault Agent sink").requires("CEDAR_VAULT_ADDR"),
+    setting("CEDAR_VAULT_K8S_ROLE", Kind::Text, None, "Log in to Vault with the pod's service account as this role").requires("CEDAR_VAULT_ADDR"),
+    setting("CEDAR_VAULT_K8S_MOUNT", Kind::Text, Some("kubernetes"), "Mount path of Vault's Kubernetes auth method").requires("CEDAR_VAULT_K8S_ROLE"),
+    setting("CEDAR_VAULT_ENV", Kind::List, None, "Variables besides the agent's settings that may hold vault: references").requires("CEDAR_VAULT_ADDR"),
+    setting("CEDAR_VAULT_SECRETS_DIR", Kind::Text, None, "Where vault-file: secrets are written").requires("CEDAR_VAULT_ADDR"),
+    setting("CEDAR_TOKEN_JWKS", Kind::Text, None, "JWKS file or URL bearer tokens are verified against"),
+    setting("CEDAR_TOKEN_ISSUER", Kind::Text, None, "Required iss of tokens").requires("CEDAR_TOKEN_JWKS"),
+    setting("CEDAR_TOKEN_AUDIENCE", Kind::Text, None, "Required aud of tokens").requires("CEDAR_TOKEN_JWKS"),
+    setting("CEDAR_TOKEN_PRINCIPAL_TYPE", Kind::EntityType, Some("User"), "Entity type of token principals").requires("CEDAR_TOKEN_JWKS"),
+    setting("CEDAR_TOKEN_PRINCIPAL_CLAIM", Kind::Text, Some("sub"), "Claim holding the principal's ID").requires("CEDAR_TOKEN_JWKS"),
+    setting("CEDAR_TOKEN_GROUPS_CLAIM", Kind::Text, None, "Claim listing the principal's groups").requires("CEDAR_TOKEN_JWKS"),
+    setting("CEDAR_TOKEN_GROUP_TYPE", Kind::EntityType, Some("Group"), "Entity type of token groups").requires("CEDAR_TOKEN_GROUPS_CLAIM"),
+    setting("CEDAR_TOKEN_CLAIMS", Kind::List, None, "Claims copied onto the principal as attributes").requires("CEDAR_TOKEN_JWKS"),
+    setting("CEDAR_TOKEN_CONTEXT_ATTRIBUTE", Kind::Text, None, "Context attribute holding the token's claims").requires("CEDAR_TOKEN_JWKS"),
+    setting("CEDAR_BATCH_MAX_ITEMS", Kind::Positive, Some("100"), "Most items in one /authorize/batch request"),
+    setting("CEDAR_BAGGAGE_KEYS", Kind::List, None, "W3C baggage entries copied into the request context"),
+    setting("CEDAR_BAGGAGE_CONTEXT_ATTRIBUTE", Kind::Text, Some("baggage"), "Context attribute holding the baggage entries").requires("CEDAR_BAGGAGE_KEYS"),
+    setting("CEDAR_TENANT_FROM", Kind::Text, None, "Where a request's tenant comes from: api-key or baggage:<entry>"),
+    setting("CEDAR_TENANT_MAX_LABELS", Kind::Count, Some("100"), "Most tenants given their own metric label").requires("CEDAR_TENANT_FROM"),
+    setting("CEDAR_TENANT_RATE_LIMIT", Kind::Count, Some("0"), "Evaluation requests per second per tenant; 0 is unlimited").requires("CEDAR_TENANT_FROM"),
+    setting("CEDAR_TENANT_MAX_ENTITIES", Kind::Count, Some("0"), "Most entities in one evaluation request of a tenant; 0 is unlimited").requires("CEDAR_TENANT_FROM"),
+    setting("CEDAR_TENANT_LIMITS", Kind::List, None, "Per-tenant overrides as tenant=rate/bytes/entities").requires("CEDAR_TENANT_FROM"),
+    setting("CEDAR_SCIM_TOKEN", Kind::Text, None, "Bearer token for the SCIM endpoints"),
+    setting("CEDAR_SCIM_USER_TYPE", Kind::EntityType, Some("User"), "Entity type of SCIM users").requires("CEDAR_SCIM_TOKEN"),
+    setting("CEDAR_SCIM_GROUP_TYPE", Kind::EntityType, Some("Group"), "Entity type of SCIM groups").requires("CEDAR_SCIM_TOKEN"),
+    setting("CEDAR_REPLICATION_TOKEN", Kind::Text, None, "Shared token for the replication stream, on leaders and replicas"),
+    setting("CEDAR_REPLICA_OF", Kind::Text, None, "http:// address of the leader to replicate state from").requires("CEDAR_REPLICATION_TOKEN"),
+    setting("CEDAR_GRPC_REFLECTION", Kind::Bool, Some("false"), "Serve gRPC server reflection for the gRPC health service"),
+    setting("CEDAR_DATA_DIR", Kind::Text, None, "Directory to persist the schema and stored entities in"),
+    setting("CEDAR_DATA_COMPACT_AFTER", Kind::Positive, Some("100"), "Log records after which the store is compacted").requires("CEDAR_DATA_DIR"),
+    setting("CEDAR_LDAP_URL", Kind::Text, None, "ldap:// or ldaps:// server to sync users and groups from").feature("ldap"),
+    setting("CEDAR_LDAP_BIND_DN", Kind::Text, None, "DN to bind as").feature("ldap").requires("CEDAR_LDAP_URL"),
+    setting("CEDAR_LDAP_BIND_PASSWORD", Kind::Text, None, "Password for CEDAR_LDAP_BIND_DN").feature("ldap").requires("CEDAR_LDAP_URL"),
+    setting("CEDAR_LDAP_BASE_DN", Kind::Text, None, "Search base").feature("ldap").requires("CEDAR_LDAP_URL"),
+    setting("CEDAR_LDAP_USER_FILTER", Kind::Text, Some("(objectClass=person)"), "Filter selecting users").feature("ldap").requires("CEDAR_LDAP_URL"),
+    setting("CEDAR_LDAP_GROUP_FILTER", Kind::Text, Some("(|(objectClass=groupOfNames)(objectClass=group))"), "Filter selecting groups").feature("ldap").requires("CEDAR_LDAP_URL"),
+    setting("CEDAR_LDAP_USER_ID_ATTR", Kind::Text, Some("uid"), "Attribute used as a user's entity ID").feature("ldap").requires("CEDAR_LDAP_URL"),
+    setting("CEDAR_LDAP_GROUP_ID_ATTR", Kind::Text, Some("cn"), "Attribute used as a group's entity ID").feature("ldap").requires("CEDAR_LDAP_URL"),
+    setting("CEDAR_LDAP_MEMBER_ATTR", Kind::Text, Some("member"), "Group attribute listing member DNs").feature("ldap").requires("CEDAR_LDAP_URL"),
+    setting("CEDAR_LDAP_USER_TYPE", Kind::EntityType, Some("User"), "Entity type of synced users").feature("ldap").requires("CEDAR_LDAP_URL"),
+    setting("CEDAR_LDAP_GROUP_TYPE", Kind::EntityType, Some("Group"), "Entity type of synced groups").feature("ldap").requires("CEDAR_LDAP_URL"),
+    setting("CEDAR_LDAP_USER_ATTRS", Kind::List, None, "User attributes to copy, as mail or mail=email").feature("ldap").requires("CEDAR_LDAP_URL"),
+    setting("CEDAR_LDAP_SYNC_INTERVAL_SECS", Kind::Positive, Some("300"), "How often the directory is synced").feature("ldap").requires("CEDAR_LDAP_URL"),
+    setting("CEDAR_K8S_IMPORT", Kind::Bool, Some("false"), "Import service accounts and RBAC roles as entities").feature("kubernetes"),
+    setting("CEDAR_K8S_NAMESPACE", Kind::Text, None, "Only watch this namespace").feature("kubernetes").requires("CEDAR_K8S_IMPORT"),
+    setting("CEDAR_K8S_ENTITY_NAMESPACE", Kind::Text, Some("k8s"), "Cedar namespace of the imported entity types").feature("kubernetes").requires("CEDAR_K8S_IMPORT"),
+    setting("CEDAR_AVP_POLICY_STORE_ID", Kind::Text, None, "Sync policies and schema from this Verified Permissions policy store").feature("avp"),
+    setting("CEDAR_AVP_SYNC_INTERVAL_SECS", Kind::Positive, Some("60"), "How often the policy store is checked for changes").feature("avp").requires("CEDAR_AVP_POLICY_STORE_ID"),
+    setting("CEDAR_HMAC_SECRETS", Kind::List, None, "Shared secrets; when set, every request must be HMAC-signed"),
+    setting("CEDAR_HMAC_SECRETS_FILE", Kind::Text, None, "File holding the HMAC secrets, watched for rotation"),
+    setting("CEDAR_HMAC_MAX_SKEW_SECS", Kind::Count, Some("300"), "Maximum age (or clock skew) of a signed request").requires("CEDAR_HMAC_SECRETS"),
+    setting("CEDAR_API_KEYS", Kind::List, None, "name=key pairs; when set, requests must send X-Api-Key"),
+    setting("CEDAR_API_KEYS_FILE", Kind::Text, None, "File holding the API keys, watched for rotation"),
+    setting("CEDAR_API_KEY_HOURLY_QUOTA", Kind::Count, Some("0"), "Requests per clock hour allowed for each key").requires("CEDAR_API_KEYS"),
+    setting("CEDAR_API_KEY_DAILY_QUOTA", Kind::Count, Some("0"), "Requests per UTC day allowed for each key").requires("CEDAR_API_KEYS"),
+    setting("CEDAR_API_KEY_QUOTAS", Kind::List, None, "Per-key overrides as name=hourly/daily").requires("CEDAR_API_KEYS"),
+    setting("CEDAR_ADMIN_KEYS", Kind::List, None, "Administrator keys as name=key; turns on admin roles"),
+    setting("CEDAR_ADMIN_KEYS_FILE", Kind::Text, None, "File holding the administrator keys, watched for rotation"),
+    setting("CEDAR_SECRETS_RELOAD_INTERVAL_SECS", Kind::Positive, Some("30"), "How often secret files are checked for rotation"),
+    setting("CEDAR_ADMIN_USERS", Kind::List, None, "Administrators using HTTP Basic auth as name:bcrypt-hash; turns on admin roles"),
+    setting("CEDAR_ADMIN_ROLES", Kind::List, None, "Roles of administrators as name=role+role"),
+    setting("CEDAR_REQUIRE_APPROVAL", Kind::Bool, Some("false"), "Policy changes need a proposal approved by a second administrator"),
+    setting("CEDAR_MAX_CONCURRENT_EVALUATIONS", Kind::Count, Some("0"), "Evaluations in flight at once; 0 is unlimited"),
+    setting("CEDAR_MAX_QUEUED_EVALUATIONS", Kind::Count, Some("100"), "Evaluations that may wait for a slot").requires("CEDAR_MAX_CONCURRENT_EVALUATIONS"),
+    setting("CEDAR_QUEUE_TIMEOUT_MS", Kind::Count, Some("100"), "Longest an evaluation waits for a slot").requires("CEDAR_MAX_CONCURRENT_EVALUATIONS"),
+    setting("CEDAR_DEADLINE_MIN_BUDGET_MS", Kind::Count, Some("5"), "Least time a request deadline must leave for the request to be evaluated"),
+    setting("CEDAR_MIN_VERSION_WAIT_MS", Kind::Count, Some("1000"), "Longest a request with min_version waits for the agent to catch up"),
+    setting("CEDAR_ENTITY_MEMORY_LIMIT_BYTES", Kind::Count, Some("0"), "Budget for stored entities; 0 is unlimited"),
+    setting("CEDAR_MAX_REQUEST_BYTES", Kind::Count, Some("0"), "Largest request body accepted; 0 is unlimited"),
+    setting("CEDAR_LOG_LEVEL", Kind::LogLevel, None, "Log level"),
+    setting("RUST_LOG", Kind::Text, Some("info"), "Used when CEDAR_LOG_LEVEL is unset"),
+    setting("CEDAR_SLOW_DECISION_MS", Kind::Count, Some("0"), "Evaluations taking at least this long are logged with a breakdown; 0 logs none"),
+];
+
+/// Features of this build that settings can depend on.
+const COMPILED_FEATURES: &[&str] = &[
+    #[cfg(feature = "profiling")]
+    "profiling",
+    #[cfg(feature = "acme")]
+    "acme",
+    #[cfg(feature = "spiffe")]
+    "spiffe",
+    #[cfg(feature = "ldap")]
+    "ldap",
+    #[cfg(feature = "kubernetes")]
+    "kubernetes",
+    #[cfg(feature = "avp")]
+    "avp",
+];
+
+fn compiled(feature: &str) -> bool {
+    COMPILED_FEATURES.contains(&feature)
+}
+
+fn check_value(kind: Kind, value: &str) -> Result<(), String> {
+    let list = || -> Vec<String> {
+        value.split(',').map(str::trim).filter(|s| !s.is_empty()).map(str::to_string).collect()
+    };
+    match kind {
+        Kind::Text | Kind::List => Ok(()),
+        Kind::Bool => match value {
+            "true" | "false" => Ok(()),
+            _ => Err("expected true or false (anything else counts as false)".to_string()),
+        },
+        Kind::Count => value.parse::<u64>().map(|_| ()).map_err(|_| "expected a whole number".to_string()),
+        Kind::Positive => match value.parse::<u64>() {
+            Ok(n) if n > 0 => Ok(()),
+            _ => Err("expected a whole number greater than zero".to_string()),
+        },
+        Kind::Cidrs => crate::ip_filter::parse_list(&list()).map(|_| ()).map_err(|e| e.to_string()),
+        Kind::Choice(choices) => match choices.iter().any(|c| c.eq_ignore_ascii_case(value)) {
+            true => Ok(()),
+            false => Err(format!("expected one of: {}", choices.join(", "))),
+        },
+        Kind::Address => value.parse::<SocketAddr>().map(|_| ()).map_err(|e| format!("expected ip:port ({})", e)),
+        Kind::Facility => crate::logging::parse_facility(value).map(|_| ()),
+        Kind::EntityType => value.parse::<EntityTypeName>().map(|_| ()).map_err(|e| e.to_string()),
+        Kind::LogLevel => check_value(Kind::Choice(LOG_LEVELS), value),
+        Kind::Time => crate::clock::parse_time(value).map(|_| ()),
+    }
+}
+
+/// Levenshtein distance, for suggesting the setting a misspelt key meant.
+fn distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut prev = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let cost = if ca == *cb { prev } else { prev + 1 };
+            prev = row[j + 1];
+            row[j + 1] = cost.min(row[j] + 1).min(prev + 1);
+        }
+    }
+    row[b.len()]
+}
+
+fn finding(severity: &'static str, key: Option<&str>, message: impl Into<String>) -> Finding {
+    Finding {
+        source: "config",
+        severity,
+        key: key.map(str::to_string),
+        message: message.into(),
+    }
+}
+
+/// Checks settings (environment variable name to value) against the schema: unknown keys,
+/// malformed values, settings this build cannot use, settings that have no effect, and
+/// settings that conflict with each other.
+pub fn check(vars: &BTreeMap<String, String>) -> Vec<Finding> {
+    let mut findings = Vec::new();
+    // Unset and empty are the same to the agent
+    let set = |key: &str| vars.get(key).is_some_and(|v| !v.is_empty());
+    let active = |key: &str| match vars.get(key).map(String::as_str) {
+        // A list setting can be read from a file instead
+        _ if set(&format!("{}_FILE", key)) => true,
+        None | Some("") | Some("false") | Some("0") | Some("off") => false,
+        // A single acceptor is the default behaviour
+        Some("1") if key == "CEDAR_ACCEPTORS" => false,
+        Some(_) => true,
+    };
+
+    for (key, value) in vars {
+        let Some(setting) = SETTINGS.iter().find(|s| s.name == key) else {
+            if key.starts_with("CEDAR_") {
+                let closest = SETTINGS.iter().min_by_key(|s| distance(key, s.name)).filter(|s| distance(key, s.name) <= 3);
+                let message = match closest {
+                    Some(s) => format!("Unknown setting (did you mean {}?)", s.name),
+                    None => "Unknown setting".to_string(),
+                };
+                findings.push(finding("error", Some(key), message));
+            }
+            continue;
+        };
+        if value.is_empty() {
+            continue;
+        }
+        if let Err(e) = check_value(setting.kind, value) {
+            findings.push(finding("error", Some(key), format!("Invalid value '{}': {}", value, e)));
+            continue;
+        }
+        match (setting.feature, setting.requires) {
+            (Some(feature), None) if !compiled(feature) && active(key) => findings.push(finding(
+                "error",
+                Some(key),
+                format!("Set, but this build does not include the `{}` feature", feature),
+            )),
+            (Some(feature), Some(_)) if !compiled(feature) => findings.push(finding(
+                "warning",
+                Some(key),
+                format!("Has no effect: this build does not include the `{}` feature", feature),
+            )),
+            (_, Some(requires)) if !active(requires) => {
+                findings.push(finding("warning", Some(key), format!("Has no effect without {}", requires)))
+            }
+            _ => {}
+        }
+    }
+
+    for key in ["CEDAR_HMAC_SECRETS", "CEDAR_API_KEYS", "CEDAR_ADMIN_KEYS"] {
+        if set(key) && set(&format!("{}_FILE", key)) {
+            findings.push(finding("error", None, format!("{} and {}_FILE cannot both be set", key, key)));
+        }
+    }
+    if set("CEDAR_TLS_CERT") != set("CEDAR_TLS_KEY") {
+        findings.push(finding("error", None, "CEDAR_TLS_CERT and CEDAR_TLS_KEY must be set together"));
+    }
+    let tls = set("CEDAR_TLS_CERT") || set("CEDAR_TLS_KEY");
+    if set("CEDAR_ACME_DOMAINS") && tls {
+        findings.push(finding("error", None, "CEDAR_ACME_DOMAINS cannot be combined with CEDAR_TLS_CERT/CEDAR_TLS_KEY"));
+    }
+    if active("CEDAR_SPIFFE") && (tls || set("CEDAR_ACME_DOMAINS")) {
+        findings.push(finding(
+            "error",
+            None,
+            "CEDAR_SPIFFE cannot be combined with CEDAR_TLS_CERT/CEDAR_TLS_KEY or CEDAR_ACME_DOMAINS",
+        ));
+    }
+    if set("CEDAR_REPLICA_OF") {
+        for source in ["CEDAR_AVP_POLICY_STORE_ID", "CEDAR_LDAP_URL", "CEDAR_K8S_IMPORT", "CEDAR_DATA_DIR"] {
+            if active(source) {
+                findings.push(finding(
+                    "error",
+                    None,
+                    format!("CEDAR_REPLICA_OF cannot be combined with {}: replicas take their state from the leader", source),
+                ));
+            }
+        }
+    }
+    findings
+}
+
+/// The settings as a JSON Schema of the environment: an object from variable name to string.
+pub fn json_schema() -> Value {
+    let properties: Map<String, Value> = SETTINGS
+        .iter()
+        .map(|s| {
+            let mut property = json!({"type": "string", "description": s.description});
+            match s.kind {
+                Kind::Bool => property["enum"] = json!(["true", "false"]),
+                Kind::Count => property["pattern"] = json!("^[0-9]+$"),
+                Kind::Positive => property["pattern"] = json!("^0*[1-9][0-9]*$"),
+                Kind::Choice(choices) => property["enum"] = json!(choices),
+                Kind::LogLevel => property["enum"] = json!(LOG_LEVELS),
+                Kind::Time => property["format"] = json!("date-time"),
+                _ => {}
+            }
+            if let Some(default) = s.default {
+                property["default"] = json!(default);
+            }
+            if let Some(feature) = s.feature {
+                property["x-cedar-feature"] = json!(feature);
+            }
+            if let Some(requires) = s.requires {
+                property["x-cedar-requires"] = json!(requires);
+            }
+            (s.name.to_string(), property)
+        })
+        .collect();
+    json!({
+        "$schema": "https://json-schema.org/draft/2020-12/schema",
+        "title": "cedar-agent configuration",
+        "description": format!("Environment variables read by cedar-agent {}", env!("CARGO_PKG_VERSION")),
+        "type": "object",
+        "properties": properties,
+        "patternProperties": {"^CEDAR_": {"not": {}}},
+    })
+}
+
+/// Reads `KEY=VALUE` lines as in Docker's `--env-file` or a dotenv file: blank lines and `#`
+/// comments are skipped, and `export ` and matching quotes around the value are stripped.
+pub fn parse_env_file(src: &str) -> Result<BTreeMap<String, String>, String> {
+    let mut vars = BTreeMap::new();
+    for (n, line) in src.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = line.strip_prefix("export ").unwrap_or(line);
+        let Some((key, value)) = line.split_once('=') else {
+            return Err(format!("Line {}: expected KEY=VALUE", n + 1));
+        };
+        let value = value.trim();
+        let value = ['"', '\'']
+            .iter()
+            .find_map(|q| value.strip_prefix(*q).and_then(|v| v.strip_suffix(*q)))
+            .unwrap_or(value);
+        vars.insert(key.trim().to_string(), value.to_string());
+    }
+    Ok(vars)
+}
+
+#[derive(Serialize)]
+struct Report {
+    valid: bool,
+    findings: Vec<Finding>,
+}
+
+/// `cedar-agent config check [--env-file FILE]` and `cedar-agent config schema`.
+pub fn run(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    match args.first().map(String::as_str) {
+        Some("schema") => {
+            println!("{}", serde_json::to_string_pretty(&json_schema())?);
+            Ok(())
+        }
+        Some("check") => {
+            let vars = match args.get(1).map(String::as_str) {
+                Some("--env-file") => {
+                    let path = args.get(2).ok_or("Missing value for --env-file")?;
+                    let src = fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+                    parse_env_file(&src).map_err(|e| format!("Failed to parse {}: {}", path, e))?
+                }
+                Some(other) => return Err(format!("Unknown option {}", other).into()),
+                None => std::env::vars().collect(),
+            };
+            let findings = check(&vars);
+            let valid = !findings.iter().any(|f| f.severity == "error");
+            println!("{}", serde_json::to_string_pretty(&Report { valid, findings })?);
+            if !valid {
+                std::process::exit(1);
+            }
+            Ok(())
+        }
+        _ => Err("Usage: cedar-agent config check [--env-file FILE] | cedar-agent config schema".into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vars(pairs: &[(&str, &str)]) -> BTreeMap<String, String> {
+        pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+    }
+
+    fn messages(findings: &[Finding]) -> Vec<(Option<&str>, &str, &str)> {
+        findings
+            .iter()
+            .map(|f| (f.key.as_deref(), f.severity, f.message.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn schema_covers_every_setting_the_agent_reads() {
+        let src = include_str!("config.rs");
+        for part in src.split("(\"").skip(1) {
+            let key = &part[..part.find('"').unwrap()];
+            let is_name = key.chars().all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || c == '_');
+            if is_name && (key.starts_with("CEDAR_") || key == "BIND_ADDR" || key == "RUST_LOG") {
+                assert!(SETTINGS.iter().any(|s| s.name == key), "{} is missing from SETTINGS", key);
+            }
+        }
+    }
+
+    #[test]
+    fn flags_unknown_keys_with_suggestions() {
+        let findings = check(&vars(&[("CEDAR_TLS_CRET", "/c.pem"), ("CEDAR_NOTHING_LIKE_IT", "x"), ("HOME", "/root")]));
+        assert_eq!(
+            messages(&findings),
+            [
+                (Some("CEDAR_NOTHING_LIKE_IT"), "error", "Unknown setting"),
+                (Some("CEDAR_TLS_CRET"), "error", "Unknown setting (did you mean CEDAR_TLS_CERT?)"),
+            ]
+        );
+    }
+
+    #[test]
+    fn flags_malformed_values() {
+        let findings = check(&vars(&[
+            ("CEDAR_ACCEPTORS", "0"),
+            ("CEDAR_DEFAULT_DECISION", "ALLOW"),
+            ("CEDAR_ALLOW_CIDRS", "10.0.0.0/8,nonsense"),
+            ("CEDAR_SCIM_TOKEN", "t"),
+            ("CEDAR_SCIM_USER_TYPE", "not a type"),
+        ]));
+        let keys: Vec<_> = findings.iter().map(|f| f.key.as_deref().unwrap()).collect();
+        assert_eq!(keys, ["CEDAR_ACCEPTORS", "CEDAR_ALLOW_CIDRS", "CEDAR_SCIM_USER_TYPE"]);
+        assert!(findings.iter().all(|f| f.severity == "error"));
+    }
+
+    #[test]
+    fn flags_conflicts_and_settings_without_effect() {
+        let findings = check(&vars(&[("CEDAR_TLS_CERT", "/c.pem"), ("CEDAR_API_KEY_DAILY_QUOTA", "100")]));
+        assert_eq!(
+            messages(&findings),
+            [
+                (Some("CEDAR_API_KEY_DAILY_QUOTA"), "warning", "Has no effect without CEDAR_API_KEYS"),
+                (None, "error", "CEDAR_TLS_CERT and CEDAR_TLS_KEY must be set together"),
+            ]
+        );
+        assert!(check(&vars(&[("CEDAR_API_KEYS", "a=b"), ("CEDAR_API_KEY_DAILY_QUOTA", "100")])).is_empty());
+        assert!(check(&vars(&[("CEDAR_API_KEYS_FILE", "/run/keys"), ("CEDAR_API_KEY_DAILY_QUOTA", "100")])).is_empty());
+        assert_eq!(
+            messages(&check(&vars(&[("CEDAR_API_KEYS", "a=b"), ("CEDAR_API_KEYS_FILE", "/run/keys")]))),
+            [(None, "error", "CEDAR_API_KEYS and CEDAR_API_KEYS_FILE cannot both be set")]
+        );
+
+        let replica = [("CEDAR_REPLICATION_TOKEN", "t"), ("CEDAR_REPLICA_OF", "http://leader:8181")];
+        assert!(check(&vars(&replica)).is_empty());
+        let findings = check(&vars(&[replica[0], replica[1], ("CEDAR_K8S_IMPORT", "true")]));
+        assert!(messages(&findings).contains(&(
+            None,
+            "error",
+            "CEDAR_REPLICA_OF cannot be combined with CEDAR_K8S_IMPORT: replicas take their state from the leader"
+        )));
+    }
+
+    #[test]
+    fn parses_env_files() {
+        let vars = parse_env_file("# comment\n\nexport CEDAR_A=\"x y\"\nCEDAR_B='1'\nCEDAR_C=a=b\n").unwrap();
+        assert_eq!(vars, super::tests::vars(&[("CEDAR_A", "x y"), ("CEDAR_B", "1"), ("CEDAR_C", "a=b")]));
+        assert!(parse_env_file("CEDAR_A\n").is_err());
+    }
+}
@@ -0,0 +1,409 @@
+use crate::schema::AttributeInfo;
+use cedar_policy::{EntityUid, Policy};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+/// The part of a request (or decision log record) the inference looks at.
+#[derive(Debug, Deserialize)]
+pub struct Sample {
+    pub action: String,
+    #[serde(default)]
+    pub context: Option<Value>,
+}
+
+/// A context attribute as sent: the types seen for it and in how many samples.
+#[derive(Debug, Serialize, PartialEq)]
+pub struct ObservedAttribute {
+    pub name: String,
+    pub types: Vec<String>,
+    pub seen: usize,
+    /// Its type in the schema, when the schema declares it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub declared: Option<String>,
+}
+
+/// A difference between what clients send and what the schema or policies expect:
+/// `undeclared` (sent, not in the schema), `type_mismatch`, `sometimes_missing` (required, but
+/// left out of some samples) or `never_sent` (declared or referenced by a policy, never sent).
+#[derive(Debug, Serialize, PartialEq)]
+pub struct Issue {
+    pub attribute: String,
+    pub issue: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub declared: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub observed: Vec<String>,
+    /// Policies that read the attribute.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub policies: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ActionUsage {
+    pub action: String,
+    pub samples: usize,
+    /// Whether the schema declares the action; without that its context is only inferred.
+    pub in_schema: bool,
+    pub attributes: Vec<ObservedAttribute>,
+    pub issues: Vec<Issue>,
+}
+
+/// Response of `POST /v1/schema/context-usage`.
+#[derive(Debug, Serialize)]
+pub struct ContextUsage {
+    pub samples: usize,
+    /// Samples left out because their action is not a valid entity UID.
+    #[serde(skip_serializing_if = "is_zero")]
+    pub skipped: usize,
+    pub actions: Vec<ActionUsage>,
+}
+
+fn is_zero(n: &usize) -> bool {
+    *n == 0
+}
+
+fn join(prefix: &str, name: &str) -> String {
+    if prefix.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}.{}", prefix, name)
+    }
+}
+
+/// The Cedar type of a context value in its JSON form, rendered like the schema introspection
+/// renders declared types. Entity references give their type name, extension values their type.
+pub fn value_type(value: &Value) -> String {
+    match value {
+        Value::Null => "Null".to_string(),
+        Value::Bool(_) => "Boolean".to_string(),
+        Value::Number(n) if n.is_i64() => "Long".to_string(),
+        Value::Number(_) => "Number".to_string(),
+        Value::String(_) => "String".to_string(),
+        Value::Array(items) => {
+            let types: BTreeSet<String> = items.iter().map(value_type).collect();
+            match types.len() {
+                0 => "Set".to_string(),
+                _ => format!("Set<{}>", types.into_iter().collect::<Vec<_>>().join(" | ")),
+            }
+        }
+        Value::Object(fields) => {
+            if let Some(ty) = fields.get("__entity").and_then(|e| e.get("type")).and_then(Value::as_str) {
+                return ty.to_string();
+            }
+            if let Some(function) = fields.get("__extn").and_then(|e| e.get("fn")).and_then(Value::as_str) {
+                return match function {
+                    "ip" => "ipaddr".to_string(),
+                    other => other.to_string(),
+                };
+            }
+            match (fields.len(), fields.get("type").and_then(Value::as_str), fields.get("id")) {
+                (2, Some(ty), Some(Value::String(_))) => ty.to_string(),
+                _ => "Record".to_string(),
+            }
+        }
+    }
+}
+
+/// Whether a value of the observed type is accepted where the declared type is expected.
+/// Extension types can be sent as strings, and entity types may be declared unqualified
+/// inside their namespace.
+fn compatible(observed: &str, declared: &str) -> bool {
+    if observed == declared {
+        return true;
+    }
+    if let (Some(observed), Some(declared)) = (observed.strip_prefix("Set<"), declared.strip_prefix("Set<")) {
+        let declared = declared.trim_end_matches('>');
+        return observed.trim_end_matches('>').split(" | ").all(|o| compatible(o, declared));
+    }
+    match observed {
+        "Set" => declared.starts_with("Set<"),
+        "String" => matches!(declared, "ipaddr" | "decimal" | "datetime" | "duration"),
+        _ => !declared.contains("::") && observed.ends_with(&format!("::{}", declared)),
+    }
+}
+
+/// Types seen for each attribute path, and in how many samples it was sent.
+type Seen = BTreeMap<String, (BTreeSet<String>, usize)>;
+
+/// Records each attribute of `context`, nested record attributes included, with its type.
+fn observe(context: &Value, prefix: &str, seen: &mut Seen) {
+    let Some(fields) = context.as_object() else {
+        return;
+    };
+    for (name, value) in fields {
+        let path = join(prefix, name);
+        let ty = value_type(value);
+        if ty == "Record" {
+            observe(value, &path, seen);
+        }
+        let entry = seen.entry(path).or_default();
+        entry.0.insert(ty);
+        entry.1 += 1;
+    }
+}
+
+/// Declared context attributes by path, with their type and whether they are required.
+fn declared_paths(attributes: &[AttributeInfo], prefix: &str, paths: &mut BTreeMap<String, (String, bool)>) {
+    for attribute in attributes {
+        let path = join(prefix, &attribute.name);
+        declared_paths(&attribute.attributes, &path, paths);
+        paths.insert(path, (attribute.ty.clone(), attribute.required));
+    }
+}
+
+/// The path under `context` that an expression reads, if it is one (`context.a.b`).
+fn context_path(expr: &Value) -> Option<String> {
+    if expr.get("Var").and_then(Value::as_str) == Some("context") {
+        return Some(String::new());
+    }
+    let access = expr.get(".")?;
+    Some(join(&context_path(&access["left"])?, access["attr"].as_str()?))
+}
+
+fn collect_references(expr: &Value, paths: &mut BTreeSet<String>) {
+    match expr {
+        Value::Object(fields) => {
+            for op in [".", "has"] {
+                if let Some(access) = fields.get(op) {
+                    if let (Some(parent), Some(attr)) = (context_path(&access["left"]), access["attr"].as_str()) {
+                        paths.insert(join(&parent, attr));
+                    }
+                }
+            }
+            fields.values().for_each(|v| collect_references(v, paths));
+        }
+        Value::Array(items) => items.iter().for_each(|v| collect_references(v, paths)),
+        _ => {}
+    }
+}
+
+/// Context attributes a policy reads or tests with `has`, by path.
+pub fn references(policy: &Policy) -> BTreeSet<String> {
+    let mut paths = BTreeSet::new();
+    if let Ok(est) = policy.to_json() {
+        collect_references(&est["conditions"], &mut paths);
+    }
+    paths
+}
+
+/// Whether the attribute's parent record is itself reported as never sent, which covers it.
+fn covered(path: &str, never_sent: &BTreeSet<String>) -> bool {
+    path.rsplit_once('.').is_some_and(|(parent, _)| never_sent.contains(parent) || covered(parent, never_sent))
+}
+
+/// Infers the context each action is sent with and compares it with the schema's declared
+/// context (`declared`, keyed by action UID; `None` without a schema) and with the attributes
+/// the policies that may apply to the action read (`referencing` maps them to policy IDs).
+pub fn analyze(
+    samples: Vec<Sample>,
+    declared: Option<&HashMap<String, Vec<AttributeInfo>>>,
+    referencing: impl Fn(&EntityUid) -> BTreeMap<String, Vec<String>>,
+) -> ContextUsage {
+    let total = samples.len();
+    let mut skipped = 0;
+    let mut by_action: BTreeMap<String, (EntityUid, usize, Seen)> = BTreeMap::new();
+    for sample in samples {
+        let Ok(action) = sample.action.parse::<EntityUid>() else {
+            skipped += 1;
+            continue;
+        };
+        let entry = by_action.entry(action.to_string()).or_insert_with(|| (action, 0, BTreeMap::new()));
+        entry.1 += 1;
+        if let Some(ref context) = sample.context {
+            observe(context, "", &mut entry.2);
+        }
+    }
+
+    let actions = by_action
+        .into_iter()
+        .map(|(name, (action, samples, seen))| {
+            let schema_context = declared.and_then(|declared| declared.get(&name));
+            let mut expected = BTreeMap::new();
+            if let Some(attributes) = schema_context {
+                declared_paths(attributes, "", &mut expected);
+            }
+            let readers = referencing(&action);
+            let mut issues = Vec::new();
+
+            let attributes = seen
+                .iter()
+                .map(|(path, (types, count))| {
+                    let types: Vec<String> = types.iter().cloned().collect();
+                    let declared = expected.get(path);
+                    match declared {
+                        None if schema_context.is_some() => issues.push(Issue {
+                            attribute: path.clone(),
+                            issue: "undeclared",
+                            declared: None,
+                            observed: types.clone(),
+                            policies: readers.get(path).cloned().unwrap_or_default(),
+                        }),
+                        Some((ty, _)) if !types.iter().all(|t| compatible(t, ty)) => issues.push(Issue {
+                            attribute: path.clone(),
+                            issue: "type_mismatch",
+                            declared: Some(ty.clone()),
+                            observed: types.clone(),
+                            policies: readers.get(path).cloned().unwrap_or_default(),
+                        }),
+                        Some((ty, true)) if *count < samples => issues.push(Issue {
+                            attribute: path.clone(),
+                            issue: "sometimes_missing",
+                            declared: Some(ty.clone()),
+                            observed: types.clone(),
+                            policies: readers.get(path).cloned().unwrap_or_default(),
+                        }),
+                        _ => {}
+                    }
+                    ObservedAttribute {
+                        name: path.clone(),
+                        types,
+                        seen: *count,
+                        declared: declared.map(|(ty, _)| ty.clone()),
+                    }
+                })
+                .collect();
+
+            let never_sent: BTreeSet<String> = expected
+                .keys()
+                .chain(readers.keys())
+                .filter(|path| !seen.contains_key(*path))
+                .cloned()
+                .collect();
+            for path in never_sent.iter().filter(|path| !covered(path, &never_sent)) {
+                // Policies reading attributes nested under it count too, as those are not listed
+                let nested = format!("{}.", path);
+                let policies: BTreeSet<String> = readers
+                    .iter()
+                    .filter(|(reference, _)| *reference == path || reference.starts_with(&nested))
+                    .flat_map(|(_, policies)| policies.iter().cloned())
+                    .collect();
+                issues.push(Issue {
+                    attribute: path.clone(),
+                    issue: "never_sent",
+                    declared: expected.get(path).map(|(ty, _)| ty.clone()),
+                    observed: Vec::new(),
+                    policies: policies.into_iter().collect(),
+                });
+            }
+            issues.sort_by(|a, b| a.attribute.cmp(&b.attribute));
+
+            ActionUsage {
+                action: name,
+                samples,
+                in_schema: schema_context.is_some(),
+                attributes,
+                issues,
+            }
+        })
+        .collect();
+
+    ContextUsage {
+        samples: total,
+        skipped,
+        actions,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn sample(action: &str, context: Value) -> Sample {
+        Sample {
+            action: action.to_string(),
+            context: Some(context),
+        }
+    }
+
+    fn attribute(name: &str, ty: &str, required: bool, attributes: Vec<AttributeInfo>) -> AttributeInfo {
+        AttributeInfo {
+            name: name.to_string(),
+            ty: ty.to_string(),
+            required,
+            attributes,
+        }
+    }
+
+    #[test]
+    fn renders_value_types() {
+        assert_eq!(value_type(&json!(3)), "Long");
+        assert_eq!(value_type(&json!(["a", 1])), "Set<Long | String>");
+        assert_eq!(value_type(&json!([])), "Set");
+        assert_eq!(value_type(&json!({"__entity": {"type": "App::User", "id": "a"}})), "App::User");
+        assert_eq!(value_type(&json!({"type": "User", "id": "a"})), "User");
+        assert_eq!(value_type(&json!({"__extn": {"fn": "ip", "arg": "10.0.0.1"}})), "ipaddr");
+        assert_eq!(value_type(&json!({"mfa": true})), "Record");
+
+        assert!(compatible("String", "ipaddr"));
+        assert!(compatible("Set", "Set<String>"));
+        assert!(compatible("Set<App::User>", "Set<User>"));
+        assert!(!compatible("Set<Long | String>", "Set<String>"));
+        assert!(!compatible("Long", "Boolean"));
+    }
+
+    #[test]
+    fn finds_context_references_in_policies() {
+        let policy = Policy::parse(
+            None,
+            r#"permit(principal, action, resource) when { context.device.trusted && context has mfa }
+               unless { context["risk"] > 5 && principal.level > 2 };"#,
+        )
+        .unwrap();
+        let paths: Vec<String> = references(&policy).into_iter().collect();
+        assert_eq!(paths, vec!["device", "device.trusted", "mfa", "risk"]);
+    }
+
+    #[test]
+    fn diffs_samples_against_schema_and_policies() {
+        let view = r#"Action::"view""#;
+        let declared = HashMap::from([(
+            view.to_string(),
+            vec![
+                attribute("ip", "ipaddr", true, vec![]),
+                attribute("mfa", "Boolean", true, vec![]),
+                attribute("level", "Long", false, vec![]),
+                attribute("device", "Record", false, vec![attribute("trusted", "Boolean", true, vec![])]),
+            ],
+        )]);
+        let samples = vec![
+            sample(view, json!({"ip": "10.0.0.1", "mfa": true, "tenant": "acme"})),
+            sample(view, json!({"ip": "10.0.0.2", "level": "high"})),
+            sample("not a uid", json!({})),
+        ];
+        let usage = analyze(samples, Some(&declared), |_| {
+            BTreeMap::from([
+                ("risk".to_string(), vec!["risky".to_string()]),
+                ("device.trusted".to_string(), vec!["devices".to_string()]),
+            ])
+        });
+
+        assert_eq!((usage.samples, usage.skipped), (3, 1));
+        let action = &usage.actions[0];
+        assert_eq!((action.action.as_str(), action.samples, action.in_schema), (view, 2, true));
+        assert_eq!(
+            action.attributes[0],
+            ObservedAttribute {
+                name: "ip".to_string(),
+                types: vec!["String".to_string()],
+                seen: 2,
+                declared: Some("ipaddr".to_string()),
+            }
+        );
+        let issues: Vec<(&str, &str)> = action.issues.iter().map(|i| (i.attribute.as_str(), i.issue)).collect();
+        assert_eq!(
+            issues,
+            vec![
+                ("device", "never_sent"),
+                ("level", "type_mismatch"),
+                ("mfa", "sometimes_missing"),
+                ("risk", "never_sent"),
+                ("tenant", "undeclared"),
+            ]
+        );
+        assert_eq!(action.issues[0].policies, vec!["devices"]);
+        assert_eq!(action.issues[3].policies, vec!["risky"]);
+        assert_eq!(action.issues[3].declared, None);
+    }
+}
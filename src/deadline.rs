@@ -0,0 +1,131 @@
+use chrono::{DateTime, Utc};
+use hyper::HeaderMap;
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+/// Absolute deadline of a request: an RFC 3339 time or Unix milliseconds.
+pub const DEADLINE_HEADER: &str = "x-request-deadline";
+/// Time left for a request, in gRPC's format: up to 8 digits and a unit (`250m`, `2S`).
+pub const TIMEOUT_HEADER: &str = "grpc-timeout";
+
+tokio::task_local! {
+    /// When the caller stops waiting for the request being served, if it said.
+    static DEADLINE: Option<Instant>;
+}
+
+/// Parses a `grpc-timeout` value.
+pub fn parse_timeout(value: &str) -> Result<Duration, String> {
+    let invalid = || format!("Invalid {} '{}' (expected up to 8 digits and a unit: H, M, S, m, u or n)", TIMEOUT_HEADER, value);
+    let unit = value.chars().last().ok_or_else(invalid)?;
+    let digits = &value[..value.len() - unit.len_utf8()];
+    if digits.is_empty() || digits.len() > 8 || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(invalid());
+    }
+    let n: u64 = digits.parse().map_err(|_| invalid())?;
+    match unit {
+        'H' => Ok(Duration::from_secs(n * 3600)),
+        'M' => Ok(Duration::from_secs(n * 60)),
+        'S' => Ok(Duration::from_secs(n)),
+        'm' => Ok(Duration::from_millis(n)),
+        'u' => Ok(Duration::from_micros(n)),
+        'n' => Ok(Duration::from_nanos(n)),
+        _ => Err(invalid()),
+    }
+}
+
+/// Time left until an `X-Request-Deadline` value, as of `now`; none once it has passed.
+pub fn parse_deadline(value: &str, now: DateTime<Utc>) -> Result<Duration, String> {
+    let deadline = match value.parse::<i64>() {
+        Ok(millis) => DateTime::from_timestamp_millis(millis),
+        Err(_) => DateTime::parse_from_rfc3339(value).ok().map(|time| time.with_timezone(&Utc)),
+    }
+    .ok_or_else(|| format!("Invalid {} '{}' (expected an RFC 3339 time or Unix milliseconds)", DEADLINE_HEADER, value))?;
+    Ok((deadline - now).to_std().unwrap_or_default())
+}
+
+/// The deadline a request that `arrived` sets with either header, the earlier if both.
+pub fn parse(headers: &HeaderMap, arrived: Instant) -> Result<Option<Instant>, String> {
+    let header = |name: &str| {
+        headers
+            .get(name)
+            .map(|value| value.to_str().map_err(|_| format!("Invalid {} header", name)))
+            .transpose()
+    };
+    let deadline = header(DEADLINE_HEADER)?
+        .map(|value| parse_deadline(value.trim(), Utc::now()))
+        .transpose()?;
+    let timeout = header(TIMEOUT_HEADER)?.map(|value| parse_timeout(value.trim())).transpose()?;
+    Ok(deadline.into_iter().chain(timeout).min().map(|left| arrived + left))
+}
+
+/// Runs `f`, the serving of a request with `deadline`, so the waits it does are bounded by it.
+pub async fn scope<F: Future>(deadline: Option<Instant>, f: F) -> F::Output {
+    DEADLINE.scope(deadline, f).await
+}
+
+/// Time left before the deadline of the request being served, if it has one.
+pub fn remaining() -> Option<Duration> {
+    DEADLINE
+        .try_with(|deadline| deadline.map(|at| at.saturating_duration_since(Instant::now())))
+        .ok()
+        .flatten()
+}
+
+/// Whether the request being served is past its deadline.
+pub fn expired() -> bool {
+    remaining().is_some_and(|left| left.is_zero())
+}
+
+/// `timeout`, shortened to the time left before the deadline of the request being served.
+pub fn bound(timeout: Duration) -> Duration {
+    remaining().map_or(timeout, |left| left.min(timeout))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_grpc_timeouts() {
+        assert_eq!(parse_timeout("250m"), Ok(Duration::from_millis(250)));
+        assert_eq!(parse_timeout("2S"), Ok(Duration::from_secs(2)));
+        assert_eq!(parse_timeout("1H"), Ok(Duration::from_secs(3600)));
+        assert_eq!(parse_timeout("1500u"), Ok(Duration::from_micros(1500)));
+        for invalid in ["", "m", "250", "250ms", "123456789m", "-5m", "2s"] {
+            assert!(parse_timeout(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn parses_deadlines() {
+        let now = DateTime::parse_from_rfc3339("2026-10-15T12:00:00Z").unwrap().with_timezone(&Utc);
+        assert_eq!(parse_deadline("2026-10-15T12:00:00.250Z", now), Ok(Duration::from_millis(250)));
+        assert_eq!(parse_deadline("2026-10-15T14:00:01+02:00", now), Ok(Duration::from_secs(1)));
+        let millis = now.timestamp_millis() + 40;
+        assert_eq!(parse_deadline(&millis.to_string(), now), Ok(Duration::from_millis(40)));
+        assert_eq!(parse_deadline("2026-10-15T11:59:59Z", now), Ok(Duration::ZERO));
+        assert!(parse_deadline("tomorrow", now).is_err());
+    }
+
+    #[tokio::test]
+    async fn bounds_waits_by_the_earlier_deadline() {
+        let mut headers = HeaderMap::new();
+        let arrived = Instant::now();
+        assert_eq!(parse(&headers, arrived), Ok(None));
+        headers.insert(TIMEOUT_HEADER, "200m".parse().unwrap());
+        headers.insert(DEADLINE_HEADER, (Utc::now().timestamp_millis() + 60_000).to_string().parse().unwrap());
+        let deadline = parse(&headers, arrived).unwrap();
+        assert_eq!(deadline, Some(arrived + Duration::from_millis(200)));
+        headers.insert(TIMEOUT_HEADER, "soon".parse().unwrap());
+        assert!(parse(&headers, arrived).is_err());
+
+        assert_eq!(bound(Duration::from_secs(5)), Duration::from_secs(5));
+        scope(deadline, async {
+            assert!(bound(Duration::from_secs(5)) <= Duration::from_millis(200));
+            assert_eq!(bound(Duration::from_millis(10)), Duration::from_millis(10));
+            assert!(!expired());
+        })
+        .await;
+        scope(Some(arrived), async { assert!(expired()) }).await;
+    }
+}
@@ -0,0 +1,212 @@
+use crate::{error_response, json_response};
+use chrono::{SecondsFormat, Utc};
+use hyper::{Body, Response, StatusCode};
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::broadcast;
+
+pub const PATH: &str = "/debug/decisions";
+pub const STREAM_PATH: &str = "/debug/decisions/stream";
+/// Decisions a slow stream may fall behind by before it skips ahead.
+const STREAM_BACKLOG: usize = 256;
+/// How long a stream stays quiet before a comment is sent to find out if the client is gone.
+const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(15);
+
+/// A decision with everything that went into it: the request as sent, minus any token, and the
+/// response in full, or the error when it could not be evaluated.
+#[derive(Debug, Serialize)]
+pub struct Record {
+    pub seq: u64,
+    pub time: String,
+    pub duration_ms: f64,
+    pub principal: String,
+    pub action: String,
+    pub resource: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub context: Option<serde_json::Value>,
+    pub entities: serde_json::Value,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub response: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl Record {
+    /// `Allow` or `Deny`, or `error` when the request could not be evaluated.
+    fn decision(&self) -> &str {
+        self.response
+            .as_ref()
+            .and_then(|response| response["decision"].as_str())
+            .unwrap_or("error")
+    }
+}
+
+/// The last decisions evaluated, for debugging an instance that ships no logs. The oldest is
+/// dropped when it is full. Each decision is also passed on to the streams following along.
+pub struct Ring {
+    records: Mutex<(u64, VecDeque<Arc<Record>>)>,
+    capacity: usize,
+    live: broadcast::Sender<Arc<Record>>,
+}
+
+impl Ring {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            records: Mutex::new((0, VecDeque::with_capacity(capacity))),
+            capacity,
+            live: broadcast::channel(STREAM_BACKLOG).0,
+        }
+    }
+
+    /// Adds a decision, numbering and timestamping it.
+    pub fn record(&self, mut record: Record) {
+        let mut records = self.records.lock().unwrap();
+        records.0 += 1;
+        record.seq = records.0;
+        record.time = Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true);
+        if records.1.len() == self.capacity {
+            records.1.pop_front();
+        }
+        let record = Arc::new(record);
+        records.1.push_back(Arc::clone(&record));
+        // Sent under the lock, so streams see decisions in order; it fails only with no streams
+        let _ = self.live.send(record);
+    }
+
+    /// Up to `limit` of the decisions kept, newest first.
+    pub fn recent(&self, limit: usize) -> Vec<Arc<Record>> {
+        let records = self.records.lock().unwrap();
+        records.1.iter().rev().take(limit).cloned().collect()
+    }
+}
+
+/// Serves `GET /debug/decisions`, all decisions kept unless `limit` asks for fewer.
+pub fn handle(ring: &Ring, params: &HashMap<String, String>) -> Response<Body> {
+    let limit = match params.get("limit").map(|limit| limit.parse::<usize>()) {
+        None => ring.capacity,
+        Some(Ok(limit)) if limit > 0 => limit,
+        Some(_) => return error_response(StatusCode::BAD_REQUEST, "Invalid limit: expected a positive number"),
+    };
+    json_response(StatusCode::OK, &ring.recent(limit))
+}
+
+/// Which decisions a stream passes on; unset fields match anything.
+#[derive(Debug, Default)]
+struct Filter {
+    principal: Option<String>,
+    action: Option<String>,
+    resource: Option<String>,
+    decision: Option<String>,
+}
+
+impl Filter {
+    fn parse(params: &HashMap<String, String>) -> Result<Self, String> {
+        let decision = match params.get("decision").map(String::as_str) {
+            None => None,
+            Some(decision @ ("Allow" | "Deny" | "error")) => Some(decision.to_string()),
+            Some(other) => return Err(format!("Invalid decision '{}' (expected Allow, Deny or error)", other)),
+        };
+        Ok(Self {
+            principal: params.get("principal").cloned(),
+            action: params.get("action").cloned(),
+            resource: params.get("resource").cloned(),
+            decision,
+        })
+    }
+
+    fn matches(&self, record: &Record) -> bool {
+        let equal = |wanted: &Option<String>, value: &str| wanted.as_deref().is_none_or(|wanted| wanted == value);
+        equal(&self.principal, &record.principal)
+            && equal(&self.action, &record.action)
+            && equal(&self.resource, &record.resource)
+            && equal(&self.decision, record.decision())
+    }
+}
+
+/// Serves `GET /debug/decisions/stream`: server-sent events, one per decision matching the
+/// `principal`, `action`, `resource` and `decision` parameters, as they are made.
+pub fn stream(ring: &Ring, params: &HashMap<String, String>) -> Response<Body> {
+    let filter = match Filter::parse(params) {
+        Ok(filter) => filter,
+        Err(e) => return error_response(StatusCode::BAD_REQUEST, e),
+    };
+    let mut decisions = ring.live.subscribe();
+    let (mut sender, body) = Body::channel();
+    tokio::spawn(async move {
+        // Only a failed send tells that the client went away, so a comment is sent when no
+        // decision was for a while.
+        loop {
+            let event = match tokio::time::timeout(KEEPALIVE_INTERVAL, decisions.recv()).await {
+                Ok(Ok(record)) if filter.matches(&record) => match serde_json::to_string(&*record) {
+                    Ok(json) => format!("id: {}\ndata: {}\n\n", record.seq, json),
+                    Err(_) => continue,
+                },
+                Ok(Ok(_)) => continue,
+                Ok(Err(broadcast::error::RecvError::Lagged(skipped))) => {
+                    format!(": {} decisions skipped\n\n", skipped)
+                }
+                Ok(Err(broadcast::error::RecvError::Closed)) => return,
+                Err(_) => ": keepalive\n\n".to_string(),
+            };
+            if sender.send_data(event.into()).await.is_err() {
+                return;
+            }
+        }
+    });
+    Response::builder()
+        .header("content-type", "text/event-stream")
+        .header("cache-control", "no-cache")
+        .body(body)
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(principal: &str) -> Record {
+        Record {
+            seq: 0,
+            time: String::new(),
+            duration_ms: 0.1,
+            principal: principal.to_string(),
+            action: r#"Action::"view""#.to_string(),
+            resource: r#"Doc::"d1""#.to_string(),
+            context: None,
+            entities: serde_json::json!([]),
+            response: None,
+            error: Some("unknown entity type".to_string()),
+        }
+    }
+
+    #[test]
+    fn keeps_the_last_decisions() {
+        let ring = Ring::new(2);
+        for principal in ["a", "b", "c"] {
+            ring.record(record(principal));
+        }
+        let kept: Vec<u64> = ring.recent(10).iter().map(|record| record.seq).collect();
+        assert_eq!(kept, [3, 2]);
+        assert_eq!(ring.recent(1)[0].principal, "c");
+        assert!(!ring.recent(1)[0].time.is_empty());
+    }
+
+    #[test]
+    fn filters_streamed_decisions() {
+        let params = |pairs: &[(&str, &str)]| -> HashMap<String, String> {
+            pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+        };
+        let mut allowed = record("alice");
+        allowed.error = None;
+        allowed.response = Some(serde_json::json!({"decision": "Allow"}));
+        let failed = record("bob");
+
+        let filter = Filter::parse(&params(&[("principal", "alice")])).unwrap();
+        assert!(filter.matches(&allowed) && !filter.matches(&failed));
+        let filter = Filter::parse(&params(&[("decision", "error"), ("action", r#"Action::"view""#)])).unwrap();
+        assert!(!filter.matches(&allowed) && filter.matches(&failed));
+        assert!(Filter::parse(&params(&[("decision", "deny")])).is_err());
+    }
+}
@@ -0,0 +1,190 @@
+use chrono::{DateTime, Duration, SecondsFormat, Utc};
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+
+pub const PATH: &str = "/v1/decisions";
+/// Decisions returned by a query unless it asks for fewer, and at most.
+const DEFAULT_LIMIT: usize = 100;
+const MAX_LIMIT: usize = 1000;
+
+/// A decision as the index keeps it: who asked for what, and why it came out as it did. Contexts
+/// and entities are left out to keep the index small.
+#[derive(Debug, Clone, Serialize)]
+pub struct Entry {
+    pub seq: u64,
+    pub time: String,
+    #[serde(skip)]
+    pub at: DateTime<Utc>,
+    pub principal: String,
+    pub action: String,
+    pub resource: String,
+    pub decision: String,
+    pub reasons: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub errors: Vec<String>,
+    /// For a denial, `forbid` when a forbid policy held, else `default`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub denied_by: Option<&'static str>,
+}
+
+/// Which decisions `GET /v1/decisions` returns; unset fields match anything.
+#[derive(Debug, Default, PartialEq)]
+pub struct Query {
+    principal: Option<String>,
+    action: Option<String>,
+    resource: Option<String>,
+    decision: Option<String>,
+    since: Option<DateTime<Utc>>,
+    until: Option<DateTime<Utc>>,
+    limit: usize,
+}
+
+impl Query {
+    /// Reads the query parameters; `since` and `until` take an RFC 3339 timestamp or a time
+    /// ago such as `5m`.
+    pub fn parse(params: &HashMap<String, String>, now: DateTime<Utc>) -> Result<Self, String> {
+        let time = |name: &str| {
+            params
+                .get(name)
+                .map(|value| parse_time(value, now).map_err(|e| format!("Invalid {}: {}", name, e)))
+                .transpose()
+        };
+        let decision = match params.get("decision").map(String::as_str) {
+            None => None,
+            Some(decision @ ("Allow" | "Deny")) => Some(decision.to_string()),
+            Some(other) => return Err(format!("Invalid decision '{}' (expected Allow or Deny)", other)),
+        };
+        let limit = match params.get("limit").map(|limit| limit.parse::<usize>()) {
+            None => DEFAULT_LIMIT,
+            Some(Ok(limit)) if limit > 0 => limit.min(MAX_LIMIT),
+            Some(_) => return Err("Invalid limit: expected a positive number".to_string()),
+        };
+        Ok(Self {
+            principal: params.get("principal").cloned(),
+            action: params.get("action").cloned(),
+            resource: params.get("resource").cloned(),
+            decision,
+            since: time("since")?,
+            until: time("until")?,
+            limit,
+        })
+    }
+
+    fn matches(&self, entry: &Entry) -> bool {
+        let equal = |wanted: &Option<String>, value: &str| wanted.as_deref().is_none_or(|wanted| wanted == value);
+        equal(&self.principal, &entry.principal)
+            && equal(&self.action, &entry.action)
+            && equal(&self.resource, &entry.resource)
+            && equal(&self.decision, &entry.decision)
+            && self.since.is_none_or(|since| entry.at >= since)
+            && self.until.is_none_or(|until| entry.at < until)
+    }
+}
+
+/// An RFC 3339 timestamp, or a time `now` minus a duration such as `90s`, `5m` or `2h`.
+fn parse_time(value: &str, now: DateTime<Utc>) -> Result<DateTime<Utc>, String> {
+    if let Ok(time) = crate::clock::parse_time(value) {
+        return Ok(time);
+    }
+    let (number, unit) = value.split_at(value.find(|c: char| !c.is_ascii_digit()).unwrap_or(value.len()));
+    let n: i64 = number
+        .parse()
+        .map_err(|_| format!("expected an RFC 3339 timestamp or a time ago such as 5m, got '{}'", value))?;
+    let ago = match unit {
+        "s" => Duration::seconds(n),
+        "m" => Duration::minutes(n),
+        "h" => Duration::hours(n),
+        _ => return Err(format!("unknown unit '{}' (expected s, m or h)", unit)),
+    };
+    Ok(now - ago)
+}
+
+/// The most recent decisions, kept in memory so they can be looked up without the decision
+/// log. The oldest is dropped when it is full.
+pub struct DecisionIndex {
+    entries: Mutex<(u64, VecDeque<Entry>)>,
+    capacity: usize,
+}
+
+impl DecisionIndex {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            entries: Mutex::new((0, VecDeque::with_capacity(capacity.min(1024)))),
+            capacity,
+        }
+    }
+
+    /// Adds a decision, numbering it after the one before.
+    pub fn record(&self, mut entry: Entry) {
+        let mut entries = self.entries.lock().unwrap();
+        entries.0 += 1;
+        entry.seq = entries.0;
+        entry.time = entry.at.to_rfc3339_opts(SecondsFormat::Millis, true);
+        if entries.1.len() == self.capacity {
+            entries.1.pop_front();
+        }
+        entries.1.push_back(entry);
+    }
+
+    /// Decisions matching `query`, newest first.
+    pub fn query(&self, query: &Query) -> Vec<Entry> {
+        let entries = self.entries.lock().unwrap();
+        entries.1.iter().rev().filter(|entry| query.matches(entry)).take(query.limit).cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(principal: &str, decision: &str, at: DateTime<Utc>) -> Entry {
+        Entry {
+            seq: 0,
+            time: String::new(),
+            at,
+            principal: principal.to_string(),
+            action: r#"Action::"view""#.to_string(),
+            resource: r#"Doc::"d1""#.to_string(),
+            decision: decision.to_string(),
+            reasons: Vec::new(),
+            errors: Vec::new(),
+            denied_by: None,
+        }
+    }
+
+    fn params(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+    }
+
+    #[test]
+    fn finds_recent_decisions_newest_first() {
+        let now = crate::clock::parse_time("2026-10-15T12:00:00Z").unwrap();
+        let index = DecisionIndex::new(3);
+        index.record(entry("alice", "Allow", now - Duration::minutes(30)));
+        index.record(entry("alice", "Deny", now - Duration::minutes(10)));
+        index.record(entry("bob", "Deny", now - Duration::minutes(4)));
+        index.record(entry("alice", "Deny", now - Duration::minutes(1)));
+
+        let seqs = |pairs: &[(&str, &str)]| -> Vec<u64> {
+            let query = Query::parse(&params(pairs), now).unwrap();
+            index.query(&query).iter().map(|entry| entry.seq).collect()
+        };
+        // The first decision fell out of the index
+        assert_eq!(seqs(&[]), [4, 3, 2]);
+        assert_eq!(seqs(&[("principal", "alice"), ("decision", "Deny")]), [4, 2]);
+        assert_eq!(seqs(&[("since", "5m")]), [4, 3]);
+        assert_eq!(seqs(&[("until", "2026-10-15T11:58:00Z")]), [3, 2]);
+        assert_eq!(seqs(&[("limit", "1")]), [4]);
+    }
+
+    #[test]
+    fn rejects_invalid_parameters() {
+        let now = Utc::now();
+        assert!(Query::parse(&params(&[("decision", "deny")]), now).is_err());
+        assert!(Query::parse(&params(&[("since", "5 minutes")]), now).is_err());
+        assert!(Query::parse(&params(&[("limit", "0")]), now).is_err());
+        assert_eq!(Query::parse(&params(&[("limit", "5000")]), now).unwrap().limit, MAX_LIMIT);
+        assert_eq!(parse_time("2h", now).unwrap(), now - Duration::hours(2));
+    }
+}
@@ -0,0 +1,578 @@
+use crate::digest;
+use chrono::{SecondsFormat, Utc};
+use hmac::{Hmac, Mac};
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::collections::VecDeque;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{self, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+
+/// `prev_hash` of the first record in a new log.
+pub const GENESIS: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+const HASH_FIELD: &str = ",\"hash\":\"";
+
+/// Lines waiting for the writer thread before `append` waits for it to catch up.
+const QUEUE: usize = 10_000;
+
+/// A logged request and its outcome, with everything needed to evaluate it again.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Decision {
+    pub principal: String,
+    pub action: String,
+    pub resource: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub context: Option<serde_json::Value>,
+    #[serde(default)]
+    pub entities: serde_json::Value,
+    pub decision: String,
+    pub reasons: Vec<String>,
+    pub errors: Vec<String>,
+}
+
+/// One line of the log, without its `hash`, which is appended after hashing the rest.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Record {
+    pub seq: u64,
+    pub time: String,
+    #[serde(flatten)]
+    pub decision: Decision,
+    pub prev_hash: String,
+}
+
+/// Written every `CEDAR_DECISION_LOG_CHECKPOINT_INTERVAL` records: the hash of record
+/// `checkpoint`, signed with `CEDAR_DECISION_LOG_SIGNING_KEY` when one is set.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Checkpoint {
+    pub checkpoint: u64,
+    pub time: String,
+    pub hash: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<String>,
+}
+
+fn sha256(data: &str) -> String {
+    digest::sha256_hex(data.as_bytes())
+}
+
+fn sign(key: &[u8], seq: u64, hash: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(format!("{}.{}", seq, hash).as_bytes());
+    digest::hex(&mac.finalize().into_bytes())
+}
+
+fn now() -> String {
+    Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true)
+}
+
+/// Splits a record line into the hashed part (the line without `hash`) and its hash.
+fn split_hash(line: &str) -> Option<(String, &str)> {
+    let start = line.rfind(HASH_FIELD)?;
+    let hash = line[start + HASH_FIELD.len()..].strip_suffix("\"}")?;
+    Some((format!("{}}}", &line[..start]), hash))
+}
+
+/// Parses one line of the log: a record, or `None` for a checkpoint.
+pub fn parse_line(line: &str) -> Result<Option<Record>, String> {
+    if serde_json::from_str::<Checkpoint>(line).is_ok() {
+        return Ok(None);
+    }
+    serde_json::from_str(line).map(Some).map_err(|e| format!("Malformed record: {}", e))
+}
+
+/// The last `limit` decisions in the log at `path`, oldest first.
+pub fn recent(path: &str, limit: usize) -> Result<Vec<Decision>, String> {
+    Ok(recent_records(path, limit)?.into_iter().map(|record| record.decision).collect())
+}
+
+/// The last `limit` records in the log at `path`, oldest first.
+pub fn recent_records(path: &str, limit: usize) -> Result<Vec<Record>, String> {
+    let file = File::open(path).map_err(|e| format!("Failed to open decision log {}: {}", path, e))?;
+    let mut records = VecDeque::with_capacity(limit.min(1024));
+    for line in BufReader::new(file).lines() {
+        let line = line.map_err(|e| format!("Failed to read decision log {}: {}", path, e))?;
+        if let Some(record) = parse_line(&line)? {
+            if records.len() == limit {
+                records.pop_front();
+            }
+            records.push_back(record);
+        }
+    }
+    Ok(records.into())
+}
+
+/// The sequence number and hash of the last record, to continue the chain after a restart.
+fn resume(path: &str) -> Result<(u64, String), String> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok((0, GENESIS.to_string())),
+        Err(e) => return Err(e.to_string()),
+    };
+    let mut head = (0, GENESIS.to_string());
+    for line in BufReader::new(file).lines() {
+        let line = line.map_err(|e| e.to_string())?;
+        // Checkpoints carry a hash too, of the record before them
+        let Some(record) = parse_line(&line)? else {
+            continue;
+        };
+        if let Some((_, hash)) = split_hash(&line) {
+            head = (record.seq, hash.to_string());
+        }
+    }
+    Ok(head)
+}
+
+struct Chain {
+    /// Feeds the writer thread; `None` once the log is closed.
+    lines: Option<SyncSender<String>>,
+    seq: u64,
+    head: String,
+}
+
+/// Decision log whose records are hash-chained: each carries the hash of the one before it,
+/// so removing, reordering or editing records breaks the chain. Periodic checkpoints sign the
+/// chain head; they also go to the application log (and syslog), so a copy of the head lives
+/// outside the file and truncating its tail shows too.
+///
+/// Records are chained in the order `append` is called and written in that order by a
+/// dedicated thread, so requests never wait on the disk.
+pub struct DecisionLog {
+    chain: Mutex<Chain>,
+    writer: Mutex<Option<JoinHandle<()>>>,
+    failures: Arc<Failures>,
+    /// The file written to; `None` for other sinks.
+    path: Option<String>,
+    /// Held while the file is open, so no other process appends to it.
+    _lock: Option<File>,
+    key: Option<Vec<u8>>,
+    checkpoint_interval: u64,
+}
+
+impl DecisionLog {
+    /// Opens the log for appending, continuing the chain of the records already in it. The log
+    /// is locked (through `<path>.lock`) first, so another process cannot extend the chain from
+    /// the same head.
+    pub fn open(path: &str, key: Option<&str>, checkpoint_interval: u64) -> Result<Self, Box<dyn std::error::Error>> {
+        let lock = crate::upgrade::lock(std::path::Path::new(&format!("{}.lock", path)))?;
+        let (seq, head) = resume(path).map_err(|e| format!("Failed to resume decision log {}: {}", path, e))?;
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|e| format!("Failed to open decision log {}: {}", path, e))?;
+        info!("Writing decision log to {} from record {}", path, seq + 1);
+        let mut log = Self::new(Box::new(file), seq, head, key, checkpoint_interval);
+        log.path = Some(path.to_string());
+        log._lock = Some(lock);
+        Ok(log)
+    }
+
+    pub fn path(&self) -> Option<&str> {
+        self.path.as_deref()
+    }
+
+    fn new(sink: Box<dyn Write + Send>, seq: u64, head: String, key: Option<&str>, checkpoint_interval: u64) -> Self {
+        let (lines, queued) = mpsc::sync_channel(QUEUE);
+        let failures = Arc::new(Failures::default());
+        let writer = std::thread::spawn({
+            let failures = Arc::clone(&failures);
+            move || write(sink, queued, &failures)
+        });
+        Self {
+            chain: Mutex::new(Chain {
+                lines: Some(lines),
+                seq,
+                head,
+            }),
+            writer: Mutex::new(Some(writer)),
+            failures,
+            path: None,
+            _lock: None,
+            key: key.map(|k| k.as_bytes().to_vec()),
+            checkpoint_interval,
+        }
+    }
+
+    pub fn append(&self, decision: Decision) -> Result<(), String> {
+        let mut chain = self.chain.lock().unwrap();
+        let record = Record {
+            seq: chain.seq + 1,
+            time: now(),
+            decision,
+            prev_hash: chain.head.clone(),
+        };
+        let body = serde_json::to_string(&record).map_err(|e| e.to_string())?;
+        let hash = sha256(&body);
+        let mut line = format!("{}{}{}\"}}\n", &body[..body.len() - 1], HASH_FIELD, hash);
+
+        let checkpoint = record.seq.is_multiple_of(self.checkpoint_interval).then(|| Checkpoint {
+            checkpoint: record.seq,
+            time: record.time.clone(),
+            signature: self.key.as_ref().map(|key| sign(key, record.seq, &hash)),
+            hash: hash.clone(),
+        });
+        if let Some(ref checkpoint) = checkpoint {
+            line.push_str(&serde_json::to_string(checkpoint).map_err(|e| e.to_string())?);
+            line.push('\n');
+        }
+
+        if self.failures.stopped.load(Ordering::Relaxed) {
+            // Ends the writer once it has discarded what was queued after the failure
+            chain.lines.take();
+            return Err("The decision log stopped at a failed write; restart the agent to resume it".to_string());
+        }
+        let Some(ref lines) = chain.lines else {
+            return Err("The decision log is closed".to_string());
+        };
+        lines.send(line).map_err(|_| "The decision log writer stopped".to_string())?;
+        chain.seq = record.seq;
+        chain.head = hash;
+        if let Some(checkpoint) = checkpoint {
+            info!(
+                target: crate::logging::DECISION,
+                "Decision log checkpoint: record {} hash {}", checkpoint.checkpoint, checkpoint.hash
+            );
+        }
+        Ok(())
+    }
+
+    /// How many records failed to write since the last call.
+    pub fn take_failures(&self) -> u64 {
+        self.failures.count.swap(0, Ordering::Relaxed)
+    }
+
+    /// Stops taking records and waits until those already appended are written, so the log
+    /// is complete before its lock is released (on exit, or to another process on upgrade).
+    pub fn close(&self) {
+        self.chain.lock().unwrap().lines.take();
+        if let Some(writer) = self.writer.lock().unwrap().take() {
+            let _ = writer.join();
+        }
+    }
+}
+
+impl Drop for DecisionLog {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+/// Writes lines to the sink in the order they were queued, until the log is closed.
+/// Records that were not written, and whether writing stopped.
+#[derive(Default)]
+struct Failures {
+    /// Since `take_failures` last read it.
+    count: AtomicU64,
+    stopped: AtomicBool,
+}
+
+/// Writes lines to the sink in the order they were queued, until the log is closed. After a
+/// failed write nothing more is written, as every later record links to the one that is
+/// missing: the file stays a valid chain up to its last record.
+fn write(mut sink: Box<dyn Write + Send>, lines: mpsc::Receiver<String>, failures: &Failures) {
+    for line in lines {
+        if failures.stopped.load(Ordering::Relaxed) {
+            failures.count.fetch_add(1, Ordering::Relaxed);
+            continue;
+        }
+        if let Err(e) = sink.write_all(line.as_bytes()).and_then(|_| sink.flush()) {
+            error!("Failed to write decision log, which stops here until the agent restarts: {}", e);
+            failures.stopped.store(true, Ordering::Relaxed);
+            failures.count.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Result of `cedar-agent verify-log`.
+#[derive(Debug, Default, Serialize)]
+pub struct Verification {
+    pub valid: bool,
+    pub records: u64,
+    pub first_seq: Option<u64>,
+    pub last_seq: Option<u64>,
+    /// Hash of the last record; compare it with the last checkpoint kept elsewhere to detect a
+    /// truncated tail.
+    pub head: Option<String>,
+    pub checkpoints: u64,
+    /// Checkpoints whose signature was checked (a signing key was given).
+    pub signed_checkpoints: u64,
+    pub errors: Vec<String>,
+}
+
+/// Walks the chain, checking every record's hash and link to the previous one, and every
+/// checkpoint against the record it covers (and its signature, given the key). The first record
+/// must link to `start`: the genesis hash, or the head of the previous file after rotation.
+pub fn verify(reader: impl BufRead, key: Option<&[u8]>, start: &str) -> Verification {
+    let mut result = Verification::default();
+    let mut head: Option<(u64, String)> = None;
+    for (n, line) in reader.lines().enumerate() {
+        let n = n + 1;
+        let line = match line {
+            Ok(line) if line.trim().is_empty() => continue,
+            Ok(line) => line,
+            Err(e) => {
+                result.errors.push(format!("Line {}: {}", n, e));
+                break;
+            }
+        };
+
+        if let Ok(checkpoint) = serde_json::from_str::<Checkpoint>(&line) {
+            result.checkpoints += 1;
+            match head {
+                Some((seq, ref hash)) if seq == checkpoint.checkpoint && *hash == checkpoint.hash => {}
+                _ => result.errors.push(format!(
+                    "Line {}: checkpoint for record {} does not match the chain",
+                    n, checkpoint.checkpoint
+                )),
+            }
+            if let Some(key) = key {
+                result.signed_checkpoints += 1;
+                if checkpoint.signature.as_deref() != Some(&sign(key, checkpoint.checkpoint, &checkpoint.hash)) {
+                    result.errors.push(format!("Line {}: checkpoint signature is missing or invalid", n));
+                }
+            }
+            continue;
+        }
+
+        let Some((body, hash)) = split_hash(&line) else {
+            result.errors.push(format!("Line {}: not a decision record", n));
+            continue;
+        };
+        let record: Record = match serde_json::from_str(&body) {
+            Ok(record) => record,
+            Err(e) => {
+                result.errors.push(format!("Line {}: malformed record: {}", n, e));
+                continue;
+            }
+        };
+        if sha256(&body) != hash {
+            result.errors.push(format!("Line {}: record {} was modified (hash mismatch)", n, record.seq));
+        }
+        match head {
+            Some((seq, _)) if record.seq != seq + 1 => result.errors.push(format!(
+                "Line {}: record {} follows record {} (records missing or reordered)",
+                n, record.seq, seq
+            )),
+            Some((_, ref prev)) if *prev != record.prev_hash => {
+                result.errors.push(format!("Line {}: record {} does not link to the previous record", n, record.seq))
+            }
+            None if record.prev_hash != start => result.errors.push(format!(
+                "Line {}: first record {} does not link to the start of the chain (records missing before it)",
+                n, record.seq
+            )),
+            _ => {}
+        }
+        result.records += 1;
+        result.first_seq.get_or_insert(record.seq);
+        result.last_seq = Some(record.seq);
+        head = Some((record.seq, hash.to_string()));
+    }
+    result.head = head.map(|(_, hash)| hash);
+    result.valid = result.errors.is_empty();
+    result
+}
+
+/// `cedar-agent verify-log FILE [--continues HASH]`, with the checkpoint key taken from
+/// `CEDAR_DECISION_LOG_SIGNING_KEY` if set. Exits nonzero unless the log verifies.
+pub fn run(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let (path, start) = match args {
+        [path] => (path, GENESIS),
+        [path, flag, hash] if flag == "--continues" => (path, hash.as_str()),
+        _ => return Err("Usage: cedar-agent verify-log FILE [--continues HASH]".into()),
+    };
+    let file = File::open(path).map_err(|e| format!("Failed to open {}: {}", path, e))?;
+    let key = std::env::var("CEDAR_DECISION_LOG_SIGNING_KEY").ok().filter(|k| !k.is_empty());
+    let result = verify(BufReader::new(file), key.as_deref().map(str::as_bytes), start);
+    println!("{}", serde_json::to_string_pretty(&result)?);
+    if !result.valid {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A sink tests can read back.
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Buffer {
+        fn write(&mut self, data: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(data)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Fails every write after the first `left`.
+    struct Failing {
+        buffer: Buffer,
+        left: usize,
+    }
+
+    impl Write for Failing {
+        fn write(&mut self, data: &[u8]) -> std::io::Result<usize> {
+            if self.left == 0 {
+                return Err(std::io::Error::other("disk full"));
+            }
+            self.left -= 1;
+            self.buffer.write(data)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn decision(principal: &str) -> Decision {
+        Decision {
+            principal: format!("User::\"{}\"", principal),
+            action: "Action::\"view\"".to_string(),
+            resource: "Doc::\"d1\"".to_string(),
+            context: None,
+            entities: serde_json::json!([]),
+            decision: "Allow".to_string(),
+            reasons: vec!["policy0".to_string()],
+            errors: Vec::new(),
+        }
+    }
+
+    fn written(count: usize, key: Option<&str>) -> String {
+        let buffer = Buffer::default();
+        let log = DecisionLog::new(Box::new(buffer.clone()), 0, GENESIS.to_string(), key, 2);
+        for i in 0..count {
+            log.append(decision(&format!("u{}", i))).unwrap();
+        }
+        log.close();
+        let bytes = buffer.0.lock().unwrap().clone();
+        String::from_utf8(bytes).unwrap()
+    }
+
+    fn check(log: &str, key: Option<&str>) -> Verification {
+        verify(log.as_bytes(), key.map(str::as_bytes), GENESIS)
+    }
+
+    #[test]
+    fn an_untouched_log_verifies() {
+        let log = written(5, Some("k"));
+        let result = check(&log, Some("k"));
+        assert!(result.valid, "{:?}", result.errors);
+        assert_eq!((result.records, result.checkpoints, result.signed_checkpoints), (5, 2, 2));
+        assert_eq!(result.last_seq, Some(5));
+        assert!(!check(&log, Some("other")).valid);
+    }
+
+    #[test]
+    fn detects_edits_removals_and_reordering() {
+        let log = written(4, None);
+        assert!(!check(&log.replacen("u1", "u9", 1), None).valid);
+
+        let lines: Vec<&str> = log.lines().collect();
+        let without_one: Vec<&str> = lines.iter().copied().filter(|l| !l.contains("\"seq\":3")).collect();
+        assert!(!check(&without_one.join("\n"), None).valid);
+
+        let mut swapped = lines.clone();
+        swapped.swap(0, 1);
+        assert!(!check(&swapped.join("\n"), None).valid);
+
+        // Dropping the first record only verifies as the continuation of the file before it
+        let (_, first_hash) = split_hash(lines[0]).unwrap();
+        let rest = lines[1..].join("\n");
+        assert!(!check(&rest, None).valid);
+        assert!(verify(rest.as_bytes(), None, first_hash).valid);
+    }
+
+    #[test]
+    fn a_rehashed_edit_still_breaks_the_link() {
+        let log = written(3, None);
+        let first = log.lines().next().unwrap();
+        let (body, _) = split_hash(first).unwrap();
+        let body = body.replace("u0", "u9");
+        let forged = format!("{}{}{}\"}}", &body[..body.len() - 1], HASH_FIELD, sha256(&body));
+        let result = check(&log.replacen(first, &forged, 1), None);
+        assert!(result.errors.iter().any(|e| e.contains("does not link")), "{:?}", result.errors);
+    }
+
+    #[test]
+    fn resumes_the_chain_of_an_existing_file() {
+        let path = std::env::temp_dir().join(format!("cedar-decisions-{}.jsonl", std::process::id()));
+        let path = path.to_str().unwrap();
+        let _ = std::fs::remove_file(path);
+        let log = DecisionLog::open(path, None, 100).unwrap();
+        log.append(decision("a")).unwrap();
+        assert!(DecisionLog::open(path, None, 100).is_err(), "the log is locked while open");
+        drop(log);
+        DecisionLog::open(path, None, 100).unwrap().append(decision("b")).unwrap();
+        let result = verify(BufReader::new(File::open(path).unwrap()), None, GENESIS);
+        std::fs::remove_file(path).unwrap();
+        std::fs::remove_file(format!("{}.lock", path)).unwrap();
+        assert!(result.valid, "{:?}", result.errors);
+        assert_eq!(result.records, 2);
+    }
+
+    #[test]
+    fn stops_at_the_first_failed_write() {
+        let buffer = Buffer::default();
+        let sink = Failing {
+            buffer: buffer.clone(),
+            left: 2,
+        };
+        let log = DecisionLog::new(Box::new(sink), 0, GENESIS.to_string(), None, 100);
+        for principal in ["a", "b", "c", "d"] {
+            log.append(decision(principal)).unwrap();
+        }
+        while !log.failures.stopped.load(Ordering::Relaxed) {
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        }
+        assert!(log.append(decision("e")).is_err());
+        log.close();
+        assert_eq!(log.take_failures(), 2);
+
+        let result = check(&String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap(), None);
+        assert!(result.valid, "{:?}", result.errors);
+        assert_eq!(result.records, 2);
+    }
+
+    #[test]
+    fn resumes_after_a_checkpoint() {
+        let path = std::env::temp_dir().join(format!("cedar-checkpointed-{}.jsonl", std::process::id()));
+        let path = path.to_str().unwrap();
+        let _ = std::fs::remove_file(path);
+        DecisionLog::open(path, None, 1).unwrap().append(decision("a")).unwrap();
+        let reopened = DecisionLog::open(path, None, 1).map(|log| log.append(decision("b")));
+        let result = verify(BufReader::new(File::open(path).unwrap()), None, GENESIS);
+        std::fs::remove_file(path).unwrap();
+        std::fs::remove_file(format!("{}.lock", path)).unwrap();
+        assert!(reopened.is_ok(), "{:?}", reopened.err());
+        assert!(result.valid, "{:?}", result.errors);
+        assert_eq!((result.records, result.checkpoints), (2, 2));
+    }
+
+    #[test]
+    fn reads_the_most_recent_decisions() {
+        let path = std::env::temp_dir().join(format!("cedar-recent-{}.jsonl", std::process::id()));
+        let path = path.to_str().unwrap();
+        let _ = std::fs::remove_file(path);
+        let log = DecisionLog::open(path, None, 2).unwrap();
+        for principal in ["a", "b", "c", "d", "e"] {
+            log.append(decision(principal)).unwrap();
+        }
+        log.close();
+        assert!(log.append(decision("f")).is_err(), "a closed log takes no more records");
+        let recent = recent(path, 2);
+        std::fs::remove_file(path).unwrap();
+        std::fs::remove_file(format!("{}.lock", path)).unwrap();
+        let principals: Vec<String> = recent.unwrap().into_iter().map(|d| d.principal).collect();
+        assert_eq!(principals, vec![r#"User::"d""#, r#"User::"e""#]);
+        assert_eq!(log.path(), Some(path));
+    }
+}
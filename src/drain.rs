@@ -0,0 +1,97 @@
+use crate::{error_response, grpc_health, json_response, parse_wait, CedarService};
+use hyper::{Body, Response, StatusCode};
+use log::{info, warn};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+pub const PATH: &str = "/admin/drain";
+/// How often requests in flight are counted while draining.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Whether the agent accepts connections, and if not, what it does once they are closed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Accepting {
+    Yes,
+    /// Stopped by `POST /admin/drain`: the agent stays up until it is stopped.
+    Drained,
+    /// Stopped on upgrade or when the service is stopped: the agent exits.
+    Exiting,
+}
+
+impl Accepting {
+    /// The state after stopping to accept, to exit or not. Exiting is final: a drain does not
+    /// keep an agent that is exiting up.
+    pub fn stop(self, exit: bool) -> Self {
+        match (self, exit) {
+            (_, true) | (Accepting::Exiting, false) => Accepting::Exiting,
+            _ => Accepting::Drained,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct DrainResponse {
+    /// `drained`, or `timeout` when requests were still in flight after
+    /// `CEDAR_DRAIN_TIMEOUT_SECS`.
+    status: &'static str,
+    in_flight: usize,
+    waited_ms: u64,
+}
+
+/// Serves `POST /admin/drain`: reports the agent not ready at once, stops accepting connections
+/// after the `grace` parameter (default `CEDAR_DRAIN_GRACE_SECS`), then answers once no other
+/// request is in flight.
+pub async fn handle(service: &CedarService, params: &HashMap<String, String>) -> Response<Body> {
+    let grace = match params.get("grace").map(|grace| parse_wait(grace)) {
+        None => service.drain_grace,
+        Some(Ok(grace)) => grace,
+        Some(Err(e)) => return error_response(StatusCode::BAD_REQUEST, format!("Invalid grace: {}", e)),
+    };
+    if service.draining.swap(true, std::sync::atomic::Ordering::Relaxed) {
+        return error_response(StatusCode::CONFLICT, "The agent is already draining");
+    }
+    service.serving.send_replace(grpc_health::ServingStatus::NotServing);
+    info!("Draining: not ready, and accepting connections for {}ms more", grace.as_millis());
+    tokio::time::sleep(grace).await;
+
+    service.stop_accepting(false);
+    info!("Stopped accepting connections; waiting for the requests in flight");
+    let started = Instant::now();
+    // This request is one of those in flight
+    let others = || service.stats.in_flight().saturating_sub(1);
+    while others() > 0 && started.elapsed() < service.drain_timeout {
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+    let in_flight = others();
+    let status = if in_flight == 0 {
+        info!("Drained: no requests in flight");
+        "drained"
+    } else {
+        warn!("{} requests still in flight after {}s of draining", in_flight, service.drain_timeout.as_secs());
+        "timeout"
+    };
+    json_response(
+        StatusCode::OK,
+        &DrainResponse {
+            status,
+            in_flight,
+            waited_ms: started.elapsed().as_millis() as u64,
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exiting_is_final() {
+        assert_eq!(Accepting::Yes.stop(false), Accepting::Drained);
+        assert_eq!(Accepting::Yes.stop(true), Accepting::Exiting);
+        // A drained agent exits when its service is stopped
+        assert_eq!(Accepting::Drained.stop(true), Accepting::Exiting);
+        assert_eq!(Accepting::Drained.stop(false), Accepting::Drained);
+        assert_eq!(Accepting::Exiting.stop(false), Accepting::Exiting);
+    }
+}
@@ -0,0 +1,877 @@
+use crate::config::{Config, EntityDuplicates, EntityHierarchy, EntityIntegrity};
+use cedar_policy::entities_errors::EntitiesError;
+use cedar_policy::{Entities, Entity, EntityTypeName, EntityUid, Schema};
+use cedar_policy_core::entities::TCComputation;
+use cedar_policy_core::extensions::Extensions;
+use cedar_policy_core::validator::CoreSchema;
+use serde::Serialize;
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fs;
+use std::sync::Arc;
+
+/// Entities loaded into the agent (as opposed to the ones sent with each request), plus the
+/// direct parent edges needed to explain memberships. `Entities` only exposes the transitive
+/// closure, which says *that* alice is in Admins but not *through which* groups.
+pub struct EntityStore {
+    entities: Entities,
+    ingest: Ingest,
+    /// The entities as loaded, with direct parents only, so the hierarchy can be rebuilt when a
+    /// request replaces some of them.
+    direct: Vec<Entity>,
+    parents: HashMap<EntityUid, HashSet<EntityUid>>,
+    /// Total size of the entities in Cedar's JSON format, the measure of the memory budget.
+    size: usize,
+}
+
+/// An error followed by its chain of causes, which carry the detail of Cedar's entity errors
+/// (which entity, which attribute).
+pub fn with_causes(e: &dyn std::error::Error) -> String {
+    let mut message = e.to_string();
+    let mut source = e.source();
+    while let Some(cause) = source {
+        message.push_str(": ");
+        message.push_str(&cause.to_string());
+        source = cause.source();
+    }
+    message
+}
+
+/// Size of an entity in Cedar's JSON format.
+fn json_size(entity: &Entity) -> usize {
+    entity.to_json_value().map(|json| json.to_string().len()).unwrap_or_default()
+}
+
+/// Parses a JSON array in Cedar's entity format, keeping each entity's direct parents.
+pub fn parse_list(json: serde_json::Value, schema: Option<&Schema>) -> Result<Vec<Entity>, String> {
+    let items = match json {
+        serde_json::Value::Array(items) => items,
+        _ => return Err("Entities must be a JSON array".to_string()),
+    };
+    items
+        .into_iter()
+        .map(|item| Entity::from_json_value(item, schema).map_err(|e| format!("Failed to parse entity: {}", e)))
+        .collect()
+}
+
+/// How a bulk write through `/v1/data/entities` combines with the stored entities.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Write {
+    /// `PUT`: the payload becomes the stored entities, or the stored entities of one type.
+    Replace(Option<EntityTypeName>),
+    /// `PATCH`: each payload entity replaces the stored one with its UID, attributes and parents
+    /// alike, or is added; everything else is kept.
+    Upsert,
+}
+
+/// What a bulk write changed.
+#[derive(Debug, Default, Serialize, PartialEq)]
+pub struct WriteSummary {
+    /// Payload entities written.
+    pub written: usize,
+    /// Stored entities dropped because the payload left them out (replace only).
+    pub removed: usize,
+    /// Stored entities after the write.
+    pub entities: usize,
+}
+
+/// The stored entities after `write`ing `payload` over `current`. Refuses a typed replace whose
+/// payload holds other types, rather than storing them outside the replaced scope.
+pub fn apply(current: &[Entity], payload: Vec<Entity>, write: &Write) -> Result<(Vec<Entity>, WriteSummary), String> {
+    let written: HashSet<EntityUid> = payload.iter().map(Entity::uid).collect();
+    let replaced = |entity: &Entity| match write {
+        Write::Replace(None) => true,
+        Write::Replace(Some(entity_type)) => entity.uid().type_name() == entity_type,
+        Write::Upsert => written.contains(&entity.uid()),
+    };
+    if let Write::Replace(Some(entity_type)) = write {
+        if let Some(other) = payload.iter().find(|entity| entity.uid().type_name() != entity_type) {
+            return Err(format!("Entity {} is not of type {}", other.uid(), entity_type));
+        }
+    }
+
+    let (dropped, kept): (Vec<&Entity>, Vec<&Entity>) = current.iter().partition(|entity| replaced(entity));
+    let summary = WriteSummary {
+        written: payload.len(),
+        removed: dropped.iter().filter(|entity| !written.contains(&entity.uid())).count(),
+        entities: 0,
+    };
+    Ok((kept.into_iter().cloned().chain(payload).collect(), summary))
+}
+
+/// An entity in Cedar's JSON format.
+pub fn to_json(entity: &Entity) -> Result<serde_json::Value, String> {
+    entity.to_json_value().map_err(|e| format!("Failed to serialize entities: {}", e))
+}
+
+/// The UIDs of the entities removed from `previous`, and the entities added to or changed in
+/// `next` in Cedar's JSON format: what a write-ahead record or a replication delta carries
+/// instead of every stored entity. Entities are compared by their JSON, since `Entity`
+/// equality looks at the UID alone.
+pub fn changes(previous: &[Entity], next: &[Entity]) -> Result<(Vec<serde_json::Value>, Vec<serde_json::Value>), String> {
+    let before: HashMap<EntityUid, &Entity> = previous.iter().map(|e| (e.uid(), e)).collect();
+    let after: HashSet<EntityUid> = next.iter().map(Entity::uid).collect();
+    let removed = before
+        .keys()
+        .filter(|uid| !after.contains(*uid))
+        .map(|uid| serde_json::json!({"type": uid.type_name().to_string(), "id": uid.id().unescaped()}))
+        .collect();
+    let mut upserted = Vec::new();
+    for entity in next {
+        let json = to_json(entity)?;
+        match before.get(&entity.uid()) {
+            Some(old) if to_json(old)? == json => {}
+            _ => upserted.push(json),
+        }
+    }
+    Ok((removed, upserted))
+}
+
+/// Reads a field that is there as `Some`, even when it is `null`, so that a schema that was
+/// removed reads apart from one that was left alone.
+pub fn present<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<Option<serde_json::Value>, D::Error> {
+    serde::Deserialize::deserialize(deserializer).map(Some)
+}
+
+/// The stored entities with those `removed` (by UID, as `changes` gives them) taken out and
+/// those `upserted` put in place of any with the same UID.
+pub fn apply_changes(
+    current: &[Entity],
+    removed: Vec<serde_json::Value>,
+    upserted: Vec<serde_json::Value>,
+    schema: Option<&Schema>,
+) -> Result<Vec<Entity>, String> {
+    let removed: HashSet<EntityUid> = removed
+        .into_iter()
+        .map(|uid| EntityUid::from_json(uid).map_err(|e| format!("Invalid entity UID: {}", e)))
+        .collect::<Result<_, _>>()?;
+    let upserted = parse_list(serde_json::Value::Array(upserted), schema)?;
+    let kept: Vec<Entity> = current.iter().filter(|e| !removed.contains(&e.uid())).cloned().collect();
+    Ok(apply(&kept, upserted, &Write::Upsert)?.0)
+}
+
+/// A problem with a proposed entity payload.
+#[derive(Debug, Serialize, PartialEq)]
+pub struct Violation {
+    /// Position of the offending entity in the payload; unset for problems of the payload as a
+    /// whole, such as a cycle.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub index: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub uid: Option<String>,
+    pub error: String,
+}
+
+/// Result of `POST /v1/data/entities/validate`.
+#[derive(Debug, Serialize)]
+pub struct ValidationReport {
+    pub valid: bool,
+    pub entities: usize,
+    pub violations: Vec<Violation>,
+}
+
+/// Checks a payload in Cedar's entity format against `schema` without loading it anywhere.
+/// Each entity is checked on its own, so every attribute, type and parent violation is
+/// reported rather than only the first, and then the payload as a whole (duplicates, cycles).
+pub fn validate(json: serde_json::Value, schema: Option<&Schema>, ingest: Ingest) -> Result<ValidationReport, String> {
+    let items = match json {
+        serde_json::Value::Array(items) => items,
+        _ => return Err("Entities must be a JSON array".to_string()),
+    };
+    let count = items.len();
+
+    let mut violations = Vec::new();
+    let mut parsed: Vec<(usize, Entity)> = Vec::new();
+    for (index, item) in items.into_iter().enumerate() {
+        let uid = item.get("uid").cloned().and_then(|uid| EntityUid::from_json(uid).ok()).map(|uid| uid.to_string());
+        let checked = Entity::from_json_value(item, schema)
+            .map_err(|e| with_causes(&e))
+            .and_then(|entity| match ingest.build(vec![entity.clone()], schema) {
+                Ok(_) => Ok(entity),
+                Err(e) => Err(with_causes(&*e)),
+            });
+        match checked {
+            Ok(entity) => parsed.push((index, entity)),
+            Err(error) => violations.push(Violation {
+                index: Some(index),
+                uid,
+                error,
+            }),
+        }
+    }
+
+    let mut first_seen: HashMap<EntityUid, (usize, &Entity)> = HashMap::new();
+    for (index, entity) in &parsed {
+        match first_seen.get(&entity.uid()) {
+            Some((first, original)) if ingest.duplicates == EntityDuplicates::Reject && *original != entity => {
+                violations.push(Violation {
+                    index: Some(*index),
+                    uid: Some(entity.uid().to_string()),
+                    error: format!("Differs from entity {} with the same UID", first),
+                })
+            }
+            Some(_) => {}
+            None => {
+                first_seen.insert(entity.uid(), (*index, entity));
+            }
+        }
+    }
+
+    if violations.is_empty() && ingest.integrity != EntityIntegrity::Off {
+        let parents = ingest
+            .dedupe(parsed.iter().map(|(_, entity)| entity.clone()).collect())
+            .into_iter()
+            .map(|entity| {
+                let (uid, _, parents) = entity.into_inner();
+                (uid, parents)
+            })
+            .collect();
+        let problems = graph_problems(&parents);
+        violations.extend(ingest.fatal(&problems).into_iter().map(|problem| Violation {
+            index: None,
+            uid: Some(problem.uid().to_string()),
+            error: problem.to_string(),
+        }));
+    }
+
+    if violations.is_empty() {
+        if let Err(e) = ingest.build(parsed.into_iter().map(|(_, entity)| entity).collect(), schema) {
+            violations.push(Violation {
+                index: None,
+                uid: None,
+                error: with_causes(&*e),
+            });
+        }
+    }
+
+    Ok(ValidationReport {
+        valid: violations.is_empty(),
+        entities: count,
+        violations,
+    })
+}
+
+/// How entity lists are turned into `Entities` (see `CEDAR_ENTITY_HIERARCHY`,
+/// `CEDAR_ENTITY_DUPLICATES` and `CEDAR_ENTITY_INTEGRITY`).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Ingest {
+    pub hierarchy: EntityHierarchy,
+    pub duplicates: EntityDuplicates,
+    pub integrity: EntityIntegrity,
+}
+
+impl Ingest {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            hierarchy: config.entity_hierarchy,
+            duplicates: config.entity_duplicates,
+            integrity: config.entity_integrity,
+        }
+    }
+
+    /// The problems of a hierarchy that make it unfit to ingest: all of them with `reject`, and
+    /// cycles whenever Cedar computes the closure, which it cannot for a cycle.
+    fn fatal<'a>(&self, problems: &'a [GraphProblem]) -> Vec<&'a GraphProblem> {
+        problems
+            .iter()
+            .filter(|problem| {
+                self.integrity == EntityIntegrity::Reject
+                    || matches!(problem, GraphProblem::Cycle(_)) && self.hierarchy == EntityHierarchy::Compute
+            })
+            .collect()
+    }
+
+    /// With `keep-last`, drops every entity that a later one with the same UID replaces.
+    fn dedupe(&self, entities: Vec<Entity>) -> Vec<Entity> {
+        if self.duplicates == EntityDuplicates::Reject {
+            return entities;
+        }
+        let mut seen = HashSet::new();
+        let mut kept: Vec<Entity> = entities.into_iter().rev().filter(|entity| seen.insert(entity.uid())).collect();
+        kept.reverse();
+        kept
+    }
+
+    fn tc(&self) -> TCComputation {
+        match self.hierarchy {
+            EntityHierarchy::Compute => TCComputation::ComputeNow,
+            EntityHierarchy::Provided => TCComputation::AssumeAlreadyComputed,
+        }
+    }
+
+    /// `Entities::from_entities`, with the closure computed or taken as given.
+    fn build(&self, entities: Vec<Entity>, schema: Option<&Schema>) -> Result<Entities, Box<EntitiesError>> {
+        cedar_policy_core::entities::Entities::from_entities(
+            self.dedupe(entities).into_iter().map(|entity| entity.as_ref().clone()),
+            schema.map(|schema| CoreSchema::new(schema.as_ref())).as_ref(),
+            self.tc(),
+            Extensions::all_available(),
+        )
+        .map(Entities::from)
+        .map_err(Box::new)
+    }
+
+    /// `Entities::add_entities`, with the closure computed or taken as given.
+    fn add(&self, base: &Entities, entities: Vec<Entity>, schema: Option<&Schema>) -> Result<Entities, Box<EntitiesError>> {
+        base.as_ref()
+            .clone()
+            .add_entities(
+                self.dedupe(entities).into_iter().map(|entity| Arc::new(entity.as_ref().clone())),
+                schema.map(|schema| CoreSchema::new(schema.as_ref())).as_ref(),
+                self.tc(),
+                Extensions::all_available(),
+            )
+            .map(Entities::from)
+            .map_err(Box::new)
+    }
+}
+
+/// Most hierarchy problems listed in an error or logged; the rest are counted.
+const MAX_REPORTED_PROBLEMS: usize = 10;
+
+/// A flaw in the hierarchy of stored entities.
+#[derive(Debug, Clone, PartialEq)]
+pub enum GraphProblem {
+    /// `uid` lists `parent`, which is not one of the entities.
+    Dangling { uid: String, parent: String },
+    /// Each entity is a member of the next, and the last of the first.
+    Cycle(Vec<String>),
+}
+
+impl GraphProblem {
+    /// The entity the problem is reported against.
+    fn uid(&self) -> &str {
+        match self {
+            GraphProblem::Dangling { uid, .. } => uid,
+            GraphProblem::Cycle(cycle) => &cycle[0],
+        }
+    }
+}
+
+impl std::fmt::Display for GraphProblem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GraphProblem::Dangling { uid, parent } => write!(f, "{} has parent {}, which does not exist", uid, parent),
+            GraphProblem::Cycle(cycle) => {
+                write!(f, "membership cycle ")?;
+                for uid in cycle {
+                    write!(f, "{} -> ", uid)?;
+                }
+                write!(f, "{}", cycle[0])
+            }
+        }
+    }
+}
+
+/// Joins `problems` into one line, listing the first few.
+fn describe(problems: &[&GraphProblem]) -> String {
+    let mut listed: Vec<String> = problems.iter().take(MAX_REPORTED_PROBLEMS).map(ToString::to_string).collect();
+    if problems.len() > MAX_REPORTED_PROBLEMS {
+        listed.push(format!("and {} more", problems.len() - MAX_REPORTED_PROBLEMS));
+    }
+    listed.join("; ")
+}
+
+/// Finds parents that are not among the entities, and membership cycles, in the direct
+/// `parents` of each entity. Parents that are actions are declared by the schema, not stored,
+/// and are not checked.
+pub fn graph_problems(parents: &HashMap<EntityUid, HashSet<EntityUid>>) -> Vec<GraphProblem> {
+    let mut uids: Vec<&EntityUid> = parents.keys().collect();
+    uids.sort_by_key(|uid| uid.to_string());
+    let sorted = |uid: &EntityUid| {
+        let mut next: Vec<&EntityUid> = parents.get(uid).into_iter().flatten().collect();
+        next.sort_by_key(|n| n.to_string());
+        next
+    };
+
+    let mut problems = Vec::new();
+    for uid in &uids {
+        for parent in sorted(uid) {
+            if !parents.contains_key(parent) && parent.type_name().basename() != "Action" {
+                problems.push(GraphProblem::Dangling {
+                    uid: uid.to_string(),
+                    parent: parent.to_string(),
+                });
+            }
+        }
+    }
+
+    // Depth-first, without recursion so deep hierarchies cannot overflow the stack: an entity
+    // met again while still on the path closes a cycle
+    let mut done: HashSet<&EntityUid> = HashSet::new();
+    for start in uids {
+        if done.contains(start) {
+            continue;
+        }
+        let mut path: Vec<&EntityUid> = vec![start];
+        let mut pending: Vec<std::vec::IntoIter<&EntityUid>> = vec![sorted(start).into_iter()];
+        let mut on_path: HashSet<&EntityUid> = HashSet::from([start]);
+        while let Some(next) = pending.last_mut() {
+            match next.next() {
+                Some(parent) if on_path.contains(parent) => {
+                    let from = path.iter().position(|uid| *uid == parent).expect("on the path");
+                    problems.push(GraphProblem::Cycle(path[from..].iter().map(ToString::to_string).collect()));
+                }
+                Some(parent) if done.contains(parent) || !parents.contains_key(parent) => {}
+                Some(parent) => {
+                    path.push(parent);
+                    on_path.insert(parent);
+                    pending.push(sorted(parent).into_iter());
+                }
+                None => {
+                    pending.pop();
+                    let uid = path.pop().expect("one per pending");
+                    on_path.remove(uid);
+                    done.insert(uid);
+                }
+            }
+        }
+    }
+    problems
+}
+
+/// An entity reached while walking the hierarchy, with the shortest chain of direct memberships
+/// leading to it (starting at the queried entity and ending at `uid`).
+#[derive(Debug, Serialize)]
+pub struct Relation {
+    pub uid: String,
+    pub path: Vec<String>,
+}
+
+impl EntityStore {
+    /// Builds the store from entities with direct parents, validating them against `schema`.
+    pub fn from_entities(direct: Vec<Entity>, schema: Option<&Schema>, ingest: Ingest) -> Result<Self, String> {
+        let count = direct.len();
+        let direct = ingest.dedupe(direct);
+        if direct.len() < count {
+            log::info!("Dropped {} entities replaced by later ones with the same UID", count - direct.len());
+        }
+        let parents = direct
+            .iter()
+            .map(|entity| {
+                let (uid, _, parents) = entity.clone().into_inner();
+                (uid, parents)
+            })
+            .collect();
+        if ingest.integrity != EntityIntegrity::Off {
+            let problems = graph_problems(&parents);
+            let fatal = ingest.fatal(&problems);
+            if !fatal.is_empty() {
+                return Err(format!("Failed to load entities: {}", describe(&fatal)));
+            }
+            if !problems.is_empty() {
+                log::warn!(
+                    "{} problems in the entity hierarchy: {}",
+                    problems.len(),
+                    describe(&problems.iter().collect::<Vec<_>>())
+                );
+            }
+        }
+        let entities = ingest
+            .build(direct.clone(), schema)
+            .map_err(|e| format!("Failed to load entities: {}", with_causes(&*e)))?;
+        let size = direct.iter().map(json_size).sum();
+        Ok(Self {
+            entities,
+            ingest,
+            direct,
+            parents,
+            size,
+        })
+    }
+
+    /// Builds the store from a JSON array in Cedar's entity format.
+    pub fn from_json(json: serde_json::Value, schema: Option<&Schema>, ingest: Ingest) -> Result<Self, Box<dyn std::error::Error>> {
+        Ok(Self::from_entities(parse_list(json, schema)?, schema, ingest)?)
+    }
+
+    /// The same entities validated against another schema, e.g. one about to be activated.
+    pub fn revalidate(&self, schema: &Schema) -> Result<Self, String> {
+        Self::from_entities(self.direct.clone(), Some(schema), self.ingest)
+    }
+
+    /// Another set of entities, ingested the same way as these.
+    pub fn replaced_with(&self, direct: Vec<Entity>, schema: Option<&Schema>) -> Result<Self, String> {
+        Self::from_entities(direct, schema, self.ingest)
+    }
+
+    /// Loads the store from a file. Without a path there are no stored entities (only the
+    /// schema's actions); a configured file that is missing is an error, so a mistyped path
+    /// does not go unnoticed.
+    pub fn load(path: Option<&str>, schema: Option<&Schema>, ingest: Ingest) -> Result<Self, Box<dyn std::error::Error>> {
+        let Some(path) = path else {
+            return Ok(Self::from_entities(Vec::new(), schema, ingest)?);
+        };
+        let src = fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read entities file {}: {}", path, e))?;
+        let json: serde_json::Value = serde_json::from_str(&src)
+            .map_err(|e| format!("Failed to parse entities file: {}", e))?;
+        let store = Self::from_json(json, schema, ingest)?;
+        log::info!("Loaded {} entities from {}", store.len(), path);
+        Ok(store)
+    }
+
+    pub fn entities(&self) -> &Entities {
+        &self.entities
+    }
+
+    pub fn ingest(&self) -> Ingest {
+        self.ingest
+    }
+
+    /// The stored entities with their direct parents, as loaded or last updated.
+    pub fn direct(&self) -> &[Entity] {
+        &self.direct
+    }
+
+    /// The stored entities combined with a request's, where a request entity replaces the
+    /// stored one with the same UID (attributes and parents alike). Without request entities
+    /// the store is used as is; when none is replaced they are added on top, which is cheap.
+    /// Only a replacement rebuilds the hierarchy from direct parents, since a replaced entity
+    /// may have dropped a membership that the stored closure still records.
+    pub fn merged_with(&self, request: Vec<Entity>, schema: Option<&Schema>) -> Result<Cow<'_, Entities>, String> {
+        if request.is_empty() {
+            return Ok(Cow::Borrowed(&self.entities));
+        }
+
+        let request = self.ingest.dedupe(request);
+        let replaced: HashSet<EntityUid> = request
+            .iter()
+            .map(|entity| entity.uid())
+            .filter(|uid| self.entities.get(uid).is_some())
+            .collect();
+        let merged = if replaced.is_empty() {
+            self.ingest.add(&self.entities, request, schema)
+        } else {
+            let kept = self
+                .direct
+                .iter()
+                .filter(|entity| !replaced.contains(&entity.uid()))
+                .cloned();
+            self.ingest.build(kept.chain(request).collect(), schema)
+        };
+        merged
+            .map(Cow::Owned)
+            .map_err(|e| format!("Failed to merge stored entities: {}", e))
+    }
+
+    pub fn len(&self) -> usize {
+        self.parents.len()
+    }
+
+    /// Approximate memory use of the entities, measured by their size in Cedar's JSON format.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Refuses a store larger than `limit` bytes (see [`EntityStore::size`]).
+    pub fn check_budget(&self, limit: Option<usize>) -> Result<(), String> {
+        match limit {
+            Some(limit) if self.size > limit => Err(format!(
+                "Entities need {} bytes, over the entity memory limit of {} bytes",
+                self.size, limit
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn contains(&self, uid: &EntityUid) -> bool {
+        self.parents.contains_key(uid)
+    }
+
+    /// Breadth-first walk from `start` along `edges`, so each entity is reported with its
+    /// shortest path.
+    fn walk(start: &EntityUid, edges: &HashMap<EntityUid, HashSet<EntityUid>>) -> Vec<Relation> {
+        let mut seen: HashSet<&EntityUid> = HashSet::from([start]);
+        let mut queue: VecDeque<(&EntityUid, Vec<String>)> =
+            VecDeque::from([(start, vec![start.to_string()])]);
+        let mut found = Vec::new();
+
+        while let Some((uid, path)) = queue.pop_front() {
+            let Some(next) = edges.get(uid) else {
+                continue;
+            };
+            let mut next: Vec<&EntityUid> = next.iter().collect();
+            next.sort_by_key(|n| n.to_string());
+            for n in next {
+                if seen.insert(n) {
+                    let mut path = path.clone();
+                    path.push(n.to_string());
+                    found.push(Relation {
+                        uid: n.to_string(),
+                        path: path.clone(),
+                    });
+                    queue.push_back((n, path));
+                }
+            }
+        }
+        found
+    }
+
+    /// Every entity `uid` is a member of, directly or transitively.
+    pub fn ancestors(&self, uid: &EntityUid) -> Vec<Relation> {
+        Self::walk(uid, &self.parents)
+    }
+
+    /// Every entity that is a member of `uid`, directly or transitively.
+    pub fn descendants(&self, uid: &EntityUid) -> Vec<Relation> {
+        let mut children: HashMap<EntityUid, HashSet<EntityUid>> = HashMap::new();
+        for (child, parents) in &self.parents {
+            for parent in parents {
+                children.entry(parent.clone()).or_default().insert(child.clone());
+            }
+        }
+        Self::walk(uid, &children)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn uid(s: &str) -> EntityUid {
+        s.parse().unwrap()
+    }
+
+    /// alice -> ops -> admins, plus bob -> admins directly.
+    fn store() -> EntityStore {
+        EntityStore::from_json(
+            serde_json::json!([
+                {"uid": {"type": "User", "id": "alice"}, "attrs": {}, "parents": [{"type": "Group", "id": "ops"}]},
+                {"uid": {"type": "User", "id": "bob"}, "attrs": {}, "parents": [{"type": "Group", "id": "admins"}]},
+                {"uid": {"type": "Group", "id": "ops"}, "attrs": {}, "parents": [{"type": "Group", "id": "admins"}]},
+                {"uid": {"type": "Group", "id": "admins"}, "attrs": {}, "parents": []}
+            ]),
+            None,
+            Ingest::default(),
+        )
+        .unwrap()
+    }
+
+    fn request(json: serde_json::Value) -> Vec<Entity> {
+        parse_list(json, None).unwrap()
+    }
+
+    #[test]
+    fn ancestors_report_shortest_paths() {
+        let ancestors = store().ancestors(&uid(r#"User::"alice""#));
+        let paths: Vec<(&str, usize)> = ancestors.iter().map(|r| (r.uid.as_str(), r.path.len())).collect();
+        assert_eq!(paths, [(r#"Group::"ops""#, 2), (r#"Group::"admins""#, 3)]);
+        assert_eq!(ancestors[1].path, [r#"User::"alice""#, r#"Group::"ops""#, r#"Group::"admins""#]);
+    }
+
+    #[test]
+    fn descendants_walk_child_edges() {
+        let descendants: Vec<String> = store()
+            .descendants(&uid(r#"Group::"admins""#))
+            .into_iter()
+            .map(|r| r.uid)
+            .collect();
+        assert_eq!(descendants, [r#"Group::"ops""#, r#"User::"bob""#, r#"User::"alice""#]);
+    }
+
+    #[test]
+    fn walk_terminates_on_cycles() {
+        let (a, b) = (uid(r#"G::"a""#), uid(r#"G::"b""#));
+        let edges = HashMap::from([(a.clone(), HashSet::from([b.clone()])), (b, HashSet::from([a.clone()]))]);
+        let found = EntityStore::walk(&a, &edges);
+        assert_eq!(found.len(), 1);
+    }
+
+    #[test]
+    fn merge_without_request_entities_borrows_the_store() {
+        let store = store();
+        assert!(matches!(store.merged_with(Vec::new(), None), Ok(Cow::Borrowed(_))));
+    }
+
+    #[test]
+    fn merge_adds_request_entities_under_stored_groups() {
+        let store = store();
+        let carol = request(serde_json::json!([
+            {"uid": {"type": "User", "id": "carol"}, "attrs": {}, "parents": [{"type": "Group", "id": "ops"}]}
+        ]));
+        let merged = store.merged_with(carol, None).unwrap();
+        assert!(merged.is_ancestor_of(&uid(r#"Group::"admins""#), &uid(r#"User::"carol""#)));
+    }
+
+    #[test]
+    fn replaced_entity_drops_inherited_memberships() {
+        let store = store();
+        let ops = request(serde_json::json!([
+            {"uid": {"type": "Group", "id": "ops"}, "attrs": {}, "parents": []}
+        ]));
+        let merged = store.merged_with(ops, None).unwrap();
+        let (alice, admins) = (uid(r#"User::"alice""#), uid(r#"Group::"admins""#));
+        assert!(!merged.is_ancestor_of(&admins, &alice));
+        assert!(merged.is_ancestor_of(&uid(r#"Group::"ops""#), &alice));
+        assert!(merged.is_ancestor_of(&admins, &uid(r#"User::"bob""#)));
+    }
+
+    #[test]
+    fn budget_counts_json_size() {
+        let store = store();
+        let expected: usize = store.direct().iter().map(json_size).sum();
+        assert!(expected > 0);
+        assert_eq!(store.size(), expected);
+        assert!(store.check_budget(None).is_ok());
+        assert!(store.check_budget(Some(expected)).is_ok());
+        let err = store.check_budget(Some(expected - 1)).unwrap_err();
+        assert!(err.contains("entity memory limit"), "{}", err);
+    }
+
+    #[test]
+    fn duplicates_are_rejected_or_the_last_kept() {
+        let entities = || {
+            request(serde_json::json!([
+                {"uid": {"type": "User", "id": "alice"}, "attrs": {"level": 1}, "parents": []},
+                {"uid": {"type": "User", "id": "bob"}, "attrs": {}, "parents": []},
+                {"uid": {"type": "User", "id": "alice"}, "attrs": {"level": 2}, "parents": []}
+            ]))
+        };
+        let err = EntityStore::from_entities(entities(), None, Ingest::default()).err().unwrap();
+        assert!(err.contains("duplicate"), "{}", err);
+
+        let keep_last = Ingest {
+            duplicates: EntityDuplicates::KeepLast,
+            ..Default::default()
+        };
+        let store = EntityStore::from_entities(entities(), None, keep_last).unwrap();
+        assert_eq!(store.len(), 2);
+        let alice = store.entities().get(&uid(r#"User::"alice""#)).unwrap();
+        assert_eq!(alice.attr("level").unwrap().unwrap().to_string(), "2");
+        assert!(store.merged_with(entities(), None).is_ok());
+    }
+
+    #[test]
+    fn validation_reports_every_violation() {
+        let (schema, _) = Schema::from_cedarschema_str(
+            "entity Group; entity User in [Group] { level: Long }; action view appliesTo { principal: User, resource: Group };",
+        )
+        .unwrap();
+        let report = validate(
+            serde_json::json!([
+                {"uid": {"type": "User", "id": "alice"}, "attrs": {"level": "high"}, "parents": []},
+                {"uid": {"type": "User", "id": "bob"}, "attrs": {}, "parents": []},
+                {"uid": {"type": "User", "id": "carol"}, "attrs": {"level": 1}, "parents": [{"type": "User", "id": "bob"}]},
+                {"uid": {"type": "Group", "id": "ops"}, "attrs": {}, "parents": []},
+                {"uid": {"type": "Group", "id": "ops"}, "attrs": {}, "parents": [{"type": "Group", "id": "all"}]}
+            ]),
+            Some(&schema),
+            Ingest::default(),
+        )
+        .unwrap();
+        assert!(!report.valid);
+        let flagged: Vec<Option<usize>> = report.violations.iter().map(|v| v.index).collect();
+        assert_eq!(flagged, [Some(0), Some(1), Some(2), Some(4)]);
+        assert_eq!(report.violations[0].uid.as_deref(), Some(r#"User::"alice""#));
+
+        let report = validate(
+            serde_json::json!([{"uid": {"type": "Group", "id": "ops"}, "attrs": {}, "parents": []}]),
+            Some(&schema),
+            Ingest::default(),
+        )
+        .unwrap();
+        assert!(report.valid);
+        assert!(validate(serde_json::json!({}), None, Ingest::default()).is_err());
+    }
+
+    #[test]
+    fn writes_replace_or_upsert() {
+        let current = store().direct().to_vec();
+        let payload = || {
+            request(serde_json::json!([
+                {"uid": {"type": "Group", "id": "ops"}, "attrs": {}, "parents": []},
+                {"uid": {"type": "Group", "id": "sre"}, "attrs": {}, "parents": []}
+            ]))
+        };
+        let uids = |entities: &[Entity]| {
+            let mut uids: Vec<String> = entities.iter().map(|e| e.uid().to_string()).collect();
+            uids.sort();
+            uids
+        };
+
+        let (all, summary) = apply(&current, payload(), &Write::Replace(None)).unwrap();
+        assert_eq!(uids(&all), [r#"Group::"ops""#, r#"Group::"sre""#]);
+        assert_eq!((summary.written, summary.removed), (2, 3));
+
+        let groups = Write::Replace(Some("Group".parse().unwrap()));
+        let (typed, summary) = apply(&current, payload(), &groups).unwrap();
+        assert_eq!(uids(&typed), [r#"Group::"ops""#, r#"Group::"sre""#, r#"User::"alice""#, r#"User::"bob""#]);
+        assert_eq!(summary.removed, 1);
+        let users = Write::Replace(Some("User".parse().unwrap()));
+        assert!(apply(&current, payload(), &users).is_err());
+
+        let (upserted, summary) = apply(&current, payload(), &Write::Upsert).unwrap();
+        assert_eq!(upserted.len(), 5);
+        assert_eq!(summary.removed, 0);
+        let ops = upserted.iter().find(|e| e.uid() == uid(r#"Group::"ops""#)).unwrap();
+        assert_eq!(ops.clone().into_inner().2.len(), 0);
+    }
+
+    #[test]
+    fn provided_hierarchies_are_used_as_given() {
+        let json = serde_json::json!([
+            {"uid": {"type": "User", "id": "alice"}, "attrs": {}, "parents": [{"type": "Group", "id": "ops"}]},
+            {"uid": {"type": "Group", "id": "ops"}, "attrs": {}, "parents": [{"type": "Group", "id": "admins"}]},
+            {"uid": {"type": "Group", "id": "admins"}, "attrs": {}, "parents": [{"type": "Group", "id": "ops"}]}
+        ]);
+        let err = EntityStore::from_json(json.clone(), None, Ingest::default()).err().unwrap();
+        assert!(err.to_string().contains("cycle"), "{}", err);
+
+        let provided = Ingest {
+            hierarchy: EntityHierarchy::Provided,
+            ..Default::default()
+        };
+        let store = EntityStore::from_json(json, None, provided).unwrap();
+        let (alice, ops, admins) = (uid(r#"User::"alice""#), uid(r#"Group::"ops""#), uid(r#"Group::"admins""#));
+        assert!(store.entities().is_ancestor_of(&ops, &alice));
+        assert!(!store.entities().is_ancestor_of(&admins, &alice));
+    }
+
+    #[test]
+    fn finds_dangling_parents_and_cycles() {
+        let (alice, a, b, c, ghost) = (
+            uid(r#"User::"alice""#),
+            uid(r#"G::"a""#),
+            uid(r#"G::"b""#),
+            uid(r#"G::"c""#),
+            uid(r#"G::"ghost""#),
+        );
+        let parents = HashMap::from([
+            (alice.clone(), HashSet::from([a.clone(), ghost.clone(), uid(r#"Action::"all""#)])),
+            (a.clone(), HashSet::from([b.clone()])),
+            (b.clone(), HashSet::from([c.clone()])),
+            (c.clone(), HashSet::from([a.clone()])),
+        ]);
+        let problems = graph_problems(&parents);
+        assert_eq!(
+            problems,
+            [
+                GraphProblem::Dangling {
+                    uid: alice.to_string(),
+                    parent: ghost.to_string()
+                },
+                GraphProblem::Cycle(vec![a.to_string(), b.to_string(), c.to_string()]),
+            ]
+        );
+        assert_eq!(problems[1].to_string(), r#"membership cycle G::"a" -> G::"b" -> G::"c" -> G::"a""#);
+    }
+
+    #[test]
+    fn integrity_warns_or_rejects() {
+        let json = serde_json::json!([
+            {"uid": {"type": "User", "id": "alice"}, "attrs": {}, "parents": [{"type": "Group", "id": "ghost"}]}
+        ]);
+        assert!(EntityStore::from_json(json.clone(), None, Ingest::default()).is_ok());
+        let reject = Ingest {
+            integrity: EntityIntegrity::Reject,
+            ..Default::default()
+        };
+        let err = EntityStore::from_json(json.clone(), None, reject).err().unwrap();
+        assert!(err.to_string().contains(r#"User::"alice" has parent Group::"ghost""#), "{}", err);
+
+        let report = validate(json, None, reject).unwrap();
+        assert!(!report.valid);
+        assert_eq!(report.violations[0].uid.as_deref(), Some(r#"User::"alice""#));
+    }
+}
@@ -0,0 +1,323 @@
+use cedar_policy::{Authorizer, Effect, Entities, Policy, PolicyId, PolicySet, Request};
+use serde::Serialize;
+use serde_json::{json, Value};
+
+/// Outcome of evaluating one expression on its own.
+#[derive(Debug, Serialize)]
+pub struct TermResult {
+    pub condition: String,
+    /// `true`, `false` or `error`.
+    pub result: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// One `when`/`unless` clause. `terms` breaks a `when { a && b }` (or `unless { a || b }`) into
+/// its top-level operands, each evaluated independently.
+#[derive(Debug, Serialize)]
+pub struct ConditionResult {
+    pub kind: String,
+    #[serde(flatten)]
+    pub body: TermResult,
+    /// Whether the clause lets the policy apply: a `when` body that is true, or an `unless`
+    /// body that is false.
+    pub passed: bool,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub terms: Vec<TermResult>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PolicyExplanation {
+    pub id: String,
+    pub effect: &'static str,
+    pub scope_matched: bool,
+    /// Whether the whole policy was satisfied (scope and all conditions).
+    pub satisfied: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub conditions: Vec<ConditionResult>,
+}
+
+/// A scope element or condition clause that kept a permit policy from applying.
+#[derive(Debug, Serialize)]
+pub struct Failure {
+    /// `principal`, `action`, `resource`, `when` or `unless`.
+    pub element: String,
+    pub constraint: String,
+    /// The top-level terms of a failed condition that did not hold.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub failed_terms: Vec<TermResult>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// A permit policy that did not apply, and every part of it that failed.
+#[derive(Debug, Serialize)]
+pub struct NearMiss {
+    pub id: String,
+    pub failures: Vec<Failure>,
+}
+
+/// An unconstrained scope element.
+fn all() -> Value {
+    json!({ "op": "All" })
+}
+
+/// Evaluates a single-policy set; `Ok(true)` when satisfied, `Err` on evaluation error.
+fn evaluate_alone(policy: Policy, request: &Request, entities: &Entities) -> Result<bool, String> {
+    let policy_set = PolicySet::from_policies([policy]).map_err(|e| e.to_string())?;
+    let response = Authorizer::new().is_authorized(request, &policy_set, entities);
+    if let Some(err) = response.diagnostics().errors().next() {
+        return Err(err.to_string());
+    }
+    let satisfied = response.diagnostics().reason().next().is_some();
+    Ok(satisfied)
+}
+
+/// Evaluates `expr` as the only condition of an unscoped permit.
+fn evaluate_term(id: &str, expr: &Value, request: &Request, entities: &Entities) -> TermResult {
+    let est = json!({
+        "effect": "permit",
+        "principal": all(),
+        "action": all(),
+        "resource": all(),
+        "conditions": [{ "kind": "when", "body": expr }],
+    });
+
+    let policy = match Policy::from_json(Some(PolicyId::new(id)), est) {
+        Ok(policy) => policy,
+        Err(e) => {
+            return TermResult {
+                condition: expr.to_string(),
+                result: "error",
+                error: Some(e.to_string()),
+            }
+        }
+    };
+
+    // Render just the expression from `permit(...) when { <expr> };`
+    let rendered = policy.to_cedar().unwrap_or_else(|| policy.to_string());
+    let condition = rendered
+        .split_once("when {")
+        .and_then(|(_, rest)| rest.trim_end().strip_suffix("};"))
+        .map(str::trim)
+        .unwrap_or(&rendered)
+        .to_string();
+
+    match evaluate_alone(policy, request, entities) {
+        Ok(value) => TermResult {
+            condition,
+            result: if value { "true" } else { "false" },
+            error: None,
+        },
+        Err(e) => TermResult {
+            condition,
+            result: "error",
+            error: Some(e),
+        },
+    }
+}
+
+/// Splits a binary `op` chain (`a && b && c`) into its operands.
+fn operands<'a>(expr: &'a Value, op: &str, out: &mut Vec<&'a Value>) {
+    match expr.get(op) {
+        Some(bin) => {
+            if let (Some(left), Some(right)) = (bin.get("left"), bin.get("right")) {
+                operands(left, op, out);
+                operands(right, op, out);
+            } else {
+                out.push(expr);
+            }
+        }
+        None => out.push(expr),
+    }
+}
+
+/// Explains how `policy` evaluates for `request`: whether its scope matched, and the value of
+/// each condition clause and its top-level terms.
+pub fn explain_policy(policy: &Policy, request: &Request, entities: &Entities) -> PolicyExplanation {
+    let id = policy.id().to_string();
+    let effect = match policy.effect() {
+        Effect::Permit => "permit",
+        Effect::Forbid => "forbid",
+    };
+
+    let overall = evaluate_alone(policy.clone(), request, entities);
+    let (satisfied, error) = match overall {
+        Ok(satisfied) => (satisfied, None),
+        Err(e) => (false, Some(e)),
+    };
+
+    let est = match policy.to_json() {
+        Ok(est) => est,
+        Err(e) => {
+            return PolicyExplanation {
+                id,
+                effect,
+                scope_matched: false,
+                satisfied,
+                error: Some(format!("Cannot explain policy: {}", e)),
+                conditions: Vec::new(),
+            }
+        }
+    };
+
+    // The scope alone: same head, no conditions, as a permit
+    let mut scope_only = est.clone();
+    scope_only["effect"] = json!("permit");
+    scope_only["conditions"] = json!([]);
+    let scope_matched = Policy::from_json(Some(policy.id().clone()), scope_only)
+        .map_err(|e| e.to_string())
+        .and_then(|p| evaluate_alone(p, request, entities))
+        .unwrap_or(false);
+
+    let conditions = est["conditions"]
+        .as_array()
+        .map(Vec::as_slice)
+        .unwrap_or_default()
+        .iter()
+        .map(|clause| {
+            let kind = clause["kind"].as_str().unwrap_or("when").to_string();
+            let body = evaluate_term(&id, &clause["body"], request, entities);
+            let passed = matches!((kind.as_str(), body.result), ("when", "true") | ("unless", "false"));
+
+            let mut parts = Vec::new();
+            operands(&clause["body"], if kind == "when" { "&&" } else { "||" }, &mut parts);
+            let terms = if parts.len() > 1 {
+                parts
+                    .into_iter()
+                    .map(|part| evaluate_term(&id, part, request, entities))
+                    .collect()
+            } else {
+                Vec::new()
+            };
+
+            ConditionResult {
+                kind,
+                body,
+                passed,
+                terms,
+            }
+        })
+        .collect();
+
+    PolicyExplanation {
+        id,
+        effect,
+        scope_matched,
+        satisfied,
+        error,
+        conditions,
+    }
+}
+
+/// The scope elements and condition clauses of `policy` that fail for `request`, or `None` when
+/// it applies.
+pub fn near_miss(policy: &Policy, request: &Request, entities: &Entities) -> Option<NearMiss> {
+    if matches!(evaluate_alone(policy.clone(), request, entities), Ok(true)) {
+        return None;
+    }
+    let id = policy.id().to_string();
+    let Ok(est) = policy.to_json() else {
+        return Some(NearMiss { id, failures: Vec::new() });
+    };
+
+    let mut failures = Vec::new();
+    for element in ["principal", "action", "resource"] {
+        if est[element] == all() {
+            continue;
+        }
+        // Only this element constrained
+        let mut alone = json!({ "effect": "permit", "principal": all(), "action": all(), "resource": all(), "conditions": [] });
+        alone[element] = est[element].clone();
+        let Ok(alone) = Policy::from_json(Some(policy.id().clone()), alone) else {
+            continue;
+        };
+        let constraint = scope_constraint(&alone, element);
+        match evaluate_alone(alone, request, entities) {
+            Ok(true) => {}
+            result => failures.push(Failure {
+                element: element.to_string(),
+                constraint,
+                failed_terms: Vec::new(),
+                error: result.err(),
+            }),
+        }
+    }
+
+    let explanation = explain_policy(policy, request, entities);
+    for clause in explanation.conditions.into_iter().filter(|clause| !clause.passed) {
+        let held = if clause.kind == "when" { "true" } else { "false" };
+        failures.push(Failure {
+            failed_terms: clause.terms.into_iter().filter(|term| term.result != held).collect(),
+            element: clause.kind,
+            constraint: clause.body.condition,
+            error: clause.body.error,
+        });
+    }
+    Some(NearMiss { id, failures })
+}
+
+/// The constraint on `element` of a policy whose other scope elements are unconstrained, as
+/// written in its head, e.g. `principal in Group::"admins"`.
+fn scope_constraint(policy: &Policy, element: &str) -> String {
+    let text = policy.to_string().split_whitespace().collect::<Vec<_>>().join(" ");
+    let head = text
+        .strip_prefix("permit(")
+        .and_then(|rest| rest.rsplit_once(')'))
+        .map_or(text.as_str(), |(head, _)| head.trim());
+    let constraint = match element {
+        "principal" => head.strip_suffix(", action, resource"),
+        "action" => head.strip_prefix("principal, ").and_then(|rest| rest.strip_suffix(", resource")),
+        _ => head.strip_prefix("principal, action, "),
+    };
+    constraint.unwrap_or(head).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn reports_what_kept_a_policy_from_applying() {
+        let policy_set = crate::policies::parse(
+            r#"@id("owner") permit(principal == User::"bob", action == Action::"edit", resource)
+                   when { resource.public && principal.level > 2 };
+               @id("viewer") permit(principal, action in [Action::"view", Action::"list"], resource);"#,
+        )
+        .unwrap();
+        let entities = Entities::from_json_value(
+            json!([
+                { "uid": { "type": "User", "id": "alice" }, "attrs": { "level": 3 }, "parents": [] },
+                { "uid": { "type": "Doc", "id": "d1" }, "attrs": { "public": false }, "parents": [] }
+            ]),
+            None,
+        )
+        .unwrap();
+        let request = |action: &str| {
+            Request::new(
+                FromStr::from_str(r#"User::"alice""#).unwrap(),
+                FromStr::from_str(action).unwrap(),
+                FromStr::from_str(r#"Doc::"d1""#).unwrap(),
+                cedar_policy::Context::empty(),
+                None,
+            )
+            .unwrap()
+        };
+        let policy = |id: &str| policy_set.policy(&PolicyId::new(id)).unwrap();
+
+        let miss = near_miss(policy("owner"), &request(r#"Action::"edit""#), &entities).unwrap();
+        let failed: Vec<(&str, &str)> =
+            miss.failures.iter().map(|f| (f.element.as_str(), f.constraint.as_str())).collect();
+        assert_eq!(failed[0], ("principal", r#"principal == User::"bob""#));
+        assert_eq!(failed[1].0, "when");
+        assert_eq!(failed.len(), 2);
+        let terms: Vec<&str> = miss.failures[1].failed_terms.iter().map(|t| t.condition.as_str()).collect();
+        assert_eq!(terms, ["resource.public"]);
+
+        let miss = near_miss(policy("viewer"), &request(r#"Action::"edit""#), &entities).unwrap();
+        assert_eq!(miss.failures[0].constraint, r#"action in [Action::"view", Action::"list"]"#);
+        assert!(near_miss(policy("viewer"), &request(r#"Action::"view""#), &entities).is_none());
+    }
+}
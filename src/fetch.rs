@@ -0,0 +1,431 @@
+#[cfg(feature = "sql")]
+use crate::fetch_sql;
+use crate::{fetch_graphql, fetch_rest};
+use crate::config::Config;
+use crate::fetch_cache::Cache;
+use crate::metrics::Metrics;
+use cedar_policy::{EntityTypeName, EntityUid};
+use hyper::client::HttpConnector;
+use hyper::{Body, Client, Request, StatusCode};
+use hyper_rustls::HttpsConnector;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::time::{Duration, Instant};
+
+pub type HttpClient = Client<HttpsConnector<HttpConnector>>;
+
+/// A client for `http://` and `https://` URLs that trusts the system's CA certificates.
+pub fn https_client() -> Result<HttpClient, String> {
+    // hyper-rustls panics on an empty trust store, so check for one first
+    let roots = rustls_native_certs::load_native_certs().map_err(|e| format!("Failed to load CA certificates: {}", e))?;
+    if roots.is_empty() {
+        return Err("No CA certificates found".to_string());
+    }
+    let connector = hyper_rustls::HttpsConnectorBuilder::new()
+        .with_native_roots()
+        .https_or_http()
+        .enable_http1()
+        .build();
+    Ok(Client::builder().build(connector))
+}
+
+/// How much of an upstream's error response is quoted in the error.
+const MAX_QUOTED_BYTES: usize = 200;
+
+/// One entry of the `CEDAR_ENTITY_FETCHERS` file.
+#[derive(Debug, Deserialize)]
+struct FetcherConfig {
+    entity_type: String,
+    #[serde(flatten)]
+    source: SourceConfig,
+    #[serde(flatten)]
+    mapping: Mapping,
+    #[serde(default = "default_timeout_ms")]
+    timeout_ms: u64,
+    /// Overrides `CEDAR_ENTITY_CACHE_TTL_SECS` for this fetcher; 0 disables caching.
+    #[serde(default)]
+    cache_ttl_secs: Option<u64>,
+}
+
+fn default_timeout_ms() -> u64 {
+    2000
+}
+
+/// Where a fetcher gets its entities from, as configured.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum SourceConfig {
+    Graphql(fetch_graphql::Query),
+    Rest(fetch_rest::Endpoint),
+    #[cfg(feature = "sql")]
+    Sql(fetch_sql::Config),
+    #[cfg(not(feature = "sql"))]
+    Sql(serde::de::IgnoredAny),
+}
+
+/// Checks that a URL (with any placeholders filled in) is http:// or https://.
+fn check_http_url(url: &str) -> Result<(), String> {
+    let url: hyper::Uri = url.parse().map_err(|e| format!("Invalid URL: {}", e))?;
+    match url.scheme_str() {
+        Some("http" | "https") => Ok(()),
+        _ => Err("URL must be http:// or https://".to_string()),
+    }
+}
+
+impl SourceConfig {
+    fn build(self) -> Result<Source, String> {
+        match self {
+            SourceConfig::Graphql(query) => {
+                check_http_url(&query.url)?;
+                Ok(Source::Graphql(query))
+            }
+            SourceConfig::Rest(endpoint) => {
+                check_http_url(&endpoint.url.replace("{type}", "type").replace("{id}", "id"))?;
+                Ok(Source::Rest(endpoint))
+            }
+            #[cfg(feature = "sql")]
+            SourceConfig::Sql(config) => Ok(Source::Sql(config.build()?)),
+            #[cfg(not(feature = "sql"))]
+            SourceConfig::Sql(_) => Err("SQL fetchers need a build with the `sql` feature".to_string()),
+        }
+    }
+}
+
+/// Where a fetcher gets its entities from.
+enum Source {
+    Graphql(fetch_graphql::Query),
+    Rest(fetch_rest::Endpoint),
+    #[cfg(feature = "sql")]
+    Sql(fetch_sql::Query),
+}
+
+impl Source {
+    fn kind(&self) -> &'static str {
+        match self {
+            Source::Graphql(_) => "graphql",
+            Source::Rest(_) => "rest",
+            #[cfg(feature = "sql")]
+            Source::Sql(_) => "sql",
+        }
+    }
+
+    async fn fetch(&self, client: &HttpClient, uid: &EntityUid) -> Result<Option<Value>, String> {
+        match self {
+            Source::Graphql(query) => query.fetch(client, uid).await,
+            Source::Rest(endpoint) => endpoint.fetch(client, uid).await,
+            #[cfg(feature = "sql")]
+            Source::Sql(query) => query.fetch(uid).await,
+        }
+    }
+}
+
+/// How an upstream result becomes an entity.
+#[derive(Debug, Default, Deserialize)]
+pub struct Mapping {
+    /// Entity attribute to path in the result; by default every field but the parents' is an
+    /// attribute under its own name.
+    #[serde(default)]
+    attributes: Option<BTreeMap<String, String>>,
+    #[serde(default)]
+    parents: Vec<Parent>,
+}
+
+/// A path in the result holding parent IDs: an ID, an object with one, or a list of either.
+#[derive(Debug, Deserialize)]
+pub struct Parent {
+    path: String,
+    #[serde(rename = "type")]
+    entity_type: String,
+    /// Field holding the ID when the parents are objects.
+    #[serde(default = "default_id_field")]
+    id: String,
+}
+
+fn default_id_field() -> String {
+    "id".to_string()
+}
+
+/// The value at a dot-separated path such as `user.groups.0`; the empty path is the value itself.
+pub fn lookup<'a>(value: &'a Value, path: &str) -> Option<&'a Value> {
+    if path.is_empty() {
+        return Some(value);
+    }
+    path.split('.').try_fold(value, |value, segment| match value {
+        Value::Object(fields) => fields.get(segment),
+        Value::Array(items) => segment.parse::<usize>().ok().and_then(|i| items.get(i)),
+        _ => None,
+    })
+}
+
+/// Replaces `{type}` and `{id}` in a template with the parts of `uid`.
+pub fn substitute(template: &str, uid: &EntityUid) -> String {
+    template
+        .replace("{type}", &uid.type_name().to_string())
+        .replace("{id}", uid.id().unescaped())
+}
+
+/// An ID given as a string or a number.
+fn id_of(value: &Value) -> Option<String> {
+    match value {
+        Value::String(id) => Some(id.clone()),
+        Value::Number(id) => Some(id.to_string()),
+        _ => None,
+    }
+}
+
+impl Mapping {
+    /// The entity `result` describes, in Cedar's JSON format. Null attributes are left out, so
+    /// optional attributes can be absent upstream.
+    pub fn to_entity(&self, uid: &EntityUid, result: &Value) -> Result<Value, String> {
+        let Value::Object(fields) = result else {
+            return Err(format!("Expected an object for {}, got {}", uid, result));
+        };
+
+        let mut attrs = Map::new();
+        match self.attributes {
+            Some(ref attributes) => {
+                for (attr, path) in attributes {
+                    if let Some(value) = lookup(result, path).filter(|v| !v.is_null()) {
+                        attrs.insert(attr.clone(), value.clone());
+                    }
+                }
+            }
+            None => {
+                let parent_fields: Vec<&str> = self.parents.iter().map(|p| p.path.split('.').next().unwrap_or_default()).collect();
+                for (name, value) in fields {
+                    if !parent_fields.contains(&name.as_str()) && !value.is_null() {
+                        attrs.insert(name.clone(), value.clone());
+                    }
+                }
+            }
+        }
+
+        let mut parents = Vec::new();
+        for parent in &self.parents {
+            let values = match lookup(result, &parent.path) {
+                None | Some(Value::Null) => continue,
+                Some(Value::Array(items)) => items.iter().collect(),
+                Some(value) => vec![value],
+            };
+            for value in values {
+                let id = match value {
+                    Value::Object(_) => lookup(value, &parent.id).and_then(id_of),
+                    value => id_of(value),
+                };
+                let Some(id) = id else {
+                    return Err(format!("Parent at `{}` of {} has no usable ID: {}", parent.path, uid, value));
+                };
+                parents.push(serde_json::json!({"type": parent.entity_type, "id": id}));
+            }
+        }
+
+        Ok(serde_json::json!({
+            "uid": {"type": uid.type_name().to_string(), "id": uid.id().unescaped()},
+            "attrs": attrs,
+            "parents": parents,
+        }))
+    }
+}
+
+/// Sends a request and reads a JSON response. `None` is a 404; other unsuccessful statuses are
+/// errors quoting the start of the body.
+pub async fn send(client: &HttpClient, request: Request<Body>) -> Result<Option<Value>, String> {
+    let response = client.request(request).await.map_err(|e| format!("Request failed: {}", e))?;
+    let status = response.status();
+    let body = hyper::body::to_bytes(response.into_body())
+        .await
+        .map_err(|e| format!("Failed to read response: {}", e))?;
+    if status == StatusCode::NOT_FOUND {
+        return Ok(None);
+    }
+    if !status.is_success() {
+        let quoted = String::from_utf8_lossy(&body[..body.len().min(MAX_QUOTED_BYTES)]).into_owned();
+        return Err(format!("Upstream answered {}: {}", status, quoted));
+    }
+    serde_json::from_slice(&body)
+        .map(Some)
+        .map_err(|e| format!("Invalid JSON response: {}", e))
+}
+
+struct Fetcher {
+    source: Source,
+    mapping: Mapping,
+    timeout: Duration,
+    /// How long fetched entities are cached; zero for not at all.
+    cache_ttl: Duration,
+}
+
+/// Fetches principals and resources on demand from the upstream services listed in
+/// `CEDAR_ENTITY_FETCHERS`, one per entity type, for entities that neither the request nor
+/// the entity store holds.
+pub struct Fetchers {
+    by_type: HashMap<EntityTypeName, Fetcher>,
+    client: HttpClient,
+    cache: Cache,
+}
+
+impl Fetchers {
+    /// Reads and checks the fetcher definitions, a JSON array.
+    pub fn load(path: &str, config: &Config) -> Result<Self, Box<dyn std::error::Error>> {
+        let json = fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+        let configs: Vec<FetcherConfig> =
+            serde_json::from_str(&json).map_err(|e| format!("Failed to parse {}: {}", path, e))?;
+
+        let cache_ttl = config.entity_cache_ttl;
+        let mut by_type = HashMap::new();
+        for config in configs {
+            let entity_type: EntityTypeName = config
+                .entity_type
+                .parse()
+                .map_err(|e| format!("Invalid entity type {}: {}", config.entity_type, e))?;
+            let source = config
+                .source
+                .build()
+                .map_err(|e| format!("Invalid fetcher for {}: {}", entity_type, e))?;
+            for parent in &config.mapping.parents {
+                parent
+                    .entity_type
+                    .parse::<EntityTypeName>()
+                    .map_err(|e| format!("Invalid parent type {} for {}: {}", parent.entity_type, entity_type, e))?;
+            }
+            if config.timeout_ms == 0 {
+                return Err(format!("timeout_ms for {} must be greater than zero", entity_type).into());
+            }
+            let fetcher = Fetcher {
+                source,
+                mapping: config.mapping,
+                timeout: Duration::from_millis(config.timeout_ms),
+                cache_ttl: config.cache_ttl_secs.map_or(cache_ttl, Duration::from_secs),
+            };
+            if by_type.insert(entity_type.clone(), fetcher).is_some() {
+                return Err(format!("More than one fetcher for {}", entity_type).into());
+            }
+        }
+
+        Ok(Self {
+            by_type,
+            client: https_client().map_err(|e| format!("{} for the entity fetchers", e))?,
+            cache: Cache::new(config.entity_cache_max_entries),
+        })
+    }
+
+    /// The entity types fetched on demand.
+    pub fn types(&self) -> impl Iterator<Item = &EntityTypeName> {
+        self.by_type.keys()
+    }
+
+    /// Whether entities of `uid`'s type are fetched on demand.
+    pub fn handles(&self, uid: &EntityUid) -> bool {
+        self.by_type.contains_key(uid.type_name())
+    }
+
+    /// Fetches one entity, in Cedar's JSON format, through the cache; `None` if the upstream
+    /// does not know it.
+    pub async fn fetch(&self, uid: &EntityUid, metrics: &Metrics) -> Result<Option<Value>, String> {
+        let Some(fetcher) = self.by_type.get(uid.type_name()) else {
+            return Ok(None);
+        };
+        if fetcher.cache_ttl.is_zero() {
+            return self.fetch_uncached(fetcher, uid, metrics).await;
+        }
+        let (result, hit) = self
+            .cache
+            .get_or_fetch(uid, fetcher.cache_ttl, || self.fetch_uncached(fetcher, uid, metrics))
+            .await;
+        metrics.incr("entity_cache_requests", &[("result", if hit { "hit" } else { "miss" })]);
+        metrics.gauge("entity_cache_entries", &[], self.cache.len() as f64);
+        result
+    }
+
+    /// Drops cached entities: the one with `uid`, those of `entity_type`, or with neither all of
+    /// them. Returns how many were dropped.
+    pub fn invalidate(&self, uid: Option<&EntityUid>, entity_type: Option<&EntityTypeName>, metrics: &Metrics) -> usize {
+        let dropped = self.cache.invalidate(|cached| {
+            uid.is_none_or(|uid| uid == cached) && entity_type.is_none_or(|t| t == cached.type_name())
+        });
+        metrics.gauge("entity_cache_entries", &[], self.cache.len() as f64);
+        dropped
+    }
+
+    async fn fetch_uncached(&self, fetcher: &Fetcher, uid: &EntityUid, metrics: &Metrics) -> Result<Option<Value>, String> {
+        let kind = fetcher.source.kind();
+        let started = Instant::now();
+        // The caller's deadline may leave less time than the fetcher's timeout
+        let timeout = crate::deadline::bound(fetcher.timeout);
+        let result = match tokio::time::timeout(timeout, fetcher.source.fetch(&self.client, uid)).await {
+            Ok(result) => result,
+            Err(_) => Err(format!("Timed out after {}ms", timeout.as_millis())),
+        };
+        let result = result.and_then(|found| found.map(|found| fetcher.mapping.to_entity(uid, &found)).transpose());
+        let outcome = match result {
+            Ok(Some(_)) => "found",
+            Ok(None) => "missing",
+            Err(_) => "error",
+        };
+        metrics.incr("entity_fetches", &[("fetcher", kind), ("result", outcome)]);
+        metrics.observe("entity_fetch_duration", &[("fetcher", kind)], started.elapsed());
+        result.map_err(|e| format!("Failed to fetch {}: {}", uid, e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn uid(s: &str) -> EntityUid {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn looks_up_dotted_paths() {
+        let value = serde_json::json!({"user": {"groups": [{"id": "ops"}]}});
+        assert_eq!(lookup(&value, "user.groups.0.id"), Some(&Value::from("ops")));
+        assert_eq!(lookup(&value, ""), Some(&value));
+        assert_eq!(lookup(&value, "user.groups.1"), None);
+        assert_eq!(lookup(&value, "user.name"), None);
+    }
+
+    #[test]
+    fn maps_results_to_entities() {
+        let result = serde_json::json!({
+            "email": "alice@example.org",
+            "manager": null,
+            "groups": [{"id": "ops"}, {"id": 7}],
+            "team": "payments",
+        });
+        let mapping: Mapping = serde_json::from_value(serde_json::json!({
+            "parents": [{"path": "groups", "type": "Group"}, {"path": "team", "type": "Team"}],
+        }))
+        .unwrap();
+        assert_eq!(
+            mapping.to_entity(&uid(r#"User::"alice""#), &result).unwrap(),
+            serde_json::json!({
+                "uid": {"type": "User", "id": "alice"},
+                "attrs": {"email": "alice@example.org"},
+                "parents": [{"type": "Group", "id": "ops"}, {"type": "Group", "id": "7"}, {"type": "Team", "id": "payments"}],
+            })
+        );
+
+        let mapping: Mapping =
+            serde_json::from_value(serde_json::json!({"attributes": {"mail": "email", "boss": "manager"}})).unwrap();
+        assert_eq!(
+            mapping.to_entity(&uid(r#"User::"alice""#), &result).unwrap()["attrs"],
+            serde_json::json!({"mail": "alice@example.org"})
+        );
+
+        assert!(mapping.to_entity(&uid(r#"User::"alice""#), &Value::from("alice")).is_err());
+        let mapping: Mapping =
+            serde_json::from_value(serde_json::json!({"parents": [{"path": "groups", "type": "Group", "id": "name"}]})).unwrap();
+        assert!(mapping.to_entity(&uid(r#"User::"alice""#), &result).is_err());
+    }
+
+    #[test]
+    fn substitutes_uid_parts() {
+        assert_eq!(
+            substitute("/{type}/{id}", &uid(r#"App::User::"a\"b""#)),
+            r#"/App::User/a"b"#
+        );
+    }
+}
@@ -0,0 +1,200 @@
+use cedar_policy::EntityUid;
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// A fetched entity, or the upstream's answer that there is none, until it expires.
+struct Cached {
+    entity: Option<Value>,
+    expires: Instant,
+}
+
+/// Locked while the entity is being fetched, so concurrent requests for it wait for that fetch
+/// instead of each calling the upstream.
+type Slot = Arc<tokio::sync::Mutex<Option<Cached>>>;
+
+/// The slots by entity, and the entities by when they were last requested, least recent first.
+#[derive(Default)]
+struct Slots {
+    by_uid: HashMap<EntityUid, (Slot, u64)>,
+    by_use: BTreeMap<u64, EntityUid>,
+    clock: u64,
+}
+
+impl Slots {
+    /// The slot for `uid`, marked as the most recently requested, with room made for it by
+    /// dropping the least recently requested entries first.
+    fn get(&mut self, uid: &EntityUid, max_entries: usize) -> Slot {
+        self.clock += 1;
+        if let Some((slot, used)) = self.by_uid.get_mut(uid) {
+            self.by_use.remove(used);
+            *used = self.clock;
+            self.by_use.insert(self.clock, uid.clone());
+            return Arc::clone(slot);
+        }
+        // An entry dropped while it is being fetched still answers the requests waiting for it
+        while self.by_uid.len() >= max_entries.max(1) {
+            let Some((_, oldest)) = self.by_use.pop_first() else {
+                break;
+            };
+            self.by_uid.remove(&oldest);
+        }
+        let slot = Slot::default();
+        self.by_uid.insert(uid.clone(), (Arc::clone(&slot), self.clock));
+        self.by_use.insert(self.clock, uid.clone());
+        slot
+    }
+
+    fn retain(&mut self, keep: impl Fn(&EntityUid) -> bool) {
+        self.by_uid.retain(|uid, _| keep(uid));
+        self.by_use.retain(|_, uid| keep(uid));
+    }
+}
+
+/// Read-through cache in front of the entity fetchers, holding at most `max_entries` entities
+/// and dropping the least recently requested to make room. Failed fetches are not cached.
+pub struct Cache {
+    max_entries: usize,
+    slots: Mutex<Slots>,
+}
+
+impl Cache {
+    pub fn new(max_entries: usize) -> Self {
+        Self {
+            max_entries,
+            slots: Mutex::new(Slots::default()),
+        }
+    }
+
+    /// The cached entity for `uid`, or the result of `fetch`, kept for `ttl` if it succeeds.
+    /// The flag is whether the cache answered.
+    pub async fn get_or_fetch<F, Fut>(&self, uid: &EntityUid, ttl: Duration, fetch: F) -> (Result<Option<Value>, String>, bool)
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<Option<Value>, String>>,
+    {
+        let slot = self.slots.lock().unwrap().get(uid, self.max_entries);
+
+        let mut cached = slot.lock().await;
+        if let Some(ref entry) = *cached {
+            if entry.expires > Instant::now() {
+                return (Ok(entry.entity.clone()), true);
+            }
+        }
+        let result = fetch().await;
+        if let Ok(ref entity) = result {
+            *cached = Some(Cached {
+                entity: entity.clone(),
+                expires: Instant::now() + ttl,
+            });
+        }
+        (result, false)
+    }
+
+    /// Drops the entries `uid`s match, returning how many there were.
+    pub fn invalidate(&self, matches: impl Fn(&EntityUid) -> bool) -> usize {
+        let mut slots = self.slots.lock().unwrap();
+        let before = slots.by_uid.len();
+        slots.retain(|uid| !matches(uid));
+        before - slots.by_uid.len()
+    }
+
+    pub fn len(&self) -> usize {
+        self.slots.lock().unwrap().by_uid.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn uid(s: &str) -> EntityUid {
+        s.parse().unwrap()
+    }
+
+    #[tokio::test]
+    async fn fetches_once_for_concurrent_requests() {
+        let cache = Arc::new(Cache::new(10));
+        let calls = Arc::new(AtomicUsize::new(0));
+        let requests = (0..8).map(|_| {
+            let (cache, calls) = (Arc::clone(&cache), Arc::clone(&calls));
+            tokio::spawn(async move {
+                cache
+                    .get_or_fetch(&uid(r#"User::"alice""#), Duration::from_secs(60), || async {
+                        calls.fetch_add(1, Ordering::SeqCst);
+                        tokio::time::sleep(Duration::from_millis(20)).await;
+                        Ok(Some(serde_json::json!({"uid": {"type": "User", "id": "alice"}})))
+                    })
+                    .await
+            })
+        });
+        let mut hits = 0;
+        for request in requests.collect::<Vec<_>>() {
+            let (result, hit) = request.await.unwrap();
+            assert!(result.unwrap().is_some());
+            hits += usize::from(hit);
+        }
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        assert_eq!(hits, 7);
+    }
+
+    #[tokio::test]
+    async fn expires_evicts_and_skips_failures() {
+        let cache = Cache::new(2);
+        let ttl = Duration::from_secs(60);
+        let missing = || async { Ok(None) };
+        assert!(!cache.get_or_fetch(&uid(r#"User::"a""#), ttl, missing).await.1);
+        assert!(cache.get_or_fetch(&uid(r#"User::"a""#), ttl, missing).await.1);
+
+        // Failures are retried on the next request
+        let failing = || async { Err("down".to_string()) };
+        assert!(cache.get_or_fetch(&uid(r#"User::"b""#), ttl, failing).await.0.is_err());
+        assert!(!cache.get_or_fetch(&uid(r#"User::"b""#), ttl, missing).await.1);
+
+        // Full: the least recently requested entry makes room
+        assert!(cache.get_or_fetch(&uid(r#"User::"c""#), Duration::from_secs(1), missing).await.0.is_ok());
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.invalidate(|u| *u == uid(r#"User::"a""#)), 0);
+        assert!(cache.get_or_fetch(&uid(r#"Doc::"d""#), Duration::from_millis(1), missing).await.0.is_ok());
+        tokio::time::sleep(Duration::from_millis(5)).await;
+        assert!(!cache.get_or_fetch(&uid(r#"Doc::"d""#), ttl, missing).await.1);
+
+        assert_eq!(cache.invalidate(|uid| uid.type_name().to_string() == "User"), 1);
+        assert_eq!(cache.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn stays_within_its_bound_while_entries_are_fetched() {
+        let cache = Arc::new(Cache::new(2));
+        let release = Arc::new(tokio::sync::Notify::new());
+        let fetching: Vec<_> = ["a", "b", "c"]
+            .into_iter()
+            .map(|id| {
+                let (cache, release) = (Arc::clone(&cache), Arc::clone(&release));
+                tokio::spawn(async move {
+                    cache
+                        .get_or_fetch(&uid(&format!(r#"User::"{}""#, id)), Duration::from_secs(60), || async move {
+                            release.notified().await;
+                            Ok(None)
+                        })
+                        .await
+                })
+            })
+            .collect();
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert_eq!(cache.len(), 2);
+
+        // The fetch of the dropped entry still answers its request
+        release.notify_waiters();
+        for request in fetching {
+            assert!(request.await.unwrap().0.is_ok());
+        }
+        assert_eq!(cache.len(), 2);
+        let missing = || async { Ok(None) };
+        assert!(!cache.get_or_fetch(&uid(r#"User::"a""#), Duration::from_secs(60), missing).await.1);
+        assert!(cache.get_or_fetch(&uid(r#"User::"c""#), Duration::from_secs(60), missing).await.1);
+    }
+}
@@ -0,0 +1,107 @@
+use crate::fetch::{self, HttpClient};
+use cedar_policy::EntityUid;
+use hyper::{Body, Method, Request};
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+/// A GraphQL query that looks up one entity.
+#[derive(Debug, Deserialize)]
+pub struct Query {
+    pub url: String,
+    pub query: String,
+    /// Query variables; `{id}` and `{type}` in string values are replaced with the parts of the
+    /// requested UID.
+    #[serde(default = "default_variables")]
+    variables: Value,
+    /// Path under `data` to the entity's object; `null` there means the entity does not exist.
+    result: String,
+    /// Sent with every query, e.g. `authorization`.
+    #[serde(default)]
+    headers: BTreeMap<String, String>,
+}
+
+fn default_variables() -> Value {
+    serde_json::json!({"id": "{id}"})
+}
+
+fn substitute_all(template: &Value, uid: &EntityUid) -> Value {
+    match template {
+        Value::String(s) => Value::String(fetch::substitute(s, uid)),
+        Value::Array(items) => Value::Array(items.iter().map(|v| substitute_all(v, uid)).collect()),
+        Value::Object(fields) => Value::Object(fields.iter().map(|(k, v)| (k.clone(), substitute_all(v, uid))).collect()),
+        other => other.clone(),
+    }
+}
+
+impl Query {
+    /// The request body for `uid`.
+    fn body(&self, uid: &EntityUid) -> Value {
+        serde_json::json!({"query": self.query, "variables": substitute_all(&self.variables, uid)})
+    }
+
+    /// The entity's object out of a GraphQL response. Any reported error fails the fetch, as a
+    /// partial result could leave out attributes or parents that policies depend on.
+    fn result(&self, response: &Value) -> Result<Option<Value>, String> {
+        if let Some(errors) = response.get("errors").and_then(Value::as_array).filter(|e| !e.is_empty()) {
+            let messages: Vec<&str> = errors
+                .iter()
+                .map(|e| e.get("message").and_then(Value::as_str).unwrap_or("unknown error"))
+                .collect();
+            return Err(format!("GraphQL errors: {}", messages.join("; ")));
+        }
+        let data = response.get("data").ok_or("GraphQL response has no data")?;
+        Ok(fetch::lookup(data, &self.result).filter(|v| !v.is_null()).cloned())
+    }
+
+    pub async fn fetch(&self, client: &HttpClient, uid: &EntityUid) -> Result<Option<Value>, String> {
+        let mut request = Request::builder()
+            .method(Method::POST)
+            .uri(&self.url)
+            .header("content-type", "application/json")
+            .header("accept", "application/json");
+        for (name, value) in &self.headers {
+            request = request.header(name, value);
+        }
+        let request = request
+            .body(Body::from(self.body(uid).to_string()))
+            .map_err(|e| format!("Invalid request: {}", e))?;
+        match fetch::send(client, request).await? {
+            Some(response) => self.result(&response),
+            None => Err(format!("{} answered 404", self.url)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn query(result: &str) -> Query {
+        serde_json::from_value(serde_json::json!({
+            "url": "http://users/graphql",
+            "query": "query($id: ID!) { user(id: $id) { email } }",
+            "variables": {"id": "{id}", "filter": {"kind": "{type}"}},
+            "result": result,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn builds_variables_from_the_uid() {
+        let body = query("user").body(&r#"User::"alice""#.parse().unwrap());
+        assert_eq!(body["variables"], serde_json::json!({"id": "alice", "filter": {"kind": "User"}}));
+        assert_eq!(body["query"], "query($id: ID!) { user(id: $id) { email } }");
+    }
+
+    #[test]
+    fn reads_the_result_and_errors() {
+        let q = query("org.user");
+        let found = serde_json::json!({"data": {"org": {"user": {"email": "a@example.org"}}}});
+        assert_eq!(q.result(&found).unwrap(), Some(serde_json::json!({"email": "a@example.org"})));
+        let missing = serde_json::json!({"data": {"org": {"user": null}}});
+        assert_eq!(q.result(&missing).unwrap(), None);
+        let failed = serde_json::json!({"data": null, "errors": [{"message": "forbidden"}]});
+        assert_eq!(q.result(&failed).unwrap_err(), "GraphQL errors: forbidden");
+    }
+}
@@ -0,0 +1,70 @@
+use crate::fetch::{self, HttpClient};
+use cedar_policy::EntityUid;
+use hyper::{Body, Request};
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+/// An HTTP endpoint that returns one entity's record.
+#[derive(Debug, Deserialize)]
+pub struct Endpoint {
+    /// URL template such as `https://users.internal/api/users/{id}`; `{id}` and `{type}` are
+    /// replaced with the percent-encoded parts of the requested UID.
+    pub url: String,
+    /// Path in the response body to the entity's object; the whole body by default.
+    #[serde(default)]
+    result: String,
+    /// Sent with every request, e.g. `authorization`.
+    #[serde(default)]
+    headers: BTreeMap<String, String>,
+}
+
+/// Percent-encodes everything but RFC 3986's unreserved characters, so an ID stays one path
+/// segment or query value.
+fn encode(s: &str) -> String {
+    let mut encoded = String::with_capacity(s.len());
+    for byte in s.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => encoded.push(byte as char),
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+impl Endpoint {
+    /// The URL for `uid`.
+    fn url(&self, uid: &EntityUid) -> String {
+        self.url
+            .replace("{type}", &encode(&uid.type_name().to_string()))
+            .replace("{id}", &encode(uid.id().unescaped()))
+    }
+
+    /// GETs the record; a 404 or a `null` result means the entity does not exist.
+    pub async fn fetch(&self, client: &HttpClient, uid: &EntityUid) -> Result<Option<Value>, String> {
+        let mut request = Request::get(self.url(uid)).header("accept", "application/json");
+        for (name, value) in &self.headers {
+            request = request.header(name, value);
+        }
+        let request = request.body(Body::empty()).map_err(|e| format!("Invalid request: {}", e))?;
+        Ok(fetch::send(client, request)
+            .await?
+            .and_then(|response| fetch::lookup(&response, &self.result).filter(|v| !v.is_null()).cloned()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fills_the_url_template() {
+        let endpoint: Endpoint =
+            serde_json::from_value(serde_json::json!({"url": "https://users.internal/api/{type}/{id}?expand=groups"})).unwrap();
+        assert_eq!(
+            endpoint.url(&r#"App::User::"a/b c""#.parse().unwrap()),
+            "https://users.internal/api/App%3A%3AUser/a%2Fb%20c?expand=groups"
+        );
+        assert_eq!(encode("ü-1"), "%C3%BC-1");
+    }
+}
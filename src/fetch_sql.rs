@@ -0,0 +1,248 @@
+use crate::secret_file::SecretFile;
+use cedar_policy::EntityUid;
+use log::{error, info};
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use sqlx::any::{AnyConnectOptions, AnyPoolOptions, AnyRow, AnyTypeInfoKind};
+use sqlx::{AnyPool, Column, Row};
+use std::collections::BTreeMap;
+use std::str::FromStr;
+use std::sync::{Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+/// Connections each SQL fetcher keeps open at most.
+const MAX_CONNECTIONS: u32 = 4;
+/// How often `url_file` and `password_file` are checked for rotated credentials, at most.
+const ROTATION_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+/// The `sql` entry of a fetcher.
+#[derive(Debug, Deserialize)]
+pub struct Config {
+    /// `postgres://` or `mysql://` connection URL; `url_env` names an environment variable that
+    /// holds it instead, keeping the password out of the file, and `url_file` a file holding it,
+    /// such as a mounted Kubernetes Secret.
+    #[serde(default)]
+    url: Option<String>,
+    #[serde(default)]
+    url_env: Option<String>,
+    #[serde(default)]
+    url_file: Option<String>,
+    /// File holding the password, which replaces any in the URL.
+    #[serde(default)]
+    password_file: Option<String>,
+    /// Returns the entity's row, its columns becoming fields; no row means no such entity.
+    query: String,
+    /// Queries whose rows' first column becomes a list under the given field, e.g. the group IDs
+    /// from a membership table to map as parents.
+    #[serde(default)]
+    lists: BTreeMap<String, String>,
+}
+
+/// SQL queries that look up one entity. Each query has the entity ID as its only parameter,
+/// written `$1` for Postgres and `?` for MySQL.
+pub struct Query {
+    /// Replaced when the credentials are rotated; queries under way finish on the old one.
+    pool: RwLock<AnyPool>,
+    /// The URL from `url` or `url_env`, or else `url_file`.
+    url: Option<String>,
+    url_file: Option<SecretFile>,
+    password_file: Option<SecretFile>,
+    /// When the files were last checked for rotated credentials.
+    checked: Mutex<Instant>,
+    query: String,
+    lists: BTreeMap<String, String>,
+}
+
+/// Sets up a pool for `url`, with `password` instead of the URL's own if given; connections are
+/// opened as fetches need them.
+fn connect(url: &str, password: Option<&str>) -> Result<AnyPool, String> {
+    if !["postgres://", "postgresql://", "mysql://", "mariadb://"].iter().any(|scheme| url.starts_with(scheme)) {
+        return Err("SQL URL must be postgres:// or mysql://".to_string());
+    }
+    sqlx::any::install_default_drivers();
+    let mut options = AnyConnectOptions::from_str(url).map_err(|e| format!("Invalid SQL URL: {}", e))?;
+    if let Some(password) = password {
+        options
+            .database_url
+            .set_password(Some(password))
+            .map_err(|_| "Invalid SQL URL: it cannot have a password".to_string())?;
+    }
+    Ok(AnyPoolOptions::new().max_connections(MAX_CONNECTIONS).connect_lazy_with(options))
+}
+
+impl Config {
+    pub fn build(self) -> Result<Query, String> {
+        let url = match (self.url, self.url_env, &self.url_file) {
+            (Some(url), None, None) => Some(url),
+            (None, Some(var), None) => Some(std::env::var(&var).map_err(|_| format!("{} is not set", var))?),
+            (None, None, Some(_)) => None,
+            _ => return Err("Set one of url, url_env and url_file".to_string()),
+        };
+        if self.url_file.is_some() && self.password_file.is_some() {
+            return Err("password_file cannot be combined with url_file".to_string());
+        }
+        let url_file = self.url_file.as_deref().map(SecretFile::open).transpose()?;
+        let password_file = self.password_file.as_deref().map(SecretFile::open).transpose()?;
+
+        let current_url = url.clone().or_else(|| url_file.as_ref().map(SecretFile::contents)).unwrap_or_default();
+        let pool = connect(&current_url, password_file.as_ref().map(SecretFile::contents).as_deref())?;
+        Ok(Query {
+            pool: RwLock::new(pool),
+            url,
+            url_file,
+            password_file,
+            checked: Mutex::new(Instant::now()),
+            query: self.query,
+            lists: self.lists,
+        })
+    }
+}
+
+/// A column's value as JSON. The `Any` driver covers integers, floats, booleans and text;
+/// cast other types (such as `numeric` or timestamps) to text in the query. Cedar has no
+/// floating-point type, so floats become strings, which a schema can declare as `decimal`.
+fn column_value(row: &AnyRow, index: usize) -> Result<Value, sqlx::Error> {
+    Ok(match row.column(index).type_info().kind() {
+        AnyTypeInfoKind::Null => Value::Null,
+        AnyTypeInfoKind::Bool => row.try_get::<Option<bool>, _>(index)?.into(),
+        AnyTypeInfoKind::SmallInt | AnyTypeInfoKind::Integer | AnyTypeInfoKind::BigInt => {
+            row.try_get::<Option<i64>, _>(index)?.into()
+        }
+        AnyTypeInfoKind::Real | AnyTypeInfoKind::Double => {
+            row.try_get::<Option<f64>, _>(index)?.map(|f| f.to_string()).into()
+        }
+        AnyTypeInfoKind::Text => row.try_get::<Option<String>, _>(index)?.into(),
+        AnyTypeInfoKind::Blob => {
+            return Err(sqlx::Error::ColumnDecode {
+                index: row.column(index).name().to_string(),
+                source: "binary columns cannot be entity attributes".into(),
+            })
+        }
+    })
+}
+
+impl Query {
+    /// Switches to a new pool when the URL or password in `url_file` or `password_file` was
+    /// rotated. New credentials that cannot be used are logged, and the current pool stays in
+    /// use until the files change again.
+    fn rotate_if_changed(&self) {
+        let changed = |file: &Option<SecretFile>| match file {
+            Some(file) => file.changed().unwrap_or_else(|e| {
+                error!("{}", e);
+                None
+            }),
+            None => None,
+        };
+        let (url, password) = (changed(&self.url_file), changed(&self.password_file));
+        if url.is_none() && password.is_none() {
+            return;
+        }
+        let current = |file: &Option<SecretFile>| file.as_ref().map(SecretFile::contents);
+        let new_url = url.clone().or_else(|| current(&self.url_file)).or_else(|| self.url.clone()).unwrap_or_default();
+        let new_password = password.clone().or_else(|| current(&self.password_file));
+        match connect(&new_url, new_password.as_deref()) {
+            Ok(pool) => {
+                *self.pool.write().unwrap() = pool;
+                for (file, contents) in [(&self.url_file, url), (&self.password_file, password)] {
+                    if let (Some(file), Some(contents)) = (file, contents) {
+                        file.commit(contents);
+                    }
+                }
+                info!("Rotated the credentials of a SQL fetcher");
+            }
+            Err(e) => error!("Failed to use the rotated credentials of a SQL fetcher, keeping the current ones: {}", e),
+        }
+    }
+
+    pub async fn fetch(&self, uid: &EntityUid) -> Result<Option<Value>, String> {
+        {
+            let mut checked = self.checked.lock().unwrap();
+            if checked.elapsed() >= ROTATION_CHECK_INTERVAL {
+                *checked = Instant::now();
+                self.rotate_if_changed();
+            }
+        }
+        let pool = self.pool.read().unwrap().clone();
+        let id = uid.id().unescaped();
+        let row = sqlx::query(&self.query)
+            .bind(id)
+            .fetch_optional(&pool)
+            .await
+            .map_err(|e| format!("Query failed: {}", e))?;
+        let Some(row) = row else {
+            return Ok(None);
+        };
+        let mut fields = Map::new();
+        for (index, column) in row.columns().iter().enumerate() {
+            let value = column_value(&row, index).map_err(|e| format!("Failed to read {}: {}", column.name(), e))?;
+            fields.insert(column.name().to_string(), value);
+        }
+
+        for (field, query) in &self.lists {
+            let rows = sqlx::query(query)
+                .bind(id)
+                .fetch_all(&pool)
+                .await
+                .map_err(|e| format!("Query for {} failed: {}", field, e))?;
+            let values = rows
+                .iter()
+                .map(|row| column_value(row, 0))
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|e| format!("Failed to read {}: {}", field, e))?;
+            fields.insert(field.clone(), Value::Array(values));
+        }
+        Ok(Some(Value::Object(fields)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(json: serde_json::Value) -> Config {
+        serde_json::from_value(json).unwrap()
+    }
+
+    #[test]
+    fn requires_one_postgres_or_mysql_url() {
+        let query = "SELECT email FROM users WHERE id = $1";
+        let error = |json| config(json).build().err().unwrap();
+        assert_eq!(
+            error(serde_json::json!({"url": "sqlite://users.db", "query": query})),
+            "SQL URL must be postgres:// or mysql://"
+        );
+        assert_eq!(error(serde_json::json!({"query": query})), "Set one of url, url_env and url_file");
+        assert_eq!(
+            error(serde_json::json!({"url": "postgres://db/users", "url_env": "USERS_DB", "query": query})),
+            "Set one of url, url_env and url_file"
+        );
+        assert_eq!(
+            error(serde_json::json!({"url_env": "CEDAR_TEST_UNSET_DATABASE_URL", "query": query})),
+            "CEDAR_TEST_UNSET_DATABASE_URL is not set"
+        );
+    }
+
+    #[tokio::test]
+    async fn picks_up_a_rotated_password() {
+        let path = std::env::temp_dir().join(format!("cedar-agent-sql-password-{}", std::process::id()));
+        std::fs::write(&path, "first\n").unwrap();
+        let query = config(serde_json::json!({
+            "url": "postgres://agent:unused@db:5432/users",
+            "password_file": path.to_str().unwrap(),
+            "query": "SELECT email FROM users WHERE id = $1",
+        }))
+        .build()
+        .unwrap();
+        let password = |query: &Query| query.pool.read().unwrap().connect_options().database_url.password().map(str::to_string);
+        assert_eq!(password(&query).as_deref(), Some("first"));
+
+        std::fs::write(&path, "second").unwrap();
+        query.rotate_if_changed();
+        assert_eq!(password(&query).as_deref(), Some("second"));
+        assert_eq!(query.password_file.as_ref().unwrap().changed(), Ok(None));
+        std::fs::remove_file(&path).unwrap();
+
+        let both = config(serde_json::json!({"url_file": "/run/url", "password_file": "/run/password", "query": "SELECT 1"}));
+        assert_eq!(both.build().err().unwrap(), "password_file cannot be combined with url_file");
+    }
+}
@@ -0,0 +1,289 @@
+use crate::config::Config;
+use cedar_policy::{Context, Entities, EntityUid, Request, Schema};
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+use std::fs;
+use std::path::Path;
+
+/// Example entities and requests generated from a schema.
+#[derive(Debug, Serialize)]
+pub struct Fixtures {
+    /// In the format `CEDAR_ENTITIES_PATH` takes.
+    pub entities: Vec<Value>,
+    /// Bodies for `POST /authorize`.
+    pub requests: Vec<Value>,
+}
+
+fn qualify(namespace: &str, name: &str) -> String {
+    if namespace.is_empty() || name.contains("::") {
+        name.to_string()
+    } else {
+        format!("{}::{}", namespace, name)
+    }
+}
+
+/// The ID of the example entity of `ty`: its first enumerated ID, or its lowercased name.
+fn example_id(schema_json: &Value, ty: &str) -> String {
+    let (namespace, name) = ty.rsplit_once("::").unwrap_or(("", ty));
+    match schema_json[namespace]["entityTypes"][name]["enum"].get(0).and_then(Value::as_str) {
+        Some(id) => id.to_string(),
+        None => format!("{}-1", name.to_lowercase()),
+    }
+}
+
+/// The example entity of `ty` as written in a request, e.g. `User::"user-1"`.
+fn example_uid(schema_json: &Value, ty: &str) -> Result<String, String> {
+    EntityUid::from_json(json!({ "type": ty, "id": example_id(schema_json, ty) }))
+        .map(|uid| uid.to_string())
+        .map_err(|e| format!("Invalid entity type {}: {}", ty, e))
+}
+
+/// Reads a schema's declarations with names resolved as Cedar resolves them: common types and
+/// entity types of the namespace first, then built-in and global names.
+struct Resolver<'a> {
+    schema_json: &'a Value,
+    namespace: &'a str,
+}
+
+impl Resolver<'_> {
+    fn definition(&self) -> &Value {
+        &self.schema_json[self.namespace]
+    }
+
+    /// The fully qualified entity type `name` refers to.
+    fn entity_type(&self, name: &str) -> String {
+        if !name.contains("::") && self.definition()["entityTypes"].get(name).is_some() {
+            return qualify(self.namespace, name);
+        }
+        name.to_string()
+    }
+
+    /// An example value of type `ty`; `name` is the attribute it is for.
+    fn example(&self, ty: &Value, name: &str, depth: usize) -> Value {
+        if depth > 16 {
+            return Value::Null;
+        }
+        let kind = ty["type"].as_str().unwrap_or_default();
+        match kind {
+            "Long" => json!(1),
+            "String" => json!(name),
+            "Boolean" => json!(true),
+            "Set" => json!([self.example(&ty["element"], name, depth + 1)]),
+            "Record" => {
+                let attributes: Map<String, Value> = ty["attributes"]
+                    .as_object()
+                    .into_iter()
+                    .flatten()
+                    .map(|(attribute, ty)| (attribute.clone(), self.example(ty, attribute, depth + 1)))
+                    .collect();
+                Value::Object(attributes)
+            }
+            "Entity" => self.reference(ty["name"].as_str().unwrap_or_default()),
+            "Extension" => extension(ty["name"].as_str().unwrap_or_default()),
+            "EntityOrCommon" => self.named(ty["name"].as_str().unwrap_or_default(), name, depth),
+            // The older form names a common type as the type itself
+            other => self.named(other, name, depth),
+        }
+    }
+
+    fn named(&self, type_name: &str, name: &str, depth: usize) -> Value {
+        if let Some(common) = self.definition()["commonTypes"].get(type_name) {
+            return self.example(common, name, depth + 1);
+        }
+        match type_name.strip_prefix("__cedar::").unwrap_or(type_name) {
+            "Long" => json!(1),
+            "String" => json!(name),
+            "Bool" | "Boolean" => json!(true),
+            "ipaddr" | "decimal" | "datetime" | "duration" => extension(type_name.trim_start_matches("__cedar::")),
+            _ => self.reference(type_name),
+        }
+    }
+
+    fn reference(&self, type_name: &str) -> Value {
+        let ty = self.entity_type(type_name);
+        json!({ "__entity": { "id": example_id(self.schema_json, &ty), "type": ty } })
+    }
+}
+
+fn extension(name: &str) -> Value {
+    let (function, arg) = match name {
+        "ipaddr" => ("ip", "192.168.0.1"),
+        "decimal" => ("decimal", "1.0"),
+        "datetime" => ("datetime", "2024-01-01T00:00:00Z"),
+        _ => ("duration", "1h"),
+    };
+    json!({ "__extn": { "fn": function, "arg": arg } })
+}
+
+/// One example entity of every entity type, with every attribute set and a parent of each type
+/// it may be a member of, and one request per action for each principal and resource type it
+/// applies to. Both are checked against the schema.
+pub fn generate(schema: &Schema, schema_json: &Value) -> Result<Fixtures, String> {
+    let mut entities = Vec::new();
+    let mut requests = Vec::new();
+    for (namespace, definition) in schema_json.as_object().into_iter().flatten() {
+        let resolver = Resolver { schema_json, namespace };
+        for (name, declaration) in definition["entityTypes"].as_object().into_iter().flatten() {
+            let ty = qualify(namespace, name);
+            // Enumerated entities have no attributes or parents
+            if let Some(ids) = declaration["enum"].as_array() {
+                entities.extend(ids.iter().map(|id| json!({ "uid": { "type": ty, "id": id }, "attrs": {}, "parents": [] })));
+                continue;
+            }
+            let parents: Vec<Value> = declaration["memberOfTypes"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(Value::as_str)
+                .map(|parent| resolver.entity_type(parent))
+                .filter(|parent| *parent != ty)
+                .map(|parent| json!({ "id": example_id(schema_json, &parent), "type": parent }))
+                .collect();
+            let shape = declaration.get("shape").cloned().unwrap_or_else(|| json!({ "type": "Record", "attributes": {} }));
+            entities.push(json!({
+                "uid": { "type": ty, "id": example_id(schema_json, &ty) },
+                "attrs": resolver.example(&shape, name, 0),
+                "parents": parents,
+            }));
+        }
+
+        for (id, declaration) in definition["actions"].as_object().into_iter().flatten() {
+            let applies_to = &declaration["appliesTo"];
+            let types = |key: &str| -> Vec<String> {
+                applies_to[key]
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .filter_map(Value::as_str)
+                    .map(|ty| resolver.entity_type(ty))
+                    .collect()
+            };
+            let action = EntityUid::from_json(json!({ "type": qualify(namespace, "Action"), "id": id }))
+                .map_err(|e| format!("Invalid action {}: {}", id, e))?;
+            let context = match applies_to.get("context") {
+                Some(context) => resolver.example(context, "context", 0),
+                None => json!({}),
+            };
+            for principal in types("principalTypes") {
+                for resource in types("resourceTypes") {
+                    requests.push(json!({
+                        "principal": example_uid(schema_json, &principal)?,
+                        "action": action.to_string(),
+                        "resource": example_uid(schema_json, &resource)?,
+                        "context": context,
+                        "entities": [],
+                    }));
+                }
+            }
+        }
+    }
+
+    let parsed = Entities::from_json_value(Value::Array(entities.clone()), Some(schema))
+        .map_err(|e| format!("Generated entities do not conform to the schema: {}", e))?;
+    for request in &requests {
+        check_request(schema, &parsed, request).map_err(|e| format!("Generated request {} is invalid: {}", request, e))?;
+    }
+    Ok(Fixtures { entities, requests })
+}
+
+/// Checks `request` against the schema, and that its principal and resource were generated.
+fn check_request(schema: &Schema, entities: &Entities, request: &Value) -> Result<(), String> {
+    let uid = |key: &str| -> Result<EntityUid, String> {
+        request[key].as_str().unwrap_or_default().parse().map_err(|e| format!("{}", e))
+    };
+    let (principal, action, resource) = (uid("principal")?, uid("action")?, uid("resource")?);
+    for entity in [&principal, &resource] {
+        if entities.get(entity).is_none() {
+            return Err(format!("no entity {}", entity));
+        }
+    }
+    let context = Context::from_json_value(request["context"].clone(), Some((schema, &action))).map_err(|e| e.to_string())?;
+    Request::new(principal, action, resource, context, Some(schema)).map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+/// `cedar-agent generate-fixtures [--out <dir>]`: generates example entities and requests from
+/// the schema at `CEDAR_SCHEMA_PATH` (and its fragments). With `--out` they are written to
+/// `entities.json` and `requests.json` in that directory, else printed as one JSON document.
+pub fn run(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let out = match args {
+        [] => None,
+        [flag, dir] if flag == "--out" => Some(Path::new(dir)),
+        _ => return Err("Usage: cedar-agent generate-fixtures [--out <dir>]".into()),
+    };
+    let config = Config::from_env()?;
+    let (schema, schema_json) = crate::schema::load(&config.schema_path, &config.schema_fragments)?
+        .ok_or_else(|| format!("No schema at {}; set CEDAR_SCHEMA_PATH", config.schema_path))?;
+    let fixtures = generate(&schema, &schema_json)?;
+    let Some(dir) = out else {
+        println!("{}", serde_json::to_string_pretty(&fixtures)?);
+        return Ok(());
+    };
+    fs::create_dir_all(dir).map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+    for (file, value) in [("entities.json", &fixtures.entities), ("requests.json", &fixtures.requests)] {
+        let path = dir.join(file);
+        fs::write(&path, serde_json::to_string_pretty(value)? + "\n").map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+    }
+    println!(
+        "Wrote {} entities and {} requests to {}",
+        fixtures.entities.len(),
+        fixtures.requests.len(),
+        dir.display()
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generates_entities_and_requests_the_schema_accepts() {
+        let schema_json = json!({
+            "App": {
+                "commonTypes": { "Address": { "type": "Record", "attributes": { "city": { "type": "String" } } } },
+                "entityTypes": {
+                    "User": {
+                        "memberOfTypes": ["Group"],
+                        "shape": { "type": "Record", "attributes": {
+                            "level": { "type": "Long" },
+                            "address": { "type": "Address" },
+                            "manager": { "type": "Entity", "name": "User", "required": false },
+                            "tags": { "type": "Set", "element": { "type": "String" } }
+                        } }
+                    },
+                    "Group": { "memberOfTypes": ["Group"] },
+                    "Doc": { "shape": { "type": "Record", "attributes": { "owner": { "type": "EntityOrCommon", "name": "User" } } } },
+                    "Status": { "enum": ["draft", "published"] }
+                },
+                "actions": {
+                    "view": { "appliesTo": {
+                        "principalTypes": ["User", "Group"],
+                        "resourceTypes": ["Doc"],
+                        "context": { "type": "Record", "attributes": {
+                            "ip": { "type": "Extension", "name": "ipaddr" },
+                            "when": { "type": "EntityOrCommon", "name": "datetime" }
+                        } }
+                    } },
+                    "read": {}
+                }
+            }
+        });
+        let schema = Schema::from_json_value(schema_json.clone()).unwrap();
+        let fixtures = generate(&schema, &schema_json).unwrap();
+
+        let user = fixtures.entities.iter().find(|e| e["uid"]["type"] == "App::User").unwrap();
+        assert_eq!(user["uid"]["id"], "user-1");
+        assert_eq!(user["parents"], json!([{ "id": "group-1", "type": "App::Group" }]));
+        assert_eq!(user["attrs"]["address"], json!({ "city": "city" }));
+        assert_eq!(user["attrs"]["manager"]["__entity"]["id"], "user-1");
+        // A type is not made a member of itself
+        let group = fixtures.entities.iter().find(|e| e["uid"]["type"] == "App::Group").unwrap();
+        assert_eq!(group["parents"], json!([]));
+        assert_eq!(fixtures.entities.iter().filter(|e| e["uid"]["type"] == "App::Status").count(), 2);
+
+        let principals: Vec<&str> = fixtures.requests.iter().map(|r| r["principal"].as_str().unwrap()).collect();
+        assert_eq!(principals, [r#"App::User::"user-1""#, r#"App::Group::"group-1""#]);
+        assert_eq!(fixtures.requests[0]["context"]["ip"]["__extn"]["fn"], "ip");
+    }
+}
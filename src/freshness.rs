@@ -0,0 +1,89 @@
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+/// How current the state from one refreshed source is.
+#[derive(Debug, Clone, Default, Serialize, PartialEq)]
+pub struct SourceStatus {
+    /// `fresh` after a successful refresh, `stale` while refreshes fail and the last good state
+    /// is being served.
+    pub status: &'static str,
+    /// Unix time of the last successful refresh.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_success: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<String>,
+    /// Failed refreshes since the last successful one.
+    #[serde(skip_serializing_if = "is_zero")]
+    pub failures: u64,
+}
+
+fn is_zero(n: &u64) -> bool {
+    *n == 0
+}
+
+/// Tracks the sources the agent refreshes from after startup (policy files, Verified
+/// Permissions, LDAP, Kubernetes, the TLS certificate), so health checks can tell when the
+/// agent is serving last-known-good state because a refresh failed.
+#[derive(Default)]
+pub struct Freshness {
+    sources: Mutex<BTreeMap<&'static str, SourceStatus>>,
+}
+
+impl Freshness {
+    pub fn succeeded(&self, source: &'static str, now: u64) {
+        self.sources.lock().unwrap().insert(source, SourceStatus {
+            status: "fresh",
+            last_success: Some(now),
+            last_error: None,
+            failures: 0,
+        });
+    }
+
+    pub fn failed(&self, source: &'static str, error: &str) {
+        let mut sources = self.sources.lock().unwrap();
+        let status = sources.entry(source).or_default();
+        status.status = "stale";
+        status.last_error = Some(error.to_string());
+        status.failures += 1;
+    }
+
+    pub fn snapshot(&self) -> BTreeMap<&'static str, SourceStatus> {
+        self.sources.lock().unwrap().clone()
+    }
+
+    pub fn is_stale(&self) -> bool {
+        self.sources.lock().unwrap().values().any(|s| s.status == "stale")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn failures_mark_a_source_stale_until_it_refreshes() {
+        let freshness = Freshness::default();
+        freshness.succeeded("ldap", 100);
+        freshness.succeeded("files", 100);
+        assert!(!freshness.is_stale());
+
+        freshness.failed("ldap", "connection refused");
+        freshness.failed("ldap", "connection refused");
+        assert!(freshness.is_stale());
+        assert_eq!(
+            freshness.snapshot()["ldap"],
+            SourceStatus {
+                status: "stale",
+                last_success: Some(100),
+                last_error: Some("connection refused".to_string()),
+                failures: 2,
+            }
+        );
+        assert_eq!(freshness.snapshot()["files"].status, "fresh");
+
+        freshness.succeeded("ldap", 400);
+        assert!(!freshness.is_stale());
+        assert_eq!(freshness.snapshot()["ldap"].failures, 0);
+    }
+}
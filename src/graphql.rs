@@ -0,0 +1,215 @@
+use crate::{policies, AuthzRequest, CedarService};
+use async_graphql::{
+    Context, EmptyMutation, EmptySubscription, Error, ErrorExtensions, Json, Object, Result, Schema, SimpleObject,
+};
+use cedar_policy::EntityUid;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::sync::{Arc, OnceLock};
+
+type AgentSchema = Schema<QueryRoot, EmptyMutation, EmptySubscription>;
+
+#[derive(SimpleObject)]
+struct ActionResult {
+    action: String,
+    decision: String,
+    reason: Vec<String>,
+}
+
+#[derive(SimpleObject)]
+struct Directive {
+    id: String,
+    value: Option<String>,
+    policy: String,
+}
+
+impl From<policies::Directive> for Directive {
+    fn from(d: policies::Directive) -> Self {
+        Directive {
+            id: d.id,
+            value: d.value,
+            policy: d.policy,
+        }
+    }
+}
+
+/// An authorization decision; mirrors the `/authorize` response with the diagnostics flattened.
+#[derive(SimpleObject)]
+struct Decision {
+    decision: String,
+    actions: Vec<ActionResult>,
+    obligations: Vec<Directive>,
+    advice: Vec<Directive>,
+    reason: Vec<String>,
+    errors: Vec<String>,
+    permits: Vec<String>,
+    forbids: Vec<String>,
+    errored: Vec<String>,
+    denied_by: Option<String>,
+    warnings: Vec<String>,
+    default_decision: Option<String>,
+}
+
+#[derive(SimpleObject)]
+struct PolicyInfo {
+    id: String,
+    effect: String,
+    annotations: Json<BTreeMap<String, String>>,
+    source: Option<String>,
+    template_id: Option<String>,
+    policy: String,
+}
+
+#[derive(SimpleObject)]
+struct RelatedEntity {
+    uid: String,
+    path: Vec<String>,
+}
+
+/// A stored entity; `ancestors` and `descendants` are only walked when selected.
+struct StoredEntity {
+    service: Arc<CedarService>,
+    uid: EntityUid,
+}
+
+#[Object]
+impl StoredEntity {
+    async fn uid(&self) -> String {
+        self.uid.to_string()
+    }
+
+    async fn ancestors(&self) -> Vec<RelatedEntity> {
+        related(self.service.state().entities.ancestors(&self.uid))
+    }
+
+    async fn descendants(&self) -> Vec<RelatedEntity> {
+        related(self.service.state().entities.descendants(&self.uid))
+    }
+}
+
+fn related(relations: Vec<crate::entities::Relation>) -> Vec<RelatedEntity> {
+    relations
+        .into_iter()
+        .map(|r| RelatedEntity {
+            uid: r.uid,
+            path: r.path,
+        })
+        .collect()
+}
+
+fn parse_uid(name: &str, value: Option<String>) -> Result<Option<EntityUid>> {
+    value
+        .map(|v| v.parse().map_err(|e| Error::new(format!("Invalid {}: {}", name, e))))
+        .transpose()
+}
+
+pub struct QueryRoot;
+
+#[Object]
+impl QueryRoot {
+    /// Evaluates a request exactly as `POST /authorize` does.
+    async fn authorize(
+        &self,
+        ctx: &Context<'_>,
+        principal: String,
+        action: String,
+        resource: String,
+        entities: Option<Json<Value>>,
+        context: Option<Json<Value>>,
+    ) -> Result<Decision> {
+        let service = ctx.data::<Arc<CedarService>>()?;
+        let req = AuthzRequest {
+            principal,
+            action,
+            resource,
+            entities: entities.map(|e| e.0).unwrap_or_else(|| Value::Array(Vec::new())),
+            context: context.map(|c| c.0),
+            min_version: None,
+            token: None,
+            evaluation_time: None,
+        };
+        let req = service
+            .evaluation_time(None)
+            .and_then(|time| service.stamp_time(req, time))
+            .map_err(Error::new)?;
+        let req = service.fetch_entities(req).await.map_err(Error::new)?;
+        let response = service
+            .authorize(req)
+            .map_err(|e| Error::new(e.to_string()).extend_with(|_, extensions| extensions.set("code", e.code())))?;
+        Ok(Decision {
+            decision: response.decision,
+            actions: response
+                .actions
+                .into_iter()
+                .map(|a| ActionResult {
+                    action: a.action,
+                    decision: a.decision.to_string(),
+                    reason: a.reason,
+                })
+                .collect(),
+            obligations: response.obligations.into_iter().map(Directive::from).collect(),
+            advice: response.advice.into_iter().map(Directive::from).collect(),
+            reason: response.diagnostics.reason,
+            errors: response.diagnostics.errors,
+            permits: response.diagnostics.permits,
+            forbids: response.diagnostics.forbids,
+            errored: response.diagnostics.errored,
+            denied_by: response.diagnostics.denied_by.map(str::to_string),
+            warnings: response.diagnostics.warnings,
+            default_decision: response.diagnostics.default_decision.map(str::to_string),
+        })
+    }
+
+    /// Loaded policies whose scope could match the given values, as `GET /v1/policies`.
+    async fn policies(
+        &self,
+        ctx: &Context<'_>,
+        principal: Option<String>,
+        action: Option<String>,
+        resource: Option<String>,
+    ) -> Result<Vec<PolicyInfo>> {
+        let service = ctx.data::<Arc<CedarService>>()?;
+        let query = policies::ScopeQuery {
+            principal: parse_uid("principal", principal)?,
+            action: parse_uid("action", action)?,
+            resource: parse_uid("resource", resource)?,
+            label: None,
+        };
+        Ok(service
+            .state()
+            .matching_policies(&query)
+            .into_iter()
+            .map(|p| PolicyInfo {
+                id: p.id,
+                effect: p.effect.to_string(),
+                annotations: Json(p.annotations),
+                source: p.source,
+                template_id: p.template_id,
+                policy: p.policy,
+            })
+            .collect())
+    }
+
+    /// A stored entity, or null when it is not in the entity store.
+    async fn entity(&self, ctx: &Context<'_>, uid: String) -> Result<Option<StoredEntity>> {
+        let service = ctx.data::<Arc<CedarService>>()?;
+        let uid: EntityUid = uid
+            .parse()
+            .map_err(|e| Error::new(format!("Invalid entity UID: {}", e)))?;
+        if !service.state().entities.contains(&uid) {
+            return Ok(None);
+        }
+        Ok(Some(StoredEntity {
+            service: Arc::clone(service),
+            uid,
+        }))
+    }
+}
+
+/// Runs a GraphQL request for `POST /graphql`. The schema is built once; the service is passed
+/// per request.
+pub async fn execute(service: Arc<CedarService>, request: async_graphql::Request) -> async_graphql::Response {
+    static SCHEMA: OnceLock<AgentSchema> = OnceLock::new();
+    let schema = SCHEMA.get_or_init(|| Schema::new(QueryRoot, EmptyMutation, EmptySubscription));
+    schema.execute(request.data(service)).await
+}
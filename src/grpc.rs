@@ -0,0 +1,93 @@
+use hyper::body::{Bytes, HttpBody};
+use hyper::header::{HeaderMap, HeaderValue, CONTENT_TYPE};
+use hyper::{Body, Response};
+use std::convert::Infallible;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
+use tonic::body::BoxBody;
+use tonic::codegen::Service;
+
+/// How long a streaming response may stay silent before an empty chunk checks that the client
+/// is still there.
+const PROBE_INTERVAL: Duration = Duration::from_secs(30);
+
+/// The gRPC status of a call to a method the agent does not serve.
+pub const UNIMPLEMENTED: u32 = 12;
+
+/// `grpc-status` (and `grpc-message`) for the end of a call.
+fn status(code: u32, message: &str) -> HeaderMap {
+    let mut trailers = HeaderMap::new();
+    trailers.insert("grpc-status", code.into());
+    if let Ok(message) = HeaderValue::from_str(message) {
+        if !message.is_empty() {
+            trailers.insert("grpc-message", message);
+        }
+    }
+    trailers
+}
+
+/// A call answered with no messages: the status goes in the headers ("Trailers-Only").
+pub fn trailers_only(code: u32, message: &str) -> Response<Body> {
+    let mut resp = Response::new(Body::empty());
+    resp.headers_mut().insert(CONTENT_TYPE, HeaderValue::from_static("application/grpc"));
+    resp.headers_mut().extend(status(code, message));
+    resp
+}
+
+/// A gRPC service the agent's HTTP handler dispatches calls to.
+pub type Handler = Arc<dyn Fn(hyper::Request<Body>) -> Pin<Box<dyn Future<Output = Response<Body>> + Send>> + Send + Sync>;
+
+/// Serves calls with a server tonic generated, on the agent's own listener rather than a
+/// separate tonic one.
+pub fn handler<S>(service: S) -> Handler
+where
+    S: Service<hyper::Request<Body>, Response = Response<BoxBody>, Error = Infallible> + Clone + Send + Sync + 'static,
+    S::Future: Send,
+{
+    Arc::new(move |req| {
+        let mut service = service.clone();
+        Box::pin(async move {
+            let resp = match std::future::poll_fn(|cx| service.poll_ready(cx)).await {
+                Ok(()) => service.call(req).await,
+                Err(e) => Err(e),
+            };
+            let (parts, body) = match resp {
+                Ok(resp) => resp.into_parts(),
+                Err(e) => match e {},
+            };
+            Response::from_parts(parts, forward(body))
+        })
+    })
+}
+
+/// Copies a tonic response body into a hyper one: the messages, then the trailers with the
+/// call's status. Streams like `Watch` send every change until the client cancels the call.
+/// Only a failed send tells that it has, so an empty chunk is sent when nothing was for a while.
+fn forward(mut body: BoxBody) -> Body {
+    let (mut sender, forwarded) = Body::channel();
+    tokio::spawn(async move {
+        loop {
+            let chunk = match tokio::time::timeout(PROBE_INTERVAL, body.data()).await {
+                Ok(Some(Ok(chunk))) => chunk,
+                Ok(Some(Err(error))) => {
+                    let mut trailers = HeaderMap::new();
+                    if error.add_header(&mut trailers).is_ok() {
+                        let _ = sender.send_trailers(trailers).await;
+                    }
+                    return;
+                }
+                Ok(None) => break,
+                Err(_) => Bytes::new(),
+            };
+            if sender.send_data(chunk).await.is_err() {
+                return;
+            }
+        }
+        if let Ok(Some(trailers)) = body.trailers().await {
+            let _ = sender.send_trailers(trailers).await;
+        }
+    });
+    forwarded
+}
@@ -0,0 +1,75 @@
+use crate::grpc;
+use crate::CedarService;
+use hyper::{Body, Response};
+use std::sync::Arc;
+use tokio::sync::watch;
+
+pub use tonic_health::ServingStatus;
+
+/// Methods of the standard `grpc.health.v1.Health` service are under this path.
+pub const PATH_PREFIX: &str = "/grpc.health.v1.Health/";
+
+/// tonic-health's `grpc.health.v1.Health`, reporting the agent as a whole (the empty service
+/// name) as `serving` says, and every change to it.
+async fn start(mut serving: watch::Receiver<ServingStatus>) -> grpc::Handler {
+    let (mut reporter, server) = tonic_health::server::health_reporter();
+    let status = *serving.borrow_and_update();
+    reporter.set_service_status("", status).await;
+    tokio::spawn(async move {
+        while serving.changed().await.is_ok() {
+            let status = *serving.borrow_and_update();
+            reporter.set_service_status("", status).await;
+        }
+    });
+    grpc::handler(server)
+}
+
+/// Serves `Check` and `Watch` of `grpc.health.v1.Health` over the agent's HTTP/2 listener, so
+/// Kubernetes gRPC probes and gRPC load balancers can check the agent. The service is started
+/// on the first call.
+pub async fn handle(req: hyper::Request<Body>, service: &Arc<CedarService>) -> Response<Body> {
+    let health = service.grpc_health.get_or_init(|| start(service.serving.subscribe())).await;
+    health(req).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::body::HttpBody;
+
+    /// Calls `method` for `service` and returns the response message and `grpc-status`.
+    async fn call(health: &grpc::Handler, method: &str, service: &str) -> (Vec<u8>, String) {
+        let mut message = vec![0x0a, service.len() as u8];
+        message.extend(service.as_bytes());
+        let mut framed = vec![0];
+        framed.extend((message.len() as u32).to_be_bytes());
+        framed.extend(message);
+        let req = hyper::Request::post(format!("{}{}", PATH_PREFIX, method))
+            .header("content-type", "application/grpc")
+            .body(Body::from(framed))
+            .unwrap();
+        let resp = health(req).await;
+        let status = resp.headers().get("grpc-status").map(|s| s.to_str().unwrap().to_string());
+        let mut body = resp.into_body();
+        let mut data = Vec::new();
+        while let Some(chunk) = body.data().await {
+            data.extend(chunk.unwrap());
+        }
+        let trailers = body.trailers().await.unwrap().unwrap_or_default();
+        let status = status.or_else(|| trailers.get("grpc-status").map(|s| s.to_str().unwrap().to_string()));
+        (data, status.unwrap())
+    }
+
+    #[tokio::test]
+    async fn reports_the_agent_and_follows_its_status() {
+        let (serving, receiver) = watch::channel(ServingStatus::NotServing);
+        let health = start(receiver).await;
+        // HealthCheckResponse with status NOT_SERVING (2), then SERVING (1)
+        assert_eq!(call(&health, "Check", "").await, (vec![0, 0, 0, 0, 2, 0x08, 2], "0".to_string()));
+        serving.send_replace(ServingStatus::Serving);
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        assert_eq!(call(&health, "Check", "").await, (vec![0, 0, 0, 0, 2, 0x08, 1], "0".to_string()));
+        // NOT_FOUND
+        assert_eq!(call(&health, "Check", "other.Service").await.1, "5");
+    }
+}
@@ -0,0 +1,55 @@
+use crate::grpc;
+use crate::CedarService;
+use hyper::{Body, Response};
+use std::sync::Arc;
+
+/// `ServerReflectionInfo` of `grpc.reflection.v1alpha`, which tonic-reflection serves, and of
+/// `v1`, which tools try first and fall back from when it is unimplemented.
+pub const PATHS: [&str; 2] = [
+    "/grpc.reflection.v1alpha.ServerReflection/ServerReflectionInfo",
+    "/grpc.reflection.v1.ServerReflection/ServerReflectionInfo",
+];
+
+/// tonic-reflection's `ServerReflection`, describing the health service from tonic-health's
+/// descriptors.
+pub fn handler() -> Result<grpc::Handler, String> {
+    let server = tonic_reflection::server::Builder::configure()
+        .register_encoded_file_descriptor_set(tonic_health::pb::FILE_DESCRIPTOR_SET)
+        .build()
+        .map_err(|e| format!("Failed to build gRPC server reflection: {}", e))?;
+    Ok(grpc::handler(server))
+}
+
+/// `ServerReflectionInfo`, so tools like grpcurl can discover the agent's gRPC services
+/// without their `.proto` files, when `CEDAR_GRPC_REFLECTION` enables it.
+pub async fn handle(req: hyper::Request<Body>, service: &Arc<CedarService>) -> Response<Body> {
+    match service.grpc_reflection {
+        Some(ref reflection) if req.uri().path() == PATHS[0] => reflection(req).await,
+        Some(_) => grpc::trailers_only(grpc::UNIMPLEMENTED, "Server reflection is served as grpc.reflection.v1alpha"),
+        None => grpc::trailers_only(grpc::UNIMPLEMENTED, "Server reflection is disabled"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::body::HttpBody;
+
+    #[tokio::test]
+    async fn lists_the_health_service() {
+        // ServerReflectionRequest with list_services (field 7)
+        let framed = [0, 0, 0, 0, 2, 0x3a, 0];
+        let req = hyper::Request::post(PATHS[0])
+            .header("content-type", "application/grpc")
+            .body(Body::from(framed.to_vec()))
+            .unwrap();
+        let mut body = handler().unwrap()(req).await.into_body();
+        let mut data = Vec::new();
+        while let Some(chunk) = body.data().await {
+            data.extend(chunk.unwrap());
+        }
+        let services = b"grpc.health.v1.Health";
+        assert!(data.windows(services.len()).any(|window| window == services));
+        assert_eq!(body.trailers().await.unwrap().unwrap()["grpc-status"], "0");
+    }
+}
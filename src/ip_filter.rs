@@ -0,0 +1,208 @@
+use std::net::IpAddr;
+use std::str::FromStr;
+
+/// An address block such as `10.0.0.0/8` or `fd00::/8`; a bare address is a single-host block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cidr {
+    network: IpAddr,
+    prefix: u8,
+}
+
+impl FromStr for Cidr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (addr, prefix) = match s.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s, None),
+        };
+        let network: IpAddr = addr
+            .parse()
+            .map_err(|e| format!("Invalid CIDR '{}': {}", s, e))?;
+        let max = if network.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(p) => match p.parse::<u8>() {
+                Ok(p) if p <= max => p,
+                _ => return Err(format!("Invalid CIDR '{}': prefix must be 0-{}", s, max)),
+            },
+            None => max,
+        };
+        Ok(Self { network, prefix })
+    }
+}
+
+impl Cidr {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.network, ip.to_canonical()) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(net) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(net) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Parses a list of CIDRs.
+pub fn parse_list(entries: &[String]) -> Result<Vec<Cidr>, String> {
+    entries.iter().map(|e| e.parse()).collect()
+}
+
+/// Source-address rules for one class of endpoints. A denied address is always rejected; when
+/// an allowlist is set, only addresses on it are accepted.
+#[derive(Debug, Clone, Default)]
+pub struct IpRules {
+    pub allow: Vec<Cidr>,
+    pub deny: Vec<Cidr>,
+}
+
+impl IpRules {
+    pub fn permits(&self, ip: IpAddr) -> bool {
+        if self.deny.iter().any(|c| c.contains(ip)) {
+            return false;
+        }
+        self.allow.is_empty() || self.allow.iter().any(|c| c.contains(ip))
+    }
+}
+
+/// Separate rules for data-plane endpoints (authorization, queries) and admin endpoints
+/// (`/admin/*`, `/debug/*`, SCIM provisioning and anything that modifies state).
+#[derive(Debug, Clone, Default)]
+pub struct IpFilter {
+    pub data_plane: IpRules,
+    pub admin: IpRules,
+}
+
+/// `POST` endpoints under `/v1/` that only evaluate and change nothing.
+const READ_ONLY_POSTS: &[&str] = &["/v1/evaluate", "/v1/data/entities/validate", "/v1/schema/infer", "/v1/policies/lint"];
+
+/// Whether `method path` is an admin endpoint rather than part of the data plane.
+pub fn is_admin(method: &hyper::Method, path: &str) -> bool {
+    let modifies = path.starts_with("/v1/")
+        && *method != hyper::Method::GET
+        && !(*method == hyper::Method::POST && READ_ONLY_POSTS.contains(&path));
+    // Recent decisions name the principals of other callers
+    let decisions = path == crate::decision_index::PATH;
+    path.starts_with("/admin/") || path.starts_with("/debug/") || path.starts_with("/scim/") || modifies || decisions
+}
+
+impl IpFilter {
+    /// Whether `ip` may call `method path`. Health checks (`/health` and the gRPC health
+    /// service) are always reachable so probes keep working.
+    pub fn permits(&self, ip: IpAddr, method: &hyper::Method, path: &str) -> bool {
+        if path == "/health" || path.starts_with(crate::grpc_health::PATH_PREFIX) {
+            return true;
+        }
+        if is_admin(method, path) {
+            self.admin.permits(ip)
+        } else {
+            self.data_plane.permits(ip)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::Method;
+
+    fn cidr(s: &str) -> Cidr {
+        s.parse().unwrap()
+    }
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn parses_and_rejects_prefixes() {
+        assert!("10.0.0.0/33".parse::<Cidr>().is_err());
+        assert!("::/129".parse::<Cidr>().is_err());
+        assert!("10.0.0.0/x".parse::<Cidr>().is_err());
+        assert!("not-an-ip".parse::<Cidr>().is_err());
+        assert_eq!(cidr("10.1.2.3"), cidr("10.1.2.3/32"));
+    }
+
+    #[test]
+    fn zero_prefix_matches_whole_family() {
+        assert!(cidr("0.0.0.0/0").contains(ip("203.0.113.9")));
+        assert!(cidr("::/0").contains(ip("2001:db8::1")));
+        assert!(!cidr("0.0.0.0/0").contains(ip("2001:db8::1")));
+    }
+
+    #[test]
+    fn host_prefixes_match_one_address() {
+        assert!(cidr("192.0.2.7/32").contains(ip("192.0.2.7")));
+        assert!(!cidr("192.0.2.7/32").contains(ip("192.0.2.8")));
+        assert!(cidr("2001:db8::7/128").contains(ip("2001:db8::7")));
+        assert!(!cidr("2001:db8::7/128").contains(ip("2001:db8::8")));
+    }
+
+    #[test]
+    fn masks_partial_prefixes() {
+        assert!(cidr("10.0.0.0/8").contains(ip("10.255.1.1")));
+        assert!(!cidr("10.0.0.0/8").contains(ip("11.0.0.1")));
+        assert!(cidr("fd00::/8").contains(ip("fdab::1")));
+        assert!(!cidr("fd00::/8").contains(ip("fe80::1")));
+    }
+
+    #[test]
+    fn v4_mapped_client_matches_v4_rule() {
+        assert!(cidr("10.0.0.0/8").contains(ip("::ffff:10.1.2.3")));
+        assert!(!cidr("10.0.0.0/8").contains(ip("::ffff:192.0.2.1")));
+    }
+
+    #[test]
+    fn deny_takes_priority_over_allow() {
+        let rules = IpRules {
+            allow: vec![cidr("10.0.0.0/8")],
+            deny: vec![cidr("10.0.0.5")],
+        };
+        assert!(rules.permits(ip("10.0.0.4")));
+        assert!(!rules.permits(ip("10.0.0.5")));
+        assert!(!rules.permits(ip("192.0.2.1")));
+        assert!(IpRules::default().permits(ip("192.0.2.1")));
+    }
+
+    #[test]
+    fn classifies_admin_endpoints() {
+        assert!(is_admin(&Method::PUT, "/v1/schema"));
+        assert!(is_admin(&Method::GET, "/admin/loglevel"));
+        assert!(is_admin(&Method::GET, "/debug/pprof/heap"));
+        assert!(is_admin(&Method::GET, "/scim/v2/Users"));
+        assert!(!is_admin(&Method::POST, "/v1/evaluate"));
+        assert!(is_admin(&Method::GET, "/v1/decisions"));
+        assert!(!is_admin(&Method::POST, "/v1/data/entities/validate"));
+        assert!(!is_admin(&Method::GET, "/v1/schema"));
+        assert!(!is_admin(&Method::POST, "/authorize"));
+    }
+
+    #[test]
+    fn applies_rules_by_endpoint_class() {
+        let filter = IpFilter {
+            data_plane: IpRules::default(),
+            admin: IpRules {
+                allow: vec![cidr("10.20.0.0/24")],
+                deny: Vec::new(),
+            },
+        };
+        let outsider = ip("192.0.2.1");
+        assert!(filter.permits(outsider, &Method::POST, "/authorize"));
+        assert!(!filter.permits(outsider, &Method::PUT, "/v1/schema"));
+        assert!(filter.permits(ip("10.20.0.9"), &Method::PUT, "/v1/schema"));
+
+        let closed = IpFilter {
+            data_plane: IpRules {
+                allow: Vec::new(),
+                deny: vec![cidr("0.0.0.0/0")],
+            },
+            admin: IpRules::default(),
+        };
+        assert!(closed.permits(outsider, &Method::GET, "/health"));
+        assert!(closed.permits(outsider, &Method::POST, "/grpc.health.v1.Health/Check"));
+    }
+}
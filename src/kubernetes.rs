@@ -0,0 +1,356 @@
+use crate::config::Config;
+use crate::CedarService;
+use cedar_policy::{Entity, EntityTypeName};
+use futures::stream::{self, BoxStream, StreamExt, TryStreamExt};
+use k8s_openapi::api::core::v1::ServiceAccount;
+use k8s_openapi::api::rbac::v1::{ClusterRole, ClusterRoleBinding, PolicyRule, Role, RoleBinding, RoleRef, Subject};
+use kube::runtime::reflector::{self, Store};
+use kube::runtime::{watcher, WatchStreamExt};
+use kube::{Api, Client, Resource};
+use log::{error, info};
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Notify;
+
+/// How long changes are left to settle before importing, so a burst (e.g. a Helm release)
+/// leads to one import.
+const SETTLE: Duration = Duration::from_secs(2);
+
+/// Entity types the importer creates, all in one Cedar namespace (`k8s` by default).
+pub struct Names {
+    service_account: EntityTypeName,
+    role: EntityTypeName,
+    cluster_role: EntityTypeName,
+    namespace: EntityTypeName,
+}
+
+impl Names {
+    pub fn new(namespace: &str) -> Result<Self, String> {
+        let type_name = |name: &str| -> Result<EntityTypeName, String> {
+            let qualified = if namespace.is_empty() {
+                name.to_string()
+            } else {
+                format!("{}::{}", namespace, name)
+            };
+            qualified
+                .parse()
+                .map_err(|e| format!("Invalid CEDAR_K8S_ENTITY_NAMESPACE: {}", e))
+        };
+        Ok(Self {
+            service_account: type_name("ServiceAccount")?,
+            role: type_name("Role")?,
+            cluster_role: type_name("ClusterRole")?,
+            namespace: type_name("Namespace")?,
+        })
+    }
+
+    /// Whether an entity is owned by the importer, i.e. replaced on every import.
+    fn owns(&self, entity: &Entity) -> bool {
+        let uid = entity.uid();
+        [&self.service_account, &self.role, &self.cluster_role, &self.namespace].contains(&uid.type_name())
+    }
+
+    fn uid(type_name: &EntityTypeName, id: &str) -> Value {
+        json!({"type": type_name.to_string(), "id": id})
+    }
+}
+
+/// The cluster objects an import is built from.
+#[derive(Default)]
+pub struct Snapshot {
+    pub service_accounts: Vec<Arc<ServiceAccount>>,
+    pub roles: Vec<Arc<Role>>,
+    pub cluster_roles: Vec<Arc<ClusterRole>>,
+    pub role_bindings: Vec<Arc<RoleBinding>>,
+    pub cluster_role_bindings: Vec<Arc<ClusterRoleBinding>>,
+}
+
+/// What a role's rules allow, as `"<verb> <resource>"` strings: `get pods`,
+/// `list apps/deployments`, `get /healthz` for non-resource URLs.
+fn permissions(rules: Option<&Vec<PolicyRule>>) -> BTreeSet<String> {
+    let mut permissions = BTreeSet::new();
+    for rule in rules.into_iter().flatten() {
+        let groups = rule.api_groups.clone().unwrap_or_else(|| vec![String::new()]);
+        for verb in &rule.verbs {
+            for group in &groups {
+                for resource in rule.resources.iter().flatten() {
+                    permissions.insert(match group.as_str() {
+                        "" => format!("{} {}", verb, resource),
+                        group => format!("{} {}/{}", verb, group, resource),
+                    });
+                }
+            }
+            for url in rule.non_resource_urls.iter().flatten() {
+                permissions.insert(format!("{} {}", verb, url));
+            }
+        }
+    }
+    permissions
+}
+
+/// Turns the snapshot into entities in Cedar's JSON format. Service accounts and roles are
+/// members of their namespace; a service account is also a member of every role or cluster role
+/// bound to it, so `principal in k8s::ClusterRole::"view"` holds for the accounts bound to it.
+pub fn to_entities(names: &Names, snapshot: &Snapshot) -> Vec<Value> {
+    // Service account ID -> role UIDs bound to it
+    let mut bound: BTreeMap<String, Vec<Value>> = BTreeMap::new();
+    let mut bind = |subjects: Option<&Vec<Subject>>, binding_namespace: Option<&str>, role_ref: &RoleRef| {
+        let role = match (role_ref.kind.as_str(), binding_namespace) {
+            ("ClusterRole", _) => Names::uid(&names.cluster_role, &role_ref.name),
+            ("Role", Some(namespace)) => Names::uid(&names.role, &format!("{}/{}", namespace, role_ref.name)),
+            _ => return,
+        };
+        for subject in subjects.into_iter().flatten().filter(|s| s.kind == "ServiceAccount") {
+            let Some(namespace) = subject.namespace.as_deref().or(binding_namespace) else {
+                continue;
+            };
+            let roles = bound.entry(format!("{}/{}", namespace, subject.name)).or_default();
+            if !roles.contains(&role) {
+                roles.push(role.clone());
+            }
+        }
+    };
+    for binding in &snapshot.role_bindings {
+        bind(binding.subjects.as_ref(), binding.metadata.namespace.as_deref(), &binding.role_ref);
+    }
+    for binding in &snapshot.cluster_role_bindings {
+        bind(binding.subjects.as_ref(), None, &binding.role_ref);
+    }
+
+    let mut namespaces = BTreeSet::new();
+    let mut entities = Vec::new();
+    for account in &snapshot.service_accounts {
+        let (Some(name), Some(namespace)) = (&account.metadata.name, &account.metadata.namespace) else {
+            continue;
+        };
+        namespaces.insert(namespace.clone());
+        let id = format!("{}/{}", namespace, name);
+        let mut parents = vec![Names::uid(&names.namespace, namespace)];
+        parents.extend(bound.remove(&id).unwrap_or_default());
+        entities.push(json!({
+            "uid": Names::uid(&names.service_account, &id),
+            "attrs": {"name": name, "namespace": namespace},
+            "parents": parents,
+        }));
+    }
+    for role in &snapshot.roles {
+        let (Some(name), Some(namespace)) = (&role.metadata.name, &role.metadata.namespace) else {
+            continue;
+        };
+        namespaces.insert(namespace.clone());
+        entities.push(json!({
+            "uid": Names::uid(&names.role, &format!("{}/{}", namespace, name)),
+            "attrs": {"name": name, "namespace": namespace, "permissions": permissions(role.rules.as_ref())},
+            "parents": [Names::uid(&names.namespace, namespace)],
+        }));
+    }
+    for role in &snapshot.cluster_roles {
+        let Some(ref name) = role.metadata.name else {
+            continue;
+        };
+        entities.push(json!({
+            "uid": Names::uid(&names.cluster_role, name),
+            "attrs": {"name": name, "permissions": permissions(role.rules.as_ref())},
+            "parents": [],
+        }));
+    }
+    for namespace in namespaces {
+        entities.push(json!({
+            "uid": Names::uid(&names.namespace, &namespace),
+            "attrs": {"name": namespace},
+            "parents": [],
+        }));
+    }
+    entities
+}
+
+/// Watches one kind of object into a store; the stream yields once per change.
+fn watch<K>(api: Api<K>) -> (Store<K>, BoxStream<'static, Result<(), watcher::Error>>)
+where
+    K: Resource + Clone + serde::de::DeserializeOwned + std::fmt::Debug + Send + Sync + 'static,
+    K::DynamicType: Default + Eq + std::hash::Hash + Clone,
+{
+    let (reader, writer) = reflector::store();
+    let changes = reflector::reflector(writer, watcher(api, watcher::Config::default()).default_backoff())
+        .map_ok(|_| ())
+        .boxed();
+    (reader, changes)
+}
+
+/// An API for namespaced objects, limited to `namespace` if one is given.
+fn scoped<K>(client: &Client, namespace: Option<&str>) -> Api<K>
+where
+    K: Resource<Scope = k8s_openapi::NamespaceResourceScope>,
+    K::DynamicType: Default,
+{
+    match namespace {
+        Some(namespace) => Api::namespaced(client.clone(), namespace),
+        None => Api::all(client.clone()),
+    }
+}
+
+/// Replaces the importer's entities with ones built from the current stores.
+fn import(service: &CedarService, names: &Names, snapshot: Snapshot) -> Result<usize, String> {
+    let json = to_entities(names, &snapshot);
+    let schema = service.state().schema.clone();
+    let imported = crate::entities::parse_list(Value::Array(json), schema.as_ref())?;
+    let count = imported.len();
+    service.update_entities("kubernetes.sync", |current| {
+        let kept = current.iter().filter(|entity| !names.owns(entity)).cloned();
+        Ok::<_, String>(kept.chain(imported).collect())
+    })?;
+    Ok(count)
+}
+
+/// Starts watching service accounts and RBAC objects, across the cluster or in
+/// `CEDAR_K8S_NAMESPACE` only (where cluster roles and cluster role bindings are not watched).
+/// The first import runs once every watch has listed its objects; after that, each change is
+/// imported once things settle. Fails only if no Kubernetes client can be configured.
+pub async fn start(service: Arc<CedarService>, config: &Config) -> Result<(), Box<dyn std::error::Error>> {
+    let names = Names::new(&config.k8s_entity_namespace)?;
+    let client = Client::try_default()
+        .await
+        .map_err(|e| format!("Failed to configure the Kubernetes client: {}", e))?;
+
+    let namespace = config.k8s_namespace.as_deref();
+    let (service_accounts, sa_changes) = watch::<ServiceAccount>(scoped(&client, namespace));
+    let (roles, role_changes) = watch::<Role>(scoped(&client, namespace));
+    let (role_bindings, binding_changes) = watch::<RoleBinding>(scoped(&client, namespace));
+    let mut changes = vec![sa_changes, role_changes, binding_changes];
+    let cluster = match namespace {
+        Some(_) => None,
+        None => {
+            let (cluster_roles, cluster_role_changes) = watch::<ClusterRole>(Api::all(client.clone()));
+            let (cluster_bindings, cluster_binding_changes) = watch::<ClusterRoleBinding>(Api::all(client));
+            changes.extend([cluster_role_changes, cluster_binding_changes]);
+            Some((cluster_roles, cluster_bindings))
+        }
+    };
+
+    // Drives the watches; a failing watch is retried with backoff
+    let changed = Arc::new(Notify::new());
+    let notify = Arc::clone(&changed);
+    let errors = Arc::clone(&service);
+    tokio::spawn(async move {
+        let mut changes = stream::select_all(changes);
+        while let Some(change) = changes.next().await {
+            match change {
+                Ok(()) => notify.notify_one(),
+                Err(e) => {
+                    error!("Kubernetes watch failed: {}", e);
+                    errors.metrics.incr("entity_syncs", &[("source", "kubernetes"), ("result", "error")]);
+                    errors.source_failed("kubernetes", &e.to_string());
+                }
+            }
+        }
+    });
+
+    tokio::spawn(async move {
+        let ready = async {
+            service_accounts.wait_until_ready().await.ok()?;
+            roles.wait_until_ready().await.ok()?;
+            role_bindings.wait_until_ready().await.ok()?;
+            if let Some((ref cluster_roles, ref cluster_bindings)) = cluster {
+                cluster_roles.wait_until_ready().await.ok()?;
+                cluster_bindings.wait_until_ready().await.ok()?;
+            }
+            Some(())
+        };
+        if ready.await.is_none() {
+            return;
+        }
+        loop {
+            let snapshot = Snapshot {
+                service_accounts: service_accounts.state(),
+                roles: roles.state(),
+                role_bindings: role_bindings.state(),
+                cluster_roles: cluster.as_ref().map(|(r, _)| r.state()).unwrap_or_default(),
+                cluster_role_bindings: cluster.as_ref().map(|(_, b)| b.state()).unwrap_or_default(),
+            };
+            match import(&service, &names, snapshot) {
+                Ok(count) => {
+                    info!("Imported {} entities from Kubernetes", count);
+                    service.metrics.incr("entity_syncs", &[("source", "kubernetes"), ("result", "success")]);
+                    service.source_refreshed("kubernetes");
+                }
+                Err(e) => {
+                    error!("Failed to import entities from Kubernetes: {}", e);
+                    service.metrics.incr("entity_syncs", &[("source", "kubernetes"), ("result", "error")]);
+                    service.source_failed("kubernetes", &e);
+                }
+            }
+            changed.notified().await;
+            tokio::time::sleep(SETTLE).await;
+        }
+    });
+
+    info!(
+        "Watching Kubernetes service accounts and RBAC in {}",
+        config.k8s_namespace.as_deref().unwrap_or("all namespaces")
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn object<K: serde::de::DeserializeOwned>(json: Value) -> Arc<K> {
+        Arc::new(serde_json::from_value(json).unwrap())
+    }
+
+    fn snapshot() -> Snapshot {
+        Snapshot {
+            service_accounts: vec![object(json!({"metadata": {"name": "payments", "namespace": "prod"}}))],
+            roles: vec![object(json!({
+                "metadata": {"name": "reader", "namespace": "prod"},
+                "rules": [{"apiGroups": ["", "apps"], "resources": ["pods", "deployments"], "verbs": ["get"]}]
+            }))],
+            cluster_roles: vec![object(json!({
+                "metadata": {"name": "health"},
+                "rules": [{"nonResourceURLs": ["/healthz"], "verbs": ["get"]}]
+            }))],
+            role_bindings: vec![object(json!({
+                "metadata": {"name": "payments-reader", "namespace": "prod"},
+                "roleRef": {"apiGroup": "rbac.authorization.k8s.io", "kind": "Role", "name": "reader"},
+                "subjects": [{"kind": "ServiceAccount", "name": "payments"}, {"kind": "User", "name": "jane"}]
+            }))],
+            cluster_role_bindings: vec![object(json!({
+                "metadata": {"name": "health"},
+                "roleRef": {"apiGroup": "rbac.authorization.k8s.io", "kind": "ClusterRole", "name": "health"},
+                "subjects": [{"kind": "ServiceAccount", "name": "payments", "namespace": "prod"}]
+            }))],
+        }
+    }
+
+    #[test]
+    fn service_accounts_are_members_of_bound_roles() {
+        let entities = to_entities(&Names::new("k8s").unwrap(), &snapshot());
+        assert_eq!(
+            entities[0],
+            json!({
+                "uid": {"type": "k8s::ServiceAccount", "id": "prod/payments"},
+                "attrs": {"name": "payments", "namespace": "prod"},
+                "parents": [
+                    {"type": "k8s::Namespace", "id": "prod"},
+                    {"type": "k8s::Role", "id": "prod/reader"},
+                    {"type": "k8s::ClusterRole", "id": "health"}
+                ]
+            })
+        );
+        let store = crate::entities::EntityStore::from_json(Value::Array(entities), None, Default::default()).unwrap();
+        assert_eq!(store.len(), 4);
+    }
+
+    #[test]
+    fn role_rules_become_permissions() {
+        let entities = to_entities(&Names::new("k8s").unwrap(), &snapshot());
+        assert_eq!(
+            entities[1]["attrs"]["permissions"],
+            json!(["get apps/deployments", "get apps/pods", "get deployments", "get pods"])
+        );
+        assert_eq!(entities[2]["attrs"]["permissions"], json!(["get /healthz"]));
+    }
+}
@@ -0,0 +1,191 @@
+use crate::{audit, error_response, json_response, query_params, replay};
+use crate::{CedarService, PolicyState};
+use cedar_policy::{Policy, PolicyId};
+use hyper::{Body, Method, Response, StatusCode};
+use log::info;
+use std::sync::Arc;
+
+/// Logged decisions disabling a label group is simulated against.
+const SIMULATED_DECISIONS: usize = 1000;
+
+/// Policies selected by their annotations: `team=payments` matches `@team("payments")` and
+/// `break_glass` any policy annotated `@break_glass`. Comma-separated terms must all match.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Selector(Vec<(String, Option<String>)>);
+
+impl Selector {
+    pub fn parse(selector: &str) -> Result<Self, String> {
+        let terms = selector
+            .split(',')
+            .map(|term| {
+                let (key, value) = match term.split_once('=') {
+                    Some((key, value)) => (key.trim(), Some(value.trim().to_string())),
+                    None => (term.trim(), None),
+                };
+                if key.is_empty() {
+                    return Err(format!("Invalid label '{}' (expected key or key=value)", selector));
+                }
+                Ok((key.to_string(), value))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Self(terms))
+    }
+
+    pub fn matches(&self, policy: &Policy) -> bool {
+        self.0.iter().all(|(key, value)| match (policy.annotation(key), value) {
+            (Some(annotation), Some(value)) => annotation == value,
+            (found, None) => found.is_some(),
+            (None, Some(_)) => false,
+        })
+    }
+}
+
+impl std::fmt::Display for Selector {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let terms: Vec<String> = self
+            .0
+            .iter()
+            .map(|(key, value)| match value {
+                Some(value) => format!("{}={}", key, value),
+                None => key.clone(),
+            })
+            .collect();
+        f.write_str(&terms.join(","))
+    }
+}
+
+/// The operation on a label group that `path` names: `/v1/policies/{operation}?label=...`.
+pub fn operation(path: &str) -> Option<&str> {
+    path.strip_prefix("/v1/policies/")
+        .filter(|operation| ["export", "disable", "enable", "simulate"].contains(operation))
+}
+
+/// IDs of the policies `selector` matches, sorted.
+fn selected(state: &PolicyState, selector: &Selector) -> Vec<PolicyId> {
+    let mut ids: Vec<PolicyId> = state
+        .policy_set
+        .policies()
+        .filter(|policy| selector.matches(policy))
+        .map(|policy| policy.id().clone())
+        .collect();
+    ids.sort();
+    ids
+}
+
+/// Serves `/v1/policies/export`, `/v1/policies/disable`, `/v1/policies/enable` and
+/// `/v1/policies/simulate` for the policies selected by the `label` parameter.
+pub async fn handle(req: hyper::Request<Body>, service: Arc<CedarService>) -> Response<Body> {
+    let selector = match query_params(req.uri()).get("label").map(|label| Selector::parse(label)) {
+        Some(Ok(selector)) => selector,
+        Some(Err(e)) => return error_response(StatusCode::BAD_REQUEST, e),
+        None => return error_response(StatusCode::BAD_REQUEST, "Missing label parameter"),
+    };
+    let state = service.state();
+    let ids = selected(&state, &selector);
+    if ids.is_empty() {
+        return error_response(StatusCode::NOT_FOUND, format!("No policies labelled {}", selector));
+    }
+    match (req.method(), operation(req.uri().path()).unwrap_or_default()) {
+        (&Method::GET, "export") => export(&state, &ids),
+        (&Method::POST, "disable") => set_disabled(&service, &selector, &ids, true),
+        (&Method::POST, "enable") => set_disabled(&service, &selector, &ids, false),
+        (&Method::POST, "simulate") => simulate(&service, &state, &selector, ids).await,
+        _ => error_response(StatusCode::NOT_FOUND, "Not found"),
+    }
+}
+
+/// The selected policies as Cedar text, ready to be loaded elsewhere.
+fn export(state: &PolicyState, ids: &[PolicyId]) -> Response<Body> {
+    let text: Vec<String> = ids
+        .iter()
+        .filter_map(|id| state.policy_set.policy(id))
+        .map(|policy| policy.to_string())
+        .collect();
+    Response::builder()
+        .header("content-type", "text/plain; charset=utf-8")
+        .body(Body::from(text.join("\n\n") + "\n"))
+        .unwrap()
+}
+
+fn set_disabled(service: &CedarService, selector: &Selector, ids: &[PolicyId], disable: bool) -> Response<Body> {
+    if let Some(resp) = crate::proposals::refuse_unapproved(service, if disable { "Disabling policies" } else { "Enabling policies" }) {
+        return resp;
+    }
+    match service.set_disabled(ids, disable) {
+        Ok(changed) => {
+            if !changed.is_empty() {
+                info!(
+                    "{} policies labelled {} {} by {}",
+                    changed.len(),
+                    selector,
+                    if disable { "disabled" } else { "enabled" },
+                    audit::identified().as_deref().unwrap_or("an unidentified caller")
+                );
+            }
+            json_response(
+                StatusCode::OK,
+                &serde_json::json!({
+                    "label": selector.to_string(),
+                    "disabled": disable,
+                    "policies": ids.iter().map(ToString::to_string).collect::<Vec<_>>(),
+                    "changed": changed.iter().map(ToString::to_string).collect::<Vec<_>>(),
+                    "data_version": service.data_version(),
+                }),
+            )
+        }
+        Err(e) => error_response(StatusCode::CONFLICT, e),
+    }
+}
+
+/// Replays recent logged decisions with the selected policies disabled, to show what disabling
+/// them would change.
+async fn simulate(service: &Arc<CedarService>, state: &PolicyState, selector: &Selector, ids: Vec<PolicyId>) -> Response<Body> {
+    let mut disabled = state.disabled.clone();
+    disabled.extend(ids.iter().cloned());
+    match replay::simulate_recent(service, state.with_disabled(disabled), SIMULATED_DECISIONS).await {
+        Ok(Some(report)) => json_response(
+            StatusCode::OK,
+            &serde_json::json!({
+                "label": selector.to_string(),
+                "policies": ids.iter().map(ToString::to_string).collect::<Vec<_>>(),
+                "simulation": report,
+            }),
+        ),
+        Ok(None) => error_response(
+            StatusCode::CONFLICT,
+            "Simulating needs the decision log (CEDAR_DECISION_LOG_PATH)",
+        ),
+        Err(e) => error_response(StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to simulate: {}", e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn selects_policies_by_annotation() {
+        let policy_set = crate::policies::parse(
+            r#"@id("a") @team("payments") @tier("critical") permit(principal, action, resource);
+               @id("b") @team("payments") @break_glass("") permit(principal, action, resource);
+               @id("c") @team("search") permit(principal, action, resource);"#,
+        )
+        .unwrap();
+        let matching = |selector: &str| {
+            let selector = Selector::parse(selector).unwrap();
+            let mut ids: Vec<String> =
+                policy_set.policies().filter(|p| selector.matches(p)).map(|p| p.id().to_string()).collect();
+            ids.sort();
+            ids
+        };
+        assert_eq!(matching("team=payments"), ["a", "b"]);
+        assert_eq!(matching("team = payments, tier=critical"), ["a"]);
+        assert_eq!(matching("break_glass"), ["b"]);
+        assert_eq!(matching("team=billing"), Vec::<String>::new());
+        assert!(Selector::parse("=payments").is_err());
+        assert!(Selector::parse("team=payments,").is_err());
+        assert_eq!(Selector::parse("team = payments,tier").unwrap().to_string(), "team=payments,tier");
+        assert_eq!(operation("/v1/policies/disable"), Some("disable"));
+        assert_eq!(operation("/v1/policies/metadata"), None);
+    }
+}
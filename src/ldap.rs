@@ -0,0 +1,286 @@
+use crate::config::Config;
+use crate::CedarService;
+use cedar_policy::{Entity, EntityTypeName};
+use ldap3::adapters::{Adapter, EntriesOnly, PagedResults};
+use ldap3::{LdapConnAsync, Scope, SearchEntry};
+use log::{error, info};
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+/// Entries per page; Active Directory refuses unpaged searches past 1000 results.
+const PAGE_SIZE: i32 = 500;
+
+/// How directory entries become entities.
+pub struct Mapping {
+    pub user_type: EntityTypeName,
+    pub group_type: EntityTypeName,
+    pub user_id_attr: String,
+    pub group_id_attr: String,
+    /// Group attribute listing member DNs (users or nested groups).
+    pub member_attr: String,
+    /// `(ldap attribute, entity attribute)` pairs copied onto users as strings.
+    pub user_attrs: Vec<(String, String)>,
+}
+
+impl Mapping {
+    pub fn from_config(config: &Config) -> Result<Self, Box<dyn std::error::Error>> {
+        let type_name = |key: &str, value: &str| -> Result<EntityTypeName, String> {
+            value.parse().map_err(|e| format!("Invalid {}: {}", key, e))
+        };
+        Ok(Self {
+            user_type: type_name("CEDAR_LDAP_USER_TYPE", &config.ldap_user_type)?,
+            group_type: type_name("CEDAR_LDAP_GROUP_TYPE", &config.ldap_group_type)?,
+            user_id_attr: config.ldap_user_id_attr.clone(),
+            group_id_attr: config.ldap_group_id_attr.clone(),
+            member_attr: config.ldap_member_attr.clone(),
+            user_attrs: config
+                .ldap_user_attrs
+                .iter()
+                .map(|attr| match attr.split_once('=') {
+                    Some((ldap, cedar)) => (ldap.to_string(), cedar.to_string()),
+                    None => (attr.clone(), attr.clone()),
+                })
+                .collect(),
+        })
+    }
+
+    /// Whether an entity is owned by the sync, i.e. replaced on every run.
+    fn owns(&self, entity: &Entity) -> bool {
+        let type_name = entity.uid().type_name().clone();
+        type_name == self.user_type || type_name == self.group_type
+    }
+}
+
+/// First value of an attribute; attribute names are matched case-insensitively, as LDAP does.
+fn first<'a>(entry: &'a SearchEntry, attr: &str) -> Option<&'a str> {
+    entry
+        .attrs
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case(attr))
+        .and_then(|(_, values)| values.first())
+        .map(String::as_str)
+}
+
+/// Turns users and groups into entities in Cedar's JSON format. Each member DN of a group that
+/// names a synced user or group becomes a parent edge, so nested groups carry over. Entries
+/// without the ID attribute, and member DNs outside the synced entries, are skipped.
+pub fn to_entities(mapping: &Mapping, users: &[SearchEntry], groups: &[SearchEntry]) -> Vec<serde_json::Value> {
+    let uid = |type_name: &EntityTypeName, id: &str| serde_json::json!({"type": type_name.to_string(), "id": id});
+
+    // DNs compare case-insensitively
+    let mut by_dn: HashMap<String, serde_json::Value> = HashMap::new();
+    for (entries, type_name, id_attr) in [
+        (users, &mapping.user_type, &mapping.user_id_attr),
+        (groups, &mapping.group_type, &mapping.group_id_attr),
+    ] {
+        for entry in entries {
+            if let Some(id) = first(entry, id_attr) {
+                by_dn.insert(entry.dn.to_lowercase(), uid(type_name, id));
+            }
+        }
+    }
+
+    let mut parents: HashMap<String, Vec<serde_json::Value>> = HashMap::new();
+    for group in groups {
+        let Some(group_uid) = by_dn.get(&group.dn.to_lowercase()) else {
+            continue;
+        };
+        let members = group
+            .attrs
+            .iter()
+            .filter(|(name, _)| name.eq_ignore_ascii_case(&mapping.member_attr))
+            .flat_map(|(_, values)| values);
+        for member in members.map(|dn| dn.to_lowercase()) {
+            if !by_dn.contains_key(&member) {
+                continue;
+            }
+            let member_parents = parents.entry(member).or_default();
+            if !member_parents.contains(group_uid) {
+                member_parents.push(group_uid.clone());
+            }
+        }
+    }
+
+    let mut seen = HashSet::new();
+    let mut entities = Vec::new();
+    for (entries, is_user) in [(users, true), (groups, false)] {
+        for entry in entries {
+            let dn = entry.dn.to_lowercase();
+            let Some(entity_uid) = by_dn.get(&dn) else {
+                continue;
+            };
+            // The same entry may match both filters; the first mapping wins
+            if !seen.insert(dn.clone()) {
+                continue;
+            }
+            let mut attrs = serde_json::Map::new();
+            if is_user {
+                for (ldap_attr, cedar_attr) in &mapping.user_attrs {
+                    if let Some(value) = first(entry, ldap_attr) {
+                        attrs.insert(cedar_attr.clone(), value.into());
+                    }
+                }
+            }
+            entities.push(serde_json::json!({
+                "uid": entity_uid,
+                "attrs": attrs,
+                "parents": parents.remove(&dn).unwrap_or_default(),
+            }));
+        }
+    }
+    entities
+}
+
+/// Runs a paged subtree search and collects the entries.
+async fn search(
+    ldap: &mut ldap3::Ldap,
+    base: &str,
+    filter: &str,
+    attrs: Vec<String>,
+) -> Result<Vec<SearchEntry>, ldap3::LdapError> {
+    let adapters: Vec<Box<dyn Adapter<_, _>>> =
+        vec![Box::new(EntriesOnly::new()), Box::new(PagedResults::new(PAGE_SIZE))];
+    let mut stream = ldap
+        .streaming_search_with(adapters, base, Scope::Subtree, filter, attrs)
+        .await?;
+    let mut entries = Vec::new();
+    while let Some(entry) = stream.next().await? {
+        entries.push(SearchEntry::construct(entry));
+    }
+    stream.finish().await.success()?;
+    Ok(entries)
+}
+
+/// Reads users and groups from the directory and maps them to entities.
+async fn fetch(config: &Config, mapping: &Mapping) -> Result<Vec<serde_json::Value>, Box<dyn std::error::Error>> {
+    let url = config.ldap_url.as_deref().unwrap_or_default();
+    let (conn, mut ldap) = LdapConnAsync::new(url)
+        .await
+        .map_err(|e| format!("Failed to connect to {}: {}", url, e))?;
+    ldap3::drive!(conn);
+
+    if let Some(ref bind_dn) = config.ldap_bind_dn {
+        let password = config.ldap_bind_password.as_deref().unwrap_or_default();
+        ldap.simple_bind(bind_dn, password)
+            .await
+            .and_then(|result| result.success())
+            .map_err(|e| format!("Failed to bind as {}: {}", bind_dn, e))?;
+    }
+
+    let mut user_attrs = vec![mapping.user_id_attr.clone()];
+    user_attrs.extend(mapping.user_attrs.iter().map(|(ldap_attr, _)| ldap_attr.clone()));
+    let group_attrs = vec![mapping.group_id_attr.clone(), mapping.member_attr.clone()];
+    let users = search(&mut ldap, &config.ldap_base_dn, &config.ldap_user_filter, user_attrs)
+        .await
+        .map_err(|e| format!("Failed to search users: {}", e))?;
+    let groups = search(&mut ldap, &config.ldap_base_dn, &config.ldap_group_filter, group_attrs)
+        .await
+        .map_err(|e| format!("Failed to search groups: {}", e))?;
+    let _ = ldap.unbind().await;
+
+    Ok(to_entities(mapping, &users, &groups))
+}
+
+/// One sync: replaces the stored entities of the user and group types with the directory's.
+async fn sync(service: &CedarService, config: &Config, mapping: &Mapping) -> Result<usize, Box<dyn std::error::Error>> {
+    let json = fetch(config, mapping).await?;
+    let schema = service.state().schema.clone();
+    let synced = crate::entities::parse_list(serde_json::Value::Array(json), schema.as_ref())?;
+    let count = synced.len();
+    service.update_entities("ldap.sync", |current| {
+        let kept = current.iter().filter(|entity| !mapping.owns(entity)).cloned();
+        Ok::<_, String>(kept.chain(synced).collect())
+    })?;
+    Ok(count)
+}
+
+/// Runs the first sync, failing startup if it does not succeed, then keeps syncing every
+/// `CEDAR_LDAP_SYNC_INTERVAL_SECS` in the background. A failed later sync keeps the entities of
+/// the last successful one.
+pub async fn start(service: Arc<CedarService>, config: &Config) -> Result<(), Box<dyn std::error::Error>> {
+    let mapping = Mapping::from_config(config)?;
+    let count = sync(&service, config, &mapping)
+        .await
+        .map_err(|e| format!("Failed to sync entities from LDAP: {}", e))?;
+    info!("Synced {} entities from LDAP", count);
+    service.metrics.incr("entity_syncs", &[("source", "ldap"), ("result", "success")]);
+    service.source_refreshed("ldap");
+
+    let config = config.clone();
+    tokio::spawn(async move {
+        let mut ticks = tokio::time::interval(config.ldap_sync_interval);
+        ticks.tick().await;
+        loop {
+            ticks.tick().await;
+            match sync(&service, &config, &mapping).await {
+                Ok(count) => {
+                    info!("Synced {} entities from LDAP", count);
+                    service.metrics.incr("entity_syncs", &[("source", "ldap"), ("result", "success")]);
+                    service.source_refreshed("ldap");
+                }
+                Err(e) => {
+                    error!("Failed to sync entities from LDAP: {}", e);
+                    service.metrics.incr("entity_syncs", &[("source", "ldap"), ("result", "error")]);
+                    service.source_failed("ldap", &e.to_string());
+                }
+            }
+        }
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(dn: &str, attrs: &[(&str, &[&str])]) -> SearchEntry {
+        SearchEntry {
+            dn: dn.to_string(),
+            attrs: attrs
+                .iter()
+                .map(|(name, values)| (name.to_string(), values.iter().map(|v| v.to_string()).collect()))
+                .collect(),
+            bin_attrs: HashMap::new(),
+        }
+    }
+
+    fn mapping() -> Mapping {
+        Mapping {
+            user_type: "User".parse().unwrap(),
+            group_type: "Group".parse().unwrap(),
+            user_id_attr: "uid".to_string(),
+            group_id_attr: "cn".to_string(),
+            member_attr: "member".to_string(),
+            user_attrs: vec![("mail".to_string(), "email".to_string())],
+        }
+    }
+
+    #[test]
+    fn maps_users_groups_and_nested_membership() {
+        let users = [entry("uid=alice,ou=people,dc=example,dc=org", &[("uid", &["alice"]), ("mail", &["alice@example.org"])])];
+        let groups = [
+            entry("cn=ops,ou=groups,dc=example,dc=org", &[("cn", &["ops"]), ("member", &["UID=Alice,ou=people,dc=example,dc=org"])]),
+            entry("cn=admins,ou=groups,dc=example,dc=org", &[("cn", &["admins"]), ("member", &["cn=ops,ou=groups,dc=example,dc=org"])]),
+        ];
+        let entities = to_entities(&mapping(), &users, &groups);
+        assert_eq!(
+            entities,
+            [
+                serde_json::json!({"uid": {"type": "User", "id": "alice"}, "attrs": {"email": "alice@example.org"}, "parents": [{"type": "Group", "id": "ops"}]}),
+                serde_json::json!({"uid": {"type": "Group", "id": "ops"}, "attrs": {}, "parents": [{"type": "Group", "id": "admins"}]}),
+                serde_json::json!({"uid": {"type": "Group", "id": "admins"}, "attrs": {}, "parents": []}),
+            ]
+        );
+    }
+
+    #[test]
+    fn skips_entries_without_ids_and_unknown_members() {
+        let users = [entry("uid=svc,dc=example,dc=org", &[("cn", &["svc"])])];
+        let groups = [entry(
+            "cn=ops,dc=example,dc=org",
+            &[("CN", &["ops"]), ("Member", &["uid=svc,dc=example,dc=org", "uid=gone,dc=example,dc=org"])],
+        )];
+        let entities = to_entities(&mapping(), &users, &groups);
+        assert_eq!(entities, [serde_json::json!({"uid": {"type": "Group", "id": "ops"}, "attrs": {}, "parents": []})]);
+    }
+}
@@ -0,0 +1,109 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+use tokio::sync::{Semaphore, SemaphorePermit};
+
+/// Why a request was shed.
+#[derive(Debug, PartialEq)]
+pub enum Shed {
+    /// Every evaluation slot was busy and the queue was full.
+    QueueFull,
+    /// The request waited in the queue longer than allowed.
+    QueueTimeout,
+}
+
+impl Shed {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Shed::QueueFull => "queue_full",
+            Shed::QueueTimeout => "queue_timeout",
+        }
+    }
+}
+
+/// Caps the evaluations in flight. Requests beyond the cap wait in a bounded queue for a slot;
+/// once the queue is full, or a request has waited too long, it is refused straight away so
+/// admitted requests keep their latency instead of everything slowing down together.
+pub struct Limiter {
+    slots: Semaphore,
+    max_queued: usize,
+    queued: AtomicUsize,
+    queue_timeout: Duration,
+}
+
+impl Limiter {
+    pub fn new(max_concurrent: usize, max_queued: usize, queue_timeout: Duration) -> Self {
+        Self {
+            slots: Semaphore::new(max_concurrent),
+            max_queued,
+            queued: AtomicUsize::new(0),
+            queue_timeout,
+        }
+    }
+
+    /// Takes an evaluation slot, held until the permit is dropped. The wait for one ends early
+    /// at the deadline of the request being served.
+    pub async fn acquire(&self) -> Result<SemaphorePermit<'_>, Shed> {
+        if let Ok(permit) = self.slots.try_acquire() {
+            return Ok(permit);
+        }
+        let joined = self
+            .queued
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |queued| {
+                (queued < self.max_queued).then_some(queued + 1)
+            });
+        if joined.is_err() {
+            return Err(Shed::QueueFull);
+        }
+        let waited = tokio::time::timeout(crate::deadline::bound(self.queue_timeout), self.slots.acquire()).await;
+        self.queued.fetch_sub(1, Ordering::AcqRel);
+        match waited {
+            Ok(Ok(permit)) => Ok(permit),
+            // The semaphore is never closed
+            Ok(Err(_)) | Err(_) => Err(Shed::QueueTimeout),
+        }
+    }
+
+    /// Requests currently waiting for a slot.
+    pub fn queued(&self) -> usize {
+        self.queued.load(Ordering::Acquire)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn queues_then_sheds_beyond_the_queue() {
+        let limiter = Limiter::new(1, 1, Duration::from_secs(5));
+        let first = limiter.acquire().await.unwrap();
+
+        // The second request waits; the third finds the queue full
+        let (second, third) = tokio::join!(limiter.acquire(), async {
+            while limiter.queued() == 0 {
+                tokio::task::yield_now().await;
+            }
+            let third = limiter.acquire().await.map(|_| ());
+            drop(first);
+            third
+        });
+        assert!(second.is_ok());
+        assert_eq!(third, Err(Shed::QueueFull));
+        assert_eq!(limiter.queued(), 0);
+    }
+
+    #[tokio::test]
+    async fn sheds_after_the_queue_timeout() {
+        let limiter = Limiter::new(1, 4, Duration::from_millis(20));
+        let _held = limiter.acquire().await.unwrap();
+        assert_eq!(limiter.acquire().await.unwrap_err(), Shed::QueueTimeout);
+        assert_eq!(limiter.queued(), 0);
+    }
+
+    #[tokio::test]
+    async fn without_a_queue_sheds_immediately() {
+        let limiter = Limiter::new(1, 0, Duration::from_secs(5));
+        let _held = limiter.acquire().await.unwrap();
+        assert_eq!(limiter.acquire().await.unwrap_err(), Shed::QueueFull);
+    }
+}
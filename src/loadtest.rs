@@ -0,0 +1,289 @@
+use crate::bench::{load_corpus, percentile};
+use crate::fetch::{https_client, HttpClient};
+use crate::parse_wait;
+use hyper::header::{HeaderName, HeaderValue};
+use hyper::{Body, Method, Request, Uri};
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::Semaphore;
+
+/// How often the sender catches up with the requests due at the target rate.
+const TICK: Duration = Duration::from_millis(1);
+
+struct LoadtestOptions {
+    target: Uri,
+    corpus_path: String,
+    rps: u64,
+    duration: Duration,
+    concurrency: usize,
+    timeout: Duration,
+    headers: Vec<(HeaderName, HeaderValue)>,
+}
+
+impl LoadtestOptions {
+    fn parse(args: &[String]) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut target = None;
+        let mut opts = LoadtestOptions {
+            target: Uri::default(),
+            corpus_path: String::new(),
+            rps: 100,
+            duration: Duration::from_secs(30),
+            concurrency: 1000,
+            timeout: Duration::from_secs(10),
+            headers: Vec::new(),
+        };
+
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let mut value = |name: &str| {
+                args.next()
+                    .cloned()
+                    .ok_or_else(|| format!("Missing value for {}", name))
+            };
+            match arg.as_str() {
+                "--target" => target = Some(value(arg)?),
+                "--corpus" => opts.corpus_path = value(arg)?,
+                "--rps" => opts.rps = value(arg)?.parse().map_err(|e| format!("Invalid --rps: {}", e))?,
+                "--duration" => opts.duration = parse_wait(&value(arg)?).map_err(|e| format!("Invalid --duration: {}", e))?,
+                "--concurrency" => {
+                    opts.concurrency = value(arg)?
+                        .parse()
+                        .map_err(|e| format!("Invalid --concurrency: {}", e))?
+                }
+                "--timeout" => opts.timeout = parse_wait(&value(arg)?).map_err(|e| format!("Invalid --timeout: {}", e))?,
+                "--header" => opts.headers.push(parse_header(&value(arg)?)?),
+                other => return Err(format!("Unknown loadtest argument: {}", other).into()),
+            }
+        }
+
+        let (Some(target), false) = (target, opts.corpus_path.is_empty()) else {
+            return Err("Usage: cedar-agent loadtest --target <http://host:port> --corpus <requests.jsonl> \
+                        [--rps N] [--duration 30s] [--concurrency N] [--timeout 10s] [--header 'Name: value']"
+                .into());
+        };
+        opts.target = authorize_uri(&target)?;
+        if opts.rps == 0 {
+            return Err("--rps must be greater than zero".into());
+        }
+        if opts.duration.is_zero() || opts.timeout.is_zero() {
+            return Err("--duration and --timeout must be greater than zero".into());
+        }
+        if opts.concurrency == 0 {
+            return Err("--concurrency must be greater than zero".into());
+        }
+        Ok(opts)
+    }
+}
+
+/// The `/authorize` endpoint of the agent at `target`, given as its base URL.
+fn authorize_uri(target: &str) -> Result<Uri, String> {
+    let uri = format!("{}/authorize", target.trim_end_matches('/'))
+        .parse::<Uri>()
+        .map_err(|e| format!("Invalid --target {}: {}", target, e))?;
+    match uri.scheme_str() {
+        Some("http" | "https") if uri.host().is_some() => Ok(uri),
+        _ => Err(format!("Invalid --target {}: expected an http:// or https:// URL", target)),
+    }
+}
+
+/// Parses a `Name: value` header sent with every request, such as an API key.
+fn parse_header(header: &str) -> Result<(HeaderName, HeaderValue), String> {
+    let (name, value) = header
+        .split_once(':')
+        .ok_or_else(|| format!("Invalid --header '{}': expected 'Name: value'", header))?;
+    let name = name.trim().parse::<HeaderName>().map_err(|e| format!("Invalid --header name: {}", e))?;
+    let value = value.trim().parse::<HeaderValue>().map_err(|e| format!("Invalid --header value: {}", e))?;
+    Ok((name, value))
+}
+
+/// What happened to the requests sent.
+#[derive(Default)]
+struct Results {
+    /// Latency of every request answered, whatever its status.
+    samples: Vec<Duration>,
+    statuses: BTreeMap<u16, usize>,
+    /// Requests that failed to connect or were not answered in time.
+    failed: usize,
+    timed_out: usize,
+    /// Requests not sent because `--concurrency` were already in flight.
+    skipped: usize,
+}
+
+impl Results {
+    fn answered(&self) -> usize {
+        self.statuses.values().sum()
+    }
+
+    /// Requests that failed, timed out or were answered with an error status.
+    fn errors(&self) -> usize {
+        let error_statuses: usize = self.statuses.iter().filter(|(status, _)| **status >= 400).map(|(_, n)| n).sum();
+        self.failed + self.timed_out + error_statuses
+    }
+
+    fn error_rate(&self) -> f64 {
+        let sent = self.answered() + self.failed + self.timed_out;
+        if sent == 0 {
+            0.0
+        } else {
+            self.errors() as f64 / sent as f64 * 100.0
+        }
+    }
+}
+
+async fn send(client: &HttpClient, opts: &LoadtestOptions, body: String, results: &Mutex<Results>) {
+    let mut req = Request::builder()
+        .method(Method::POST)
+        .uri(opts.target.clone())
+        .header("content-type", "application/json");
+    for (name, value) in &opts.headers {
+        req = req.header(name, value);
+    }
+    let req = req.body(Body::from(body)).expect("valid loadtest request");
+
+    let started = Instant::now();
+    let answered = tokio::time::timeout(opts.timeout, async {
+        let resp = client.request(req).await?;
+        let status = resp.status().as_u16();
+        hyper::body::to_bytes(resp.into_body()).await?;
+        Ok::<_, hyper::Error>(status)
+    })
+    .await;
+    let elapsed = started.elapsed();
+    let mut results = results.lock().unwrap();
+    match answered {
+        Ok(Ok(status)) => {
+            results.samples.push(elapsed);
+            *results.statuses.entry(status).or_default() += 1;
+        }
+        Ok(Err(_)) => results.failed += 1,
+        Err(_) => results.timed_out += 1,
+    }
+}
+
+fn report(opts: &LoadtestOptions, mut results: Results, elapsed: Duration) {
+    results.samples.sort();
+    let sent = results.answered() + results.failed + results.timed_out;
+    println!("Load test of {} at {} req/s for {:?}", opts.target, opts.rps, opts.duration);
+    println!("  sent:       {} ({:.0} req/s)", sent, sent as f64 / elapsed.as_secs_f64());
+    if results.skipped > 0 {
+        println!("  skipped:    {} (--concurrency {} in flight)", results.skipped, opts.concurrency);
+    }
+    let statuses = results
+        .statuses
+        .iter()
+        .map(|(status, n)| format!("{} x{}", status, n))
+        .collect::<Vec<_>>()
+        .join(", ");
+    println!("  answered:   {} ({})", results.answered(), statuses);
+    println!("  failed:     {} ({} timed out)", results.failed + results.timed_out, results.timed_out);
+    println!("  error rate: {:.2}%", results.error_rate());
+    if let Some(max) = results.samples.last() {
+        println!(
+            "  latency:    p50 {:?}  p90 {:?}  p99 {:?}  p99.9 {:?}  max {:?}",
+            percentile(&results.samples, 0.50),
+            percentile(&results.samples, 0.90),
+            percentile(&results.samples, 0.99),
+            percentile(&results.samples, 0.999),
+            max
+        );
+    }
+}
+
+/// Sends the corpus, in a loop, to a remote agent at a steady rate, then reports latency
+/// percentiles and error rates. Requests are sent on schedule whether or not earlier ones were
+/// answered, up to `--concurrency` in flight, so a slow agent shows as latency rather than as a
+/// lower rate.
+pub async fn run(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let opts = Arc::new(LoadtestOptions::parse(args)?);
+    let corpus: Vec<String> = load_corpus(&opts.corpus_path)?.into_iter().map(|(body, _)| body).collect();
+    let client = https_client()?;
+    let results = Arc::new(Mutex::new(Results::default()));
+    let in_flight = Arc::new(Semaphore::new(opts.concurrency));
+
+    println!(
+        "Sending {} distinct requests to {} at {} req/s for {:?}",
+        corpus.len(),
+        opts.target,
+        opts.rps,
+        opts.duration
+    );
+    let mut bodies = corpus.iter().cycle();
+    let mut ticks = tokio::time::interval(TICK);
+    ticks.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+    let started = Instant::now();
+    let mut due_sent = 0u64;
+    while started.elapsed() < opts.duration {
+        ticks.tick().await;
+        let due = (started.elapsed().as_secs_f64() * opts.rps as f64) as u64;
+        for _ in due_sent..due {
+            let body = bodies.next().expect("the corpus is not empty").clone();
+            let Ok(permit) = Arc::clone(&in_flight).try_acquire_owned() else {
+                results.lock().unwrap().skipped += 1;
+                continue;
+            };
+            let (client, opts, results) = (client.clone(), Arc::clone(&opts), Arc::clone(&results));
+            tokio::spawn(async move {
+                send(&client, &opts, body, &results).await;
+                drop(permit);
+            });
+        }
+        due_sent = due_sent.max(due);
+    }
+    // Wait for the requests still in flight
+    let _all = in_flight.acquire_many(opts.concurrency as u32).await?;
+    let elapsed = started.elapsed();
+
+    let results = std::mem::take(&mut *results.lock().unwrap());
+    report(&opts, results, elapsed);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn parses_options() {
+        let opts = LoadtestOptions::parse(&args(&[
+            "--target",
+            "http://agent:8181/",
+            "--corpus",
+            "requests.jsonl",
+            "--rps",
+            "5000",
+            "--duration",
+            "2m",
+            "--header",
+            "X-API-Key: secret",
+        ]))
+        .unwrap();
+        assert_eq!(opts.target, "http://agent:8181/authorize");
+        assert_eq!((opts.rps, opts.duration), (5000, Duration::from_secs(120)));
+        assert_eq!(opts.headers[0].0, "x-api-key");
+        assert_eq!(opts.headers[0].1, "secret");
+
+        assert!(LoadtestOptions::parse(&args(&["--target", "http://agent:8181"])).is_err());
+        assert!(LoadtestOptions::parse(&args(&["--target", "agent:8181", "--corpus", "r.jsonl"])).is_err());
+        assert!(LoadtestOptions::parse(&args(&["--target", "http://a", "--corpus", "r.jsonl", "--rps", "0"])).is_err());
+        assert!(LoadtestOptions::parse(&args(&["--target", "http://a", "--corpus", "r.jsonl", "--header", "X"])).is_err());
+    }
+
+    #[test]
+    fn counts_errors_over_requests_sent() {
+        let mut results = Results::default();
+        assert_eq!(results.error_rate(), 0.0);
+        results.statuses.insert(200, 90);
+        results.statuses.insert(503, 5);
+        results.failed = 3;
+        results.timed_out = 2;
+        // Skipped requests were never sent
+        results.skipped = 50;
+        assert_eq!(results.errors(), 10);
+        assert_eq!(results.error_rate(), 10.0);
+    }
+}
@@ -0,0 +1,279 @@
+use crate::config::Config;
+use chrono::Utc;
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use std::io::Write;
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs, UdpSocket};
+#[cfg(unix)]
+use std::os::unix::net::UnixDatagram;
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::time::Duration;
+
+/// Log target for authorization decision records, reported as MSGID `decision` in syslog.
+pub const DECISION: &str = "decision";
+
+/// Parses a syslog facility name (`daemon`, `local0`..`local7`, ...) into its code.
+pub fn parse_facility(name: &str) -> Result<u8, String> {
+    let code = match name.to_ascii_lowercase().as_str() {
+        "kern" => 0,
+        "user" => 1,
+        "mail" => 2,
+        "daemon" => 3,
+        "auth" => 4,
+        "syslog" => 5,
+        "authpriv" => 10,
+        local if local.starts_with("local") => match local["local".len()..].parse::<u8>() {
+            Ok(n) if n <= 7 => 16 + n,
+            _ => return Err(format!("Invalid syslog facility '{}'", name)),
+        },
+        _ => return Err(format!("Invalid syslog facility '{}'", name)),
+    };
+    Ok(code)
+}
+
+/// Lines waiting for the TCP writer; beyond this, new lines are dropped rather than queued.
+const TCP_QUEUE: usize = 4096;
+/// Bound on each connect and write, so an unreachable collector only delays the writer thread.
+const TCP_TIMEOUT: Duration = Duration::from_secs(2);
+
+fn tcp_connect(addr: &str) -> std::io::Result<TcpStream> {
+    let addrs: Vec<SocketAddr> = addr.to_socket_addrs()?.collect();
+    let mut last_err = std::io::Error::new(std::io::ErrorKind::NotFound, "address resolved to nothing");
+    for addr in addrs {
+        match TcpStream::connect_timeout(&addr, TCP_TIMEOUT) {
+            Ok(stream) => {
+                stream.set_write_timeout(Some(TCP_TIMEOUT))?;
+                return Ok(stream);
+            }
+            Err(e) => last_err = e,
+        }
+    }
+    Err(last_err)
+}
+
+/// Writes queued lines to the collector, reconnecting on the next line after a failure. Runs on
+/// its own thread so logging never blocks on the network.
+fn tcp_writer(addr: String, mut stream: Option<TcpStream>, lines: Receiver<String>) {
+    for line in lines {
+        if stream.is_none() {
+            stream = tcp_connect(&addr).ok();
+        }
+        if let Some(ref mut s) = stream {
+            if write!(s, "{} {}", line.len(), line).is_err() {
+                stream = None;
+            }
+        }
+    }
+}
+
+enum Transport {
+    Udp(UdpSocket),
+    /// Handed to a writer thread through a bounded queue.
+    Tcp(SyncSender<String>),
+    #[cfg(unix)]
+    Unix(UnixDatagram, String),
+}
+
+/// RFC 5424 syslog sink over UDP, TCP (octet-counting framing) or a local datagram socket.
+struct Syslog {
+    transport: Transport,
+    facility: u8,
+    hostname: String,
+    app_name: String,
+}
+
+impl Syslog {
+    /// Connects to `udp://host:port`, `tcp://host:port` or `unix:///dev/log`.
+    fn connect(target: &str, facility: u8) -> Result<Self, Box<dyn std::error::Error>> {
+        let transport = match target.split_once("://") {
+            Some(("udp", addr)) => {
+                let socket = UdpSocket::bind("0.0.0.0:0")
+                    .map_err(|e| format!("Failed to open syslog socket: {}", e))?;
+                socket
+                    .connect(addr)
+                    .map_err(|e| format!("Failed to resolve syslog address {}: {}", addr, e))?;
+                Transport::Udp(socket)
+            }
+            Some(("tcp", addr)) => {
+                let stream = tcp_connect(addr)
+                    .map_err(|e| format!("Failed to connect to syslog at {}: {}", addr, e))?;
+                let (lines, queue) = mpsc::sync_channel(TCP_QUEUE);
+                let addr = addr.to_string();
+                std::thread::Builder::new()
+                    .name("syslog-tcp".to_string())
+                    .spawn(move || tcp_writer(addr, Some(stream), queue))
+                    .map_err(|e| format!("Failed to start syslog writer: {}", e))?;
+                Transport::Tcp(lines)
+            }
+            #[cfg(unix)]
+            Some(("unix", path)) => {
+                let socket = UnixDatagram::unbound()
+                    .map_err(|e| format!("Failed to open syslog socket: {}", e))?;
+                Transport::Unix(socket, path.to_string())
+            }
+            _ => {
+                return Err(format!(
+                    "Invalid syslog target '{}' (expected udp://host:port, tcp://host:port or unix:///path)",
+                    target
+                )
+                .into())
+            }
+        };
+
+        let hostname = std::env::var("HOSTNAME")
+            .ok()
+            .or_else(|| std::fs::read_to_string("/etc/hostname").ok())
+            .map(|h| h.trim().to_string())
+            .filter(|h| !h.is_empty())
+            .unwrap_or_else(|| "-".to_string());
+
+        Ok(Self {
+            transport,
+            facility,
+            hostname,
+            app_name: "cedar-agent".to_string(),
+        })
+    }
+
+    fn send(&self, record: &Record) {
+        let severity = match record.level() {
+            Level::Error => 3,
+            Level::Warn => 4,
+            Level::Info => 6,
+            Level::Debug | Level::Trace => 7,
+        };
+        let msg_id = if record.target() == DECISION { DECISION } else { "-" };
+        let line = format!(
+            "<{}>1 {} {} {} {} {} - {}",
+            self.facility * 8 + severity,
+            Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Micros, true),
+            self.hostname,
+            self.app_name,
+            std::process::id(),
+            msg_id,
+            record.args()
+        );
+
+        // Delivery is best-effort; a lost log line must not fail the request that produced it
+        match &self.transport {
+            Transport::Udp(socket) => {
+                let _ = socket.send(line.as_bytes());
+            }
+            Transport::Tcp(lines) => {
+                let _ = lines.try_send(line);
+            }
+            #[cfg(unix)]
+            Transport::Unix(socket, path) => {
+                let _ = socket.send_to(line.as_bytes(), path);
+            }
+        }
+    }
+}
+
+/// Writes info and below to stdout and warnings and errors to stderr, as the agent always has,
+/// and copies every record to syslog when configured, and to the Windows Event Log when the
+/// agent runs as a service.
+struct Logger {
+    syslog: Option<Syslog>,
+    #[cfg(windows)]
+    event_log: Option<crate::winservice::EventLog>,
+}
+
+impl Logger {
+    fn new(config: &Config) -> Result<Self, Box<dyn std::error::Error>> {
+        let syslog = match config.syslog {
+            Some(ref target) => Some(Syslog::connect(target, config.syslog_facility)?),
+            None => None,
+        };
+        Ok(Self {
+            syslog,
+            #[cfg(windows)]
+            event_log: None,
+        })
+    }
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        match record.level() {
+            Level::Error => eprintln!("{}", record.args()),
+            Level::Warn => eprintln!("Warning: {}", record.args()),
+            _ => println!("{}", record.args()),
+        }
+        if let Some(ref syslog) = self.syslog {
+            syslog.send(record);
+        }
+        #[cfg(windows)]
+        if let Some(ref event_log) = self.event_log {
+            event_log.report(record.level(), &record.args().to_string());
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+/// Installs the global logger with the configured level and outputs.
+pub fn init(config: &Config) -> Result<(), Box<dyn std::error::Error>> {
+    install(config, Logger::new(config)?)
+}
+
+/// Installs the global logger as `init` does, writing to the Windows Event Log as `source` too,
+/// for the agent running as a service without a console.
+#[cfg(windows)]
+pub fn init_with_event_log(config: &Config, source: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let mut logger = Logger::new(config)?;
+    logger.event_log = Some(crate::winservice::EventLog::register(source)?);
+    install(config, logger)
+}
+
+fn install(config: &Config, logger: Logger) -> Result<(), Box<dyn std::error::Error>> {
+    log::set_boxed_logger(Box::new(logger))
+        .map_err(|e| format!("Failed to install logger: {}", e))?;
+    log::set_max_level(config.log_level);
+
+    if let Some(ref target) = config.syslog {
+        log::info!("Sending logs to syslog at {}", target);
+    }
+    Ok(())
+}
+
+/// Changes the log level of the running agent.
+pub fn set_level(level: LevelFilter) {
+    let previous = log::max_level();
+    log::set_max_level(level);
+    // Logged at warn so the change is visible whatever the new level is
+    log::warn!("Log level changed from {} to {}", previous, level);
+}
+
+/// Next level in the SIGUSR1 cycle: info → debug → trace → info. Levels below info go to debug.
+fn next_level(level: LevelFilter) -> LevelFilter {
+    match level {
+        LevelFilter::Debug => LevelFilter::Trace,
+        LevelFilter::Trace => LevelFilter::Info,
+        LevelFilter::Info => LevelFilter::Debug,
+        _ => LevelFilter::Debug,
+    }
+}
+
+/// Cycles the log level on every SIGUSR1.
+#[cfg(unix)]
+pub async fn cycle_on_sigusr1() {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut signals = match signal(SignalKind::user_defined1()) {
+        Ok(signals) => signals,
+        Err(e) => {
+            log::error!("Failed to listen for SIGUSR1: {}", e);
+            return;
+        }
+    };
+    while signals.recv().await.is_some() {
+        set_level(next_level(log::max_level()));
+    }
+}
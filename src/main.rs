@@ -1,224 +1,3777 @@
-use cedar_policy::{Authorizer, Context, Entities, PolicySet, Request, Schema};
+use cedar_policy::{
+    AuthorizationError, Authorizer, Context, Effect, Entities, Entity, EntityId, EntityTypeName, EntityUid, Policy,
+    PolicyId, PolicySet, Request, Schema, ValidationMode, Validator,
+};
+use hyper::body::HttpBody;
+use hyper::server::conn::{AddrIncoming, AddrStream};
 use hyper::service::{make_service_fn, service_fn};
 use hyper::{Body, Method, Response, Server, StatusCode};
 use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::convert::Infallible;
-use std::fs;
-use std::net::SocketAddr;
-use std::sync::Arc;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex, RwLock, RwLockWriteGuard};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use log::{debug, error, info, warn};
 
-#[derive(Debug, Deserialize)]
+mod acceptors;
+mod access_log;
+#[cfg(feature = "acme")]
+mod acme;
+mod archive;
+mod audit;
+#[cfg(feature = "avp")]
+mod avp;
+mod baggage;
+mod bench;
+mod capabilities;
+mod catalog;
+mod chaos;
+mod clock;
+mod complexity;
+mod config;
+mod config_check;
+mod context_usage;
+mod deadline;
+mod debug_decisions;
+mod decision_index;
+mod decision_log;
+mod digest;
+mod drain;
+mod entities;
+mod explain;
+mod fetch;
+mod fetch_cache;
+mod fetch_graphql;
+mod fetch_rest;
+#[cfg(feature = "sql")]
+mod fetch_sql;
+mod fixtures;
+mod freshness;
+#[cfg(feature = "graphql")]
+mod graphql;
+mod grpc;
+mod grpc_health;
+mod grpc_reflection;
+mod ip_filter;
+#[cfg(feature = "kubernetes")]
+mod kubernetes;
+mod labels;
+#[cfg(feature = "ldap")]
+mod ldap;
+mod limiter;
+mod loadtest;
+mod logging;
+mod metrics;
+#[cfg(feature = "playground")]
+mod playground;
+mod policies;
+mod policy_lint;
+mod proposals;
+mod quota;
+mod rbac;
+mod reload;
+mod response_format;
+mod replay;
+mod replication;
+#[cfg(feature = "profiling")]
+mod profiling;
+#[cfg(feature = "spiffe")]
+mod svid;
+mod schedule;
+mod schema;
+mod schema_infer;
+mod schema_lint;
+mod scim;
+mod secret_file;
+mod signing;
+mod slow_log;
+mod staging;
+mod stats;
+mod store;
+mod suggest;
+mod tenant;
+mod tls;
+mod token;
+mod upgrade;
+mod validate;
+mod vault;
+#[cfg(any(windows, test))]
+mod winservice;
+
+use config::{Config, DefaultDecision};
+use entities::EntityStore;
+
+#[derive(Debug, Clone, Deserialize)]
 struct AuthzRequest {
+    /// May be left out on mTLS connections with a SPIFFE ID, which then becomes the principal.
+    #[serde(default)]
     principal: String,
     action: String,
     resource: String,
     entities: serde_json::Value,
+    #[serde(default)]
+    context: Option<serde_json::Value>,
+    /// Data version (from `X-Cedar-Data-Version`) the agent must have reached to evaluate this.
+    #[serde(default)]
+    min_version: Option<u64>,
+    /// Bearer token (JWT) standing for the principal, which is then left out.
+    #[serde(default)]
+    token: Option<String>,
+    /// When the request is evaluated, set by `stamp_time`; the clock's time when `None`.
+    #[serde(skip)]
+    evaluation_time: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+/// Body of `POST /authorize/batch`: one principal, or a token standing for it, and the
+/// action/resource pairs to authorize it for.
+#[derive(Debug, Deserialize)]
+struct BatchRequest {
+    #[serde(default)]
+    token: Option<String>,
+    #[serde(default)]
+    principal: String,
+    /// Shared by every item; an item's own context is merged over it.
+    #[serde(default)]
+    context: Option<serde_json::Value>,
+    #[serde(default = "no_entities")]
+    entities: serde_json::Value,
+    requests: Vec<BatchItem>,
+    #[serde(default)]
+    min_version: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+struct BatchItem {
+    action: String,
+    resource: String,
+    #[serde(default)]
+    context: Option<serde_json::Value>,
+}
+
+fn no_entities() -> serde_json::Value {
+    serde_json::Value::Array(Vec::new())
 }
 
 #[derive(Debug, Serialize)]
 struct AuthzResponse {
     decision: String,
+    /// Per-action decisions when the requested action was a group evaluated as its members.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    actions: Vec<ActionDecision>,
+    /// `@obligation` directives of the policies that determined the decision.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    obligations: Vec<policies::Directive>,
+    /// `@advice` directives of the policies that determined the decision.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    advice: Vec<policies::Directive>,
     diagnostics: Diagnostics,
 }
 
+#[derive(Debug, Serialize)]
+struct ActionDecision {
+    action: String,
+    decision: &'static str,
+    reason: Vec<String>,
+}
+
+impl AuthzResponse {
+    /// Replaces the decision with one forced by fault injection. The diagnostics still describe
+    /// the evaluation, with a warning saying the decision was forced.
+    fn force(&mut self, forced: chaos::Forced) {
+        self.decision = forced.as_str().to_string();
+        for action in &mut self.actions {
+            action.decision = forced.as_str();
+        }
+        if forced == chaos::Forced::Allow {
+            self.diagnostics.denied_by = None;
+        }
+        self.diagnostics
+            .warnings
+            .push(format!("Decision forced to {} by fault injection (CEDAR_CHAOS)", forced.as_str()));
+    }
+}
+
 #[derive(Debug, Serialize)]
 struct Diagnostics {
     reason: Vec<String>,
     errors: Vec<String>,
+    /// Permit policies whose conditions held, including ones a forbid overrode.
+    permits: Vec<String>,
+    /// Forbid policies whose conditions held.
+    forbids: Vec<String>,
+    /// Policies that failed to evaluate and were skipped.
+    errored: Vec<String>,
+    /// For a denial, `forbid` when a forbid policy held, else `default` (no permit held).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    denied_by: Option<&'static str>,
+    /// Set when no policy applied and the configured default decision was used.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    default_decision: Option<&'static str>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    warnings: Vec<String>,
+    /// Each of `errors` with the failing expression and where it is in its policy file.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    error_details: Vec<ErrorDetail>,
+    /// Source file of each policy in `reason`/`errors`, reported when overlays are configured.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    sources: BTreeMap<String, String>,
+}
+
+/// Body of `POST /v1/evaluate`: a request plus the policies (and optionally schema) to
+/// evaluate it against, independent of the agent's loaded state.
+#[derive(Debug, Deserialize)]
+struct EvaluateRequest {
+    policies: String,
+    #[serde(default)]
+    schema: Option<serde_json::Value>,
+    #[serde(flatten)]
+    request: AuthzRequest,
+}
+
+/// Why a request could not be evaluated. A request that is malformed or that the schema
+/// rejects is the client's problem and answers `400`, with a `code` naming what was wrong; only
+/// faults of the agent itself answer `500`. A request past its deadline answers `504`.
+#[derive(Debug)]
+enum RequestError {
+    Invalid { code: &'static str, message: String },
+    Internal(String),
+    DeadlineExceeded,
+}
+
+impl RequestError {
+    fn invalid(code: &'static str, message: impl Into<String>) -> Self {
+        RequestError::Invalid {
+            code,
+            message: message.into(),
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            RequestError::Invalid { code, .. } => code,
+            RequestError::Internal(_) => "internal",
+            RequestError::DeadlineExceeded => "deadline_exceeded",
+        }
+    }
+
+    fn status(&self) -> StatusCode {
+        match self {
+            RequestError::Invalid { .. } => StatusCode::BAD_REQUEST,
+            RequestError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            RequestError::DeadlineExceeded => StatusCode::GATEWAY_TIMEOUT,
+        }
+    }
+
+    /// Logs the error, at `error` level only for the agent's own faults.
+    fn log(&self, what: &str) {
+        match self {
+            RequestError::Invalid { .. } | RequestError::DeadlineExceeded => warn!("{} rejected: {}", what, self),
+            RequestError::Internal(_) => error!("{} failed: {}", what, self),
+        }
+    }
+
+    fn body(&self) -> serde_json::Value {
+        serde_json::json!({"error": self.to_string(), "code": self.code()})
+    }
+
+    /// Logs the error and renders it.
+    fn response(&self, what: &str) -> Response<Body> {
+        self.log(what);
+        json_response(self.status(), &self.body())
+    }
+}
+
+impl std::fmt::Display for RequestError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RequestError::Invalid { message, .. } | RequestError::Internal(message) => f.write_str(message),
+            RequestError::DeadlineExceeded => f.write_str("Deadline exceeded before the request was evaluated"),
+        }
+    }
+}
+
+impl std::error::Error for RequestError {}
+
+/// Body of `POST /v1/policies/lint`: Cedar policy text to lint instead of the active policies.
+#[derive(Debug, Deserialize)]
+struct PolicyLintRequest {
+    policies: String,
+}
+
+/// Body of `POST /v1/schema/context-usage`: sample requests, or none to read the most recent
+/// `limit` records of the decision log.
+#[derive(Debug, Deserialize)]
+struct ContextUsageRequest {
+    #[serde(default)]
+    requests: Option<Vec<context_usage::Sample>>,
+    #[serde(default = "default_context_usage_limit")]
+    limit: usize,
+}
+
+fn default_context_usage_limit() -> usize {
+    1000
+}
+
+#[derive(Debug, Serialize)]
+struct EvaluateResponse {
+    #[serde(flatten)]
+    response: AuthzResponse,
+    /// Strict-mode validation of the inline policies against the inline schema.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    validation_errors: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct ExplainResponse {
+    #[serde(flatten)]
+    response: AuthzResponse,
+    policies: Vec<explain::PolicyExplanation>,
+}
+
+/// Body of `POST /authorize/why-not` responses.
+#[derive(Debug, Serialize)]
+struct WhyNotResponse {
+    decision: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    denied_by: Option<&'static str>,
+    /// Forbid policies that held; the request stays denied while they do, whatever permits.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    forbidden_by: Vec<String>,
+    /// Permit policies that did not apply, those failing the fewest checks first.
+    near_misses: Vec<explain::NearMiss>,
+}
+
+/// Body of `POST /authorize/suggest` responses.
+#[derive(Debug, Serialize)]
+struct SuggestResponse {
+    decision: String,
+    /// A permit policy to start from, in Cedar's text format.
+    policy: String,
+    /// Set when a forbid policy denies the request, which no permit overrides.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    warning: Option<String>,
+}
+
+/// Near misses a why-not analysis reports at most.
+const MAX_NEAR_MISSES: usize = 10;
+
+/// Body of `GET`/`PUT /admin/loglevel`.
+#[derive(Debug, Serialize, Deserialize)]
+struct LogLevel {
+    level: String,
 }
 
 #[derive(Debug, Serialize)]
 struct HealthResponse {
+    /// `healthy`, `stale` while a source fails to refresh and its last good state is served, or
+    /// `draining` (with a 503) once `POST /admin/drain` took the agent out of rotation.
     status: String,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    sources: BTreeMap<&'static str, freshness::SourceStatus>,
 }
 
-struct CedarService {
+fn error_policy_id(err: &AuthorizationError) -> &PolicyId {
+    match err {
+        AuthorizationError::PolicyEvaluationError(e) => e.policy_id(),
+    }
+}
+
+/// Permit policies that held for a request a forbid denied. Cedar only reports the forbids
+/// then, so the request is evaluated again against `permits`, the policies without the forbids.
+fn overridden_permits(permits: &PolicySet, request: &Request, entities: &Entities) -> Vec<PolicyId> {
+    Authorizer::new()
+        .is_authorized(request, permits, entities)
+        .diagnostics()
+        .reason()
+        .cloned()
+        .collect()
+}
+
+/// A policy that failed to evaluate, with the expression that failed. Positions are 1-based
+/// and refer to the policy's file; they are left out for policies not parsed from text (such
+/// as those synced from Verified Permissions).
+#[derive(Debug, Serialize, PartialEq)]
+struct ErrorDetail {
+    policy: String,
+    message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    help: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    source: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    line: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    column: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    snippet: Option<String>,
+}
+
+fn error_detail(err: &AuthorizationError, sources: &HashMap<PolicyId, String>) -> ErrorDetail {
+    use miette::Diagnostic;
+    let AuthorizationError::PolicyEvaluationError(e) = err;
+    let error = e.inner();
+    // Cedar's errors carry the whole text the policy was parsed from, and the span in it
+    let span = error.labels().and_then(|mut labels| labels.next()).map(|label| *label.inner());
+    let location = span
+        .zip(error.source_code())
+        .and_then(|(span, code)| code.read_span(&span, 0, 0).ok())
+        .map(|contents| {
+            (contents.line() + 1, contents.column() + 1, String::from_utf8_lossy(contents.data()).into_owned())
+        });
+    let (line, column, snippet) = match location {
+        Some((line, column, snippet)) => (Some(line), Some(column), Some(snippet)),
+        None => (None, None, None),
+    };
+    ErrorDetail {
+        policy: e.policy_id().to_string(),
+        message: error.to_string(),
+        help: error.help().map(|help| help.to_string()),
+        source: sources.get(e.policy_id()).cloned(),
+        line,
+        column,
+        snippet,
+    }
+}
+
+/// Everything a decision is evaluated against. Replaced as a whole on updates so an in-flight
+/// request always sees a consistent policy set and schema.
+struct PolicyState {
     policy_set: PolicySet,
+    policy_sources: HashMap<PolicyId, String>,
+    layered: bool,
     schema: Option<Schema>,
+    /// The schema as it was loaded or uploaded, served back by `GET /v1/schema`.
+    schema_json: Option<serde_json::Value>,
+    /// Entities held by the agent, merged with the ones sent in each request.
+    entities: Arc<EntityStore>,
+    /// Policies taken out of evaluation with `POST /v1/policies/{id}/disable`. They stay disabled
+    /// across policy changes for as long as a policy with their ID exists.
+    disabled: BTreeSet<PolicyId>,
+    /// Which of the policies scheduled with `@effective_from` and `@expires_at` are in force.
+    schedule: schedule::Schedule,
 }
 
-impl CedarService {
-    fn new(policy_path: &str, schema_path: &str) -> Result<Self, Box<dyn std::error::Error>> {
-        println!("Loading policies from: {}", policy_path);
-        println!("Loading schema from: {}", schema_path);
-
-        let policy_src = fs::read_to_string(policy_path)
-            .map_err(|e| format!("Failed to read policy file: {}", e))?;
-        
-        let policy_set = policy_src.parse::<PolicySet>()
-            .map_err(|e| format!("Failed to parse policies: {}", e))?;
-
-        let schema = if let Ok(schema_src) = fs::read_to_string(schema_path) {
-            Some(Schema::from_json_str(&schema_src)
-                .map_err(|e| format!("Failed to parse schema: {}", e))?)
-        } else {
-            println!("Warning: Schema file not found, proceeding without schema validation");
-            None
+impl PolicyState {
+    /// A state of `policies`, `schema` (parsed and as it was given) and `entities`, with no
+    /// policy disabled.
+    fn new(
+        policies: policies::LoadedPolicies,
+        schema: Option<Schema>,
+        schema_json: Option<serde_json::Value>,
+        entities: Arc<EntityStore>,
+    ) -> PolicyState {
+        PolicyState {
+            policy_set: policies.policy_set,
+            policy_sources: policies.sources,
+            layered: policies.layered,
+            schema,
+            schema_json,
+            entities,
+            disabled: Default::default(),
+            schedule: Default::default(),
+        }
+    }
+
+    /// The policies with their sources, to build another state around.
+    fn policies(&self) -> policies::LoadedPolicies {
+        policies::LoadedPolicies {
+            policy_set: self.policy_set.clone(),
+            sources: self.policy_sources.clone(),
+            layered: self.layered,
+        }
+    }
+
+    /// Parses the request context. With a schema loaded, parsing is driven by the action's
+    /// declared context type, so `ipaddr`, `decimal`, `datetime` and `duration` attributes can be
+    /// sent as plain strings and are coerced into extension values (malformed strings are
+    /// rejected here rather than surfacing later as evaluation type errors).
+    fn parse_context(&self, ctx: serde_json::Value, action: &EntityUid) -> Result<Context, RequestError> {
+        let schema = self.schema.as_ref().map(|s| (s, action));
+        let context = Context::from_json_value(ctx, schema).map_err(|e| {
+            RequestError::invalid("invalid_context", format!("Failed to parse context for {}: {}", action, e))
+        })?;
+        Ok(context)
+    }
+
+    /// Parses entities sent with a request and adds the stored ones; an entity sent with the
+    /// request replaces the stored one.
+    fn parse_entities(&self, json: serde_json::Value) -> Result<Cow<'_, Entities>, RequestError> {
+        let request_entities = entities::parse_list(json, self.schema.as_ref())
+            .map_err(|e| RequestError::invalid("invalid_entities", format!("Failed to parse entities: {}", e)))?;
+        self.entities
+            .merged_with(request_entities, self.schema.as_ref())
+            .map_err(|e| RequestError::invalid("invalid_entities", e))
+    }
+
+    /// Parses a request into the entities and Cedar request(s) to evaluate: one per action, more
+    /// than one when an action group is expanded.
+    fn prepare(&self, mut req: AuthzRequest) -> Result<PreparedRequest<'_>, RequestError> {
+        let entities = self.parse_entities(std::mem::take(&mut req.entities))?;
+        self.prepare_with(req, entities)
+    }
+
+    /// `prepare` with the request's entities already parsed, ignoring the ones it carries.
+    fn prepare_with<'a>(&'a self, req: AuthzRequest, entities: Cow<'a, Entities>) -> Result<PreparedRequest<'a>, RequestError> {
+        // Parse principal, action, and resource
+        let principal: EntityUid = req.principal.parse()
+            .map_err(|e| RequestError::invalid("invalid_principal", format!("Failed to parse principal: {}", e)))?;
+        let action: EntityUid = req.action.parse()
+            .map_err(|e| RequestError::invalid("invalid_action", format!("Failed to parse action: {}", e)))?;
+        let resource: EntityUid = req.resource.parse()
+            .map_err(|e| RequestError::invalid("invalid_resource", format!("Failed to parse resource: {}", e)))?;
+
+        // An action group the schema does not apply directly is evaluated as its member actions
+        let actions = match self.schema {
+            Some(ref schema) => schema::expand_action(schema, &principal, &action, &resource).map_err(|e| match e {
+                schema::ExpandError::NotApplicable(e) => RequestError::invalid("schema_violation", e),
+                schema::ExpandError::Schema(e) => RequestError::Internal(e),
+            })?,
+            None => vec![action.clone()],
         };
+        let expanded = actions.len() != 1 || actions[0] != action;
+
+        let mut requests = Vec::with_capacity(actions.len());
+        for action in actions {
+            // Parse context; with a schema, extension-typed attributes are coerced from strings
+            let context = match req.context {
+                Some(ref ctx) => self.parse_context(ctx.clone(), &action)?,
+                None => Context::empty(),
+            };
+
+            // Build Cedar request
+            let cedar_request = Request::new(principal.clone(), action.clone(), resource.clone(), context, self.schema.as_ref())
+                .map_err(|e| RequestError::invalid("schema_violation", format!("Failed to create request: {}", e)))?;
+            requests.push((action, cedar_request));
+        }
+
+        Ok(PreparedRequest {
+            entities,
+            requests,
+            expanded,
+        })
+    }
+
+    /// The same policies and schema with another set of stored entities.
+    fn with_entities(&self, entities: EntityStore) -> PolicyState {
+        PolicyState {
+            disabled: self.disabled.clone(),
+            ..PolicyState::new(self.policies(), self.schema.clone(), self.schema_json.clone(), Arc::new(entities))
+        }
+    }
+
+    /// The same policies, schema and entities with another set of disabled policies.
+    fn with_disabled(&self, disabled: BTreeSet<PolicyId>) -> PolicyState {
+        PolicyState {
+            disabled,
+            ..PolicyState::new(self.policies(), self.schema.clone(), self.schema_json.clone(), Arc::clone(&self.entities))
+        }
+    }
+
+    /// The disabled policies that are still in `policy_set`, to carry over to it.
+    fn disabled_in(&self, policy_set: &PolicySet) -> BTreeSet<PolicyId> {
+        self.disabled.iter().filter(|id| policy_set.policy(id).is_some()).cloned().collect()
+    }
+
+    /// The policies that take part in decisions at `now`, when some are disabled or outside
+    /// their scheduled window; `None` when all of them do.
+    fn in_force(&self, now: chrono::DateTime<chrono::Utc>) -> Option<Arc<PolicySet>> {
+        self.schedule.effective(&self.policy_set, &self.disabled, now)
+    }
+
+    fn summary(&self, policy: &Policy) -> policies::PolicySummary {
+        policies::PolicySummary {
+            disabled: self.disabled.contains(policy.id()),
+            ..policies::PolicySummary::new(policy, &self.policy_sources)
+        }
+    }
 
-        println!("Cedar service initialized successfully");
-        println!("Loaded {} policies", policy_set.policies().count());
+    /// What `samples` show about the context clients send, against the schema and the
+    /// attributes the loaded policies read.
+    fn context_usage(&self, samples: Vec<context_usage::Sample>) -> context_usage::ContextUsage {
+        let declared: Option<HashMap<String, Vec<schema::AttributeInfo>>> = match (&self.schema, &self.schema_json) {
+            (Some(schema), Some(schema_json)) => Some(
+                schema::introspect(schema, schema_json)
+                    .actions
+                    .into_iter()
+                    .map(|action| (action.uid, action.context))
+                    .collect(),
+            ),
+            _ => None,
+        };
+        let references: HashMap<String, BTreeSet<String>> = self
+            .policy_set
+            .policies()
+            .map(|p| (p.id().to_string(), context_usage::references(p)))
+            .filter(|(_, paths)| !paths.is_empty())
+            .collect();
+        context_usage::analyze(samples, declared.as_ref(), |action| {
+            let query = policies::ScopeQuery {
+                principal: None,
+                action: Some(action.clone()),
+                resource: None,
+                label: None,
+            };
+            let mut readers: BTreeMap<String, Vec<String>> = BTreeMap::new();
+            for policy in self.matching_policies(&query) {
+                for path in references.get(&policy.id).into_iter().flatten() {
+                    readers.entry(path.clone()).or_default().push(policy.id.clone());
+                }
+            }
+            readers
+        })
+    }
 
-        Ok(Self { policy_set, schema })
+    /// Loaded policies whose scope could match `query`, sorted by ID.
+    fn matching_policies(&self, query: &policies::ScopeQuery) -> Vec<policies::PolicySummary> {
+        let actions = self
+            .schema
+            .as_ref()
+            .and_then(|schema| schema.action_entities().ok())
+            .unwrap_or_else(|| self.entities.entities().clone());
+        let mut matching: Vec<policies::PolicySummary> = self
+            .policy_set
+            .policies()
+            .filter(|p| query.label.as_ref().is_none_or(|label| label.matches(p)))
+            .filter(|p| policies::scope_matches(p, query, self.entities.entities(), &actions))
+            .map(|p| self.summary(p))
+            .collect();
+        matching.sort_by(|a, b| a.id.cmp(&b.id));
+        matching
     }
+}
+
+/// A parsed request, ready for evaluation.
+struct PreparedRequest<'a> {
+    /// Borrows the stored entities when the request brings none of its own.
+    entities: Cow<'a, Entities>,
+    requests: Vec<(EntityUid, Request)>,
+    /// Whether the requested action was a group evaluated as its member actions.
+    expanded: bool,
+}
+
+struct CedarService {
+    state: RwLock<Arc<PolicyState>>,
+    default_decision: DefaultDecision,
+    response_format: config::ResponseFormat,
+    log_decisions: bool,
+    metrics: metrics::Metrics,
+    access_log: Option<access_log::AccessLog>,
+    decision_log: Option<decision_log::DecisionLog>,
+    /// Recent decisions for `GET /v1/decisions`.
+    decision_index: Option<decision_index::DecisionIndex>,
+    /// Recent decisions in full for `/debug/decisions`, with the debug endpoints on.
+    debug_decisions: Option<debug_decisions::Ring>,
+    audit_log: Option<audit::AuditLog>,
+    ip_filter: ip_filter::IpFilter,
+    hmac: Option<signing::HmacVerifier>,
+    api_keys: Option<quota::ApiKeys>,
+    /// Administrators and their roles on the admin API.
+    rbac: Option<rbac::Rbac>,
+    limiter: Option<limiter::Limiter>,
+    entity_memory_limit: Option<usize>,
+    max_request_bytes: Option<usize>,
+    /// Entity type of principals taken from client SPIFFE IDs.
+    spiffe_principal_type: EntityTypeName,
+    /// Context attribute set to the evaluation time, and where that time comes from.
+    time_attribute: Option<String>,
+    clock: clock::Clock,
+    test_mode: bool,
+    /// Whether policy changes must go through an approved proposal (`CEDAR_REQUIRE_APPROVAL`).
+    require_approval: bool,
+    /// Faults injected into evaluations, with `CEDAR_CHAOS`.
+    chaos: Option<chaos::Chaos>,
+    /// Copies configured W3C baggage entries into request contexts.
+    baggage: Option<baggage::Baggage>,
+    /// Identifies the tenant of requests, whose metrics are then labelled with it.
+    tenants: Option<tenant::Tenants>,
+    scim: Option<scim::Scim>,
+    /// Set on a leader, which streams its state to replicas.
+    replication: Option<replication::Leader>,
+    /// The leader this agent replicates from; a replica refuses writes.
+    replica_of: Option<String>,
+    /// Files `POST /admin/reload` reads; `None` when policies come from Verified Permissions.
+    reload_sources: Option<reload::Sources>,
+    /// The last dry-run reload, until it is committed or replaced by another.
+    staged_reload: Mutex<Option<reload::Candidate>>,
+    /// Policy changes waiting for a second administrator's approval.
+    proposals: proposals::Proposals,
+    /// Policies loaded next to the active ones, to be tested and then promoted.
+    staging: staging::Staging,
+    freshness: freshness::Freshness,
+    /// What `grpc.health.v1.Health` reports; a replica is not serving until its first snapshot.
+    serving: tokio::sync::watch::Sender<grpc_health::ServingStatus>,
+    /// The `grpc.health.v1.Health` service, started on the first call.
+    grpc_health: tokio::sync::OnceCell<grpc::Handler>,
+    /// gRPC server reflection, with `CEDAR_GRPC_REFLECTION`.
+    grpc_reflection: Option<grpc::Handler>,
+    /// Where the schema and stored entities are persisted, with `CEDAR_DATA_DIR`.
+    store: Option<store::Store>,
+    /// Advanced on every write to the policies, schema or entities.
+    data_version: tokio::sync::watch::Sender<u64>,
+    /// When each policy was introduced and last determined a decision.
+    catalog: catalog::Catalog,
+    /// Limits on the complexity of the policies activated.
+    complexity_limits: complexity::Limits,
+    /// Severities of the rules `/v1/policies/lint` applies.
+    policy_lint: policy_lint::Rules,
+    /// Upstream services principals and resources are fetched from when a request lacks them.
+    fetchers: Option<fetch::Fetchers>,
+    /// Verifies the bearer tokens requests may send in place of a principal.
+    token_verifier: Option<token::Verifier>,
+    batch_max_items: usize,
+    /// Longest a request with `min_version` waits for the agent to catch up.
+    min_version_wait: Duration,
+    /// Least time a request's deadline must leave for the request to be served.
+    deadline_min_budget: Duration,
+    /// Evaluations taking at least this long are logged with where their time went.
+    slow_decision: Option<Duration>,
+    /// Connection, request and runtime counters for `GET /debug/stats`.
+    stats: stats::Stats,
+    /// Whether the agent accepts connections; it stops on upgrade, on `POST /admin/drain` and
+    /// when the service is stopped.
+    accepting: tokio::sync::watch::Sender<drain::Accepting>,
+    /// Set by `POST /admin/drain`, which reports the agent not ready from then on.
+    draining: std::sync::atomic::AtomicBool,
+    /// How long `POST /admin/drain` keeps accepting connections by default.
+    drain_grace: Duration,
+    /// How long open connections are waited for once the agent stops accepting.
+    drain_timeout: Duration,
+    #[cfg(feature = "profiling")]
+    debug_endpoints: bool,
+}
 
-    fn authorize(&self, req: AuthzRequest) -> Result<AuthzResponse, Box<dyn std::error::Error>> {
-        println!("Authorization request - Principal: {}, Action: {}, Resource: {}", 
-            req.principal, req.action, req.resource);
+impl CedarService {
+    fn new(config: &Config) -> Result<Self, Box<dyn std::error::Error>> {
+        let policy_path = &config.policy_path;
+        let schema_path = &config.schema_path;
+        info!("Loading policies from: {}", policy_path);
+        info!("Loading schema from: {}", schema_path);
+        for fragment in &config.schema_fragments {
+            info!("Loading schema fragments from: {}", fragment);
+        }
 
-        // Parse entities
-        let entities = if let Some(ref schema) = self.schema {
-            Entities::from_json_value(req.entities, Some(schema))
-                .map_err(|e| format!("Failed to parse entities: {}", e))?
+        // Policies synced from Verified Permissions or replicated from a leader replace the
+        // files, which need not exist
+        let replica = config.replica_of.is_some();
+        let loaded = if config.avp_policy_store_id.is_some() || replica {
+            policies::LoadedPolicies::default()
         } else {
-            Entities::from_json_value(req.entities, None)
-                .map_err(|e| format!("Failed to parse entities: {}", e))?
+            policies::load(policy_path, &config.policy_overlays)?
         };
+        let policy_set = loaded.policy_set;
 
-        // Parse principal, action, and resource
-        let principal = req.principal.parse()
-            .map_err(|e| format!("Failed to parse principal: {}", e))?;
-        let action = req.action.parse()
-            .map_err(|e| format!("Failed to parse action: {}", e))?;
-        let resource = req.resource.parse()
-            .map_err(|e| format!("Failed to parse resource: {}", e))?;
-
-        // Create context (empty for now)
-        let context = Context::empty();
-
-        // Build Cedar request
-        let cedar_request = if let Some(ref schema) = self.schema {
-            Request::new(principal, action, resource, context, Some(schema))
-                .map_err(|e| format!("Failed to create request: {}", e))?
+        // What was persisted before a restart takes the place of the schema and entity files
+        let (store, recovered) = match config.data_dir {
+            Some(ref dir) => {
+                let (store, recovered) = store::Store::open(dir, config.data_compact_after)?;
+                (Some(store), recovered)
+            }
+            None => (None, store::Record::default()),
+        };
+
+        let (schema, schema_json) = if replica {
+            (None, None)
+        } else if let Some(schema_json) = recovered.schema {
+            // A persisted `null` is a schema that was removed
+            match schema_json {
+                serde_json::Value::Null => (None, None),
+                schema_json => {
+                    let schema = Schema::from_json_value(schema_json.clone())
+                        .map_err(|e| format!("Failed to parse persisted schema: {}", e))?;
+                    (Some(schema), Some(schema_json))
+                }
+            }
         } else {
-            Request::new(principal, action, resource, context, None)
-                .map_err(|e| format!("Failed to create request: {}", e))?
+            match schema::load(schema_path, &config.schema_fragments)? {
+                Some((schema, schema_json)) => (Some(schema), Some(schema_json)),
+                None => {
+                    warn!("Schema file not found, proceeding without schema validation");
+                    (None, None)
+                }
+            }
         };
 
-        // Evaluate authorization
-        let authorizer = Authorizer::new();
-        let response = authorizer.is_authorized(&cedar_request, &self.policy_set, &entities);
+        let ingest = entities::Ingest::from_config(config);
+        let entities = match recovered.entities {
+            Some(entities) => EntityStore::from_json(serde_json::Value::Array(entities), schema.as_ref(), ingest)
+                .map_err(|e| format!("Failed to load persisted entities: {}", e))?,
+            None => EntityStore::load(config.entities_path.as_deref().filter(|_| !replica), schema.as_ref(), ingest)?,
+        };
+        entities
+            .check_budget(config.entity_memory_limit)
+            .map_err(|e| format!("Failed to load entities: {}", e))?;
+
+        if config.debug_endpoints && !cfg!(feature = "profiling") {
+            warn!("CEDAR_DEBUG_ENDPOINTS is set but this build has no profiling support; only /debug/decisions is served");
+        }
+
+        if config.test_mode {
+            warn!("CEDAR_TEST_MODE is set: requests may choose their own evaluation time");
+        }
+        if config.require_approval && config.admin_keys.is_empty() && config.admin_users.is_empty() {
+            return Err("CEDAR_REQUIRE_APPROVAL needs administrators (CEDAR_ADMIN_KEYS or CEDAR_ADMIN_USERS) to propose and approve changes".into());
+        }
+        if config.chaos.is_some() {
+            warn!("CEDAR_CHAOS is set: faults may be injected into evaluations");
+        }
+        if !config.admin_users.is_empty() && config.tls_cert_path.is_none() && config.acme_domains.is_empty() {
+            warn!("CEDAR_ADMIN_USERS is set without TLS: Basic sign-ins are refused until the agent serves TLS");
+        }
+
+        info!("Cedar service initialized successfully");
+        info!("Loaded {} policies", policy_set.policies().count());
+
+        let policies = policies::LoadedPolicies { policy_set, ..loaded };
+        let state = PolicyState::new(policies, schema, schema_json, Arc::new(entities));
+
+        let fetchers = config
+            .entity_fetchers
+            .as_deref()
+            .map(|path| fetch::Fetchers::load(path, config))
+            .transpose()?;
+        if let Some(ref fetchers) = fetchers {
+            let types: Vec<String> = fetchers.types().map(|t| t.to_string()).collect();
+            info!("Fetching {} entities on demand", types.join(", "));
+        }
 
-        // Build response
-        let decision = match response.decision() {
-            cedar_policy::Decision::Allow => "Allow",
-            cedar_policy::Decision::Deny => "Deny",
+        let service = Self {
+            state: RwLock::new(Arc::new(state)),
+            default_decision: config.default_decision,
+            response_format: config.response_format,
+            log_decisions: true,
+            metrics: metrics::Metrics::new(config)?,
+            access_log: config
+                .access_log
+                .map(|format| access_log::AccessLog::open(format, &config.access_log_path))
+                .transpose()?,
+            decision_log: config
+                .decision_log_path
+                .as_deref()
+                .map(|path| {
+                    decision_log::DecisionLog::open(
+                        path,
+                        config.decision_log_signing_key.as_deref(),
+                        config.decision_log_checkpoint_interval,
+                    )
+                })
+                .transpose()?,
+            decision_index: (config.decision_index_size > 0)
+                .then(|| decision_index::DecisionIndex::new(config.decision_index_size)),
+            debug_decisions: config
+                .debug_endpoints
+                .then(|| debug_decisions::Ring::new(config.debug_decisions)),
+            audit_log: config.audit_log_path.as_deref().map(audit::AuditLog::open).transpose()?,
+            ip_filter: config.ip_filter.clone(),
+            hmac: (!config.hmac_secrets.is_empty())
+                .then(|| signing::HmacVerifier::new(&config.hmac_secrets, config.hmac_max_skew)),
+            api_keys: (!config.api_keys.is_empty())
+                .then(|| {
+                    let default = quota::Quota {
+                        hourly: config.api_key_hourly_quota,
+                        daily: config.api_key_daily_quota,
+                    };
+                    quota::ApiKeys::new(&config.api_keys, &config.api_key_quotas, default)
+                })
+                .transpose()?,
+            rbac: (!config.admin_keys.is_empty() || !config.admin_users.is_empty())
+                .then(|| rbac::Rbac::new(&config.admin_keys, &config.admin_users, &config.admin_roles))
+                .transpose()?,
+            limiter: config
+                .max_concurrent_evaluations
+                .map(|max| limiter::Limiter::new(max, config.max_queued_evaluations, config.queue_timeout)),
+            entity_memory_limit: config.entity_memory_limit,
+            max_request_bytes: config.max_request_bytes,
+            spiffe_principal_type: config
+                .spiffe_principal_type
+                .parse()
+                .map_err(|e| format!("Invalid CEDAR_SPIFFE_PRINCIPAL_TYPE: {}", e))?,
+            time_attribute: config.context_time_attribute.clone(),
+            clock: config.fixed_time.map_or(clock::Clock::System, clock::Clock::Fixed),
+            test_mode: config.test_mode,
+            require_approval: config.require_approval,
+            chaos: config.chaos.clone().map(chaos::Chaos::new),
+            baggage: (!config.baggage_keys.is_empty())
+                .then(|| baggage::Baggage::new(&config.baggage_keys, &config.baggage_context_attribute)),
+            tenants: match config.tenant_from {
+                Some(ref from) if from.trim() == "api-key" && config.api_keys.is_empty() => {
+                    return Err("CEDAR_TENANT_FROM=api-key requires CEDAR_API_KEYS".into());
+                }
+                Some(ref from) => {
+                    let default = tenant::Limits {
+                        rate: config.tenant_rate_limit,
+                        max_request_bytes: config.max_request_bytes,
+                        max_entities: config.tenant_max_entities,
+                    };
+                    Some(tenant::Tenants::new(from, config.tenant_max_labels, default, &config.tenant_limits)?)
+                }
+                None => None,
+            },
+            scim: config
+                .scim_token
+                .as_deref()
+                .map(|token| scim::Scim::new(token, &config.scim_user_type, &config.scim_group_type))
+                .transpose()?,
+            replication: config.replication_token.as_deref().map(replication::Leader::new),
+            replica_of: config.replica_of.clone(),
+            reload_sources: (config.avp_policy_store_id.is_none() && !replica).then(|| reload::Sources::from_config(config)),
+            staged_reload: Mutex::new(None),
+            proposals: proposals::Proposals::default(),
+            staging: staging::Staging::default(),
+            freshness: freshness::Freshness::default(),
+            serving: tokio::sync::watch::Sender::new(if replica {
+                grpc_health::ServingStatus::NotServing
+            } else {
+                grpc_health::ServingStatus::Serving
+            }),
+            grpc_health: tokio::sync::OnceCell::new(),
+            grpc_reflection: config.grpc_reflection.then(grpc_reflection::handler).transpose()?,
+            store,
+            data_version: tokio::sync::watch::Sender::new(0),
+            catalog: catalog::Catalog::default(),
+            complexity_limits: config.complexity_limits,
+            policy_lint: config.policy_lint.clone(),
+            fetchers,
+            token_verifier: config
+                .token_jwks
+                .as_deref()
+                .map(|jwks| token::Verifier::new(config, jwks))
+                .transpose()?,
+            batch_max_items: config.batch_max_items,
+            min_version_wait: config.min_version_wait,
+            deadline_min_budget: config.deadline_min_budget,
+            slow_decision: config.slow_decision,
+            stats: stats::Stats::new(),
+            accepting: tokio::sync::watch::Sender::new(drain::Accepting::Yes),
+            draining: std::sync::atomic::AtomicBool::new(false),
+            drain_grace: config.drain_grace,
+            drain_timeout: config.drain_timeout,
+            #[cfg(feature = "profiling")]
+            debug_endpoints: config.debug_endpoints,
         };
+        service.record_entity_usage(&service.state().entities);
+        service.check_complexity(&service.state().policy_set)?;
+        service.catalog.update(&service.state().policy_set, 0, unix_now());
+        if service.reload_sources.is_some() {
+            service.source_refreshed("files");
+        }
+        Ok(service)
+    }
 
-        // Get policy IDs that determined the decision
-        let reason: Vec<String> = response
-            .diagnostics()
-            .reason()
-            .map(|policy_id| policy_id.to_string())
-            .collect();
+    fn record_entity_usage(&self, entities: &EntityStore) {
+        self.metrics.gauge("entity_store_bytes", &[], entities.size() as f64);
+        self.metrics.gauge("entity_store_entities", &[], entities.len() as f64);
+    }
 
-        // Get any errors that occurred during evaluation
-        let errors: Vec<String> = response
-            .diagnostics()
-            .errors()
-            .map(|e| e.to_string())
-            .collect();
+    /// Records a successful refresh from `source`.
+    fn source_refreshed(&self, source: &'static str) {
+        self.freshness.succeeded(source, unix_now());
+        self.metrics.gauge("source_stale", &[("source", source)], 0.0);
+    }
 
-        println!("Authorization decision: {} (reasons: {:?}, errors: {:?})", 
-            decision, reason, errors);
+    /// Records a failed refresh from `source`, whose last good state stays active.
+    fn source_failed(&self, source: &'static str, error: &str) {
+        self.freshness.failed(source, error);
+        self.metrics.incr("source_refresh_errors", &[("source", source)]);
+        self.metrics.gauge("source_stale", &[("source", source)], 1.0);
+    }
 
-        Ok(AuthzResponse {
-            decision: decision.to_string(),
-            diagnostics: Diagnostics { reason, errors },
-        })
+    /// Uses the client's SPIFFE ID, if it has one, as the principal of a request that leaves it
+    /// out, e.g. `Workload::"spiffe://example.org/payments"`.
+    fn peer_principal(&self, mut req: AuthzRequest, peer: Option<&tls::PeerIdentity>) -> AuthzRequest {
+        if let (true, Some(peer)) = (req.principal.is_empty(), peer) {
+            let uid = EntityUid::from_type_name_and_id(self.spiffe_principal_type.clone(), EntityId::new(&peer.0));
+            req.principal = uid.to_string();
+        }
+        req
     }
-}
 
-async fn handle_request(
-    req: hyper::Request<Body>,
-    service: Arc<CedarService>,
-) -> Result<Response<Body>, Infallible> {
-    match (req.method(), req.uri().path()) {
-        (&Method::GET, "/health") => {
-            let health = HealthResponse {
-                status: "healthy".to_string(),
-            };
-            let json = serde_json::to_string(&health).unwrap();
-            Ok(Response::builder()
-                .header("content-type", "application/json")
-                .body(Body::from(json))
-                .unwrap())
+    /// Verifies a bearer token, answering `401` for one that fails.
+    async fn verify_token(&self, token: &str) -> Result<token::Identity, Response<Body>> {
+        let Some(ref verifier) = self.token_verifier else {
+            return Err(RequestError::invalid("unsupported", "Tokens require CEDAR_TOKEN_JWKS").response("Authorization"));
+        };
+        match verifier.verify(token).await {
+            Ok(identity) => {
+                self.metrics.incr("token_verifications", &[("result", "valid")]);
+                Ok(identity)
+            }
+            Err(e) => {
+                warn!("Token rejected: {}", e);
+                self.metrics.incr("token_verifications", &[("result", "invalid")]);
+                Err(json_response(StatusCode::UNAUTHORIZED, &serde_json::json!({"error": e, "code": "invalid_token"})))
+            }
+        }
+    }
+
+    /// Makes the principal of a request that sends a token the one the token stands for.
+    async fn token_principal(&self, mut req: AuthzRequest) -> Result<AuthzRequest, Response<Body>> {
+        let Some(token) = req.token.take() else {
+            return Ok(req);
+        };
+        if !req.principal.is_empty() {
+            let error = RequestError::invalid("invalid_principal", "Send a token or a principal, not both");
+            return Err(error.response("Authorization"));
         }
+        let identity = self.verify_token(&token).await?;
+        Ok(with_identity(req, &identity))
+    }
 
-        (&Method::POST, "/authorize") => {
-            let body_bytes = match hyper::body::to_bytes(req.into_body()).await {
-                Ok(bytes) => bytes,
-                Err(e) => {
-                    eprintln!("Failed to read request body: {}", e);
-                    return Ok(Response::builder()
-                        .status(StatusCode::BAD_REQUEST)
-                        .body(Body::from(format!(r#"{{"error":"Failed to read body: {}"}}"#, e)))
-                        .unwrap());
-                }
-            };
+    /// Authorizes each item of a batch for its one principal. The token is verified, missing
+    /// entities fetched and the entities parsed once for every item; items that cannot be
+    /// evaluated get an error in their place.
+    async fn authorize_batch(
+        &self,
+        batch: BatchRequest,
+        requested_time: Option<&str>,
+        baggage: Option<&str>,
+        format: config::ResponseFormat,
+        peer: Option<&tls::PeerIdentity>,
+    ) -> Result<serde_json::Value, Response<Body>> {
+        if batch.requests.len() > self.batch_max_items {
+            let error = RequestError::invalid(
+                "batch_too_large",
+                format!("A batch holds at most {} requests", self.batch_max_items),
+            );
+            return Err(error.response("Batch authorization"));
+        }
+        self.catch_up(batch.min_version).await?;
+        let identity = match batch.token {
+            Some(_) if !batch.principal.is_empty() => {
+                let error = RequestError::invalid("invalid_principal", "Send a token or a principal, not both");
+                return Err(error.response("Batch authorization"));
+            }
+            Some(ref token) => Some(self.verify_token(token).await?),
+            None => None,
+        };
 
-            match serde_json::from_slice::<AuthzRequest>(&body_bytes) {
-                Ok(authz_req) => match service.authorize(authz_req) {
-                    Ok(authz_response) => {
-                        let json = serde_json::to_string(&authz_response).unwrap();
-                        Ok(Response::builder()
-                            .header("content-type", "application/json")
-                            .body(Body::from(json))
-                            .unwrap())
-                    }
-                    Err(e) => {
-                        eprintln!("Authorization error: {}", e);
-                        Ok(Response::builder()
-                            .status(StatusCode::INTERNAL_SERVER_ERROR)
-                            .header("content-type", "application/json")
-                            .body(Body::from(format!(r#"{{"error":"{}"}}"#, e)))
-                            .unwrap())
-                    }
-                },
+        // What every item shares: the principal and the entities
+        let shared = AuthzRequest {
+            principal: batch.principal,
+            action: String::new(),
+            resource: String::new(),
+            entities: batch.entities,
+            context: None,
+            min_version: None,
+            token: None,
+            evaluation_time: None,
+        };
+        let mut shared = match identity {
+            Some(ref identity) => with_identity(shared, identity),
+            None => self.peer_principal(shared, peer),
+        };
+        let uids = std::iter::once(&shared.principal).chain(batch.requests.iter().map(|item| &item.resource));
+        self.fetch_missing(uids, &mut shared.entities)
+            .await
+            .map_err(|e| self.fetch_failed(e))?;
+
+        // Items carry the entities only for the decision log
+        let state = self.state();
+        let logged = self.decision_log.is_some() && self.log_decisions;
+        let entities = if logged { shared.entities.clone() } else { std::mem::take(&mut shared.entities) };
+        let started = Instant::now();
+        self.check_entity_quota(&entities).map_err(|e| e.response("Batch authorization"))?;
+        let parsed = state.parse_entities(entities).map_err(|e| e.response("Batch authorization"))?;
+        self.metrics.observe("entity_parse_duration", &[], started.elapsed());
+        self.metrics.observe("parse_duration", &[], started.elapsed());
+
+        let time = self.evaluation_time(requested_time).map_err(|e| error_response(StatusCode::BAD_REQUEST, e))?;
+        let mut results = Vec::with_capacity(batch.requests.len());
+        for item in batch.requests {
+            let mut context = merge_context(batch.context.as_ref(), item.context);
+            if let Some(ref identity) = identity {
+                add_claims(&mut context, identity);
+            }
+            let req = AuthzRequest {
+                principal: shared.principal.clone(),
+                action: item.action,
+                resource: item.resource,
+                entities: shared.entities.clone(),
+                context,
+                min_version: None,
+                token: None,
+                evaluation_time: None,
+            };
+            let req = self
+                .stamp_time(req, time)
+                .and_then(|req| self.stamp_baggage(req, baggage))
+                .map_err(|e| error_response(StatusCode::BAD_REQUEST, e))?;
+            results.push(match self.authorize_with(&state, req, Some(&parsed)) {
+                Ok(response) => response_format::render(format, serde_json::to_value(&response).unwrap()),
                 Err(e) => {
-                    eprintln!("Parse error: {}", e);
-                    Ok(Response::builder()
-                        .status(StatusCode::BAD_REQUEST)
-                        .header("content-type", "application/json")
-                        .body(Body::from(format!(r#"{{"error":"Invalid request: {}"}}"#, e)))
-                        .unwrap())
+                    e.log("Batch item");
+                    e.body()
                 }
-            }
+            });
         }
+        Ok(serde_json::json!({ "results": results }))
+    }
 
-        _ => Ok(Response::builder()
-            .status(StatusCode::NOT_FOUND)
-            .body(Body::from("Not found"))
-            .unwrap()),
+    /// A request's evaluation time: the clock's, or in test mode the one the request asks for
+    /// with `X-Cedar-Evaluation-Time`.
+    fn evaluation_time(&self, requested: Option<&str>) -> Result<chrono::DateTime<chrono::Utc>, String> {
+        match requested {
+            Some(_) if !self.test_mode => Err("X-Cedar-Evaluation-Time requires CEDAR_TEST_MODE=true".to_string()),
+            Some(time) => clock::parse_time(time).map_err(|e| format!("Invalid X-Cedar-Evaluation-Time: {}", e)),
+            None => Ok(self.clock.now()),
+        }
     }
-}
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let policy_path = std::env::var("CEDAR_POLICY_PATH")
-        .unwrap_or_else(|_| "/app/policies/policy.cedar".to_string());
-    let schema_path = std::env::var("CEDAR_SCHEMA_PATH")
-        .unwrap_or_else(|_| "/app/policies/schema.cedarschema.json".to_string());
-    let bind_addr = std::env::var("BIND_ADDR")
-        .unwrap_or_else(|_| "0.0.0.0:8181".to_string());
+    /// Evaluates a request at `time`: scheduled policies are in force as at `time`, and the
+    /// configured time attribute of its context is set to it.
+    fn stamp_time(&self, mut req: AuthzRequest, time: chrono::DateTime<chrono::Utc>) -> Result<AuthzRequest, String> {
+        if let Some(ref attribute) = self.time_attribute {
+            req.context = Some(clock::stamp(req.context.take(), attribute, time)?);
+        }
+        req.evaluation_time = Some(time);
+        Ok(req)
+    }
 
-    let service = Arc::new(CedarService::new(&policy_path, &schema_path)?);
+    /// Sets the reserved baggage attribute of a request's context to the configured entries of
+    /// the `baggage` header it came with.
+    fn stamp_baggage(&self, mut req: AuthzRequest, header: Option<&str>) -> Result<AuthzRequest, String> {
+        if let Some(ref baggage) = self.baggage {
+            req.context = Some(baggage.stamp(req.context.take(), header)?);
+        }
+        Ok(req)
+    }
 
-    let addr: SocketAddr = bind_addr
-        .parse()
-        .map_err(|e| format!("Invalid bind address: {}", e))?;
+    /// Adds the principal and resource to a request that neither sends them nor finds them
+    /// stored, when their entity type has a fetcher. UIDs that do not parse are left for
+    /// evaluation to report.
+    async fn fetch_entities(&self, mut req: AuthzRequest) -> Result<AuthzRequest, String> {
+        self.fetch_missing([&req.principal, &req.resource], &mut req.entities).await?;
+        Ok(req)
+    }
 
-    let make_svc = make_service_fn(move |_| {
-        let service = Arc::clone(&service);
-        async move {
-            Ok::<_, Infallible>(service_fn(move |req| {
-                handle_request(req, Arc::clone(&service))
-            }))
+    /// Adds the entities `uids` name to `entities` when they are neither in it nor stored and
+    /// their type has a fetcher, fetching them concurrently.
+    async fn fetch_missing<'a>(
+        &self,
+        uids: impl IntoIterator<Item = &'a String>,
+        entities: &mut serde_json::Value,
+    ) -> Result<(), String> {
+        let Some(ref fetchers) = self.fetchers else {
+            return Ok(());
+        };
+        let state = self.state();
+        let sent: HashSet<EntityUid> = match entities {
+            serde_json::Value::Array(ref items) => items
+                .iter()
+                .filter_map(|item| item.get("uid").cloned())
+                .filter_map(|uid| EntityUid::from_json(uid).ok())
+                .collect(),
+            _ => HashSet::new(),
+        };
+        let missing: BTreeSet<EntityUid> = uids
+            .into_iter()
+            .filter_map(|uid| uid.parse::<EntityUid>().ok())
+            .filter(|uid| fetchers.handles(uid) && !sent.contains(uid) && !state.entities.contains(uid))
+            .collect();
+        if missing.is_empty() {
+            return Ok(());
         }
-    });
+        let fetched = futures::future::join_all(missing.iter().map(|uid| fetchers.fetch(uid, &self.metrics))).await;
+        if let serde_json::Value::Array(ref mut items) = entities {
+            for entity in fetched {
+                items.extend(entity?);
+            }
+        }
+        Ok(())
+    }
+
+    /// Disables the per-request log lines, e.g. while benchmarking.
+    fn without_decision_logging(mut self) -> Self {
+        self.log_decisions = false;
+        self
+    }
 
-    println!("Cedar Local Agent listening on {}", addr);
-    Server::bind(&addr).serve(make_svc).await?;
+    /// Evaluates a request and explains, for every loaded policy, whether its scope matched and
+    /// how each of its conditions evaluated.
+    fn explain(&self, req: AuthzRequest) -> Result<ExplainResponse, RequestError> {
+        let state = self.state();
+        let response = self.evaluate(&state, req.clone())?;
+
+        let now = req.evaluation_time.unwrap_or_else(|| self.clock.now());
+        let prepared = state.prepare(req)?;
+        let [(_, ref request)] = prepared.requests[..] else {
+            return Err(RequestError::invalid(
+                "unsupported",
+                "Explain a single action rather than an action group",
+            ));
+        };
+
+        let in_force = state.in_force(now);
+        let mut policies: Vec<explain::PolicyExplanation> = in_force
+            .as_deref()
+            .unwrap_or(&state.policy_set)
+            .policies()
+            .map(|policy| explain::explain_policy(policy, request, &prepared.entities))
+            .collect();
+        policies.sort_by(|a, b| a.id.cmp(&b.id));
 
-    Ok(())
-}
\ No newline at end of file
+        Ok(ExplainResponse { response, policies })
+    }
+
+    /// Evaluates a request and, when it is denied, reports the permit policies that came closest
+    /// to allowing it and what failed in each.
+    fn why_not(&self, req: AuthzRequest) -> Result<WhyNotResponse, RequestError> {
+        let state = self.state();
+        let response = self.evaluate(&state, req.clone())?;
+        let mut why_not = WhyNotResponse {
+            decision: response.decision,
+            denied_by: response.diagnostics.denied_by,
+            forbidden_by: response.diagnostics.forbids,
+            near_misses: Vec::new(),
+        };
+        if why_not.decision != "Deny" {
+            return Ok(why_not);
+        }
+
+        let now = req.evaluation_time.unwrap_or_else(|| self.clock.now());
+        let prepared = state.prepare(req)?;
+        let [(_, ref request)] = prepared.requests[..] else {
+            return Err(RequestError::invalid(
+                "unsupported",
+                "Ask why not for a single action rather than an action group",
+            ));
+        };
+
+        let in_force = state.in_force(now);
+        let mut near_misses: Vec<explain::NearMiss> = in_force
+            .as_deref()
+            .unwrap_or(&state.policy_set)
+            .policies()
+            .filter(|policy| policy.effect() == Effect::Permit)
+            .filter_map(|policy| explain::near_miss(policy, request, &prepared.entities))
+            .collect();
+        near_misses.sort_by(|a, b| a.failures.len().cmp(&b.failures.len()).then_with(|| a.id.cmp(&b.id)));
+        near_misses.truncate(MAX_NEAR_MISSES);
+        why_not.near_misses = near_misses;
+        Ok(why_not)
+    }
+
+    /// Suggests a permit policy that would allow a denied request, for an author to edit.
+    fn suggest(&self, req: AuthzRequest) -> Result<SuggestResponse, RequestError> {
+        let state = self.state();
+        let response = self.evaluate(&state, req.clone())?;
+        if response.decision != "Deny" {
+            return Err(RequestError::invalid("not_denied", "The request is already allowed"));
+        }
+
+        let mut context: Vec<String> = req
+            .context
+            .as_ref()
+            .and_then(serde_json::Value::as_object)
+            .map(|context| context.keys().cloned().collect())
+            .unwrap_or_default();
+        context.sort();
+        let prepared = state.prepare(req.clone())?;
+        let (principal, resource) = match prepared.requests.first().map(|(_, request)| (request.principal(), request.resource())) {
+            Some((Some(principal), Some(resource))) => (principal.clone(), resource.clone()),
+            _ => return Err(RequestError::Internal("Request has no principal or resource".to_string())),
+        };
+        let action: EntityUid = req
+            .action
+            .parse()
+            .map_err(|e| RequestError::invalid("invalid_action", format!("Failed to parse action: {}", e)))?;
+        let policy = suggest::skeleton(&suggest::Denied {
+            principal: &principal,
+            action: &action,
+            action_group: prepared.expanded,
+            resource: &resource,
+            principal_hints: suggest::EntityHints::of(&prepared.entities, &principal),
+            resource_hints: suggest::EntityHints::of(&prepared.entities, &resource),
+            context,
+        });
+        let forbids = response.diagnostics.forbids;
+        Ok(SuggestResponse {
+            decision: response.decision,
+            policy,
+            warning: (!forbids.is_empty())
+                .then(|| format!("Denied by forbid policies {}; a permit does not override them", forbids.join(", "))),
+        })
+    }
+
+    /// Evaluates a request against inline policies, schema and entities only; the agent's
+    /// loaded policies and stored entities are not consulted.
+    fn evaluate_inline(&self, req: EvaluateRequest) -> Result<EvaluateResponse, RequestError> {
+        let policy_set = policies::parse(&req.policies)
+            .map_err(|e| RequestError::invalid("invalid_policies", format!("Failed to parse policies: {}", e)))?;
+        let schema = match req.schema {
+            Some(ref schema_json) => Some(Schema::from_json_value(schema_json.clone())
+                .map_err(|e| RequestError::invalid("invalid_schema", format!("Failed to parse schema: {}", e)))?),
+            None => None,
+        };
+
+        let validation_errors = match schema {
+            Some(ref schema) => Validator::new(schema.clone())
+                .validate(&policy_set, ValidationMode::Strict)
+                .validation_errors()
+                .map(|e| e.to_string())
+                .collect(),
+            None => Vec::new(),
+        };
+
+        // The stored entities must conform to the inline schema; without one, they were stored
+        // already and rebuilding them cannot be the request's fault
+        let entities = self
+            .state()
+            .entities
+            .replaced_with(Vec::new(), schema.as_ref())
+            .map_err(|e| match schema {
+                Some(_) => RequestError::invalid("schema_violation", e),
+                None => RequestError::Internal(e),
+            })?;
+        let policies = policies::LoadedPolicies { policy_set, ..Default::default() };
+        let state = PolicyState::new(policies, schema, req.schema, Arc::new(entities));
+        let response = self.evaluate(&state, req.request)?;
+
+        Ok(EvaluateResponse {
+            response,
+            validation_errors,
+        })
+    }
+
+    /// The current policy state. Callers keep using the returned snapshot even if it is
+    /// replaced concurrently.
+    fn state(&self) -> Arc<PolicyState> {
+        Arc::clone(&self.state.read().unwrap())
+    }
+
+    /// Replaces the active schema after checking that the loaded policies and stored entities
+    /// still validate against it. On rejection the current schema stays active and the
+    /// validation errors are returned.
+    fn replace_schema(&self, schema_json: serde_json::Value) -> Result<schema::SchemaUpdate, schema::SchemaUpdateError> {
+        self.audited("schema.replace", || self.try_replace_schema(schema_json))
+    }
+
+    fn try_replace_schema(&self, schema_json: serde_json::Value) -> Result<schema::SchemaUpdate, schema::SchemaUpdateError> {
+        let new_schema = Schema::from_json_value(schema_json.clone())
+            .map_err(|e| schema::SchemaUpdateError::invalid(format!("Failed to parse schema: {}", e)))?;
+
+        let mut state = self.state.write().unwrap();
+        let (impact, entities) = schema::impact(&new_schema, &state.policy_set, &state.entities);
+        let entities = match entities {
+            Some(entities) if impact.compatible => entities,
+            _ => return Err(schema::SchemaUpdateError::incompatible(impact)),
+        };
+        let update = schema::SchemaUpdate {
+            status: "updated",
+            warnings: impact.warnings,
+        };
+
+        let replaced = PolicyState {
+            disabled: state.disabled.clone(),
+            ..PolicyState::new(state.policies(), Some(new_schema), Some(schema_json), Arc::new(entities))
+        };
+        self.activate(&mut state, replaced, "schema.replace").map_err(schema::SchemaUpdateError::invalid)?;
+        info!("Schema replaced ({} validation warnings)", update.warnings.len());
+
+        Ok(update)
+    }
+
+    /// Takes policies out of evaluation, or puts them back, leaving the policy set as it is.
+    /// Returns the ones that were not already disabled (or enabled).
+    fn set_disabled(&self, ids: &[PolicyId], disabled: bool) -> Result<Vec<PolicyId>, String> {
+        let action = if disabled { "policy.disable" } else { "policy.enable" };
+        self.audited(action, || {
+            let mut state = self.state.write().unwrap();
+            if let Some(id) = ids.iter().find(|id| state.policy_set.policy(id).is_none()) {
+                return Err(format!("No policy {}", id));
+            }
+            let changed: Vec<PolicyId> = ids.iter().filter(|id| state.disabled.contains(id) != disabled).cloned().collect();
+            if changed.is_empty() {
+                return Ok(changed);
+            }
+            let mut all = state.disabled.clone();
+            for id in &changed {
+                if disabled {
+                    all.insert(id.clone());
+                } else {
+                    all.remove(id);
+                }
+            }
+            let replaced = state.with_disabled(all);
+            self.activate(&mut state, replaced, action)?;
+            Ok(changed)
+        })
+    }
+
+    /// Replaces the policies, schema, entities and disabled policies with an archive's.
+    fn import(&self, contents: archive::Contents) -> Result<archive::Summary, String> {
+        self.audited("import", || {
+            let summary = contents.summary();
+            let state = contents.into_state(self)?;
+            let mut current = self.state.write().unwrap();
+            self.activate(&mut current, state, "import")?;
+            self.record_entity_usage(&current.entities);
+            Ok(summary)
+        })
+    }
+
+    /// Makes `state` the active state in place of the one `current` guards and advances the data
+    /// version, waking requests that wait for it. Called with the write lock held, so versions
+    /// follow the order in which states are activated. With `CEDAR_DATA_DIR` set the change is
+    /// persisted first, and if that fails the current state stays active. The change is
+    /// recorded in the audit log as `action`.
+    fn activate(
+        &self,
+        current: &mut RwLockWriteGuard<'_, Arc<PolicyState>>,
+        state: PolicyState,
+        action: &str,
+    ) -> Result<(), String> {
+        self.check_complexity(&state.policy_set)?;
+        if let Some(ref store) = self.store {
+            store.record(current, &state).inspect_err(|_| self.metrics.incr("store_errors", &[]))?;
+        }
+        let hashes = self.audit_log.as_ref().map(|_| (audit::Hashes::of(current), audit::Hashes::of(&state)));
+        **current = Arc::new(state);
+        self.data_version.send_modify(|version| *version += 1);
+        self.metrics.gauge("data_version", &[], self.data_version() as f64);
+        self.catalog.update(&current.policy_set, self.data_version(), unix_now());
+        if let (Some(log), Some((before, after))) = (&self.audit_log, hashes) {
+            let written = log.success(action, before, after, self.data_version());
+            self.audit_log_written(written);
+        }
+        Ok(())
+    }
+
+    /// Checks policies about to be activated against the complexity limits: refuses them when
+    /// the limits reject complex policies, else warns about the ones over the limits.
+    fn check_complexity(&self, policy_set: &PolicySet) -> Result<(), String> {
+        let over = self.complexity_limits.check(policy_set)?;
+        for policy in &over {
+            warn!("Policy over the complexity limits: {}", policy);
+        }
+        self.metrics.gauge("complex_policies", &[], over.len() as f64);
+        Ok(())
+    }
+
+    /// Runs a change to the state, recording in the audit log when it is rejected or fails
+    /// (`activate` records the ones that succeed).
+    fn audited<T, E: std::fmt::Display>(&self, action: &str, change: impl FnOnce() -> Result<T, E>) -> Result<T, E> {
+        let result = change();
+        if let (Some(log), Err(e)) = (&self.audit_log, &result) {
+            let written = log.failure(action, audit::Hashes::of(&self.state()), e.to_string());
+            self.audit_log_written(written);
+        }
+        result
+    }
+
+    fn audit_log_written(&self, written: Result<(), String>) {
+        if let Err(e) = written {
+            error!("Failed to write audit log: {}", e);
+            self.metrics.incr("audit_log_errors", &[]);
+        }
+    }
+
+    /// Makes a state replicated from the leader active under the leader's data version, so
+    /// versions handed out by the leader can be waited for on any replica.
+    fn replicate(&self, state: PolicyState, version: u64) {
+        let mut current = self.state.write().unwrap();
+        self.record_entity_usage(&state.entities);
+        *current = Arc::new(state);
+        self.data_version.send_replace(version);
+        self.metrics.gauge("data_version", &[], version as f64);
+        self.catalog.update(&current.policy_set, version, unix_now());
+        // A draining agent stays out of rotation
+        if !self.draining.load(std::sync::atomic::Ordering::Relaxed) {
+            self.serving.send_if_modified(|status| {
+                std::mem::replace(status, grpc_health::ServingStatus::Serving) != grpc_health::ServingStatus::Serving
+            });
+        }
+    }
+
+    /// The current state with its data version. Versions advance under the write lock, so
+    /// holding the read lock keeps the two consistent.
+    fn versioned_state(&self) -> (u64, Arc<PolicyState>) {
+        let state = self.state.read().unwrap();
+        (self.data_version(), Arc::clone(&state))
+    }
+
+    /// The number of writes to policies, schema and entities since startup.
+    fn data_version(&self) -> u64 {
+        *self.data_version.borrow()
+    }
+
+    /// Stops accepting connections; those open are served until they close. With `exit`, the
+    /// agent then exits.
+    fn stop_accepting(&self, exit: bool) {
+        self.accepting.send_if_modified(|accepting| {
+            let next = accepting.stop(exit);
+            std::mem::replace(accepting, next) != next
+        });
+    }
+
+    /// Resolves once the agent stops accepting connections.
+    async fn stopped(&self) {
+        let _ = self.accepting.subscribe().wait_for(|accepting| *accepting != drain::Accepting::Yes).await;
+    }
+
+    /// Resolves once the agent is to exit when its connections are closed.
+    async fn exiting(&self) {
+        let _ = self.accepting.subscribe().wait_for(|accepting| *accepting == drain::Accepting::Exiting).await;
+    }
+
+    /// Answers a request whose deadline passed during `stage`.
+    fn deadline_exceeded(&self, stage: &str) -> Response<Body> {
+        self.metrics.incr("deadline_exceeded", &[("stage", stage)]);
+        RequestError::DeadlineExceeded.response("Request")
+    }
+
+    /// Answers a request whose entities could not be fetched, or that ran out of time for it.
+    fn fetch_failed(&self, e: String) -> Response<Body> {
+        if deadline::expired() {
+            return self.deadline_exceeded("fetch");
+        }
+        error_response(StatusCode::BAD_GATEWAY, e)
+    }
+
+    /// Waits, up to `CEDAR_MIN_VERSION_WAIT_MS`, until the data version reaches `min_version`,
+    /// so a request sees a write whose version the caller was given.
+    async fn catch_up(&self, min_version: Option<u64>) -> Result<(), Response<Body>> {
+        let Some(min_version) = min_version else {
+            return Ok(());
+        };
+        let mut versions = self.data_version.subscribe();
+        let reached = tokio::time::timeout(deadline::bound(self.min_version_wait), versions.wait_for(|v| *v >= min_version))
+            .await
+            .is_ok_and(|waited| waited.is_ok());
+        if reached {
+            return Ok(());
+        }
+        if deadline::expired() {
+            return Err(self.deadline_exceeded("min_version"));
+        }
+        self.metrics.incr("min_version_timeouts", &[]);
+        let mut resp = error_response(
+            StatusCode::SERVICE_UNAVAILABLE,
+            format!("Data version {} not reached (at {})", min_version, self.data_version()),
+        );
+        resp.headers_mut().insert(hyper::header::RETRY_AFTER, 1.into());
+        Err(resp)
+    }
+
+    /// Waits, up to `wait`, until the data version differs from `since`, and returns the version
+    /// then current. A version below `since` (the agent restarted) counts as a change at once.
+    async fn version_after(&self, since: u64, wait: Duration) -> u64 {
+        let mut versions = self.data_version.subscribe();
+        let _ = tokio::time::timeout(wait, versions.wait_for(|v| *v != since)).await;
+        self.data_version()
+    }
+
+    /// Rewrites the stored entities: `update` gets the current ones (with direct parents) and
+    /// returns the new set, which is validated against the active schema before it replaces the
+    /// old one. Updates are serialized, so concurrent writers never lose each other's changes.
+    /// The update is recorded in the audit log as `action`.
+    fn update_entities<E: From<String> + std::fmt::Display>(
+        &self,
+        action: &str,
+        update: impl FnOnce(&[Entity]) -> Result<Vec<Entity>, E>,
+    ) -> Result<usize, E> {
+        self.audited(action, || self.try_update_entities(action, update))
+    }
+
+    fn try_update_entities<E: From<String>>(
+        &self,
+        action: &str,
+        update: impl FnOnce(&[Entity]) -> Result<Vec<Entity>, E>,
+    ) -> Result<usize, E> {
+        let mut state = self.state.write().unwrap();
+        let entities = state.entities.replaced_with(update(state.entities.direct())?, state.schema.as_ref())?;
+        entities.check_budget(self.entity_memory_limit)?;
+        let count = entities.len();
+        let entities = state.with_entities(entities);
+        self.activate(&mut state, entities, action)?;
+        self.record_entity_usage(&state.entities);
+        Ok(count)
+    }
+
+    /// Replaces the active policies, and the schema along with them when one is given, after
+    /// checking them together: the policies must pass strict validation against the schema and
+    /// the stored entities must conform to a new schema. On rejection nothing changes.
+    /// With `base`, the change is refused if the active policies no longer have that digest.
+    /// With `text`, the policy text of `loaded`, the policy file is replaced with it first, so
+    /// reloads and restarts keep the change; it is refused if the file cannot be written. The
+    /// change is recorded in the audit log as `action`.
+    fn replace_policies(
+        &self,
+        action: &str,
+        loaded: policies::LoadedPolicies,
+        schema: Option<(Schema, serde_json::Value)>,
+        base: Option<&str>,
+        text: Option<&str>,
+    ) -> Result<schema::SchemaUpdate, schema::SchemaUpdateError> {
+        self.audited(action, || self.try_replace_policies(action, loaded, schema, base, text))
+    }
+
+    fn try_replace_policies(
+        &self,
+        action: &str,
+        loaded: policies::LoadedPolicies,
+        schema: Option<(Schema, serde_json::Value)>,
+        base: Option<&str>,
+        text: Option<&str>,
+    ) -> Result<schema::SchemaUpdate, schema::SchemaUpdateError> {
+        let mut state = self.state.write().unwrap();
+        if base.is_some_and(|base| audit::policies_hash(&state.policy_set) != base) {
+            return Err(schema::SchemaUpdateError::invalid(
+                "Policies rejected: the active policies changed since the proposal; propose the change again".to_string(),
+            ));
+        }
+        let schema_changed = schema.is_some();
+        let (schema, schema_json) = match schema {
+            Some((schema, schema_json)) => (Some(schema), Some(schema_json)),
+            None => (state.schema.clone(), state.schema_json.clone()),
+        };
+
+        let update = match schema {
+            Some(ref schema) => schema::check_policies(schema, &loaded.policy_set).map_err(|e| schema::SchemaUpdateError {
+                error: format!("Policies rejected: {} fail validation against the schema", e.validation_errors.len()),
+                validation_errors: e.validation_errors,
+                impact: None,
+            })?,
+            None => schema::SchemaUpdate {
+                status: "updated",
+                warnings: Vec::new(),
+            },
+        };
+        let entities = match schema {
+            Some(ref schema) if schema_changed => Arc::new(state.entities.revalidate(schema).map_err(|e| {
+                schema::SchemaUpdateError {
+                    error: "Schema rejected: stored entities fail validation against it".to_string(),
+                    validation_errors: vec![e],
+                    impact: None,
+                }
+            })?),
+            _ => Arc::clone(&state.entities),
+        };
+
+        // Written under the lock, so the file and the active policies change together
+        let previous = match text {
+            Some(text) => {
+                let sources = self.reload_sources.as_ref().ok_or_else(|| {
+                    schema::SchemaUpdateError::invalid("Policies come from Verified Permissions; there is no policy file to write".to_string())
+                })?;
+                Some((sources, sources.write_policies(text).map_err(schema::SchemaUpdateError::invalid)?))
+            }
+            None => None,
+        };
+        let disabled = state.disabled_in(&loaded.policy_set);
+        let activated = self.activate(&mut state, PolicyState {
+            disabled,
+            ..PolicyState::new(loaded, schema, schema_json, entities)
+        }, action);
+        if let (Err(_), Some((sources, previous))) = (&activated, previous) {
+            if let Err(e) = sources.write_policies(&previous) {
+                error!("Failed to restore the policy file after a rejected change: {}", e);
+            }
+        }
+        activated.map_err(schema::SchemaUpdateError::invalid)?;
+        Ok(update)
+    }
+
+    /// Checks a reload candidate against `state`: the stored entities it keeps must conform to
+    /// its schema, and the entities must fit the memory budget. Returns what would change and
+    /// the entities to activate.
+    fn check_reload(
+        &self,
+        state: &PolicyState,
+        candidate: &reload::Candidate,
+        status: &'static str,
+    ) -> Result<(reload::Report, Arc<EntityStore>), schema::SchemaUpdateError> {
+        let schema_json = candidate.schema.as_ref().map(|(_, json)| json);
+        let schema_changed = schema_json != state.schema_json.as_ref();
+        let entities = match (&candidate.entities, &candidate.schema) {
+            (Some(entities), _) => Arc::clone(entities),
+            (None, Some((schema, _))) if schema_changed => Arc::new(state.entities.revalidate(schema).map_err(|e| {
+                schema::SchemaUpdateError {
+                    error: "Reload rejected: stored entities fail validation against the new schema".to_string(),
+                    validation_errors: vec![e],
+                    impact: None,
+                }
+            })?),
+            (None, _) => Arc::clone(&state.entities),
+        };
+        entities
+            .check_budget(self.entity_memory_limit)
+            .map_err(|e| schema::SchemaUpdateError::invalid(format!("Reload rejected: {}", e)))?;
+
+        let report = reload::Report {
+            status,
+            version: candidate.version.clone(),
+            policies: reload::diff(&state.policy_set, &candidate.policies.policy_set),
+            schema_changed,
+            entities: entities.len(),
+            warnings: candidate.warnings.clone(),
+        };
+        Ok((report, entities))
+    }
+
+    /// Validates a reload against the active state without activating it, and keeps it so a
+    /// follow-up call can commit exactly this version.
+    fn stage_reload(&self, candidate: reload::Candidate) -> Result<reload::Report, schema::SchemaUpdateError> {
+        let (report, _) = self.check_reload(&self.state(), &candidate, "validated")?;
+        *self.staged_reload.lock().unwrap() = Some(candidate);
+        Ok(report)
+    }
+
+    /// The staged reload, if it is the given version.
+    fn take_staged_reload(&self, version: &str) -> Option<reload::Candidate> {
+        let mut staged = self.staged_reload.lock().unwrap();
+        staged.as_ref().is_some_and(|c| c.version == version).then(|| staged.take()).flatten()
+    }
+
+    /// Replaces the policies, schema and entities with a reload candidate. On rejection
+    /// nothing changes.
+    fn activate_reload(&self, candidate: reload::Candidate) -> Result<reload::Report, schema::SchemaUpdateError> {
+        self.audited("reload", || self.try_activate_reload(candidate))
+    }
+
+    fn try_activate_reload(&self, candidate: reload::Candidate) -> Result<reload::Report, schema::SchemaUpdateError> {
+        let mut state = self.state.write().unwrap();
+        let (report, entities) = self.check_reload(&state, &candidate, "activated")?;
+        let (schema, schema_json) = candidate.schema.unzip();
+        let disabled = state.disabled_in(&candidate.policies.policy_set);
+        self.activate(&mut state, PolicyState {
+            disabled,
+            ..PolicyState::new(candidate.policies, schema, schema_json, entities)
+        }, "reload")
+        .map_err(schema::SchemaUpdateError::invalid)?;
+        self.record_entity_usage(&state.entities);
+        info!(
+            "Reloaded version {} ({} added, {} removed, {} changed policies)",
+            report.version,
+            report.policies.added.len(),
+            report.policies.removed.len(),
+            report.policies.changed.len()
+        );
+        Ok(report)
+    }
+
+    fn authorize(&self, req: AuthzRequest) -> Result<AuthzResponse, RequestError> {
+        self.authorize_with(&self.state(), req, None)
+    }
+
+    /// `authorize` against the staging policy set. The decision stays out of the decision log,
+    /// the policy catalog and the live request metrics.
+    fn authorize_staging(&self, req: AuthzRequest) -> Result<AuthzResponse, RequestError> {
+        let state = self
+            .staging
+            .state(&self.state())
+            .ok_or_else(|| RequestError::invalid("no_staging_policy_set", "No staging policy set is loaded"))?;
+        let result = self.evaluate(&state, req);
+        let decision = match result {
+            Ok(ref response) => response.decision.as_str(),
+            Err(RequestError::Invalid { .. }) => "invalid",
+            Err(RequestError::Internal(_)) => "error",
+            Err(RequestError::DeadlineExceeded) => "deadline_exceeded",
+        };
+        self.metrics.incr("staging_authorize_requests", &[("decision", decision)]);
+        result
+    }
+
+    /// `authorize` against `state`, with the request's entities already parsed when `parsed` is
+    /// given, as a batch does once for all of its items.
+    fn authorize_with(&self, state: &PolicyState, req: AuthzRequest, parsed: Option<&Entities>) -> Result<AuthzResponse, RequestError> {
+        if self.log_decisions {
+            info!("Authorization request - Principal: {}, Action: {}, Resource: {}", 
+                req.principal, req.action, req.resource);
+        }
+
+        let started = Instant::now();
+        let logged = self.decision_log.as_ref().filter(|_| self.log_decisions).map(|log| (log, req.clone()));
+        let indexed = self.decision_index.as_ref().filter(|_| self.log_decisions).map(|index| {
+            (index, [req.principal.clone(), req.action.clone(), req.resource.clone()])
+        });
+        let debugged = self.debug_decisions.as_ref().filter(|_| self.log_decisions).map(|ring| (ring, req.clone()));
+        let mut result = self.evaluate_with(state, req, parsed);
+        if let (Some(chaos), Ok(response)) = (&self.chaos, &mut result) {
+            if let Some(forced) = chaos.forced_decision() {
+                self.metrics.incr("chaos_faults", &[("fault", "decision")]);
+                response.force(forced);
+            }
+        }
+        if let Some((ring, req)) = debugged {
+            ring.record(debug_decisions::Record {
+                seq: 0,
+                time: String::new(),
+                duration_ms: started.elapsed().as_secs_f64() * 1000.0,
+                principal: req.principal,
+                action: req.action,
+                resource: req.resource,
+                context: req.context,
+                entities: req.entities,
+                response: result.as_ref().ok().and_then(|response| serde_json::to_value(response).ok()),
+                error: result.as_ref().err().map(ToString::to_string),
+            });
+        }
+        if let (Some((index, [principal, action, resource])), Ok(response)) = (indexed, &result) {
+            index.record(decision_index::Entry {
+                seq: 0,
+                time: String::new(),
+                at: chrono::Utc::now(),
+                principal,
+                action,
+                resource,
+                decision: response.decision.clone(),
+                reasons: response.diagnostics.reason.clone(),
+                errors: response.diagnostics.errors.clone(),
+                denied_by: response.diagnostics.denied_by,
+            });
+        }
+        let decision = match result {
+            Ok(ref response) => response.decision.as_str(),
+            Err(RequestError::Invalid { .. }) => "invalid",
+            Err(RequestError::Internal(_)) => "error",
+            Err(RequestError::DeadlineExceeded) => "deadline_exceeded",
+        };
+        if let (Some((log, req)), Ok(response)) = (logged, &result) {
+            let record = decision_log::Decision {
+                principal: req.principal,
+                action: req.action,
+                resource: req.resource,
+                context: req.context,
+                entities: req.entities,
+                decision: response.decision.clone(),
+                reasons: response.diagnostics.reason.clone(),
+                errors: response.diagnostics.errors.clone(),
+            };
+            if let Err(e) = log.append(record) {
+                error!("Failed to write decision log: {}", e);
+                self.metrics.incr("decision_log_errors", &[]);
+            }
+            // Writes the log's thread failed since the last decision
+            for _ in 0..log.take_failures() {
+                self.metrics.incr("decision_log_errors", &[]);
+            }
+        }
+        if let Ok(ref response) = result {
+            self.catalog.hit(&response.diagnostics.reason, unix_now());
+        }
+        let tenant = self.tenant();
+        let mut labels = vec![("decision", decision)];
+        labels.extend(tenant.as_deref().map(|tenant| ("tenant", tenant)));
+        self.metrics.incr("authorize_requests", &labels);
+        self.metrics.observe("authorize_duration", &labels[1..], started.elapsed());
+        result
+    }
+
+    /// The tenant label of the request being served, when metrics are labelled by tenant.
+    fn tenant(&self) -> Option<String> {
+        self.tenants
+            .as_ref()
+            .map(|_| tenant::current().unwrap_or_else(|| tenant::NONE.to_string()))
+    }
+
+    /// Refuses an evaluation request that sends more entities than its tenant may.
+    fn check_entity_quota(&self, entities: &serde_json::Value) -> Result<(), RequestError> {
+        let (Some(tenants), Some(tenant)) = (&self.tenants, tenant::current()) else {
+            return Ok(());
+        };
+        let Some(max) = tenants.limits(&tenant).max_entities else {
+            return Ok(());
+        };
+        let sent = entities.as_array().map_or(0, Vec::len);
+        if sent > max {
+            self.metrics.incr("tenant_rejections", &[("tenant", &tenant), ("limit", "entities")]);
+            return Err(RequestError::invalid(
+                "too_many_entities",
+                format!("Tenant '{}' may send at most {} entities, not {}", tenant, max, sent),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Evaluates a request against the given state, which need not be the active one.
+    fn evaluate(&self, state: &PolicyState, req: AuthzRequest) -> Result<AuthzResponse, RequestError> {
+        self.evaluate_with(state, req, None)
+    }
+
+    /// `evaluate` with the request's entities already parsed when `parsed` is given. Entity
+    /// parsing, request validation and policy evaluation are timed separately, as
+    /// `entity_parse_duration`, `validation_duration` and `evaluation_duration`; `parse_duration`
+    /// is the first two together.
+    fn evaluate_with(&self, state: &PolicyState, mut req: AuthzRequest, parsed: Option<&Entities>) -> Result<AuthzResponse, RequestError> {
+        // The caller has given up on the decision, as may happen between the items of a batch
+        if deadline::expired() {
+            self.metrics.incr("deadline_exceeded", &[("stage", "evaluation")]);
+            return Err(RequestError::DeadlineExceeded);
+        }
+        let summary = format!("principal: {}, action: {}, resource: {}", req.principal, req.action, req.resource);
+        let now = req.evaluation_time.unwrap_or_else(|| self.clock.now());
+        let mut phases = slow_log::Phases::new();
+        let started = Instant::now();
+        let entities = match parsed {
+            Some(entities) => Cow::Borrowed(entities),
+            None => {
+                self.check_entity_quota(&req.entities)?;
+                state.parse_entities(std::mem::take(&mut req.entities))?
+            }
+        };
+        phases.entity_parse = started.elapsed();
+        let prepared = state.prepare_with(req, entities)?;
+        phases.validation = started.elapsed() - phases.entity_parse;
+        // Entities parsed by the caller were timed there
+        if parsed.is_none() {
+            self.metrics.observe("entity_parse_duration", &[], phases.entity_parse);
+        }
+        self.metrics.observe("validation_duration", &[], phases.validation);
+        self.metrics.observe("parse_duration", &[], started.elapsed());
+
+        // Disabled policies, and those outside their scheduled window, take no part
+        let started = Instant::now();
+        let in_force = state.in_force(now);
+        let policy_set = in_force.as_deref().unwrap_or(&state.policy_set);
+        phases.slicing = started.elapsed();
+        debug!(
+            "Evaluating {} against {} policies with {} entities",
+            summary,
+            policy_set.policies().count(),
+            prepared.entities.iter().count()
+        );
+
+        let authorizer = Authorizer::new();
+        let started = Instant::now();
+        let responses: Vec<(EntityUid, Request, cedar_policy::Response)> = prepared
+            .requests
+            .into_iter()
+            .map(|(action, cedar_request)| {
+                // Evaluate authorization
+                let response = authorizer.is_authorized(&cedar_request, policy_set, &prepared.entities);
+                debug!(
+                    "{}: {:?} (determining: {:?}, errors: {})",
+                    action,
+                    response.decision(),
+                    response.diagnostics().reason().map(|id| id.to_string()).collect::<Vec<_>>(),
+                    response.diagnostics().errors().count()
+                );
+                (action, cedar_request, response)
+            })
+            .collect();
+        phases.evaluation = started.elapsed();
+        self.metrics.observe("evaluation_duration", &[], phases.evaluation);
+        let expanded = prepared.expanded;
+
+        // Build response; an expanded group is allowed only if every member action is
+        let all_allowed = responses
+            .iter()
+            .all(|(_, _, r)| r.decision() == cedar_policy::Decision::Allow);
+        let mut decision = if all_allowed { "Allow" } else { "Deny" };
+
+        let mut reason: Vec<String> = Vec::new();
+        let mut errors: Vec<String> = Vec::new();
+        let mut error_details = Vec::new();
+        let mut sources = BTreeMap::new();
+        let mut action_decisions = Vec::new();
+        let (mut permits, mut forbids, mut errored) = (BTreeSet::new(), BTreeSet::new(), BTreeSet::new());
+        for (action, cedar_request, response) in &responses {
+            // Get policy IDs that determined the decision
+            let action_reason: Vec<String> = response
+                .diagnostics()
+                .reason()
+                .map(|policy_id| policy_id.to_string())
+                .collect();
+
+            // Split the determining policies by effect; a forbid hides the permits it overrode
+            for id in response.diagnostics().reason() {
+                match state.policy_set.policy(id).map(|p| p.effect()) {
+                    Some(Effect::Forbid) => forbids.insert(id.to_string()),
+                    _ => permits.insert(id.to_string()),
+                };
+            }
+            if response.decision() == cedar_policy::Decision::Deny && response.diagnostics().reason().next().is_some() {
+                if let Some(permit_set) = state.schedule.permits(&state.policy_set, in_force.as_ref()) {
+                    permits.extend(
+                        overridden_permits(&permit_set, cedar_request, &prepared.entities)
+                            .iter()
+                            .map(PolicyId::to_string),
+                    );
+                }
+            }
+
+            // Get any errors that occurred during evaluation
+            for e in response.diagnostics().errors() {
+                errored.insert(error_policy_id(e).to_string());
+                let message = e.to_string();
+                if !errors.contains(&message) {
+                    errors.push(message);
+                    error_details.push(error_detail(e, &state.policy_sources));
+                }
+            }
+
+            // Attribute determining and erroring policies to their source layer
+            if state.layered {
+                let ids = response
+                    .diagnostics()
+                    .reason()
+                    .chain(response.diagnostics().errors().map(error_policy_id));
+                for id in ids {
+                    if let Some(source) = state.policy_sources.get(id) {
+                        sources.insert(id.to_string(), source.clone());
+                    }
+                }
+            }
+
+            for id in &action_reason {
+                if !reason.contains(id) {
+                    reason.push(id.clone());
+                }
+            }
+            if expanded {
+                action_decisions.push(ActionDecision {
+                    action: action.to_string(),
+                    decision: match response.decision() {
+                        cedar_policy::Decision::Allow => "Allow",
+                        cedar_policy::Decision::Deny => "Deny",
+                    },
+                    reason: action_reason,
+                });
+            }
+        }
+
+        // Nothing applied: fall back to the configured default decision
+        let mut default_decision = None;
+        let mut warnings = Vec::new();
+        if reason.is_empty() && errors.is_empty() {
+            default_decision = Some(self.default_decision.as_str());
+            match self.default_decision {
+                DefaultDecision::Deny => {}
+                DefaultDecision::Allow => decision = "Allow",
+                DefaultDecision::DenyWithWarning => {
+                    let warning = "No policy applied to this request; denied by default".to_string();
+                    warn!("{} ({})", warning, summary);
+                    warnings.push(warning);
+                }
+            }
+        }
+
+        // Directives come from the determining policies with the decision's effect; an expanded
+        // group's reasons may include permits of member actions a forbid did not deny
+        let effect = if decision == "Allow" { Effect::Permit } else { Effect::Forbid };
+        let determining: Vec<&cedar_policy::Policy> = reason
+            .iter()
+            .filter_map(|id| state.policy_set.policy(&PolicyId::new(id)))
+            .filter(|p| p.effect() == effect)
+            .collect();
+        let obligations = determining
+            .iter()
+            .flat_map(|p| policies::directives(p, policies::OBLIGATION_ANNOTATION))
+            .collect();
+        let advice = determining
+            .iter()
+            .flat_map(|p| policies::directives(p, policies::ADVICE_ANNOTATION))
+            .collect();
+
+        let denied_by = match decision {
+            "Deny" if !forbids.is_empty() => Some("forbid"),
+            "Deny" => Some("default"),
+            _ => None,
+        };
+
+        if self.log_decisions {
+            info!(target: logging::DECISION, "Authorization decision: {} (reasons: {:?}, errors: {:?})", 
+                decision, reason, errors);
+        }
+        if let Some(threshold) = self.slow_decision.filter(|threshold| phases.total() >= *threshold) {
+            self.metrics.incr("slow_decisions", &[]);
+            warn!(
+                "Slow decision: {:.1}ms (threshold {}ms) for {}: {} by {:?} ({}; {} policies, {} entities)",
+                phases.total().as_secs_f64() * 1000.0,
+                threshold.as_millis(),
+                summary,
+                decision,
+                reason,
+                phases.breakdown(),
+                policy_set.policies().count(),
+                prepared.entities.iter().count()
+            );
+        }
+
+        Ok(AuthzResponse {
+            decision: decision.to_string(),
+            actions: action_decisions,
+            obligations,
+            advice,
+            diagnostics: Diagnostics {
+                reason,
+                errors,
+                permits: permits.into_iter().collect(),
+                forbids: forbids.into_iter().collect(),
+                errored: errored.into_iter().collect(),
+                denied_by,
+                default_decision,
+                warnings,
+                error_details,
+                sources,
+            },
+        })
+    }
+}
+
+fn json_response<T: Serialize>(status: StatusCode, body: &T) -> Response<Body> {
+    let json = serde_json::to_string(body).unwrap();
+    Response::builder()
+        .status(status)
+        .header("content-type", "application/json")
+        .body(Body::from(json))
+        .unwrap()
+}
+
+fn error_response(status: StatusCode, message: impl Into<String>) -> Response<Body> {
+    json_response(status, &serde_json::json!({ "error": message.into() }))
+}
+
+/// Decodes `%XX` escapes in a path segment, e.g. `User::%22alice%22`.
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).ok();
+            if let Some(b) = hex.and_then(|h| u8::from_str_radix(h, 16).ok()) {
+                out.push(b);
+                i += 3;
+                continue;
+            }
+        }
+        out.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+/// Parses the query string into decoded key/value pairs.
+fn query_params(uri: &hyper::Uri) -> HashMap<String, String> {
+    uri.query()
+        .unwrap_or("")
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (percent_decode(key), percent_decode(value))
+        })
+        .collect()
+}
+
+/// `PUT` (replace, optionally only the entities of `?type=`) and `PATCH` (upsert)
+/// `/v1/data/entities`. The write is validated as a whole and applied atomically.
+async fn write_entities(req: hyper::Request<Body>, service: &CedarService) -> Response<Body> {
+    let write = if req.method() == Method::PUT {
+        match query_params(req.uri()).get("type") {
+            Some(entity_type) => match entity_type.parse::<EntityTypeName>() {
+                Ok(entity_type) => entities::Write::Replace(Some(entity_type)),
+                Err(e) => return error_response(StatusCode::BAD_REQUEST, format!("Invalid type: {}", e)),
+            },
+            None => entities::Write::Replace(None),
+        }
+    } else {
+        entities::Write::Upsert
+    };
+    let json = match read_json::<serde_json::Value>(req).await {
+        Ok(json) => json,
+        Err(resp) => return resp,
+    };
+    let payload = match entities::parse_list(json, service.state().schema.as_ref()) {
+        Ok(payload) => payload,
+        Err(e) => return error_response(StatusCode::BAD_REQUEST, e),
+    };
+
+    let mut summary = entities::WriteSummary::default();
+    let action = if write == entities::Write::Upsert { "entities.upsert" } else { "entities.replace" };
+    let result = service.update_entities(action, |current| {
+        let (updated, applied) = entities::apply(current, payload, &write)?;
+        summary = applied;
+        Ok::<_, String>(updated)
+    });
+    match result {
+        Ok(count) => {
+            summary.entities = count;
+            info!(
+                "Entities {}: {} written, {} removed",
+                if write == entities::Write::Upsert { "upserted" } else { "replaced" },
+                summary.written,
+                summary.removed
+            );
+            json_response(StatusCode::OK, &summary)
+        }
+        Err(e) => {
+            error!("Entity write rejected: {}", e);
+            error_response(StatusCode::BAD_REQUEST, e)
+        }
+    }
+}
+
+/// How long `GET /v1/data/version?since=` waits for a change by default, and at most.
+const DEFAULT_VERSION_WAIT: Duration = Duration::from_secs(30);
+const MAX_VERSION_WAIT: Duration = Duration::from_secs(120);
+
+/// Parses a wait such as `30s`, `500ms` or `2m`; a bare number is seconds.
+fn parse_wait(s: &str) -> Result<Duration, String> {
+    let (number, unit) = s.split_at(s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len()));
+    let n: u64 = number.parse().map_err(|_| format!("expected a duration such as 30s, got '{}'", s))?;
+    match unit {
+        "" | "s" => Ok(Duration::from_secs(n)),
+        "ms" => Ok(Duration::from_millis(n)),
+        "m" => Ok(Duration::from_secs(n.saturating_mul(60))),
+        _ => Err(format!("unknown unit '{}' (expected ms, s or m)", unit)),
+    }
+}
+
+/// Reads and deserializes a JSON request body, or produces the 400 response to send back.
+async fn read_json<T: serde::de::DeserializeOwned>(req: hyper::Request<Body>) -> Result<T, Response<Body>> {
+    let body_bytes = hyper::body::to_bytes(req.into_body()).await.map_err(|e| {
+        error!("Failed to read request body: {}", e);
+        error_response(StatusCode::BAD_REQUEST, format!("Failed to read body: {}", e))
+    })?;
+    serde_json::from_slice(&body_bytes).map_err(|e| {
+        error!("Parse error: {}", e);
+        error_response(StatusCode::BAD_REQUEST, format!("Invalid request: {}", e))
+    })
+}
+
+async fn handle_request(
+    req: hyper::Request<Body>,
+    service: Arc<CedarService>,
+) -> Result<Response<Body>, Infallible> {
+    let peer = req.extensions().get::<tls::PeerIdentity>().cloned();
+    if let Some(ref leader) = service.replica_of {
+        if replication::is_write(req.method(), req.uri().path()) {
+            return Ok(error_response(
+                StatusCode::CONFLICT,
+                format!("This agent is a read-only replica; send writes to {}", leader),
+            ));
+        }
+    }
+    match (req.method(), req.uri().path()) {
+        (&Method::GET, "/health") => {
+            let draining = service.draining.load(std::sync::atomic::Ordering::Relaxed);
+            let status = match (draining, service.freshness.is_stale()) {
+                (true, _) => "draining",
+                (false, true) => "stale",
+                (false, false) => "healthy",
+            };
+            let health = HealthResponse {
+                status: status.to_string(),
+                sources: service.freshness.snapshot(),
+            };
+            let json = serde_json::to_string(&health).unwrap();
+            Ok(Response::builder()
+                .status(if draining { StatusCode::SERVICE_UNAVAILABLE } else { StatusCode::OK })
+                .header("content-type", "application/json")
+                .body(Body::from(json))
+                .unwrap())
+        }
+
+        (&Method::POST, path) if path.starts_with(grpc_health::PATH_PREFIX) => Ok(grpc_health::handle(req, &service).await),
+
+        (&Method::POST, path) if grpc_reflection::PATHS.contains(&path) => Ok(grpc_reflection::handle(req, &service).await),
+
+        (&Method::GET, capabilities::PATH) => Ok(json_response(StatusCode::OK, &capabilities::capabilities(&service))),
+
+        (&Method::GET, "/metrics") => Ok(Response::builder()
+            .header("content-type", "text/plain; version=0.0.4")
+            .body(Body::from(service.metrics.render_prometheus()))
+            .unwrap()),
+
+        (&Method::POST, "/authorize") => {
+            let target = match staging::Target::parse(query_params(req.uri()).get("policyset").map(String::as_str)) {
+                Ok(target) => target,
+                Err(e) => return Ok(error_response(StatusCode::BAD_REQUEST, e)),
+            };
+            let requested_time = evaluation_time_header(&req);
+            let baggage = baggage::header(&req);
+            let format = match response_format_header(&req, service.response_format) {
+                Ok(format) => format,
+                Err(e) => return Ok(error_response(StatusCode::BAD_REQUEST, e)),
+            };
+            let reading = Instant::now();
+            let body_bytes = match hyper::body::to_bytes(req.into_body()).await {
+                Ok(bytes) => bytes,
+                Err(e) => {
+                    error!("Failed to read request body: {}", e);
+                    return Ok(Response::builder()
+                        .status(StatusCode::BAD_REQUEST)
+                        .body(Body::from(format!(r#"{{"error":"Failed to read body: {}"}}"#, e)))
+                        .unwrap());
+                }
+            };
+            let body_read = reading.elapsed();
+
+            match serde_json::from_slice::<AuthzRequest>(&body_bytes) {
+                Ok(authz_req) => {
+                    if let Err(resp) = service.catch_up(authz_req.min_version).await {
+                        return Ok(resp);
+                    }
+                    let authz_req = match service.token_principal(authz_req).await {
+                        Ok(authz_req) => service.peer_principal(authz_req, peer.as_ref()),
+                        Err(resp) => return Ok(resp),
+                    };
+                    let authz_req = match service
+                        .evaluation_time(requested_time.as_deref())
+                        .and_then(|time| service.stamp_time(authz_req, time))
+                        .and_then(|authz_req| service.stamp_baggage(authz_req, baggage.as_deref()))
+                    {
+                        Ok(authz_req) => authz_req,
+                        Err(e) => return Ok(error_response(StatusCode::BAD_REQUEST, e)),
+                    };
+                    let authz_req = match service.fetch_entities(authz_req).await {
+                        Ok(authz_req) => authz_req,
+                        Err(e) => return Ok(service.fetch_failed(e)),
+                    };
+                    let result = slow_log::with_body_read(body_read, || match target {
+                        staging::Target::Active => service.authorize(authz_req),
+                        staging::Target::Staging => service.authorize_staging(authz_req),
+                    });
+                    match result {
+                        Ok(authz_response) => {
+                            let json = serde_json::to_value(&authz_response).unwrap();
+                            let json = response_format::render(format, json).to_string();
+                            Ok(Response::builder()
+                                .header("content-type", "application/json")
+                                .extension(access_log::LoggedDecision(authz_response.decision))
+                                .body(Body::from(json))
+                                .unwrap())
+                        }
+                        Err(e) => Ok(e.response("Authorization")),
+                    }
+                }
+                Err(e) => {
+                    error!("Parse error: {}", e);
+                    Ok(Response::builder()
+                        .status(StatusCode::BAD_REQUEST)
+                        .header("content-type", "application/json")
+                        .body(Body::from(format!(r#"{{"error":"Invalid request: {}"}}"#, e)))
+                        .unwrap())
+                }
+            }
+        }
+
+        (&Method::GET, "/v1/schema") => match &service.state().schema_json {
+            Some(schema_json) => Ok(json_response(StatusCode::OK, schema_json)),
+            None => Ok(error_response(StatusCode::NOT_FOUND, "No schema loaded")),
+        },
+
+        (&Method::GET, "/v1/schema/introspect") => {
+            let state = service.state();
+            match (&state.schema, &state.schema_json) {
+                (Some(schema), Some(schema_json)) => Ok(json_response(
+                    StatusCode::OK,
+                    &schema::introspect(schema, schema_json),
+                )),
+                _ => Ok(error_response(StatusCode::NOT_FOUND, "No schema loaded")),
+            }
+        }
+
+        (&Method::GET, "/v1/schema/lint") => {
+            let state = service.state();
+            match (&state.schema, &state.schema_json) {
+                (Some(schema), Some(schema_json)) => match schema.action_entities() {
+                    Ok(actions) => Ok(json_response(
+                        StatusCode::OK,
+                        &schema_lint::lint(&schema::introspect(schema, schema_json), &state.policy_set, &actions),
+                    )),
+                    Err(e) => Ok(error_response(
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        format!("Failed to read schema actions: {}", e),
+                    )),
+                },
+                _ => Ok(error_response(StatusCode::NOT_FOUND, "No schema loaded")),
+            }
+        }
+
+        (&Method::GET, "/v1/schema/action-groups") => match service.state().schema {
+            Some(ref schema) => Ok(json_response(StatusCode::OK, &schema::action_groups(schema))),
+            None => Ok(error_response(StatusCode::NOT_FOUND, "No schema loaded")),
+        },
+
+        (&Method::PUT, "/v1/schema") => {
+            let schema_json = match read_json::<serde_json::Value>(req).await {
+                Ok(schema_json) => schema_json,
+                Err(resp) => return Ok(resp),
+            };
+            match service.replace_schema(schema_json) {
+                Ok(update) => Ok(json_response(StatusCode::OK, &update)),
+                Err(e) => {
+                    error!("Schema update rejected: {}", e.error);
+                    Ok(json_response(StatusCode::BAD_REQUEST, &e))
+                }
+            }
+        }
+
+        (&Method::POST, "/v1/schema/impact") => {
+            let schema_json = match read_json::<serde_json::Value>(req).await {
+                Ok(schema_json) => schema_json,
+                Err(resp) => return Ok(resp),
+            };
+            match Schema::from_json_value(schema_json) {
+                Ok(schema) => {
+                    let state = service.state();
+                    let (impact, _) = schema::impact(&schema, &state.policy_set, &state.entities);
+                    Ok(json_response(StatusCode::OK, &impact))
+                }
+                Err(e) => Ok(error_response(StatusCode::BAD_REQUEST, format!("Failed to parse schema: {}", e))),
+            }
+        }
+
+        (&Method::POST, "/v1/schema/infer") => {
+            let entities = match read_json::<Vec<serde_json::Value>>(req).await {
+                Ok(entities) => entities,
+                Err(resp) => return Ok(resp),
+            };
+            match schema_infer::infer(&entities) {
+                Ok(inferred) => Ok(json_response(StatusCode::OK, &inferred)),
+                Err(e) => Ok(error_response(StatusCode::BAD_REQUEST, e)),
+            }
+        }
+
+        (&Method::POST, "/v1/schema/context-usage") => {
+            let usage_req = match read_json::<ContextUsageRequest>(req).await {
+                Ok(usage_req) => usage_req,
+                Err(resp) => return Ok(resp),
+            };
+            let samples = match usage_req.requests {
+                Some(requests) => requests,
+                None => {
+                    let Some(path) = service.decision_log.as_ref().and_then(|log| log.path()) else {
+                        return Ok(error_response(
+                            StatusCode::BAD_REQUEST,
+                            "Send requests, or set CEDAR_DECISION_LOG_PATH to use recent decisions",
+                        ));
+                    };
+                    let path = path.to_string();
+                    let limit = usage_req.limit;
+                    match tokio::task::spawn_blocking(move || decision_log::recent(&path, limit)).await {
+                        Ok(Ok(decisions)) => decisions
+                            .into_iter()
+                            .map(|d| context_usage::Sample {
+                                action: d.action,
+                                context: d.context,
+                            })
+                            .collect(),
+                        Ok(Err(e)) => return Ok(error_response(StatusCode::INTERNAL_SERVER_ERROR, e)),
+                        Err(e) => return Ok(error_response(StatusCode::INTERNAL_SERVER_ERROR, e.to_string())),
+                    }
+                }
+            };
+            Ok(json_response(StatusCode::OK, &service.state().context_usage(samples)))
+        }
+
+        (&Method::POST, "/authorize/batch") => {
+            let requested_time = evaluation_time_header(&req);
+            let baggage = baggage::header(&req);
+            let format = match response_format_header(&req, service.response_format) {
+                Ok(format) => format,
+                Err(e) => return Ok(error_response(StatusCode::BAD_REQUEST, e)),
+            };
+            let batch = match read_json::<BatchRequest>(req).await {
+                Ok(batch) => batch,
+                Err(resp) => return Ok(resp),
+            };
+            match service
+                .authorize_batch(batch, requested_time.as_deref(), baggage.as_deref(), format, peer.as_ref())
+                .await
+            {
+                Ok(results) => Ok(json_response(StatusCode::OK, &results)),
+                Err(resp) => Ok(resp),
+            }
+        }
+
+        (&Method::POST, "/authorize/explain" | "/authorize/why-not" | "/authorize/suggest") => {
+            let path = req.uri().path().to_string();
+            let requested_time = evaluation_time_header(&req);
+            let baggage = baggage::header(&req);
+            let authz_req = match read_json::<AuthzRequest>(req).await {
+                Ok(authz_req) => authz_req,
+                Err(resp) => return Ok(resp),
+            };
+            let authz_req = match service.token_principal(authz_req).await {
+                Ok(authz_req) => service.peer_principal(authz_req, peer.as_ref()),
+                Err(resp) => return Ok(resp),
+            };
+            if let Err(resp) = service.catch_up(authz_req.min_version).await {
+                return Ok(resp);
+            }
+            let authz_req = match service
+                .evaluation_time(requested_time.as_deref())
+                .and_then(|time| service.stamp_time(authz_req, time))
+                .and_then(|authz_req| service.stamp_baggage(authz_req, baggage.as_deref()))
+            {
+                Ok(authz_req) => authz_req,
+                Err(e) => return Ok(error_response(StatusCode::BAD_REQUEST, e)),
+            };
+            let authz_req = match service.fetch_entities(authz_req).await {
+                Ok(authz_req) => authz_req,
+                Err(e) => return Ok(service.fetch_failed(e)),
+            };
+            match path.as_str() {
+                "/authorize/why-not" => match service.why_not(authz_req) {
+                    Ok(why_not) => Ok(json_response(StatusCode::OK, &why_not)),
+                    Err(e) => Ok(e.response("Why-not analysis")),
+                },
+                "/authorize/suggest" => match service.suggest(authz_req) {
+                    Ok(suggestion) => Ok(json_response(StatusCode::OK, &suggestion)),
+                    Err(e) => Ok(e.response("Policy suggestion")),
+                },
+                _ => match service.explain(authz_req) {
+                    Ok(explanation) => Ok(json_response(StatusCode::OK, &explanation)),
+                    Err(e) => Ok(e.response("Explain")),
+                },
+            }
+        }
+
+        (&Method::POST, "/v1/evaluate") => {
+            let requested_time = evaluation_time_header(&req);
+            let baggage = baggage::header(&req);
+            let mut eval_req = match read_json::<EvaluateRequest>(req).await {
+                Ok(eval_req) => eval_req,
+                Err(resp) => return Ok(resp),
+            };
+            eval_req.request = match service
+                .evaluation_time(requested_time.as_deref())
+                .and_then(|time| service.stamp_time(eval_req.request, time))
+                .and_then(|authz_req| service.stamp_baggage(authz_req, baggage.as_deref()))
+            {
+                Ok(authz_req) => authz_req,
+                Err(e) => return Ok(error_response(StatusCode::BAD_REQUEST, e)),
+            };
+            match service.evaluate_inline(eval_req) {
+                Ok(response) => Ok(json_response(StatusCode::OK, &response)),
+                Err(e) => Ok(e.response("Evaluation")),
+            }
+        }
+
+        (&Method::GET, "/v1/policies") => {
+            let params = query_params(req.uri());
+            let mut query = policies::ScopeQuery::default();
+            for (name, slot) in [
+                ("principal", &mut query.principal),
+                ("action", &mut query.action),
+                ("resource", &mut query.resource),
+            ] {
+                if let Some(value) = params.get(name) {
+                    match value.parse() {
+                        Ok(uid) => *slot = Some(uid),
+                        Err(e) => {
+                            return Ok(error_response(
+                                StatusCode::BAD_REQUEST,
+                                format!("Invalid {}: {}", name, e),
+                            ))
+                        }
+                    }
+                }
+            }
+            if let Some(label) = params.get("label") {
+                match labels::Selector::parse(label) {
+                    Ok(label) => query.label = Some(label),
+                    Err(e) => return Ok(error_response(StatusCode::BAD_REQUEST, e)),
+                }
+            }
+
+            Ok(json_response(StatusCode::OK, &service.state().matching_policies(&query)))
+        }
+
+        (&Method::GET, decision_index::PATH) => match service.decision_index {
+            Some(ref index) => match decision_index::Query::parse(&query_params(req.uri()), chrono::Utc::now()) {
+                Ok(query) => Ok(json_response(StatusCode::OK, &index.query(&query))),
+                Err(e) => Ok(error_response(StatusCode::BAD_REQUEST, e)),
+            },
+            None => Ok(error_response(
+                StatusCode::NOT_FOUND,
+                "The decision index is disabled (CEDAR_DECISION_INDEX_SIZE=0)",
+            )),
+        },
+
+        (&Method::GET, "/v1/policies/lint") => Ok(json_response(
+            StatusCode::OK,
+            &policy_lint::lint(&service.state().policy_set, &service.policy_lint),
+        )),
+
+        (&Method::POST, "/v1/policies/lint") => {
+            let body = match read_json::<PolicyLintRequest>(req).await {
+                Ok(body) => body,
+                Err(resp) => return Ok(resp),
+            };
+            match policies::parse(&body.policies) {
+                Ok(policy_set) => Ok(json_response(StatusCode::OK, &policy_lint::lint(&policy_set, &service.policy_lint))),
+                Err(e) => Ok(error_response(StatusCode::BAD_REQUEST, format!("Failed to parse policies: {}", e))),
+            }
+        }
+
+        (&Method::GET, "/v1/policies/metadata") => {
+            Ok(json_response(StatusCode::OK, &service.catalog.metadata(&service.state())))
+        }
+
+        (&Method::PUT | &Method::PATCH, "/v1/data/entities") => Ok(write_entities(req, &service).await),
+
+        (&Method::GET, "/v1/data/version") => {
+            let params = query_params(req.uri());
+            let since = match params.get("since").map(|since| since.parse::<u64>()) {
+                Some(Ok(since)) => since,
+                Some(Err(e)) => return Ok(error_response(StatusCode::BAD_REQUEST, format!("Invalid since: {}", e))),
+                None => return Ok(json_response(StatusCode::OK, &serde_json::json!({ "version": service.data_version() }))),
+            };
+            let wait = match params.get("wait").map(|wait| parse_wait(wait)) {
+                Some(Ok(wait)) => wait.min(MAX_VERSION_WAIT),
+                Some(Err(e)) => return Ok(error_response(StatusCode::BAD_REQUEST, format!("Invalid wait: {}", e))),
+                None => DEFAULT_VERSION_WAIT,
+            };
+            let version = service.version_after(since, wait).await;
+            Ok(json_response(
+                StatusCode::OK,
+                &serde_json::json!({ "version": version, "changed": version != since }),
+            ))
+        }
+
+        (&Method::POST, "/v1/data/entities/validate") => {
+            let json = match read_json::<serde_json::Value>(req).await {
+                Ok(json) => json,
+                Err(resp) => return Ok(resp),
+            };
+            let state = service.state();
+            match entities::validate(json, state.schema.as_ref(), state.entities.ingest()) {
+                Ok(report) => Ok(json_response(StatusCode::OK, &report)),
+                Err(e) => Ok(error_response(StatusCode::BAD_REQUEST, e)),
+            }
+        }
+
+        (&Method::GET, path) if path.starts_with("/v1/data/entities/") => {
+            let rest = &path["/v1/data/entities/".len()..];
+            let (uid, relation) = match rest.rsplit_once('/') {
+                Some((uid, relation @ ("ancestors" | "descendants"))) => (uid, relation),
+                _ => return Ok(error_response(StatusCode::NOT_FOUND, "Not found")),
+            };
+            let uid: EntityUid = match percent_decode(uid).parse() {
+                Ok(uid) => uid,
+                Err(e) => {
+                    return Ok(error_response(
+                        StatusCode::BAD_REQUEST,
+                        format!("Invalid entity UID: {}", e),
+                    ))
+                }
+            };
+
+            let state = service.state();
+            if !state.entities.contains(&uid) {
+                return Ok(error_response(
+                    StatusCode::NOT_FOUND,
+                    format!("Entity {} is not in the entity store", uid),
+                ));
+            }
+            let related = if relation == "ancestors" {
+                state.entities.ancestors(&uid)
+            } else {
+                state.entities.descendants(&uid)
+            };
+            Ok(json_response(
+                StatusCode::OK,
+                &serde_json::json!({ "uid": uid.to_string(), relation: related }),
+            ))
+        }
+
+        (_, path) if path == proposals::PATH || path.starts_with("/v1/policies/proposals/") => {
+            Ok(proposals::handle(req, Arc::clone(&service)).await)
+        }
+
+        (_, path) if labels::operation(path).is_some() => Ok(labels::handle(req, Arc::clone(&service)).await),
+
+        (&Method::POST, path) if policies::toggle(path).is_some() => {
+            let (id, disable) = policies::toggle(path).unwrap_or_default();
+            if let Some(resp) = proposals::refuse_unapproved(&service, if disable { "Disabling a policy" } else { "Enabling a policy" }) {
+                return Ok(resp);
+            }
+            let id = PolicyId::new(percent_decode(id));
+            if service.state().policy_set.policy(&id).is_none() {
+                return Ok(error_response(StatusCode::NOT_FOUND, format!("No policy {}", id)));
+            }
+            match service.set_disabled(std::slice::from_ref(&id), disable) {
+                Ok(changed) => {
+                    let changed = !changed.is_empty();
+                    if changed {
+                        info!(
+                            "Policy {} {} by {}",
+                            id,
+                            if disable { "disabled" } else { "enabled" },
+                            audit::identified().as_deref().unwrap_or("an unidentified caller")
+                        );
+                    }
+                    Ok(json_response(
+                        StatusCode::OK,
+                        &serde_json::json!({
+                            "id": id.to_string(),
+                            "disabled": disable,
+                            "changed": changed,
+                            "data_version": service.data_version(),
+                        }),
+                    ))
+                }
+                Err(e) => Ok(error_response(StatusCode::CONFLICT, e)),
+            }
+        }
+
+        (_, path) if path == staging::PATH || path.starts_with("/admin/staging/") => {
+            Ok(staging::handle(req, &service).await)
+        }
+
+        (_, path) if path.starts_with("/scim/v2/") => Ok(scim::handle(req, &service).await),
+
+        (&Method::GET, "/v1/usage") => match (&service.api_keys, req.extensions().get::<quota::Caller>()) {
+            (Some(keys), Some(caller)) => Ok(json_response(StatusCode::OK, &keys.usage(Some(&caller.0), unix_now())[0])),
+            _ => Ok(error_response(StatusCode::NOT_FOUND, "API keys are not configured")),
+        },
+
+        (&Method::GET, "/admin/usage") => match service.api_keys {
+            Some(ref keys) => Ok(json_response(StatusCode::OK, &keys.usage(None, unix_now()))),
+            None => Ok(error_response(StatusCode::NOT_FOUND, "API keys are not configured")),
+        },
+
+        (&Method::DELETE, "/admin/entity-cache") => {
+            let Some(ref fetchers) = service.fetchers else {
+                return Ok(error_response(StatusCode::NOT_FOUND, "No entity fetchers are configured"));
+            };
+            let params = query_params(req.uri());
+            let uid = match params.get("uid") {
+                Some(uid) => match uid.parse::<EntityUid>() {
+                    Ok(uid) => Some(uid),
+                    Err(e) => return Ok(error_response(StatusCode::BAD_REQUEST, format!("Invalid uid: {}", e))),
+                },
+                None => None,
+            };
+            let entity_type = match params.get("type") {
+                Some(entity_type) => match entity_type.parse::<EntityTypeName>() {
+                    Ok(entity_type) => Some(entity_type),
+                    Err(e) => return Ok(error_response(StatusCode::BAD_REQUEST, format!("Invalid type: {}", e))),
+                },
+                None => None,
+            };
+            let invalidated = fetchers.invalidate(uid.as_ref(), entity_type.as_ref(), &service.metrics);
+            Ok(json_response(StatusCode::OK, &serde_json::json!({ "invalidated": invalidated })))
+        }
+
+        (&Method::GET, "/admin/replication/stream") => Ok(replication::stream(&req, &service)),
+
+        // The archive holds every entity and an import replaces everything, so both need an
+        // administrator, whom the address rules alone do not identify
+        (&Method::GET, "/admin/export") | (&Method::POST, "/admin/import") if service.rbac.is_none() => Ok(error_response(
+            StatusCode::FORBIDDEN,
+            "Export and import need administrators; set CEDAR_ADMIN_KEYS or CEDAR_ADMIN_USERS",
+        )),
+
+        (&Method::GET, "/admin/export") => match archive::export(&service.state(), service.data_version()) {
+            Ok(archive) => Ok(Response::builder()
+                .header("content-type", "application/gzip")
+                .header(
+                    "content-disposition",
+                    format!("attachment; filename=\"cedar-agent-{}.tar.gz\"", service.data_version()),
+                )
+                .body(Body::from(archive))
+                .unwrap()),
+            Err(e) => Ok(error_response(StatusCode::INTERNAL_SERVER_ERROR, e)),
+        },
+
+        (&Method::POST, "/admin/import") => {
+            if service.reload_sources.is_none() {
+                return Ok(error_response(
+                    StatusCode::CONFLICT,
+                    "Policies come from Verified Permissions; import the archive into the policy store",
+                ));
+            }
+            if let Some(resp) = proposals::refuse_unapproved(&service, "Importing policies") {
+                return Ok(resp);
+            }
+            let (parts, body) = req.into_parts();
+            let body = match read_at_most(&parts.headers, body, archive::MAX_ARCHIVE).await {
+                Ok(Some(body)) => body,
+                Ok(None) => {
+                    return Ok(error_response(
+                        StatusCode::PAYLOAD_TOO_LARGE,
+                        format!("Archive exceeds {} bytes", archive::MAX_ARCHIVE),
+                    ))
+                }
+                Err(e) => return Ok(error_response(StatusCode::BAD_REQUEST, format!("Failed to read body: {}", e))),
+            };
+            // Decompressing and parsing up to a gigabyte would hold up the other requests
+            let contents = tokio::task::spawn_blocking(move || archive::import(&body))
+                .await
+                .unwrap_or_else(|e| Err(format!("Failed to read the archive: {}", e)));
+            match contents.and_then(|contents| service.import(contents)) {
+                Ok(summary) => {
+                    info!(
+                        "Imported an archive of data version {} ({} policies, {} entities)",
+                        summary.exported_version, summary.policies, summary.entities
+                    );
+                    Ok(json_response(
+                        StatusCode::OK,
+                        &serde_json::json!({
+                            "status": "imported",
+                            "archive": summary,
+                            "data_version": service.data_version(),
+                        }),
+                    ))
+                }
+                Err(e) => {
+                    warn!("Archive not imported: {}", e);
+                    Ok(error_response(StatusCode::BAD_REQUEST, format!("Archive rejected: {}", e)))
+                }
+            }
+        }
+
+        (&Method::POST, "/admin/reload") => {
+            let Some(ref sources) = service.reload_sources else {
+                return Ok(error_response(
+                    StatusCode::CONFLICT,
+                    "Policies come from Verified Permissions; there are no files to reload",
+                ));
+            };
+            let params = query_params(req.uri());
+            let dry_run = params.get("dry_run").is_some_and(|v| v == "true");
+            let result = match params.get("version") {
+                Some(_) if dry_run => {
+                    return Ok(error_response(
+                        StatusCode::BAD_REQUEST,
+                        "version commits an earlier dry run and cannot be combined with dry_run",
+                    ))
+                }
+                Some(version) => match service.take_staged_reload(version) {
+                    Some(candidate) => service.activate_reload(candidate),
+                    None => {
+                        return Ok(error_response(
+                            StatusCode::CONFLICT,
+                            format!("No validated reload with version {}; run a dry run first", version),
+                        ))
+                    }
+                },
+                None => reload::load(sources).and_then(|candidate| {
+                    if dry_run {
+                        service.stage_reload(candidate)
+                    } else {
+                        service.activate_reload(candidate)
+                    }
+                }),
+            };
+            match result {
+                Ok(report) => {
+                    service.metrics.incr("reloads", &[("result", report.status)]);
+                    if !dry_run {
+                        service.source_refreshed("files");
+                    }
+                    Ok(json_response(StatusCode::OK, &report))
+                }
+                Err(e) => {
+                    error!("Reload rejected: {}", e.error);
+                    service.metrics.incr("reloads", &[("result", "rejected")]);
+                    if !dry_run {
+                        service.source_failed("files", &e.error);
+                    }
+                    Ok(json_response(StatusCode::BAD_REQUEST, &e))
+                }
+            }
+        }
+
+        (&Method::GET | &Method::PUT | &Method::DELETE, chaos::PATH) => Ok(chaos::handle(req, &service).await),
+
+        (&Method::POST, drain::PATH) => Ok(drain::handle(&service, &query_params(req.uri())).await),
+
+        (&Method::GET, "/admin/loglevel") => Ok(json_response(
+            StatusCode::OK,
+            &LogLevel {
+                level: log::max_level().to_string().to_lowercase(),
+            },
+        )),
+
+        (&Method::PUT, "/admin/loglevel") => {
+            let body = match read_json::<LogLevel>(req).await {
+                Ok(body) => body,
+                Err(resp) => return Ok(resp),
+            };
+            match body.level.parse::<log::LevelFilter>() {
+                Ok(level) => {
+                    logging::set_level(level);
+                    Ok(json_response(StatusCode::OK, &body))
+                }
+                Err(_) => Ok(error_response(
+                    StatusCode::BAD_REQUEST,
+                    format!(
+                        "Invalid log level '{}' (expected off, error, warn, info, debug or trace)",
+                        body.level
+                    ),
+                )),
+            }
+        }
+
+        #[cfg(feature = "graphql")]
+        (&Method::POST, "/graphql") => {
+            let gql_req = match read_json::<async_graphql::Request>(req).await {
+                Ok(gql_req) => gql_req,
+                Err(resp) => return Ok(resp),
+            };
+            let response = graphql::execute(Arc::clone(&service), gql_req).await;
+            Ok(json_response(StatusCode::OK, &response))
+        }
+
+        (&Method::GET, "/debug/stats") => {
+            let queued = service.limiter.as_ref().map_or(0, |limiter| limiter.queued());
+            Ok(json_response(StatusCode::OK, &service.stats.report(queued)))
+        }
+
+        (&Method::GET, debug_decisions::PATH) => match service.debug_decisions {
+            Some(ref ring) => Ok(debug_decisions::handle(ring, &query_params(req.uri()))),
+            None => Ok(error_response(StatusCode::NOT_FOUND, "Not found")),
+        },
+
+        (&Method::GET, debug_decisions::STREAM_PATH) => match service.debug_decisions {
+            Some(ref ring) => Ok(debug_decisions::stream(ring, &query_params(req.uri()))),
+            None => Ok(error_response(StatusCode::NOT_FOUND, "Not found")),
+        },
+
+        #[cfg(feature = "profiling")]
+        (&Method::GET, "/debug/pprof/profile") if service.debug_endpoints => {
+            let params = query_params(req.uri());
+            let flamegraph = params.get("format").map(String::as_str) == Some("flamegraph");
+            Ok(profiling::cpu_profile(params.get("seconds").map(String::as_str), flamegraph).await)
+        }
+
+        #[cfg(feature = "profiling")]
+        (&Method::GET, "/debug/pprof/heap") if service.debug_endpoints => match profiling::heap_stats() {
+            Ok(stats) => Ok(json_response(StatusCode::OK, &stats)),
+            Err(e) => Ok(error_response(StatusCode::INTERNAL_SERVER_ERROR, e)),
+        },
+
+        #[cfg(feature = "profiling")]
+        (&Method::GET, "/debug/pprof/tasks") if service.debug_endpoints => {
+            Ok(json_response(StatusCode::OK, &profiling::task_stats()))
+        }
+
+        #[cfg(feature = "playground")]
+        (&Method::GET, "/playground") => Ok(playground::page()),
+
+        _ => Ok(Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(Body::from("Not found"))
+            .unwrap()),
+    }
+}
+
+/// Checks the HMAC signature when signing is enabled. The body has to be read for that, so the
+/// request is handed back rebuilt around the buffered bytes.
+async fn verify_signature(
+    req: hyper::Request<Body>,
+    service: &CedarService,
+) -> Result<hyper::Request<Body>, Response<Body>> {
+    let Some(ref verifier) = service.hmac else {
+        return Ok(req);
+    };
+    if is_health_check(req.uri().path()) {
+        return Ok(req);
+    }
+
+    let (parts, body) = req.into_parts();
+    let body = hyper::body::to_bytes(body).await.map_err(|e| {
+        error_response(StatusCode::BAD_REQUEST, format!("Failed to read body: {}", e))
+    })?;
+    let header = |name: &str| parts.headers.get(name).and_then(|v| v.to_str().ok());
+    let path = parts.uri.path_and_query().map(|p| p.as_str()).unwrap_or("/");
+
+    if let Err(e) = verifier.verify(
+        header(signing::TIMESTAMP_HEADER),
+        header(signing::SIGNATURE_HEADER),
+        parts.method.as_str(),
+        path,
+        &body,
+    ) {
+        warn!("Rejected {} {}: {}", parts.method, path, e);
+        return Err(error_response(StatusCode::UNAUTHORIZED, e));
+    }
+    Ok(hyper::Request::from_parts(parts, Body::from(body)))
+}
+
+/// Buffers the body when `CEDAR_MAX_REQUEST_BYTES` is set, refusing a larger one with `413`
+/// without reading past the limit.
+async fn limit_body(
+    req: hyper::Request<Body>,
+    service: &CedarService,
+    tenant: Option<&str>,
+) -> Result<hyper::Request<Body>, Response<Body>> {
+    let limit = match (&service.tenants, tenant) {
+        (Some(tenants), Some(tenant)) => tenants.limits(tenant).max_request_bytes,
+        _ => service.max_request_bytes,
+    };
+    let Some(limit) = limit else {
+        return Ok(req);
+    };
+    let too_large = || {
+        let labels: Vec<_> = tenant.map(|tenant| ("tenant", tenant)).into_iter().collect();
+        service.metrics.incr("oversized_requests", &labels);
+        error_response(StatusCode::PAYLOAD_TOO_LARGE, format!("Request body exceeds {} bytes", limit))
+    };
+    let (parts, body) = req.into_parts();
+    match read_at_most(&parts.headers, body, limit).await {
+        Ok(Some(body)) => Ok(hyper::Request::from_parts(parts, Body::from(body))),
+        Ok(None) => Err(too_large()),
+        Err(e) => Err(error_response(StatusCode::BAD_REQUEST, format!("Failed to read body: {}", e))),
+    }
+}
+
+/// Reads `body`, or `None` once it, or the length `headers` declare, exceeds `limit` bytes;
+/// nothing past the limit is read.
+async fn read_at_most(
+    headers: &hyper::HeaderMap,
+    mut body: Body,
+    limit: usize,
+) -> Result<Option<Vec<u8>>, hyper::Error> {
+    let declared = headers
+        .get(hyper::header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<usize>().ok());
+    if declared.is_some_and(|len| len > limit) {
+        return Ok(None);
+    }
+    let mut buffered = Vec::new();
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk?;
+        if buffered.len() + chunk.len() > limit {
+            return Ok(None);
+        }
+        buffered.extend_from_slice(&chunk);
+    }
+    Ok(Some(buffered))
+}
+
+fn evaluation_time_header(req: &hyper::Request<Body>) -> Option<String> {
+    req.headers()
+        .get(clock::EVALUATION_TIME_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_string)
+}
+
+/// The response format a request asks for with `X-Cedar-Response-Format`, or the configured one.
+fn response_format_header(req: &hyper::Request<Body>, default: config::ResponseFormat) -> Result<config::ResponseFormat, String> {
+    match req.headers().get(response_format::HEADER) {
+        Some(value) => value.to_str().unwrap_or_default().parse(),
+        None => Ok(default),
+    }
+}
+
+/// Makes a token's principal the request's, adding its entity and, if configured, its claims to
+/// the context. Entities or a context that are not a list and a record are left for evaluation
+/// to reject.
+fn with_identity(mut req: AuthzRequest, identity: &token::Identity) -> AuthzRequest {
+    req.principal = identity.principal.to_string();
+    if let serde_json::Value::Array(ref mut entities) = req.entities {
+        entities.push(identity.entity.clone());
+    }
+    add_claims(&mut req.context, identity);
+    req
+}
+
+/// Sets a token's claims as the configured context attribute, over any the request sent.
+fn add_claims(context: &mut Option<serde_json::Value>, identity: &token::Identity) {
+    if let Some((ref name, ref claims)) = identity.context {
+        let context = context.get_or_insert_with(|| serde_json::json!({}));
+        if let Some(context) = context.as_object_mut() {
+            context.insert(name.clone(), claims.clone());
+        }
+    }
+}
+
+/// A batch item's context: its own attributes over the batch's shared ones.
+fn merge_context(shared: Option<&serde_json::Value>, item: Option<serde_json::Value>) -> Option<serde_json::Value> {
+    match (shared, item) {
+        (Some(serde_json::Value::Object(shared)), Some(serde_json::Value::Object(item))) => {
+            let mut merged = shared.clone();
+            merged.extend(item);
+            Some(serde_json::Value::Object(merged))
+        }
+        (shared, item) => item.or_else(|| shared.cloned()),
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// Identifies the caller by `X-Api-Key` when API keys are configured and counts the request
+/// against the key's quotas. Probes, metrics and the admin endpoints, which have their own
+/// address rules, need no key; usage lookups need one but are not counted.
+async fn check_api_key(
+    mut req: hyper::Request<Body>,
+    service: &CedarService,
+) -> Result<hyper::Request<Body>, Response<Body>> {
+    let Some(ref keys) = service.api_keys else {
+        return Ok(req);
+    };
+    let path = req.uri().path();
+    if is_health_check(path) || path == "/metrics" || ["/admin/", "/debug/", "/scim/"].iter().any(|p| path.starts_with(p)) {
+        return Ok(req);
+    }
+    // Administrators authenticate with their admin key instead
+    if service.rbac.is_some() && rbac::allowed(req.method(), path).is_some() {
+        return Ok(req);
+    }
+
+    let header = req.headers().get(quota::API_KEY_HEADER).and_then(|v| v.to_str().ok());
+    let Some(name) = keys.identify(header) else {
+        warn!("Rejected {} {}: missing or unknown API key", req.method(), path);
+        return Err(error_response(StatusCode::UNAUTHORIZED, "Missing or unknown API key"));
+    };
+    if path != "/v1/usage" {
+        if let Err(exceeded) = keys.admit(&name, unix_now()) {
+            service.metrics.incr("quota_rejections", &[("key", &name), ("window", exceeded.window)]);
+            let mut resp = error_response(
+                StatusCode::TOO_MANY_REQUESTS,
+                format!("API key '{}' exceeded its {} quota of {} requests", name, exceeded.window, exceeded.limit),
+            );
+            resp.headers_mut().insert(hyper::header::RETRY_AFTER, exceeded.retry_after.into());
+            return Err(resp);
+        }
+    }
+    req.extensions_mut().insert(quota::Caller(name));
+    Ok(req)
+}
+
+/// Admits an evaluation request under its tenant's rate limit, or answers `429`.
+async fn admit_tenant(
+    req: hyper::Request<Body>,
+    service: &CedarService,
+    tenant: &str,
+) -> Result<hyper::Request<Body>, Response<Body>> {
+    let Some(ref tenants) = service.tenants else {
+        return Ok(req);
+    };
+    tenants.admit(tenant, Instant::now()).map(|_| req).map_err(|wait| {
+        service.metrics.incr("tenant_rejections", &[("tenant", tenant), ("limit", "rate")]);
+        let mut resp = json_response(
+            StatusCode::TOO_MANY_REQUESTS,
+            &serde_json::json!({
+                "error": format!("Tenant '{}' exceeded its rate limit", tenant),
+                "code": "rate_limited",
+            }),
+        );
+        resp.headers_mut().insert(hyper::header::RETRY_AFTER, (wait.as_secs_f64().ceil() as u64).max(1).into());
+        resp
+    })
+}
+
+/// Checks that the administrator making a request to the admin API, by their key or Basic
+/// credentials, holds a role the endpoint allows, when administrators are configured.
+async fn check_admin_role(
+    req: hyper::Request<Body>,
+    service: &CedarService,
+    client: IpAddr,
+) -> Result<hyper::Request<Body>, Response<Body>> {
+    let Some(ref rbac) = service.rbac else {
+        return Ok(req);
+    };
+    let Some(roles) = rbac::allowed(req.method(), req.uri().path()) else {
+        return Ok(req);
+    };
+    let basic = req.headers().get(hyper::header::AUTHORIZATION).and_then(|v| v.to_str().ok());
+    let basic = basic.filter(|header| header.starts_with("Basic "));
+    if basic.is_some() && req.extensions().get::<tls::Encrypted>().is_none() {
+        warn!("Rejected {} {}: Basic credentials sent without TLS from {}", req.method(), req.uri().path(), client);
+        return Err(error_response(StatusCode::FORBIDDEN, "Basic credentials are only accepted over TLS"));
+    }
+    let name = match basic {
+        Some(header) => match rbac.authenticate(header, client).await {
+            Ok(name) => name,
+            Err(wait) => {
+                warn!("Rejected {} {}: too many failed sign-ins from {}", req.method(), req.uri().path(), client);
+                service.metrics.incr("admin_sign_in_throttled", &[]);
+                let mut resp = error_response(StatusCode::TOO_MANY_REQUESTS, "Too many failed sign-ins; try again later");
+                resp.headers_mut().insert(hyper::header::RETRY_AFTER, wait.as_secs().max(1).into());
+                return Err(resp);
+            }
+        },
+        None => {
+            let header = req.headers().get(quota::API_KEY_HEADER).and_then(|v| v.to_str().ok());
+            rbac.identify(header)
+        }
+    };
+    let Some(name) = name else {
+        warn!("Rejected {} {}: missing or unknown admin credentials", req.method(), req.uri().path());
+        let mut resp = error_response(StatusCode::UNAUTHORIZED, "Missing or unknown admin credentials");
+        if rbac.has_users() {
+            let challenge = hyper::header::HeaderValue::from_static(r#"Basic realm="cedar-agent admin""#);
+            resp.headers_mut().insert(hyper::header::WWW_AUTHENTICATE, challenge);
+        }
+        return Err(resp);
+    };
+    let name = name.as_str();
+    if !rbac.permits(name, roles) {
+        warn!("Rejected {} {}: admin '{}' lacks the role", req.method(), req.uri().path(), name);
+        let roles: Vec<String> = roles.iter().map(|role| role.to_string()).collect();
+        return Err(error_response(
+            StatusCode::FORBIDDEN,
+            format!("Admin '{}' needs one of the roles: {}", name, roles.join(", ")),
+        ));
+    }
+    let mut req = req;
+    req.extensions_mut().insert(rbac::Admin(name.to_string()));
+    Ok(req)
+}
+
+/// Health checks over HTTP and gRPC, which skip request authentication so probes keep working.
+fn is_health_check(path: &str) -> bool {
+    path == "/health" || path.starts_with(grpc_health::PATH_PREFIX)
+}
+
+/// Response header with the data version, so a writer can pass it on as `min_version`.
+const DATA_VERSION_HEADER: &str = "x-cedar-data-version";
+
+/// Requests that evaluate policies, and so count against `CEDAR_MAX_CONCURRENT_EVALUATIONS`.
+fn is_evaluation(path: &str) -> bool {
+    matches!(
+        path,
+        "/authorize"
+            | "/authorize/batch"
+            | "/authorize/explain"
+            | "/authorize/why-not"
+            | "/authorize/suggest"
+            | "/v1/evaluate"
+            | "/graphql"
+    )
+}
+
+/// Per-connection wrapper around `handle_request`: applies the source-address rules before the
+/// body is read and writes the access log entry.
+async fn serve(
+    req: hyper::Request<Body>,
+    service: Arc<CedarService>,
+    client: SocketAddr,
+) -> Result<Response<Body>, Infallible> {
+    let _in_flight = service.stats.request();
+    let entry = service
+        .access_log
+        .as_ref()
+        .map(|_| access_log::AccessEntry::new(client, &req));
+    let started = Instant::now();
+
+    let response = if !service.ip_filter.permits(client.ip(), req.method(), req.uri().path()) {
+        warn!("Rejected {} {} from {}", req.method(), req.uri().path(), client.ip());
+        error_response(StatusCode::FORBIDDEN, "Source address not allowed")
+    } else {
+        let evaluation = is_evaluation(req.uri().path());
+        let deadline = match evaluation {
+            true => deadline::parse(req.headers(), started),
+            false => Ok(None),
+        };
+        let checked = match check_api_key(req, &service).await {
+            Ok(req) => check_admin_role(req, &service, client.ip()).await,
+            Err(resp) => Err(resp),
+        };
+        // The tenant is known with the API key, and sets the limits of the request
+        let tenant = match (&service.tenants, &checked) {
+            (Some(tenants), Ok(req)) => Some(tenants.identify(req)),
+            _ => None,
+        };
+        let checked = match checked {
+            Ok(req) => limit_body(req, &service, tenant.as_deref()).await,
+            Err(resp) => Err(resp),
+        };
+        let checked = match checked {
+            Ok(req) => verify_signature(req, &service).await,
+            Err(resp) => Err(resp),
+        };
+        // After the signature, so forged requests do not use up the tenant's rate limit
+        let checked = match (checked, tenant.as_deref()) {
+            (Ok(req), Some(tenant)) if evaluation => admit_tenant(req, &service, tenant).await,
+            (checked, _) => checked,
+        };
+        let checked = match (checked, &service.chaos) {
+            (Ok(req), Some(chaos)) if evaluation => chaos::inject(req, chaos, &service.metrics).await,
+            (checked, _) => checked,
+        };
+        // Work the caller will not wait for is refused before it starts
+        let checked = match (checked, &deadline) {
+            (Ok(_), Err(e)) => Err(RequestError::invalid("invalid_deadline", e.clone()).response("Request")),
+            (Ok(_), Ok(Some(at))) if at.saturating_duration_since(Instant::now()) < service.deadline_min_budget => {
+                Err(service.deadline_exceeded("admission"))
+            }
+            (checked, _) => checked,
+        };
+        let actor = checked.as_ref().ok().map(|req| audit::Actor::of(req, client.ip()));
+        let handled = async {
+            Ok::<_, Infallible>(match checked {
+                Ok(req) => match service.limiter {
+                    Some(ref limiter) if is_evaluation(req.uri().path()) => match limiter.acquire().await {
+                        Ok(_permit) => {
+                            service.metrics.gauge("queued_evaluations", &[], limiter.queued() as f64);
+                            handle_request(req, Arc::clone(&service)).await?
+                        }
+                        Err(limiter::Shed::QueueTimeout) if deadline::expired() => service.deadline_exceeded("queue"),
+                        Err(shed) => {
+                            let tenant = service.tenant();
+                            let mut labels = vec![("reason", shed.as_str())];
+                            labels.extend(tenant.as_deref().map(|tenant| ("tenant", tenant)));
+                            service.metrics.incr("shed_requests", &labels);
+                            let mut resp = error_response(StatusCode::SERVICE_UNAVAILABLE, "Overloaded, try again shortly");
+                            resp.headers_mut().insert(hyper::header::RETRY_AFTER, 1.into());
+                            resp
+                        }
+                    },
+                    _ => handle_request(req, Arc::clone(&service)).await?,
+                },
+                Err(resp) => resp,
+            })
+        };
+        let handled = deadline::scope(deadline.ok().flatten(), handled);
+        // Evaluations are labelled with, and limited by, their tenant; other requests may
+        // change the state, which the audit log attributes to their caller
+        match (tenant, actor) {
+            (Some(tenant), _) if evaluation => tenant::scope(tenant, handled).await?,
+            (_, Some(actor)) if !evaluation => audit::scope(actor, handled).await?,
+            _ => handled.await?,
+        }
+    };
+
+    let mut response = response;
+    response.headers_mut().insert(DATA_VERSION_HEADER, service.data_version().into());
+    if let (Some(log), Some(entry)) = (&service.access_log, entry) {
+        log.record(entry, &response, started.elapsed());
+    }
+    Ok(response)
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args: Vec<String> = std::env::args().collect();
+    if args.get(1).map(String::as_str) == Some("config") {
+        return config_check::run(&args[2..]);
+    }
+    if args.get(1).map(String::as_str) == Some("verify-log") {
+        return decision_log::run(&args[2..]);
+    }
+    // Before logging starts, so the report is all that goes to stdout
+    if args.get(1).map(String::as_str) == Some("replay") {
+        return replay::run(&args[2..]);
+    }
+    if args.get(1).map(String::as_str) == Some("export") {
+        return archive::run_export(&args[2..]);
+    }
+    if args.get(1).map(String::as_str) == Some("import") {
+        return archive::run_import(&args[2..]);
+    }
+    if args.get(1).map(String::as_str) == Some("lint") {
+        return policy_lint::run(&args[2..]);
+    }
+    if args.get(1).map(String::as_str) == Some("infer-schema") {
+        return schema_infer::run(&args[2..]);
+    }
+    if args.get(1).map(String::as_str) == Some("generate-fixtures") {
+        return fixtures::run(&args[2..]);
+    }
+    if args.get(1).map(String::as_str) == Some("loadtest") {
+        return loadtest::run(&args[2..]).await;
+    }
+    if args.get(1).map(String::as_str) == Some("service") {
+        #[cfg(windows)]
+        return winservice::run(&args[2..]);
+        #[cfg(not(windows))]
+        return Err("cedar-agent service is only available on Windows".into());
+    }
+    // Before the configuration is read, so settings can refer to secrets in Vault
+    let vault = vault::resolve_env().await?;
+    if args.iter().any(|arg| arg == "--validate-only") {
+        validate::run();
+    }
+
+    let config = Config::from_env()?;
+    logging::init(&config)?;
+    if let Some(vault) = vault {
+        tokio::spawn(vault.keep_alive());
+    }
+
+    if args.get(1).map(String::as_str) == Some("bench") {
+        return bench::run(&args[2..]).await;
+    }
+
+    run_agent(config, std::future::pending()).await
+}
+
+/// Runs the agent until serving fails, or until it stopped accepting and its connections
+/// drained. It stops accepting once `shutdown` resolves.
+async fn run_agent(
+    config: Config,
+    shutdown: impl std::future::Future<Output = ()> + Send + 'static,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let service = Arc::new(CedarService::new(&config)?);
+    tokio::spawn({
+        let service = Arc::clone(&service);
+        async move {
+            shutdown.await;
+            service.stop_accepting(true);
+        }
+    });
+
+    let addr: SocketAddr = config
+        .bind_addr
+        .parse()
+        .map_err(|e| format!("Invalid bind address: {}", e))?;
+
+    secret_file::start(&service, &config);
+
+    #[cfg(unix)]
+    tokio::spawn(logging::cycle_on_sigusr1());
+    #[cfg(unix)]
+    tokio::spawn(upgrade::upgrade_on_sigusr2(Arc::clone(&service), config.upgrade_timeout));
+
+    if config.replica_of.is_some() {
+        if config.avp_policy_store_id.is_some() || config.ldap_url.is_some() || config.k8s_import || config.data_dir.is_some() {
+            return Err("CEDAR_REPLICA_OF cannot be combined with CEDAR_AVP_POLICY_STORE_ID, CEDAR_LDAP_URL, \
+                        CEDAR_K8S_IMPORT or CEDAR_DATA_DIR: replicas take their state from the leader"
+                .into());
+        }
+        replication::start(Arc::clone(&service), &config)?;
+    }
+
+    if config.avp_policy_store_id.is_some() {
+        #[cfg(feature = "avp")]
+        avp::start(Arc::clone(&service), &config).await?;
+        #[cfg(not(feature = "avp"))]
+        return Err("CEDAR_AVP_POLICY_STORE_ID is set but this build does not include the `avp` feature".into());
+    }
+
+    if config.ldap_url.is_some() {
+        #[cfg(feature = "ldap")]
+        ldap::start(Arc::clone(&service), &config).await?;
+        #[cfg(not(feature = "ldap"))]
+        return Err("CEDAR_LDAP_URL is set but this build does not include the `ldap` feature".into());
+    }
+
+    if config.k8s_import {
+        #[cfg(feature = "kubernetes")]
+        kubernetes::start(Arc::clone(&service), &config).await?;
+        #[cfg(not(feature = "kubernetes"))]
+        return Err("CEDAR_K8S_IMPORT is set but this build does not include the `kubernetes` feature".into());
+    }
+
+    if config.spiffe {
+        if config.tls_cert_path.is_some() || config.tls_key_path.is_some() || !config.acme_domains.is_empty() {
+            return Err("CEDAR_SPIFFE cannot be combined with CEDAR_TLS_CERT/CEDAR_TLS_KEY or CEDAR_ACME_DOMAINS".into());
+        }
+        #[cfg(feature = "spiffe")]
+        return svid::serve(addr, service, &config).await;
+        #[cfg(not(feature = "spiffe"))]
+        return Err("CEDAR_SPIFFE is set but this build does not include the `spiffe` feature".into());
+    }
+
+    if !config.acme_domains.is_empty() {
+        if config.tls_cert_path.is_some() || config.tls_key_path.is_some() {
+            return Err("CEDAR_ACME_DOMAINS cannot be combined with CEDAR_TLS_CERT/CEDAR_TLS_KEY".into());
+        }
+        #[cfg(feature = "acme")]
+        return acme::serve(addr, service, &config).await;
+        #[cfg(not(feature = "acme"))]
+        return Err("CEDAR_ACME_DOMAINS is set but this build does not include the `acme` feature".into());
+    }
+
+    match (&config.tls_cert_path, &config.tls_key_path) {
+        (Some(cert), Some(key)) => {
+            let acceptors = acceptors::Acceptors::bind(addr, &config)?;
+            let certs = Arc::new(tls::CertReloader::new(cert, key, &service)?);
+            tokio::spawn(Arc::clone(&certs).watch(Arc::clone(&service), config.tls_reload_interval));
+            return tls::serve_tls(acceptors, service, tls::server_config(certs, false)?, None).await;
+        }
+        (None, None) => {}
+        _ => return Err("CEDAR_TLS_CERT and CEDAR_TLS_KEY must be set together".into()),
+    }
+
+    let acceptors = acceptors::Acceptors::bind(addr, &config)?;
+    info!("Cedar Local Agent listening on {}", addr);
+    let serving = acceptors.run({
+        let service = Arc::clone(&service);
+        move |listener| {
+            let service = Arc::clone(&service);
+            async move {
+                service.stats.register_acceptor();
+                let stopping = Arc::clone(&service);
+                let make_svc = make_service_fn(move |conn: &AddrStream| {
+                    let service = Arc::clone(&service);
+                    let client = conn.remote_addr();
+                    // Dropped with the connection's service
+                    let connection = service.stats.connection();
+                    async move {
+                        Ok::<_, Infallible>(service_fn(move |req| {
+                            let _connection = &connection;
+                            serve(req, Arc::clone(&service), client)
+                        }))
+                    }
+                });
+                Server::builder(AddrIncoming::from_listener(listener)?)
+                    .serve(make_svc)
+                    .with_graceful_shutdown(async move { stopping.stopped().await })
+                    .await?;
+                Ok(())
+            }
+        }
+    });
+    let drained = upgrade::drain(serving, &service).await;
+    // Before exiting releases its lock, so a new process continues a complete log
+    if let Some(ref log) = service.decision_log {
+        log.close();
+    }
+    drained
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn percent_decode_handles_escapes() {
+        assert_eq!(percent_decode("User::%22alice%22"), r#"User::"alice""#);
+        assert_eq!(percent_decode("a%2Fb%2fc"), "a/b/c");
+        assert_eq!(percent_decode("%E2%9C%93"), "✓");
+    }
+
+    #[test]
+    fn parse_wait_accepts_units() {
+        assert_eq!(parse_wait("30s"), Ok(Duration::from_secs(30)));
+        assert_eq!(parse_wait("45"), Ok(Duration::from_secs(45)));
+        assert_eq!(parse_wait("250ms"), Ok(Duration::from_millis(250)));
+        assert_eq!(parse_wait("2m"), Ok(Duration::from_secs(120)));
+        assert!(parse_wait("s").is_err());
+        assert!(parse_wait("10h").is_err());
+        assert!(parse_wait("-1s").is_err());
+    }
+
+    #[test]
+    fn error_details_locate_the_failing_expression() {
+        let text = "@id(\"owner\")\npermit (principal, action, resource)\nwhen { resource.owner == principal };\n";
+        let policy_set = policies::parse(text).unwrap();
+        let request = Request::new(
+            r#"User::"alice""#.parse().unwrap(),
+            r#"Action::"view""#.parse().unwrap(),
+            r#"Doc::"a""#.parse().unwrap(),
+            Context::empty(),
+            None,
+        )
+        .unwrap();
+        let response = Authorizer::new().is_authorized(&request, &policy_set, &Entities::empty());
+        let sources = HashMap::from([(PolicyId::new("owner"), "policy.cedar".to_string())]);
+        let details: Vec<ErrorDetail> = response.diagnostics().errors().map(|e| error_detail(e, &sources)).collect();
+        assert_eq!(details.len(), 1);
+        let detail = &details[0];
+        assert_eq!(detail.policy, "owner");
+        assert_eq!(detail.source.as_deref(), Some("policy.cedar"));
+        assert!(detail.message.contains("Doc::\"a\""), "{}", detail.message);
+        assert_eq!((detail.line, detail.column), (Some(3), Some(8)));
+        assert_eq!(detail.snippet.as_deref(), Some("resource"));
+    }
+
+    #[test]
+    fn finds_the_permits_a_forbid_overrode() {
+        let mut policy_set = policies::parse(
+            r#"
+            @id("owner") permit (principal, action, resource) when { context.owner };
+            @id("staff") permit (principal, action, resource);
+            @id("never") permit (principal, action, resource) when { false };
+            @id("locked") forbid (principal, action, resource) when { context.locked };
+            @id("block") forbid (principal == ?principal, action, resource);
+            "#,
+        )
+        .unwrap();
+        policy_set
+            .link(
+                PolicyId::new("block"),
+                PolicyId::new("block-bob"),
+                HashMap::from([(cedar_policy::SlotId::principal(), r#"User::"bob""#.parse().unwrap())]),
+            )
+            .unwrap();
+        let request = |principal: &str| {
+            Request::new(
+                principal.parse().unwrap(),
+                r#"Action::"view""#.parse().unwrap(),
+                r#"Doc::"a""#.parse().unwrap(),
+                Context::from_json_value(serde_json::json!({"owner": true, "locked": true}), None).unwrap(),
+                None,
+            )
+            .unwrap()
+        };
+        let ids = |principal: &str| -> Vec<String> {
+            let permits = schedule::Schedule::default().permits(&policy_set, None).unwrap();
+            let mut ids: Vec<String> = overridden_permits(&permits, &request(principal), &Entities::empty())
+                .iter()
+                .map(PolicyId::to_string)
+                .collect();
+            ids.sort();
+            ids
+        };
+        assert_eq!(ids(r#"User::"alice""#), vec!["owner", "staff"]);
+        assert_eq!(ids(r#"User::"bob""#), vec!["owner", "staff"]);
+    }
+
+    #[test]
+    fn prepare_classifies_request_errors() {
+        let schema = Schema::from_cedarschema_str(
+            "entity User; entity Doc; action view appliesTo { principal: User, resource: Doc, context: { ip: ipaddr } };",
+        )
+        .unwrap()
+        .0;
+        let entities = Arc::new(EntityStore::from_entities(Vec::new(), Some(&schema), Default::default()).unwrap());
+        let state = PolicyState::new(Default::default(), Some(schema), None, entities);
+        let code = |principal: &str, entities: serde_json::Value, context: serde_json::Value| {
+            let req = AuthzRequest {
+                principal: principal.to_string(),
+                action: r#"Action::"view""#.to_string(),
+                resource: r#"Doc::"d""#.to_string(),
+                entities,
+                context: Some(context),
+                min_version: None,
+                token: None,
+                evaluation_time: None,
+            };
+            match state.prepare(req) {
+                Ok(_) => "ok",
+                Err(e) => {
+                    assert_eq!(e.status(), StatusCode::BAD_REQUEST);
+                    e.code()
+                }
+            }
+        };
+        let ip = serde_json::json!({"ip": "10.0.0.1"});
+        assert_eq!(code(r#"User::"a""#, serde_json::json!([]), ip.clone()), "ok");
+        assert_eq!(code("alice", serde_json::json!([]), ip.clone()), "invalid_principal");
+        assert_eq!(code(r#"User::"a""#, serde_json::json!({}), ip.clone()), "invalid_entities");
+        assert_eq!(code(r#"User::"a""#, serde_json::json!([]), serde_json::json!({"ip": "nope"})), "invalid_context");
+        assert_eq!(code(r#"Doc::"a""#, serde_json::json!([]), ip), "schema_violation");
+        assert_eq!(RequestError::Internal("x".to_string()).status(), StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    #[test]
+    fn batch_items_reuse_parsed_entities() {
+        let entities = Arc::new(EntityStore::from_entities(Vec::new(), None, Default::default()).unwrap());
+        let state = PolicyState::new(Default::default(), None, None, entities);
+        let parsed = state
+            .parse_entities(serde_json::json!([
+                {"uid": {"type": "User", "id": "alice"}, "attrs": {}, "parents": [{"type": "Group", "id": "admins"}]}
+            ]))
+            .unwrap();
+        for resource in [r#"Doc::"a""#, r#"Doc::"b""#] {
+            // The item's own entities are not parsed again
+            let req = AuthzRequest {
+                principal: r#"User::"alice""#.to_string(),
+                action: r#"Action::"view""#.to_string(),
+                resource: resource.to_string(),
+                entities: serde_json::Value::Null,
+                context: None,
+                min_version: None,
+                token: None,
+                evaluation_time: None,
+            };
+            let prepared = state.prepare_with(req.clone(), Cow::Borrowed(&parsed)).unwrap();
+            assert!(prepared.entities.get(&r#"User::"alice""#.parse().unwrap()).is_some());
+            assert_eq!(state.prepare(req).err().unwrap().code(), "invalid_entities");
+        }
+    }
+
+    #[tokio::test]
+    async fn evaluates_scheduled_policies_at_the_requested_time() {
+        let path = std::env::temp_dir().join(format!("cedar-scheduled-{}.cedar", std::process::id()));
+        std::fs::write(
+            &path,
+            r#"@effective_from("2026-10-15T08:00:00Z") @expires_at("2026-10-15T12:00:00Z") permit(principal, action, resource);"#,
+        )
+        .unwrap();
+        let config = Config {
+            policy_path: path.to_str().unwrap().to_string(),
+            schema_path: path.with_extension("schema.json").to_str().unwrap().to_string(),
+            test_mode: true,
+            context_time_attribute: Some("now".to_string()),
+            ..Config::from_env().unwrap()
+        };
+        let service = CedarService::new(&config);
+        std::fs::remove_file(&path).unwrap();
+        let service = service.unwrap();
+        let decision = |time: &str| {
+            let req = AuthzRequest {
+                principal: r#"User::"alice""#.to_string(),
+                action: r#"Action::"view""#.to_string(),
+                resource: r#"Doc::"d""#.to_string(),
+                entities: no_entities(),
+                context: None,
+                min_version: None,
+                token: None,
+                evaluation_time: None,
+            };
+            let req = service.evaluation_time(Some(time)).and_then(|time| service.stamp_time(req, time)).unwrap();
+            let state = service.state();
+            (service.explain(req.clone()).unwrap().policies.len(), service.evaluate(&state, req).unwrap().decision)
+        };
+        assert_eq!(decision("2026-10-15T07:59:59Z"), (0, "Deny".to_string()));
+        assert_eq!(decision("2026-10-15T09:00:00Z"), (1, "Allow".to_string()));
+        assert_eq!(decision("2026-10-15T12:00:00Z"), (0, "Deny".to_string()));
+    }
+
+    #[tokio::test]
+    async fn accepts_basic_credentials_over_tls_only() {
+        let path = std::env::temp_dir().join(format!("cedar-basic-{}.cedar", std::process::id()));
+        std::fs::write(&path, "permit (principal, action, resource);\n").unwrap();
+        let config = Config {
+            policy_path: path.to_str().unwrap().to_string(),
+            schema_path: path.with_extension("schema.json").to_str().unwrap().to_string(),
+            admin_users: vec![format!("ops:{}", bcrypt::hash("s3cret", 4).unwrap())],
+            admin_roles: vec!["ops=operator".to_string()],
+            ..Config::from_env().unwrap()
+        };
+        let service = CedarService::new(&config);
+        std::fs::remove_file(&path).unwrap();
+        let service = service.unwrap();
+        let request = |encrypted: bool| {
+            let mut req = hyper::Request::post("/admin/reload")
+                .header(hyper::header::AUTHORIZATION, "Basic b3BzOnMzY3JldA==")
+                .body(Body::empty())
+                .unwrap();
+            if encrypted {
+                req.extensions_mut().insert(tls::Encrypted);
+            }
+            req
+        };
+        let client: IpAddr = "10.0.0.1".parse().unwrap();
+        let plain = check_admin_role(request(false), &service, client).await;
+        assert_eq!(plain.err().unwrap().status(), StatusCode::FORBIDDEN);
+        let req = check_admin_role(request(true), &service, client).await.unwrap();
+        assert_eq!(req.extensions().get::<rbac::Admin>().unwrap().0, "ops");
+    }
+
+    #[test]
+    fn batch_items_share_the_token_principal() {
+        let shared = serde_json::json!({"ip": "10.0.0.1", "mfa": false});
+        let context = merge_context(Some(&shared), Some(serde_json::json!({"mfa": true})));
+        assert_eq!(context, Some(serde_json::json!({"ip": "10.0.0.1", "mfa": true})));
+        assert_eq!(merge_context(Some(&shared), None), Some(shared.clone()));
+
+        let identity = token::Identity {
+            principal: r#"User::"alice""#.parse().unwrap(),
+            entity: serde_json::json!({"uid": {"type": "User", "id": "alice"}, "attrs": {}, "parents": []}),
+            context: Some(("token".to_string(), serde_json::json!({"scope": "read"}))),
+        };
+        let req = AuthzRequest {
+            principal: String::new(),
+            action: r#"Action::"view""#.to_string(),
+            resource: r#"Doc::"d""#.to_string(),
+            entities: no_entities(),
+            context: context.clone(),
+            min_version: None,
+            token: None,
+            evaluation_time: None,
+        };
+        let req = with_identity(req, &identity);
+        assert_eq!(req.principal, r#"User::"alice""#);
+        assert_eq!(req.entities.as_array().unwrap().len(), 1);
+        assert_eq!(req.context.unwrap()["token"]["scope"], "read");
+    }
+
+    #[test]
+    fn percent_decode_keeps_malformed_escapes() {
+        assert_eq!(percent_decode("100%"), "100%");
+        assert_eq!(percent_decode("%2"), "%2");
+        assert_eq!(percent_decode("%zz%41"), "%zzA");
+        assert_eq!(percent_decode("%%41"), "%A");
+    }
+}
@@ -0,0 +1,190 @@
+use crate::config::Config;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::net::UdpSocket;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Upper bounds, in seconds, of the latency histogram buckets.
+const BUCKETS: [f64; 10] = [0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 1.0];
+
+/// Metric name plus its labels, e.g. `("authorize_requests", [("decision", "Allow")])`.
+type Key = (&'static str, Vec<(&'static str, String)>);
+
+#[derive(Default)]
+struct Histogram {
+    buckets: [u64; BUCKETS.len()],
+    sum: f64,
+    count: u64,
+}
+
+/// Pushes each sample as a DogStatsD datagram; labels become tags.
+struct StatsdSink {
+    socket: UdpSocket,
+    prefix: String,
+    tags: Vec<String>,
+}
+
+impl StatsdSink {
+    fn send(&self, name: &str, value: &str, kind: &str, labels: &[(&'static str, String)]) {
+        let mut line = format!("{}.{}:{}|{}", self.prefix, name, value, kind);
+        let tags: Vec<String> = self
+            .tags
+            .iter()
+            .cloned()
+            .chain(labels.iter().map(|(k, v)| format!("{}:{}", k, v)))
+            .collect();
+        if !tags.is_empty() {
+            line.push_str("|#");
+            line.push_str(&tags.join(","));
+        }
+        // Metrics are best-effort: a full buffer or absent collector never fails a request
+        let _ = self.socket.send(line.as_bytes());
+    }
+}
+
+/// Counters, gauges and latency histograms, served in Prometheus text format by `GET /metrics` and,
+/// when `CEDAR_STATSD_ADDR` is set, also pushed to a StatsD/DogStatsD collector.
+pub struct Metrics {
+    counters: Mutex<BTreeMap<Key, u64>>,
+    gauges: Mutex<BTreeMap<Key, f64>>,
+    histograms: Mutex<BTreeMap<Key, Histogram>>,
+    statsd: Option<StatsdSink>,
+}
+
+impl Metrics {
+    pub fn new(config: &Config) -> Result<Self, Box<dyn std::error::Error>> {
+        let statsd = match config.statsd_addr {
+            Some(ref addr) => {
+                let socket = UdpSocket::bind("0.0.0.0:0")
+                    .map_err(|e| format!("Failed to open StatsD socket: {}", e))?;
+                socket
+                    .connect(addr)
+                    .map_err(|e| format!("Failed to resolve StatsD address {}: {}", addr, e))?;
+                socket.set_nonblocking(true)?;
+                log::info!("Pushing metrics to StatsD at {}", addr);
+                Some(StatsdSink {
+                    socket,
+                    prefix: config.statsd_prefix.clone(),
+                    tags: config.statsd_tags.clone(),
+                })
+            }
+            None => None,
+        };
+
+        Ok(Self {
+            counters: Mutex::new(BTreeMap::new()),
+            gauges: Mutex::new(BTreeMap::new()),
+            histograms: Mutex::new(BTreeMap::new()),
+            statsd,
+        })
+    }
+
+    /// Increments a counter by one.
+    pub fn incr(&self, name: &'static str, labels: &[(&'static str, &str)]) {
+        let labels = owned(labels);
+        if let Some(ref statsd) = self.statsd {
+            statsd.send(name, "1", "c", &labels);
+        }
+        *self.counters.lock().unwrap().entry((name, labels)).or_default() += 1;
+    }
+
+    /// Sets a gauge to its current value.
+    pub fn gauge(&self, name: &'static str, labels: &[(&'static str, &str)], value: f64) {
+        let labels = owned(labels);
+        if let Some(ref statsd) = self.statsd {
+            statsd.send(name, &value.to_string(), "g", &labels);
+        }
+        self.gauges.lock().unwrap().insert((name, labels), value);
+    }
+
+    /// Records a duration in a histogram (a `ms` timing for StatsD).
+    pub fn observe(&self, name: &'static str, labels: &[(&'static str, &str)], elapsed: Duration) {
+        let labels = owned(labels);
+        if let Some(ref statsd) = self.statsd {
+            statsd.send(name, &format!("{:.3}", elapsed.as_secs_f64() * 1000.0), "ms", &labels);
+        }
+
+        let seconds = elapsed.as_secs_f64();
+        let mut histograms = self.histograms.lock().unwrap();
+        let histogram = histograms.entry((name, labels)).or_default();
+        for (bucket, bound) in histogram.buckets.iter_mut().zip(BUCKETS) {
+            if seconds <= bound {
+                *bucket += 1;
+            }
+        }
+        histogram.sum += seconds;
+        histogram.count += 1;
+    }
+
+    /// Renders every metric in the Prometheus text exposition format.
+    pub fn render_prometheus(&self) -> String {
+        let mut out = String::new();
+
+        let mut last = "";
+        for ((name, labels), value) in self.counters.lock().unwrap().iter() {
+            if *name != last {
+                let _ = writeln!(out, "# TYPE cedar_agent_{}_total counter", name);
+                last = name;
+            }
+            let _ = writeln!(out, "cedar_agent_{}_total{} {}", name, render_labels(labels, None), value);
+        }
+
+        let mut last = "";
+        for ((name, labels), value) in self.gauges.lock().unwrap().iter() {
+            if *name != last {
+                let _ = writeln!(out, "# TYPE cedar_agent_{} gauge", name);
+                last = name;
+            }
+            let _ = writeln!(out, "cedar_agent_{}{} {}", name, render_labels(labels, None), value);
+        }
+
+        let mut last = "";
+        for ((name, labels), histogram) in self.histograms.lock().unwrap().iter() {
+            if *name != last {
+                let _ = writeln!(out, "# TYPE cedar_agent_{}_seconds histogram", name);
+                last = name;
+            }
+            for (count, bound) in histogram.buckets.iter().zip(BUCKETS) {
+                let le = bound.to_string();
+                let _ = writeln!(
+                    out,
+                    "cedar_agent_{}_seconds_bucket{} {}",
+                    name,
+                    render_labels(labels, Some(&le)),
+                    count
+                );
+            }
+            let _ = writeln!(
+                out,
+                "cedar_agent_{}_seconds_bucket{} {}",
+                name,
+                render_labels(labels, Some("+Inf")),
+                histogram.count
+            );
+            let _ = writeln!(out, "cedar_agent_{}_seconds_sum{} {}", name, render_labels(labels, None), histogram.sum);
+            let _ = writeln!(out, "cedar_agent_{}_seconds_count{} {}", name, render_labels(labels, None), histogram.count);
+        }
+
+        out
+    }
+}
+
+fn owned(labels: &[(&'static str, &str)]) -> Vec<(&'static str, String)> {
+    labels.iter().map(|(k, v)| (*k, v.to_string())).collect()
+}
+
+fn render_labels(labels: &[(&'static str, String)], le: Option<&str>) -> String {
+    let mut parts: Vec<String> = labels
+        .iter()
+        .map(|(k, v)| format!("{}=\"{}\"", k, v.replace('\\', "\\\\").replace('"', "\\\"")))
+        .collect();
+    if let Some(le) = le {
+        parts.push(format!("le=\"{}\"", le));
+    }
+    if parts.is_empty() {
+        String::new()
+    } else {
+        format!("{{{}}}", parts.join(","))
+    }
+}
@@ -0,0 +1,12 @@
+use hyper::{Body, Response};
+
+/// The playground page, embedded at build time so the binary needs no asset directory.
+static INDEX: &str = include_str!("../assets/playground.html");
+
+/// Serves the single-page playground for `GET /playground`.
+pub fn page() -> Response<Body> {
+    Response::builder()
+        .header("content-type", "text/html; charset=utf-8")
+        .body(Body::from(INDEX))
+        .unwrap()
+}
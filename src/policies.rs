@@ -0,0 +1,436 @@
+use cedar_policy::{
+    ActionConstraint, Effect, Entities, EntityUid, Policy, PolicyId, PolicySet, PrincipalConstraint,
+    ResourceConstraint,
+};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::Path;
+
+/// The effective policy set plus, for every policy and template, the file it came from.
+#[derive(Default)]
+pub struct LoadedPolicies {
+    pub policy_set: PolicySet,
+    pub sources: HashMap<PolicyId, String>,
+    pub layered: bool,
+}
+
+impl LoadedPolicies {
+    /// A policy set that came from one place, such as a proposal, as the source of every policy
+    /// and template.
+    pub fn from_set(policy_set: PolicySet, source: &str) -> Self {
+        let sources = policy_set
+            .policies()
+            .map(|p| p.id().clone())
+            .chain(policy_set.templates().map(|t| t.id().clone()))
+            .map(|id| (id, source.to_string()))
+            .collect();
+        Self {
+            policy_set,
+            sources,
+            layered: false,
+        }
+    }
+}
+
+/// Expands an overlay entry: a directory contributes its `*.cedar` files in name order.
+pub fn expand_overlay(path: &str) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    expand_files(path, "cedar")
+}
+
+/// A file as itself, or a directory as its files with `extension`, in name order.
+pub fn expand_files(path: &str, extension: &str) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    if !Path::new(path).is_dir() {
+        return Ok(vec![path.to_string()]);
+    }
+
+    let mut files: Vec<String> = fs::read_dir(path)
+        .map_err(|e| format!("Failed to read directory {}: {}", path, e))?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|p| p.extension().is_some_and(|ext| ext == extension))
+        .map(|p| p.to_string_lossy().into_owned())
+        .collect();
+    files.sort();
+    Ok(files)
+}
+
+/// Annotation that names a policy or template: `@id("staff-manage-branch-products")`.
+pub const ID_ANNOTATION: &str = "id";
+
+/// Parses Cedar policy text. Policies and templates annotated with `@id` get that ID, which
+/// stays the same when policies are added, removed or reordered; the others keep the
+/// positional ID Cedar assigns (`policy0`, `policy1`, ...). Two policies with the same ID, or
+/// a malformed `@effective_from`/`@expires_at` window, are an error.
+pub fn parse(src: &str) -> Result<PolicySet, String> {
+    let parsed = src.parse::<PolicySet>().map_err(|e| e.to_string())?;
+    crate::schedule::check(&parsed)?;
+    let annotated = parsed.policies().any(|p| p.annotation(ID_ANNOTATION).is_some())
+        || parsed.templates().any(|t| t.annotation(ID_ANNOTATION).is_some());
+    if !annotated {
+        return Ok(parsed);
+    }
+
+    let mut policy_set = PolicySet::new();
+    for template in parsed.templates() {
+        let id = annotated_id(template.annotation(ID_ANNOTATION), template.id())?;
+        policy_set
+            .add_template(template.new_id(id))
+            .map_err(|e| e.to_string())?;
+    }
+    for policy in parsed.policies() {
+        let id = annotated_id(policy.annotation(ID_ANNOTATION), policy.id())?;
+        policy_set.add(policy.new_id(id)).map_err(|e| e.to_string())?;
+    }
+    Ok(policy_set)
+}
+
+fn annotated_id(annotation: Option<&str>, positional: &PolicyId) -> Result<PolicyId, String> {
+    match annotation {
+        Some(id) if id.trim().is_empty() => Err(format!("Empty @id on {}", positional)),
+        Some(id) => Ok(PolicyId::new(id)),
+        None => Ok(positional.clone()),
+    }
+}
+
+fn parse_policy_file(path: &str) -> Result<PolicySet, Box<dyn std::error::Error>> {
+    let policy_src = fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read policy file {}: {}", path, e))?;
+    let policy_set = parse(&policy_src)
+        .map_err(|e| format!("Failed to parse policies in {}: {}", path, e))?;
+    Ok(policy_set)
+}
+
+/// Loads the base policy file and composes the overlays on top of it, in order.
+///
+/// Base policies keep their `@id` or the ID Cedar assigns them. Overlay policies without an
+/// `@id` are namespaced by file stem (`team-payments/policy0`) so positional IDs from different
+/// files cannot collide; a clash that remains (two overlays with the same file name, or the same
+/// `@id` in two files) is an error rather than a silent rename.
+pub fn load(base: &str, overlays: &[String]) -> Result<LoadedPolicies, Box<dyn std::error::Error>> {
+    let mut policy_set = parse_policy_file(base)?;
+    let mut sources: HashMap<PolicyId, String> = policy_set
+        .policies()
+        .map(|p| p.id().clone())
+        .chain(policy_set.templates().map(|t| t.id().clone()))
+        .map(|id| (id, base.to_string()))
+        .collect();
+
+    let mut layered = false;
+    for overlay in overlays {
+        for path in expand_overlay(overlay)? {
+            log::info!("Loading policy overlay from: {}", path);
+            let layer = parse_policy_file(&path)?;
+            let stem = Path::new(&path)
+                .file_stem()
+                .map(|s| s.to_string_lossy().into_owned())
+                .unwrap_or_else(|| path.clone());
+            let namespaced = |id: &PolicyId, annotated: bool| {
+                if annotated {
+                    id.clone()
+                } else {
+                    PolicyId::new(format!("{}/{}", stem, id))
+                }
+            };
+
+            for template in layer.templates() {
+                let id = namespaced(template.id(), template.annotation(ID_ANNOTATION).is_some());
+                policy_set
+                    .add_template(template.new_id(id.clone()))
+                    .map_err(|e| format!("Failed to add template from {}: {}", path, e))?;
+                sources.insert(id, path.clone());
+            }
+            for policy in layer.policies() {
+                let id = namespaced(policy.id(), policy.annotation(ID_ANNOTATION).is_some());
+                policy_set
+                    .add(policy.new_id(id.clone()))
+                    .map_err(|e| format!("Failed to add policy from {}: {}", path, e))?;
+                sources.insert(id, path.clone());
+            }
+            layered = true;
+        }
+    }
+
+    Ok(LoadedPolicies {
+        policy_set,
+        sources,
+        layered,
+    })
+}
+
+/// Annotation listing what the caller must do to enforce a decision the policy determines,
+/// e.g. `@obligation("require_mfa")`.
+pub const OBLIGATION_ANNOTATION: &str = "obligation";
+/// Annotation listing what the caller may act on, e.g. `@advice("notify=security")`.
+pub const ADVICE_ANNOTATION: &str = "advice";
+
+/// One entry of a determining policy's `@obligation` or `@advice` annotation.
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct Directive {
+    pub id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub value: Option<String>,
+    /// The policy that carries it.
+    pub policy: String,
+}
+
+/// The directives in `policy`'s annotation `key`: a comma-separated list of `name` or
+/// `name=value` entries, such as `@obligation("require_mfa, mask=ssn")`.
+pub fn directives(policy: &Policy, key: &str) -> Vec<Directive> {
+    let Some(annotation) = policy.annotation(key) else {
+        return Vec::new();
+    };
+    annotation
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| {
+            let (id, value) = match entry.split_once('=') {
+                Some((id, value)) => (id.trim(), Some(value.trim().to_string())),
+                None => (entry, None),
+            };
+            Directive {
+                id: id.to_string(),
+                value,
+                policy: policy.id().to_string(),
+            }
+        })
+        .collect()
+}
+
+/// A policy as reported by `GET /v1/policies`.
+#[derive(Debug, Serialize)]
+pub struct PolicySummary {
+    pub id: String,
+    pub effect: &'static str,
+    pub annotations: BTreeMap<String, String>,
+    /// File the policy was loaded from.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+    /// Template the policy was linked from, for template-linked policies.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub template_id: Option<String>,
+    /// The policy as written in its source file.
+    pub policy: String,
+    /// Whether the policy is disabled and takes no part in decisions.
+    pub disabled: bool,
+}
+
+impl PolicySummary {
+    pub fn new(policy: &Policy, sources: &HashMap<PolicyId, String>) -> Self {
+        let source_id = policy.template_id().unwrap_or(policy.id());
+        Self {
+            id: policy.id().to_string(),
+            effect: match policy.effect() {
+                Effect::Permit => "permit",
+                Effect::Forbid => "forbid",
+            },
+            annotations: policy
+                .annotations()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+            source: sources.get(source_id).cloned(),
+            template_id: policy.template_id().map(|id| id.to_string()),
+            policy: policy.to_string(),
+            disabled: false,
+        }
+    }
+}
+
+/// The policy ID and whether to disable it, for `/v1/policies/{id}/disable` and
+/// `/v1/policies/{id}/enable`.
+pub fn toggle(path: &str) -> Option<(&str, bool)> {
+    let rest = path.strip_prefix("/v1/policies/")?;
+    let (id, disable) = match rest.rsplit_once('/')? {
+        (id, "disable") => (id, true),
+        (id, "enable") => (id, false),
+        _ => return None,
+    };
+    (!id.is_empty()).then_some((id, disable))
+}
+
+/// Values to match policy heads against; `None` matches any constraint.
+#[derive(Debug, Default)]
+pub struct ScopeQuery {
+    pub principal: Option<EntityUid>,
+    pub action: Option<EntityUid>,
+    pub resource: Option<EntityUid>,
+    /// Annotations the policy must carry.
+    pub label: Option<crate::labels::Selector>,
+}
+
+/// `uid in group`, using the hierarchy in `entities` (an entity is always `in` itself).
+fn is_in(entities: &Entities, uid: &EntityUid, group: &EntityUid) -> bool {
+    uid == group || entities.is_ancestor_of(group, uid)
+}
+
+fn principal_matches(constraint: PrincipalConstraint, uid: &EntityUid, entities: &Entities) -> bool {
+    match constraint {
+        PrincipalConstraint::Any => true,
+        PrincipalConstraint::Eq(e) => &e == uid,
+        PrincipalConstraint::In(group) => is_in(entities, uid, &group),
+        PrincipalConstraint::Is(ty) => uid.type_name() == &ty,
+        PrincipalConstraint::IsIn(ty, group) => uid.type_name() == &ty && is_in(entities, uid, &group),
+    }
+}
+
+fn resource_matches(constraint: ResourceConstraint, uid: &EntityUid, entities: &Entities) -> bool {
+    match constraint {
+        ResourceConstraint::Any => true,
+        ResourceConstraint::Eq(e) => &e == uid,
+        ResourceConstraint::In(group) => is_in(entities, uid, &group),
+        ResourceConstraint::Is(ty) => uid.type_name() == &ty,
+        ResourceConstraint::IsIn(ty, group) => uid.type_name() == &ty && is_in(entities, uid, &group),
+    }
+}
+
+/// Whether an action scope matches the action `uid`, using the action-group hierarchy in `entities`.
+pub fn action_matches(constraint: ActionConstraint, uid: &EntityUid, entities: &Entities) -> bool {
+    match constraint {
+        ActionConstraint::Any => true,
+        ActionConstraint::Eq(e) => &e == uid,
+        ActionConstraint::In(groups) => groups.iter().any(|group| is_in(entities, uid, group)),
+    }
+}
+
+/// Whether the policy's scope (head) could match a request with the queried values. Conditions
+/// are not evaluated, so a match means "may apply", not "applies". `entities` supplies the
+/// membership hierarchy for `in` constraints; `actions` the action-group hierarchy.
+pub fn scope_matches(policy: &Policy, query: &ScopeQuery, entities: &Entities, actions: &Entities) -> bool {
+    query
+        .principal
+        .as_ref()
+        .is_none_or(|p| principal_matches(policy.principal_constraint(), p, entities))
+        && query
+            .action
+            .as_ref()
+            .is_none_or(|a| action_matches(policy.action_constraint(), a, actions))
+        && query
+            .resource
+            .as_ref()
+            .is_none_or(|r| resource_matches(policy.resource_constraint(), r, entities))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(src: &str) -> Policy {
+        Policy::parse(None, src).unwrap()
+    }
+
+    fn uid(s: &str) -> EntityUid {
+        s.parse().unwrap()
+    }
+
+    fn entities() -> Entities {
+        Entities::from_json_value(
+            serde_json::json!([
+                {"uid": {"type": "User", "id": "alice"}, "attrs": {}, "parents": [{"type": "Group", "id": "ops"}]},
+                {"uid": {"type": "Group", "id": "ops"}, "attrs": {}, "parents": [{"type": "Group", "id": "admins"}]},
+                {"uid": {"type": "Group", "id": "admins"}, "attrs": {}, "parents": []},
+                {"uid": {"type": "Doc", "id": "runbook"}, "attrs": {}, "parents": [{"type": "Group", "id": "ops"}]},
+                {"uid": {"type": "Action", "id": "read"}, "attrs": {}, "parents": [{"type": "Action", "id": "view"}]},
+                {"uid": {"type": "Action", "id": "view"}, "attrs": {}, "parents": []}
+            ]),
+            None,
+        )
+        .unwrap()
+    }
+
+    fn query(principal: Option<&str>, action: Option<&str>, resource: Option<&str>) -> ScopeQuery {
+        ScopeQuery {
+            principal: principal.map(uid),
+            action: action.map(uid),
+            resource: resource.map(uid),
+            label: None,
+        }
+    }
+
+    #[test]
+    fn reads_directives_from_annotations() {
+        let p = policy(r#"@obligation("require_mfa, mask = ssn,") @advice("") permit(principal, action, resource);"#);
+        let directive = |id: &str, value: Option<&str>| Directive {
+            id: id.to_string(),
+            value: value.map(str::to_string),
+            policy: "policy0".to_string(),
+        };
+        assert_eq!(
+            directives(&p, OBLIGATION_ANNOTATION),
+            vec![directive("require_mfa", None), directive("mask", Some("ssn"))]
+        );
+        assert!(directives(&p, ADVICE_ANNOTATION).is_empty());
+    }
+
+    #[test]
+    fn names_policies_by_their_id_annotation() {
+        let src = r#"
+            @id("allow-owners") permit (principal, action, resource) when { principal == resource.owner };
+            permit (principal == User::"root", action, resource);
+            @id("edit-template") permit (principal == ?principal, action, resource);
+        "#;
+        let set = parse(src).unwrap();
+        let mut ids: Vec<_> = set.policies().map(|p| p.id().to_string()).collect();
+        ids.sort();
+        assert_eq!(ids, ["allow-owners", "policy1"]);
+        assert!(set.template(&PolicyId::new("edit-template")).is_some());
+
+        // Without annotations Cedar's positional IDs are kept
+        let set = parse("permit (principal, action, resource);").unwrap();
+        assert_eq!(set.policies().next().unwrap().id().to_string(), "policy0");
+
+        let duplicate = r#"@id("a") permit (principal, action, resource); @id("a") forbid (principal, action, resource);"#;
+        assert!(parse(duplicate).is_err());
+        // An @id may not take the positional ID of another policy
+        assert!(parse(r#"@id("policy1") permit (principal, action, resource); permit (principal, action, resource);"#).is_err());
+        assert!(parse(r#"@id(" ") permit (principal, action, resource);"#).is_err());
+    }
+
+    #[test]
+    fn empty_query_matches_everything() {
+        let p = policy(r#"permit (principal == User::"bob", action, resource);"#);
+        assert!(scope_matches(&p, &query(None, None, None), &entities(), &entities()));
+    }
+
+    #[test]
+    fn principal_equality_and_membership() {
+        let entities = entities();
+        let eq = policy(r#"permit (principal == User::"alice", action, resource);"#);
+        assert!(scope_matches(&eq, &query(Some(r#"User::"alice""#), None, None), &entities, &entities));
+        assert!(!scope_matches(&eq, &query(Some(r#"User::"bob""#), None, None), &entities, &entities));
+
+        let transitive = policy(r#"permit (principal in Group::"admins", action, resource);"#);
+        assert!(scope_matches(&transitive, &query(Some(r#"User::"alice""#), None, None), &entities, &entities));
+        assert!(!scope_matches(&transitive, &query(Some(r#"User::"bob""#), None, None), &entities, &entities));
+    }
+
+    #[test]
+    fn action_groups_use_action_hierarchy() {
+        let entities = entities();
+        let p = policy(r#"permit (principal, action in [Action::"view"], resource);"#);
+        assert!(scope_matches(&p, &query(None, Some(r#"Action::"read""#), None), &entities, &entities));
+        assert!(!scope_matches(&p, &query(None, Some(r#"Action::"delete""#), None), &entities, &entities));
+        assert!(!scope_matches(&p, &query(None, Some(r#"Action::"read""#), None), &entities, &Entities::empty()));
+    }
+
+    #[test]
+    fn resource_type_constraints() {
+        let entities = entities();
+        let p = policy(r#"permit (principal, action, resource is Doc in Group::"ops");"#);
+        assert!(scope_matches(&p, &query(None, None, Some(r#"Doc::"runbook""#)), &entities, &entities));
+        assert!(!scope_matches(&p, &query(None, None, Some(r#"Doc::"d""#)), &entities, &entities));
+        assert!(!scope_matches(&p, &query(None, None, Some(r#"Group::"ops""#)), &entities, &entities));
+
+        let p = policy(r#"permit (principal, action, resource is Doc);"#);
+        assert!(scope_matches(&p, &query(None, None, Some(r#"Doc::"d""#)), &entities, &entities));
+    }
+
+    #[test]
+    fn parses_enable_and_disable_paths() {
+        assert_eq!(toggle("/v1/policies/admin-console/disable"), Some(("admin-console", true)));
+        assert_eq!(toggle("/v1/policies/teams%2Fpolicy0/enable"), Some(("teams%2Fpolicy0", false)));
+        assert_eq!(toggle("/v1/policies//disable"), None);
+        assert_eq!(toggle("/v1/policies/disable"), None);
+        assert_eq!(toggle("/v1/policies/admin-console/delete"), None);
+    }
+
+}
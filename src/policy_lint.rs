@@ -0,0 +1,289 @@
+use crate::config::Config;
+use crate::policies::{self, ID_ANNOTATION};
+use cedar_policy::{ActionConstraint, Effect, PolicySet, PrincipalConstraint, ResourceConstraint};
+use serde::Serialize;
+use serde_json::Value;
+use std::str::FromStr;
+
+/// How a rule's findings count: an `error` fails the lint, a `warning` does not, and `off`
+/// skips the rule.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Error,
+    Warning,
+    Off,
+}
+
+impl FromStr for Severity {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "error" => Ok(Severity::Error),
+            "warning" => Ok(Severity::Warning),
+            "off" => Ok(Severity::Off),
+            other => Err(format!("Invalid lint severity '{}' (expected error, warning or off)", other)),
+        }
+    }
+}
+
+/// Annotation naming who is responsible for a policy: `@owner("payments-team")`.
+const OWNER_ANNOTATION: &str = "owner";
+
+/// The rules and the severity each has unless `CEDAR_POLICY_LINT` changes it.
+pub const RULES: &[(&str, Severity)] = &[
+    // No `@id`, so the policy's ID changes when policies are added or reordered
+    ("missing-id", Severity::Warning),
+    // No `@owner` naming who to ask about the policy
+    ("missing-owner", Severity::Warning),
+    // An `@id` that is not kebab-case (`staff-manage-products`)
+    ("id-naming", Severity::Warning),
+    // `permit(principal, action, resource);`, which allows everything
+    ("permit-all", Severity::Error),
+    // `forbid(principal, action, resource);`, which denies everything
+    ("forbid-all", Severity::Error),
+    // `when { true }` or `unless { false }`, a condition that changes nothing
+    ("trivial-condition", Severity::Warning),
+];
+
+/// The severity of every rule.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rules {
+    severities: Vec<(&'static str, Severity)>,
+}
+
+impl Default for Rules {
+    fn default() -> Self {
+        Rules {
+            severities: RULES.to_vec(),
+        }
+    }
+}
+
+impl Rules {
+    /// The default severities, changed by `rule=severity` entries such as `missing-owner=error`.
+    pub fn parse(entries: &[String]) -> Result<Self, String> {
+        let mut rules = Rules::default();
+        for entry in entries {
+            let (rule, severity) = entry
+                .split_once('=')
+                .ok_or_else(|| format!("Invalid CEDAR_POLICY_LINT entry '{}' (expected rule=severity)", entry))?;
+            let slot = rules
+                .severities
+                .iter_mut()
+                .find(|(name, _)| *name == rule.trim())
+                .ok_or_else(|| format!("Unknown lint rule '{}'", rule.trim()))?;
+            slot.1 = severity.trim().parse()?;
+        }
+        Ok(rules)
+    }
+
+    fn severity(&self, rule: &str) -> Severity {
+        self.severities
+            .iter()
+            .find(|(name, _)| *name == rule)
+            .map_or(Severity::Off, |(_, severity)| *severity)
+    }
+}
+
+#[derive(Debug, Serialize, PartialEq)]
+pub struct LintFinding {
+    /// ID of the policy or template.
+    pub policy: String,
+    pub rule: &'static str,
+    pub severity: Severity,
+    pub message: String,
+}
+
+/// Response of `/v1/policies/lint` and output of `cedar-agent lint`.
+#[derive(Debug, Serialize)]
+pub struct LintReport {
+    /// Whether no finding is an error.
+    pub valid: bool,
+    pub findings: Vec<LintFinding>,
+}
+
+/// Whether `id` is kebab-case: lowercase letters and digits in words joined by single hyphens.
+fn kebab_case(id: &str) -> bool {
+    id.split('-').all(|word| {
+        !word.is_empty() && word.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit())
+    }) && id.starts_with(|c: char| c.is_ascii_lowercase())
+}
+
+/// Whether a condition of the JSON policy format can only ever be met: `when { true }` or
+/// `unless { false }`.
+fn trivial(condition: &Value) -> bool {
+    let expected = condition["kind"] == "when";
+    condition["body"]["Value"].as_bool() == Some(expected)
+}
+
+/// What is checked of a policy or template.
+struct Linted<'a> {
+    id: String,
+    id_annotation: Option<&'a str>,
+    owner: Option<&'a str>,
+    json: Option<Value>,
+    /// The effect, when the scope is unconstrained.
+    unconstrained: Option<Effect>,
+}
+
+fn lint_one(linted: Linted<'_>, rules: &Rules, findings: &mut Vec<LintFinding>) {
+    let mut report = |rule: &'static str, message: String| {
+        let severity = rules.severity(rule);
+        if severity != Severity::Off {
+            findings.push(LintFinding {
+                policy: linted.id.clone(),
+                rule,
+                severity,
+                message,
+            });
+        }
+    };
+
+    match linted.id_annotation {
+        None => report("missing-id", "has no @id annotation".to_string()),
+        Some(id) if !kebab_case(id) => report("id-naming", format!("@id(\"{}\") is not kebab-case", id)),
+        Some(_) => {}
+    }
+    if linted.owner.is_none() {
+        report("missing-owner", "has no @owner annotation".to_string());
+    }
+
+    let conditions: Vec<&Value> = linted
+        .json
+        .as_ref()
+        .and_then(|json| json["conditions"].as_array())
+        .map(|conditions| conditions.iter().collect())
+        .unwrap_or_default();
+    let trivial_conditions = conditions.iter().filter(|condition| trivial(condition)).count();
+    if trivial_conditions > 0 {
+        report(
+            "trivial-condition",
+            "has a condition that is always met (when { true } or unless { false })".to_string(),
+        );
+    }
+    // Conditions that are always met leave the policy as broad as its scope
+    if trivial_conditions == conditions.len() {
+        match linted.unconstrained {
+            Some(Effect::Permit) => report("permit-all", "permits every principal every action on every resource".to_string()),
+            Some(Effect::Forbid) => report("forbid-all", "forbids every principal every action on every resource".to_string()),
+            None => {}
+        }
+    }
+}
+
+/// Checks the static policies and the templates of `policy_set`; template-linked policies are
+/// checked as their template.
+pub fn lint(policy_set: &PolicySet, rules: &Rules) -> LintReport {
+    let mut findings = Vec::new();
+    for policy in policy_set.policies().filter(|policy| policy.is_static()) {
+        let unconstrained = (policy.principal_constraint() == PrincipalConstraint::Any
+            && policy.action_constraint() == ActionConstraint::Any
+            && policy.resource_constraint() == ResourceConstraint::Any)
+            .then(|| policy.effect());
+        let linted = Linted {
+            id: policy.id().to_string(),
+            id_annotation: policy.annotation(ID_ANNOTATION),
+            owner: policy.annotation(OWNER_ANNOTATION),
+            json: policy.to_json().ok(),
+            unconstrained,
+        };
+        lint_one(linted, rules, &mut findings);
+    }
+    for template in policy_set.templates() {
+        let linted = Linted {
+            id: template.id().to_string(),
+            id_annotation: template.annotation(ID_ANNOTATION),
+            owner: template.annotation(OWNER_ANNOTATION),
+            json: template.to_json().ok(),
+            // A template constrains the principal or the resource to a slot
+            unconstrained: None,
+        };
+        lint_one(linted, rules, &mut findings);
+    }
+    LintReport {
+        valid: !findings.iter().any(|finding| finding.severity == Severity::Error),
+        findings,
+    }
+}
+
+/// `cedar-agent lint [<policy files>...]`: lints the given policy files, or the configured
+/// policies (`CEDAR_POLICY_PATH` with its overlays), with the rules of `CEDAR_POLICY_LINT`.
+/// Prints the report as JSON and exits nonzero if a finding is an error.
+pub fn run(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    if let Some(flag) = args.iter().find(|arg| arg.starts_with('-')) {
+        return Err(format!("Unknown lint argument: {}\nUsage: cedar-agent lint [<policy files>...]", flag).into());
+    }
+    let config = Config::from_env()?;
+    let policy_set = if args.is_empty() {
+        policies::load(&config.policy_path, &config.policy_overlays)?.policy_set
+    } else {
+        policies::load(&args[0], &args[1..])?.policy_set
+    };
+
+    let report = lint(&policy_set, &config.policy_lint);
+    println!("{}", serde_json::to_string_pretty(&report)?);
+    if !report.valid {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn findings(text: &str, rules: &Rules) -> Vec<(String, &'static str, Severity)> {
+        let policy_set = policies::parse(text).unwrap();
+        lint(&policy_set, rules)
+            .findings
+            .into_iter()
+            .map(|finding| (finding.policy, finding.rule, finding.severity))
+            .collect()
+    }
+
+    #[test]
+    fn applies_each_rule() {
+        let text = r#"
+            @id("read-docs") @owner("docs-team") permit (principal, action == Action::"read", resource);
+            @id("AllowEverything") @owner("ops") permit (principal, action, resource) when { true };
+            forbid (principal, action, resource) unless { false };
+            @id("audit") @owner("sec") forbid (principal, action, resource) when { context.suspended };
+            @id("viewers") @owner("docs-team") permit (principal == ?principal, action, resource);
+        "#;
+        let found = findings(text, &Rules::default());
+        let expected = [
+            ("AllowEverything", "id-naming", Severity::Warning),
+            ("AllowEverything", "trivial-condition", Severity::Warning),
+            ("AllowEverything", "permit-all", Severity::Error),
+            ("policy2", "missing-id", Severity::Warning),
+            ("policy2", "missing-owner", Severity::Warning),
+            ("policy2", "trivial-condition", Severity::Warning),
+            ("policy2", "forbid-all", Severity::Error),
+        ];
+        assert_eq!(
+            found,
+            expected.map(|(policy, rule, severity)| (policy.to_string(), rule, severity)).to_vec()
+        );
+        assert!(!lint(&policies::parse(text).unwrap(), &Rules::default()).valid);
+    }
+
+    #[test]
+    fn severities_are_configurable() {
+        let rules = Rules::parse(&["missing-owner=error".to_string(), " trivial-condition = off ".to_string()]).unwrap();
+        let found = findings(r#"@id("a") permit (principal == User::"x", action, resource) when { true };"#, &rules);
+        assert_eq!(found, vec![("a".to_string(), "missing-owner", Severity::Error)]);
+        assert!(Rules::parse(&["no-such-rule=error".to_string()]).is_err());
+        assert!(Rules::parse(&["missing-id=fatal".to_string()]).is_err());
+        assert!(Rules::parse(&["missing-id".to_string()]).is_err());
+    }
+
+    #[test]
+    fn checks_kebab_case() {
+        assert!(kebab_case("staff-manage-products2"));
+        for id in ["Staff", "staff_products", "-staff", "staff--a", "staff-", "2fa-required", ""] {
+            assert!(!kebab_case(id), "{}", id);
+        }
+    }
+}
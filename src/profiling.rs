@@ -0,0 +1,142 @@
+use hyper::{Body, Response, StatusCode};
+use serde::Serialize;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use tikv_jemalloc_ctl::{epoch, stats};
+
+#[global_allocator]
+static GLOBAL: tikv_jemallocator::Jemalloc = tikv_jemallocator::Jemalloc;
+
+/// Set while a CPU profile is being collected; the sampler is process-wide.
+static PROFILING: AtomicBool = AtomicBool::new(false);
+
+/// Clears `PROFILING` when dropped. It lives in the sampling task, so the flag is released when
+/// sampling actually stops, even if the client disconnected and its request was dropped.
+struct ProfilingFlag;
+
+impl Drop for ProfilingFlag {
+    fn drop(&mut self) {
+        PROFILING.store(false, Ordering::SeqCst);
+    }
+}
+
+const DEFAULT_PROFILE_SECONDS: u64 = 10;
+const MAX_PROFILE_SECONDS: u64 = 60;
+
+/// Samples the CPU for `seconds` and returns either a pprof protobuf (for `go tool pprof`) or,
+/// with `flamegraph`, an SVG.
+pub async fn cpu_profile(seconds: Option<&str>, flamegraph: bool) -> Response<Body> {
+    let seconds = match seconds.map(str::parse::<u64>) {
+        None => DEFAULT_PROFILE_SECONDS,
+        Some(Ok(s)) if (1..=MAX_PROFILE_SECONDS).contains(&s) => s,
+        _ => {
+            return crate::error_response(
+                StatusCode::BAD_REQUEST,
+                format!("seconds must be between 1 and {}", MAX_PROFILE_SECONDS),
+            )
+        }
+    };
+    if PROFILING.swap(true, Ordering::SeqCst) {
+        return crate::error_response(StatusCode::CONFLICT, "A CPU profile is already being collected");
+    }
+
+    let flag = ProfilingFlag;
+
+    log::info!("Collecting {}s CPU profile", seconds);
+    let result = tokio::task::spawn_blocking(move || {
+        let _flag = flag;
+        collect(seconds, flamegraph)
+    })
+    .await;
+
+    match result {
+        Ok(Ok((content_type, body))) => Response::builder()
+            .header("content-type", content_type)
+            .body(Body::from(body))
+            .unwrap(),
+        Ok(Err(e)) => crate::error_response(StatusCode::INTERNAL_SERVER_ERROR, e),
+        Err(e) => crate::error_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Profiler task failed: {}", e),
+        ),
+    }
+}
+
+fn collect(seconds: u64, flamegraph: bool) -> Result<(&'static str, Vec<u8>), String> {
+    use pprof::protos::Message;
+
+    let guard = pprof::ProfilerGuardBuilder::default()
+        .frequency(99)
+        .blocklist(&["libc", "libgcc", "pthread", "vdso"])
+        .build()
+        .map_err(|e| format!("Failed to start profiler: {}", e))?;
+    std::thread::sleep(Duration::from_secs(seconds));
+    let report = guard
+        .report()
+        .build()
+        .map_err(|e| format!("Failed to build profile: {}", e))?;
+
+    let mut body = Vec::new();
+    if flamegraph {
+        report
+            .flamegraph(&mut body)
+            .map_err(|e| format!("Failed to render flamegraph: {}", e))?;
+        Ok(("image/svg+xml", body))
+    } else {
+        let profile = report.pprof().map_err(|e| format!("Failed to encode profile: {}", e))?;
+        profile
+            .write_to_vec(&mut body)
+            .map_err(|e| format!("Failed to encode profile: {}", e))?;
+        Ok(("application/octet-stream", body))
+    }
+}
+
+/// Allocator statistics from jemalloc, in bytes.
+#[derive(Debug, Serialize)]
+pub struct HeapStats {
+    pub allocated: usize,
+    pub active: usize,
+    pub resident: usize,
+    pub mapped: usize,
+    pub retained: usize,
+    pub metadata: usize,
+}
+
+pub fn heap_stats() -> Result<HeapStats, String> {
+    // jemalloc caches its statistics; advancing the epoch refreshes them
+    epoch::advance().map_err(|e| format!("Failed to refresh allocator stats: {}", e))?;
+    let read = |r: tikv_jemalloc_ctl::Result<usize>| r.map_err(|e| format!("Failed to read allocator stats: {}", e));
+    Ok(HeapStats {
+        allocated: read(stats::allocated::read())?,
+        active: read(stats::active::read())?,
+        resident: read(stats::resident::read())?,
+        mapped: read(stats::mapped::read())?,
+        retained: read(stats::retained::read())?,
+        metadata: read(stats::metadata::read())?,
+    })
+}
+
+/// Summary of the async runtime: worker count, live tasks and queued work.
+#[derive(Debug, Serialize)]
+pub struct TaskStats {
+    pub workers: usize,
+    pub alive_tasks: usize,
+    pub global_queue_depth: usize,
+    /// Per-worker park counts (how often each worker went idle) and busy time since startup.
+    pub worker_park_counts: Vec<u64>,
+    pub worker_busy_ms: Vec<u128>,
+}
+
+pub fn task_stats() -> TaskStats {
+    let metrics = tokio::runtime::Handle::current().metrics();
+    let workers = metrics.num_workers();
+    TaskStats {
+        workers,
+        alive_tasks: metrics.num_alive_tasks(),
+        global_queue_depth: metrics.global_queue_depth(),
+        worker_park_counts: (0..workers).map(|w| metrics.worker_park_count(w)).collect(),
+        worker_busy_ms: (0..workers)
+            .map(|w| metrics.worker_total_busy_duration(w).as_millis())
+            .collect(),
+    }
+}
@@ -0,0 +1,298 @@
+use crate::{audit, error_response, json_response, policies, reload, replay, schema};
+use crate::{CedarService, PolicyState};
+use chrono::{SecondsFormat, Utc};
+use hyper::{Body, Method, Response, StatusCode};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+pub const PATH: &str = "/v1/policies/proposals";
+/// Most proposals waiting for approval at once.
+const MAX_PENDING: usize = 100;
+/// Logged decisions a proposal is simulated against.
+const SIMULATED_DECISIONS: usize = 1000;
+
+#[derive(Debug, Deserialize)]
+struct ProposalRequest {
+    /// The complete policy set to activate, as Cedar text.
+    policies: String,
+    #[serde(default)]
+    description: Option<String>,
+}
+
+/// A policy change waiting for a second administrator to approve it.
+#[derive(Debug, Serialize)]
+struct Proposal {
+    id: String,
+    proposer: String,
+    created: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    description: Option<String>,
+    policies: String,
+    /// Digest of the active policies the change was proposed against; approving it after they
+    /// changed would undo the other change.
+    base: String,
+    changes: reload::PolicyDiff,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    warnings: Vec<String>,
+    /// Recent logged decisions that the change would flip; `None` without a decision log.
+    simulation: Option<replay::Report>,
+    #[serde(skip)]
+    policy_set: cedar_policy::PolicySet,
+}
+
+/// Policy changes proposed and waiting for approval, by ID.
+#[derive(Default)]
+pub struct Proposals {
+    pending: Mutex<BTreeMap<u64, Arc<Proposal>>>,
+    next: Mutex<u64>,
+}
+
+#[derive(Debug, Serialize)]
+struct Approval<'a> {
+    status: &'static str,
+    id: &'a str,
+    proposer: &'a str,
+    approver: &'a str,
+    changes: &'a reload::PolicyDiff,
+    data_version: u64,
+}
+
+impl Proposals {
+    /// Adds a proposal under the next ID; `None` when too many are pending.
+    fn add(&self, mut proposal: Proposal) -> Option<Arc<Proposal>> {
+        let mut pending = self.pending.lock().unwrap();
+        if pending.len() >= MAX_PENDING {
+            return None;
+        }
+        let mut next = self.next.lock().unwrap();
+        *next += 1;
+        proposal.id = next.to_string();
+        let proposal = Arc::new(proposal);
+        pending.insert(*next, Arc::clone(&proposal));
+        Some(proposal)
+    }
+
+    fn get(&self, id: &str) -> Option<Arc<Proposal>> {
+        self.pending.lock().unwrap().get(&id.parse().ok()?).cloned()
+    }
+
+    fn remove(&self, id: &str) -> Option<Arc<Proposal>> {
+        self.pending.lock().unwrap().remove(&id.parse().ok()?)
+    }
+
+    fn list(&self) -> Vec<Arc<Proposal>> {
+        self.pending.lock().unwrap().values().cloned().collect()
+    }
+}
+
+/// Why `approver` may not approve a proposal by `proposer`, if they may not. Both are
+/// administrator names: API keys neither propose nor approve.
+fn refuse_approval(proposer: &str, approver: Option<&str>) -> Option<String> {
+    match approver {
+        None => Some("Approving a proposal needs an administrator (CEDAR_ADMIN_KEYS or CEDAR_ADMIN_USERS)".to_string()),
+        Some(approver) if approver == proposer => {
+            Some(format!("Proposal was made by '{}' and needs another administrator's approval", proposer))
+        }
+        Some(_) => None,
+    }
+}
+
+/// The refusal of a policy change one caller makes alone, when `CEDAR_REQUIRE_APPROVAL` has
+/// every change go through a proposal. `change` names it, as in "Promoting the staging set".
+pub fn refuse_unapproved(service: &CedarService, change: &str) -> Option<Response<Body>> {
+    if !service.require_approval {
+        return None;
+    }
+    warn!(
+        "Refused: {} by {} without approval",
+        change.to_lowercase(),
+        audit::identified().as_deref().unwrap_or("an unidentified caller")
+    );
+    Some(error_response(
+        StatusCode::FORBIDDEN,
+        format!(
+            "{} needs a second administrator's approval (CEDAR_REQUIRE_APPROVAL); propose the change at {}",
+            change, PATH
+        ),
+    ))
+}
+
+/// Serves `/v1/policies/proposals`, `/v1/policies/proposals/{id}` and
+/// `/v1/policies/proposals/{id}/approve`.
+pub async fn handle(req: hyper::Request<Body>, service: Arc<CedarService>) -> Response<Body> {
+    if service.reload_sources.is_none() {
+        return error_response(
+            StatusCode::CONFLICT,
+            "Policies come from Verified Permissions; propose changes there",
+        );
+    }
+    let path = req.uri().path().to_string();
+    let rest = path[PATH.len()..].trim_start_matches('/');
+    match (req.method().clone(), rest.split_once('/')) {
+        (Method::GET, None) if rest.is_empty() => json_response(StatusCode::OK, &service.proposals.list()),
+        (Method::POST, None) if rest.is_empty() => propose(req, service).await,
+        (Method::GET, None) => match service.proposals.get(rest) {
+            Some(proposal) => json_response(StatusCode::OK, &proposal),
+            None => not_found(rest),
+        },
+        (Method::DELETE, None) => match service.proposals.remove(rest) {
+            Some(proposal) => {
+                info!(
+                    "Proposal {} by {} withdrawn by {}",
+                    proposal.id,
+                    proposal.proposer,
+                    audit::identified().as_deref().unwrap_or("an unidentified caller")
+                );
+                Response::builder().status(StatusCode::NO_CONTENT).body(Body::empty()).unwrap()
+            }
+            None => not_found(rest),
+        },
+        (Method::POST, Some((id, "approve"))) => approve(id, &service),
+        _ => error_response(StatusCode::NOT_FOUND, "Not found"),
+    }
+}
+
+fn not_found(id: &str) -> Response<Body> {
+    error_response(StatusCode::NOT_FOUND, format!("No pending proposal {}", id))
+}
+
+/// Stages a change after validating it against the active schema and simulating it against
+/// recent logged decisions.
+async fn propose(req: hyper::Request<Body>, service: Arc<CedarService>) -> Response<Body> {
+    let Some(proposer) = audit::administrator() else {
+        return error_response(
+            StatusCode::FORBIDDEN,
+            "Proposing a change needs an administrator (CEDAR_ADMIN_KEYS or CEDAR_ADMIN_USERS)",
+        );
+    };
+    let body = match crate::read_json::<ProposalRequest>(req).await {
+        Ok(body) => body,
+        Err(resp) => return resp,
+    };
+    let policy_set = match policies::parse(&body.policies) {
+        Ok(policy_set) => policy_set,
+        Err(e) => return error_response(StatusCode::BAD_REQUEST, format!("Failed to parse policies: {}", e)),
+    };
+    let state = service.state();
+    let warnings = match schema::check_candidate(state.schema.as_ref(), &policy_set, "Proposal") {
+        Ok(warnings) => warnings,
+        Err(e) => return json_response(StatusCode::BAD_REQUEST, &e),
+    };
+
+    let proposed = policies::LoadedPolicies::from_set(policy_set.clone(), "proposal");
+    let proposed = PolicyState {
+        disabled: state.disabled_in(&policy_set),
+        ..PolicyState::new(proposed, state.schema.clone(), state.schema_json.clone(), Arc::clone(&state.entities))
+    };
+    let simulation = match replay::simulate_recent(&service, proposed, SIMULATED_DECISIONS).await {
+        Ok(simulation) => simulation,
+        Err(e) => return error_response(StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to simulate: {}", e)),
+    };
+
+    let proposal = Proposal {
+        id: String::new(),
+        proposer,
+        created: Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+        description: body.description,
+        policies: body.policies,
+        base: audit::policies_hash(&state.policy_set),
+        changes: reload::diff(&state.policy_set, &policy_set),
+        warnings,
+        simulation,
+        policy_set,
+    };
+    match service.proposals.add(proposal) {
+        Some(proposal) => {
+            info!("Proposal {} by {} staged", proposal.id, proposal.proposer);
+            json_response(StatusCode::CREATED, &proposal)
+        }
+        None => error_response(
+            StatusCode::CONFLICT,
+            format!("{} proposals are already pending; approve or withdraw some first", MAX_PENDING),
+        ),
+    }
+}
+
+/// Activates a pending proposal on behalf of an administrator other than its proposer.
+fn approve(id: &str, service: &CedarService) -> Response<Body> {
+    let Some(proposal) = service.proposals.get(id) else {
+        return not_found(id);
+    };
+    let approver = audit::administrator();
+    if let Some(reason) = refuse_approval(&proposal.proposer, approver.as_deref()) {
+        warn!("Refused approval of proposal {}: {}", id, reason);
+        return error_response(StatusCode::FORBIDDEN, reason);
+    }
+    let approver = approver.unwrap_or_default();
+    // Taken so that two approvals cannot both activate it
+    let Some(proposal) = service.proposals.remove(id) else {
+        return not_found(id);
+    };
+    let loaded = policies::LoadedPolicies::from_set(proposal.policy_set.clone(), &format!("proposal {}", proposal.id));
+    match service.replace_policies("proposal.approve", loaded, None, Some(&proposal.base), Some(&proposal.policies)) {
+        Ok(_) => {
+            info!("Proposal {} by {} approved by {} and activated", proposal.id, proposal.proposer, approver);
+            json_response(
+                StatusCode::OK,
+                &Approval {
+                    status: "activated",
+                    id: &proposal.id,
+                    proposer: &proposal.proposer,
+                    approver: &approver,
+                    changes: &proposal.changes,
+                    data_version: service.data_version(),
+                },
+            )
+        }
+        Err(e) => {
+            warn!("Proposal {} could not be activated: {}", proposal.id, e);
+            json_response(StatusCode::CONFLICT, &e)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn proposal(proposer: &str) -> Proposal {
+        Proposal {
+            id: String::new(),
+            proposer: proposer.to_string(),
+            created: String::new(),
+            description: None,
+            policies: String::new(),
+            base: String::new(),
+            changes: reload::PolicyDiff::default(),
+            warnings: Vec::new(),
+            simulation: None,
+            policy_set: cedar_policy::PolicySet::new(),
+        }
+    }
+
+    #[test]
+    fn approval_needs_a_second_administrator() {
+        assert!(refuse_approval("alice", None).is_some());
+        assert!(refuse_approval("alice", Some("alice")).is_some());
+        assert_eq!(refuse_approval("alice", Some("bob")), None);
+    }
+
+    #[test]
+    fn keeps_pending_proposals_up_to_the_limit() {
+        let proposals = Proposals::default();
+        let first = proposals.add(proposal("alice")).unwrap();
+        assert_eq!(first.id, "1");
+        assert_eq!(proposals.get("1").unwrap().proposer, "alice");
+        assert!(proposals.get("2").is_none() && proposals.get("x").is_none());
+        for _ in 1..MAX_PENDING {
+            assert!(proposals.add(proposal("bob")).is_some());
+        }
+        assert!(proposals.add(proposal("carol")).is_none());
+        assert_eq!(proposals.remove("1").unwrap().proposer, "alice");
+        assert!(proposals.remove("1").is_none());
+        assert_eq!(proposals.add(proposal("carol")).unwrap().id, (MAX_PENDING + 1).to_string());
+        assert_eq!(proposals.list().len(), MAX_PENDING);
+    }
+}
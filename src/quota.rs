@@ -0,0 +1,334 @@
+use crate::digest;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::{Mutex, RwLock};
+
+pub const API_KEY_HEADER: &str = "x-api-key";
+
+const HOUR: u64 = 3600;
+const DAY: u64 = 86400;
+
+/// Name of the key a request was made with, attached to the request as an extension.
+#[derive(Clone)]
+pub struct Caller(pub String);
+
+/// Request limits of a key; `None` is unlimited.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Quota {
+    pub hourly: Option<u64>,
+    pub daily: Option<u64>,
+}
+
+struct Key {
+    name: String,
+    digest: [u8; 32],
+    quota: Quota,
+}
+
+/// Requests counted in the current hour and day, identified by their window numbers.
+#[derive(Default)]
+struct Counter {
+    hour: u64,
+    hourly: u64,
+    day: u64,
+    daily: u64,
+}
+
+impl Counter {
+    /// Starts new windows once the clock has moved past the counted ones.
+    fn roll(&mut self, now: u64) {
+        if self.hour != now / HOUR {
+            self.hour = now / HOUR;
+            self.hourly = 0;
+        }
+        if self.day != now / DAY {
+            self.day = now / DAY;
+            self.daily = 0;
+        }
+    }
+}
+
+/// A request refused because a quota is used up.
+#[derive(Debug, PartialEq)]
+pub struct Exceeded {
+    pub window: &'static str,
+    pub limit: u64,
+    /// Seconds until the window resets.
+    pub retry_after: u64,
+}
+
+#[derive(Debug, Serialize, PartialEq)]
+pub struct WindowUsage {
+    pub used: u64,
+    pub limit: Option<u64>,
+    /// Unix time the window resets at.
+    pub resets_at: u64,
+}
+
+#[derive(Debug, Serialize, PartialEq)]
+pub struct KeyUsage {
+    pub key: String,
+    pub hourly: WindowUsage,
+    pub daily: WindowUsage,
+}
+
+/// API keys callers identify themselves with, and their usage. Windows are fixed clock hours
+/// and UTC days; only admitted requests count.
+pub struct ApiKeys {
+    /// Replaced as a whole when the keys are rotated.
+    keys: RwLock<Vec<Key>>,
+    /// Quotas of keys by name, and of the keys without one.
+    overrides: HashMap<String, Quota>,
+    default: Quota,
+    counters: Mutex<HashMap<String, Counter>>,
+}
+
+/// Parses one limit of a `hourly/daily` pair; empty or `0` is unlimited.
+fn limit(value: &str) -> Result<Option<u64>, String> {
+    match value.trim() {
+        "" => Ok(None),
+        value => match value.parse::<u64>() {
+            Ok(0) => Ok(None),
+            Ok(limit) => Ok(Some(limit)),
+            Err(e) => Err(format!("Invalid quota '{}': {}", value, e)),
+        },
+    }
+}
+
+impl ApiKeys {
+    /// Builds the key set from `name=key` entries, `name=hourly/daily` quota overrides and the
+    /// default quota of keys without one.
+    pub fn new(keys: &[String], quotas: &[String], default: Quota) -> Result<Self, String> {
+        let mut overrides = HashMap::new();
+        for entry in quotas {
+            let (name, limits) = entry
+                .split_once('=')
+                .ok_or_else(|| format!("Invalid CEDAR_API_KEY_QUOTAS entry '{}' (expected name=hourly/daily)", entry))?;
+            let (hourly, daily) = limits.split_once('/').unwrap_or((limits, ""));
+            overrides.insert(name.trim().to_string(), Quota {
+                hourly: limit(hourly)?,
+                daily: limit(daily)?,
+            });
+        }
+
+        let api_keys = Self {
+            keys: RwLock::default(),
+            overrides,
+            default,
+            counters: Mutex::new(HashMap::new()),
+        };
+        *api_keys.keys.write().unwrap() = api_keys.parse(keys)?;
+        Ok(api_keys)
+    }
+
+    /// Parses `name=key` entries, each taking its quota override or the default.
+    fn parse(&self, keys: &[String]) -> Result<Vec<Key>, String> {
+        let mut parsed: Vec<Key> = Vec::new();
+        for entry in keys {
+            let Some((name, key)) = entry.split_once('=').filter(|(name, key)| !name.is_empty() && !key.is_empty()) else {
+                return Err("Invalid CEDAR_API_KEYS entry (expected name=key)".to_string());
+            };
+            if parsed.iter().any(|k| k.name == name) {
+                return Err(format!("Duplicate API key name '{}'", name));
+            }
+            parsed.push(Key {
+                name: name.to_string(),
+                digest: digest::sha256(key.as_bytes()),
+                quota: self.overrides.get(name).copied().unwrap_or(self.default),
+            });
+        }
+        if let Some(name) = self.overrides.keys().find(|name| !parsed.iter().any(|k| &k.name == *name)) {
+            return Err(format!("CEDAR_API_KEY_QUOTAS names unknown API key '{}'", name));
+        }
+        Ok(parsed)
+    }
+
+    /// Replaces the keys with rotated ones. Usage is counted by name, so a key keeps its usage
+    /// when only its value changes.
+    pub fn rotate(&self, keys: &[String]) -> Result<(), String> {
+        if keys.is_empty() {
+            return Err("No API keys".to_string());
+        }
+        let parsed = self.parse(keys)?;
+        *self.keys.write().unwrap() = parsed;
+        Ok(())
+    }
+
+    /// Name of the key sent in `X-Api-Key`. Digests are compared, and every key is checked, so
+    /// timing does not reveal how much of a key matched.
+    pub fn identify(&self, header: Option<&str>) -> Option<String> {
+        let sent = digest::sha256(header?.trim().as_bytes());
+        self.keys
+            .read()
+            .unwrap()
+            .iter()
+            .fold(None, |found, key| if key.digest == sent { Some(key) } else { found })
+            .map(|key| key.name.clone())
+    }
+
+    /// Names of the keys.
+    pub fn names(&self) -> Vec<String> {
+        self.keys.read().unwrap().iter().map(|key| key.name.clone()).collect()
+    }
+
+    fn quota(&self, name: &str) -> Quota {
+        self.keys.read().unwrap().iter().find(|k| k.name == name).map(|k| k.quota).unwrap_or_default()
+    }
+
+    /// Counts a request by `name` at Unix time `now`, unless that would exceed a quota.
+    pub fn admit(&self, name: &str, now: u64) -> Result<(), Exceeded> {
+        let quota = self.quota(name);
+        let mut counters = self.counters.lock().unwrap();
+        let counter = counters.entry(name.to_string()).or_default();
+        counter.roll(now);
+        if let Some(limit) = quota.daily.filter(|limit| counter.daily >= *limit) {
+            return Err(Exceeded {
+                window: "daily",
+                limit,
+                retry_after: DAY - now % DAY,
+            });
+        }
+        if let Some(limit) = quota.hourly.filter(|limit| counter.hourly >= *limit) {
+            return Err(Exceeded {
+                window: "hourly",
+                limit,
+                retry_after: HOUR - now % HOUR,
+            });
+        }
+        counter.hourly += 1;
+        counter.daily += 1;
+        Ok(())
+    }
+
+    /// Usage of one key, or of every key when `name` is `None`.
+    pub fn usage(&self, name: Option<&str>, now: u64) -> Vec<KeyUsage> {
+        let mut counters = self.counters.lock().unwrap();
+        self.keys
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|key| name.is_none_or(|name| key.name == name))
+            .map(|key| {
+                let counter = counters.entry(key.name.clone()).or_default();
+                counter.roll(now);
+                KeyUsage {
+                    key: key.name.clone(),
+                    hourly: WindowUsage {
+                        used: counter.hourly,
+                        limit: key.quota.hourly,
+                        resets_at: (now / HOUR + 1) * HOUR,
+                    },
+                    daily: WindowUsage {
+                        used: counter.daily,
+                        limit: key.quota.daily,
+                        resets_at: (now / DAY + 1) * DAY,
+                    },
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys() -> ApiKeys {
+        ApiKeys::new(
+            &["web=k-web".to_string(), "batch=k-batch".to_string()],
+            &["batch=2/3".to_string()],
+            Quota {
+                hourly: None,
+                daily: Some(100),
+            },
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn identifies_keys_by_value() {
+        let keys = keys();
+        assert_eq!(keys.identify(Some("k-batch")).as_deref(), Some("batch"));
+        assert_eq!(keys.identify(Some(" k-web ")).as_deref(), Some("web"));
+        assert_eq!(keys.identify(Some("k-other")), None);
+        assert_eq!(keys.identify(None), None);
+    }
+
+    #[test]
+    fn enforces_hourly_then_daily_quotas() {
+        let keys = keys();
+        let start = 10 * DAY + 100;
+        assert_eq!(keys.admit("batch", start), Ok(()));
+        assert_eq!(keys.admit("batch", start + 1), Ok(()));
+        assert_eq!(
+            keys.admit("batch", start + 2),
+            Err(Exceeded {
+                window: "hourly",
+                limit: 2,
+                retry_after: HOUR - 102,
+            })
+        );
+        // The next hour opens again, up to the daily limit
+        assert_eq!(keys.admit("batch", start + HOUR), Ok(()));
+        assert_eq!(keys.admit("batch", start + HOUR + 1).unwrap_err().window, "daily");
+        assert_eq!(keys.admit("batch", start + DAY), Ok(()));
+        // Other keys have their own counters and the default quota
+        assert_eq!(keys.admit("web", start + 2), Ok(()));
+    }
+
+    #[test]
+    fn reports_usage() {
+        let keys = keys();
+        let now = 10 * DAY + 100;
+        keys.admit("batch", now).unwrap();
+        let usage = keys.usage(Some("batch"), now);
+        assert_eq!(
+            usage,
+            [KeyUsage {
+                key: "batch".to_string(),
+                hourly: WindowUsage {
+                    used: 1,
+                    limit: Some(2),
+                    resets_at: 10 * DAY + HOUR,
+                },
+                daily: WindowUsage {
+                    used: 1,
+                    limit: Some(3),
+                    resets_at: 11 * DAY,
+                },
+            }]
+        );
+        assert_eq!(keys.usage(None, now).len(), 2);
+    }
+
+    #[test]
+    fn rotated_keys_keep_their_usage_and_quota() {
+        let keys = keys();
+        let now = 10 * DAY + 100;
+        keys.admit("batch", now).unwrap();
+        keys.rotate(&["batch=k-batch-2".to_string(), "ci=k-ci".to_string()]).unwrap();
+        assert_eq!(keys.identify(Some("k-batch")), None);
+        assert_eq!(keys.identify(Some("k-batch-2")).as_deref(), Some("batch"));
+        assert_eq!(keys.identify(Some("k-web")), None);
+        assert_eq!(keys.identify(Some("k-ci")).as_deref(), Some("ci"));
+        assert_eq!(keys.usage(Some("batch"), now)[0].hourly.used, 1);
+        assert_eq!(keys.usage(Some("batch"), now)[0].hourly.limit, Some(2));
+
+        // Rotations that would break the configuration are refused, keeping the current keys
+        assert!(keys.rotate(&[]).is_err());
+        assert!(keys.rotate(&["web=k-web".to_string()]).is_err());
+        assert!(keys.rotate(&["batch=a".to_string(), "batch=b".to_string()]).is_err());
+        assert_eq!(keys.identify(Some("k-ci")).as_deref(), Some("ci"));
+    }
+
+    #[test]
+    fn rejects_bad_configuration() {
+        let default = Quota::default();
+        assert!(ApiKeys::new(&["nokey".to_string()], &[], default).is_err());
+        assert!(ApiKeys::new(&["a=1".to_string(), "a=2".to_string()], &[], default).is_err());
+        assert!(ApiKeys::new(&["a=1".to_string()], &["b=1/1".to_string()], default).is_err());
+        assert!(ApiKeys::new(&["a=1".to_string()], &["a=x/1".to_string()], default).is_err());
+        let unlimited = ApiKeys::new(&["a=1".to_string()], &["a=0/".to_string()], Quota { hourly: Some(5), daily: None }).unwrap();
+        assert_eq!(unlimited.quota("a"), Quota::default());
+    }
+}
@@ -0,0 +1,395 @@
+use crate::{digest, quota};
+use base64::Engine;
+use hyper::Method;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Most verified Basic credentials remembered, so that a polling dashboard is not checked
+/// against bcrypt on every request.
+const MAX_VERIFIED: usize = 1024;
+/// Failed Basic sign-ins from one address, or for one name, after which further attempts are
+/// refused until `FAILURE_WINDOW` has passed since the first.
+const MAX_FAILURES: u32 = 5;
+const FAILURE_WINDOW: Duration = Duration::from_secs(60);
+/// Most addresses and names whose failed sign-ins are counted at a time.
+const MAX_TRACKED: usize = 10_000;
+
+/// What an administrator may do on the admin API.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Role {
+    /// Read admin and debug state: usage, log level, runtime stats, schema analyses.
+    Viewer,
+    /// Replace the schema, reload policies, stage and promote policies, and propose or approve
+    /// policy changes.
+    PolicyEditor,
+    /// Write entities and flush the entity cache.
+    DataEditor,
+    /// Change the log level, reload, flush the entity cache and profile the agent.
+    Operator,
+}
+
+impl Role {
+    fn parse(name: &str) -> Result<Self, String> {
+        match name.trim() {
+            "viewer" => Ok(Role::Viewer),
+            "policy-editor" => Ok(Role::PolicyEditor),
+            "data-editor" => Ok(Role::DataEditor),
+            "operator" => Ok(Role::Operator),
+            other => Err(format!(
+                "Unknown admin role '{}' (expected viewer, policy-editor, data-editor or operator)",
+                other
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Role::Viewer => "viewer",
+            Role::PolicyEditor => "policy-editor",
+            Role::DataEditor => "data-editor",
+            Role::Operator => "operator",
+        })
+    }
+}
+
+const ANY: &[Role] = &[Role::Viewer, Role::PolicyEditor, Role::DataEditor, Role::Operator];
+
+/// Roles any one of which may call `method path`; `None` when the endpoint is not part of the
+/// admin API or has a credential of its own (SCIM, replication). Admin endpoints not listed
+/// here are left to operators.
+pub fn allowed(method: &Method, path: &str) -> Option<&'static [Role]> {
+    if path.starts_with("/scim/") || path == "/admin/replication/stream" {
+        return None;
+    }
+    let roles: &'static [Role] = match (method, path) {
+        (&Method::GET, "/admin/usage" | "/admin/loglevel" | "/debug/stats") => ANY,
+        (&Method::POST, "/v1/schema/impact" | "/v1/schema/context-usage") => ANY,
+        (&Method::PUT, "/v1/schema") => &[Role::PolicyEditor],
+        (&Method::POST, "/admin/reload") => &[Role::PolicyEditor, Role::Operator],
+        (&Method::GET, crate::staging::PATH | crate::decision_index::PATH) => ANY,
+        (_, path) if path.starts_with(crate::staging::PATH) => &[Role::PolicyEditor],
+        (&Method::POST | &Method::DELETE, path) if path.starts_with(crate::proposals::PATH) => &[Role::PolicyEditor],
+        (&Method::POST, path) if crate::policies::toggle(path).is_some() => &[Role::PolicyEditor, Role::Operator],
+        (&Method::POST, "/v1/policies/simulate") => ANY,
+        (&Method::POST, "/v1/policies/disable" | "/v1/policies/enable") => &[Role::PolicyEditor, Role::Operator],
+        (&Method::PUT | &Method::PATCH, "/v1/data/entities") => &[Role::DataEditor],
+        (&Method::DELETE, "/admin/entity-cache") => &[Role::DataEditor, Role::Operator],
+        (method, path) if crate::ip_filter::is_admin(method, path) => &[Role::Operator],
+        _ => return None,
+    };
+    Some(roles)
+}
+
+/// Name of the administrator a request authenticated as, attached to the request as an extension.
+#[derive(Clone)]
+pub struct Admin(pub String);
+
+/// Who failed to sign in: the address a request came from, or the name it gave.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Subject {
+    Address(IpAddr),
+    Name(String),
+}
+
+/// Named administrators, their keys or bcrypt-hashed passwords, and their roles.
+pub struct Rbac {
+    keys: quota::ApiKeys,
+    users: HashMap<String, String>,
+    roles: HashMap<String, HashSet<Role>>,
+    /// Digests of the Basic credentials that verified.
+    verified: Mutex<HashSet<[u8; 32]>>,
+    /// A hash as costly as the users' ones, checked for names that are not users, so the time
+    /// taken does not tell which names are.
+    decoy: String,
+    /// Failed sign-ins and when the first of them in the current window was.
+    failures: Mutex<HashMap<Subject, (u32, Instant)>>,
+}
+
+impl Rbac {
+    /// Builds the administrators from `name=key` entries, `name:bcrypt-hash` users and
+    /// `name=role+role` bindings; every administrator needs at least one role.
+    pub fn new(keys: &[String], users: &[String], bindings: &[String]) -> Result<Self, String> {
+        let mut roles = HashMap::new();
+        for entry in bindings {
+            let (name, names) = entry
+                .split_once('=')
+                .filter(|(name, _)| !name.trim().is_empty())
+                .ok_or_else(|| format!("Invalid CEDAR_ADMIN_ROLES entry '{}' (expected name=role+role)", entry))?;
+            let granted = names.split('+').map(Role::parse).collect::<Result<HashSet<_>, _>>()?;
+            roles.insert(name.trim().to_string(), granted);
+        }
+        let keys = quota::ApiKeys::new(keys, &[], quota::Quota::default())
+            .map_err(|e| e.replace("CEDAR_API_KEYS", "CEDAR_ADMIN_KEYS"))?;
+        let mut hashes = HashMap::new();
+        for entry in users {
+            let (name, hash) = entry
+                .split_once(':')
+                .filter(|(name, hash)| !name.is_empty() && hash.starts_with("$2"))
+                .ok_or_else(|| format!("Invalid CEDAR_ADMIN_USERS entry for '{}' (expected name:bcrypt-hash)", entry.split(':').next().unwrap_or_default()))?;
+            if hashes.insert(name.to_string(), hash.to_string()).is_some() {
+                return Err(format!("Duplicate admin name '{}'", name));
+            }
+        }
+        check_names(&keys.names(), &hashes, &roles)?;
+        let cost = hashes
+            .values()
+            .map(|hash| hash.parse::<bcrypt::HashParts>().map(|parts| parts.get_cost()))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| format!("Invalid bcrypt hash in CEDAR_ADMIN_USERS: {}", e))?
+            .into_iter()
+            .max()
+            .unwrap_or(bcrypt::DEFAULT_COST);
+        let decoy = bcrypt::hash_with_salt("", cost, [0; 16]).map_err(|e| e.to_string())?.to_string();
+        Ok(Self {
+            keys,
+            users: hashes,
+            roles,
+            verified: Mutex::default(),
+            decoy,
+            failures: Mutex::default(),
+        })
+    }
+
+    /// Replaces the administrators' keys with rotated ones, which must name the same kind of
+    /// administrators: each with a role, and none also a Basic auth user.
+    pub fn rotate_keys(&self, keys: &[String]) -> Result<(), String> {
+        let rotated = quota::ApiKeys::new(keys, &[], quota::Quota::default())
+            .map_err(|e| e.replace("CEDAR_API_KEYS", "CEDAR_ADMIN_KEYS"))?;
+        check_names(&rotated.names(), &self.users, &self.roles)?;
+        self.keys.rotate(keys)
+    }
+
+    /// Name of the administrator whose key was sent.
+    pub fn identify(&self, header: Option<&str>) -> Option<String> {
+        self.keys.identify(header)
+    }
+
+    /// Name of the administrator whose `Authorization: Basic` credentials were sent from
+    /// `address`, or `None`. Passwords are checked against their bcrypt hash off the async
+    /// runtime, and names that are not users against a decoy that takes as long. After
+    /// `MAX_FAILURES` failures from the address or for the name, attempts other than with
+    /// credentials that verified before are refused with how long until they may be made again.
+    pub async fn authenticate(&self, header: &str, address: IpAddr) -> Result<Option<String>, Duration> {
+        let Some((name, password)) = basic_credentials(header) else {
+            return Ok(None);
+        };
+        let user = self.users.get(&name).cloned();
+        let hash = user.clone().unwrap_or_else(|| self.decoy.clone());
+
+        let digest = digest::of_parts(&[&hash, &password]);
+        if user.is_some() && self.verified.lock().unwrap().contains(&digest) {
+            return Ok(Some(name));
+        }
+        let subjects = [Subject::Address(address), Subject::Name(name.clone())];
+        self.throttled(&subjects, Instant::now()).map_or(Ok(()), Err)?;
+
+        let verified = tokio::task::spawn_blocking(move || bcrypt::verify(&password, &hash).unwrap_or(false))
+            .await
+            .unwrap_or(false);
+        if !verified || user.is_none() {
+            self.failed(subjects, Instant::now());
+            return Ok(None);
+        }
+        let mut failures = self.failures.lock().unwrap();
+        for subject in &subjects {
+            failures.remove(subject);
+        }
+        drop(failures);
+        let mut remembered = self.verified.lock().unwrap();
+        if remembered.len() >= MAX_VERIFIED {
+            remembered.clear();
+        }
+        remembered.insert(digest);
+        Ok(Some(name))
+    }
+
+    /// How long until any of `subjects` may try to sign in again, if one failed too often.
+    fn throttled(&self, subjects: &[Subject], now: Instant) -> Option<Duration> {
+        let failures = self.failures.lock().unwrap();
+        subjects
+            .iter()
+            .filter_map(|subject| failures.get(subject))
+            .filter(|(count, _)| *count >= MAX_FAILURES)
+            .map(|(_, first)| FAILURE_WINDOW.saturating_sub(now.duration_since(*first)))
+            .filter(|wait| !wait.is_zero())
+            .max()
+    }
+
+    /// Counts a failed sign-in against each of `subjects`.
+    fn failed(&self, subjects: [Subject; 2], now: Instant) {
+        let mut failures = self.failures.lock().unwrap();
+        if failures.len() >= MAX_TRACKED {
+            failures.retain(|_, (_, first)| now.duration_since(*first) < FAILURE_WINDOW);
+        }
+        for subject in subjects {
+            if failures.len() >= MAX_TRACKED && !failures.contains_key(&subject) {
+                continue;
+            }
+            let (count, first) = failures.entry(subject).or_insert((0, now));
+            if now.duration_since(*first) >= FAILURE_WINDOW {
+                (*count, *first) = (0, now);
+            }
+            *count += 1;
+        }
+    }
+
+    /// Whether administrators may sign in with HTTP Basic authentication.
+    pub fn has_users(&self) -> bool {
+        !self.users.is_empty()
+    }
+
+    /// Whether administrator `name` holds one of `roles`.
+    pub fn permits(&self, name: &str, roles: &[Role]) -> bool {
+        self.roles.get(name).is_some_and(|granted| roles.iter().any(|role| granted.contains(role)))
+    }
+}
+
+/// The name and password of an `Authorization: Basic` header.
+fn basic_credentials(header: &str) -> Option<(String, String)> {
+    let encoded = header.strip_prefix("Basic ")?.trim();
+    let decoded = base64::engine::general_purpose::STANDARD.decode(encoded).ok()?;
+    let (name, password) = std::str::from_utf8(&decoded).ok()?.split_once(':')?;
+    Some((name.to_string(), password.to_string()))
+}
+
+/// Checks that admins with keys and users are distinct and have roles, and that the roles are
+/// for known admins.
+fn check_names(keys: &[String], users: &HashMap<String, String>, roles: &HashMap<String, HashSet<Role>>) -> Result<(), String> {
+    if let Some(name) = keys.iter().find(|name| users.contains_key(*name)) {
+        return Err(format!("Duplicate admin name '{}'", name));
+    }
+    let names: HashSet<&str> = keys.iter().chain(users.keys()).map(String::as_str).collect();
+    if let Some(name) = names.iter().find(|name| !roles.contains_key(**name)) {
+        return Err(format!("Admin '{}' has no role in CEDAR_ADMIN_ROLES", name));
+    }
+    if let Some(name) = roles.keys().find(|name| !names.contains(name.as_str())) {
+        return Err(format!("CEDAR_ADMIN_ROLES names unknown admin '{}'", name));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rbac() -> Rbac {
+        let keys = ["grafana=k1".to_string(), "ci=k2".to_string(), "oncall=k3".to_string()];
+        let roles = [
+            "grafana=viewer".to_string(),
+            "ci=policy-editor+data-editor".to_string(),
+            "oncall=operator".to_string(),
+        ];
+        Rbac::new(&keys, &[], &roles).unwrap()
+    }
+
+    fn permitted(rbac: &Rbac, name: &str, method: Method, path: &str) -> bool {
+        rbac.permits(name, allowed(&method, path).unwrap())
+    }
+
+    #[test]
+    fn roles_govern_admin_endpoints() {
+        let rbac = rbac();
+        assert!(permitted(&rbac, "grafana", Method::GET, "/debug/stats"));
+        assert!(!permitted(&rbac, "grafana", Method::PUT, "/v1/schema"));
+        assert!(!permitted(&rbac, "grafana", Method::PATCH, "/v1/data/entities"));
+        assert!(permitted(&rbac, "ci", Method::PUT, "/v1/schema"));
+        assert!(permitted(&rbac, "ci", Method::POST, "/v1/policies/proposals/3/approve"));
+        assert!(!permitted(&rbac, "oncall", Method::POST, "/v1/policies/proposals"));
+        assert!(permitted(&rbac, "ci", Method::POST, "/admin/staging/promote"));
+        assert!(permitted(&rbac, "grafana", Method::GET, "/admin/staging"));
+        assert!(!permitted(&rbac, "grafana", Method::PUT, "/admin/staging"));
+        assert!(permitted(&rbac, "ci", Method::PATCH, "/v1/data/entities"));
+        assert!(permitted(&rbac, "ci", Method::GET, "/admin/usage"));
+        assert!(!permitted(&rbac, "ci", Method::PUT, "/admin/loglevel"));
+        assert!(permitted(&rbac, "oncall", Method::PUT, "/admin/loglevel"));
+        assert!(permitted(&rbac, "oncall", Method::GET, "/debug/pprof/heap"));
+        assert!(permitted(&rbac, "oncall", Method::POST, "/admin/reload"));
+        assert!(permitted(&rbac, "oncall", Method::POST, "/v1/policies/admin-console/disable"));
+        assert!(!permitted(&rbac, "grafana", Method::POST, "/v1/policies/admin-console/enable"));
+        assert!(permitted(&rbac, "grafana", Method::POST, "/v1/policies/simulate"));
+        assert!(permitted(&rbac, "oncall", Method::POST, "/v1/policies/disable"));
+        assert!(!permitted(&rbac, "oncall", Method::PUT, "/v1/data/entities"));
+        assert!(!permitted(&rbac, "unknown", Method::GET, "/debug/stats"));
+        assert_eq!(rbac.identify(Some("k2")).as_deref(), Some("ci"));
+    }
+
+    #[test]
+    fn rotates_keys() {
+        let rbac = rbac();
+        let rotated = ["grafana=k1".to_string(), "ci=k2-new".to_string(), "oncall=k3".to_string()];
+        rbac.rotate_keys(&rotated).unwrap();
+        assert_eq!(rbac.identify(Some("k2")), None);
+        assert_eq!(rbac.identify(Some("k2-new")).as_deref(), Some("ci"));
+        // An admin without a role, or a role without its admin, is refused
+        assert!(rbac.rotate_keys(&["grafana=k1".to_string(), "ci=k2".to_string(), "root=k4".to_string()]).is_err());
+        assert!(rbac.rotate_keys(&["grafana=k1".to_string(), "ci=k2".to_string()]).is_err());
+        assert_eq!(rbac.identify(Some("k2-new")).as_deref(), Some("ci"));
+    }
+
+    #[test]
+    fn leaves_other_endpoints_alone() {
+        assert_eq!(allowed(&Method::POST, "/authorize"), None);
+        assert_eq!(allowed(&Method::GET, "/v1/schema"), None);
+        assert_eq!(allowed(&Method::POST, "/v1/evaluate"), None);
+        assert_eq!(allowed(&Method::GET, "/scim/v2/Users"), None);
+        assert_eq!(allowed(&Method::GET, "/admin/replication/stream"), None);
+        assert_eq!(allowed(&Method::POST, "/admin/something-new"), Some(&[Role::Operator][..]));
+    }
+
+    #[test]
+    fn rejects_bad_bindings() {
+        let keys = ["grafana=k1".to_string()];
+        assert!(Rbac::new(&keys, &[], &["grafana=admin".to_string()]).is_err());
+        assert!(Rbac::new(&keys, &[], &["grafana".to_string()]).is_err());
+        assert!(Rbac::new(&keys, &[], &[]).is_err());
+        assert!(Rbac::new(&["grafana".to_string()], &[], &["grafana=viewer".to_string()]).is_err());
+        assert!(Rbac::new(&keys, &[], &["grafana=viewer".to_string(), "ci=viewer".to_string()]).is_err());
+        let users = |user: &str| vec![user.to_string()];
+        assert!(Rbac::new(&keys, &users("ops:secret"), &["grafana=viewer".to_string(), "ops=viewer".to_string()]).is_err());
+        assert!(Rbac::new(&keys, &users(&format!("grafana:{}", HASH)), &["grafana=viewer".to_string()]).is_err());
+    }
+
+    /// bcrypt of `U*U`.
+    const HASH: &str = "$2b$05$CCCCCCCCCCCCCCCCCCCCC.E5YPO9kmyuRGyh0XouQYb4YMJKvyOeW";
+
+    #[tokio::test]
+    async fn authenticates_basic_credentials() {
+        let users = [format!("ops:{}", HASH)];
+        let rbac = Rbac::new(&[], &users, &["ops=operator".to_string()]).unwrap();
+        let basic = |credentials: &str| format!("Basic {}", base64::engine::general_purpose::STANDARD.encode(credentials));
+        let address: IpAddr = "192.0.2.1".parse().unwrap();
+        assert_eq!(rbac.authenticate(&basic("ops:U*U"), address).await, Ok(Some("ops".to_string())));
+        // Remembered the second time
+        assert_eq!(rbac.verified.lock().unwrap().len(), 1);
+        assert_eq!(rbac.authenticate(&basic("ops:U*U"), address).await, Ok(Some("ops".to_string())));
+        assert_eq!(rbac.authenticate(&basic("ops:U*U*"), address).await, Ok(None));
+        assert_eq!(rbac.authenticate(&basic("root:U*U"), address).await, Ok(None));
+        assert_eq!(rbac.authenticate("Basic !!!", address).await, Ok(None));
+        assert_eq!(rbac.authenticate("Bearer abc", address).await, Ok(None));
+        assert_eq!(rbac.verified.lock().unwrap().len(), 1);
+        assert_eq!(rbac.decoy.parse::<bcrypt::HashParts>().unwrap().get_cost(), 5);
+    }
+
+    #[tokio::test]
+    async fn throttles_failed_sign_ins() {
+        let users = [format!("ops:{}", HASH)];
+        let rbac = Rbac::new(&[], &users, &["ops=operator".to_string()]).unwrap();
+        let basic = |credentials: &str| format!("Basic {}", base64::engine::general_purpose::STANDARD.encode(credentials));
+        let (first, second): (IpAddr, IpAddr) = ("192.0.2.1".parse().unwrap(), "192.0.2.2".parse().unwrap());
+        for _ in 0..MAX_FAILURES {
+            assert_eq!(rbac.authenticate(&basic("root:guess"), first).await, Ok(None));
+        }
+        // The address, and the name from anywhere, wait; unknown names are counted like users
+        assert!(rbac.authenticate(&basic("ops:U*U"), first).await.is_err());
+        assert!(rbac.authenticate(&basic("root:guess"), second).await.is_err());
+        assert_eq!(rbac.authenticate(&basic("ops:U*U"), second).await, Ok(Some("ops".to_string())));
+
+        let later = Instant::now() + FAILURE_WINDOW;
+        assert_eq!(rbac.throttled(&[Subject::Address(first)], later), None);
+    }
+}
@@ -0,0 +1,269 @@
+use crate::config::Config;
+use crate::digest;
+use crate::entities::{EntityStore, Ingest};
+use crate::policies::{self, LoadedPolicies};
+use crate::schema::{self, SchemaUpdateError};
+use cedar_policy::{PolicySet, Schema};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::Path;
+use std::sync::Arc;
+
+/// The files `POST /admin/reload` reads: the ones the agent started from.
+pub struct Sources {
+    policy_path: String,
+    policy_overlays: Vec<String>,
+    schema_path: String,
+    schema_fragments: Vec<String>,
+    entities_path: Option<String>,
+    entity_ingest: Ingest,
+}
+
+impl Sources {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            policy_path: config.policy_path.clone(),
+            policy_overlays: config.policy_overlays.clone(),
+            schema_path: config.schema_path.clone(),
+            schema_fragments: config.schema_fragments.clone(),
+            entities_path: config.entities_path.clone(),
+            entity_ingest: Ingest::from_config(config),
+        }
+    }
+
+    /// Replaces the contents of the policy file (`CEDAR_POLICY_PATH`) with `text`, so reloads
+    /// and restarts load the policies it holds, and returns the contents it had. The file is
+    /// replaced atomically. Policies composed with overlays cannot be written back to one file.
+    pub fn write_policies(&self, text: &str) -> Result<String, String> {
+        if !self.policy_overlays.is_empty() {
+            return Err("Policies are composed with CEDAR_POLICY_OVERLAYS, so they cannot be written to one file".to_string());
+        }
+        let path = Path::new(&self.policy_path);
+        let previous = fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", self.policy_path, e))?;
+        let tmp = path.with_extension("cedar.tmp");
+        let written = File::create(&tmp)
+            .and_then(|mut file| file.write_all(text.as_bytes()).and_then(|_| file.sync_all()))
+            .and_then(|_| fs::rename(&tmp, path));
+        if let Err(e) = written {
+            let _ = fs::remove_file(&tmp);
+            return Err(format!("Failed to write {}: {}", self.policy_path, e));
+        }
+        Ok(previous)
+    }
+}
+
+/// Policies, schema and entities read from the files and validated together, ready to be
+/// activated as they are.
+pub struct Candidate {
+    /// Identifies the content, so a dry run's result can be committed exactly.
+    pub version: String,
+    pub policies: LoadedPolicies,
+    pub schema: Option<(Schema, serde_json::Value)>,
+    /// `None` without an entities file, in which case the stored entities are kept.
+    pub entities: Option<Arc<EntityStore>>,
+    /// Cedar's validation warnings for the policies.
+    pub warnings: Vec<String>,
+}
+
+/// Policies (and templates) that a reload adds, removes or changes, by ID.
+#[derive(Debug, Default, Serialize, PartialEq)]
+pub struct PolicyDiff {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    pub changed: Vec<String>,
+}
+
+/// What `POST /admin/reload` reports: what would change on a dry run, or what did.
+#[derive(Debug, Serialize)]
+pub struct Report {
+    /// `validated` on a dry run, `activated` otherwise.
+    pub status: &'static str,
+    pub version: String,
+    pub policies: PolicyDiff,
+    pub schema_changed: bool,
+    pub entities: usize,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<String>,
+}
+
+/// Every policy and template by ID, as text.
+pub fn statements(policy_set: &PolicySet) -> BTreeMap<String, String> {
+    policy_set
+        .policies()
+        .map(|p| (p.id().to_string(), p.to_string()))
+        .chain(policy_set.templates().map(|t| (t.id().to_string(), t.to_string())))
+        .collect()
+}
+
+pub fn diff(old: &PolicySet, new: &PolicySet) -> PolicyDiff {
+    let (old, new) = (statements(old), statements(new));
+    let mut diff = PolicyDiff::default();
+    for (id, statement) in &new {
+        match old.get(id) {
+            None => diff.added.push(id.clone()),
+            Some(previous) if previous != statement => diff.changed.push(id.clone()),
+            Some(_) => {}
+        }
+    }
+    diff.removed = old.keys().filter(|id| !new.contains_key(*id)).cloned().collect();
+    diff
+}
+
+/// JSON with object keys and array elements sorted: Cedar's attribute maps and sets (entity
+/// parents, set attributes) come out of `to_json_value` in no particular order.
+pub fn canonical(json: serde_json::Value) -> serde_json::Value {
+    match json {
+        serde_json::Value::Array(items) => {
+            let mut items: Vec<serde_json::Value> = items.into_iter().map(canonical).collect();
+            items.sort_by_cached_key(|item| item.to_string());
+            serde_json::Value::Array(items)
+        }
+        serde_json::Value::Object(fields) => {
+            let sorted: BTreeMap<String, serde_json::Value> = fields.into_iter().map(|(k, v)| (k, canonical(v))).collect();
+            sorted.into_iter().collect()
+        }
+        other => other,
+    }
+}
+
+/// A digest of everything a candidate would activate.
+fn version(policy_set: &PolicySet, schema_json: Option<&serde_json::Value>, entities: Option<&EntityStore>) -> String {
+    let mut hasher = Sha256::new();
+    for (id, statement) in statements(policy_set) {
+        hasher.update(id.as_bytes());
+        hasher.update([0]);
+        hasher.update(statement.as_bytes());
+        hasher.update([0]);
+    }
+    hasher.update(schema_json.map(|json| canonical(json.clone()).to_string()).unwrap_or_default().as_bytes());
+    hasher.update([0]);
+    for entity in entities.map(EntityStore::direct).unwrap_or_default() {
+        let json = entity.to_json_value().map(|json| canonical(json).to_string()).unwrap_or_default();
+        hasher.update(json.as_bytes());
+        hasher.update([0]);
+    }
+    digest::hex(&hasher.finalize()[..8])
+}
+
+/// Reads and validates the files the way startup does: the policies must pass strict
+/// validation against the schema, and the entities must conform to it.
+pub fn load(sources: &Sources) -> Result<Candidate, SchemaUpdateError> {
+    let loaded = policies::load(&sources.policy_path, &sources.policy_overlays)
+        .map_err(|e| SchemaUpdateError::invalid(e.to_string()))?;
+
+    let schema = schema::load(&sources.schema_path, &sources.schema_fragments).map_err(SchemaUpdateError::invalid)?;
+
+    let warnings = match schema {
+        Some((ref schema, _)) => {
+            schema::check_policies(schema, &loaded.policy_set)
+                .map_err(|e| SchemaUpdateError {
+                    error: format!("Policies rejected: {} fail validation against the schema", e.validation_errors.len()),
+                    validation_errors: e.validation_errors,
+                    impact: None,
+                })?
+                .warnings
+        }
+        None => Vec::new(),
+    };
+
+    let entities = match sources.entities_path {
+        Some(ref path) => Some(Arc::new(
+            EntityStore::load(Some(path), schema.as_ref().map(|(schema, _)| schema), sources.entity_ingest)
+                .map_err(|e| SchemaUpdateError::invalid(e.to_string()))?,
+        )),
+        None => None,
+    };
+
+    Ok(Candidate {
+        version: version(&loaded.policy_set, schema.as_ref().map(|(_, json)| json), entities.as_deref()),
+        policies: loaded,
+        schema,
+        entities,
+        warnings,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy_set(src: &str) -> PolicySet {
+        src.parse().unwrap()
+    }
+
+    #[test]
+    fn diffs_policies_by_id() {
+        let old = policy_set(
+            "permit (principal, action, resource);\n\
+             permit (principal, action, resource) when { context.a };",
+        );
+        let new = policy_set(
+            "permit (principal, action, resource);\n\
+             permit (principal, action, resource) when { context.b };",
+        );
+        assert_eq!(
+            diff(&old, &new),
+            PolicyDiff {
+                changed: vec!["policy1".to_string()],
+                ..Default::default()
+            }
+        );
+        assert_eq!(diff(&old, &old), PolicyDiff::default());
+    }
+
+    #[test]
+    fn reports_added_and_removed_policies() {
+        let old = policy_set("permit (principal, action, resource);");
+        let new = policy_set("permit (principal, action, resource);\nforbid (principal, action, resource);");
+        assert_eq!(
+            diff(&old, &new),
+            PolicyDiff {
+                added: vec!["policy1".to_string()],
+                ..Default::default()
+            }
+        );
+        assert_eq!(diff(&new, &old).removed, ["policy1"]);
+    }
+
+    #[test]
+    fn versions_identify_content() {
+        let a = policy_set("permit (principal, action, resource);");
+        let b = policy_set("forbid (principal, action, resource);");
+        assert_eq!(version(&a, None, None), version(&a, None, None));
+        assert_ne!(version(&a, None, None), version(&b, None, None));
+        assert_ne!(version(&a, None, None), version(&a, Some(&serde_json::json!({})), None));
+    }
+
+    #[test]
+    fn canonical_json_ignores_set_order() {
+        assert_eq!(
+            canonical(serde_json::json!({"parents": [{"id": "b"}, {"id": "a"}], "attrs": {"y": 1, "x": [2, 1]}})).to_string(),
+            canonical(serde_json::json!({"attrs": {"x": [1, 2], "y": 1}, "parents": [{"id": "a"}, {"id": "b"}]})).to_string()
+        );
+    }
+
+    #[test]
+    fn writes_policies_back_to_the_policy_file() {
+        let path = std::env::temp_dir().join(format!("cedar-reload-{}.cedar", std::process::id()));
+        fs::write(&path, "permit (principal, action, resource);\n").unwrap();
+        let mut sources = Sources {
+            policy_path: path.to_str().unwrap().to_string(),
+            policy_overlays: Vec::new(),
+            schema_path: String::new(),
+            schema_fragments: Vec::new(),
+            entities_path: None,
+            entity_ingest: Ingest::default(),
+        };
+        let previous = sources.write_policies("forbid (principal, action, resource);\n").unwrap();
+        assert_eq!(previous, "permit (principal, action, resource);\n");
+        assert_eq!(fs::read_to_string(&path).unwrap(), "forbid (principal, action, resource);\n");
+
+        sources.policy_overlays.push("overlays".to_string());
+        assert!(sources.write_policies("permit (principal, action, resource);\n").is_err());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "forbid (principal, action, resource);\n");
+        fs::remove_file(&path).unwrap();
+    }
+}
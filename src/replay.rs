@@ -0,0 +1,244 @@
+use crate::config::Config;
+use crate::decision_log::{self, Record};
+use crate::{policies, AuthzRequest, CedarService, PolicyState};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+use std::sync::Arc;
+
+/// Group of changes no policy accounts for: neither before nor after did a policy determine
+/// the decision, so the default decision (or an error) decided it.
+const NO_POLICY: &str = "(default decision)";
+
+struct ReplayOptions {
+    config: Config,
+    log_path: String,
+}
+
+impl ReplayOptions {
+    fn parse(args: &[String]) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut opts = ReplayOptions {
+            config: Config::from_env()?,
+            log_path: String::new(),
+        };
+
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let mut value = |name: &str| {
+                args.next()
+                    .cloned()
+                    .ok_or_else(|| format!("Missing value for {}", name))
+            };
+            match arg.as_str() {
+                "--log" => opts.log_path = value(arg)?,
+                "--policies" => {
+                    // A directory holds the candidate set: its first file (by name) is the base
+                    // and the rest are overlays, as CEDAR_POLICY_PATH and CEDAR_POLICY_OVERLAYS
+                    let path = value(arg)?;
+                    let mut files = policies::expand_overlay(&path)?;
+                    if files.is_empty() {
+                        return Err(format!("No .cedar files in {}", path).into());
+                    }
+                    opts.config.policy_path = files.remove(0);
+                    opts.config.policy_overlays = files;
+                }
+                "--schema" => opts.config.schema_path = value(arg)?,
+                "--entities" => opts.config.entities_path = Some(value(arg)?),
+                other => return Err(format!("Unknown replay argument: {}", other).into()),
+            }
+        }
+
+        if opts.log_path.is_empty() {
+            return Err("Usage: cedar-agent replay --log <decisions.jsonl> [--policies <file or dir>] \
+                        [--schema <path>] [--entities <path>]"
+                .into());
+        }
+        if !Path::new(&opts.config.policy_path).exists() {
+            return Err(format!("Policy file {} not found", opts.config.policy_path).into());
+        }
+        // Candidate policies come from files, never from Verified Permissions
+        opts.config.avp_policy_store_id = None;
+        opts.config.decision_log_path = None;
+        Ok(opts)
+    }
+}
+
+/// A logged request whose decision differs under the candidate policies.
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct Change {
+    pub seq: u64,
+    pub principal: String,
+    pub action: String,
+    pub resource: String,
+    pub before: String,
+    pub after: String,
+    pub before_reasons: Vec<String>,
+    pub after_reasons: Vec<String>,
+}
+
+/// A logged request that no longer evaluates, e.g. because the candidate schema rejects it.
+#[derive(Debug, Serialize)]
+pub struct ReplayError {
+    pub seq: u64,
+    pub error: String,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct Report {
+    pub replayed: usize,
+    pub changed: usize,
+    /// Changed decisions under each policy that started or stopped determining them. A change
+    /// with several such policies is listed under each.
+    pub by_policy: BTreeMap<String, Vec<Change>>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub errors: Vec<ReplayError>,
+}
+
+/// Policies that determined the decision before or after but not both: the ones a change is
+/// down to.
+fn responsible(change: &Change) -> Vec<String> {
+    let mut policies: Vec<String> = change
+        .before_reasons
+        .iter()
+        .filter(|id| !change.after_reasons.contains(id))
+        .chain(change.after_reasons.iter().filter(|id| !change.before_reasons.contains(id)))
+        .cloned()
+        .collect();
+    if policies.is_empty() {
+        policies.push(NO_POLICY.to_string());
+    }
+    policies
+}
+
+impl Report {
+    fn record(&mut self, change: Change) {
+        self.changed += 1;
+        for policy in responsible(&change) {
+            self.by_policy.entry(policy).or_default().push(change.clone());
+        }
+    }
+}
+
+fn replay(service: &CedarService, state: &PolicyState, record: Record, report: &mut Report) {
+    report.replayed += 1;
+    let logged = record.decision;
+    let req = AuthzRequest {
+        principal: logged.principal.clone(),
+        action: logged.action.clone(),
+        resource: logged.resource.clone(),
+        entities: logged.entities,
+        context: logged.context,
+        min_version: None,
+        token: None,
+        evaluation_time: None,
+    };
+    match service.evaluate(state, req) {
+        Ok(response) if response.decision != logged.decision => report.record(Change {
+            seq: record.seq,
+            principal: logged.principal,
+            action: logged.action,
+            resource: logged.resource,
+            before: logged.decision,
+            after: response.decision,
+            before_reasons: logged.reasons,
+            after_reasons: response.diagnostics.reason,
+        }),
+        Ok(_) => {}
+        Err(e) => report.errors.push(ReplayError {
+            seq: record.seq,
+            error: e.to_string(),
+        }),
+    }
+}
+
+/// Re-evaluates logged requests against `state` and reports each decision that would change.
+pub fn simulate(service: &CedarService, state: &PolicyState, records: Vec<Record>) -> Report {
+    let mut report = Report::default();
+    for record in records {
+        replay(service, state, record, &mut report);
+    }
+    report
+}
+
+/// Simulates `state` against the `limit` most recent logged decisions, off the request threads;
+/// `None` without a decision log.
+pub async fn simulate_recent(service: &Arc<CedarService>, state: PolicyState, limit: usize) -> Result<Option<Report>, String> {
+    let Some(path) = service.decision_log.as_ref().and_then(|log| log.path()).map(str::to_string) else {
+        return Ok(None);
+    };
+    let service = Arc::clone(service);
+    tokio::task::spawn_blocking(move || {
+        decision_log::recent_records(&path, limit).map(|records| Some(simulate(&service, &state, records)))
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+/// `cedar-agent replay`: re-evaluates every request in a decision log against candidate
+/// policies and reports each decision that changed, grouped by policy. Exits nonzero if any
+/// decision changed or a request no longer evaluates.
+pub fn run(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let opts = ReplayOptions::parse(args)?;
+    let service = CedarService::new(&opts.config)?.without_decision_logging();
+    let file = File::open(&opts.log_path).map_err(|e| format!("Failed to open {}: {}", opts.log_path, e))?;
+
+    let state = service.state();
+    let mut report = Report::default();
+    for (n, line) in BufReader::new(file).lines().enumerate() {
+        let line = line.map_err(|e| format!("Failed to read {}: {}", opts.log_path, e))?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        match decision_log::parse_line(&line).map_err(|e| format!("Line {}: {}", n + 1, e))? {
+            Some(record) => replay(&service, &state, record, &mut report),
+            None => continue,
+        }
+    }
+
+    println!("{}", serde_json::to_string_pretty(&report)?);
+    if report.changed > 0 || !report.errors.is_empty() {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn change(seq: u64, before: &[&str], after: &[&str]) -> Change {
+        let ids = |ids: &[&str]| ids.iter().map(|id| id.to_string()).collect();
+        Change {
+            seq,
+            principal: "User::\"alice\"".to_string(),
+            action: "Action::\"view\"".to_string(),
+            resource: "Doc::\"d1\"".to_string(),
+            before: "Allow".to_string(),
+            after: "Deny".to_string(),
+            before_reasons: ids(before),
+            after_reasons: ids(after),
+        }
+    }
+
+    #[test]
+    fn attributes_changes_to_policies_that_started_or_stopped_applying() {
+        assert_eq!(responsible(&change(1, &["p0"], &["p1"])), ["p0", "p1"]);
+        assert_eq!(responsible(&change(1, &["p0", "p2"], &["p2", "p3"])), ["p0", "p3"]);
+        assert_eq!(responsible(&change(1, &[], &[])), [NO_POLICY]);
+    }
+
+    #[test]
+    fn groups_changes_by_policy() {
+        let mut report = Report::default();
+        report.record(change(1, &["p0"], &[]));
+        report.record(change(2, &["p0"], &["forbid-new"]));
+        report.record(change(3, &[], &[]));
+        assert_eq!(report.changed, 3);
+        let seqs = |policy: &str| report.by_policy[policy].iter().map(|c| c.seq).collect::<Vec<_>>();
+        assert_eq!(seqs("p0"), [1, 2]);
+        assert_eq!(seqs("forbid-new"), [2]);
+        assert_eq!(seqs(NO_POLICY), [3]);
+    }
+}
@@ -0,0 +1,571 @@
+use crate::config::Config;
+use crate::digest;
+use crate::entities::{self, EntityStore};
+use crate::{error_response, fetch, query_params, CedarService, PolicyState};
+use cedar_policy::{PolicyId, PolicySet, Schema};
+use hyper::body::HttpBody;
+use hyper::client::connect::Connect;
+use hyper::header::AUTHORIZATION;
+use hyper::{Body, Client, Method, Response, StatusCode, Uri};
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// How often an idle stream carries a heartbeat, so replicas notice a dead leader.
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(15);
+/// A replica reconnects when nothing arrives for this long.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(45);
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+/// A stream carries a full snapshot again after this many deltas, so a replica never drifts
+/// from the leader for long.
+const SNAPSHOT_EVERY: usize = 100;
+
+/// One line of the replication stream.
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Message {
+    /// The leader's whole state at a version.
+    Snapshot(Box<Snapshot>),
+    /// What changed since the version the stream last carried.
+    Delta(Box<Delta>),
+    /// Nothing changed since the last message.
+    Heartbeat { epoch: String, version: u64 },
+}
+
+/// The policies of a state, which a delta carries whole when any of them changed.
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct Policies {
+    /// The policy set in Cedar's JSON format.
+    pub policies: Value,
+    /// Source text of each policy by ID, as served by the policy endpoints.
+    pub policy_sources: BTreeMap<String, String>,
+    pub layered: bool,
+    /// IDs of the disabled policies.
+    #[serde(default)]
+    pub disabled: Vec<String>,
+}
+
+impl Policies {
+    fn new(state: &PolicyState) -> Result<Self, String> {
+        Ok(Self {
+            policies: state
+                .policy_set
+                .clone()
+                .to_json()
+                .map_err(|e| format!("Failed to serialize policies: {}", e))?,
+            policy_sources: state
+                .policy_sources
+                .iter()
+                .map(|(id, src)| (id.to_string(), src.clone()))
+                .collect(),
+            layered: state.layered,
+            disabled: state.disabled.iter().map(|id| id.to_string()).collect(),
+        })
+    }
+
+    /// Sets the policies of `state` to these.
+    fn apply(self, state: &mut PolicyState) -> Result<(), String> {
+        state.policy_set = PolicySet::from_json_value(self.policies).map_err(|e| format!("Invalid policies: {}", e))?;
+        state.policy_sources = self
+            .policy_sources
+            .into_iter()
+            .map(|(id, src)| (PolicyId::new(id), src))
+            .collect();
+        state.layered = self.layered;
+        state.disabled = self.disabled.into_iter().map(PolicyId::new).collect();
+        Ok(())
+    }
+}
+
+fn parse_schema(json: Option<&Value>) -> Result<Option<Schema>, String> {
+    json.map(|json| Schema::from_json_value(json.clone()).map_err(|e| format!("Invalid schema: {}", e)))
+        .transpose()
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct Snapshot {
+    pub epoch: String,
+    pub version: u64,
+    #[serde(flatten)]
+    pub policies: Policies,
+    pub schema: Option<Value>,
+    /// Stored entities in Cedar's JSON format.
+    pub entities: Vec<Value>,
+}
+
+impl Snapshot {
+    fn new(epoch: &str, version: u64, state: &PolicyState) -> Result<Self, String> {
+        Ok(Self {
+            epoch: epoch.to_string(),
+            version,
+            policies: Policies::new(state)?,
+            schema: state.schema_json.clone(),
+            entities: state.entities.direct().iter().map(entities::to_json).collect::<Result<_, _>>()?,
+        })
+    }
+
+    /// Parses the snapshot into a state, ingesting entities as this agent is configured to.
+    fn into_state(self, service: &CedarService) -> Result<PolicyState, String> {
+        let schema = parse_schema(self.schema.as_ref())?;
+        let entities = EntityStore::from_json(Value::Array(self.entities), schema.as_ref(), service.state().entities.ingest())
+            .map_err(|e| format!("Invalid entities: {}", e))?;
+        entities.check_budget(service.entity_memory_limit)?;
+        let mut state = PolicyState::new(Default::default(), schema, self.schema, Arc::new(entities));
+        self.policies.apply(&mut state)?;
+        Ok(state)
+    }
+}
+
+/// The changes from one version of the leader's state to the next: the policies if any
+/// changed, the schema if it changed (`null` once removed), and the entities that were removed,
+/// added or changed.
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct Delta {
+    pub epoch: String,
+    /// The version the changes apply to.
+    pub base: u64,
+    pub version: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub policies: Option<Policies>,
+    #[serde(default, skip_serializing_if = "Option::is_none", deserialize_with = "entities::present")]
+    pub schema: Option<Value>,
+    /// UIDs of the entities that were removed.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub removed: Vec<Value>,
+    /// Entities that were added or changed, replacing any with the same UID.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub upserted: Vec<Value>,
+}
+
+impl Delta {
+    fn new(epoch: &str, base: (u64, &PolicyState), version: u64, state: &PolicyState) -> Result<Self, String> {
+        let (base, previous) = base;
+        let policies_changed = previous.policy_set != state.policy_set
+            || previous.policy_sources != state.policy_sources
+            || previous.layered != state.layered
+            || previous.disabled != state.disabled;
+        let (removed, upserted) = if Arc::ptr_eq(&previous.entities, &state.entities) {
+            Default::default()
+        } else {
+            entities::changes(previous.entities.direct(), state.entities.direct())?
+        };
+        Ok(Self {
+            epoch: epoch.to_string(),
+            base,
+            version,
+            policies: if policies_changed { Some(Policies::new(state)?) } else { None },
+            schema: (previous.schema_json != state.schema_json).then(|| state.schema_json.clone().unwrap_or(Value::Null)),
+            removed,
+            upserted,
+        })
+    }
+
+    /// Applies the changes to `current`, ingesting entities as it was. The entities must stay
+    /// within `memory_limit`.
+    fn apply(self, current: &PolicyState, memory_limit: Option<usize>) -> Result<PolicyState, String> {
+        let (schema, schema_json) = match self.schema {
+            Some(Value::Null) => (None, None),
+            Some(json) => (parse_schema(Some(&json))?, Some(json)),
+            None => (current.schema.clone(), current.schema_json.clone()),
+        };
+        let entities = if schema_json == current.schema_json && self.removed.is_empty() && self.upserted.is_empty() {
+            Arc::clone(&current.entities)
+        } else {
+            let direct = entities::apply_changes(current.entities.direct(), self.removed, self.upserted, schema.as_ref())?;
+            let entities = current
+                .entities
+                .replaced_with(direct, schema.as_ref())
+                .map_err(|e| format!("Invalid entities: {}", e))?;
+            entities.check_budget(memory_limit)?;
+            Arc::new(entities)
+        };
+        let mut state = PolicyState {
+            disabled: current.disabled.clone(),
+            ..PolicyState::new(current.policies(), schema, schema_json, entities)
+        };
+        if let Some(policies) = self.policies {
+            policies.apply(&mut state)?;
+        }
+        Ok(state)
+    }
+}
+
+/// Serves the agent's state to replicas on `GET /admin/replication/stream`.
+pub struct Leader {
+    token_digest: [u8; 32],
+    /// Identifies this run of the agent. Data versions restart at zero with every start, so a
+    /// replica resuming from another epoch is sent a full snapshot.
+    epoch: String,
+}
+
+impl Leader {
+    pub fn new(token: &str) -> Self {
+        let started = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        Self {
+            token_digest: digest::sha256(token.as_bytes()),
+            epoch: format!("{:x}", started.as_nanos()),
+        }
+    }
+
+    fn authorized(&self, header: Option<&str>) -> bool {
+        header
+            .and_then(|h| h.strip_prefix("Bearer "))
+            .is_some_and(|token| digest::sha256(token.trim().as_bytes()) == self.token_digest)
+    }
+}
+
+/// `GET /admin/replication/stream?since=&epoch=`: newline-delimited JSON messages. The stream
+/// opens with a snapshot unless the replica already holds `since` from this epoch, then carries
+/// a delta for every change, a snapshot in place of every `SNAPSHOT_EVERY`th, and a heartbeat
+/// while idle.
+pub fn stream(req: &hyper::Request<Body>, service: &Arc<CedarService>) -> Response<Body> {
+    let Some(ref leader) = service.replication else {
+        return error_response(StatusCode::NOT_FOUND, "Replication is not configured");
+    };
+    let header = req.headers().get(AUTHORIZATION).and_then(|h| h.to_str().ok());
+    if !leader.authorized(header) {
+        return error_response(StatusCode::UNAUTHORIZED, "Invalid replication token");
+    }
+    let params = query_params(req.uri());
+    let resume = match (params.get("epoch"), params.get("since").map(|since| since.parse::<u64>())) {
+        (_, Some(Err(e))) => return error_response(StatusCode::BAD_REQUEST, format!("Invalid since: {}", e)),
+        (Some(epoch), Some(Ok(since))) if *epoch == leader.epoch => Some(since),
+        _ => None,
+    };
+
+    let (mut sender, body) = Body::channel();
+    let epoch = leader.epoch.clone();
+    let service = Arc::clone(service);
+    tokio::spawn(async move {
+        let mut versions = service.data_version.subscribe();
+        // The version and state the replica holds, which the next delta applies to
+        let mut sent: Option<(u64, Arc<PolicyState>)> = None;
+        let mut deltas = 0;
+        loop {
+            versions.borrow_and_update();
+            let (version, state) = service.versioned_state();
+            let message = match sent {
+                Some((base, _)) if base == version => Ok(Message::Heartbeat { epoch: epoch.clone(), version }),
+                None if resume == Some(version) => Ok(Message::Heartbeat { epoch: epoch.clone(), version }),
+                Some((base, ref previous)) if deltas < SNAPSHOT_EVERY => {
+                    Delta::new(&epoch, (base, previous), version, &state).map(|delta| Message::Delta(Box::new(delta)))
+                }
+                _ => Snapshot::new(&epoch, version, &state).map(|snapshot| Message::Snapshot(Box::new(snapshot))),
+            };
+            let message = match message {
+                Ok(message) => message,
+                Err(e) => {
+                    error!("Failed to send version {} to a replica: {}", version, e);
+                    break;
+                }
+            };
+            let mut line = serde_json::to_vec(&message).unwrap();
+            line.push(b'\n');
+            // The replica went away
+            if sender.send_data(line.into()).await.is_err() {
+                break;
+            }
+            match message {
+                Message::Snapshot(_) => {
+                    service.metrics.incr("replication_snapshots", &[("direction", "sent")]);
+                    deltas = 0;
+                }
+                Message::Delta(_) => {
+                    service.metrics.incr("replication_deltas", &[("direction", "sent")]);
+                    deltas += 1;
+                }
+                Message::Heartbeat { .. } => {}
+            }
+            sent = Some((version, state));
+            let _ = tokio::time::timeout(HEARTBEAT_INTERVAL, versions.changed()).await;
+        }
+    });
+    Response::builder()
+        .header("content-type", "application/x-ndjson")
+        .body(body)
+        .unwrap()
+}
+
+/// Requests that change the agent's state. A replica refuses them: its state only ever comes
+/// from the leader.
+pub fn is_write(method: &Method, path: &str) -> bool {
+    match path {
+        "/v1/data/entities" => method == Method::PUT || method == Method::PATCH,
+        "/v1/schema" => method == Method::PUT,
+        "/admin/reload" | "/admin/staging/promote" | "/admin/import" => method == Method::POST,
+        _ if path.starts_with(crate::proposals::PATH) => method != Method::GET,
+        "/v1/policies/disable" | "/v1/policies/enable" => method == Method::POST,
+        _ if crate::policies::toggle(path).is_some() => method == Method::POST,
+        _ => path.starts_with("/scim/") && method != Method::GET,
+    }
+}
+
+/// The stream endpoint of the leader at `base`, e.g. `http://leader:8181` or
+/// `https://leader:8443`.
+fn stream_uri(base: &str) -> Result<String, String> {
+    let uri = format!("{}/admin/replication/stream", base.trim_end_matches('/'));
+    let parsed: Uri = uri.parse().map_err(|e| format!("Invalid CEDAR_REPLICA_OF: {}", e))?;
+    if !matches!(parsed.scheme_str(), Some("http" | "https")) || parsed.host().is_none() {
+        return Err(format!("Invalid CEDAR_REPLICA_OF: expected http(s)://host:port, got {}", base));
+    }
+    Ok(uri)
+}
+
+/// Splits a byte stream into lines, holding a partial line until the rest arrives.
+#[derive(Default)]
+struct Lines {
+    partial: Vec<u8>,
+}
+
+impl Lines {
+    fn push(&mut self, chunk: &[u8]) -> Vec<Vec<u8>> {
+        self.partial.extend_from_slice(chunk);
+        let mut lines = Vec::new();
+        while let Some(end) = self.partial.iter().position(|b| *b == b'\n') {
+            let mut line: Vec<u8> = self.partial.drain(..=end).collect();
+            line.pop();
+            if !line.is_empty() {
+                lines.push(line);
+            }
+        }
+        lines
+    }
+}
+
+/// The epoch and version last applied, where a reconnect resumes from.
+type Position = Option<(String, u64)>;
+
+/// Reads the leader's stream until it breaks, applying every snapshot and delta. Returns why it
+/// stopped.
+async fn follow<C: Connect + Clone + Send + Sync + 'static>(
+    client: &Client<C>,
+    stream: &str,
+    token: &str,
+    service: &CedarService,
+    position: &mut Position,
+    backoff: &mut Duration,
+) -> String {
+    let uri = match position {
+        Some((epoch, version)) => format!("{}?since={}&epoch={}", stream, version, epoch),
+        None => stream.to_string(),
+    };
+    let req = hyper::Request::get(uri)
+        .header(AUTHORIZATION, format!("Bearer {}", token))
+        .body(Body::empty())
+        .expect("valid replication request");
+    let resp = match client.request(req).await {
+        Ok(resp) => resp,
+        Err(e) => return format!("Failed to connect to the leader: {}", e),
+    };
+    let status = resp.status();
+    if !status.is_success() {
+        let body = hyper::body::to_bytes(resp.into_body()).await.unwrap_or_default();
+        return format!("The leader refused the stream ({}): {}", status, String::from_utf8_lossy(&body).trim());
+    }
+
+    let mut body = resp.into_body();
+    let mut lines = Lines::default();
+    loop {
+        let chunk = match tokio::time::timeout(IDLE_TIMEOUT, body.data()).await {
+            Ok(Some(Ok(chunk))) => chunk,
+            Ok(Some(Err(e))) => return format!("Failed to read the replication stream: {}", e),
+            Ok(None) => return "The leader closed the stream".to_string(),
+            Err(_) => return format!("Nothing from the leader for {}s", IDLE_TIMEOUT.as_secs()),
+        };
+        for line in lines.push(&chunk) {
+            let message = match serde_json::from_slice::<Message>(&line) {
+                Ok(message) => message,
+                Err(e) => return format!("Invalid replication message: {}", e),
+            };
+            *backoff = INITIAL_BACKOFF;
+            match message {
+                Message::Snapshot(snapshot) => {
+                    let (epoch, version) = (snapshot.epoch.clone(), snapshot.version);
+                    match snapshot.into_state(service) {
+                        Ok(state) => service.replicate(state, version),
+                        Err(e) => return format!("Failed to apply snapshot {}: {}", version, e),
+                    }
+                    info!("Replicated version {} from the leader", version);
+                    service.metrics.incr("replication_snapshots", &[("direction", "applied")]);
+                    *position = Some((epoch, version));
+                }
+                Message::Delta(delta) => {
+                    let (epoch, version) = (delta.epoch.clone(), delta.version);
+                    if position.as_ref() != Some(&(epoch.clone(), delta.base)) {
+                        // Start over from a snapshot
+                        *position = None;
+                        return format!("Delta {} does not follow the version applied", version);
+                    }
+                    match delta.apply(&service.state(), service.entity_memory_limit) {
+                        Ok(state) => service.replicate(state, version),
+                        Err(e) => {
+                            *position = None;
+                            return format!("Failed to apply delta {}: {}", version, e);
+                        }
+                    }
+                    info!("Replicated version {} from the leader", version);
+                    service.metrics.incr("replication_deltas", &[("direction", "applied")]);
+                    *position = Some((epoch, version));
+                }
+                Message::Heartbeat { .. } => {}
+            }
+            service.source_refreshed("leader");
+        }
+    }
+}
+
+/// Follows the leader at `CEDAR_REPLICA_OF` in the background, reconnecting with backoff and
+/// resuming from the last applied version whenever the stream breaks.
+pub fn start(service: Arc<CedarService>, config: &Config) -> Result<(), Box<dyn std::error::Error>> {
+    let Some(ref leader) = config.replica_of else {
+        return Ok(());
+    };
+    let stream = stream_uri(leader)?;
+    let token = config
+        .replication_token
+        .clone()
+        .ok_or("CEDAR_REPLICA_OF requires CEDAR_REPLICATION_TOKEN")?;
+    info!("Replicating state from {}", leader);
+    service.source_failed("leader", "Waiting for the first snapshot");
+
+    // A plain-HTTP leader needs no CA certificates, so only an https:// one loads them
+    if stream.starts_with("https://") {
+        let client = fetch::https_client().map_err(|e| format!("{} for CEDAR_REPLICA_OF", e))?;
+        tokio::spawn(replicate(client, stream, token, service));
+    } else {
+        tokio::spawn(replicate(Client::new(), stream, token, service));
+    }
+    Ok(())
+}
+
+async fn replicate<C: Connect + Clone + Send + Sync + 'static>(
+    client: Client<C>,
+    stream: String,
+    token: String,
+    service: Arc<CedarService>,
+) {
+    let mut position = None;
+    let mut backoff = INITIAL_BACKOFF;
+    loop {
+        let reason = follow(&client, &stream, &token, &service, &mut position, &mut backoff).await;
+        warn!("Replication stream stopped: {}; reconnecting in {}s", reason, backoff.as_secs());
+        service.source_failed("leader", &reason);
+        tokio::time::sleep(backoff).await;
+        backoff = (backoff * 2).min(MAX_BACKOFF);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn messages_round_trip_as_tagged_lines() {
+        let heartbeat = Message::Heartbeat { epoch: "e1".to_string(), version: 7 };
+        let json = serde_json::to_value(&heartbeat).unwrap();
+        assert_eq!(json, json!({"type": "heartbeat", "epoch": "e1", "version": 7}));
+        assert_eq!(serde_json::from_value::<Message>(json).unwrap(), heartbeat);
+
+        let snapshot = Message::Snapshot(Box::new(Snapshot {
+            epoch: "e1".to_string(),
+            version: 8,
+            policies: Policies {
+                policies: json!({"staticPolicies": {}, "templates": {}, "templateLinks": []}),
+                policy_sources: BTreeMap::new(),
+                layered: false,
+                disabled: vec!["policy0".to_string()],
+            },
+            schema: None,
+            entities: vec![json!({"uid": {"type": "User", "id": "alice"}, "attrs": {}, "parents": []})],
+        }));
+        let json = serde_json::to_value(&snapshot).unwrap();
+        assert_eq!(json["type"], "snapshot");
+        assert_eq!(json["disabled"], json!(["policy0"]));
+        assert_eq!(serde_json::from_value::<Message>(json).unwrap(), snapshot);
+
+        let delta = Message::Delta(Box::new(Delta {
+            epoch: "e1".to_string(),
+            base: 8,
+            version: 9,
+            policies: None,
+            schema: Some(Value::Null),
+            removed: vec![json!({"type": "User", "id": "alice"})],
+            upserted: Vec::new(),
+        }));
+        let json = serde_json::to_value(&delta).unwrap();
+        assert_eq!(json, json!({"type": "delta", "epoch": "e1", "base": 8, "version": 9, "schema": null,
+                                "removed": [{"type": "User", "id": "alice"}]}));
+        assert_eq!(serde_json::from_value::<Message>(json).unwrap(), delta);
+    }
+
+    fn state(policies: &str, entities: Value) -> PolicyState {
+        let loaded = crate::policies::LoadedPolicies::from_set(policies.parse().unwrap(), "test");
+        let entities = EntityStore::from_json(entities, None, entities::Ingest::default()).unwrap();
+        PolicyState::new(loaded, None, None, Arc::new(entities))
+    }
+
+    #[test]
+    fn deltas_carry_only_what_changed() {
+        let user = |id: &str, admin: bool| json!({"uid": {"type": "User", "id": id}, "attrs": {"admin": admin}, "parents": []});
+        let before = state("permit (principal, action, resource);", json!([user("a", false), user("b", false)]));
+        let after = state("permit (principal, action, resource);", json!([user("b", true), user("c", false)]));
+
+        let delta = Delta::new("e1", (4, &before), 5, &after).unwrap();
+        assert_eq!((delta.base, delta.version), (4, 5));
+        assert_eq!(delta.policies, None);
+        assert_eq!(delta.schema, None);
+        assert_eq!(delta.removed, [json!({"type": "User", "id": "a"})]);
+        assert_eq!(delta.upserted.len(), 2);
+
+        let applied = delta.apply(&before, None).unwrap();
+        let mut ids: Vec<String> = applied.entities.direct().iter().map(|e| e.uid().id().unescaped().to_string()).collect();
+        ids.sort();
+        assert_eq!(ids, ["b", "c"]);
+        let b = applied.entities.direct().iter().find(|e| e.uid().id().unescaped() == "b").unwrap();
+        assert_eq!(entities::to_json(b).unwrap()["attrs"]["admin"], json!(true));
+
+        let forbid = state("forbid (principal, action, resource);", json!([user("b", true), user("c", false)]));
+        let delta = Delta::new("e1", (5, &applied), 6, &forbid).unwrap();
+        assert!(delta.policies.is_some());
+        assert_eq!(delta.apply(&applied, None).unwrap().policy_set, forbid.policy_set);
+    }
+
+    #[test]
+    fn splits_lines_across_chunks() {
+        let mut lines = Lines::default();
+        assert!(lines.push(b"{\"a\"").is_empty());
+        assert_eq!(lines.push(b":1}\n\n{\"b\":2}\n{\"c\""), [b"{\"a\":1}".to_vec(), b"{\"b\":2}".to_vec()]);
+        assert_eq!(lines.push(b":3}\n"), [b"{\"c\":3}".to_vec()]);
+    }
+
+    #[test]
+    fn replicas_refuse_writes_and_need_an_http_or_https_leader() {
+        assert!(is_write(&Method::PATCH, "/v1/data/entities"));
+        assert!(is_write(&Method::PUT, "/v1/schema"));
+        assert!(is_write(&Method::POST, "/admin/reload"));
+        assert!(is_write(&Method::DELETE, "/scim/v2/Users/1"));
+        assert!(!is_write(&Method::GET, "/scim/v2/Users"));
+        assert!(is_write(&Method::POST, "/v1/policies/admin-console/disable"));
+        assert!(!is_write(&Method::POST, "/authorize"));
+        assert!(!is_write(&Method::POST, "/v1/data/entities/validate"));
+
+        assert_eq!(stream_uri("http://leader:8181/").unwrap(), "http://leader:8181/admin/replication/stream");
+        assert_eq!(stream_uri("https://leader:8443").unwrap(), "https://leader:8443/admin/replication/stream");
+        assert!(stream_uri("ftp://leader:8181").is_err());
+        assert!(stream_uri("leader:8181").is_err());
+    }
+
+    #[test]
+    fn only_the_shared_token_opens_the_stream() {
+        let leader = Leader::new("s3cret");
+        assert!(leader.authorized(Some("Bearer s3cret")));
+        assert!(!leader.authorized(Some("Bearer other")));
+        assert!(!leader.authorized(Some("s3cret")));
+        assert!(!leader.authorized(None));
+    }
+}
@@ -0,0 +1,114 @@
+use crate::config::ResponseFormat;
+use serde_json::{json, Map, Value};
+
+/// Request header that picks the response format for one request, overriding
+/// `CEDAR_RESPONSE_FORMAT`.
+pub const HEADER: &str = "x-cedar-response-format";
+
+/// `snake_case` to `camelCase`.
+fn camel_case(key: &str) -> String {
+    let mut camel = String::with_capacity(key.len());
+    let mut upper = false;
+    for c in key.chars() {
+        match c {
+            '_' if !camel.is_empty() => upper = true,
+            c if upper => {
+                camel.extend(c.to_uppercase());
+                upper = false;
+            }
+            c => camel.push(c),
+        }
+    }
+    camel
+}
+
+/// Renames the keys of every object in `value`. Maps keyed by policy ID (`sources`) keep their
+/// keys, as those are data rather than field names.
+fn camel_keys(value: Value) -> Value {
+    match value {
+        Value::Object(fields) => Value::Object(
+            fields
+                .into_iter()
+                .map(|(key, value)| {
+                    let value = if key == "sources" { value } else { camel_keys(value) };
+                    (camel_case(&key), value)
+                })
+                .collect::<Map<String, Value>>(),
+        ),
+        Value::Array(items) => Value::Array(items.into_iter().map(camel_keys).collect()),
+        other => other,
+    }
+}
+
+/// An `/authorize` response in `format`.
+pub fn render(format: ResponseFormat, response: Value) -> Value {
+    match format {
+        ResponseFormat::Cedar => response,
+        ResponseFormat::Allowed => {
+            let mut response = response;
+            let allowed = response["decision"] == "Allow";
+            if let Some(fields) = response.as_object_mut() {
+                fields.insert("allowed".to_string(), Value::Bool(allowed));
+            }
+            response
+        }
+        ResponseFormat::Camel => camel_keys(response),
+        ResponseFormat::Avp => {
+            let diagnostics = &response["diagnostics"];
+            let ids = |field: &str| diagnostics[field].as_array().cloned().unwrap_or_default();
+            json!({
+                "decision": response["decision"].as_str().unwrap_or("Deny").to_uppercase(),
+                "determiningPolicies": ids("reason")
+                    .into_iter()
+                    .map(|id| json!({"policyId": id}))
+                    .collect::<Vec<_>>(),
+                "errors": ids("errors")
+                    .into_iter()
+                    .map(|message| json!({"errorDescription": message}))
+                    .collect::<Vec<_>>(),
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn response() -> Value {
+        json!({
+            "decision": "Deny",
+            "diagnostics": {
+                "reason": ["no_weekends"],
+                "errors": ["policy `owner`: attribute `owner` not found"],
+                "denied_by": "forbid",
+                "error_details": [{"policy": "owner", "message": "..."}],
+                "sources": {"no_weekends": "base.cedar"}
+            }
+        })
+    }
+
+    #[test]
+    fn converts_keys_to_camel_case() {
+        assert_eq!(camel_case("default_decision"), "defaultDecision");
+        assert_eq!(camel_case("decision"), "decision");
+        let camel = render(ResponseFormat::Camel, response());
+        assert_eq!(camel["diagnostics"]["deniedBy"], "forbid");
+        assert_eq!(camel["diagnostics"]["errorDetails"][0]["policy"], "owner");
+        assert_eq!(camel["diagnostics"]["sources"]["no_weekends"], "base.cedar");
+    }
+
+    #[test]
+    fn renders_allowed_and_avp_shapes() {
+        assert_eq!(render(ResponseFormat::Allowed, response())["allowed"], false);
+        assert_eq!(render(ResponseFormat::Cedar, response()), response());
+        assert_eq!(
+            render(ResponseFormat::Avp, response()),
+            json!({
+                "decision": "DENY",
+                "determiningPolicies": [{"policyId": "no_weekends"}],
+                "errors": [{"errorDescription": "policy `owner`: attribute `owner` not found"}]
+            })
+        );
+    }
+}
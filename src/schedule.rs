@@ -0,0 +1,334 @@
+use chrono::{DateTime, Utc};
+use cedar_policy::{Effect, Policy, PolicyId, PolicySet};
+use log::{error, log};
+use std::collections::{BTreeSet, HashMap};
+use std::sync::{Arc, Mutex, OnceLock};
+
+/// Annotations that limit when a static policy is in force: `@effective_from("2026-11-01T00:00:00Z")`
+/// and `@expires_at("2026-11-02T00:00:00Z")`.
+pub const EFFECTIVE_FROM: &str = "effective_from";
+pub const EXPIRES_AT: &str = "expires_at";
+
+/// When a policy is in force: from `from` (inclusive) until `until` (exclusive).
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Window {
+    from: Option<DateTime<Utc>>,
+    until: Option<DateTime<Utc>>,
+}
+
+impl Window {
+    /// A window that is never open.
+    const NEVER: Window = Window {
+        from: None,
+        until: Some(DateTime::<Utc>::MIN_UTC),
+    };
+
+    fn contains(&self, now: DateTime<Utc>) -> bool {
+        self.from.is_none_or(|from| from <= now) && self.until.is_none_or(|until| now < until)
+    }
+}
+
+/// The time window of a static policy, if it carries one.
+fn window(policy: &Policy) -> Result<Option<Window>, String> {
+    let time = |key: &str| {
+        policy
+            .annotation(key)
+            .map(|value| crate::clock::parse_time(value).map_err(|e| format!("Invalid @{} on {}: {}", key, policy.id(), e)))
+            .transpose()
+    };
+    let window = Window {
+        from: time(EFFECTIVE_FROM)?,
+        until: time(EXPIRES_AT)?,
+    };
+    if let (Some(from), Some(until)) = (window.from, window.until) {
+        if until <= from {
+            return Err(format!("@{} of {} is not after its @{}", EXPIRES_AT, policy.id(), EFFECTIVE_FROM));
+        }
+    }
+    Ok((window.from.is_some() || window.until.is_some()).then_some(window))
+}
+
+/// Checks the time windows of a policy set's static policies: a malformed timestamp, or a
+/// window that ends before it starts, is an error.
+pub fn check(policy_set: &PolicySet) -> Result<(), String> {
+    policy_set.policies().filter(|p| p.is_static()).try_for_each(|policy| window(policy).map(|_| ()))
+}
+
+/// The time windows of the static policies that carry one. A window that does not parse only
+/// affects its own policy, which fails closed: a `permit` is never in force, a `forbid` always.
+fn windows(policy_set: &PolicySet) -> HashMap<PolicyId, Window> {
+    let mut windows = HashMap::new();
+    for policy in policy_set.policies().filter(|p| p.is_static()) {
+        match window(policy) {
+            Ok(Some(window)) => {
+                windows.insert(policy.id().clone(), window);
+            }
+            Ok(None) => {}
+            Err(e) => {
+                error!("{}; the policy is {}", e, if policy.effect() == Effect::Permit { "not in force" } else { "kept in force" });
+                if policy.effect() == Effect::Permit {
+                    windows.insert(policy.id().clone(), Window::NEVER);
+                }
+            }
+        }
+    }
+    windows
+}
+
+/// The policies in force at some time, and the span of time they stay so.
+struct Effective {
+    since: Option<DateTime<Utc>>,
+    until: Option<DateTime<Utc>>,
+    policy_set: Arc<PolicySet>,
+    /// Scheduled policies not in force.
+    dormant: Vec<String>,
+}
+
+/// The permits of the policies in force, without the forbids, and the set they were taken from.
+struct Permits {
+    /// The effective set, or `None` for the whole policy set.
+    from: Option<Arc<PolicySet>>,
+    /// `None` when the forbids could not be taken out.
+    policy_set: Option<Arc<PolicySet>>,
+}
+
+/// Which of a policy set's policies are in force, leaving out the disabled ones and those outside
+/// their window; worked out when first needed and again only when a window opens or closes.
+#[derive(Default)]
+pub struct Schedule {
+    windows: OnceLock<HashMap<PolicyId, Window>>,
+    effective: Mutex<Option<Effective>>,
+    permits: Mutex<Option<Permits>>,
+}
+
+impl Schedule {
+    /// `policy_set` without the `disabled` policies and the scheduled ones that are not in force
+    /// at `now`; `None` when it disables and schedules none. Every source rejects windows that
+    /// do not parse, but should one get through, only its own policy is affected: a `permit` is
+    /// left out and a `forbid` kept. `disabled` must be the same on every call.
+    pub fn effective(&self, policy_set: &PolicySet, disabled: &BTreeSet<PolicyId>, now: DateTime<Utc>) -> Option<Arc<PolicySet>> {
+        let windows = self.windows.get_or_init(|| windows(policy_set));
+        if windows.is_empty() && disabled.is_empty() {
+            return None;
+        }
+        let mut effective = self.effective.lock().unwrap();
+        if let Some(ref cached) = *effective {
+            let span = Window { from: cached.since, until: cached.until };
+            if span.contains(now) {
+                return Some(Arc::clone(&cached.policy_set));
+            }
+        }
+
+        let mut set = policy_set.clone();
+        for id in disabled {
+            if set.remove_static(id.clone()).is_err() {
+                // Template-linked
+                let _ = set.unlink(id.clone());
+            }
+        }
+        let mut dormant = Vec::new();
+        for (id, window) in windows {
+            if !window.contains(now) && !disabled.contains(id) && set.remove_static(id.clone()).is_ok() {
+                dormant.push(id.to_string());
+            }
+        }
+        dormant.sort();
+        let boundaries = windows.values().filter(|w| **w != Window::NEVER).flat_map(|w| [w.from, w.until]).flatten();
+        let (since, until) = span(boundaries, now);
+        if !windows.is_empty() {
+            // A window opening or closing is worth noting; working out a new state's set is not
+            let level = match *effective {
+                Some(ref previous) if previous.dormant != dormant => log::Level::Info,
+                _ => log::Level::Debug,
+            };
+            log!(
+                level,
+                "{} of {} scheduled policies in force{}",
+                windows.len() - dormant.len(),
+                windows.len(),
+                if dormant.is_empty() { String::new() } else { format!(" (not in force: {})", dormant.join(", ")) }
+            );
+        }
+        let policy_set = Arc::new(set);
+        *effective = Some(Effective {
+            since,
+            until,
+            policy_set: Arc::clone(&policy_set),
+            dormant,
+        });
+        Some(policy_set)
+    }
+
+    /// The policies in force without their forbids, to find the permits a forbid overrode:
+    /// `effective` as `effective()` gave it, else `policy_set`. Worked out when first needed and
+    /// again only when the policies in force change; `None` if a forbid cannot be taken out.
+    pub fn permits(&self, policy_set: &PolicySet, effective: Option<&Arc<PolicySet>>) -> Option<Arc<PolicySet>> {
+        let mut permits = self.permits.lock().unwrap();
+        let current = match (permits.as_ref(), effective) {
+            (Some(cached), Some(effective)) => cached.from.as_ref().is_some_and(|from| Arc::ptr_eq(from, effective)),
+            (Some(cached), None) => cached.from.is_none(),
+            (None, _) => false,
+        };
+        if !current {
+            *permits = Some(Permits {
+                from: effective.cloned(),
+                policy_set: without_forbids(effective.map_or(policy_set, |set| set)).map(Arc::new),
+            });
+        }
+        permits.as_ref().and_then(|permits| permits.policy_set.clone())
+    }
+}
+
+fn without_forbids(policy_set: &PolicySet) -> Option<PolicySet> {
+    let mut permits = policy_set.clone();
+    let forbids: Vec<(PolicyId, bool)> = policy_set
+        .policies()
+        .filter(|p| p.effect() == Effect::Forbid)
+        .map(|p| (p.id().clone(), p.is_static()))
+        .collect();
+    for (id, is_static) in forbids {
+        let removed = if is_static {
+            permits.remove_static(id).is_ok()
+        } else {
+            permits.unlink(id).is_ok()
+        };
+        if !removed {
+            return None;
+        }
+    }
+    Some(permits)
+}
+
+/// The latest boundary at or before `now` and the earliest after it: the span in which no
+/// window opens or closes.
+fn span(boundaries: impl Iterator<Item = DateTime<Utc>>, now: DateTime<Utc>) -> (Option<DateTime<Utc>>, Option<DateTime<Utc>>) {
+    let (mut since, mut until) = (None, None);
+    for boundary in boundaries {
+        if boundary <= now {
+            since = since.max(Some(boundary));
+        } else {
+            until = Some(until.map_or(boundary, |until: DateTime<Utc>| until.min(boundary)));
+        }
+    }
+    (since, until)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn time(s: &str) -> DateTime<Utc> {
+        crate::clock::parse_time(s).unwrap()
+    }
+
+    fn policies() -> PolicySet {
+        crate::policies::parse(
+            r#"@id("always") permit(principal, action, resource);
+               @id("launch") @effective_from("2026-11-01T00:00:00Z") permit(principal, action, resource);
+               @id("break-glass") @effective_from("2026-10-15T08:00:00Z") @expires_at("2026-10-15T12:00:00Z")
+               permit(principal, action, resource);"#,
+        )
+        .unwrap()
+    }
+
+    fn ids(policy_set: Option<Arc<PolicySet>>) -> Vec<String> {
+        let policy_set = policy_set.unwrap();
+        let mut ids: Vec<String> = policy_set.policies().map(|p| p.id().to_string()).collect();
+        ids.sort();
+        ids
+    }
+
+    #[test]
+    fn keeps_policies_in_force_within_their_window() {
+        let (policy_set, schedule) = (policies(), Schedule::default());
+        assert_eq!(ids(schedule.effective(&policy_set, &BTreeSet::new(), time("2026-10-15T07:00:00Z"))), ["always"]);
+        assert_eq!(ids(schedule.effective(&policy_set, &BTreeSet::new(), time("2026-10-15T08:00:00Z"))), ["always", "break-glass"]);
+        assert_eq!(ids(schedule.effective(&policy_set, &BTreeSet::new(), time("2026-10-15T11:59:59Z"))), ["always", "break-glass"]);
+        assert_eq!(ids(schedule.effective(&policy_set, &BTreeSet::new(), time("2026-10-15T12:00:00Z"))), ["always"]);
+        assert_eq!(ids(schedule.effective(&policy_set, &BTreeSet::new(), time("2026-11-01T00:00:00Z"))), ["always", "launch"]);
+        // Going back in time (a fixed clock in tests) works out the set again
+        assert_eq!(ids(schedule.effective(&policy_set, &BTreeSet::new(), time("2026-10-15T09:00:00Z"))), ["always", "break-glass"]);
+    }
+
+    #[test]
+    fn leaves_out_disabled_policies() {
+        let (policy_set, schedule) = (policies(), Schedule::default());
+        let disabled = BTreeSet::from([PolicyId::new("always"), PolicyId::new("break-glass")]);
+        assert_eq!(ids(schedule.effective(&policy_set, &disabled, time("2026-10-15T09:00:00Z"))), Vec::<String>::new());
+        assert_eq!(ids(schedule.effective(&policy_set, &disabled, time("2026-11-01T00:00:00Z"))), ["launch"]);
+
+        let mut linked = crate::policies::parse(
+            r#"@id("t") permit(principal == ?principal, action, resource);
+               @id("p") permit(principal, action, resource);"#,
+        )
+        .unwrap();
+        let principal = r#"User::"alice""#.parse().unwrap();
+        linked
+            .link(PolicyId::new("t"), PolicyId::new("alice"), [(cedar_policy::SlotId::principal(), principal)].into())
+            .unwrap();
+        let disabled = BTreeSet::from([PolicyId::new("alice")]);
+        assert_eq!(ids(Schedule::default().effective(&linked, &disabled, Utc::now())), ["p"]);
+    }
+
+    #[test]
+    fn keeps_the_permits_until_the_policies_in_force_change() {
+        let mut policy_set = policies();
+        policy_set.add(Policy::parse(Some(PolicyId::new("deny")), "forbid(principal, action, resource);").unwrap()).unwrap();
+        let schedule = Schedule::default();
+        let permits = schedule.permits(&policy_set, None);
+        assert_eq!(ids(permits.clone()), ["always", "break-glass", "launch"]);
+        assert!(Arc::ptr_eq(permits.as_ref().unwrap(), schedule.permits(&policy_set, None).as_ref().unwrap()));
+
+        let effective = schedule.effective(&policy_set, &BTreeSet::new(), time("2026-10-15T09:00:00Z"));
+        let permits = schedule.permits(&policy_set, effective.as_ref());
+        assert_eq!(ids(permits.clone()), ["always", "break-glass"]);
+        assert!(Arc::ptr_eq(permits.as_ref().unwrap(), schedule.permits(&policy_set, effective.as_ref()).as_ref().unwrap()));
+        let effective = schedule.effective(&policy_set, &BTreeSet::new(), time("2026-10-15T13:00:00Z"));
+        assert_eq!(ids(schedule.permits(&policy_set, effective.as_ref())), ["always"]);
+    }
+
+    #[test]
+    fn leaves_unscheduled_policy_sets_alone() {
+        let policy_set = crate::policies::parse("permit(principal, action, resource);").unwrap();
+        assert!(Schedule::default().effective(&policy_set, &BTreeSet::new(), Utc::now()).is_none());
+    }
+
+    #[test]
+    fn rejects_malformed_windows() {
+        assert!(check(&policies()).is_ok());
+        for src in [
+            r#"@effective_from("tomorrow") permit(principal, action, resource);"#,
+            r#"@effective_from("2026-11-02T00:00:00Z") @expires_at("2026-11-01T00:00:00Z") permit(principal, action, resource);"#,
+        ] {
+            assert!(check(&src.parse().unwrap()).is_err());
+        }
+    }
+
+    #[test]
+    fn a_malformed_window_only_affects_its_own_policy() {
+        // Built without `policies::parse`, which would reject the bad windows
+        let policy_set: PolicySet = r#"
+            @effective_from("2026-10-15T08:00:00Z") @expires_at("2026-10-15T12:00:00Z") permit(principal, action, resource);
+            @expires_at("yesterday") permit(principal, action, resource);
+            @expires_at("yesterday") forbid(principal, action, resource);
+            permit(principal, action, resource);"#
+            .parse()
+            .unwrap();
+        assert!(check(&policy_set).is_err());
+        let schedule = Schedule::default();
+        // The good window still closes, the permit with the bad one stays out, the forbid in
+        assert_eq!(ids(schedule.effective(&policy_set, &BTreeSet::new(), time("2026-10-15T09:00:00Z"))), ["policy0", "policy2", "policy3"]);
+        assert_eq!(ids(schedule.effective(&policy_set, &BTreeSet::new(), time("2026-10-15T13:00:00Z"))), ["policy2", "policy3"]);
+    }
+
+    #[test]
+    fn spans_the_time_between_boundaries() {
+        let boundaries = [time("2026-10-15T08:00:00Z"), time("2026-10-15T12:00:00Z"), time("2026-11-01T00:00:00Z")];
+        assert_eq!(
+            span(boundaries.into_iter(), time("2026-10-15T09:00:00Z")),
+            (Some(boundaries[0]), Some(boundaries[1]))
+        );
+        assert_eq!(span(boundaries.into_iter(), time("2026-10-01T00:00:00Z")), (None, Some(boundaries[0])));
+        assert_eq!(span(boundaries.into_iter(), time("2026-12-01T00:00:00Z")), (Some(boundaries[2]), None));
+    }
+}
@@ -0,0 +1,692 @@
+use crate::entities::EntityStore;
+use cedar_policy::{Entities, EntityUid, PolicySet, Schema, ValidationMode, Validator};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use serde::Serialize;
+use serde_json::Value;
+
+/// Result of an accepted schema update.
+#[derive(Debug, Serialize)]
+pub struct SchemaUpdate {
+    pub status: &'static str,
+    pub warnings: Vec<String>,
+}
+
+/// Why a schema update was refused.
+#[derive(Debug, Serialize)]
+pub struct SchemaUpdateError {
+    pub error: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub validation_errors: Vec<String>,
+    /// The policies and entities a rejected schema would break.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub impact: Option<Box<SchemaImpact>>,
+}
+
+impl std::fmt::Display for SchemaUpdateError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.error)?;
+        if !self.validation_errors.is_empty() {
+            write!(f, ": {}", self.validation_errors.join("; "))?;
+        }
+        Ok(())
+    }
+}
+
+impl SchemaUpdateError {
+    pub fn invalid(error: String) -> Self {
+        Self {
+            error,
+            validation_errors: Vec::new(),
+            impact: None,
+        }
+    }
+
+    /// Rejects a schema that `impact` shows is incompatible with the loaded state.
+    pub fn incompatible(impact: SchemaImpact) -> Self {
+        let listed_entities = impact.entities.iter().filter(|e| e.uid.is_some()).count();
+        Self {
+            error: format!(
+                "Schema rejected: {} loaded policies and {} stored entities fail validation against it",
+                impact.policies.len(),
+                listed_entities + impact.more_entities,
+            ),
+            validation_errors: impact
+                .policies
+                .iter()
+                .flat_map(|p| p.errors.iter().cloned())
+                .chain(impact.entities.iter().map(|e| match e.uid {
+                    Some(ref uid) => format!("{}: {}", uid, e.error),
+                    None => e.error.clone(),
+                }))
+                .collect(),
+            impact: Some(Box::new(impact)),
+        }
+    }
+}
+
+/// Validates the policy set against a candidate schema in strict mode.
+pub fn check_policies(schema: &Schema, policy_set: &PolicySet) -> Result<SchemaUpdate, SchemaUpdateError> {
+    let validator = Validator::new(schema.clone());
+    let result = validator.validate(policy_set, ValidationMode::Strict);
+
+    let validation_errors: Vec<String> = result.validation_errors().map(|e| e.to_string()).collect();
+    if !validation_errors.is_empty() {
+        return Err(SchemaUpdateError {
+            error: format!(
+                "Schema rejected: {} loaded policies fail validation against it",
+                validation_errors.len()
+            ),
+            validation_errors,
+            impact: None,
+        });
+    }
+
+    Ok(SchemaUpdate {
+        status: "updated",
+        warnings: result.validation_warnings().map(|w| w.to_string()).collect(),
+    })
+}
+
+/// Validates `what` (proposed or staged policies) against the active schema, if there is one,
+/// and returns Cedar's validation warnings.
+pub fn check_candidate(schema: Option<&Schema>, policy_set: &PolicySet, what: &str) -> Result<Vec<String>, SchemaUpdateError> {
+    let Some(schema) = schema else {
+        return Ok(Vec::new());
+    };
+    match check_policies(schema, policy_set) {
+        Ok(update) => Ok(update.warnings),
+        Err(e) => Err(SchemaUpdateError {
+            error: format!("{} rejected: {} fail validation against the schema", what, e.validation_errors.len()),
+            ..e
+        }),
+    }
+}
+
+/// Stored entities an impact report lists at most; the rest are only counted.
+const MAX_LISTED_ENTITIES: usize = 100;
+
+/// A policy that fails strict validation against a candidate schema.
+#[derive(Debug, Serialize, PartialEq)]
+pub struct PolicyImpact {
+    pub id: String,
+    pub errors: Vec<String>,
+}
+
+/// A stored entity that does not conform to a candidate schema. `uid` is left out for a
+/// problem with the stored entities as a whole rather than one of them.
+#[derive(Debug, Serialize, PartialEq)]
+pub struct EntityImpact {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub uid: Option<String>,
+    pub error: String,
+}
+
+/// What a candidate schema would break: the loaded policies that fail strict validation
+/// against it and the stored entities that do not conform to it.
+#[derive(Debug, Serialize)]
+pub struct SchemaImpact {
+    pub compatible: bool,
+    pub policies: Vec<PolicyImpact>,
+    pub entities: Vec<EntityImpact>,
+    /// Nonconforming entities beyond the ones listed.
+    #[serde(skip_serializing_if = "is_zero")]
+    pub more_entities: usize,
+    pub warnings: Vec<String>,
+}
+
+fn is_zero(n: &usize) -> bool {
+    *n == 0
+}
+
+/// Checks the policy set and stored entities against a candidate schema. Also returns the
+/// entities revalidated against it, when they all conform.
+pub fn impact(schema: &Schema, policy_set: &PolicySet, entities: &EntityStore) -> (SchemaImpact, Option<EntityStore>) {
+    let result = Validator::new(schema.clone()).validate(policy_set, ValidationMode::Strict);
+    let mut by_policy: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for e in result.validation_errors() {
+        by_policy.entry(e.policy_id().to_string()).or_default().push(e.to_string());
+    }
+    let policies: Vec<PolicyImpact> = by_policy.into_iter().map(|(id, errors)| PolicyImpact { id, errors }).collect();
+    let warnings = result.validation_warnings().map(|w| w.to_string()).collect();
+
+    // Checking the whole set at once is the common, fast path; only when it fails is each
+    // entity checked on its own to find the ones that break
+    let (revalidated, failing, more_entities) = match entities.revalidate(schema) {
+        Ok(revalidated) => (Some(revalidated), Vec::new(), 0),
+        Err(e) => {
+            let mut failing = entities.direct().iter().filter_map(|entity| {
+                Entities::from_entities([entity.clone()], Some(schema)).err().map(|err| EntityImpact {
+                    uid: Some(entity.uid().to_string()),
+                    error: crate::entities::with_causes(&err),
+                })
+            });
+            let listed: Vec<EntityImpact> = failing.by_ref().take(MAX_LISTED_ENTITIES).collect();
+            let more = failing.count();
+            if listed.is_empty() {
+                (None, vec![EntityImpact { uid: None, error: e }], 0)
+            } else {
+                (None, listed, more)
+            }
+        }
+    };
+
+    let impact = SchemaImpact {
+        compatible: policies.is_empty() && failing.is_empty(),
+        policies,
+        entities: failing,
+        more_entities,
+        warnings,
+    };
+    (impact, revalidated)
+}
+
+/// Sections of a JSON schema namespace whose entries are merged by name.
+const SECTIONS: [&str; 3] = ["entityTypes", "actions", "commonTypes"];
+
+/// Adds `definition` under `name` in `map`, unless the same definition is there already. What
+/// is being defined is described by `label`, which also keys where it was first defined.
+fn define(
+    map: &mut serde_json::Map<String, Value>,
+    name: String,
+    definition: Value,
+    label: String,
+    file: &str,
+    origins: &mut HashMap<String, String>,
+) -> Result<(), String> {
+    match map.get(&name) {
+        Some(existing) if *existing != definition => {
+            Err(format!("{} is defined differently in {} and {}", label, origins[&label], file))
+        }
+        Some(_) => Ok(()),
+        None => {
+            map.insert(name, definition);
+            origins.insert(label, file.to_string());
+            Ok(())
+        }
+    }
+}
+
+/// Merges JSON schema fragments, each paired with the file it came from, into one schema. A
+/// namespace may be spread over several fragments; an entity type, action or common type
+/// defined in more than one must be defined identically, and anything else is a conflict
+/// naming both files.
+pub fn merge_fragments(fragments: Vec<(String, Value)>) -> Result<Value, String> {
+    let mut merged = serde_json::Map::new();
+    let mut origins = HashMap::new();
+    for (file, fragment) in fragments {
+        let Value::Object(namespaces) = fragment else {
+            return Err(format!("{}: a schema must be an object of namespaces", file));
+        };
+        for (namespace, body) in namespaces {
+            let Value::Object(body) = body else {
+                return Err(format!("{}: namespace `{}` must be an object", file, namespace));
+            };
+            let target = merged
+                .entry(namespace.clone())
+                .or_insert_with(|| Value::Object(Default::default()))
+                .as_object_mut()
+                .expect("namespaces are objects");
+            for (key, value) in body {
+                if !SECTIONS.contains(&key.as_str()) {
+                    // Namespace properties such as annotations
+                    let label = format!("`{}` of namespace `{}`", key, namespace);
+                    define(target, key, value, label, &file, &mut origins)?;
+                    continue;
+                }
+                let Value::Object(entries) = value else {
+                    return Err(format!("{}: `{}` of namespace `{}` must be an object", file, key, namespace));
+                };
+                let section = target
+                    .entry(key.clone())
+                    .or_insert_with(|| Value::Object(Default::default()))
+                    .as_object_mut()
+                    .ok_or_else(|| format!("`{}` of namespace `{}` must be an object", key, namespace))?;
+                for (name, definition) in entries {
+                    let label = format!("`{}` in {}", qualify(&namespace, &name), key);
+                    define(section, name, definition, label, &file, &mut origins)?;
+                }
+            }
+        }
+    }
+    Ok(Value::Object(merged))
+}
+
+/// Reads the schema at `path` and the fragments in `fragments` (files, or directories of
+/// `*.json` files), and merges them into the effective schema. A missing `path` is left out;
+/// `None` when there is no schema at all.
+pub fn load(path: &str, fragments: &[String]) -> Result<Option<(Schema, Value)>, String> {
+    let mut files: Vec<String> = Vec::new();
+    if fs::metadata(path).is_ok() || fragments.is_empty() {
+        files.push(path.to_string());
+    }
+    for fragment in fragments {
+        files.extend(crate::policies::expand_files(fragment, "json").map_err(|e| e.to_string())?);
+    }
+
+    let mut parsed = Vec::with_capacity(files.len());
+    for file in files {
+        let src = match fs::read_to_string(&file) {
+            Ok(src) => src,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound && file == path => return Ok(None),
+            Err(e) => return Err(format!("Failed to read schema {}: {}", file, e)),
+        };
+        let json: Value = serde_json::from_str(&src).map_err(|e| format!("Failed to parse schema {}: {}", file, e))?;
+        parsed.push((file, json));
+    }
+    if parsed.is_empty() {
+        return Ok(None);
+    }
+
+    let schema_json = if parsed.len() == 1 {
+        parsed.pop().map(|(_, json)| json).unwrap_or_default()
+    } else {
+        merge_fragments(parsed).map_err(|e| format!("Failed to merge schema fragments: {}", e))?
+    };
+    let schema = Schema::from_json_value(schema_json.clone()).map_err(|e| format!("Failed to parse schema: {}", e))?;
+    Ok(Some((schema, schema_json)))
+}
+
+/// A declared attribute, with nested record attributes expanded.
+#[derive(Debug, Serialize)]
+pub struct AttributeInfo {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub ty: String,
+    pub required: bool,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub attributes: Vec<AttributeInfo>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct EntityTypeInfo {
+    pub name: String,
+    /// Entity types this type may be a member of, transitively.
+    pub member_of_types: Vec<String>,
+    pub attributes: Vec<AttributeInfo>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ActionInfo {
+    pub uid: String,
+    pub principal_types: Vec<String>,
+    pub resource_types: Vec<String>,
+    /// Action groups containing this action, transitively.
+    pub member_of: Vec<String>,
+    pub context: Vec<AttributeInfo>,
+}
+
+/// Structured view of the active schema for `GET /v1/schema/introspect`.
+#[derive(Debug, Serialize)]
+pub struct SchemaIntrospection {
+    pub entity_types: Vec<EntityTypeInfo>,
+    pub actions: Vec<ActionInfo>,
+}
+
+fn qualify(namespace: &str, name: &str) -> String {
+    if namespace.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}::{}", namespace, name)
+    }
+}
+
+/// Resolves a reference to a common type declared in the same namespace.
+fn resolve_common<'a>(ty: &'a Value, namespace: &'a Value) -> &'a Value {
+    match ty.get("type").and_then(Value::as_str) {
+        Some(name) => namespace
+            .get("commonTypes")
+            .and_then(|common| common.get(name))
+            .unwrap_or(ty),
+        None => ty,
+    }
+}
+
+fn render_type(ty: &Value, namespace: &Value) -> String {
+    let ty = resolve_common(ty, namespace);
+    match ty.get("type").and_then(Value::as_str).unwrap_or("Unknown") {
+        "Set" => format!(
+            "Set<{}>",
+            ty.get("element")
+                .map(|element| render_type(element, namespace))
+                .unwrap_or_else(|| "Unknown".to_string())
+        ),
+        "Entity" | "Extension" | "EntityOrCommon" => ty
+            .get("name")
+            .and_then(Value::as_str)
+            .unwrap_or("Unknown")
+            .to_string(),
+        other => other.to_string(),
+    }
+}
+
+fn record_attributes(record: &Value, namespace: &Value) -> Vec<AttributeInfo> {
+    let record = resolve_common(record, namespace);
+    let Some(attrs) = record.get("attributes").and_then(Value::as_object) else {
+        return Vec::new();
+    };
+
+    attrs
+        .iter()
+        .map(|(name, ty)| {
+            let resolved = resolve_common(ty, namespace);
+            let nested = if resolved.get("type").and_then(Value::as_str) == Some("Record") {
+                record_attributes(resolved, namespace)
+            } else {
+                Vec::new()
+            };
+            AttributeInfo {
+                name: name.clone(),
+                ty: render_type(ty, namespace),
+                required: ty.get("required").and_then(Value::as_bool).unwrap_or(true),
+                attributes: nested,
+            }
+        })
+        .collect()
+}
+
+fn sorted_strings<T: ToString>(items: impl Iterator<Item = T>) -> Vec<String> {
+    let mut items: Vec<String> = items.map(|i| i.to_string()).collect();
+    items.sort();
+    items
+}
+
+/// Builds the introspection document. Relationships (applies-to, memberships) come from the
+/// parsed schema; attribute shapes are read from the schema JSON since Cedar does not expose
+/// them through its API.
+pub fn introspect(schema: &Schema, schema_json: &Value) -> SchemaIntrospection {
+    let namespaces: Vec<(&str, &Value)> = schema_json
+        .as_object()
+        .map(|obj| obj.iter().map(|(ns, def)| (ns.as_str(), def)).collect())
+        .unwrap_or_default();
+
+    let mut entity_types: Vec<EntityTypeInfo> = schema
+        .entity_types()
+        .map(|ty| {
+            let name = ty.to_string();
+            let attributes = namespaces
+                .iter()
+                .find_map(|(ns, def)| {
+                    def.get("entityTypes")?
+                        .as_object()?
+                        .iter()
+                        .find(|(basename, _)| qualify(ns, basename) == name)
+                        .map(|(_, ety)| match ety.get("shape") {
+                            Some(shape) => record_attributes(shape, def),
+                            None => Vec::new(),
+                        })
+                })
+                .unwrap_or_default();
+            EntityTypeInfo {
+                member_of_types: schema.ancestors(ty).map(sorted_strings).unwrap_or_default(),
+                name,
+                attributes,
+            }
+        })
+        .collect();
+    entity_types.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let action_entities = schema.action_entities().ok();
+    let mut actions: Vec<ActionInfo> = schema
+        .actions()
+        .map(|uid| {
+            let action_type = uid.type_name().to_string();
+            let context = namespaces
+                .iter()
+                .find_map(|(ns, def)| {
+                    if qualify(ns, "Action") != action_type {
+                        return None;
+                    }
+                    let action = def.get("actions")?.get(uid.id().unescaped())?;
+                    let context = action.get("appliesTo")?.get("context")?;
+                    Some(record_attributes(context, def))
+                })
+                .unwrap_or_default();
+            ActionInfo {
+                uid: uid.to_string(),
+                principal_types: schema
+                    .principals_for_action(uid)
+                    .map(sorted_strings)
+                    .unwrap_or_default(),
+                resource_types: schema
+                    .resources_for_action(uid)
+                    .map(sorted_strings)
+                    .unwrap_or_default(),
+                member_of: action_entities
+                    .as_ref()
+                    .and_then(|entities| entities.ancestors(uid))
+                    .map(sorted_strings)
+                    .unwrap_or_default(),
+                context,
+            }
+        })
+        .collect();
+    actions.sort_by(|a, b| a.uid.cmp(&b.uid));
+
+    SchemaIntrospection {
+        entity_types,
+        actions,
+    }
+}
+
+/// An action group and the actions it contains, transitively.
+#[derive(Debug, Serialize)]
+pub struct ActionGroupInfo {
+    pub group: String,
+    /// Member actions that are not groups themselves.
+    pub actions: Vec<String>,
+    pub subgroups: Vec<String>,
+}
+
+/// All actions contained in `group`, transitively, excluding the group itself.
+fn group_members(schema: &Schema, action_entities: &Entities, group: &EntityUid) -> Vec<EntityUid> {
+    let mut members: Vec<EntityUid> = schema
+        .actions()
+        .filter(|a| *a != group && action_entities.is_ancestor_of(group, a))
+        .cloned()
+        .collect();
+    members.sort_by_key(|a| a.to_string());
+    members
+}
+
+pub fn action_groups(schema: &Schema) -> Vec<ActionGroupInfo> {
+    let Ok(action_entities) = schema.action_entities() else {
+        return Vec::new();
+    };
+    let groups: HashSet<&EntityUid> = schema.action_groups().collect();
+
+    let mut infos: Vec<ActionGroupInfo> = groups
+        .iter()
+        .map(|group| {
+            let (subgroups, actions): (Vec<EntityUid>, Vec<EntityUid>) =
+                group_members(schema, &action_entities, group)
+                    .into_iter()
+                    .partition(|member| groups.contains(member));
+            ActionGroupInfo {
+                group: group.to_string(),
+                actions: sorted_strings(actions.iter()),
+                subgroups: sorted_strings(subgroups.iter()),
+            }
+        })
+        .collect();
+    infos.sort_by(|a, b| a.group.cmp(&b.group));
+    infos
+}
+
+fn applies_to(schema: &Schema, action: &EntityUid, principal: &EntityUid, resource: &EntityUid) -> bool {
+    let principal_ok = schema
+        .principals_for_action(action)
+        .is_some_and(|mut types| types.any(|t| t == principal.type_name()));
+    let resource_ok = schema
+        .resources_for_action(action)
+        .is_some_and(|mut types| types.any(|t| t == resource.type_name()));
+    principal_ok && resource_ok
+}
+
+/// Why [`expand_action`] could not resolve the actions to evaluate.
+#[derive(Debug, PartialEq)]
+pub enum ExpandError {
+    /// The request names a group with no member action applicable to its principal and
+    /// resource types.
+    NotApplicable(String),
+    /// The schema's action hierarchy could not be built.
+    Schema(String),
+}
+
+/// Resolves the action(s) a request is evaluated against. A plain action, or a group the schema
+/// declares as applicable to the principal and resource types, is evaluated as-is. Any other
+/// action group expands to its member actions that apply to those types.
+pub fn expand_action(
+    schema: &Schema,
+    principal: &EntityUid,
+    action: &EntityUid,
+    resource: &EntityUid,
+) -> Result<Vec<EntityUid>, ExpandError> {
+    let is_group = schema.action_groups().any(|g| g == action);
+    if !is_group || applies_to(schema, action, principal, resource) {
+        return Ok(vec![action.clone()]);
+    }
+
+    let action_entities = schema
+        .action_entities()
+        .map_err(|e| ExpandError::Schema(format!("Failed to resolve action groups: {}", e)))?;
+    let members: Vec<EntityUid> = group_members(schema, &action_entities, action)
+        .into_iter()
+        .filter(|member| applies_to(schema, member, principal, resource))
+        .collect();
+
+    if members.is_empty() {
+        return Err(ExpandError::NotApplicable(format!(
+            "Action group {} contains no actions applicable to {} and {}",
+            action,
+            principal.type_name(),
+            resource.type_name()
+        )));
+    }
+    Ok(members)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn schema() -> Schema {
+        let src = r#"
+            entity Group;
+            entity User in [Group];
+            entity Doc;
+            action manage;
+            action read, write in [manage] appliesTo { principal: User, resource: Doc };
+            action rename in [manage] appliesTo { principal: User, resource: Group };
+        "#;
+        Schema::from_cedarschema_str(src).unwrap().0
+    }
+
+    fn uid(s: &str) -> EntityUid {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn plain_action_is_evaluated_as_is() {
+        let actions = expand_action(&schema(), &uid(r#"User::"a""#), &uid(r#"Action::"read""#), &uid(r#"Doc::"d""#));
+        assert_eq!(actions, Ok(vec![uid(r#"Action::"read""#)]));
+    }
+
+    #[test]
+    fn group_expands_to_applicable_members() {
+        let actions = expand_action(&schema(), &uid(r#"User::"a""#), &uid(r#"Action::"manage""#), &uid(r#"Doc::"d""#));
+        assert_eq!(actions, Ok(vec![uid(r#"Action::"read""#), uid(r#"Action::"write""#)]));
+
+        let actions = expand_action(&schema(), &uid(r#"User::"a""#), &uid(r#"Action::"manage""#), &uid(r#"Group::"g""#));
+        assert_eq!(actions, Ok(vec![uid(r#"Action::"rename""#)]));
+    }
+
+    #[test]
+    fn group_without_applicable_members_is_an_error() {
+        let result = expand_action(&schema(), &uid(r#"Group::"g""#), &uid(r#"Action::"manage""#), &uid(r#"Doc::"d""#));
+        assert!(matches!(result, Err(ExpandError::NotApplicable(_))));
+    }
+
+    #[test]
+    fn reports_the_policies_and_entities_a_schema_breaks() {
+        use crate::entities::Ingest;
+        let entities = EntityStore::from_json(
+            serde_json::json!([
+                {"uid": {"type": "User", "id": "alice"}, "attrs": {"team": "ops"}, "parents": []},
+                {"uid": {"type": "User", "id": "bob"}, "attrs": {"team": "ops", "level": 3}, "parents": []},
+                {"uid": {"type": "Doc", "id": "d"}, "attrs": {}, "parents": []}
+            ]),
+            None,
+            Ingest::default(),
+        )
+        .unwrap();
+        let policy_set = crate::policies::parse(
+            r#"@id("by-team") permit (principal, action == Action::"read", resource) when { principal.team == "ops" };
+               @id("by-level") permit (principal, action == Action::"read", resource) when { principal has level && principal.level > 2 };"#,
+        )
+        .unwrap();
+        let schema = |user: &str| {
+            Schema::from_cedarschema_str(&format!(
+                "entity User {}; entity Doc; action read appliesTo {{ principal: User, resource: Doc }};",
+                user
+            ))
+            .unwrap()
+            .0
+        };
+
+        let (report, revalidated) = impact(&schema("{ team: String, level?: Long }"), &policy_set, &entities);
+        assert!(report.compatible && revalidated.is_some());
+
+        // Dropping `team` breaks the policy reading it and the users that have it
+        let (report, revalidated) = impact(&schema("{ level?: Long }"), &policy_set, &entities);
+        assert!(!report.compatible && revalidated.is_none());
+        assert_eq!(report.policies.iter().map(|p| p.id.as_str()).collect::<Vec<_>>(), ["by-team"]);
+        let mut uids: Vec<_> = report.entities.iter().map(|e| e.uid.clone().unwrap()).collect();
+        uids.sort();
+        assert_eq!(uids, [r#"User::"alice""#, r#"User::"bob""#]);
+
+        let error = SchemaUpdateError::incompatible(report);
+        assert_eq!(error.error, "Schema rejected: 1 loaded policies and 2 stored entities fail validation against it");
+        assert_eq!(error.validation_errors.len(), 3);
+    }
+
+    #[test]
+    fn merges_fragments_per_namespace() {
+        let user = serde_json::json!({"shape": {"type": "Record", "attributes": {}}});
+        let billing = serde_json::json!({"Billing": {
+            "entityTypes": {"User": user, "Invoice": {}},
+            "actions": {"pay": {"appliesTo": {"principalTypes": ["User"], "resourceTypes": ["Invoice"]}}}
+        }});
+        let shared = serde_json::json!({"Billing": {"entityTypes": {"User": user}, "actions": {}}, "Docs": {"entityTypes": {}, "actions": {}}});
+        let merged = merge_fragments(vec![("billing.json".into(), billing.clone()), ("shared.json".into(), shared)]).unwrap();
+        assert_eq!(merged["Billing"]["entityTypes"].as_object().unwrap().len(), 2);
+        assert!(merged["Docs"].is_object());
+        Schema::from_json_value(merged).unwrap();
+
+        let conflicting = serde_json::json!({"Billing": {"entityTypes": {"User": {}}, "actions": {}}});
+        let error = merge_fragments(vec![("billing.json".into(), billing), ("other.json".into(), conflicting)]).unwrap_err();
+        assert_eq!(error, "`Billing::User` in entityTypes is defined differently in billing.json and other.json");
+        assert!(merge_fragments(vec![("bad.json".into(), serde_json::json!([]))]).is_err());
+    }
+
+    #[test]
+    fn loads_the_base_schema_and_fragment_directories() {
+        let dir = std::env::temp_dir().join(format!("cedar-schema-{}", std::process::id()));
+        let fragments = dir.join("fragments");
+        fs::create_dir_all(&fragments).unwrap();
+        let base = dir.join("schema.json");
+        fs::write(&base, r#"{"": {"entityTypes": {"User": {}}, "actions": {}}}"#).unwrap();
+        fs::write(fragments.join("a.json"), r#"{"Docs": {"entityTypes": {"Doc": {}}, "actions": {}}}"#).unwrap();
+        fs::write(fragments.join("notes.txt"), "not a schema").unwrap();
+        let (base, fragments) = (base.to_str().unwrap(), vec![fragments.to_str().unwrap().to_string()]);
+
+        let (schema, json) = load(base, &fragments).unwrap().unwrap();
+        assert_eq!(json.as_object().unwrap().len(), 2);
+        assert_eq!(schema.entity_types().count(), 2);
+        // Without the base file the fragments alone make the schema
+        let missing = dir.join("missing.json");
+        assert_eq!(load(missing.to_str().unwrap(), &fragments).unwrap().unwrap().1.as_object().unwrap().len(), 1);
+        assert!(load(missing.to_str().unwrap(), &[]).unwrap().is_none());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
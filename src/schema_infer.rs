@@ -0,0 +1,280 @@
+use cedar_policy::{Schema, SchemaFragment};
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+
+/// Response of `POST /v1/schema/infer`.
+#[derive(Debug, Serialize)]
+pub struct Inferred {
+    /// The draft schema in Cedar's JSON schema format, as `CEDAR_SCHEMA_PATH` takes it.
+    pub schema: Value,
+    /// The same schema in Cedar's human-readable schema format.
+    pub cedar: String,
+    pub entities: usize,
+    pub entity_types: usize,
+    /// What the inference had to guess, and what an author still has to fill in.
+    pub warnings: Vec<String>,
+}
+
+/// The types seen for one attribute path of an entity type, and on how many entities.
+#[derive(Debug, Default)]
+struct Attribute {
+    types: BTreeMap<String, (Value, usize)>,
+    seen: usize,
+}
+
+/// What the corpus shows of one entity type.
+#[derive(Debug, Default)]
+struct EntityType {
+    count: usize,
+    attributes: BTreeMap<String, Attribute>,
+    parents: BTreeSet<String>,
+}
+
+/// The type name of an entity reference, `{"type": ..., "id": ...}` or `{"__entity": {...}}`.
+fn reference_type(value: &Value) -> Option<&str> {
+    let reference = value.get("__entity").unwrap_or(value);
+    let fields = reference.as_object()?;
+    match (fields.len(), fields.get("type"), fields.get("id")) {
+        (2, Some(Value::String(ty)), Some(Value::String(_))) => Some(ty),
+        _ => None,
+    }
+}
+
+/// Splits `App::Doc` into its namespace (`App`) and name (`Doc`).
+fn split_name(name: &str) -> (&str, &str) {
+    name.rsplit_once("::").unwrap_or(("", name))
+}
+
+/// How `name` is written in the schema of `namespace`: unqualified inside its own namespace.
+fn relative(name: &str, namespace: &str) -> String {
+    match split_name(name) {
+        (ns, base) if ns == namespace => base.to_string(),
+        _ => name.to_string(),
+    }
+}
+
+/// The JSON schema type of an attribute value, `None` for a value with no Cedar type. Nested
+/// records are typed from this one value; `warnings` notes what had to be guessed.
+fn value_type(value: &Value, namespace: &str, path: &str, warnings: &mut BTreeSet<String>) -> Option<Value> {
+    Some(match value {
+        Value::Bool(_) => json!({ "type": "Boolean" }),
+        Value::Number(n) if n.is_i64() => json!({ "type": "Long" }),
+        Value::String(_) => json!({ "type": "String" }),
+        Value::Array(items) => {
+            let element = items.iter().find_map(|item| value_type(item, namespace, path, warnings));
+            let element = element.unwrap_or_else(|| {
+                warnings.insert(format!("{}: the element type of an empty set was assumed to be String", path));
+                json!({ "type": "String" })
+            });
+            json!({ "type": "Set", "element": element })
+        }
+        Value::Object(fields) => {
+            if let Some(ty) = reference_type(value) {
+                return Some(json!({ "type": "Entity", "name": relative(ty, namespace) }));
+            }
+            if let Some(function) = fields.get("__extn").and_then(|e| e.get("fn")).and_then(Value::as_str) {
+                let name = if function == "ip" { "ipaddr" } else { function };
+                return Some(json!({ "type": "Extension", "name": name }));
+            }
+            let mut attributes = Map::new();
+            for (name, value) in fields {
+                let nested = format!("{}.{}", path, name);
+                if let Some(ty) = value_type(value, namespace, &nested, warnings) {
+                    attributes.insert(name.clone(), ty);
+                }
+            }
+            json!({ "type": "Record", "attributes": attributes })
+        }
+        Value::Null | Value::Number(_) => {
+            warnings.insert(format!("{}: left out, as {} has no Cedar type", path, value));
+            return None;
+        }
+    })
+}
+
+/// Infers a draft schema from entities in Cedar's entity JSON format: one entity type per type
+/// seen, with the attributes its entities have (required when all of them have it) and the
+/// types of their parents. `Action` entities become actions, without `appliesTo`.
+pub fn infer(entities: &[Value]) -> Result<Inferred, String> {
+    let mut types: BTreeMap<String, EntityType> = BTreeMap::new();
+    // Parents of each action, by namespace and ID
+    let mut actions: BTreeMap<(String, String), BTreeSet<(String, String)>> = BTreeMap::new();
+    let mut warnings = BTreeSet::new();
+
+    for (index, entity) in entities.iter().enumerate() {
+        let uid = entity.get("uid").ok_or_else(|| format!("Entity {} has no uid", index))?;
+        let ty = reference_type(uid).ok_or_else(|| format!("Entity {} has an invalid uid: {}", index, uid))?;
+        let parents: Vec<(String, String)> = entity["parents"]
+            .as_array()
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|parent| {
+                let parent = parent.get("__entity").unwrap_or(parent);
+                Some((reference_type(parent)?.to_string(), parent["id"].as_str()?.to_string()))
+            })
+            .collect();
+
+        let (namespace, name) = split_name(ty);
+        if name == "Action" {
+            let id = uid.get("__entity").unwrap_or(uid)["id"].as_str().unwrap_or_default().to_string();
+            actions.entry((namespace.to_string(), id)).or_default().extend(parents);
+            continue;
+        }
+
+        let entity_type = types.entry(ty.to_string()).or_default();
+        entity_type.count += 1;
+        entity_type.parents.extend(parents.into_iter().map(|(ty, _)| ty));
+        for (attribute, value) in entity["attrs"].as_object().into_iter().flatten() {
+            let path = format!("{}.{}", ty, attribute);
+            let Some(value_type) = value_type(value, namespace, &path, &mut warnings) else {
+                continue;
+            };
+            let seen = entity_type.attributes.entry(attribute.clone()).or_default();
+            seen.seen += 1;
+            seen.types.entry(value_type.to_string()).or_insert((value_type, 0)).1 += 1;
+        }
+    }
+
+    // Parent types and action groups never seen as entities still need declaring
+    let parent_types: BTreeSet<String> = types.values().flat_map(|ty| ty.parents.iter().cloned()).collect();
+    for parent in parent_types {
+        if split_name(&parent).1 != "Action" {
+            types.entry(parent).or_default();
+        }
+    }
+    let groups: BTreeSet<(String, String)> = actions.values().flatten().cloned().collect();
+    for (ty, id) in groups {
+        if let (namespace, "Action") = split_name(&ty) {
+            actions.entry((namespace.to_string(), id)).or_default();
+        }
+    }
+
+    let mut schema = Map::new();
+    for (name, entity_type) in &types {
+        let (namespace, base) = split_name(name);
+        let mut attributes = Map::new();
+        for (attribute, seen) in &entity_type.attributes {
+            let (value_type, _) = seen.types.values().max_by_key(|(_, count)| *count).unwrap();
+            if seen.types.len() > 1 {
+                warnings.insert(format!(
+                    "{}.{}: seen with {} different types; the most common was used",
+                    name,
+                    attribute,
+                    seen.types.len()
+                ));
+            }
+            let mut value_type = value_type.clone();
+            if seen.seen < entity_type.count {
+                value_type["required"] = json!(false);
+            }
+            attributes.insert(attribute.clone(), value_type);
+        }
+        let mut declaration = json!({ "shape": { "type": "Record", "attributes": attributes } });
+        if !entity_type.parents.is_empty() {
+            let member_of: Vec<String> = entity_type.parents.iter().map(|parent| relative(parent, namespace)).collect();
+            declaration["memberOfTypes"] = json!(member_of);
+        }
+        namespace_of(&mut schema, namespace)["entityTypes"][base] = declaration;
+    }
+    for ((namespace, id), parents) in &actions {
+        let mut declaration = json!({});
+        let member_of: Vec<Value> = parents
+            .iter()
+            .map(|(ty, parent)| match relative(ty, namespace).as_str() {
+                "Action" => json!({ "id": parent }),
+                ty => json!({ "type": ty, "id": parent }),
+            })
+            .collect();
+        if !member_of.is_empty() {
+            declaration["memberOf"] = json!(member_of);
+        }
+        namespace_of(&mut schema, namespace)["actions"][id.as_str()] = declaration;
+    }
+    if !actions.is_empty() {
+        warnings.insert("Actions were declared without appliesTo: add the principal and resource types each applies to".to_string());
+    }
+
+    let schema = Value::Object(schema);
+    let fragment = SchemaFragment::from_json_value(schema.clone()).map_err(|e| format!("Failed to build a schema: {}", e))?;
+    let mut cedar = fragment.to_cedarschema().map_err(|e| format!("Failed to render the schema: {}", e))?;
+    // Built-in types are rendered fully qualified; that is only needed when a type shadows one
+    let builtins = ["Bool", "Long", "String", "ipaddr", "decimal", "datetime", "duration"];
+    if !types.keys().any(|name| builtins.contains(&split_name(name).1)) {
+        cedar = cedar.replace("__cedar::", "");
+    }
+    Schema::from_json_value(schema.clone()).map_err(|e| format!("The inferred schema is invalid: {}", e))?;
+    Ok(Inferred {
+        schema,
+        cedar,
+        entities: entities.len(),
+        entity_types: types.len(),
+        warnings: warnings.into_iter().collect(),
+    })
+}
+
+/// The declaration of `namespace` in `schema`, added when missing.
+fn namespace_of<'a>(schema: &'a mut Map<String, Value>, namespace: &str) -> &'a mut Value {
+    schema
+        .entry(namespace.to_string())
+        .or_insert_with(|| json!({ "entityTypes": {}, "actions": {} }))
+}
+
+/// `cedar-agent infer-schema <entities.json> [--format json|cedar]`: prints a draft schema
+/// inferred from an entities file; warnings go to stderr.
+pub fn run(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let (path, format) = match args {
+        [path] => (path, "json"),
+        [path, flag, format] if flag == "--format" && (format == "json" || format == "cedar") => (path, format.as_str()),
+        _ => return Err("Usage: cedar-agent infer-schema <entities.json> [--format json|cedar]".into()),
+    };
+    let text = fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+    let entities: Vec<Value> = serde_json::from_str(&text).map_err(|e| format!("Failed to parse {}: {}", path, e))?;
+    let inferred = infer(&entities)?;
+    for warning in &inferred.warnings {
+        eprintln!("warning: {}", warning);
+    }
+    match format {
+        "cedar" => print!("{}", inferred.cedar),
+        _ => println!("{}", serde_json::to_string_pretty(&inferred.schema)?),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn infers_entity_types_attributes_and_memberships() {
+        let entities = json!([
+            { "uid": { "type": "App::User", "id": "alice" },
+              "attrs": { "level": 3, "email": "a@example.com", "manager": { "__entity": { "type": "App::User", "id": "bob" } },
+                         "address": { "city": "Oslo" }, "ip": { "__extn": { "fn": "ip", "arg": "10.0.0.1" } } },
+              "parents": [{ "type": "App::Group", "id": "staff" }] },
+            { "uid": { "type": "App::User", "id": "bob" }, "attrs": { "level": "high", "tags": [] }, "parents": [] },
+            { "uid": { "type": "App::User", "id": "carol" }, "attrs": { "level": 1 }, "parents": [] },
+            { "uid": { "type": "Doc", "id": "d1" }, "attrs": { "owner": { "type": "App::User", "id": "alice" } }, "parents": [] },
+            { "uid": { "type": "App::Action", "id": "view" }, "attrs": {}, "parents": [{ "type": "App::Action", "id": "read" }] }
+        ]);
+        let inferred = infer(entities.as_array().unwrap()).unwrap();
+        let user = &inferred.schema["App"]["entityTypes"]["User"];
+        assert_eq!(user["memberOfTypes"], json!(["Group"]));
+        let attributes = &user["shape"]["attributes"];
+        assert_eq!(attributes["level"], json!({ "type": "Long" }));
+        assert_eq!(attributes["email"], json!({ "type": "String", "required": false }));
+        assert_eq!(attributes["manager"], json!({ "type": "Entity", "name": "User", "required": false }));
+        assert_eq!(attributes["address"]["attributes"]["city"], json!({ "type": "String" }));
+        assert_eq!(attributes["ip"]["name"], "ipaddr");
+        assert_eq!(attributes["tags"]["element"], json!({ "type": "String" }));
+        assert!(inferred.schema["App"]["entityTypes"]["Group"].is_object());
+        assert_eq!(inferred.schema[""]["entityTypes"]["Doc"]["shape"]["attributes"]["owner"]["name"], "App::User");
+        assert_eq!(inferred.schema["App"]["actions"]["view"], json!({ "memberOf": [{ "id": "read" }] }));
+        assert_eq!(inferred.entity_types, 3);
+        assert_eq!(inferred.warnings.len(), 3, "{:?}", inferred.warnings);
+        assert!(inferred.cedar.contains("namespace App"));
+        assert!(inferred.cedar.contains("level: Long"));
+    }
+}
@@ -0,0 +1,215 @@
+use crate::policies::action_matches;
+use crate::schema::{AttributeInfo, SchemaIntrospection};
+use cedar_policy::{Entities, EntityUid, PolicySet};
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::BTreeSet;
+
+/// Parts of the schema the policies never use, for `GET /v1/schema/lint` and `--validate-only`.
+/// Unused parts are not wrong, but often left over from renames or removed features.
+#[derive(Debug, Default, Serialize, PartialEq)]
+pub struct SchemaLint {
+    /// Entity types no action applies to, that no such type may be a member of, that no
+    /// attribute refers to and that no policy names.
+    pub unused_entity_types: Vec<String>,
+    /// Actions no policy's action scope matches.
+    pub unused_actions: Vec<String>,
+    /// Entity attributes (`Type.attr`) and context attributes (`Action::"a" context.attr`)
+    /// whose name no policy reads or tests with `has`. Nested attributes are listed by path,
+    /// unless their record is listed already.
+    pub unused_attributes: Vec<String>,
+}
+
+impl SchemaLint {
+    /// One message per unused part.
+    pub fn messages(&self) -> Vec<String> {
+        let types = self.unused_entity_types.iter().map(|ty| format!("entity type {} is never used", ty));
+        let actions = self.unused_actions.iter().map(|action| format!("no policy applies to action {}", action));
+        let attributes = self
+            .unused_attributes
+            .iter()
+            .map(|attribute| format!("no policy reads attribute {}", attribute));
+        types.chain(actions).chain(attributes).collect()
+    }
+}
+
+/// What the policies read: attribute names accessed with `.` or `has`, and entity types named
+/// in scopes, `is` tests or entity literals.
+#[derive(Default)]
+struct Usage {
+    attributes: BTreeSet<String>,
+    entity_types: BTreeSet<String>,
+}
+
+impl Usage {
+    fn walk(&mut self, json: &Value) {
+        match json {
+            Value::Object(fields) => {
+                for op in [".", "has"] {
+                    if let Some(attr) = fields.get(op).and_then(|access| access["attr"].as_str()) {
+                        self.attributes.insert(attr.to_string());
+                    }
+                }
+                if let Some(ty) = fields.get("entity_type").and_then(Value::as_str) {
+                    self.entity_types.insert(ty.to_string());
+                }
+                if let (Some(ty), Some(_)) = (fields.get("type").and_then(Value::as_str), fields.get("id")) {
+                    self.entity_types.insert(ty.to_string());
+                }
+                fields.values().for_each(|v| self.walk(v));
+            }
+            Value::Array(items) => items.iter().for_each(|v| self.walk(v)),
+            _ => {}
+        }
+    }
+}
+
+/// Whether `ty`, an attribute type as the introspection renders it, refers to `entity_type`.
+/// Attribute types may leave out the namespace they are declared in.
+fn refers_to(ty: &str, entity_type: &str) -> bool {
+    let ty = ty.trim_start_matches("Set<").trim_end_matches('>');
+    ty == entity_type || entity_type.ends_with(&format!("::{}", ty))
+}
+
+fn attribute_types<'a>(attributes: &'a [AttributeInfo], types: &mut Vec<&'a str>) {
+    for attribute in attributes {
+        types.push(&attribute.ty);
+        attribute_types(&attribute.attributes, types);
+    }
+}
+
+fn unused_attributes(prefix: &str, attributes: &[AttributeInfo], usage: &Usage, unused: &mut Vec<String>) {
+    for attribute in attributes {
+        let path = format!("{}.{}", prefix, attribute.name);
+        if usage.attributes.contains(&attribute.name) {
+            unused_attributes(&path, &attribute.attributes, usage, unused);
+        } else {
+            unused.push(path);
+        }
+    }
+}
+
+/// Compares the schema, as introspected, with the policies and templates of `policy_set`.
+/// `actions` holds the schema's action-group hierarchy.
+pub fn lint(schema: &SchemaIntrospection, policy_set: &PolicySet, actions: &Entities) -> SchemaLint {
+    let mut usage = Usage::default();
+    for policy in policy_set.policies() {
+        if let Ok(json) = policy.to_json() {
+            usage.walk(&json);
+        }
+    }
+    for template in policy_set.templates() {
+        if let Ok(json) = template.to_json() {
+            usage.walk(&json);
+        }
+    }
+
+    let mut applied: BTreeSet<&str> = BTreeSet::new();
+    for action in &schema.actions {
+        applied.extend(action.principal_types.iter().chain(&action.resource_types).map(String::as_str));
+    }
+    let mut referenced = Vec::new();
+    for entity_type in &schema.entity_types {
+        attribute_types(&entity_type.attributes, &mut referenced);
+    }
+    for action in &schema.actions {
+        attribute_types(&action.context, &mut referenced);
+    }
+    let unused_entity_types = schema
+        .entity_types
+        .iter()
+        .filter(|ty| {
+            let name = ty.name.as_str();
+            !applied.contains(name)
+                && !usage.entity_types.contains(name)
+                && !referenced.iter().any(|attribute_ty| refers_to(attribute_ty, name))
+                && !schema
+                    .entity_types
+                    .iter()
+                    .any(|member| applied.contains(member.name.as_str()) && member.member_of_types.iter().any(|parent| parent == name))
+        })
+        .map(|ty| ty.name.clone())
+        .collect();
+
+    let constraints: Vec<_> = policy_set
+        .policies()
+        .map(|policy| policy.action_constraint())
+        .chain(policy_set.templates().map(|template| template.action_constraint()))
+        .collect();
+    let unused_actions = schema
+        .actions
+        .iter()
+        .filter(|action| match action.uid.parse::<EntityUid>() {
+            Ok(uid) => !constraints.iter().any(|constraint| action_matches(constraint.clone(), &uid, actions)),
+            Err(_) => false,
+        })
+        .map(|action| action.uid.clone())
+        .collect();
+
+    let mut unused_attributes = Vec::new();
+    for entity_type in &schema.entity_types {
+        self::unused_attributes(&entity_type.name, &entity_type.attributes, &usage, &mut unused_attributes);
+    }
+    for action in &schema.actions {
+        self::unused_attributes(&format!("{} context", action.uid), &action.context, &usage, &mut unused_attributes);
+    }
+
+    SchemaLint {
+        unused_entity_types,
+        unused_actions,
+        unused_attributes,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cedar_policy::Schema;
+
+    #[test]
+    fn finds_what_no_policy_uses() {
+        let schema_json = serde_json::json!({
+            "": {
+                "entityTypes": {
+                    "User": {"memberOfTypes": ["Team"], "shape": {"type": "Record", "attributes": {
+                        "level": {"type": "Long"},
+                        "address": {"type": "Record", "attributes": {"city": {"type": "String"}, "zip": {"type": "String"}}},
+                        "manager": {"type": "Entity", "name": "Employee"}
+                    }}},
+                    "Team": {},
+                    "Employee": {},
+                    "Doc": {"shape": {"type": "Record", "attributes": {"owner": {"type": "String"}, "title": {"type": "String"}}}},
+                    "Legacy": {}
+                },
+                "actions": {
+                    "read": {"appliesTo": {"principalTypes": ["User"], "resourceTypes": ["Doc"],
+                        "context": {"type": "Record", "attributes": {"ip": {"type": "String"}}}}, "memberOf": [{"id": "readers"}]},
+                    "readers": {},
+                    "archive": {"appliesTo": {"principalTypes": ["User"], "resourceTypes": ["Doc"]}}
+                }
+            }
+        });
+        let schema = Schema::from_json_value(schema_json.clone()).unwrap();
+        let policy_set = crate::policies::parse(
+            r#"permit (principal, action in Action::"readers", resource)
+            when { principal.level > 1 && principal.address.city == "Paris" && resource has owner };"#,
+        )
+        .unwrap();
+        let introspection = crate::schema::introspect(&schema, &schema_json);
+        let lint = lint(&introspection, &policy_set, &schema.action_entities().unwrap());
+
+        // Team is a parent of User, Employee an attribute type
+        assert_eq!(lint.unused_entity_types, vec!["Legacy"]);
+        assert_eq!(lint.unused_actions, vec![r#"Action::"archive""#]);
+        assert_eq!(
+            lint.unused_attributes,
+            vec!["Doc.title", "User.address.zip", "User.manager", r#"Action::"read" context.ip"#]
+        );
+        assert_eq!(lint.messages()[0], "entity type Legacy is never used");
+
+        let everything = crate::policies::parse("permit (principal, action, resource) when { principal is Legacy };").unwrap();
+        let lint = super::lint(&introspection, &everything, &schema.action_entities().unwrap());
+        assert!(lint.unused_actions.is_empty());
+        assert!(lint.unused_entity_types.is_empty());
+    }
+}
@@ -0,0 +1,739 @@
+use crate::{digest, percent_decode, query_params, read_json, CedarService};
+use cedar_policy::{Entity, EntityTypeName};
+use hyper::{Body, Method, Response, StatusCode};
+use serde_json::{json, Map, Value};
+
+const PREFIX: &str = "/scim/v2";
+const USER_SCHEMA: &str = "urn:ietf:params:scim:schemas:core:2.0:User";
+const GROUP_SCHEMA: &str = "urn:ietf:params:scim:schemas:core:2.0:Group";
+const LIST_SCHEMA: &str = "urn:ietf:params:scim:api:messages:2.0:ListResponse";
+const ERROR_SCHEMA: &str = "urn:ietf:params:scim:api:messages:2.0:Error";
+const CONFIG_SCHEMA: &str = "urn:ietf:params:scim:schemas:core:2.0:ServiceProviderConfig";
+
+/// Largest page returned by a list request.
+const MAX_RESULTS: usize = 200;
+
+/// A SCIM error response (RFC 7644 §3.12).
+#[derive(Debug)]
+pub struct ScimError {
+    status: StatusCode,
+    scim_type: Option<&'static str>,
+    detail: String,
+}
+
+impl ScimError {
+    fn new(status: StatusCode, scim_type: Option<&'static str>, detail: impl Into<String>) -> Self {
+        Self {
+            status,
+            scim_type,
+            detail: detail.into(),
+        }
+    }
+
+    fn invalid(detail: impl Into<String>) -> Self {
+        Self::new(StatusCode::BAD_REQUEST, Some("invalidValue"), detail)
+    }
+
+    fn not_found(kind: Kind, id: &str) -> Self {
+        Self::new(StatusCode::NOT_FOUND, None, format!("{} {} not found", kind.name(), id))
+    }
+}
+
+impl std::fmt::Display for ScimError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.detail)
+    }
+}
+
+/// Entities that fail to parse or to validate against the schema.
+impl From<String> for ScimError {
+    fn from(detail: String) -> Self {
+        Self::invalid(detail)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    User,
+    Group,
+}
+
+impl Kind {
+    fn name(self) -> &'static str {
+        match self {
+            Kind::User => "User",
+            Kind::Group => "Group",
+        }
+    }
+}
+
+/// SCIM 2.0 provisioning of users and groups into the entity store. Each user and group is an
+/// entity whose ID is its SCIM `id`; group membership is kept as the members' parents.
+pub struct Scim {
+    token_digest: [u8; 32],
+    user_type: EntityTypeName,
+    group_type: EntityTypeName,
+}
+
+impl Scim {
+    pub fn new(token: &str, user_type: &str, group_type: &str) -> Result<Self, String> {
+        Ok(Self {
+            token_digest: digest::sha256(token.as_bytes()),
+            user_type: user_type
+                .parse()
+                .map_err(|e| format!("Invalid CEDAR_SCIM_USER_TYPE: {}", e))?,
+            group_type: group_type
+                .parse()
+                .map_err(|e| format!("Invalid CEDAR_SCIM_GROUP_TYPE: {}", e))?,
+        })
+    }
+
+    /// Checks an `Authorization: Bearer` header. Digests are compared so the comparison takes
+    /// the same time however much of the token matches.
+    fn authorized(&self, header: Option<&str>) -> bool {
+        header
+            .and_then(|h| h.strip_prefix("Bearer "))
+            .is_some_and(|token| digest::sha256(token.trim().as_bytes()) == self.token_digest)
+    }
+
+    fn type_name(&self, kind: Kind) -> String {
+        match kind {
+            Kind::User => self.user_type.to_string(),
+            Kind::Group => self.group_type.to_string(),
+        }
+    }
+}
+
+/// The `(type, id)` of an entity reference in Cedar's JSON format, explicit or `__entity`.
+fn uid_parts(uid: &Value) -> Option<(&str, &str)> {
+    let uid = uid.get("__entity").unwrap_or(uid);
+    Some((uid.get("type")?.as_str()?, uid.get("id")?.as_str()?))
+}
+
+/// Accepts booleans and the `"True"`/`"False"` strings some IdPs send.
+fn as_bool(value: &Value) -> Option<bool> {
+    match value {
+        Value::Bool(b) => Some(*b),
+        Value::String(s) => match s.to_ascii_lowercase().as_str() {
+            "true" => Some(true),
+            "false" => Some(false),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// The primary (or else first) address of a SCIM `emails` value.
+fn primary_email(value: &Value) -> Option<&str> {
+    match value {
+        Value::String(s) => Some(s),
+        Value::Array(emails) => emails
+            .iter()
+            .find(|e| e.get("primary").and_then(as_bool) == Some(true))
+            .or_else(|| emails.first())
+            .and_then(|e| e.get("value"))
+            .and_then(Value::as_str),
+        _ => None,
+    }
+}
+
+/// The value of `attr eq "value"` in a filter or a `members[value eq "id"]` path.
+fn eq_filter(filter: &str) -> Option<(&str, &str)> {
+    let (attr, rest) = filter.trim().split_once(' ')?;
+    let (op, value) = rest.trim().split_once(' ')?;
+    let value = value.trim().strip_prefix('"')?.strip_suffix('"')?;
+    op.eq_ignore_ascii_case("eq").then_some((attr, value))
+}
+
+/// The stored entities as JSON, edited by SCIM operations and parsed back as a whole.
+struct Directory<'a> {
+    scim: &'a Scim,
+    entities: Vec<Value>,
+}
+
+impl<'a> Directory<'a> {
+    fn new(scim: &'a Scim, entities: &[Entity]) -> Result<Self, ScimError> {
+        let entities = entities
+            .iter()
+            .map(|e| e.to_json_value().map_err(|e| e.to_string()))
+            .collect::<Result<_, _>>()?;
+        Ok(Self { scim, entities })
+    }
+
+    fn into_entities(self) -> Result<Vec<Entity>, String> {
+        crate::entities::parse_list(Value::Array(self.entities), None)
+    }
+
+    fn is(&self, entity: &Value, kind: Kind) -> bool {
+        uid_parts(&entity["uid"]).is_some_and(|(t, _)| t == self.scim.type_name(kind))
+    }
+
+    fn position(&self, kind: Kind, id: &str) -> Option<usize> {
+        let type_name = self.scim.type_name(kind);
+        self.entities
+            .iter()
+            .position(|e| uid_parts(&e["uid"]) == Some((type_name.as_str(), id)))
+    }
+
+    fn find(&self, kind: Kind, id: &str) -> Result<usize, ScimError> {
+        self.position(kind, id).ok_or_else(|| ScimError::not_found(kind, id))
+    }
+
+    fn id(entity: &Value) -> &str {
+        uid_parts(&entity["uid"]).map(|(_, id)| id).unwrap_or_default()
+    }
+
+    fn has_parent(&self, entity: &Value, group_id: &str) -> bool {
+        let group_type = self.scim.type_name(Kind::Group);
+        entity["parents"]
+            .as_array()
+            .is_some_and(|parents| parents.iter().any(|p| uid_parts(p) == Some((group_type.as_str(), group_id))))
+    }
+
+    fn resource(&self, kind: Kind, entity: &Value) -> Value {
+        let id = Self::id(entity);
+        let attrs = &entity["attrs"];
+        let mut resource = match kind {
+            Kind::User => {
+                let group_type = self.scim.type_name(Kind::Group);
+                let groups: Vec<Value> = entity["parents"]
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .filter_map(uid_parts)
+                    .filter(|(t, _)| *t == group_type)
+                    .map(|(_, group)| json!({"value": group}))
+                    .collect();
+                let mut user = json!({
+                    "schemas": [USER_SCHEMA],
+                    "id": id,
+                    "userName": attrs.get("userName").and_then(Value::as_str).unwrap_or(id),
+                    "active": attrs.get("active").and_then(Value::as_bool).unwrap_or(true),
+                    "groups": groups,
+                });
+                if let Some(name) = attrs.get("displayName") {
+                    user["displayName"] = name.clone();
+                }
+                if let Some(email) = attrs.get("email") {
+                    user["emails"] = json!([{"value": email, "primary": true}]);
+                }
+                user
+            }
+            Kind::Group => {
+                let members: Vec<Value> = self
+                    .entities
+                    .iter()
+                    .filter(|e| self.has_parent(e, id))
+                    .filter_map(|e| {
+                        let kind = [Kind::User, Kind::Group].into_iter().find(|k| self.is(e, *k))?;
+                        Some(json!({"value": Self::id(e), "type": kind.name()}))
+                    })
+                    .collect();
+                json!({
+                    "schemas": [GROUP_SCHEMA],
+                    "id": id,
+                    "displayName": attrs.get("displayName").and_then(Value::as_str).unwrap_or(id),
+                    "members": members,
+                })
+            }
+        };
+        resource["meta"] = json!({
+            "resourceType": kind.name(),
+            "location": format!("{}/{}s/{}", PREFIX, kind.name(), id),
+        });
+        resource
+    }
+
+    fn get(&self, kind: Kind, id: &str) -> Result<Value, ScimError> {
+        Ok(self.resource(kind, &self.entities[self.find(kind, id)?]))
+    }
+
+    /// Lists resources, optionally narrowed by an `eq` filter on `id`, `userName` or
+    /// `displayName`, with 1-based `start_index` paging.
+    fn list(&self, kind: Kind, filter: Option<&str>, start_index: usize, count: usize) -> Result<Value, ScimError> {
+        let filter = match filter {
+            Some(filter) => Some(eq_filter(filter).ok_or_else(|| {
+                ScimError::new(StatusCode::BAD_REQUEST, Some("invalidFilter"), format!("Unsupported filter: {}", filter))
+            })?),
+            None => None,
+        };
+        let matching: Vec<Value> = self
+            .entities
+            .iter()
+            .filter(|e| self.is(e, kind))
+            .map(|e| self.resource(kind, e))
+            .filter(|r| match filter {
+                Some((attr, value)) => r
+                    .as_object()
+                    .and_then(|r| r.iter().find(|(name, _)| name.eq_ignore_ascii_case(attr)))
+                    .and_then(|(_, v)| v.as_str())
+                    == Some(value),
+                None => true,
+            })
+            .collect();
+        let total = matching.len();
+        let page: Vec<Value> = matching
+            .into_iter()
+            .skip(start_index.max(1) - 1)
+            .take(count.min(MAX_RESULTS))
+            .collect();
+        Ok(json!({
+            "schemas": [LIST_SCHEMA],
+            "totalResults": total,
+            "startIndex": start_index.max(1),
+            "itemsPerPage": page.len(),
+            "Resources": page,
+        }))
+    }
+
+    /// Sets the attributes a SCIM resource body carries; anything else in it is ignored.
+    fn apply_body(kind: Kind, attrs: &mut Map<String, Value>, body: &Value) -> Result<(), ScimError> {
+        let Some(body) = body.as_object() else {
+            return Err(ScimError::invalid("Expected a JSON object"));
+        };
+        for (name, value) in body {
+            Self::apply_attr(kind, attrs, name, Some(value))?;
+        }
+        Ok(())
+    }
+
+    /// Sets (or with `None`, removes) one attribute by its SCIM name or path.
+    fn apply_attr(kind: Kind, attrs: &mut Map<String, Value>, path: &str, value: Option<&Value>) -> Result<(), ScimError> {
+        let name = path.split(['[', '.']).next().unwrap_or(path).to_ascii_lowercase();
+        match (kind, name.as_str(), value) {
+            (Kind::User, "username", Some(value)) => {
+                let user_name = value.as_str().ok_or_else(|| ScimError::invalid("userName must be a string"))?;
+                attrs.insert("userName".to_string(), user_name.into());
+            }
+            (Kind::User, "active", Some(value)) => {
+                let active = as_bool(value).ok_or_else(|| ScimError::invalid("active must be a boolean"))?;
+                attrs.insert("active".to_string(), active.into());
+            }
+            (Kind::User, "emails", Some(value)) => {
+                if let Some(email) = primary_email(value) {
+                    attrs.insert("email".to_string(), email.into());
+                }
+            }
+            (Kind::User, "emails", None) => {
+                attrs.remove("email");
+            }
+            (_, "displayname", Some(value)) => {
+                let name = value.as_str().ok_or_else(|| ScimError::invalid("displayName must be a string"))?;
+                attrs.insert("displayName".to_string(), name.into());
+            }
+            (Kind::User, "displayname", None) => {
+                attrs.remove("displayName");
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// The entity a `{"value": id, "type": ...}` member reference names.
+    fn member(&self, member: &Value) -> Result<usize, ScimError> {
+        let id = member
+            .get("value")
+            .and_then(Value::as_str)
+            .ok_or_else(|| ScimError::invalid("Member without a value"))?;
+        let kinds = match member.get("type").and_then(Value::as_str) {
+            Some(t) if t.eq_ignore_ascii_case("group") => vec![Kind::Group],
+            Some(t) if t.eq_ignore_ascii_case("user") => vec![Kind::User],
+            _ => vec![Kind::User, Kind::Group],
+        };
+        kinds
+            .into_iter()
+            .find_map(|kind| self.position(kind, id))
+            .ok_or_else(|| ScimError::invalid(format!("Member {} does not exist", id)))
+    }
+
+    fn set_parent(&mut self, index: usize, group_id: &str, member: bool) {
+        let group_type = self.scim.type_name(Kind::Group);
+        let group_ref = json!({"type": group_type, "id": group_id});
+        let entity = &mut self.entities[index];
+        if !entity["parents"].is_array() {
+            entity["parents"] = json!([]);
+        }
+        let parents = entity["parents"].as_array_mut().unwrap();
+        parents.retain(|p| uid_parts(p) != Some((group_type.as_str(), group_id)));
+        if member {
+            parents.push(group_ref);
+        }
+    }
+
+    /// Adds (or removes) the listed members of a group.
+    fn set_members(&mut self, group_id: &str, members: Option<&Value>, member: bool) -> Result<(), ScimError> {
+        let members = match members {
+            Some(Value::Array(members)) => members.clone(),
+            Some(single @ Value::Object(_)) => vec![single.clone()],
+            Some(_) => return Err(ScimError::invalid("members must be an array")),
+            None => Vec::new(),
+        };
+        let indices = members.iter().map(|m| self.member(m)).collect::<Result<Vec<_>, _>>()?;
+        for index in indices {
+            self.set_parent(index, group_id, member);
+        }
+        Ok(())
+    }
+
+    fn clear_members(&mut self, group_id: &str) {
+        for index in 0..self.entities.len() {
+            if self.has_parent(&self.entities[index], group_id) {
+                self.set_parent(index, group_id, false);
+            }
+        }
+    }
+
+    fn attrs_mut(&mut self, index: usize) -> &mut Map<String, Value> {
+        let entity = &mut self.entities[index];
+        if !entity["attrs"].is_object() {
+            entity["attrs"] = json!({});
+        }
+        entity["attrs"].as_object_mut().unwrap()
+    }
+
+    /// Creates a resource; its `id` is the `userName` (users) or `displayName` (groups).
+    fn create(&mut self, kind: Kind, body: &Value) -> Result<Value, ScimError> {
+        let id_attr = match kind {
+            Kind::User => "userName",
+            Kind::Group => "displayName",
+        };
+        let id = body
+            .get(id_attr)
+            .and_then(Value::as_str)
+            .filter(|id| !id.is_empty())
+            .ok_or_else(|| ScimError::invalid(format!("{} is required", id_attr)))?
+            .to_string();
+        if self.position(kind, &id).is_some() {
+            return Err(ScimError::new(
+                StatusCode::CONFLICT,
+                Some("uniqueness"),
+                format!("{} {} already exists", kind.name(), id),
+            ));
+        }
+
+        let mut attrs = Map::new();
+        if kind == Kind::User {
+            attrs.insert("active".to_string(), true.into());
+        }
+        Self::apply_body(kind, &mut attrs, body)?;
+        self.entities.push(json!({
+            "uid": {"type": self.scim.type_name(kind), "id": id},
+            "attrs": attrs,
+            "parents": [],
+        }));
+        if kind == Kind::Group {
+            self.set_members(&id, body.get("members"), true)?;
+        }
+        self.get(kind, &id)
+    }
+
+    /// Replaces a resource's attributes (and a group's members); the `id` stays.
+    fn replace(&mut self, kind: Kind, id: &str, body: &Value) -> Result<Value, ScimError> {
+        let index = self.find(kind, id)?;
+        let mut attrs = Map::new();
+        if kind == Kind::User {
+            attrs.insert("active".to_string(), true.into());
+        }
+        Self::apply_body(kind, &mut attrs, body)?;
+        *self.attrs_mut(index) = attrs;
+        if kind == Kind::Group {
+            self.clear_members(id);
+            self.set_members(id, body.get("members"), true)?;
+        }
+        self.get(kind, id)
+    }
+
+    /// Applies a `PatchOp` request: `add`, `replace` and `remove` of attributes and members.
+    fn patch(&mut self, kind: Kind, id: &str, body: &Value) -> Result<Value, ScimError> {
+        let index = self.find(kind, id)?;
+        let operations = body
+            .get("Operations")
+            .and_then(Value::as_array)
+            .ok_or_else(|| ScimError::invalid("Operations is required"))?;
+        for operation in operations {
+            let op = operation.get("op").and_then(Value::as_str).unwrap_or_default().to_ascii_lowercase();
+            let path = operation.get("path").and_then(Value::as_str);
+            let value = operation.get("value");
+            let members_path = path.filter(|p| p.to_ascii_lowercase().starts_with("members"));
+
+            match (op.as_str(), kind, members_path) {
+                ("add" | "replace" | "remove", Kind::Group, Some(path)) => {
+                    if op == "replace" {
+                        self.clear_members(id);
+                    }
+                    match (op.as_str(), path.find('[')) {
+                        ("remove", Some(start)) => {
+                            let filter = path[start + 1..].trim_end_matches(']');
+                            let (_, member) = eq_filter(filter)
+                                .ok_or_else(|| ScimError::invalid(format!("Unsupported path: {}", path)))?;
+                            self.set_members(id, Some(&json!([{"value": member}])), false)?;
+                        }
+                        ("remove", None) if value.is_none() => self.clear_members(id),
+                        (op, _) => self.set_members(id, value, op != "remove")?,
+                    }
+                }
+                ("add" | "replace", _, None) if path.is_none() => {
+                    let value = value.ok_or_else(|| ScimError::invalid("value is required"))?;
+                    if let Some(members) = value.get("members").filter(|_| kind == Kind::Group) {
+                        if op == "replace" {
+                            self.clear_members(id);
+                        }
+                        self.set_members(id, Some(members), true)?;
+                    }
+                    Self::apply_body(kind, self.attrs_mut(index), value)?;
+                }
+                ("add" | "replace", _, None) => {
+                    let value = value.ok_or_else(|| ScimError::invalid("value is required"))?;
+                    Self::apply_attr(kind, self.attrs_mut(index), path.unwrap_or_default(), Some(value))?;
+                }
+                ("remove", _, None) => {
+                    let path = path.ok_or_else(|| ScimError::new(StatusCode::BAD_REQUEST, Some("noTarget"), "path is required"))?;
+                    Self::apply_attr(kind, self.attrs_mut(index), path, None)?;
+                }
+                _ => return Err(ScimError::invalid(format!("Unsupported patch operation '{}'", op))),
+            }
+        }
+        self.get(kind, id)
+    }
+
+    /// Deletes a resource; a deleted group is also dropped from its members' parents.
+    fn delete(&mut self, kind: Kind, id: &str) -> Result<(), ScimError> {
+        let index = self.find(kind, id)?;
+        self.entities.remove(index);
+        if kind == Kind::Group {
+            self.clear_members(id);
+        }
+        Ok(())
+    }
+}
+
+fn scim_response(status: StatusCode, body: &Value) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header("content-type", "application/scim+json")
+        .body(Body::from(body.to_string()))
+        .unwrap()
+}
+
+fn error_response(e: ScimError) -> Response<Body> {
+    let mut body = json!({
+        "schemas": [ERROR_SCHEMA],
+        "status": e.status.as_u16().to_string(),
+        "detail": e.detail,
+    });
+    if let Some(scim_type) = e.scim_type {
+        body["scimType"] = scim_type.into();
+    }
+    scim_response(e.status, &body)
+}
+
+fn service_provider_config() -> Value {
+    json!({
+        "schemas": [CONFIG_SCHEMA],
+        "patch": {"supported": true},
+        "bulk": {"supported": false, "maxOperations": 0, "maxPayloadSize": 0},
+        "filter": {"supported": true, "maxResults": MAX_RESULTS},
+        "changePassword": {"supported": false},
+        "sort": {"supported": false},
+        "etag": {"supported": false},
+        "authenticationSchemes": [{
+            "type": "oauthbearertoken",
+            "name": "OAuth Bearer Token",
+            "description": "The token set in CEDAR_SCIM_TOKEN",
+        }],
+    })
+}
+
+/// Applies a write to the stored entities and returns what the operation produced.
+fn write<T>(
+    service: &CedarService,
+    scim: &Scim,
+    op: impl FnOnce(&mut Directory) -> Result<T, ScimError>,
+) -> Result<T, ScimError> {
+    let mut result = None;
+    service.update_entities("scim.write", |current| {
+        let mut directory = Directory::new(scim, current)?;
+        result = Some(op(&mut directory)?);
+        Ok::<_, ScimError>(directory.into_entities()?)
+    })?;
+    Ok(result.expect("update applied"))
+}
+
+/// Serves `/scim/v2/Users`, `/scim/v2/Groups` (and `/{id}` of each) and
+/// `/scim/v2/ServiceProviderConfig`.
+pub async fn handle(req: hyper::Request<Body>, service: &CedarService) -> Response<Body> {
+    let Some(ref scim) = service.scim else {
+        return crate::error_response(StatusCode::NOT_FOUND, "Not found");
+    };
+    let authorization = req.headers().get("authorization").and_then(|v| v.to_str().ok());
+    if !scim.authorized(authorization) {
+        return error_response(ScimError::new(StatusCode::UNAUTHORIZED, None, "Invalid or missing bearer token"));
+    }
+
+    let path = req.uri().path().strip_prefix(PREFIX).unwrap_or_default().to_string();
+    if path == "/ServiceProviderConfig" {
+        return scim_response(StatusCode::OK, &service_provider_config());
+    }
+    let (collection, id) = match path.trim_start_matches('/').split_once('/') {
+        Some((collection, id)) => (collection.to_string(), Some(percent_decode(id))),
+        None => (path.trim_start_matches('/').to_string(), None),
+    };
+    let kind = match collection.as_str() {
+        "Users" => Kind::User,
+        "Groups" => Kind::Group,
+        _ => return error_response(ScimError::new(StatusCode::NOT_FOUND, None, "Not found")),
+    };
+
+    let method = req.method().clone();
+    let params = query_params(req.uri());
+    let body = if matches!(method, Method::POST | Method::PUT | Method::PATCH) {
+        match read_json::<Value>(req).await {
+            Ok(body) => body,
+            Err(resp) => return resp,
+        }
+    } else {
+        Value::Null
+    };
+
+    let result = match (&method, id) {
+        (&Method::GET, None) => {
+            let number = |name: &str, default: usize| {
+                params.get(name).map_or(Ok(default), |v| {
+                    v.parse().map_err(|_| ScimError::invalid(format!("Invalid {}", name)))
+                })
+            };
+            number("startIndex", 1)
+                .and_then(|start_index| Ok((start_index, number("count", MAX_RESULTS)?)))
+                .and_then(|(start_index, count)| {
+                    let filter = params.get("filter").map(String::as_str);
+                    Directory::new(scim, service.state().entities.direct())?.list(kind, filter, start_index, count)
+                })
+                .map(|list| (StatusCode::OK, Some(list)))
+        }
+        (&Method::GET, Some(id)) => Directory::new(scim, service.state().entities.direct())
+            .and_then(|d| d.get(kind, &id))
+            .map(|resource| (StatusCode::OK, Some(resource))),
+        (&Method::POST, None) => {
+            write(service, scim, |d| d.create(kind, &body)).map(|r| (StatusCode::CREATED, Some(r)))
+        }
+        (&Method::PUT, Some(id)) => {
+            write(service, scim, |d| d.replace(kind, &id, &body)).map(|r| (StatusCode::OK, Some(r)))
+        }
+        (&Method::PATCH, Some(id)) => {
+            write(service, scim, |d| d.patch(kind, &id, &body)).map(|r| (StatusCode::OK, Some(r)))
+        }
+        (&Method::DELETE, Some(id)) => {
+            write(service, scim, |d| d.delete(kind, &id)).map(|()| (StatusCode::NO_CONTENT, None))
+        }
+        _ => Err(ScimError::new(StatusCode::METHOD_NOT_ALLOWED, None, "Method not allowed")),
+    };
+
+    match result {
+        Ok((status, Some(body))) => scim_response(status, &body),
+        Ok((status, None)) => Response::builder().status(status).body(Body::empty()).unwrap(),
+        Err(e) => {
+            if e.status.is_client_error() && e.status != StatusCode::NOT_FOUND {
+                log::warn!("SCIM {} {} rejected: {}", method, path, e.detail);
+            }
+            error_response(e)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scim() -> Scim {
+        Scim::new("secret", "User", "Group").unwrap()
+    }
+
+    fn directory(scim: &Scim) -> Directory<'_> {
+        Directory::new(scim, &[]).unwrap()
+    }
+
+    fn member_ids(group: &Value) -> Vec<&str> {
+        group["members"].as_array().unwrap().iter().map(|m| m["value"].as_str().unwrap()).collect()
+    }
+
+    #[test]
+    fn bearer_token_must_match() {
+        let scim = scim();
+        assert!(scim.authorized(Some("Bearer secret")));
+        assert!(!scim.authorized(Some("Bearer secre")));
+        assert!(!scim.authorized(Some("secret")));
+        assert!(!scim.authorized(None));
+    }
+
+    #[test]
+    fn creates_users_and_rejects_duplicates() {
+        let scim = scim();
+        let mut d = directory(&scim);
+        let body = json!({"userName": "alice", "displayName": "Alice", "emails": [{"value": "a@example.org", "primary": true}]});
+        let user = d.create(Kind::User, &body).unwrap();
+        assert_eq!(user["id"], "alice");
+        assert_eq!(user["active"], true);
+        assert_eq!(user["emails"][0]["value"], "a@example.org");
+        assert_eq!(d.create(Kind::User, &body).unwrap_err().status, StatusCode::CONFLICT);
+        assert_eq!(d.create(Kind::User, &json!({})).unwrap_err().status, StatusCode::BAD_REQUEST);
+
+        let entities = d.into_entities().unwrap();
+        assert_eq!(entities[0].uid().to_string(), r#"User::"alice""#);
+    }
+
+    #[test]
+    fn group_members_become_parents() {
+        let scim = scim();
+        let mut d = directory(&scim);
+        d.create(Kind::User, &json!({"userName": "alice"})).unwrap();
+        d.create(Kind::User, &json!({"userName": "bob"})).unwrap();
+        let group = d
+            .create(Kind::Group, &json!({"displayName": "ops", "members": [{"value": "alice"}]}))
+            .unwrap();
+        assert_eq!(member_ids(&group), ["alice"]);
+
+        let patch = json!({"Operations": [
+            {"op": "add", "path": "members", "value": [{"value": "bob"}]},
+            {"op": "remove", "path": "members[value eq \"alice\"]"}
+        ]});
+        let group = d.patch(Kind::Group, "ops", &patch).unwrap();
+        assert_eq!(member_ids(&group), ["bob"]);
+        assert_eq!(d.get(Kind::User, "bob").unwrap()["groups"][0]["value"], "ops");
+
+        let unknown = json!({"Operations": [{"op": "add", "path": "members", "value": [{"value": "carol"}]}]});
+        assert_eq!(d.patch(Kind::Group, "ops", &unknown).unwrap_err().status, StatusCode::BAD_REQUEST);
+
+        d.delete(Kind::Group, "ops").unwrap();
+        assert_eq!(d.get(Kind::User, "bob").unwrap()["groups"], json!([]));
+        assert_eq!(d.get(Kind::Group, "ops").unwrap_err().status, StatusCode::NOT_FOUND);
+    }
+
+    #[test]
+    fn patch_accepts_okta_and_azure_deactivation() {
+        let scim = scim();
+        let mut d = directory(&scim);
+        d.create(Kind::User, &json!({"userName": "alice"})).unwrap();
+        let okta = json!({"Operations": [{"op": "replace", "value": {"active": false}}]});
+        assert_eq!(d.patch(Kind::User, "alice", &okta).unwrap()["active"], false);
+        let azure = json!({"Operations": [{"op": "Replace", "path": "active", "value": "True"}]});
+        assert_eq!(d.patch(Kind::User, "alice", &azure).unwrap()["active"], true);
+    }
+
+    #[test]
+    fn lists_with_eq_filter_and_paging() {
+        let scim = scim();
+        let mut d = directory(&scim);
+        for name in ["alice", "bob", "carol"] {
+            d.create(Kind::User, &json!({"userName": name})).unwrap();
+        }
+        let list = d.list(Kind::User, Some(r#"userName eq "bob""#), 1, 10).unwrap();
+        assert_eq!(list["totalResults"], 1);
+        assert_eq!(list["Resources"][0]["id"], "bob");
+
+        let page = d.list(Kind::User, None, 2, 1).unwrap();
+        assert_eq!(page["totalResults"], 3);
+        assert_eq!(page["Resources"][0]["id"], "bob");
+
+        assert!(d.list(Kind::User, Some("userName sw \"a\""), 1, 10).is_err());
+    }
+}
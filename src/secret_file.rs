@@ -0,0 +1,149 @@
+use crate::config::Config;
+use crate::CedarService;
+use log::{error, info};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// A file holding a credential, such as a key of a Kubernetes Secret mounted as a volume. It
+/// is reread to pick up rotated values; Kubernetes swaps the whole directory in one step, so a
+/// reread never sees half a rotation.
+#[derive(Debug)]
+pub struct SecretFile {
+    path: String,
+    /// The contents in use, without surrounding whitespace such as a trailing newline.
+    current: Mutex<String>,
+}
+
+fn read(path: &str) -> Result<String, String> {
+    std::fs::read_to_string(path)
+        .map(|contents| contents.trim().to_string())
+        .map_err(|e| format!("Failed to read secret file {}: {}", path, e))
+}
+
+impl SecretFile {
+    pub fn open(path: &str) -> Result<Self, String> {
+        Ok(SecretFile {
+            path: path.to_string(),
+            current: Mutex::new(read(path)?),
+        })
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// The contents in use.
+    pub fn contents(&self) -> String {
+        self.current.lock().unwrap().clone()
+    }
+
+    /// The file's contents, if they differ from those in use.
+    pub fn changed(&self) -> Result<Option<String>, String> {
+        let contents = read(&self.path)?;
+        Ok((*self.current.lock().unwrap() != contents).then_some(contents))
+    }
+
+    /// Puts rotated contents in use.
+    pub fn commit(&self, contents: String) {
+        *self.current.lock().unwrap() = contents;
+    }
+}
+
+/// Entries of a secret holding a list: one per line or comma-separated, like the variable the
+/// file stands in for.
+pub fn entries(contents: &str) -> Vec<String> {
+    contents
+        .split(['\n', ','])
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// Checks `file` every `interval` and hands changed entries to `rotate`. Entries it refuses,
+/// and files that cannot be read, are logged and counted on every check until the file is
+/// fixed, and the current secret stays in use meanwhile.
+async fn watch(
+    file: Arc<SecretFile>,
+    secret: &'static str,
+    service: Arc<CedarService>,
+    interval: Duration,
+    rotate: impl Fn(&CedarService, &[String]) -> Result<(), String>,
+) {
+    let mut ticker = tokio::time::interval(interval);
+    ticker.tick().await;
+    loop {
+        ticker.tick().await;
+        let result = file.changed().and_then(|changed| match changed {
+            Some(contents) => {
+                rotate(&service, &entries(&contents))?;
+                file.commit(contents);
+                Ok(true)
+            }
+            None => Ok(false),
+        });
+        match result {
+            Ok(false) => {}
+            Ok(true) => {
+                service.metrics.incr("secret_rotations", &[("secret", secret), ("result", "success")]);
+                info!("Rotated {} from {}", secret, file.path());
+            }
+            Err(e) => {
+                service.metrics.incr("secret_rotations", &[("secret", secret), ("result", "error")]);
+                error!("Failed to rotate {} from {}, keeping the current ones: {}", secret, file.path(), e);
+            }
+        }
+    }
+}
+
+/// Watches the files `CEDAR_API_KEYS_FILE`, `CEDAR_HMAC_SECRETS_FILE` and
+/// `CEDAR_ADMIN_KEYS_FILE` name, rotating the keys and secrets in use when they change.
+pub fn start(service: &Arc<CedarService>, config: &Config) {
+    let interval = config.secrets_reload_interval;
+    if let Some(ref file) = config.api_keys_file {
+        tokio::spawn(watch(Arc::clone(file), "api_keys", Arc::clone(service), interval, |service, keys| {
+            service.api_keys.as_ref().map_or(Ok(()), |api_keys| api_keys.rotate(keys))
+        }));
+    }
+    if let Some(ref file) = config.hmac_secrets_file {
+        tokio::spawn(watch(Arc::clone(file), "hmac_secrets", Arc::clone(service), interval, |service, secrets| {
+            service.hmac.as_ref().map_or(Ok(()), |hmac| hmac.rotate(secrets))
+        }));
+    }
+    if let Some(ref file) = config.admin_keys_file {
+        tokio::spawn(watch(Arc::clone(file), "admin_keys", Arc::clone(service), interval, |service, keys| {
+            service.rbac.as_ref().map_or(Ok(()), |rbac| rbac.rotate_keys(keys))
+        }));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_entries_by_line_or_comma() {
+        assert_eq!(entries("web=k1\nbatch=k2, ci=k3\n\n"), ["web=k1", "batch=k2", "ci=k3"]);
+        assert!(entries(" \n").is_empty());
+    }
+
+    #[test]
+    fn notices_changed_contents() {
+        let path = std::env::temp_dir().join(format!("cedar-agent-secret-test-{}", std::process::id()));
+        std::fs::write(&path, "old\n").unwrap();
+        let file = SecretFile::open(path.to_str().unwrap()).unwrap();
+        assert_eq!(file.contents(), "old");
+        assert_eq!(file.changed(), Ok(None));
+
+        std::fs::write(&path, "new").unwrap();
+        assert_eq!(file.changed(), Ok(Some("new".to_string())));
+        // Still changed until the new contents are put in use
+        assert_eq!(file.changed(), Ok(Some("new".to_string())));
+        file.commit("new".to_string());
+        assert_eq!(file.changed(), Ok(None));
+
+        std::fs::remove_file(&path).unwrap();
+        assert!(file.changed().is_err());
+        assert_eq!(file.contents(), "new");
+    }
+}
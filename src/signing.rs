@@ -0,0 +1,199 @@
+use crate::digest;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::sync::{Mutex, RwLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub const TIMESTAMP_HEADER: &str = "x-cedar-timestamp";
+pub const SIGNATURE_HEADER: &str = "x-cedar-signature";
+
+/// Verifies HMAC-SHA256 request signatures.
+///
+/// The caller sends `X-Cedar-Timestamp` (Unix seconds) and `X-Cedar-Signature: sha256=<hex>`,
+/// where the signature covers `{timestamp}\n{METHOD}\n{path and query}\n{hex SHA-256 of the body}`.
+/// None of the fields can hold a newline, so no bytes can move from one field to the next.
+/// Requests outside the allowed clock skew are refused, and so is a signature seen before within
+/// that window.
+pub struct HmacVerifier {
+    /// Every configured secret is accepted, so keys can be rotated without downtime.
+    secrets: RwLock<Vec<Vec<u8>>>,
+    max_skew: u64,
+    /// Digests already accepted, with their timestamps, for replay detection. Keyed on the
+    /// decoded bytes so re-encoding a signature (e.g. in upper-case hex) is still a replay.
+    seen: Mutex<HashMap<Vec<u8>, u64>>,
+}
+
+fn unhex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+impl HmacVerifier {
+    pub fn new(secrets: &[String], max_skew: u64) -> Self {
+        Self {
+            secrets: RwLock::new(secrets.iter().map(|s| s.as_bytes().to_vec()).collect()),
+            max_skew,
+            seen: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Replaces the secrets with rotated ones. Signatures already accepted stay remembered, so
+    /// they cannot be replayed across the rotation.
+    pub fn rotate(&self, secrets: &[String]) -> Result<(), String> {
+        if secrets.is_empty() {
+            return Err("No HMAC secrets".to_string());
+        }
+        *self.secrets.write().unwrap() = secrets.iter().map(|s| s.as_bytes().to_vec()).collect();
+        Ok(())
+    }
+
+    fn mac(secret: &[u8], timestamp: &str, method: &str, path: &str, body: &[u8]) -> Hmac<Sha256> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("HMAC accepts keys of any length");
+        let body = digest::sha256_hex(body);
+        mac.update(format!("{}\n{}\n{}\n{}", timestamp, method, path, body).as_bytes());
+        mac
+    }
+
+    pub fn verify(
+        &self,
+        timestamp: Option<&str>,
+        signature: Option<&str>,
+        method: &str,
+        path: &str,
+        body: &[u8],
+    ) -> Result<(), String> {
+        let (Some(timestamp), Some(signature)) = (timestamp, signature) else {
+            return Err("Missing request signature".to_string());
+        };
+        let sent_at: u64 = timestamp
+            .parse()
+            .map_err(|_| "Invalid signature timestamp".to_string())?;
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        if now.abs_diff(sent_at) > self.max_skew {
+            return Err("Signature timestamp outside the allowed window".to_string());
+        }
+
+        let digest = signature
+            .strip_prefix("sha256=")
+            .and_then(unhex)
+            .ok_or_else(|| "Malformed signature".to_string())?;
+        let valid = self
+            .secrets
+            .read()
+            .unwrap()
+            .iter()
+            .any(|secret| Self::mac(secret, timestamp, method, path, body).verify_slice(&digest).is_ok());
+        if !valid {
+            return Err("Invalid request signature".to_string());
+        }
+
+        let mut seen = self.seen.lock().unwrap();
+        seen.retain(|_, at| now.abs_diff(*at) <= self.max_skew);
+        if seen.insert(digest, sent_at).is_some() {
+            return Err("Replayed request signature".to_string());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECRET: &str = "s3cret";
+
+    fn now() -> u64 {
+        SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs()
+    }
+
+    fn sign(timestamp: &str, method: &str, path: &str, body: &[u8]) -> String {
+        let digest = HmacVerifier::mac(SECRET.as_bytes(), timestamp, method, path, body).finalize().into_bytes();
+        format!("sha256={}", digest::hex(&digest))
+    }
+
+    fn verifier() -> HmacVerifier {
+        HmacVerifier::new(&["old".to_string(), SECRET.to_string()], 300)
+    }
+
+    #[test]
+    fn accepts_valid_signature() {
+        let ts = now().to_string();
+        let sig = sign(&ts, "POST", "/authorize", b"{}");
+        assert_eq!(verifier().verify(Some(&ts), Some(&sig), "POST", "/authorize", b"{}"), Ok(()));
+    }
+
+    #[test]
+    fn rejects_tampered_body() {
+        let ts = now().to_string();
+        let sig = sign(&ts, "POST", "/authorize", b"{}");
+        let result = verifier().verify(Some(&ts), Some(&sig), "POST", "/authorize", b"{\"a\":1}");
+        assert_eq!(result, Err("Invalid request signature".to_string()));
+    }
+
+    #[test]
+    fn fields_do_not_run_into_each_other() {
+        let ts = now().to_string();
+        assert_ne!(sign(&ts, "POST", "/v1/x.json", b"B"), sign(&ts, "POST", "/v1/x", b"json.B"));
+        let sig = sign(&ts, "POST", "/v1/x.json", b"B");
+        let result = verifier().verify(Some(&ts), Some(&sig), "POST", "/v1/x", b"json.B");
+        assert_eq!(result, Err("Invalid request signature".to_string()));
+    }
+
+    #[test]
+    fn rejects_timestamp_outside_window() {
+        let ts = (now() - 301).to_string();
+        let sig = sign(&ts, "POST", "/authorize", b"{}");
+        let result = verifier().verify(Some(&ts), Some(&sig), "POST", "/authorize", b"{}");
+        assert_eq!(result, Err("Signature timestamp outside the allowed window".to_string()));
+    }
+
+    #[test]
+    fn rejects_replay() {
+        let verifier = verifier();
+        let ts = now().to_string();
+        let sig = sign(&ts, "POST", "/authorize", b"{}");
+        assert!(verifier.verify(Some(&ts), Some(&sig), "POST", "/authorize", b"{}").is_ok());
+        let result = verifier.verify(Some(&ts), Some(&sig), "POST", "/authorize", b"{}");
+        assert_eq!(result, Err("Replayed request signature".to_string()));
+    }
+
+    #[test]
+    fn rejects_replay_with_changed_hex_case() {
+        let verifier = verifier();
+        let ts = now().to_string();
+        let sig = sign(&ts, "POST", "/authorize", b"{}");
+        assert!(verifier.verify(Some(&ts), Some(&sig), "POST", "/authorize", b"{}").is_ok());
+
+        let upper = format!("sha256={}", sig["sha256=".len()..].to_ascii_uppercase());
+        let result = verifier.verify(Some(&ts), Some(&upper), "POST", "/authorize", b"{}");
+        assert_eq!(result, Err("Replayed request signature".to_string()));
+    }
+
+    #[test]
+    fn rotated_secrets_replace_the_old_ones() {
+        let verifier = verifier();
+        let ts = now().to_string();
+        let first = sign(&ts, "POST", "/authorize", b"{}");
+        assert!(verifier.verify(Some(&ts), Some(&first), "POST", "/authorize", b"{}").is_ok());
+
+        verifier.rotate(&["new".to_string()]).unwrap();
+        let sig = sign(&ts, "POST", "/authorize", b"[]");
+        let result = verifier.verify(Some(&ts), Some(&sig), "POST", "/authorize", b"[]");
+        assert_eq!(result, Err("Invalid request signature".to_string()));
+        assert!(verifier.rotate(&[]).is_err());
+
+        // Accepted signatures are still remembered after a rotation
+        verifier.rotate(&["new".to_string(), SECRET.to_string()]).unwrap();
+        let result = verifier.verify(Some(&ts), Some(&first), "POST", "/authorize", b"{}");
+        assert_eq!(result, Err("Replayed request signature".to_string()));
+    }
+}
@@ -0,0 +1,80 @@
+use std::time::Duration;
+
+tokio::task_local! {
+    /// How long reading the body of the request being evaluated took.
+    static BODY_READ: Duration;
+}
+
+/// Runs `f`, an evaluation of a request whose body took `elapsed` to read, so a slow decision
+/// reports it.
+pub fn with_body_read<T>(elapsed: Duration, f: impl FnOnce() -> T) -> T {
+    BODY_READ.sync_scope(elapsed, f)
+}
+
+/// Where the time of one evaluation went.
+#[derive(Debug, Default)]
+pub struct Phases {
+    /// Unknown for gRPC calls and requests evaluated as part of another.
+    pub body_read: Option<Duration>,
+    /// Parsing the entities sent and merging them with the stored ones.
+    pub entity_parse: Duration,
+    /// Parsing the principal, action, resource and context and checking them against the schema.
+    pub validation: Duration,
+    /// Narrowing the policy set to the policies in force.
+    pub slicing: Duration,
+    pub evaluation: Duration,
+}
+
+impl Phases {
+    /// Phases timed during evaluation, with the body read time of the request served.
+    pub fn new() -> Self {
+        Phases {
+            body_read: BODY_READ.try_with(|elapsed| *elapsed).ok(),
+            ..Phases::default()
+        }
+    }
+
+    pub fn total(&self) -> Duration {
+        self.body_read.unwrap_or_default() + self.entity_parse + self.validation + self.slicing + self.evaluation
+    }
+
+    /// The phases as `name 1.2ms` pairs, in the order they run.
+    pub fn breakdown(&self) -> String {
+        let ms = |elapsed: Duration| format!("{:.1}ms", elapsed.as_secs_f64() * 1000.0);
+        let mut phases = Vec::new();
+        if let Some(body_read) = self.body_read {
+            phases.push(format!("body read {}", ms(body_read)));
+        }
+        phases.push(format!("entity parse {}", ms(self.entity_parse)));
+        phases.push(format!("validation {}", ms(self.validation)));
+        phases.push(format!("slicing {}", ms(self.slicing)));
+        phases.push(format!("evaluation {}", ms(self.evaluation)));
+        phases.join(", ")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn breaks_down_the_phases() {
+        let mut phases = Phases {
+            entity_parse: Duration::from_micros(2500),
+            validation: Duration::from_micros(300),
+            slicing: Duration::from_micros(60),
+            evaluation: Duration::from_millis(40),
+            ..Phases::new()
+        };
+        assert_eq!(phases.body_read, None);
+        assert_eq!(
+            phases.breakdown(),
+            "entity parse 2.5ms, validation 0.3ms, slicing 0.1ms, evaluation 40.0ms"
+        );
+        assert_eq!(phases.total(), Duration::from_micros(42_860));
+
+        phases.body_read = with_body_read(Duration::from_millis(3), || Phases::new().body_read);
+        assert!(phases.breakdown().starts_with("body read 3.0ms, entity parse"));
+        assert_eq!(phases.total(), Duration::from_micros(45_860));
+    }
+}
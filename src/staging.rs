@@ -0,0 +1,232 @@
+use crate::{audit, error_response, json_response, policies, proposals, reload, schema};
+use crate::{CedarService, PolicyState};
+use chrono::{SecondsFormat, Utc};
+use hyper::{Body, Method, Response, StatusCode};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, RwLock};
+
+pub const PATH: &str = "/admin/staging";
+/// Source of staged policies in diagnostics.
+const SOURCE: &str = "staging";
+
+/// Which policy set `/authorize?policyset=` evaluates against.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Target {
+    Active,
+    Staging,
+}
+
+impl Target {
+    pub fn parse(value: Option<&str>) -> Result<Self, String> {
+        match value {
+            None | Some("active") => Ok(Target::Active),
+            Some("staging") => Ok(Target::Staging),
+            Some(other) => Err(format!("Unknown policyset '{}' (expected active or staging)", other)),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct StagingRequest {
+    /// The complete policy set to stage, as Cedar text.
+    policies: String,
+}
+
+/// A policy set loaded next to the active one, to be tested and then promoted.
+#[derive(Debug, Serialize)]
+struct Staged {
+    policies: String,
+    staged_by: String,
+    staged_at: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    warnings: Vec<String>,
+    #[serde(skip)]
+    policy_set: cedar_policy::PolicySet,
+}
+
+/// What `GET /admin/staging` and `PUT /admin/staging` report.
+#[derive(Debug, Serialize)]
+struct Report<'a> {
+    #[serde(flatten)]
+    staged: &'a Staged,
+    /// How the staged policies differ from the active ones.
+    changes: reload::PolicyDiff,
+}
+
+/// The staging policy set, if one is loaded.
+#[derive(Default)]
+pub struct Staging {
+    staged: RwLock<Option<Arc<Staged>>>,
+}
+
+impl Staging {
+    /// The active state with the staged policies in place of the active ones, so staged
+    /// policies see the current schema and entities.
+    pub fn state(&self, active: &PolicyState) -> Option<PolicyState> {
+        let staged = self.staged.read().unwrap().clone()?;
+        let loaded = policies::LoadedPolicies::from_set(staged.policy_set.clone(), SOURCE);
+        Some(PolicyState {
+            disabled: active.disabled_in(&staged.policy_set),
+            ..PolicyState::new(loaded, active.schema.clone(), active.schema_json.clone(), Arc::clone(&active.entities))
+        })
+    }
+}
+
+/// Serves `/admin/staging` and `/admin/staging/promote`.
+pub async fn handle(req: hyper::Request<Body>, service: &CedarService) -> Response<Body> {
+    let staging = &service.staging;
+    match (req.method(), req.uri().path()) {
+        (&Method::GET, PATH) => match staging.staged.read().unwrap().clone() {
+            Some(staged) => report(service, &staged),
+            None => not_staged(),
+        },
+        (&Method::PUT, PATH) => stage(req, service).await,
+        (&Method::DELETE, PATH) => match staging.staged.write().unwrap().take() {
+            Some(_) => {
+                info!("Staging policy set cleared");
+                Response::builder().status(StatusCode::NO_CONTENT).body(Body::empty()).unwrap()
+            }
+            None => not_staged(),
+        },
+        (&Method::POST, "/admin/staging/promote") => promote(service),
+        _ => error_response(StatusCode::NOT_FOUND, "Not found"),
+    }
+}
+
+fn not_staged() -> Response<Body> {
+    error_response(StatusCode::NOT_FOUND, "No staging policy set is loaded")
+}
+
+fn report(service: &CedarService, staged: &Staged) -> Response<Body> {
+    let changes = reload::diff(&service.state().policy_set, &staged.policy_set);
+    json_response(StatusCode::OK, &Report { staged, changes })
+}
+
+/// Loads the staging policy set after validating it against the active schema, replacing any
+/// staged before.
+async fn stage(req: hyper::Request<Body>, service: &CedarService) -> Response<Body> {
+    if service.reload_sources.is_none() {
+        return error_response(
+            StatusCode::CONFLICT,
+            "Policies come from Verified Permissions; there is no staging policy set",
+        );
+    }
+    let body = match crate::read_json::<StagingRequest>(req).await {
+        Ok(body) => body,
+        Err(resp) => return resp,
+    };
+    let policy_set = match policies::parse(&body.policies) {
+        Ok(policy_set) => policy_set,
+        Err(e) => return error_response(StatusCode::BAD_REQUEST, format!("Failed to parse policies: {}", e)),
+    };
+    let warnings = match schema::check_candidate(service.state().schema.as_ref(), &policy_set, "Staging") {
+        Ok(warnings) => warnings,
+        Err(e) => return json_response(StatusCode::BAD_REQUEST, &e),
+    };
+    let staged = Arc::new(Staged {
+        policies: body.policies,
+        staged_by: audit::identified().unwrap_or_else(|| "anonymous".to_string()),
+        staged_at: Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+        warnings,
+        policy_set,
+    });
+    *service.staging.staged.write().unwrap() = Some(Arc::clone(&staged));
+    info!(
+        "Staged {} policies from {}",
+        staged.policy_set.policies().count(),
+        staged.staged_by
+    );
+    report(service, &staged)
+}
+
+/// Activates the staging policy set in one step and clears it.
+fn promote(service: &CedarService) -> Response<Body> {
+    if service.reload_sources.is_none() {
+        return error_response(
+            StatusCode::CONFLICT,
+            "Policies come from Verified Permissions; there is no staging policy set",
+        );
+    }
+    if let Some(resp) = proposals::refuse_unapproved(service, "Promoting the staging set") {
+        return resp;
+    }
+    let Some(staged) = service.staging.staged.write().unwrap().take() else {
+        return not_staged();
+    };
+    let loaded = policies::LoadedPolicies::from_set(staged.policy_set.clone(), SOURCE);
+    let changes = reload::diff(&service.state().policy_set, &staged.policy_set);
+    match service.replace_policies("staging.promote", loaded, None, None, Some(&staged.policies)) {
+        Ok(_) => {
+            info!(
+                "Promoted the staging policy set ({} added, {} removed, {} changed policies)",
+                changes.added.len(),
+                changes.removed.len(),
+                changes.changed.len()
+            );
+            json_response(
+                StatusCode::OK,
+                &serde_json::json!({
+                    "status": "promoted",
+                    "policies": changes,
+                    "data_version": service.data_version(),
+                }),
+            )
+        }
+        Err(e) => {
+            warn!("Staging policy set not promoted: {}", e);
+            // Keep it staged, unless another was staged meanwhile
+            service.staging.staged.write().unwrap().get_or_insert(staged);
+            json_response(StatusCode::CONFLICT, &e)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn parses_the_policy_set_parameter() {
+        assert_eq!(Target::parse(None), Ok(Target::Active));
+        assert_eq!(Target::parse(Some("active")), Ok(Target::Active));
+        assert_eq!(Target::parse(Some("staging")), Ok(Target::Staging));
+        assert!(Target::parse(Some("blue")).is_err());
+    }
+
+    #[tokio::test]
+    async fn promotion_needs_approval_when_required() {
+        let path = std::env::temp_dir().join(format!("cedar-staging-{}.cedar", std::process::id()));
+        fs::write(&path, "permit (principal, action, resource);\n").unwrap();
+        let config = crate::config::Config {
+            policy_path: path.to_str().unwrap().to_string(),
+            schema_path: path.with_extension("schema.json").to_str().unwrap().to_string(),
+            require_approval: true,
+            ..crate::config::Config::from_env().unwrap()
+        };
+        assert!(CedarService::new(&config).is_err(), "approval needs administrators");
+        let config = crate::config::Config {
+            admin_keys: vec!["alice=alice-key".to_string()],
+            admin_roles: vec!["alice=policy-editor".to_string()],
+            ..config
+        };
+        let service = CedarService::new(&config).unwrap();
+
+        let policy_set = policies::parse("forbid (principal, action, resource);").unwrap();
+        *service.staging.staged.write().unwrap() = Some(Arc::new(Staged {
+            policies: policy_set.to_string(),
+            staged_by: "alice".to_string(),
+            staged_at: String::new(),
+            warnings: Vec::new(),
+            policy_set,
+        }));
+        let resp = promote(&service);
+        let written = fs::read_to_string(&path);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+        assert!(service.staging.staged.read().unwrap().is_some(), "the set stays staged");
+        assert_eq!(written.unwrap(), "permit (principal, action, resource);\n");
+        assert_eq!(service.state().policy_set.policies().next().unwrap().effect(), cedar_policy::Effect::Permit);
+    }
+}
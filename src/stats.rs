@@ -0,0 +1,245 @@
+use serde::Serialize;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::runtime::Handle;
+
+/// Connection and runtime counters for `GET /debug/stats`, kept whether or not metrics are
+/// scraped.
+pub struct Stats {
+    started: Instant,
+    connections: Arc<AtomicUsize>,
+    accepted: Arc<AtomicU64>,
+    in_flight: AtomicUsize,
+    requests: AtomicU64,
+    /// Tokio runtimes serving requests, with the busy time of their workers at the last report.
+    runtimes: Mutex<Vec<Runtime>>,
+}
+
+struct Runtime {
+    name: String,
+    handle: Handle,
+    sampled: Instant,
+    busy: Duration,
+}
+
+/// Counts a connection as open until dropped.
+pub struct ConnectionGuard(Arc<AtomicUsize>);
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Counts a request as in flight until dropped.
+pub struct RequestGuard<'a>(&'a AtomicUsize);
+
+impl Drop for RequestGuard<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Report {
+    uptime_seconds: u64,
+    connections: Counts,
+    requests: Counts,
+    /// Evaluations waiting for a slot under `CEDAR_MAX_CONCURRENT_EVALUATIONS`.
+    queued_evaluations: usize,
+    runtimes: Vec<RuntimeReport>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    memory: Option<Memory>,
+}
+
+#[derive(Debug, Serialize)]
+struct Counts {
+    /// Open connections, or requests being handled.
+    current: usize,
+    total: u64,
+}
+
+#[derive(Debug, Serialize)]
+struct RuntimeReport {
+    name: String,
+    workers: usize,
+    alive_tasks: usize,
+    global_queue_depth: usize,
+    /// Share of the workers' time spent busy since the previous report (or since startup).
+    utilization: f64,
+    window_seconds: f64,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+struct Memory {
+    resident_bytes: u64,
+    peak_resident_bytes: u64,
+}
+
+impl Stats {
+    /// Starts counting, with the runtime of the caller as `main`.
+    pub fn new() -> Self {
+        let stats = Self {
+            started: Instant::now(),
+            connections: Arc::default(),
+            accepted: Arc::default(),
+            in_flight: AtomicUsize::new(0),
+            requests: AtomicU64::new(0),
+            runtimes: Mutex::new(Vec::new()),
+        };
+        if let Ok(handle) = Handle::try_current() {
+            stats.add_runtime("main".to_string(), handle);
+        }
+        stats
+    }
+
+    /// Registers the runtime of an acceptor thread (see `CEDAR_ACCEPTORS`); a no-op on the main
+    /// runtime.
+    pub fn register_acceptor(&self) {
+        if let Some(name) = std::thread::current().name().filter(|name| name.starts_with("acceptor-")) {
+            self.add_runtime(name.to_string(), Handle::current());
+        }
+    }
+
+    fn add_runtime(&self, name: String, handle: Handle) {
+        let busy = busy_time(&handle);
+        self.runtimes.lock().unwrap().push(Runtime {
+            name,
+            handle,
+            sampled: Instant::now(),
+            busy,
+        });
+    }
+
+    pub fn connection(&self) -> ConnectionGuard {
+        self.connections.fetch_add(1, Ordering::Relaxed);
+        self.accepted.fetch_add(1, Ordering::Relaxed);
+        ConnectionGuard(Arc::clone(&self.connections))
+    }
+
+    /// Requests being handled.
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.load(Ordering::Relaxed)
+    }
+
+    pub fn request(&self) -> RequestGuard<'_> {
+        self.in_flight.fetch_add(1, Ordering::Relaxed);
+        self.requests.fetch_add(1, Ordering::Relaxed);
+        RequestGuard(&self.in_flight)
+    }
+
+    pub fn report(&self, queued_evaluations: usize) -> Report {
+        let now = Instant::now();
+        let runtimes = self
+            .runtimes
+            .lock()
+            .unwrap()
+            .iter_mut()
+            .map(|runtime| {
+                let metrics = runtime.handle.metrics();
+                let busy = busy_time(&runtime.handle);
+                let window = now - runtime.sampled;
+                let report = RuntimeReport {
+                    name: runtime.name.clone(),
+                    workers: metrics.num_workers(),
+                    alive_tasks: metrics.num_alive_tasks(),
+                    global_queue_depth: metrics.global_queue_depth(),
+                    utilization: utilization(busy.saturating_sub(runtime.busy), window, metrics.num_workers()),
+                    window_seconds: window.as_secs_f64(),
+                };
+                runtime.sampled = now;
+                runtime.busy = busy;
+                report
+            })
+            .collect();
+        Report {
+            uptime_seconds: self.started.elapsed().as_secs(),
+            connections: Counts {
+                current: self.connections.load(Ordering::Relaxed),
+                total: self.accepted.load(Ordering::Relaxed),
+            },
+            requests: Counts {
+                current: self.in_flight.load(Ordering::Relaxed),
+                total: self.requests.load(Ordering::Relaxed),
+            },
+            queued_evaluations,
+            runtimes,
+            memory: std::fs::read_to_string("/proc/self/status").ok().and_then(|status| memory(&status)),
+        }
+    }
+}
+
+/// Time all of a runtime's workers have spent busy.
+fn busy_time(handle: &Handle) -> Duration {
+    let metrics = handle.metrics();
+    (0..metrics.num_workers()).map(|worker| metrics.worker_total_busy_duration(worker)).sum()
+}
+
+/// Busy time as a share of what `workers` could have spent busy in `window`, rounded to
+/// three places.
+fn utilization(busy: Duration, window: Duration, workers: usize) -> f64 {
+    let capacity = window.as_secs_f64() * workers as f64;
+    if capacity == 0.0 {
+        return 0.0;
+    }
+    ((busy.as_secs_f64() / capacity).min(1.0) * 1000.0).round() / 1000.0
+}
+
+/// Resident and peak resident memory from Linux's `/proc/self/status`.
+fn memory(status: &str) -> Option<Memory> {
+    let kilobytes = |field: &str| {
+        status
+            .lines()
+            .find_map(|line| line.strip_prefix(field))
+            .and_then(|value| value.trim().strip_suffix("kB"))
+            .and_then(|value| value.trim().parse::<u64>().ok())
+    };
+    Some(Memory {
+        resident_bytes: kilobytes("VmRSS:")? * 1024,
+        peak_resident_bytes: kilobytes("VmHWM:")? * 1024,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_memory_from_proc_status() {
+        let status = "Name:\tcedar-agent\nVmHWM:\t   20480 kB\nVmRSS:\t   10240 kB\nThreads:\t9\n";
+        assert_eq!(
+            memory(status),
+            Some(Memory {
+                resident_bytes: 10240 * 1024,
+                peak_resident_bytes: 20480 * 1024,
+            })
+        );
+        assert_eq!(memory("Name:\tcedar-agent\n"), None);
+    }
+
+    #[test]
+    fn utilization_is_a_share_of_worker_time() {
+        let second = Duration::from_secs(1);
+        assert_eq!(utilization(second, 2 * second, 4), 0.125);
+        assert_eq!(utilization(8 * second, second, 4), 1.0);
+        assert_eq!(utilization(second, Duration::ZERO, 4), 0.0);
+    }
+
+    #[tokio::test]
+    async fn counts_connections_and_requests_until_dropped() {
+        let stats = Stats::new();
+        let connection = stats.connection();
+        {
+            let _request = stats.request();
+            let report = stats.report(0);
+            assert_eq!((report.connections.current, report.requests.current), (1, 1));
+        }
+        drop(connection);
+        let report = stats.report(2);
+        assert_eq!((report.connections.current, report.connections.total), (0, 1));
+        assert_eq!((report.requests.current, report.requests.total), (0, 1));
+        assert_eq!(report.queued_evaluations, 2);
+        assert_eq!(report.runtimes[0].name, "main");
+    }
+}
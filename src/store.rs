@@ -0,0 +1,325 @@
+use crate::entities;
+use crate::PolicyState;
+use cedar_policy::Entity;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashSet;
+use std::fs::{self, File, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+const SNAPSHOT: &str = "snapshot.json";
+const LOG: &str = "wal.jsonl";
+const LOCK: &str = "lock";
+
+/// One change in the write-ahead log, or the whole persisted state in the snapshot. Fields
+/// left out did not change. A `null` schema is one that was removed.
+#[derive(Debug, Default, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Record {
+    pub seq: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none", deserialize_with = "entities::present")]
+    pub schema: Option<Value>,
+    /// Stored entities in Cedar's JSON format, all of them: the snapshot holds the whole set,
+    /// and log records hold only `removed` and `upserted`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub entities: Option<Vec<Value>>,
+    /// UIDs of entities that were removed.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub removed: Vec<Value>,
+    /// Entities that were added or changed, replacing any with the same UID.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub upserted: Vec<Value>,
+}
+
+/// Replays log records over a snapshot. A last line without its newline is a write a crash cut
+/// short, which was never acknowledged, and is dropped; a complete line that does not parse is
+/// corruption. Records the snapshot already includes (left by a compaction cut short) are
+/// skipped. Returns the state, the length of the log up to its last complete record, and the
+/// number of records in it.
+fn recover(mut state: Record, log: &str) -> Result<(Record, usize, usize), String> {
+    let (mut len, mut records) = (0, 0);
+    for (n, line) in log.split_inclusive('\n').enumerate() {
+        if !line.ends_with('\n') {
+            break;
+        }
+        let record: Record = serde_json::from_str(line).map_err(|e| format!("Corrupt record on line {}: {}", n + 1, e))?;
+        len += line.len();
+        records += 1;
+        if record.seq <= state.seq {
+            continue;
+        }
+        state.seq = record.seq;
+        if record.schema.is_some() {
+            state.schema = record.schema;
+        }
+        if record.entities.is_some() {
+            state.entities = record.entities;
+        }
+        if !record.removed.is_empty() || !record.upserted.is_empty() {
+            let replaced: HashSet<&Value> = record.removed.iter().chain(record.upserted.iter().map(|e| &e["uid"])).collect();
+            let entities = state.entities.get_or_insert_with(Vec::new);
+            entities.retain(|e| !replaced.contains(&e["uid"]));
+            entities.extend(record.upserted);
+        }
+    }
+    Ok((state, len, records))
+}
+
+fn entity_json(entities: &[Entity]) -> Result<Vec<Value>, String> {
+    entities.iter().map(entities::to_json).collect()
+}
+
+struct Log {
+    file: File,
+    /// Length of the log, to cut off a record that failed part-way.
+    len: u64,
+    seq: u64,
+    records: usize,
+}
+
+/// Keeps the schema and stored entities on disk in `CEDAR_DATA_DIR`, so writes survive a
+/// restart: each change is appended to a write-ahead log and synced before it is activated, and
+/// the log is folded into a snapshot every `CEDAR_DATA_COMPACT_AFTER` records.
+pub struct Store {
+    dir: PathBuf,
+    compact_after: usize,
+    log: Mutex<Log>,
+    /// Held while the store is open, so no other process writes to the directory.
+    _lock: File,
+}
+
+impl Store {
+    /// Opens the store in `dir`, creating it if needed, and recovers the persisted state. The
+    /// directory is locked first, so another process cannot write to it in the meantime.
+    pub fn open(dir: &str, compact_after: usize) -> Result<(Self, Record), Box<dyn std::error::Error>> {
+        let dir = PathBuf::from(dir);
+        fs::create_dir_all(&dir).map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+        let lock = crate::upgrade::lock(&dir.join(LOCK))?;
+
+        let snapshot_path = dir.join(SNAPSHOT);
+        let snapshot = match fs::read_to_string(&snapshot_path) {
+            Ok(json) => serde_json::from_str(&json)
+                .map_err(|e| format!("Failed to read {}: {}", snapshot_path.display(), e))?,
+            Err(e) if e.kind() == ErrorKind::NotFound => Record::default(),
+            Err(e) => return Err(format!("Failed to read {}: {}", snapshot_path.display(), e).into()),
+        };
+        let log_path = dir.join(LOG);
+        let log = match fs::read_to_string(&log_path) {
+            Ok(log) => log,
+            Err(e) if e.kind() == ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(format!("Failed to read {}: {}", log_path.display(), e).into()),
+        };
+        let (state, len, records) =
+            recover(snapshot, &log).map_err(|e| format!("Failed to recover {}: {}", log_path.display(), e))?;
+
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&log_path)
+            .map_err(|e| format!("Failed to open {}: {}", log_path.display(), e))?;
+        if len < log.len() {
+            warn!("Dropping an incomplete record at the end of {}", log_path.display());
+            file.set_len(len as u64)
+                .and_then(|_| file.sync_data())
+                .map_err(|e| format!("Failed to truncate {}: {}", log_path.display(), e))?;
+        }
+        if state.seq > 0 {
+            info!("Recovered state from {} (version {}, {} log records)", dir.display(), state.seq, records);
+        }
+
+        let store = Store {
+            dir,
+            compact_after,
+            log: Mutex::new(Log {
+                file,
+                len: len as u64,
+                seq: state.seq,
+                records,
+            }),
+            _lock: lock,
+        };
+        Ok((store, state))
+    }
+
+    /// Durably records what changed from `previous` to `next` before `next` is activated: the
+    /// schema if it changed, and the entities that were removed, added or changed. Changes to
+    /// policies alone are not recorded: policies are always read from their source.
+    pub fn record(&self, previous: &PolicyState, next: &PolicyState) -> Result<(), String> {
+        let schema_changed = previous.schema_json != next.schema_json;
+        let entities_changed = !Arc::ptr_eq(&previous.entities, &next.entities);
+        if !schema_changed && !entities_changed {
+            return Ok(());
+        }
+
+        let (removed, upserted) = if entities_changed {
+            entities::changes(previous.entities.direct(), next.entities.direct())?
+        } else {
+            Default::default()
+        };
+        if !schema_changed && removed.is_empty() && upserted.is_empty() {
+            return Ok(());
+        }
+
+        let mut log = self.log.lock().unwrap();
+        let record = Record {
+            seq: log.seq + 1,
+            schema: schema_changed.then(|| next.schema_json.clone().unwrap_or(Value::Null)),
+            entities: None,
+            removed,
+            upserted,
+        };
+        let mut line = serde_json::to_vec(&record).map_err(|e| format!("Failed to serialize change: {}", e))?;
+        line.push(b'\n');
+        let path = self.dir.join(LOG);
+        if let Err(e) = log.file.write_all(&line).and_then(|_| log.file.sync_data()) {
+            let _ = log.file.set_len(log.len);
+            return Err(format!("Failed to write {}: {}", path.display(), e));
+        }
+        log.len += line.len() as u64;
+        log.seq = record.seq;
+        log.records += 1;
+
+        // The change is durable in the log already, so a failed compaction is retried later
+        if log.records >= self.compact_after {
+            if let Err(e) = self.compact(&mut log, next) {
+                warn!("Failed to compact {}: {}", self.dir.display(), e);
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes `state` as the snapshot and empties the log. The snapshot replaces the old one
+    /// atomically, and keeps its sequence number so a log left behind by a crash before it is
+    /// emptied is not replayed over it.
+    fn compact(&self, log: &mut Log, state: &PolicyState) -> Result<(), String> {
+        let snapshot = Record {
+            seq: log.seq,
+            schema: Some(state.schema_json.clone().unwrap_or(Value::Null)),
+            entities: Some(entity_json(state.entities.direct())?),
+            ..Default::default()
+        };
+        let json = serde_json::to_vec(&snapshot).map_err(|e| format!("Failed to serialize snapshot: {}", e))?;
+        let tmp = self.dir.join(format!("{}.tmp", SNAPSHOT));
+        write_synced(&tmp, &json)?;
+        fs::rename(&tmp, self.dir.join(SNAPSHOT)).map_err(|e| format!("Failed to replace snapshot: {}", e))?;
+        sync_dir(&self.dir)?;
+
+        log.file
+            .set_len(0)
+            .and_then(|_| log.file.sync_data())
+            .map_err(|e| format!("Failed to truncate the log: {}", e))?;
+        log.len = 0;
+        log.records = 0;
+        info!("Compacted {} at version {}", self.dir.display(), log.seq);
+        Ok(())
+    }
+}
+
+fn write_synced(path: &Path, contents: &[u8]) -> Result<(), String> {
+    let mut file = File::create(path).map_err(|e| format!("Failed to create {}: {}", path.display(), e))?;
+    file.write_all(contents)
+        .and_then(|_| file.sync_all())
+        .map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+}
+
+/// Makes a rename in `dir` durable.
+fn sync_dir(dir: &Path) -> Result<(), String> {
+    #[cfg(unix)]
+    File::open(dir)
+        .and_then(|d| d.sync_all())
+        .map_err(|e| format!("Failed to sync {}: {}", dir.display(), e))?;
+    #[cfg(not(unix))]
+    let _ = dir;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::entities::{EntityStore, Ingest};
+    use serde_json::json;
+
+    fn state(schema: Option<Value>, entities: Value) -> PolicyState {
+        let entities = EntityStore::from_json(entities, None, Ingest::default()).unwrap();
+        PolicyState::new(Default::default(), None, schema, Arc::new(entities))
+    }
+
+    fn user(id: &str) -> Value {
+        json!({"uid": {"type": "User", "id": id}, "attrs": {}, "parents": []})
+    }
+
+    #[test]
+    fn replays_the_log_over_the_snapshot() {
+        let snapshot = Record { seq: 2, schema: Some(json!({"": {}})), entities: Some(vec![]), ..Default::default() };
+        let log = concat!(
+            "{\"seq\":2,\"entities\":[]}\n",
+            "{\"seq\":3,\"entities\":[{\"uid\":{\"type\":\"User\",\"id\":\"a\"},\"attrs\":{},\"parents\":[]}]}\n",
+            "{\"seq\":4,\"schema\":null}\n",
+            "{\"seq\":5,\"sch",
+        );
+        let (state, len, records) = recover(snapshot, log).unwrap();
+        assert_eq!(state.seq, 4);
+        assert_eq!(state.schema, Some(Value::Null));
+        assert_eq!(state.entities.unwrap().len(), 1);
+        assert_eq!((len, records), (log.len() - "{\"seq\":5,\"sch".len(), 3));
+
+        assert!(recover(Record::default(), "{\"seq\":1}\nnot json\n{\"seq\":2}\n").is_err());
+    }
+
+    #[test]
+    fn logs_only_the_entities_that_changed() {
+        let before = EntityStore::from_json(json!([user("a"), user("b")]), None, Ingest::default()).unwrap();
+        let mut changed = user("b");
+        changed["attrs"] = json!({"admin": true});
+        let after = EntityStore::from_json(json!([changed, user("c")]), None, Ingest::default()).unwrap();
+        let (removed, upserted) = entities::changes(before.direct(), after.direct()).unwrap();
+        assert_eq!(removed, [json!({"type": "User", "id": "a"})]);
+        assert_eq!(upserted.iter().map(|e| e["uid"]["id"].clone()).collect::<Vec<_>>(), ["b", "c"]);
+
+        let record = Record { seq: 3, removed, upserted, ..Default::default() };
+        let log = serde_json::to_string(&record).unwrap() + "\n";
+        let snapshot = Record { seq: 2, entities: Some(vec![user("a"), user("b")]), ..Default::default() };
+        let (state, _, _) = recover(snapshot, &log).unwrap();
+        let entities = state.entities.unwrap();
+        assert_eq!(entities.iter().map(|e| e["uid"]["id"].clone()).collect::<Vec<_>>(), ["b", "c"]);
+        assert_eq!(entities[0]["attrs"]["admin"], json!(true));
+    }
+
+    #[test]
+    fn recovers_writes_across_restarts_and_compactions() {
+        let dir = std::env::temp_dir().join(format!("cedar-store-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let dir_name = dir.to_str().unwrap();
+
+        let (store, recovered) = Store::open(dir_name, 3).unwrap();
+        assert_eq!(recovered, Record::default());
+        assert!(Store::open(dir_name, 3).is_err(), "the directory is locked while open");
+        let empty = state(None, json!([]));
+        let one = state(None, json!([user("a")]));
+        store.record(&empty, &one).unwrap();
+        // A change to the policies alone is not recorded
+        let policies_only = PolicyState {
+            entities: Arc::clone(&one.entities),
+            ..state(None, json!([]))
+        };
+        store.record(&one, &policies_only).unwrap();
+        drop(store);
+
+        let (store, recovered) = Store::open(dir_name, 3).unwrap();
+        assert_eq!(recovered.seq, 1);
+        assert_eq!(recovered.entities.unwrap().len(), 1);
+        let two = state(Some(json!({"": {"entityTypes": {}, "actions": {}}})), json!([user("a"), user("b")]));
+        store.record(&one, &empty).unwrap();
+        store.record(&empty, &two).unwrap();
+        assert_eq!(fs::read_to_string(dir.join(LOG)).unwrap(), "");
+        drop(store);
+
+        let (_, recovered) = Store::open(dir_name, 3).unwrap();
+        assert_eq!(recovered.seq, 3);
+        assert_eq!(recovered.schema, two.schema_json);
+        assert_eq!(recovered.entities.unwrap().len(), 2);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,140 @@
+use cedar_policy::{Entities, EntityUid};
+
+/// What is known about an entity of a denied request, to hint at conditions.
+#[derive(Debug, Default)]
+pub struct EntityHints {
+    pub attributes: Vec<String>,
+    pub parents: Vec<String>,
+}
+
+impl EntityHints {
+    /// The attribute names and parents of `uid` among `entities`, sorted.
+    pub fn of(entities: &Entities, uid: &EntityUid) -> Self {
+        let Some(json) = entities.get(uid).and_then(|entity| entity.to_json_value().ok()) else {
+            return Self::default();
+        };
+        let mut attributes: Vec<String> = json["attrs"].as_object().map(|attrs| attrs.keys().cloned().collect()).unwrap_or_default();
+        let mut parents: Vec<String> = json["parents"]
+            .as_array()
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|parent| EntityUid::from_json(parent.clone()).ok())
+            .map(|parent| parent.to_string())
+            .collect();
+        attributes.sort();
+        parents.sort();
+        Self { attributes, parents }
+    }
+
+    fn describe(&self) -> Option<String> {
+        let mut parts = Vec::new();
+        if !self.attributes.is_empty() {
+            parts.push(self.attributes.join(", "));
+        }
+        if !self.parents.is_empty() {
+            parts.push(format!("in {}", self.parents.join(", ")));
+        }
+        (!parts.is_empty()).then(|| parts.join("; "))
+    }
+}
+
+/// A denied request a permit policy is suggested for.
+pub struct Denied<'a> {
+    pub principal: &'a EntityUid,
+    pub action: &'a EntityUid,
+    /// Whether `action` is a group evaluated as its member actions.
+    pub action_group: bool,
+    pub resource: &'a EntityUid,
+    pub principal_hints: EntityHints,
+    pub resource_hints: EntityHints,
+    pub context: Vec<String>,
+}
+
+/// A permit policy allowing exactly `denied`, with a `when { true }` placeholder and comments
+/// naming what its conditions could test. It is meant to be edited, not loaded as is.
+pub fn skeleton(denied: &Denied) -> String {
+    let id = slug(&format!(
+        "allow-{}-{}",
+        denied.action.id().unescaped(),
+        denied.resource.type_name().basename()
+    ));
+    let action = if denied.action_group { "in" } else { "==" };
+
+    let mut hints = Vec::new();
+    if let Some(principal) = denied.principal_hints.describe() {
+        hints.push(format!("principal: {}", principal));
+    }
+    if let Some(resource) = denied.resource_hints.describe() {
+        hints.push(format!("resource: {}", resource));
+    }
+    if !denied.context.is_empty() {
+        hints.push(format!("context: {}", denied.context.join(", ")));
+    }
+
+    let mut policy = String::from("// Suggested from a denied request. It allows only this principal and resource:\n");
+    policy.push_str("// widen the scope (e.g. `principal in Group::\"...\"`) and replace `true` with real conditions.\n");
+    policy.push_str(&format!("@id(\"{}\")\n", id));
+    policy.push_str(&format!(
+        "permit (\n    principal == {},\n    action {} {},\n    resource == {}\n)\nwhen {{\n",
+        denied.principal, action, denied.action, denied.resource
+    ));
+    for hint in hints {
+        policy.push_str(&format!("    // {}\n", hint.replace('\n', " ")));
+    }
+    policy.push_str("    true\n};\n");
+    policy
+}
+
+/// `text` lowercased, with runs of anything but letters and digits turned into one `-`.
+fn slug(text: &str) -> String {
+    let mut slug = String::new();
+    for c in text.chars() {
+        if c.is_ascii_alphanumeric() {
+            slug.push(c.to_ascii_lowercase());
+        } else if !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    slug.trim_matches('-').to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn suggests_a_policy_that_allows_the_request() {
+        let uid = |text: &str| -> EntityUid { text.parse().unwrap() };
+        let entities = Entities::from_json_value(
+            serde_json::json!([{
+                "uid": { "type": "User", "id": "alice" },
+                "attrs": { "level": 3, "department": "sales" },
+                "parents": [{ "type": "Group", "id": "staff" }]
+            }]),
+            None,
+        )
+        .unwrap();
+        let (principal, action, resource) = (uid(r#"User::"alice""#), uid(r#"Action::"read notes""#), uid(r#"App::Doc::"d1""#));
+        let denied = Denied {
+            principal: &principal,
+            action: &action,
+            action_group: false,
+            resource: &resource,
+            principal_hints: EntityHints::of(&entities, &principal),
+            resource_hints: EntityHints::of(&entities, &resource),
+            context: vec!["ip".to_string()],
+        };
+        let policy = skeleton(&denied);
+        assert!(policy.contains(r#"@id("allow-read-notes-doc")"#));
+        assert!(policy.contains(r#"// principal: department, level; in Group::"staff""#));
+        assert!(policy.contains("// context: ip"));
+        assert!(!policy.contains("// resource: "));
+
+        // It parses, and allows exactly the denied request
+        let policy_set = crate::policies::parse(&policy).unwrap();
+        let request = cedar_policy::Request::new(principal, action, resource, cedar_policy::Context::empty(), None).unwrap();
+        let response = cedar_policy::Authorizer::new().is_authorized(&request, &policy_set, &entities);
+        assert_eq!(response.decision(), cedar_policy::Decision::Allow);
+    }
+}
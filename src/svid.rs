@@ -0,0 +1,69 @@
+use crate::acceptors::Acceptors;
+use crate::config::Config;
+use crate::{tls, CedarService};
+use log::{error, info};
+use spiffe::X509Source;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+/// Records the expiry of the SVID currently served.
+fn record_expiry(source: &X509Source, service: &CedarService) {
+    let expiry = source
+        .svid()
+        .map_err(|e| e.to_string())
+        .and_then(|svid| match svid.cert_chain().first() {
+            Some(leaf) => tls::cert_expiry(leaf.as_bytes()),
+            None => Err("SVID has no certificate".to_string()),
+        });
+    match expiry {
+        Ok(not_after) => service.metrics.gauge("tls_cert_expiry_timestamp_seconds", &[], not_after as f64),
+        Err(e) => error!("Failed to read SVID expiry: {}", e),
+    }
+}
+
+/// Serves mTLS with the workload's X.509-SVID from the SPIFFE Workload API. The SVID and trust
+/// bundles are rotated in the background by the source; every client must present an SVID
+/// from an accepted trust domain, and its SPIFFE ID is attached to its requests.
+pub async fn serve(
+    addr: SocketAddr,
+    service: Arc<CedarService>,
+    config: &Config,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let acceptors = Acceptors::bind(addr, config)?;
+    let mut builder = X509Source::builder();
+    if let Some(ref endpoint) = config.spiffe_endpoint {
+        builder = builder.endpoint(endpoint);
+    }
+    let source = builder
+        .build()
+        .await
+        .map_err(|e| format!("Failed to fetch SVID from the SPIFFE Workload API: {}", e))?;
+    if let Ok(svid) = source.svid() {
+        info!("Serving with SPIFFE ID {}", svid.spiffe_id());
+    }
+    record_expiry(&source, &service);
+
+    let server = spiffe_rustls::mtls_server(source.clone()).with_alpn_protocols([b"h2".as_ref(), b"http/1.1".as_ref()]);
+    let server = if config.spiffe_trust_domains.is_empty() {
+        server
+    } else {
+        let domains = spiffe_rustls::trust_domains(config.spiffe_trust_domains.iter().map(String::as_str))
+            .map_err(|e| format!("Invalid CEDAR_SPIFFE_TRUST_DOMAINS: {}", e))?;
+        server.authorize(domains)
+    };
+    let server_config = server
+        .build()
+        .map_err(|e| format!("Failed to configure SPIFFE mTLS: {}", e))?;
+
+    let rotations = Arc::clone(&service);
+    tokio::spawn(async move {
+        let mut updates = source.updated();
+        while updates.changed().await.is_ok() {
+            info!("SPIFFE SVID or trust bundle rotated");
+            rotations.metrics.incr("tls_reloads", &[("result", "success")]);
+            record_expiry(&source, &rotations);
+        }
+    });
+
+    tls::serve_tls(acceptors, service, Arc::new(server_config), None).await
+}
@@ -0,0 +1,290 @@
+use crate::{baggage, quota};
+use hyper::Body;
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Label of requests that name no tenant.
+pub const NONE: &str = "none";
+/// Label of tenants seen after `CEDAR_TENANT_MAX_LABELS` others already have their own.
+pub const OTHER: &str = "other";
+/// Longest tenant label; longer names are cut.
+const MAX_LABEL_LEN: usize = 64;
+
+tokio::task_local! {
+    /// Metric label of the tenant the current request is served for.
+    static TENANT: String;
+}
+
+/// Where a request's tenant comes from.
+#[derive(Debug, PartialEq)]
+enum Source {
+    /// The name of the API key it was made with.
+    ApiKey,
+    /// An entry of its W3C baggage.
+    Baggage(String),
+}
+
+/// Limits on a tenant's requests; `None` is unlimited.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Limits {
+    /// Evaluation requests per second, in bursts of up to a second's worth.
+    pub rate: Option<u32>,
+    pub max_request_bytes: Option<usize>,
+    /// Most entities one evaluation request may send.
+    pub max_entities: Option<usize>,
+}
+
+/// Evaluation requests a tenant may still make, refilled at its rate.
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    /// Takes one request out of the bucket, or says how long until one is available.
+    fn take(&mut self, rate: u32, now: Instant) -> Result<(), Duration> {
+        let rate = f64::from(rate);
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * rate).min(rate.max(1.0));
+        self.updated = now;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - self.tokens) / rate))
+        }
+    }
+}
+
+/// Identifies the tenant of each request for metric labels and limits, giving only the first
+/// `max` tenants seen their own label so that callers cannot grow the number of series (or
+/// rate limit buckets) unbounded. Tenants with limits of their own always keep their label.
+pub struct Tenants {
+    source: Source,
+    max: usize,
+    labelled: Mutex<HashSet<String>>,
+    default: Limits,
+    limits: HashMap<String, Limits>,
+    buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+/// Parses one limit of a `rate/bytes/entities` override: empty keeps the default, `0` is
+/// unlimited.
+fn limit<T: std::str::FromStr + PartialEq + Default>(value: &str, default: Option<T>) -> Result<Option<T>, String>
+where
+    T::Err: std::fmt::Display,
+{
+    match value.trim() {
+        "" => Ok(default),
+        value => match value.parse::<T>() {
+            Ok(limit) if limit == T::default() => Ok(None),
+            Ok(limit) => Ok(Some(limit)),
+            Err(e) => Err(format!("Invalid tenant limit '{}': {}", value, e)),
+        },
+    }
+}
+
+impl Tenants {
+    /// Parses `CEDAR_TENANT_FROM` (`api-key` or `baggage:<entry>`) and the
+    /// `tenant=rate/bytes/entities` overrides of the default limits.
+    pub fn new(from: &str, max: usize, default: Limits, overrides: &[String]) -> Result<Self, String> {
+        let source = match from.trim() {
+            "api-key" => Source::ApiKey,
+            from => match from.strip_prefix("baggage:").map(str::trim) {
+                Some(entry) if !entry.is_empty() => Source::Baggage(entry.to_string()),
+                _ => return Err(format!("Invalid CEDAR_TENANT_FROM '{}' (expected api-key or baggage:<entry>)", from)),
+            },
+        };
+        let mut limits = HashMap::new();
+        for entry in overrides {
+            let (name, values) = entry
+                .split_once('=')
+                .map(|(name, values)| (name.trim(), values))
+                .filter(|(name, _)| !name.is_empty())
+                .ok_or_else(|| format!("Invalid CEDAR_TENANT_LIMITS entry '{}' (expected tenant=rate/bytes/entities)", entry))?;
+            if sanitize(name) != name || name.len() > MAX_LABEL_LEN {
+                return Err(format!("Invalid tenant '{}' in CEDAR_TENANT_LIMITS (use letters, digits, '-', '_' and '.')", name));
+            }
+            let mut values = values.split('/');
+            let tenant = Limits {
+                rate: limit(values.next().unwrap_or_default(), default.rate)?,
+                max_request_bytes: limit(values.next().unwrap_or_default(), default.max_request_bytes)?,
+                max_entities: limit(values.next().unwrap_or_default(), default.max_entities)?,
+            };
+            if values.next().is_some() {
+                return Err(format!("Invalid CEDAR_TENANT_LIMITS entry '{}' (expected tenant=rate/bytes/entities)", entry));
+            }
+            limits.insert(name.to_string(), tenant);
+        }
+        Ok(Self {
+            source,
+            max,
+            labelled: Mutex::new(HashSet::new()),
+            default,
+            limits,
+            buckets: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// The limits of a tenant, by its label.
+    pub fn limits(&self, tenant: &str) -> Limits {
+        self.limits.get(tenant).copied().unwrap_or(self.default)
+    }
+
+    /// Admits an evaluation request of a tenant under its rate limit, or says how long until
+    /// it would be admitted.
+    pub fn admit(&self, tenant: &str, now: Instant) -> Result<(), Duration> {
+        let Some(rate) = self.limits(tenant).rate else {
+            return Ok(());
+        };
+        let mut buckets = self.buckets.lock().unwrap();
+        let bucket = buckets.entry(tenant.to_string()).or_insert_with(|| Bucket {
+            tokens: f64::from(rate.max(1)),
+            updated: now,
+        });
+        bucket.take(rate, now)
+    }
+
+    /// The metric label of the tenant a request is made for.
+    pub fn identify(&self, req: &hyper::Request<Body>) -> String {
+        let tenant = match self.source {
+            Source::ApiKey => req.extensions().get::<quota::Caller>().map(|caller| caller.0.clone()),
+            Source::Baggage(ref entry) => baggage::header(req)
+                .and_then(|header| baggage::parse(&header, std::slice::from_ref(entry)).remove(entry))
+                .and_then(|value| value.as_str().map(str::to_string)),
+        };
+        self.label(tenant.as_deref())
+    }
+
+    /// `tenant` as a label: sanitized, or `none` or `other`.
+    fn label(&self, tenant: Option<&str>) -> String {
+        let label = match tenant.map(str::trim).filter(|tenant| !tenant.is_empty()) {
+            Some(tenant) => sanitize(tenant),
+            None => return NONE.to_string(),
+        };
+        let mut labelled = self.labelled.lock().unwrap();
+        if labelled.contains(&label) || self.limits.contains_key(&label) {
+            label
+        } else if labelled.len() < self.max {
+            labelled.insert(label.clone());
+            label
+        } else {
+            OTHER.to_string()
+        }
+    }
+}
+
+/// The first characters of a tenant that make a label, with any but letters, digits, `-`, `_`
+/// and `.` replaced by `_`.
+fn sanitize(tenant: &str) -> String {
+    tenant
+        .chars()
+        .take(MAX_LABEL_LEN)
+        .map(|c| if c.is_ascii_alphanumeric() || "-_.".contains(c) { c } else { '_' })
+        .collect()
+}
+
+/// Runs `future` with `tenant` as the tenant of the request being served.
+pub async fn scope<F: std::future::Future>(tenant: String, future: F) -> F::Output {
+    TENANT.scope(tenant, future).await
+}
+
+/// The tenant label of the request being served; `None` outside of one (e.g. over gRPC or
+/// when replaying decisions).
+pub fn current() -> Option<String> {
+    TENANT.try_with(String::clone).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_the_source() {
+        assert_eq!(Tenants::new("api-key", 10, Limits::default(), &[]).unwrap().source, Source::ApiKey);
+        assert_eq!(
+            Tenants::new("baggage:tenant_id", 10, Limits::default(), &[]).unwrap().source,
+            Source::Baggage("tenant_id".to_string())
+        );
+        assert!(Tenants::new("baggage:", 10, Limits::default(), &[]).is_err());
+        assert!(Tenants::new("header", 10, Limits::default(), &[]).is_err());
+    }
+
+    #[test]
+    fn caps_the_number_of_labels() {
+        let tenants = Tenants::new("api-key", 2, Limits::default(), &[]).unwrap();
+        assert_eq!(tenants.label(Some("acme")), "acme");
+        assert_eq!(tenants.label(Some("globex corp\n")), "globex_corp");
+        assert_eq!(tenants.label(Some("initech")), OTHER);
+        assert_eq!(tenants.label(Some("acme")), "acme");
+        assert_eq!(tenants.label(Some(" ")), NONE);
+        assert_eq!(tenants.label(None), NONE);
+        assert_eq!(tenants.label(Some(&"x".repeat(100))), OTHER);
+    }
+
+    #[test]
+    fn identifies_the_tenant_from_baggage() {
+        let tenants = Tenants::new("baggage:tenant_id", 10, Limits::default(), &[]).unwrap();
+        let req = hyper::Request::builder()
+            .header("baggage", "region=eu")
+            .header("baggage", "tenant_id=acme%20corp")
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(tenants.identify(&req), "acme_corp");
+        assert_eq!(tenants.identify(&hyper::Request::new(Body::empty())), NONE);
+    }
+
+    #[tokio::test]
+    async fn scopes_the_current_tenant() {
+        assert_eq!(scope("acme".to_string(), async { current() }).await.as_deref(), Some("acme"));
+        assert_eq!(current(), None);
+    }
+
+    fn limited() -> Tenants {
+        let default = Limits {
+            rate: Some(2),
+            max_request_bytes: Some(1000),
+            max_entities: None,
+        };
+        let overrides = ["acme=10//500".to_string(), "globex=0/0/".to_string()];
+        Tenants::new("api-key", 0, default, &overrides).unwrap()
+    }
+
+    #[test]
+    fn overrides_default_limits() {
+        let tenants = limited();
+        let limits = |rate, max_request_bytes, max_entities| Limits {
+            rate,
+            max_request_bytes,
+            max_entities,
+        };
+        assert_eq!(tenants.limits("acme"), limits(Some(10), Some(1000), Some(500)));
+        assert_eq!(tenants.limits("globex"), limits(None, None, None));
+        assert_eq!(tenants.limits("initech"), limits(Some(2), Some(1000), None));
+        for overrides in [&["acme"][..], &["=1"], &["acme=x"], &["acme=1/2/3/4"], &["ac me=1"]] {
+            let overrides: Vec<String> = overrides.iter().map(|o| o.to_string()).collect();
+            assert!(Tenants::new("api-key", 0, Limits::default(), &overrides).is_err());
+        }
+    }
+
+    #[test]
+    fn tenants_with_limits_keep_their_label() {
+        let tenants = limited();
+        assert_eq!(tenants.label(Some("acme")), "acme");
+        assert_eq!(tenants.label(Some("initech")), OTHER);
+    }
+
+    #[test]
+    fn rate_limits_each_tenant_separately() {
+        let tenants = limited();
+        let now = Instant::now();
+        assert!(tenants.admit("other", now).is_ok());
+        assert!(tenants.admit("other", now).is_ok());
+        assert_eq!(tenants.admit("other", now), Err(Duration::from_millis(500)));
+        assert!(tenants.admit("other", now + Duration::from_millis(500)).is_ok());
+        assert!(tenants.admit("none", now).is_ok());
+        assert!((0..100).all(|_| tenants.admit("globex", now).is_ok()));
+        assert_eq!((0..20).filter(|_| tenants.admit("acme", now).is_ok()).count(), 10);
+    }
+}
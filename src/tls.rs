@@ -0,0 +1,311 @@
+use crate::acceptors::Acceptors;
+use crate::upgrade;
+use crate::{serve, CedarService};
+use hyper::server::conn::Http;
+use hyper::service::service_fn;
+use log::{debug, error, info};
+use std::fs::{self, File};
+use std::io::BufReader;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, SystemTime};
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpListener;
+use tokio::task::JoinSet;
+use tokio_rustls::rustls::crypto::ring;
+use tokio_rustls::rustls::server::{Acceptor, ClientHello, ResolvesServerCert};
+use tokio_rustls::rustls::sign::CertifiedKey;
+use tokio_rustls::rustls::ServerConfig;
+use tokio_rustls::LazyConfigAcceptor;
+use x509_parser::extensions::GeneralName;
+
+/// Serves the certificate currently on disk. Each handshake picks up the latest loaded pair, so
+/// a rotated certificate takes effect for new connections while open ones carry on untouched.
+#[derive(Debug)]
+pub struct CertReloader {
+    cert_path: String,
+    key_path: String,
+    current: RwLock<Arc<CertifiedKey>>,
+    /// Modification times of the cert and key when last loaded.
+    loaded: Mutex<(Option<SystemTime>, Option<SystemTime>)>,
+}
+
+fn modified(path: &str) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// Expiry of a DER certificate, in Unix seconds.
+pub fn cert_expiry(der: &[u8]) -> Result<i64, String> {
+    let (_, parsed) = x509_parser::parse_x509_certificate(der).map_err(|e| e.to_string())?;
+    Ok(parsed.validity().not_after.timestamp())
+}
+
+/// The SPIFFE ID (`spiffe://` URI SAN) of a DER certificate. Certificates with none, or with
+/// more than one, have no SPIFFE identity.
+pub fn spiffe_id(der: &[u8]) -> Option<String> {
+    let (_, parsed) = x509_parser::parse_x509_certificate(der).ok()?;
+    let san = parsed.subject_alternative_name().ok()??;
+    let mut ids = san.value.general_names.iter().filter_map(|name| match name {
+        GeneralName::URI(uri) if uri.starts_with("spiffe://") => Some(uri.to_string()),
+        _ => None,
+    });
+    match (ids.next(), ids.next()) {
+        (Some(id), None) => Some(id),
+        _ => None,
+    }
+}
+
+/// Verified SPIFFE ID of the client on an mTLS connection, attached to each of its requests.
+#[derive(Debug, Clone)]
+pub struct PeerIdentity(pub String);
+
+/// Attached to every request that came over TLS, which admin passwords may be sent on.
+#[derive(Debug, Clone, Copy)]
+pub struct Encrypted;
+
+/// Reads a PEM certificate chain and private key; also returns the leaf's expiry (Unix seconds).
+fn load(cert_path: &str, key_path: &str) -> Result<(CertifiedKey, i64), String> {
+    let cert_file = File::open(cert_path).map_err(|e| format!("Failed to open certificate {}: {}", cert_path, e))?;
+    let certs = rustls_pemfile::certs(&mut BufReader::new(cert_file))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("Failed to parse certificate {}: {}", cert_path, e))?;
+    let leaf = certs
+        .first()
+        .ok_or_else(|| format!("No certificate found in {}", cert_path))?;
+    let not_after = cert_expiry(leaf).map_err(|e| format!("Failed to parse certificate {}: {}", cert_path, e))?;
+
+    let key_file = File::open(key_path).map_err(|e| format!("Failed to open private key {}: {}", key_path, e))?;
+    let key = rustls_pemfile::private_key(&mut BufReader::new(key_file))
+        .map_err(|e| format!("Failed to parse private key {}: {}", key_path, e))?
+        .ok_or_else(|| format!("No private key found in {}", key_path))?;
+    let signing_key = ring::sign::any_supported_type(&key)
+        .map_err(|e| format!("Unsupported private key in {}: {}", key_path, e))?;
+
+    Ok((CertifiedKey::new(certs, signing_key), not_after))
+}
+
+impl CertReloader {
+    pub fn new(cert_path: &str, key_path: &str, service: &CedarService) -> Result<Self, Box<dyn std::error::Error>> {
+        let loaded = (modified(cert_path), modified(key_path));
+        let (key, not_after) = load(cert_path, key_path)?;
+        service.metrics.gauge("tls_cert_expiry_timestamp_seconds", &[], not_after as f64);
+        service.source_refreshed("tls");
+        info!("Loaded TLS certificate from {}", cert_path);
+        Ok(Self {
+            cert_path: cert_path.to_string(),
+            key_path: key_path.to_string(),
+            current: RwLock::new(Arc::new(key)),
+            loaded: Mutex::new(loaded),
+        })
+    }
+
+    /// Reloads the pair if either file changed. A pair that fails to load (for instance caught
+    /// between writing the cert and the key) is skipped and retried on the next check, and the
+    /// previous certificate stays in use meanwhile.
+    fn reload_if_changed(&self, service: &CedarService) {
+        let on_disk = (modified(&self.cert_path), modified(&self.key_path));
+        let mut loaded = self.loaded.lock().unwrap();
+        if *loaded == on_disk {
+            return;
+        }
+
+        match load(&self.cert_path, &self.key_path) {
+            Ok((key, not_after)) => {
+                *self.current.write().unwrap() = Arc::new(key);
+                *loaded = on_disk;
+                service.metrics.gauge("tls_cert_expiry_timestamp_seconds", &[], not_after as f64);
+                service.metrics.incr("tls_reloads", &[("result", "success")]);
+                service.source_refreshed("tls");
+                info!("Reloaded TLS certificate from {}", self.cert_path);
+            }
+            Err(e) => {
+                service.metrics.incr("tls_reloads", &[("result", "error")]);
+                service.source_failed("tls", &e.to_string());
+                error!("Failed to reload TLS certificate, keeping the current one: {}", e);
+            }
+        }
+    }
+
+    /// Checks the files for changes every `interval`.
+    pub async fn watch(self: Arc<Self>, service: Arc<CedarService>, interval: Duration) {
+        let mut ticker = tokio::time::interval(interval);
+        ticker.tick().await;
+        loop {
+            ticker.tick().await;
+            self.reload_if_changed(&service);
+        }
+    }
+}
+
+impl ResolvesServerCert for CertReloader {
+    fn resolve(&self, _hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        Some(Arc::clone(&self.current.read().unwrap()))
+    }
+}
+
+/// ALPN protocol of TLS-ALPN-01 validation connections (RFC 8737).
+const ACME_TLS_ALPN: &[u8] = b"acme-tls/1";
+
+/// Server TLS settings around a certificate source. `acme` additionally advertises the
+/// `acme-tls/1` protocol used by TLS-ALPN-01 validation.
+pub fn server_config(certs: Arc<dyn ResolvesServerCert>, acme: bool) -> Result<Arc<ServerConfig>, String> {
+    let mut config = ServerConfig::builder_with_provider(Arc::new(ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .map_err(|e| format!("Failed to configure TLS: {}", e))?
+        .with_no_client_auth()
+        .with_cert_resolver(certs);
+    // h2 first, for gRPC clients (the health service); HTTP/1.1 clients still get HTTP/1.1
+    config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    if acme {
+        config.alpn_protocols.push(ACME_TLS_ALPN.to_vec());
+    }
+    Ok(Arc::new(config))
+}
+
+/// Accepts TLS connections on the agent's sockets and serves them with the same handler as plain
+/// HTTP. With a `challenge` config, ACME TLS-ALPN-01 validation handshakes are answered with it
+/// and closed.
+pub async fn serve_tls(
+    acceptors: Acceptors,
+    service: Arc<CedarService>,
+    config: Arc<ServerConfig>,
+    challenge: Option<Arc<ServerConfig>>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    info!("Cedar Local Agent listening on {} (TLS)", acceptors.addr());
+    let serving = acceptors.run({
+        let service = Arc::clone(&service);
+        move |listener| accept_tls(listener, Arc::clone(&service), Arc::clone(&config), challenge.clone())
+    });
+    upgrade::drain(serving, &service).await
+}
+
+async fn accept_tls(
+    listener: TcpListener,
+    service: Arc<CedarService>,
+    config: Arc<ServerConfig>,
+    challenge: Option<Arc<ServerConfig>>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    service.stats.register_acceptor();
+    // The connections accepted here, which run on this acceptor's runtime until they close
+    let mut connections = JoinSet::new();
+    loop {
+        let accepted = tokio::select! {
+            accepted = listener.accept() => accepted,
+            Some(_) = connections.join_next(), if !connections.is_empty() => continue,
+            () = service.stopped() => break,
+        };
+        let (tcp, client) = match accepted {
+            Ok(conn) => conn,
+            Err(e) => {
+                error!("Failed to accept connection: {}", e);
+                continue;
+            }
+        };
+        let config = Arc::clone(&config);
+        let challenge = challenge.clone();
+        let service = Arc::clone(&service);
+        connections.spawn(async move {
+            let _connection = service.stats.connection();
+            let handshake = match LazyConfigAcceptor::new(Acceptor::default(), tcp).await {
+                Ok(handshake) => handshake,
+                Err(e) => {
+                    debug!("TLS handshake with {} failed: {}", client, e);
+                    return;
+                }
+            };
+
+            let is_challenge = handshake
+                .client_hello()
+                .alpn()
+                .is_some_and(|mut protocols| protocols.any(|p| p == ACME_TLS_ALPN));
+            if let (true, Some(challenge)) = (is_challenge, challenge) {
+                info!("Answering ACME TLS-ALPN-01 validation from {}", client);
+                if let Ok(mut stream) = handshake.into_stream(challenge).await {
+                    let _ = stream.shutdown().await;
+                }
+                return;
+            }
+
+            let stream = match handshake.into_stream(config).await {
+                Ok(stream) => stream,
+                Err(e) => {
+                    debug!("TLS handshake with {} failed: {}", client, e);
+                    return;
+                }
+            };
+            // Only present when the config verified a client certificate
+            let peer = stream
+                .get_ref()
+                .1
+                .peer_certificates()
+                .and_then(|certs| certs.first())
+                .and_then(|leaf| spiffe_id(leaf))
+                .map(PeerIdentity);
+            let stopping = Arc::clone(&service);
+            let handler = service_fn(move |mut req: hyper::Request<hyper::Body>| {
+                req.extensions_mut().insert(Encrypted);
+                if let Some(ref peer) = peer {
+                    req.extensions_mut().insert(peer.clone());
+                }
+                serve(req, Arc::clone(&service), client)
+            });
+            let connection = Http::new().serve_connection(stream, handler);
+            tokio::pin!(connection);
+            let result = tokio::select! {
+                result = &mut connection => result,
+                () = stopping.stopped() => {
+                    // Finishes the request in progress, then closes
+                    connection.as_mut().graceful_shutdown();
+                    connection.await
+                }
+            };
+            if let Err(e) = result {
+                debug!("Connection from {} ended with error: {}", client, e);
+            }
+        });
+    }
+    drop(listener);
+    while connections.join_next().await.is_some() {}
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rcgen::{CertificateParams, KeyPair, SanType};
+
+    fn cert_with(sans: Vec<SanType>) -> Vec<u8> {
+        let mut params = CertificateParams::default();
+        params.subject_alt_names = sans;
+        params.self_signed(&KeyPair::generate().unwrap()).unwrap().der().to_vec()
+    }
+
+    fn uri(value: &str) -> SanType {
+        SanType::URI(value.try_into().unwrap())
+    }
+
+    #[test]
+    fn spiffe_id_reads_the_uri_san() {
+        let der = cert_with(vec![
+            SanType::DnsName("payments.internal".try_into().unwrap()),
+            uri("spiffe://example.org/payments"),
+        ]);
+        assert_eq!(spiffe_id(&der).as_deref(), Some("spiffe://example.org/payments"));
+    }
+
+    #[test]
+    fn spiffe_id_ignores_other_uris_and_ambiguous_certs() {
+        assert_eq!(spiffe_id(&cert_with(vec![uri("https://example.org/")])), None);
+        assert_eq!(spiffe_id(&cert_with(Vec::new())), None);
+        let two = cert_with(vec![uri("spiffe://example.org/a"), uri("spiffe://example.org/b")]);
+        assert_eq!(spiffe_id(&two), None);
+    }
+
+    #[test]
+    fn cert_expiry_reads_not_after() {
+        let mut params = CertificateParams::default();
+        params.not_after = rcgen::date_time_ymd(2030, 1, 1);
+        let der = params.self_signed(&KeyPair::generate().unwrap()).unwrap().der().to_vec();
+        assert_eq!(cert_expiry(&der), Ok(1_893_456_000));
+        assert!(cert_expiry(b"not a certificate").is_err());
+    }
+}
@@ -0,0 +1,324 @@
+use crate::config::Config;
+use crate::fetch::{self, HttpClient};
+use cedar_policy::{EntityId, EntityTypeName, EntityUid};
+use hyper::{Body, Request};
+use jsonwebtoken::jwk::JwkSet;
+use jsonwebtoken::{DecodingKey, Validation};
+use log::info;
+use serde_json::{Map, Value};
+use std::sync::RwLock;
+use std::time::{Duration, Instant};
+
+/// Least time between fetches of a JWKS URL, however many tokens name a key it lacks.
+const MIN_REFETCH_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Longest a JWKS fetch may take.
+const FETCH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A JWKS URL, fetched again when a token names a key it lacked.
+struct Remote {
+    url: String,
+    client: HttpClient,
+}
+
+/// The principal a verified token stands for.
+#[derive(Debug)]
+pub struct Identity {
+    pub principal: EntityUid,
+    /// The principal's entity, with the configured claims as attributes and groups as parents.
+    pub entity: Value,
+    /// Claims for the configured context attribute, if any.
+    pub context: Option<(String, Value)>,
+}
+
+/// Verifies bearer tokens (JWTs) against a JWKS and maps their claims to a principal, in the way
+/// Verified Permissions' `IsAuthorizedWithToken` does.
+pub struct Verifier {
+    /// Unset when the keys come from a file.
+    remote: Option<Remote>,
+    keys: RwLock<JwkSet>,
+    /// When the JWKS URL was last fetched; locked while it is being fetched.
+    fetched: tokio::sync::Mutex<Option<Instant>>,
+    issuer: Option<String>,
+    audience: Option<String>,
+    principal_type: EntityTypeName,
+    principal_claim: String,
+    groups_claim: Option<String>,
+    group_type: EntityTypeName,
+    /// Claims copied onto the principal as attributes.
+    claims: Vec<String>,
+    context_attribute: Option<String>,
+}
+
+impl Verifier {
+    /// Reads a JWKS file now; a JWKS URL is fetched when the first token is verified.
+    pub fn new(config: &Config, jwks: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let (remote, keys) = if jwks.starts_with("https://") || jwks.starts_with("http://") {
+            let client = fetch::https_client().map_err(|e| format!("{} for the token JWKS", e))?;
+            let remote = Remote {
+                url: jwks.to_string(),
+                client,
+            };
+            (Some(remote), JwkSet { keys: Vec::new() })
+        } else {
+            let json = std::fs::read_to_string(jwks).map_err(|e| format!("Failed to read JWKS {}: {}", jwks, e))?;
+            let keys = serde_json::from_str(&json).map_err(|e| format!("Failed to parse JWKS {}: {}", jwks, e))?;
+            (None, keys)
+        };
+        Ok(Self {
+            remote,
+            keys: RwLock::new(keys),
+            fetched: tokio::sync::Mutex::new(None),
+            issuer: config.token_issuer.clone(),
+            audience: config.token_audience.clone(),
+            principal_type: config
+                .token_principal_type
+                .parse()
+                .map_err(|e| format!("Invalid CEDAR_TOKEN_PRINCIPAL_TYPE: {}", e))?,
+            principal_claim: config.token_principal_claim.clone(),
+            groups_claim: config.token_groups_claim.clone(),
+            group_type: config
+                .token_group_type
+                .parse()
+                .map_err(|e| format!("Invalid CEDAR_TOKEN_GROUP_TYPE: {}", e))?,
+            claims: config.token_claims.clone(),
+            context_attribute: config.token_context_attribute.clone(),
+        })
+    }
+
+    /// Checks the token's signature, expiry, issuer and audience, and maps its claims.
+    pub async fn verify(&self, token: &str) -> Result<Identity, String> {
+        let header = jsonwebtoken::decode_header(token).map_err(|e| format!("Invalid token: {}", e))?;
+        let key = match self.key(header.kid.as_deref(), header.alg)? {
+            Some(key) => key,
+            None => {
+                self.refetch().await?;
+                self.key(header.kid.as_deref(), header.alg)?
+                    .ok_or_else(|| format!("No key {} in the JWKS", header.kid.as_deref().unwrap_or("(no kid)")))?
+            }
+        };
+
+        let mut validation = Validation::new(header.alg);
+        validation.set_required_spec_claims(&["exp"]);
+        if let Some(ref issuer) = self.issuer {
+            validation.set_issuer(&[issuer]);
+        }
+        match self.audience {
+            Some(ref audience) => validation.set_audience(&[audience]),
+            None => validation.validate_aud = false,
+        }
+        let data = jsonwebtoken::decode::<Map<String, Value>>(token, &key, &validation)
+            .map_err(|e| format!("Invalid token: {}", e))?;
+        self.identity(&data.claims)
+    }
+
+    /// The key a token signed with `alg` names, or the only one without a `kid`. `None` when the
+    /// JWKS lacks it, which may mean the issuer rotated keys since it was fetched.
+    fn key(&self, kid: Option<&str>, alg: jsonwebtoken::Algorithm) -> Result<Option<DecodingKey>, String> {
+        let keys = self.keys.read().unwrap();
+        let jwk = match kid {
+            Some(kid) => keys.find(kid),
+            None if keys.keys.len() == 1 => keys.keys.first(),
+            None if keys.keys.is_empty() => None,
+            None => return Err("Token names no key (kid) and the JWKS has several".to_string()),
+        };
+        let Some(jwk) = jwk else {
+            return Ok(None);
+        };
+        // A key meant for one algorithm must not verify tokens claiming another
+        if let Some(key_alg) = jwk.common.key_algorithm {
+            if format!("{:?}", key_alg) != format!("{:?}", alg) {
+                return Err(format!("Token algorithm {:?} does not match its key's ({:?})", alg, key_alg));
+            }
+        }
+        DecodingKey::from_jwk(jwk).map(Some).map_err(|e| format!("Unusable key in the JWKS: {}", e))
+    }
+
+    /// Fetches the JWKS URL again, unless it was fetched within `MIN_REFETCH_INTERVAL`.
+    async fn refetch(&self) -> Result<(), String> {
+        let Some(Remote { ref url, ref client }) = self.remote else {
+            return Ok(());
+        };
+        let mut fetched = self.fetched.lock().await;
+        if fetched.is_some_and(|at| at.elapsed() < MIN_REFETCH_INTERVAL) {
+            return Ok(());
+        }
+        *fetched = Some(Instant::now());
+        let request = Request::get(url)
+            .header("accept", "application/json")
+            .body(Body::empty())
+            .map_err(|e| format!("Invalid JWKS URL: {}", e))?;
+        let json = tokio::time::timeout(FETCH_TIMEOUT, fetch::send(client, request))
+            .await
+            .map_err(|_| "JWKS fetch timed out".to_string())?
+            .map_err(|e| format!("Failed to fetch JWKS: {}", e))?
+            .ok_or_else(|| format!("JWKS not found at {}", url))?;
+        let keys: JwkSet = serde_json::from_value(json).map_err(|e| format!("Failed to parse JWKS: {}", e))?;
+        info!("Fetched {} token signing keys from {}", keys.keys.len(), url);
+        *self.keys.write().unwrap() = keys;
+        Ok(())
+    }
+
+    /// Maps verified claims to the principal's UID and entity.
+    fn identity(&self, claims: &Map<String, Value>) -> Result<Identity, String> {
+        let id = claims
+            .get(&self.principal_claim)
+            .and_then(claim_id)
+            .ok_or_else(|| format!("Token has no {} claim", self.principal_claim))?;
+        let principal = EntityUid::from_type_name_and_id(self.principal_type.clone(), EntityId::new(&id));
+
+        let attrs: Map<String, Value> = self
+            .claims
+            .iter()
+            .filter_map(|name| Some((name.clone(), attribute(claims.get(name)?)?)))
+            .collect();
+        let groups = match self.groups_claim.as_ref().and_then(|claim| claims.get(claim)) {
+            Some(Value::Array(groups)) => groups.iter().filter_map(claim_id).collect(),
+            Some(group) => claim_id(group).into_iter().collect(),
+            None => Vec::new(),
+        };
+        let parents: Vec<Value> = groups
+            .into_iter()
+            .map(|id| serde_json::json!({"type": self.group_type.to_string(), "id": id}))
+            .collect();
+        let entity = serde_json::json!({
+            "uid": {"type": self.principal_type.to_string(), "id": id},
+            "attrs": attrs,
+            "parents": parents,
+        });
+
+        let context = self.context_attribute.as_ref().map(|name| {
+            let record: Map<String, Value> = claims
+                .iter()
+                .filter_map(|(claim, value)| Some((claim.clone(), attribute(value)?)))
+                .collect();
+            (name.clone(), Value::Object(record))
+        });
+        Ok(Identity {
+            principal,
+            entity,
+            context,
+        })
+    }
+}
+
+/// An ID given as a string or an integer.
+fn claim_id(value: &Value) -> Option<String> {
+    match value {
+        Value::String(id) => Some(id.clone()),
+        Value::Number(id) if id.is_i64() => Some(id.to_string()),
+        _ => None,
+    }
+}
+
+/// A claim as a Cedar value: strings, booleans, integers and sets of them. Other claims (floats,
+/// nested objects, `null`) have no Cedar equivalent and are left out.
+fn attribute(value: &Value) -> Option<Value> {
+    match value {
+        Value::String(_) | Value::Bool(_) => Some(value.clone()),
+        Value::Number(n) if n.is_i64() => Some(value.clone()),
+        Value::Array(items) => items.iter().map(attribute).collect::<Option<Vec<_>>>().map(Value::Array),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jsonwebtoken::{EncodingKey, Header};
+
+    const SECRET: &[u8] = b"token-test-secret";
+
+    fn verifier(keys: Value) -> Verifier {
+        Verifier {
+            remote: None,
+            keys: RwLock::new(serde_json::from_value(keys).unwrap()),
+            fetched: tokio::sync::Mutex::new(None),
+            issuer: Some("https://issuer.example".to_string()),
+            audience: Some("cedar-agent".to_string()),
+            principal_type: "User".parse().unwrap(),
+            principal_claim: "sub".to_string(),
+            groups_claim: Some("groups".to_string()),
+            group_type: "Group".parse().unwrap(),
+            claims: vec!["email".to_string(), "level".to_string(), "score".to_string()],
+            context_attribute: Some("token".to_string()),
+        }
+    }
+
+    fn jwks() -> Value {
+        // base64url of SECRET
+        serde_json::json!({"keys": [{"kty": "oct", "kid": "k1", "alg": "HS256", "k": "dG9rZW4tdGVzdC1zZWNyZXQ"}]})
+    }
+
+    fn sign(claims: Value, kid: Option<&str>) -> String {
+        let mut header = Header::new(jsonwebtoken::Algorithm::HS256);
+        header.kid = kid.map(str::to_string);
+        jsonwebtoken::encode(&header, &claims, &EncodingKey::from_secret(SECRET)).unwrap()
+    }
+
+    fn claims(overrides: Value) -> Value {
+        let mut claims = serde_json::json!({
+            "sub": "alice",
+            "iss": "https://issuer.example",
+            "aud": "cedar-agent",
+            "exp": 4102444800u64,
+            "email": "alice@example.com",
+            "level": 3,
+            "score": 0.5,
+            "groups": ["admins", "staff"],
+        });
+        claims.as_object_mut().unwrap().extend(overrides.as_object().unwrap().clone());
+        claims
+    }
+
+    #[tokio::test]
+    async fn maps_a_verified_token_to_its_principal() {
+        let verifier = verifier(jwks());
+        let identity = verifier.verify(&sign(claims(serde_json::json!({})), Some("k1"))).await.unwrap();
+        assert_eq!(identity.principal.to_string(), r#"User::"alice""#);
+        assert_eq!(
+            identity.entity,
+            serde_json::json!({
+                "uid": {"type": "User", "id": "alice"},
+                "attrs": {"email": "alice@example.com", "level": 3},
+                "parents": [{"type": "Group", "id": "admins"}, {"type": "Group", "id": "staff"}],
+            })
+        );
+        let (name, record) = identity.context.unwrap();
+        assert_eq!(name, "token");
+        assert_eq!(record["groups"], serde_json::json!(["admins", "staff"]));
+        assert!(record.get("score").is_none());
+
+        // The only key serves tokens without a kid
+        assert!(verifier.verify(&sign(claims(serde_json::json!({})), None)).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn rejects_tokens_that_fail_validation() {
+        let verifier = &verifier(jwks());
+        let error = |overrides| {
+            let token = sign(claims(overrides), Some("k1"));
+            async move { verifier.verify(&token).await.unwrap_err() }
+        };
+        assert!(error(serde_json::json!({"exp": 1})).await.contains("ExpiredSignature"));
+        assert!(error(serde_json::json!({"iss": "https://other.example"})).await.contains("InvalidIssuer"));
+        assert!(error(serde_json::json!({"aud": "other"})).await.contains("InvalidAudience"));
+        assert_eq!(error(serde_json::json!({"sub": null})).await, "Token has no sub claim");
+
+        let unknown = verifier.verify(&sign(claims(serde_json::json!({})), Some("k2"))).await.unwrap_err();
+        assert_eq!(unknown, "No key k2 in the JWKS");
+        let forged = jsonwebtoken::encode(
+            &Header::new(jsonwebtoken::Algorithm::HS256),
+            &claims(serde_json::json!({})),
+            &EncodingKey::from_secret(b"guessed"),
+        )
+        .unwrap();
+        assert!(verifier.verify(&forged).await.unwrap_err().contains("InvalidSignature"));
+
+        // A key pinned to HS256 does not verify an HS384 token
+        let mut header = Header::new(jsonwebtoken::Algorithm::HS384);
+        header.kid = Some("k1".to_string());
+        let token = jsonwebtoken::encode(&header, &claims(serde_json::json!({})), &EncodingKey::from_secret(SECRET)).unwrap();
+        assert!(verifier.verify(&token).await.unwrap_err().contains("does not match"));
+    }
+}
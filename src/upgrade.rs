@@ -0,0 +1,341 @@
+use crate::CedarService;
+use log::{info, warn};
+use std::fs::File;
+use std::future::Future;
+use std::path::Path;
+use std::time::Duration;
+
+/// The listening sockets a new process inherits, as comma-separated descriptors.
+#[cfg(unix)]
+const FDS_VAR: &str = "CEDAR_UPGRADE_FDS";
+/// The socket answering ACME HTTP-01 challenges a new process inherits.
+#[cfg(unix)]
+const HTTP01_VAR: &str = "CEDAR_UPGRADE_HTTP01_FD";
+/// A pipe the new process writes to once it serves on the inherited sockets.
+#[cfg(unix)]
+const READY_VAR: &str = "CEDAR_UPGRADE_READY_FD";
+
+/// How often a process started by an upgrade checks whether the one it replaces has exited.
+#[cfg(unix)]
+const EXIT_POLL: Duration = Duration::from_millis(50);
+
+/// The descriptors of the sockets the agent listens on, to hand over on upgrade.
+#[cfg(unix)]
+static LISTENERS: std::sync::OnceLock<Vec<std::os::unix::io::RawFd>> = std::sync::OnceLock::new();
+/// The descriptor of the socket answering ACME HTTP-01 challenges, to hand over on upgrade.
+#[cfg(unix)]
+static HTTP01: std::sync::OnceLock<std::os::unix::io::RawFd> = std::sync::OnceLock::new();
+
+/// Parses the descriptors of `CEDAR_UPGRADE_FDS`; standard input, output and error are not
+/// sockets to listen on.
+#[cfg(unix)]
+fn parse_fds(value: &str) -> Result<Vec<i32>, String> {
+    value
+        .split(',')
+        .map(|fd| match fd.trim().parse::<i32>() {
+            Ok(fd) if fd > 2 => Ok(fd),
+            _ => Err(format!("'{}' is not a socket descriptor", fd.trim())),
+        })
+        .collect()
+}
+
+/// Whether `fd` is an open descriptor, so it can be owned without closing someone else's.
+#[cfg(unix)]
+fn is_open(fd: i32) -> bool {
+    // SAFETY: F_GETFD only reads the descriptor's flags
+    unsafe { libc::fcntl(fd, libc::F_GETFD) >= 0 }
+}
+
+/// Sets or clears `FD_CLOEXEC` on `fd`.
+#[cfg(unix)]
+fn set_cloexec(fd: i32, cloexec: bool) -> std::io::Result<()> {
+    // SAFETY: reads and writes the descriptor's flags only; async-signal-safe, for `pre_exec`
+    unsafe {
+        let flags = libc::fcntl(fd, libc::F_GETFD);
+        let flags = if cloexec { flags | libc::FD_CLOEXEC } else { flags & !libc::FD_CLOEXEC };
+        if flags < 0 || libc::fcntl(fd, libc::F_SETFD, flags) < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+/// The listening sockets handed over by the process this one replaces, if it was started to
+/// take over from one.
+#[cfg(unix)]
+pub fn inherited() -> Result<Option<Vec<std::net::TcpListener>>, String> {
+    let Some(value) = std::env::var(FDS_VAR).ok().filter(|value| !value.is_empty()) else {
+        return Ok(None);
+    };
+    let fds = parse_fds(&value).map_err(|e| format!("Invalid {}: {}", FDS_VAR, e))?;
+    fds.into_iter().map(adopt).collect::<Result<_, _>>().map(Some)
+}
+
+#[cfg(not(unix))]
+pub fn inherited() -> Result<Option<Vec<std::net::TcpListener>>, String> {
+    Ok(None)
+}
+
+/// The socket answering ACME HTTP-01 challenges handed over by the process this one replaces,
+/// if it had one.
+#[cfg(all(unix, feature = "acme"))]
+pub fn inherited_http01() -> Result<Option<std::net::TcpListener>, String> {
+    let Some(value) = std::env::var(HTTP01_VAR).ok().filter(|value| !value.is_empty()) else {
+        return Ok(None);
+    };
+    match parse_fds(&value).map_err(|e| format!("Invalid {}: {}", HTTP01_VAR, e))?[..] {
+        [fd] => adopt(fd).map(Some),
+        _ => Err(format!("Invalid {}: expected one descriptor", HTTP01_VAR)),
+    }
+}
+
+#[cfg(all(not(unix), feature = "acme"))]
+pub fn inherited_http01() -> Result<Option<std::net::TcpListener>, String> {
+    Ok(None)
+}
+
+/// Owns an inherited listening socket.
+#[cfg(unix)]
+fn adopt(fd: i32) -> Result<std::net::TcpListener, String> {
+    use std::os::unix::io::FromRawFd;
+
+    if !is_open(fd) {
+        return Err(format!("Inherited descriptor {} is not open", fd));
+    }
+    // SAFETY: the descriptor is open, and was left open for this process to own
+    let listener = unsafe { std::net::TcpListener::from_raw_fd(fd) };
+    listener
+        .local_addr()
+        .map_err(|e| format!("Inherited descriptor {} is not a listening socket: {}", fd, e))?;
+    // Not passed on to anything this process starts, unless it upgrades in turn
+    set_cloexec(fd, true).map_err(|e| format!("Failed to adopt descriptor {}: {}", fd, e))?;
+    Ok(listener)
+}
+
+/// Remembers the sockets the agent listens on, for an upgrade to hand over.
+pub fn register(listeners: &[std::net::TcpListener]) {
+    #[cfg(unix)]
+    {
+        use std::os::unix::io::AsRawFd;
+        let _ = LISTENERS.set(listeners.iter().map(AsRawFd::as_raw_fd).collect());
+    }
+    #[cfg(not(unix))]
+    let _ = listeners;
+}
+
+/// Remembers the socket answering ACME HTTP-01 challenges, for an upgrade to hand over.
+#[cfg(feature = "acme")]
+pub fn register_http01(listener: &std::net::TcpListener) {
+    #[cfg(unix)]
+    {
+        use std::os::unix::io::AsRawFd;
+        let _ = HTTP01.set(listener.as_raw_fd());
+    }
+    #[cfg(not(unix))]
+    let _ = listener;
+}
+
+/// Takes the exclusive lock of `path`, created if needed, for as long as the returned file is
+/// open, so one process at a time writes the decision log or data directory it guards. A process
+/// started by an upgrade finds the lock held by the one it replaces: it reports ready, so that
+/// one stops accepting, and waits for it to finish its requests and exit before going on.
+pub fn lock(path: &Path) -> Result<File, String> {
+    let file = std::fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(path)
+        .map_err(|e| format!("Failed to open {}: {}", path.display(), e))?;
+    match file.try_lock() {
+        Ok(()) => return Ok(file),
+        Err(std::fs::TryLockError::Error(e)) => return Err(format!("Failed to lock {}: {}", path.display(), e)),
+        Err(std::fs::TryLockError::WouldBlock) => {}
+    }
+    #[cfg(unix)]
+    if std::env::var_os(FDS_VAR).is_some_and(|value| !value.is_empty()) {
+        // The process this one replaces is its parent until it exits
+        let previous = std::os::unix::process::parent_id();
+        signal_ready();
+        info!("Waiting for process {} to release {}", previous, path.display());
+        while std::os::unix::process::parent_id() == previous {
+            std::thread::sleep(EXIT_POLL);
+        }
+        if file.try_lock().is_ok() {
+            return Ok(file);
+        }
+    }
+    Err(format!("{} is locked: another agent uses it", path.display()))
+}
+
+/// Tells the process this one replaces that it serves, so that one stops accepting. Only the
+/// first call does anything.
+pub fn signal_ready() {
+    #[cfg(unix)]
+    {
+        use std::io::Write;
+        use std::os::unix::io::FromRawFd;
+        use std::sync::atomic::{AtomicBool, Ordering};
+
+        static SIGNALLED: AtomicBool = AtomicBool::new(false);
+        let Some(fd) = std::env::var(READY_VAR).ok().and_then(|fd| fd.parse::<i32>().ok()) else {
+            return;
+        };
+        if SIGNALLED.swap(true, Ordering::Relaxed) || fd <= 2 || !is_open(fd) {
+            return;
+        }
+        // SAFETY: the descriptor is open, and was left open for this process to write to
+        let mut pipe = unsafe { std::fs::File::from_raw_fd(fd) };
+        match pipe.write_all(b"1") {
+            Ok(()) => info!("Took over the listening sockets of the previous process"),
+            Err(e) => warn!("Failed to tell the previous process this one is ready: {}", e),
+        }
+    }
+}
+
+/// Starts this executable again, with the same arguments and environment, handing it the
+/// listening sockets. Settings read from Vault are handed over as their references, for the
+/// new process to read with leases of its own. Returns its PID once it serves on them, or once
+/// it waits for this process to exit to take over the decision log or data directory; it is
+/// killed if it is not ready within `timeout`.
+#[cfg(unix)]
+async fn hand_over(timeout: Duration) -> Result<u32, String> {
+    use std::io::Read;
+    use std::os::unix::io::AsRawFd;
+    use std::os::unix::process::CommandExt;
+
+    let fds = LISTENERS.get().ok_or("the agent is not listening yet")?.clone();
+    let exe = std::env::current_exe().map_err(|e| format!("Failed to find the agent's executable: {}", e))?;
+    let (mut ready, writer) = std::io::pipe().map_err(|e| format!("Failed to create a pipe: {}", e))?;
+    let fds_value = fds.iter().map(i32::to_string).collect::<Vec<_>>().join(",");
+    let mut inherit = fds;
+    inherit.push(writer.as_raw_fd());
+    inherit.extend(HTTP01.get());
+
+    let mut command = std::process::Command::new(&exe);
+    command
+        .args(std::env::args_os().skip(1))
+        .envs(crate::vault::references().iter().cloned())
+        .env(FDS_VAR, fds_value)
+        .env(READY_VAR, writer.as_raw_fd().to_string());
+    match HTTP01.get() {
+        Some(fd) => command.env(HTTP01_VAR, fd.to_string()),
+        None => command.env_remove(HTTP01_VAR),
+    };
+    // SAFETY: only fcntl runs between fork and exec, on descriptors open in this process
+    unsafe {
+        command.pre_exec(move || inherit.iter().try_for_each(|fd| set_cloexec(*fd, false)));
+    }
+    let mut child = command.spawn().map_err(|e| format!("Failed to start {}: {}", exe.display(), e))?;
+    // Only the new process holds the pipe open now, so it ends if that process exits
+    drop(writer);
+    let pid = child.id();
+    info!("Started process {} to take over the listening sockets", pid);
+
+    let signalled = tokio::task::spawn_blocking(move || ready.read(&mut [0u8; 1]));
+    match tokio::time::timeout(timeout, signalled).await {
+        Ok(Ok(Ok(1))) => Ok(pid),
+        Ok(Ok(Ok(_))) => {
+            let status = child.wait().map(|status| status.to_string()).unwrap_or_default();
+            Err(format!("process {} exited before it was ready ({})", pid, status))
+        }
+        Ok(Ok(Err(e))) => {
+            let _ = child.kill();
+            let _ = child.wait();
+            Err(format!("failed to hear from process {}: {}", pid, e))
+        }
+        Ok(Err(e)) => Err(format!("failed to wait for process {}: {}", pid, e)),
+        Err(_) => {
+            let _ = child.kill();
+            let _ = child.wait();
+            Err(format!("process {} was not ready within {}s and was stopped", pid, timeout.as_secs()))
+        }
+    }
+}
+
+/// Upgrades on every SIGUSR2 until one succeeds: a new process started from the agent's
+/// executable takes over the listening sockets, and this one stops accepting and drains. A
+/// failed upgrade leaves this process serving as before.
+#[cfg(unix)]
+pub async fn upgrade_on_sigusr2(service: std::sync::Arc<CedarService>, timeout: Duration) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut signals = match signal(SignalKind::user_defined2()) {
+        Ok(signals) => signals,
+        Err(e) => {
+            log::error!("Failed to listen for SIGUSR2: {}", e);
+            return;
+        }
+    };
+    while signals.recv().await.is_some() {
+        if *service.accepting.borrow() != crate::drain::Accepting::Yes {
+            log::error!("Not upgrading on SIGUSR2: the agent no longer accepts connections");
+            continue;
+        }
+        info!("Upgrading on SIGUSR2");
+        match hand_over(timeout).await {
+            Ok(pid) => {
+                info!("Process {} serves on the listening sockets; draining", pid);
+                service.stop_accepting(true);
+                return;
+            }
+            Err(e) => log::error!("Upgrade failed, still serving: {}", e),
+        }
+    }
+}
+
+/// Runs `serving` until it fails, or until the agent stopped accepting to exit and every
+/// connection is closed. Connections still open `CEDAR_DRAIN_TIMEOUT_SECS` after the agent
+/// stopped accepting to exit are dropped. A drained agent stays up until it is stopped.
+pub async fn drain<F>(serving: F, service: &CedarService) -> Result<(), Box<dyn std::error::Error>>
+where
+    F: Future<Output = Result<(), Box<dyn std::error::Error>>>,
+{
+    let timeout = service.drain_timeout;
+    let deadline = async {
+        service.exiting().await;
+        tokio::time::sleep(timeout).await;
+    };
+    let serving = async {
+        serving.await?;
+        if *service.accepting.borrow() != crate::drain::Accepting::Exiting {
+            info!("All connections closed; the agent stays up, not accepting connections, until it is stopped");
+            service.exiting().await;
+        }
+        info!("All connections closed; exiting");
+        Ok(())
+    };
+    tokio::select! {
+        result = serving => result,
+        () = deadline => {
+            warn!("Connections still open {}s after the agent stopped accepting; exiting", timeout.as_secs());
+            Ok(())
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_inherited_descriptors() {
+        assert_eq!(parse_fds("3"), Ok(vec![3]));
+        assert_eq!(parse_fds("5, 6,7"), Ok(vec![5, 6, 7]));
+        assert!(parse_fds("").is_err());
+        assert!(parse_fds("3,x").is_err());
+        assert!(parse_fds("2").is_err());
+        assert!(parse_fds("-1").is_err());
+    }
+
+    #[test]
+    fn toggles_close_on_exec() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let fd = std::os::unix::io::AsRawFd::as_raw_fd(&listener);
+        set_cloexec(fd, false).unwrap();
+        // SAFETY: reads the descriptor's flags only
+        assert_eq!(unsafe { libc::fcntl(fd, libc::F_GETFD) } & libc::FD_CLOEXEC, 0);
+        set_cloexec(fd, true).unwrap();
+        assert_ne!(unsafe { libc::fcntl(fd, libc::F_GETFD) } & libc::FD_CLOEXEC, 0);
+        assert!(is_open(fd));
+    }
+}
@@ -0,0 +1,191 @@
+use crate::config::Config;
+use crate::entities::EntityStore;
+use crate::{policies, schema, schema_lint};
+use cedar_policy::{PolicySet, Schema, ValidationMode, Validator};
+use serde::Serialize;
+
+#[derive(Debug, Serialize, PartialEq)]
+pub struct Finding {
+    /// `config`, `policies`, `schema` or `entities`.
+    pub source: &'static str,
+    /// `error` fails validation; `warning` does not.
+    pub severity: &'static str,
+    /// Setting the finding is about, for `config check`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub key: Option<String>,
+    pub message: String,
+}
+
+impl Finding {
+    fn error(source: &'static str, message: impl Into<String>) -> Self {
+        Self {
+            source,
+            severity: "error",
+            key: None,
+            message: message.into(),
+        }
+    }
+
+    fn warning(source: &'static str, message: impl Into<String>) -> Self {
+        Self {
+            source,
+            severity: "warning",
+            key: None,
+            message: message.into(),
+        }
+    }
+}
+
+/// What `--validate-only` prints.
+#[derive(Debug, Serialize)]
+pub struct Report {
+    pub valid: bool,
+    pub policies: usize,
+    pub templates: usize,
+    pub entities: usize,
+    pub findings: Vec<Finding>,
+}
+
+/// Strict validation of the policies against the schema, as the agent does before activating
+/// either, with warnings reported too.
+pub fn validation_findings(schema: &Schema, policy_set: &PolicySet) -> Vec<Finding> {
+    let result = Validator::new(schema.clone()).validate(policy_set, ValidationMode::Strict);
+    result
+        .validation_errors()
+        .map(|e| Finding::error("policies", e.to_string()))
+        .chain(result.validation_warnings().map(|w| Finding::warning("policies", w.to_string())))
+        .collect()
+}
+
+/// Parts of the schema the policies never use, as warnings.
+fn lint_findings(schema: &Schema, schema_json: &serde_json::Value, policy_set: &PolicySet) -> Vec<Finding> {
+    let Ok(actions) = schema.action_entities() else {
+        return Vec::new();
+    };
+    schema_lint::lint(&schema::introspect(schema, schema_json), policy_set, &actions)
+        .messages()
+        .into_iter()
+        .map(|message| Finding::warning("schema", message))
+        .collect()
+}
+
+/// Loads every configured source the way startup does, but collects every problem instead of
+/// stopping at the first one.
+pub fn check(config: &Config) -> Report {
+    let mut findings = Vec::new();
+
+    let policy_set = if config.avp_policy_store_id.is_some() {
+        findings.push(Finding::warning(
+            "policies",
+            "Policies come from Verified Permissions (CEDAR_AVP_POLICY_STORE_ID) and are not checked",
+        ));
+        None
+    } else {
+        match policies::load(&config.policy_path, &config.policy_overlays) {
+            Ok(loaded) => Some(loaded.policy_set),
+            Err(e) => {
+                findings.push(Finding::error("policies", e.to_string()));
+                None
+            }
+        }
+    };
+
+    let schema = match schema::load(&config.schema_path, &config.schema_fragments) {
+        Ok(Some(loaded)) => Some(loaded),
+        Ok(None) => {
+            findings.push(Finding::warning(
+                "schema",
+                format!("Schema file {} not found; the agent would run without schema validation", config.schema_path),
+            ));
+            None
+        }
+        Err(e) => {
+            findings.push(Finding::error("schema", e));
+            None
+        }
+    };
+
+    if let (Some((schema, schema_json)), Some(policy_set)) = (&schema, &policy_set) {
+        findings.extend(validation_findings(schema, policy_set));
+        findings.extend(lint_findings(schema, schema_json, policy_set));
+    }
+    let schema = schema.map(|(schema, _)| schema);
+
+    let mut entities = 0;
+    match EntityStore::load(config.entities_path.as_deref(), schema.as_ref(), crate::entities::Ingest::from_config(config)) {
+        Ok(store) => {
+            entities = store.len();
+            if let Err(e) = store.check_budget(config.entity_memory_limit) {
+                findings.push(Finding::error("entities", e));
+            }
+        }
+        Err(e) => findings.push(Finding::error("entities", e.to_string())),
+    }
+
+    Report {
+        valid: !findings.iter().any(|f| f.severity == "error"),
+        policies: policy_set.as_ref().map_or(0, |p| p.policies().count()),
+        templates: policy_set.as_ref().map_or(0, |p| p.templates().count()),
+        entities,
+        findings,
+    }
+}
+
+/// Runs `cedar-agent --validate-only`: prints the report as JSON and exits nonzero unless
+/// everything is valid. Configuration errors are reported the same way.
+pub fn run() -> ! {
+    let report = match Config::from_env() {
+        Ok(config) => check(&config),
+        Err(e) => Report {
+            valid: false,
+            policies: 0,
+            templates: 0,
+            entities: 0,
+            findings: vec![Finding::error("config", e.to_string())],
+        },
+    };
+    println!("{}", serde_json::to_string_pretty(&report).unwrap());
+    std::process::exit(if report.valid { 0 } else { 1 })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn schema() -> Schema {
+        Schema::from_json_value(serde_json::json!({
+            "": {
+                "entityTypes": {"User": {}, "Doc": {"shape": {"type": "Record", "attributes": {"owner": {"type": "String"}}}}},
+                "actions": {"read": {"appliesTo": {"principalTypes": ["User"], "resourceTypes": ["Doc"]}}}
+            }
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn valid_policies_have_no_findings() {
+        let policies: PolicySet = r#"permit (principal, action == Action::"read", resource) when { resource.owner == "a" };"#
+            .parse()
+            .unwrap();
+        assert_eq!(validation_findings(&schema(), &policies), []);
+    }
+
+    #[test]
+    fn reports_each_invalid_policy() {
+        let policies: PolicySet = r#"
+            permit (principal, action == Action::"read", resource) when { resource.title == "a" };
+            permit (principal == Group::"x", action, resource);
+        "#
+        .parse()
+        .unwrap();
+        let findings = validation_findings(&schema(), &policies);
+        for id in ["policy0", "policy1"] {
+            assert!(
+                findings.iter().any(|f| f.severity == "error" && f.message.contains(id)),
+                "{:?}",
+                findings
+            );
+        }
+        assert!(findings.iter().all(|f| f.source == "policies"));
+    }
+}
@@ -0,0 +1,442 @@
+use std::collections::BTreeMap;
+
+/// The service `install`, `uninstall` and `run` act on unless `--name` names another.
+const DEFAULT_NAME: &str = "cedar-agent";
+
+/// A `cedar-agent service` command.
+#[derive(Debug, PartialEq)]
+enum Command {
+    Install,
+    Uninstall,
+    Run,
+}
+
+/// Parses `<install|uninstall|run> [--name <name>]`. Names are kept to letters, digits, `-`, `_`
+/// and `.`, so they need no quoting in the service's command line.
+fn parse_args(args: &[String]) -> Result<(Command, String), String> {
+    let usage = "Usage: cedar-agent service <install|uninstall|run> [--name <name>]";
+    let command = match args.first().map(String::as_str) {
+        Some("install") => Command::Install,
+        Some("uninstall") => Command::Uninstall,
+        Some("run") => Command::Run,
+        _ => return Err(usage.to_string()),
+    };
+    let name = match &args[1..] {
+        [] => DEFAULT_NAME.to_string(),
+        [flag, name] if flag == "--name" => name.clone(),
+        _ => return Err(usage.to_string()),
+    };
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || "-_.".contains(c)) {
+        return Err(format!("Invalid service name '{}' (letters, digits, '-', '_' and '.' only)", name));
+    }
+    Ok((command, name))
+}
+
+/// The agent's settings among `vars`, as `KEY=value` entries for the service's environment: the
+/// service control manager starts services with the system's environment, not the shell's the
+/// service was installed from. Those an upgrade sets are left out.
+fn agent_environment(vars: impl IntoIterator<Item = (String, String)>) -> Vec<String> {
+    let vars: BTreeMap<String, String> = vars
+        .into_iter()
+        .filter(|(key, _)| key.starts_with("CEDAR_") || key == "BIND_ADDR" || key == "RUST_LOG")
+        .filter(|(key, _)| !key.starts_with("CEDAR_UPGRADE_"))
+        .collect();
+    vars.into_iter().map(|(key, value)| format!("{}={}", key, value)).collect()
+}
+
+/// `text` as a NUL-terminated UTF-16 string.
+fn wide(text: &str) -> Vec<u16> {
+    text.encode_utf16().chain([0]).collect()
+}
+
+/// `strings` as a `REG_MULTI_SZ` value: each NUL-terminated, then a NUL ending the list.
+fn multi_sz(strings: &[String]) -> Vec<u16> {
+    strings.iter().flat_map(|text| wide(text)).chain([0]).collect()
+}
+
+#[cfg(windows)]
+pub use service::{run, EventLog};
+
+#[cfg(windows)]
+mod service {
+    use super::{agent_environment, multi_sz, parse_args, wide, Command};
+    use crate::config::Config;
+    use log::Level;
+    use std::ffi::c_void;
+    use std::io;
+    use std::ptr::{null, null_mut};
+    use std::sync::OnceLock;
+    use std::time::Duration;
+    use tokio::sync::Notify;
+    use windows_sys::core::PWSTR;
+    use windows_sys::Win32::Foundation::{
+        ERROR_CALL_NOT_IMPLEMENTED, ERROR_SERVICE_SPECIFIC_ERROR, HANDLE, NO_ERROR,
+    };
+    use windows_sys::Win32::System::EventLog::{
+        DeregisterEventSource, RegisterEventSourceW, ReportEventW, EVENTLOG_ERROR_TYPE, EVENTLOG_INFORMATION_TYPE,
+        EVENTLOG_WARNING_TYPE,
+    };
+    use windows_sys::Win32::System::Registry::{
+        RegCloseKey, RegOpenKeyExW, RegSetValueExW, HKEY, HKEY_LOCAL_MACHINE, KEY_SET_VALUE, REG_MULTI_SZ,
+    };
+    use windows_sys::Win32::System::Services::{
+        ChangeServiceConfig2W, CloseServiceHandle, CreateServiceW, DeleteService, OpenSCManagerW, OpenServiceW,
+        RegisterServiceCtrlHandlerExW, SetServiceStatus, StartServiceCtrlDispatcherW, SC_HANDLE,
+        SC_MANAGER_CONNECT, SC_MANAGER_CREATE_SERVICE, SERVICE_ACCEPT_SHUTDOWN, SERVICE_ACCEPT_STOP,
+        SERVICE_ALL_ACCESS, SERVICE_AUTO_START, SERVICE_CONFIG_DESCRIPTION, SERVICE_CONTROL_INTERROGATE,
+        SERVICE_CONTROL_SHUTDOWN, SERVICE_CONTROL_STOP, SERVICE_DESCRIPTIONW, SERVICE_ERROR_NORMAL,
+        SERVICE_RUNNING, SERVICE_START_PENDING, SERVICE_STATUS, SERVICE_STATUS_CURRENT_STATE,
+        SERVICE_STATUS_HANDLE, SERVICE_STOPPED, SERVICE_STOP_PENDING, SERVICE_TABLE_ENTRYW,
+        SERVICE_WIN32_OWN_PROCESS,
+    };
+
+    /// An event source in the Windows Event Log (the Application log), which the agent's log
+    /// records are reported to when it runs as a service.
+    pub struct EventLog(HANDLE);
+
+    // SAFETY: an event source handle may be used from any thread
+    unsafe impl Send for EventLog {}
+    unsafe impl Sync for EventLog {}
+
+    impl EventLog {
+        pub fn register(source: &str) -> Result<Self, String> {
+            let source = wide(source);
+            // SAFETY: `source` is NUL-terminated and outlives the call
+            let handle = unsafe { RegisterEventSourceW(null(), source.as_ptr()) };
+            if handle.is_null() {
+                return Err(format!("Failed to open the event log: {}", io::Error::last_os_error()));
+            }
+            Ok(Self(handle))
+        }
+
+        /// Reports `message`, as an error, a warning, or information for the other levels.
+        /// Delivery is best-effort, as with syslog.
+        pub fn report(&self, level: Level, message: &str) {
+            let kind = match level {
+                Level::Error => EVENTLOG_ERROR_TYPE,
+                Level::Warn => EVENTLOG_WARNING_TYPE,
+                _ => EVENTLOG_INFORMATION_TYPE,
+            };
+            let message = wide(message);
+            let strings = [message.as_ptr()];
+            // SAFETY: the source is open, and the one string is NUL-terminated and outlives the call
+            unsafe {
+                ReportEventW(self.0, kind, 0, 0, null_mut(), 1, 0, strings.as_ptr(), null());
+            }
+        }
+    }
+
+    impl Drop for EventLog {
+        fn drop(&mut self) {
+            // SAFETY: the source was opened by `register` and is not used after this
+            unsafe {
+                DeregisterEventSource(self.0);
+            }
+        }
+    }
+
+    /// A handle to the service control manager or a service, closed when dropped.
+    struct ScHandle(SC_HANDLE);
+
+    impl Drop for ScHandle {
+        fn drop(&mut self) {
+            // SAFETY: the handle is open and not used after this
+            unsafe {
+                CloseServiceHandle(self.0);
+            }
+        }
+    }
+
+    fn open_manager(access: u32) -> Result<ScHandle, String> {
+        // SAFETY: null names the local machine's active database
+        let handle = unsafe { OpenSCManagerW(null(), null(), access) };
+        if handle.is_null() {
+            return Err(format!(
+                "Failed to open the service control manager: {}",
+                io::Error::last_os_error()
+            ));
+        }
+        Ok(ScHandle(handle))
+    }
+
+    /// `cedar-agent service <install|uninstall|run> [--name <name>]`.
+    pub fn run(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+        let (command, name) = parse_args(args)?;
+        match command {
+            Command::Install => install(&name),
+            Command::Uninstall => uninstall(&name),
+            Command::Run => dispatch(&name),
+        }
+    }
+
+    /// Registers the service to start automatically, running this executable with the agent's
+    /// settings from the current environment.
+    fn install(name: &str) -> Result<(), Box<dyn std::error::Error>> {
+        // Settings that would fail startup fail the install instead
+        Config::from_env()?;
+        let exe = std::env::current_exe().map_err(|e| format!("Failed to find the agent's executable: {}", e))?;
+        let command = format!("\"{}\" service run --name {}", exe.display(), name);
+
+        let manager = open_manager(SC_MANAGER_CREATE_SERVICE)?;
+        let (service_name, display_name, binary) = (wide(name), wide(&format!("Cedar Agent ({})", name)), wide(&command));
+        // SAFETY: every string is NUL-terminated and outlives the call
+        let service = unsafe {
+            CreateServiceW(
+                manager.0,
+                service_name.as_ptr(),
+                display_name.as_ptr(),
+                SERVICE_ALL_ACCESS,
+                SERVICE_WIN32_OWN_PROCESS,
+                SERVICE_AUTO_START,
+                SERVICE_ERROR_NORMAL,
+                binary.as_ptr(),
+                null(),
+                null_mut(),
+                null(),
+                null(),
+                null(),
+            )
+        };
+        if service.is_null() {
+            return Err(format!("Failed to create service {}: {}", name, io::Error::last_os_error()).into());
+        }
+        let service = ScHandle(service);
+
+        let mut text = wide("Evaluates Cedar authorization requests over HTTP");
+        let description = SERVICE_DESCRIPTIONW { lpDescription: text.as_mut_ptr() };
+        // SAFETY: `description` points at a NUL-terminated string that outlives the call
+        unsafe {
+            ChangeServiceConfig2W(service.0, SERVICE_CONFIG_DESCRIPTION, &description as *const _ as *const c_void);
+        }
+
+        let environment = agent_environment(std::env::vars());
+        if !environment.is_empty() {
+            set_environment(name, &environment)?;
+        }
+        println!(
+            "Installed service {} with {} settings from this environment; start it with `sc start {}`",
+            name,
+            environment.len(),
+            name
+        );
+        Ok(())
+    }
+
+    /// Sets the service's `Environment` value, which the service control manager adds to the
+    /// environment the service starts with.
+    fn set_environment(name: &str, environment: &[String]) -> Result<(), String> {
+        let path = wide(&format!("SYSTEM\\CurrentControlSet\\Services\\{}", name));
+        let mut key: HKEY = null_mut();
+        // SAFETY: `path` is NUL-terminated and `key` is written only on success
+        let status = unsafe { RegOpenKeyExW(HKEY_LOCAL_MACHINE, path.as_ptr(), 0, KEY_SET_VALUE, &mut key) };
+        if status != NO_ERROR {
+            return Err(format!(
+                "Failed to open the registry key of service {}: {}",
+                name,
+                io::Error::from_raw_os_error(status as i32)
+            ));
+        }
+        let value = multi_sz(environment);
+        let value_name = wide("Environment");
+        // SAFETY: the key is open, and `value` holds the byte length given
+        let status = unsafe {
+            let status = RegSetValueExW(
+                key,
+                value_name.as_ptr(),
+                0,
+                REG_MULTI_SZ,
+                value.as_ptr() as *const u8,
+                (value.len() * 2) as u32,
+            );
+            RegCloseKey(key);
+            status
+        };
+        if status != NO_ERROR {
+            return Err(format!(
+                "Failed to set the environment of service {}: {}",
+                name,
+                io::Error::from_raw_os_error(status as i32)
+            ));
+        }
+        Ok(())
+    }
+
+    fn uninstall(name: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let manager = open_manager(SC_MANAGER_CONNECT)?;
+        let service_name = wide(name);
+        // SAFETY: `service_name` is NUL-terminated and outlives the call
+        let service = unsafe { OpenServiceW(manager.0, service_name.as_ptr(), SERVICE_ALL_ACCESS) };
+        if service.is_null() {
+            return Err(format!("Failed to open service {}: {}", name, io::Error::last_os_error()).into());
+        }
+        let service = ScHandle(service);
+        // SAFETY: the service handle is open
+        if unsafe { DeleteService(service.0) } == 0 {
+            return Err(format!("Failed to delete service {}: {}", name, io::Error::last_os_error()).into());
+        }
+        println!("Removed service {}; a running agent is removed once it stops", name);
+        Ok(())
+    }
+
+    /// The service this process runs as.
+    static NAME: OnceLock<String> = OnceLock::new();
+    /// The runtime the agent runs on; the service's thread belongs to the control manager.
+    static RUNTIME: OnceLock<tokio::runtime::Handle> = OnceLock::new();
+    static STATUS: OnceLock<StatusHandle> = OnceLock::new();
+    /// Notified when the control manager asks the service to stop.
+    static STOP: Notify = Notify::const_new();
+    /// How long a stop may take: the drain timeout, and a little more.
+    static STOP_WAIT: OnceLock<Duration> = OnceLock::new();
+
+    struct StatusHandle(SERVICE_STATUS_HANDLE);
+
+    // SAFETY: a service status handle may be used from any thread
+    unsafe impl Send for StatusHandle {}
+    unsafe impl Sync for StatusHandle {}
+
+    /// Connects this process to the service control manager, which then runs `service_main`
+    /// on a thread of its own. Returns when the service has stopped.
+    fn dispatch(name: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let _ = NAME.set(name.to_string());
+        let _ = RUNTIME.set(tokio::runtime::Handle::current());
+        let mut service_name = wide(name);
+        let table = [
+            SERVICE_TABLE_ENTRYW {
+                lpServiceName: service_name.as_mut_ptr(),
+                lpServiceProc: Some(service_main),
+            },
+            SERVICE_TABLE_ENTRYW {
+                lpServiceName: null_mut(),
+                lpServiceProc: None,
+            },
+        ];
+        // SAFETY: the table ends with a null entry, and outlives the dispatcher
+        let connected = tokio::task::block_in_place(|| unsafe { StartServiceCtrlDispatcherW(table.as_ptr()) });
+        if connected == 0 {
+            return Err(format!(
+                "Failed to connect to the service control manager: {} (`service run` is started by \
+                 Windows; use `service install` and `sc start`)",
+                io::Error::last_os_error()
+            )
+            .into());
+        }
+        Ok(())
+    }
+
+    fn set_status(state: SERVICE_STATUS_CURRENT_STATE, exit_code: u32) {
+        let Some(handle) = STATUS.get() else {
+            return;
+        };
+        let wait = match state {
+            SERVICE_START_PENDING => Duration::from_secs(30),
+            SERVICE_STOP_PENDING => STOP_WAIT.get().copied().unwrap_or(Duration::from_secs(30)),
+            _ => Duration::ZERO,
+        };
+        let status = SERVICE_STATUS {
+            dwServiceType: SERVICE_WIN32_OWN_PROCESS,
+            dwCurrentState: state,
+            dwControlsAccepted: if state == SERVICE_RUNNING { SERVICE_ACCEPT_STOP | SERVICE_ACCEPT_SHUTDOWN } else { 0 },
+            dwWin32ExitCode: if exit_code == 0 { NO_ERROR } else { ERROR_SERVICE_SPECIFIC_ERROR },
+            dwServiceSpecificExitCode: exit_code,
+            dwCheckPoint: 0,
+            dwWaitHint: wait.as_millis() as u32,
+        };
+        // SAFETY: the handle was returned by RegisterServiceCtrlHandlerExW
+        unsafe {
+            SetServiceStatus(handle.0, &status);
+        }
+    }
+
+    unsafe extern "system" fn control(control: u32, _event: u32, _data: *mut c_void, _context: *mut c_void) -> u32 {
+        match control {
+            SERVICE_CONTROL_STOP | SERVICE_CONTROL_SHUTDOWN => {
+                set_status(SERVICE_STOP_PENDING, 0);
+                STOP.notify_one();
+                NO_ERROR
+            }
+            SERVICE_CONTROL_INTERROGATE => NO_ERROR,
+            _ => ERROR_CALL_NOT_IMPLEMENTED,
+        }
+    }
+
+    /// Runs the agent until the control manager stops the service: it then stops accepting and
+    /// drains, as on an upgrade.
+    unsafe extern "system" fn service_main(_argc: u32, _argv: *mut PWSTR) {
+        let name = NAME.get().map(String::as_str).unwrap_or(super::DEFAULT_NAME);
+        let service_name = wide(name);
+        // SAFETY: `service_name` is NUL-terminated and outlives the call
+        let handle = unsafe { RegisterServiceCtrlHandlerExW(service_name.as_ptr(), Some(control), null()) };
+        if handle.is_null() {
+            return;
+        }
+        let _ = STATUS.set(StatusHandle(handle));
+        set_status(SERVICE_START_PENDING, 0);
+
+        let vault = RUNTIME.get().map_or(Ok(None), |runtime| runtime.block_on(crate::vault::resolve_env()));
+        let config = vault.map_err(Into::into).and_then(|vault| {
+            let config = Config::from_env()?;
+            crate::logging::init_with_event_log(&config, name)?;
+            Ok((config, vault))
+        });
+        let (config, vault) = match config {
+            Ok(config) => config,
+            Err(e) => {
+                // Logging may not be set up, so the failure goes to the event log directly
+                if let Ok(event_log) = EventLog::register(name) {
+                    event_log.report(Level::Error, &format!("Failed to start: {}", e));
+                }
+                set_status(SERVICE_STOPPED, 1);
+                return;
+            }
+        };
+        let _ = STOP_WAIT.set(config.drain_timeout + Duration::from_secs(5));
+        let Some(runtime) = RUNTIME.get() else {
+            set_status(SERVICE_STOPPED, 1);
+            return;
+        };
+        if let Some(vault) = vault {
+            runtime.spawn(vault.keep_alive());
+        }
+
+        set_status(SERVICE_RUNNING, 0);
+        let result = runtime.block_on(crate::run_agent(config, STOP.notified()));
+        match result {
+            Ok(()) => set_status(SERVICE_STOPPED, 0),
+            Err(e) => {
+                log::error!("Error: {}", e);
+                set_status(SERVICE_STOPPED, 1);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn parses_service_commands() {
+        assert_eq!(parse_args(&args(&["install"])), Ok((Command::Install, "cedar-agent".to_string())));
+        assert_eq!(parse_args(&args(&["run", "--name", "cedar-eu.1"])), Ok((Command::Run, "cedar-eu.1".to_string())));
+        assert!(parse_args(&args(&[])).is_err());
+        assert!(parse_args(&args(&["start"])).is_err());
+        assert!(parse_args(&args(&["install", "--name"])).is_err());
+        assert!(parse_args(&args(&["install", "--name", "a b"])).is_err());
+        assert!(parse_args(&args(&["install", "--name", "..\\x\""])).is_err());
+    }
+
+    #[test]
+    fn copies_the_agent_settings_into_the_service_environment() {
+        let vars = [("PATH", "C:\\Windows"), ("CEDAR_POLICY_PATH", "C:\\cedar\\policy.cedar"), ("BIND_ADDR", "0.0.0.0:8181"), ("CEDAR_UPGRADE_FDS", "3")]
+            .map(|(key, value)| (key.to_string(), value.to_string()));
+        let environment = agent_environment(vars);
+        assert_eq!(environment, ["BIND_ADDR=0.0.0.0:8181", "CEDAR_POLICY_PATH=C:\\cedar\\policy.cedar"]);
+
+        let value = multi_sz(&environment[..1]);
+        assert_eq!(String::from_utf16(&value).unwrap(), "BIND_ADDR=0.0.0.0:8181\0\0");
+    }
+}